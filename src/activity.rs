@@ -0,0 +1,182 @@
+//! The statements running in each SQL session, as
+//! `information_schema.active_queries` lists them and `KILL QUERY`
+//! cancels them.
+//!
+//! A session registers each statement as it starts and removes it once
+//! it finishes. The text recorded is all of what the session was sent,
+//! as Postgres shows a simple query of several statements. Killing a
+//! statement cancels its connection's operation through the connection's
+//! `CancelHandle`, so one waiting for a lock is cancelled once it has it.
+
+use crate::database::CancelHandle;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// The running statements of a database's sessions.
+#[derive(Default)]
+pub(crate) struct Activity {
+    next_session: AtomicU64,
+    next_query: AtomicU64,
+    queries: Mutex<BTreeMap<u64, Running>>,
+}
+
+struct Running {
+    query: ActiveQuery,
+    cancel: CancelHandle,
+}
+
+/// A running statement, as `information_schema.active_queries` lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ActiveQuery {
+    pub(crate) id: u64,
+    pub(crate) session: u64,
+    /// `None` for a session not held to a user's privileges
+    pub(crate) user: Option<String>,
+    pub(crate) started: SystemTime,
+    pub(crate) state: QueryState,
+    pub(crate) query: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum QueryState {
+    Active,
+    /// Killed, but not yet stopped
+    Cancelling,
+}
+
+impl QueryState {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            QueryState::Active => "active",
+            QueryState::Cancelling => "cancelling",
+        }
+    }
+}
+
+impl Activity {
+    /// An id for a new session.
+    pub(crate) fn next_session(&self) -> u64 {
+        self.next_session.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Register a statement of `session`'s, run from `query`, returning
+    /// its id.
+    pub(crate) fn start(
+        &self,
+        session: u64,
+        user: Option<&str>,
+        query: &str,
+        cancel: CancelHandle,
+    ) -> u64 {
+        let id = self.next_query.fetch_add(1, Ordering::Relaxed) + 1;
+        let query = ActiveQuery {
+            id,
+            session,
+            user: user.map(str::to_string),
+            started: SystemTime::now(),
+            state: QueryState::Active,
+            query: query.to_string(),
+        };
+        let mut queries = self.queries.lock().unwrap();
+        queries.insert(id, Running { query, cancel });
+        id
+    }
+
+    /// Remove the finished statement `id`.
+    pub(crate) fn finish(&self, id: u64) {
+        let mut queries = self.queries.lock().unwrap();
+        // A kill that came too late to stop the statement mustn't stop
+        // the session's next one instead
+        if let Some(running) = queries.remove(&id) {
+            if running.query.state == QueryState::Cancelling {
+                running.cancel.reset();
+            }
+        }
+    }
+
+    /// The running statements, in the order they started.
+    pub(crate) fn list(&self) -> Vec<ActiveQuery> {
+        let queries = self.queries.lock().unwrap();
+        queries
+            .values()
+            .map(|running| running.query.clone())
+            .collect()
+    }
+
+    /// The running statement `id`, if it is still running.
+    pub(crate) fn get(&self, id: u64) -> Option<ActiveQuery> {
+        let queries = self.queries.lock().unwrap();
+        queries.get(&id).map(|running| running.query.clone())
+    }
+
+    /// Cancel the running statement `id`, returning whether it was still
+    /// running.
+    pub(crate) fn kill(&self, id: u64) -> bool {
+        let mut queries = self.queries.lock().unwrap();
+        let Some(running) = queries.get_mut(&id) else {
+            return false;
+        };
+        running.query.state = QueryState::Cancelling;
+        running.cancel.cancel();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::tests::test_config;
+    use crate::database::Database;
+    use crate::sql::SqlSession;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_kill_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        config.storage.page_size = 128;
+        let database = Database::with_config(&config).unwrap();
+
+        let mut admin = SqlSession::new(database.connect());
+        let results = admin.execute(
+            "CREATE USER bob PASSWORD 'b'; CREATE TABLE; GRANT SELECT ON ALL TABLES TO bob",
+        );
+        assert!(results.iter().all(Result::is_ok));
+        let results = admin.execute("select * from INFORMATION_SCHEMA.ACTIVE_QUERIES");
+        let listed = results[0].as_ref().unwrap();
+        assert_eq!(listed.rows.len(), 1);
+        assert_eq!(listed.rows[0][1], Some(admin.id().to_string()));
+        assert_eq!(listed.rows[0][4].as_deref(), Some("active"));
+
+        // A statement waiting on a lock is killed once it has it
+        let mut writer = SqlSession::new(database.connect());
+        let results = writer.execute("BEGIN; INSERT INTO 1 VALUES ('a')");
+        assert!(results.iter().all(Result::is_ok));
+        thread::scope(|scope| {
+            let reader = scope.spawn(|| {
+                let mut bob = SqlSession::for_user(database.connect(), "bob");
+                let killed = bob.execute("SELECT * FROM 1").remove(0);
+                (killed, bob.execute("SELECT * FROM 1").remove(0))
+            });
+            let query = loop {
+                let results = admin.execute("SELECT * FROM information_schema.active_queries");
+                let rows = &results[0].as_ref().unwrap().rows;
+                if let Some(row) = rows.iter().find(|row| row[2].as_deref() == Some("bob")) {
+                    break row[0].clone().unwrap();
+                }
+                thread::sleep(Duration::from_millis(5));
+            };
+            let results = admin.execute(&format!("KILL QUERY {}", query));
+            assert_eq!(results[0].as_ref().unwrap().tag, "KILL");
+            writer.execute("COMMIT")[0].as_ref().unwrap();
+            let (killed, next) = reader.join().unwrap();
+            assert_eq!(killed.unwrap_err().code(), "57014");
+            assert_eq!(next.unwrap().rows.len(), 1);
+        });
+
+        let results = admin.execute("KILL QUERY 999");
+        assert_eq!(results[0].as_ref().unwrap_err().code(), "42704");
+    }
+}
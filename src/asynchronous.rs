@@ -0,0 +1,257 @@
+//! An async surface over the embedded API, for applications running on an
+//! async executor.
+//!
+//! Storage calls block, so they never run on the executor: each
+//! `AsyncConnection` owns a thread serving its `Connection`, and its
+//! operations return futures that the thread completes. The futures only
+//! rely on `std::task`, so they work under any executor.
+//!
+//! `Server` likewise accepts and serves clients on threads of its own, so
+//! it can be started from async code, and `Server::shutdown` stops it
+//! without blocking.
+
+use crate::database::{Connection, Database, DatabaseError, Row, RowId, TableId};
+use crate::logging::log;
+use crate::server::{execute, Outcome, Request};
+use crate::storage::{panic_message, LockRecover};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+/// A result that another thread will provide.
+pub struct Pending<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+struct Slot<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+    /// Set once the value has been returned from `poll`
+    taken: bool,
+}
+
+/// Completes a `Pending`.
+pub(crate) struct Completer<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+impl<T> Pending<T> {
+    pub(crate) fn new() -> (Self, Completer<T>) {
+        let slot = Arc::new(Mutex::new(Slot {
+            value: None,
+            waker: None,
+            taken: false,
+        }));
+        (Self { slot: slot.clone() }, Completer { slot })
+    }
+}
+
+impl<T> Completer<T> {
+    pub(crate) fn complete(self, value: T) {
+        let waker = {
+            let mut slot = self.slot.lock_recover();
+            slot.value = Some(value);
+            slot.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Future for Pending<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let mut slot = self.slot.lock_recover();
+        assert!(!slot.taken, "Pending polled after completing");
+        match slot.value.take() {
+            Some(value) => {
+                slot.taken = true;
+                Poll::Ready(value)
+            }
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce(&mut Connection) + Send>;
+
+/// A `Connection` whose operations are awaited rather than blocked on. It
+/// behaves as a `Connection` does: operations run in the order they are
+/// made, and dropping it rolls back an unfinished transaction.
+pub struct AsyncConnection {
+    jobs: Sender<Job>,
+}
+
+impl AsyncConnection {
+    /// Connect to `database`, starting the connection's thread.
+    pub fn new(database: Arc<Database>) -> Self {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        // Detached rather than joined on drop, so dropping the connection
+        // doesn't block the executor; the thread ends after its last job
+        thread::spawn(move || {
+            let mut connection = database.connect();
+            for job in receiver {
+                job(&mut connection);
+            }
+        });
+        Self { jobs }
+    }
+
+    /// Run `request`, resolving to the rows it read and its outcome.
+    pub fn query(&self, request: Request) -> Pending<Result<(Vec<Row>, Outcome), DatabaseError>> {
+        self.run(move |connection| {
+            let database = connection.database();
+            execute(database, connection, request)
+        })
+    }
+
+    pub fn begin(&self) -> Pending<Result<(), DatabaseError>> {
+        self.run(|connection| connection.begin())
+    }
+
+    pub fn commit(&self) -> Pending<Result<(), DatabaseError>> {
+        self.run(|connection| connection.commit())
+    }
+
+    pub fn rollback(&self) -> Pending<Result<(), DatabaseError>> {
+        self.run(|connection| connection.rollback())
+    }
+
+    pub fn insert(&self, table: TableId, data: Vec<u8>) -> Pending<Result<RowId, DatabaseError>> {
+        self.run(move |connection| connection.insert(table, &data))
+    }
+
+    pub fn get(&self, row: RowId) -> Pending<Result<Option<Row>, DatabaseError>> {
+        self.run(move |connection| connection.get(row))
+    }
+
+    pub fn update(&self, row: RowId, data: Vec<u8>) -> Pending<Result<(), DatabaseError>> {
+        self.run(move |connection| connection.update(row, &data))
+    }
+
+    pub fn delete(&self, row: RowId) -> Pending<Result<bool, DatabaseError>> {
+        self.run(move |connection| connection.delete(row))
+    }
+
+    pub fn scan(&self, table: TableId) -> Pending<Result<Vec<Row>, DatabaseError>> {
+        self.run(move |connection| connection.scan(table))
+    }
+
+    /// Run `operation` on the connection's thread. Should it panic, its
+    /// transaction is rolled back and it resolves to `Panicked`, and the
+    /// connection carries on with the next operation.
+    fn run<T: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&mut Connection) -> Result<T, DatabaseError> + Send + 'static,
+    ) -> Pending<Result<T, DatabaseError>> {
+        let (pending, completer) = Pending::new();
+        let job: Job = Box::new(move |connection| {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| operation(connection)))
+                .unwrap_or_else(|panic| {
+                    let message = panic_message(&*panic).to_string();
+                    log!(Error, "async operation failed", panic = message);
+                    if connection.in_transaction() {
+                        if let Err(e) = connection.rollback() {
+                            log!(Error, "could not roll back", error = e);
+                        }
+                    }
+                    Err(DatabaseError::Panicked(message))
+                });
+            completer.complete(result);
+        });
+        // The thread only stops once the sender is dropped
+        self.jobs.send(job).expect("connection thread stopped");
+        pending
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::database::tests::test_config;
+    use std::task::Wake;
+    use std::thread::Thread;
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Just enough of an executor to run one future to completion.
+    pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn test_async_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        config.storage.page_size = 128;
+        let database = Arc::new(Database::with_config(&config).unwrap());
+
+        let connection = AsyncConnection::new(database.clone());
+        block_on(async {
+            let Outcome::Table(table) = connection.query(Request::CreateTable).await.unwrap().1
+            else {
+                panic!("expected a table");
+            };
+            connection.begin().await.unwrap();
+            let row = connection.insert(table, b"hello".to_vec()).await.unwrap();
+            // Not yet visible to another connection's thread, which waits
+            // on the transaction's lock
+            let other = AsyncConnection::new(database.clone());
+            let scan = other.scan(table);
+            connection.commit().await.unwrap();
+            assert_eq!(
+                scan.await.unwrap(),
+                vec![Row {
+                    id: row,
+                    data: b"hello".to_vec()
+                }]
+            );
+
+            assert!(connection.delete(row).await.unwrap());
+            assert!(matches!(
+                connection.commit().await,
+                Err(DatabaseError::NoTransaction)
+            ));
+            let (rows, _) = connection.query(Request::Scan(table)).await.unwrap();
+            assert!(rows.is_empty());
+
+            // A panic fails its own operation, rolling back its
+            // transaction, and the connection serves the next
+            connection.begin().await.unwrap();
+            connection.insert(table, b"lost".to_vec()).await.unwrap();
+            let panicked = connection.run(|_| -> Result<(), _> { panic!("boom") });
+            assert!(matches!(
+                panicked.await,
+                Err(DatabaseError::Panicked(message)) if message == "boom"
+            ));
+            assert!(connection.scan(table).await.unwrap().is_empty());
+            assert!(matches!(
+                connection.commit().await,
+                Err(DatabaseError::NoTransaction)
+            ));
+        });
+    }
+}
@@ -0,0 +1,160 @@
+//! An audit trail of who changed the schema or privileges, and who tried
+//! to log in, kept in a file of its own apart from the log.
+//!
+//! Each event is a line, appended as it happens, of its time in UTC and
+//! `key=value` fields as the log writes them: `user`, with `-` for a
+//! session with no user such as the embedded shell's, `event`, the
+//! statement for a `statement` event, and `outcome`, with the error when
+//! it `failed`:
+//!
+//! ```text
+//! 2026-10-14T09:30:00.125Z user=alice event=statement statement="GRANT SELECT ON 3 TO bob" outcome=ok
+//! 2026-10-14T09:30:02.500Z user=mallory event=login outcome=failed error="wrong user or password"
+//! ```
+//!
+//! Passwords are never written. The file is only ever appended to, and
+//! an event that can't be written is logged instead.
+
+use crate::config::AuditConfig;
+use crate::logging::{log, quoted, Timestamp};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// What an audit event records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AuditEvent<'a> {
+    Login,
+    /// A statement changing the schema, users or privileges, as text
+    Statement(&'a str),
+}
+
+pub(crate) struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub(crate) fn open(config: &AuditConfig) -> io::Result<Self> {
+        let path = Path::new(&config.file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Record `event` by `user`, which failed with the error in `outcome`
+    /// if it did.
+    pub(crate) fn record(&self, user: Option<&str>, event: AuditEvent, outcome: Result<(), &str>) {
+        let line = line(SystemTime::now(), user, event, outcome);
+        // Written whole at once, so events from other sessions never
+        // interleave with it
+        if let Err(e) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            log!(Error, "could not write to the audit log", error = e);
+        }
+    }
+}
+
+fn line(
+    time: SystemTime,
+    user: Option<&str>,
+    event: AuditEvent,
+    outcome: Result<(), &str>,
+) -> String {
+    let user = match user {
+        Some(user) => quoted(user),
+        None => "-".to_string(),
+    };
+    let mut line = format!("{} user={}", Timestamp(time), user);
+    match event {
+        AuditEvent::Login => line.push_str(" event=login"),
+        AuditEvent::Statement(statement) => {
+            line.push_str(" event=statement statement=");
+            line.push_str(&quoted(statement));
+        }
+    }
+    match outcome {
+        Ok(()) => line.push_str(" outcome=ok"),
+        Err(error) => {
+            line.push_str(" outcome=failed error=");
+            line.push_str(&quoted(error));
+        }
+    }
+    line.push('\n');
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_config;
+    use crate::database::Database;
+    use crate::sql::SqlSession;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_audit_log() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_791_970_200_125);
+        assert_eq!(
+            line(
+                time,
+                Some("alice"),
+                AuditEvent::Statement("DROP USER bob"),
+                Ok(())
+            ),
+            "2026-10-14T09:30:00.125Z user=alice event=statement statement=\"DROP USER bob\" outcome=ok\n"
+        );
+        assert_eq!(
+            line(time, None, AuditEvent::Login, Err("no such user")),
+            "2026-10-14T09:30:00.125Z user=- event=login outcome=failed error=\"no such user\"\n"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit").join("audit.log");
+        let config = AuditConfig {
+            file: path.to_string_lossy().into_owned(),
+        };
+        AuditLog::open(&config)
+            .unwrap()
+            .record(Some("alice"), AuditEvent::Login, Ok(()));
+        // Reopening appends rather than starting again
+        AuditLog::open(&config)
+            .unwrap()
+            .record(Some("bob"), AuditEvent::Login, Ok(()));
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.contains("user=alice") && text.contains("user=bob"));
+
+        // Sessions record what they change, whether or not it's allowed
+        let mut config = test_config(&dir.path().join("db"));
+        config.audit = Some(AuditConfig {
+            file: dir.path().join("db.audit").to_string_lossy().into_owned(),
+        });
+        let database = Database::with_config(&config).unwrap();
+        let mut session = SqlSession::new(database.connect());
+        for result in session.execute(
+            "CREATE USER root PASSWORD 'hunter2' SUPERUSER; CREATE USER bob PASSWORD 'b'; \
+             CREATE TABLE; SELECT * FROM 1",
+        ) {
+            result.unwrap();
+        }
+        let mut bob = SqlSession::for_user(database.connect(), "bob");
+        assert!(bob.execute("GRANT SELECT ON ALL TABLES TO bob")[0].is_err());
+        let text = fs::read_to_string(dir.path().join("db.audit")).unwrap();
+        let lines: Vec<&str> = text.lines().map(|line| &line[25..]).collect();
+        assert_eq!(
+            lines,
+            [
+                "user=- event=statement statement=\"CREATE USER root SUPERUSER\" outcome=ok",
+                "user=- event=statement statement=\"CREATE USER bob\" outcome=ok",
+                "user=- event=statement statement=\"CREATE TABLE\" outcome=ok",
+                "user=bob event=statement statement=\"GRANT SELECT ON ALL TABLES TO bob\" \
+                 outcome=failed error=\"Permission denied: bob isn't a superuser\"",
+            ]
+        );
+    }
+}
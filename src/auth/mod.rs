@@ -0,0 +1,332 @@
+//! Database users, kept in the catalog with their passwords hashed by
+//! scrypt, and the privileges they hold on tables.
+
+mod privileges;
+
+pub use privileges::Privilege;
+
+use crate::database::{Connection, Database, DatabaseError, Row, TableId};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use scrypt::Params;
+use sha2::{Digest, Sha256};
+use std::io::{Cursor, Read};
+
+/// Marks a catalog record as a user.
+const USER: u8 = 1;
+
+const SALT_SIZE: usize = 16;
+const HASH_SIZE: usize = 32;
+
+/// The scrypt cost new passwords are hashed with: `2 ^ 14` rounds of
+/// 1KiB blocks, 16MiB in all. Tests hash cheaply.
+const LOG_N: u8 = if cfg!(test) { 4 } else { 14 };
+const R: u32 = 8;
+const P: u32 = 1;
+
+/// The most a stored hash's cost may be, past which its record is taken
+/// to be corrupt rather than given the memory and time it asks for:
+/// 1GiB of blocks, worked through up to 16 times.
+const MAX_MEMORY: u64 = 1 << 30;
+const MAX_P: u32 = 16;
+
+/// A password hashed with scrypt, with the salt and cost it was hashed
+/// with, so the cost can change without invalidating stored passwords.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PasswordHash {
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: [u8; SALT_SIZE],
+    hash: [u8; HASH_SIZE],
+}
+
+impl PasswordHash {
+    /// Hash `password` with a fresh random salt.
+    fn new(password: &str) -> Self {
+        let mut salt = [0; SALT_SIZE];
+        getrandom::getrandom(&mut salt).expect("no random source for salts");
+        Self::with_salt(password, salt, LOG_N, R, P)
+    }
+
+    fn with_salt(password: &str, salt: [u8; SALT_SIZE], log_n: u8, r: u32, p: u32) -> Self {
+        let params = Params::new(log_n, r, p).expect("a cost checked by valid_cost");
+        let mut hash = [0; HASH_SIZE];
+        scrypt::scrypt(password.as_bytes(), &salt, &params, &mut hash)
+            .expect("a hash's size is one scrypt can produce");
+        Self {
+            log_n,
+            r,
+            p,
+            salt,
+            hash,
+        }
+    }
+
+    /// Whether `password` is the one hashed, compared in constant time.
+    fn verify(&self, password: &str) -> bool {
+        let other = Self::with_salt(password, self.salt, self.log_n, self.r, self.p);
+        equal_in_constant_time(&other.hash, &self.hash)
+    }
+}
+
+/// Whether a hash of cost `2 ^ log_n`, block size `r` and parallelism
+/// `p` is one scrypt takes and that is within `MAX_MEMORY` and `MAX_P`.
+fn valid_cost(log_n: u8, r: u32, p: u32) -> bool {
+    // Past 2 ^ 24 even the smallest blocks take more than `MAX_MEMORY`,
+    // and the shift below can't overflow
+    log_n <= 24
+        && (128 * u64::from(r)) << log_n <= MAX_MEMORY
+        && p <= MAX_P
+        && Params::new(log_n, r, p).is_ok()
+}
+
+/// Whether `password` is `expected`, taking as long whichever byte they
+/// first differ at, or however long either is.
+pub(crate) fn password_matches(expected: &str, password: &str) -> bool {
+    let expected = Sha256::digest(expected.as_bytes()).into();
+    equal_in_constant_time(&expected, &Sha256::digest(password.as_bytes()).into())
+}
+
+/// Whether two hashes are equal, looking at every byte however early they
+/// differ.
+fn equal_in_constant_time(a: &[u8; HASH_SIZE], b: &[u8; HASH_SIZE]) -> bool {
+    let diff = a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b));
+    std::hint::black_box(diff) == 0
+}
+
+/// A user record: `USER`, the name's length and bytes, the hash's cost,
+/// salt and hash, then whether the user is a superuser.
+struct User {
+    name: String,
+    password: PasswordHash,
+    /// Superusers hold every privilege, and manage users and privileges
+    superuser: bool,
+}
+
+impl User {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![USER];
+        bytes
+            .write_u16::<BigEndian>(self.name.len() as u16)
+            .unwrap();
+        bytes.extend_from_slice(self.name.as_bytes());
+        bytes.push(self.password.log_n);
+        bytes.write_u32::<BigEndian>(self.password.r).unwrap();
+        bytes.write_u32::<BigEndian>(self.password.p).unwrap();
+        bytes.extend_from_slice(&self.password.salt);
+        bytes.extend_from_slice(&self.password.hash);
+        bytes.push(self.superuser as u8);
+        bytes
+    }
+
+    /// The user a catalog record holds, or `None` if it holds something
+    /// else.
+    fn decode(bytes: &[u8]) -> Result<Option<Self>, DatabaseError> {
+        if bytes.first() != Some(&USER) {
+            return Ok(None);
+        }
+        let decode = || -> std::io::Result<Self> {
+            let mut cursor = Cursor::new(&bytes[1..]);
+            let mut name = vec![0; cursor.read_u16::<BigEndian>()? as usize];
+            cursor.read_exact(&mut name)?;
+            let (log_n, r, p) = (
+                cursor.read_u8()?,
+                cursor.read_u32::<BigEndian>()?,
+                cursor.read_u32::<BigEndian>()?,
+            );
+            let mut salt = [0; SALT_SIZE];
+            cursor.read_exact(&mut salt)?;
+            if !valid_cost(log_n, r, p) {
+                return Err(std::io::ErrorKind::InvalidData.into());
+            }
+            let mut hash = [0; HASH_SIZE];
+            cursor.read_exact(&mut hash)?;
+            let superuser = cursor.read_u8()? != 0;
+            Ok(Self {
+                name: String::from_utf8(name).map_err(|_| std::io::ErrorKind::InvalidData)?,
+                password: PasswordHash {
+                    log_n,
+                    r,
+                    p,
+                    salt,
+                    hash,
+                },
+                superuser,
+            })
+        };
+        decode()
+            .map(Some)
+            .map_err(|_| DatabaseError::CorruptedCatalog)
+    }
+}
+
+/// Every user in the catalog, with the record holding it.
+fn users(connection: &mut Connection) -> Result<Vec<(Row, User)>, DatabaseError> {
+    let mut users = Vec::new();
+    for row in connection.scan(TableId::CATALOG)? {
+        if let Some(user) = User::decode(&row.data)? {
+            users.push((row, user));
+        }
+    }
+    Ok(users)
+}
+
+impl Database {
+    /// Add a user who logs in with `password`, holding no privileges
+    /// unless a `superuser`.
+    pub fn create_user(
+        &self,
+        name: &str,
+        password: &str,
+        superuser: bool,
+    ) -> Result<(), DatabaseError> {
+        // Hashing is slow on purpose, so it's done before taking any lock
+        let user = User {
+            name: name.to_string(),
+            password: PasswordHash::new(password),
+            superuser,
+        };
+        let mut connection = self.connect_system();
+        connection.begin()?;
+        connection.lock_exclusive(TableId::CATALOG)?;
+        if users(&mut connection)?
+            .iter()
+            .any(|(_, user)| user.name == name)
+        {
+            return Err(DatabaseError::UserExists(name.to_string()));
+        }
+        connection.insert(TableId::CATALOG, &user.encode())?;
+        connection.commit()
+    }
+
+    /// Remove a user, and the privileges granted to them.
+    pub fn drop_user(&self, name: &str) -> Result<(), DatabaseError> {
+        let mut connection = self.connect_system();
+        connection.begin()?;
+        connection.lock_exclusive(TableId::CATALOG)?;
+        let (row, _) = users(&mut connection)?
+            .into_iter()
+            .find(|(_, user)| user.name == name)
+            .ok_or_else(|| DatabaseError::NoSuchUser(name.to_string()))?;
+        connection.delete(row.id)?;
+        for (row, _) in privileges::grants(&mut connection)?
+            .into_iter()
+            .filter(|(_, grant)| grant.user == name)
+        {
+            connection.delete(row.id)?;
+        }
+        connection.commit()
+    }
+
+    /// The names of the database's users, in the order they were created.
+    pub fn users(&self) -> Result<Vec<String>, DatabaseError> {
+        let users = users(&mut self.connect_system())?;
+        Ok(users.into_iter().map(|(_, user)| user.name).collect())
+    }
+
+    /// Whether `name` is a user who logs in with `password`.
+    pub fn authenticate(&self, name: &str, password: &str) -> Result<bool, DatabaseError> {
+        let users = users(&mut self.connect_system())?;
+        Ok(users
+            .into_iter()
+            .find(|(_, user)| user.name == name)
+            .is_some_and(|(_, user)| user.password.verify(password)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_config;
+
+    #[test]
+    fn test_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = || {
+            let mut config = test_config(dir.path());
+            config.storage.page_size = 256;
+            Database::with_config(&config).unwrap()
+        };
+        {
+            let database = open();
+            database.create_user("alice", "secret", false).unwrap();
+            database.create_user("bob", "hunter2", true).unwrap();
+            assert!(matches!(
+                database.create_user("alice", "again", false),
+                Err(DatabaseError::UserExists(_))
+            ));
+            // User tables don't see the catalog
+            let table = database.create_table().unwrap();
+            assert_eq!(database.tables(), vec![table]);
+        }
+
+        let database = open();
+        assert_eq!(database.users().unwrap(), vec!["alice", "bob"]);
+        assert!(database.authenticate("alice", "secret").unwrap());
+        assert!(!database.authenticate("alice", "hunter2").unwrap());
+        assert!(!database.authenticate("carol", "secret").unwrap());
+        database.drop_user("alice").unwrap();
+        assert!(!database.authenticate("alice", "secret").unwrap());
+        assert!(matches!(
+            database.drop_user("alice"),
+            Err(DatabaseError::NoSuchUser(_))
+        ));
+
+        // Salted, so equal passwords hash differently
+        assert_ne!(PasswordHash::new("same"), PasswordHash::new("same"));
+    }
+    #[test]
+    fn test_password_matches() {
+        assert!(password_matches("secret", "secret"));
+        assert!(!password_matches("secret", "secreT"));
+        assert!(!password_matches("secret", "secret2"));
+        assert!(!password_matches("secret", ""));
+        assert!(password_matches("", ""));
+    }
+
+    #[test]
+    fn test_scrypt() {
+        // Hashes stored before keep verifying: scrypt is as RFC 7914 has it
+        let salt: [u8; SALT_SIZE] = std::array::from_fn(|i| i as u8);
+        let hash = PasswordHash::with_salt("secret", salt, 4, 8, 1);
+        let hex: String = hash
+            .hash
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert_eq!(
+            hex,
+            "14162b6f27a3a6c44c38ec4d8c9e168712e5208908c69fe2ce631f1c9ef0f585"
+        );
+        assert!(hash.verify("secret"));
+        assert!(!hash.verify("Secret"));
+    }
+
+    #[test]
+    fn test_decode_checks_cost() {
+        let user = |log_n: u8, r: u32, p: u32| User {
+            name: "alice".to_string(),
+            password: PasswordHash {
+                log_n,
+                r,
+                p,
+                salt: [0; SALT_SIZE],
+                hash: [0; HASH_SIZE],
+            },
+            superuser: false,
+        };
+        assert!(User::decode(&user(LOG_N, R, P).encode()).unwrap().is_some());
+        for (log_n, r, p) in [
+            (0, 0, 1),
+            (14, 8, 0),
+            (63, 8, 1),
+            (20, 1 << 20, 1),
+            (14, 8, 64),
+        ] {
+            assert!(matches!(
+                User::decode(&user(log_n, r, p).encode()),
+                Err(DatabaseError::CorruptedCatalog)
+            ));
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use super::users;
+use crate::database::{Connection, Database, DatabaseError, Row, TableId};
+use crate::partition::Partitioning;
+use crate::table_options::TableOptions;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::fmt::{self, Display};
+use std::io::{Cursor, Read};
+
+/// Marks a catalog record as a grant.
+const GRANT: u8 = 2;
+
+/// What a user may do to a table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Privilege {
+    Select,
+    Insert,
+    Update,
+    Delete,
+    /// Create tables, when granted on every table
+    Ddl,
+}
+
+impl Privilege {
+    pub const ALL: [Privilege; 5] = [
+        Privilege::Select,
+        Privilege::Insert,
+        Privilege::Update,
+        Privilege::Delete,
+        Privilege::Ddl,
+    ];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl Display for Privilege {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Privilege::Select => "SELECT",
+            Privilege::Insert => "INSERT",
+            Privilege::Update => "UPDATE",
+            Privilege::Delete => "DELETE",
+            Privilege::Ddl => "DDL",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The privileges a user holds on one table, or on every table. A grant
+/// record: `GRANT`, the user's name's length and bytes, whether it covers
+/// every table, the table, then the privileges as a bit set.
+pub(super) struct Grant {
+    pub(super) user: String,
+    table: Option<TableId>,
+    privileges: u8,
+}
+
+impl Grant {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![GRANT];
+        bytes
+            .write_u16::<BigEndian>(self.user.len() as u16)
+            .unwrap();
+        bytes.extend_from_slice(self.user.as_bytes());
+        bytes.push(self.table.is_none() as u8);
+        let table = self.table.map_or(0, |table| table.0);
+        bytes.write_u32::<BigEndian>(table).unwrap();
+        bytes.push(self.privileges);
+        bytes
+    }
+
+    /// The grant a catalog record holds, or `None` if it holds something
+    /// else.
+    fn decode(bytes: &[u8]) -> Result<Option<Self>, DatabaseError> {
+        if bytes.first() != Some(&GRANT) {
+            return Ok(None);
+        }
+        let decode = || -> std::io::Result<Self> {
+            let mut cursor = Cursor::new(&bytes[1..]);
+            let mut user = vec![0; cursor.read_u16::<BigEndian>()? as usize];
+            cursor.read_exact(&mut user)?;
+            let every_table = cursor.read_u8()? != 0;
+            let table = TableId(cursor.read_u32::<BigEndian>()?);
+            Ok(Self {
+                user: String::from_utf8(user).map_err(|_| std::io::ErrorKind::InvalidData)?,
+                table: (!every_table).then_some(table),
+                privileges: cursor.read_u8()?,
+            })
+        };
+        decode()
+            .map(Some)
+            .map_err(|_| DatabaseError::CorruptedCatalog)
+    }
+}
+
+/// Every grant in the catalog, with the record holding it.
+pub(super) fn grants(connection: &mut Connection) -> Result<Vec<(Row, Grant)>, DatabaseError> {
+    let mut grants = Vec::new();
+    for row in connection.scan(TableId::CATALOG)? {
+        if let Some(grant) = Grant::decode(&row.data)? {
+            grants.push((row, grant));
+        }
+    }
+    Ok(grants)
+}
+
+fn bits(privileges: &[Privilege]) -> u8 {
+    privileges
+        .iter()
+        .fold(0, |bits, privilege| bits | privilege.bit())
+}
+
+impl Database {
+    /// Give `user` `privileges` on `table`, or with `None` on every table,
+    /// including those created later.
+    pub fn grant(
+        &self,
+        user: &str,
+        privileges: &[Privilege],
+        table: Option<TableId>,
+    ) -> Result<(), DatabaseError> {
+        self.change_grant(user, table, |held| held | bits(privileges))
+    }
+
+    /// Take `privileges` on `table`, or with `None` on every table, back
+    /// from `user`. Privileges granted on every table aren't taken back
+    /// from one table alone.
+    pub fn revoke(
+        &self,
+        user: &str,
+        privileges: &[Privilege],
+        table: Option<TableId>,
+    ) -> Result<(), DatabaseError> {
+        self.change_grant(user, table, |held| held & !bits(privileges))
+    }
+
+    /// Whether `user` holds `privilege` on `table`, or with `None` on every
+    /// table. Superusers hold every privilege, as does everyone while the
+    /// database has no users.
+    pub fn is_allowed(
+        &self,
+        user: &str,
+        privilege: Privilege,
+        table: Option<TableId>,
+    ) -> Result<bool, DatabaseError> {
+        let mut connection = self.connect_system();
+        let users = users(&mut connection)?;
+        if users.is_empty() {
+            return Ok(true);
+        }
+        match users.iter().find(|(_, found)| found.name == user) {
+            None => return Ok(false),
+            Some((_, user)) if user.superuser => return Ok(true),
+            Some(_) => {}
+        }
+        Ok(grants(&mut connection)?.iter().any(|(_, grant)| {
+            grant.user == user
+                && (grant.table.is_none() || grant.table == table)
+                && grant.privileges & privilege.bit() != 0
+        }))
+    }
+
+    /// Fail with `PermissionDenied` unless `user` holds `privilege` on
+    /// `table`, as `is_allowed` decides.
+    pub fn check_privilege(
+        &self,
+        user: &str,
+        privilege: Privilege,
+        table: Option<TableId>,
+    ) -> Result<(), DatabaseError> {
+        if self.is_allowed(user, privilege, table)? {
+            return Ok(());
+        }
+        let on = match table {
+            Some(table) => format!("table {}", table),
+            None => "every table".to_string(),
+        };
+        Err(DatabaseError::PermissionDenied(format!(
+            "{} lacks {} on {}",
+            user, privilege, on
+        )))
+    }
+
+    /// Fail with `PermissionDenied` unless `user` may manage users and
+    /// privileges: a superuser, or anyone while the database has no users.
+    pub fn check_superuser(&self, user: &str) -> Result<(), DatabaseError> {
+        let users = users(&mut self.connect_system())?;
+        if users.is_empty()
+            || users
+                .iter()
+                .any(|(_, found)| found.name == user && found.superuser)
+        {
+            return Ok(());
+        }
+        Err(DatabaseError::PermissionDenied(format!(
+            "{} isn't a superuser",
+            user
+        )))
+    }
+
+    /// Create a table stored as `options` say for `user`, who needs DDL on
+    /// every table, and grant them every privilege on it.
+    pub fn create_table_as(
+        &self,
+        user: &str,
+        options: TableOptions,
+    ) -> Result<TableId, DatabaseError> {
+        self.create_as(user, || self.create_table_with(options))
+    }
+
+    /// Create a partitioned table for `user` as `create_table_as` does a
+    /// table. Their privileges on it reach its partitions through it.
+    pub fn create_partitioned_table_as(
+        &self,
+        user: &str,
+        options: TableOptions,
+        partitioning: Partitioning,
+    ) -> Result<TableId, DatabaseError> {
+        self.create_as(user, || {
+            self.create_partitioned_table(options, partitioning)
+        })
+    }
+
+    fn create_as(
+        &self,
+        user: &str,
+        create: impl FnOnce() -> Result<TableId, DatabaseError>,
+    ) -> Result<TableId, DatabaseError> {
+        self.check_privilege(user, Privilege::Ddl, None)?;
+        let table = create()?;
+        // Superusers, and users of a database without any, need no grant
+        let users = users(&mut self.connect_system())?;
+        if users
+            .iter()
+            .any(|(_, found)| found.name == user && !found.superuser)
+        {
+            self.grant(user, &Privilege::ALL, Some(table))?;
+        }
+        Ok(table)
+    }
+
+    /// Replace the privileges `user` holds on `table` with what `change`
+    /// makes of them.
+    fn change_grant(
+        &self,
+        user: &str,
+        table: Option<TableId>,
+        change: impl FnOnce(u8) -> u8,
+    ) -> Result<(), DatabaseError> {
+        if let Some(table) = table {
+            if !self.tables().contains(&table) {
+                return Err(DatabaseError::NoSuchTable(table));
+            }
+        }
+        let mut connection = self.connect_system();
+        connection.begin()?;
+        connection.lock_exclusive(TableId::CATALOG)?;
+        if !users(&mut connection)?
+            .iter()
+            .any(|(_, found)| found.name == user)
+        {
+            return Err(DatabaseError::NoSuchUser(user.to_string()));
+        }
+        let existing = grants(&mut connection)?
+            .into_iter()
+            .find(|(_, grant)| grant.user == user && grant.table == table);
+        let held = existing.as_ref().map_or(0, |(_, grant)| grant.privileges);
+        let grant = Grant {
+            user: user.to_string(),
+            table,
+            privileges: change(held),
+        };
+        // A grant of nothing is no grant at all
+        match existing {
+            Some((row, _)) if grant.privileges == 0 => {
+                connection.delete(row.id)?;
+            }
+            Some((row, _)) => connection.update(row.id, &grant.encode())?,
+            None if grant.privileges == 0 => {}
+            None => {
+                connection.insert(TableId::CATALOG, &grant.encode())?;
+            }
+        }
+        connection.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_config;
+
+    #[test]
+    fn test_privileges() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        config.storage.page_size = 256;
+        let database = Database::with_config(&config).unwrap();
+
+        // Until there are users, anyone may do anything
+        let table = database
+            .create_table_as("nobody", TableOptions::default())
+            .unwrap();
+        assert!(database
+            .is_allowed("nobody", Privilege::Delete, Some(table))
+            .unwrap());
+        database.check_superuser("nobody").unwrap();
+
+        database.create_user("root", "pw", true).unwrap();
+        database.create_user("alice", "pw", false).unwrap();
+        assert!(database.is_allowed("root", Privilege::Ddl, None).unwrap());
+        assert!(!database
+            .is_allowed("alice", Privilege::Select, Some(table))
+            .unwrap());
+        assert!(!database
+            .is_allowed("nobody", Privilege::Select, Some(table))
+            .unwrap());
+        assert!(matches!(
+            database.check_superuser("alice"),
+            Err(DatabaseError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            database.create_table_as("alice", TableOptions::default()),
+            Err(DatabaseError::PermissionDenied(_))
+        ));
+
+        database
+            .grant(
+                "alice",
+                &[Privilege::Select, Privilege::Insert],
+                Some(table),
+            )
+            .unwrap();
+        assert!(database
+            .is_allowed("alice", Privilege::Insert, Some(table))
+            .unwrap());
+        database
+            .revoke("alice", &[Privilege::Insert], Some(table))
+            .unwrap();
+        assert!(!database
+            .is_allowed("alice", Privilege::Insert, Some(table))
+            .unwrap());
+        assert!(database
+            .is_allowed("alice", Privilege::Select, Some(table))
+            .unwrap());
+
+        // Creators own the tables they create
+        database.grant("alice", &[Privilege::Ddl], None).unwrap();
+        let own = database
+            .create_table_as("alice", TableOptions::default())
+            .unwrap();
+        assert!(database
+            .is_allowed("alice", Privilege::Delete, Some(own))
+            .unwrap());
+        assert!(!database
+            .is_allowed("alice", Privilege::Delete, Some(table))
+            .unwrap());
+
+        assert!(matches!(
+            database.grant("carol", &[Privilege::Select], Some(table)),
+            Err(DatabaseError::NoSuchUser(_))
+        ));
+        assert!(matches!(
+            database.grant("alice", &[Privilege::Select], Some(TableId(99))),
+            Err(DatabaseError::NoSuchTable(_))
+        ));
+
+        // Dropping a user drops their grants, so a new user of the same
+        // name starts with none
+        database.drop_user("alice").unwrap();
+        database.create_user("alice", "pw", false).unwrap();
+        assert!(!database
+            .is_allowed("alice", Privilege::Select, Some(table))
+            .unwrap());
+    }
+}
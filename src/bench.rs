@@ -0,0 +1,598 @@
+//! Synthetic workloads to measure the database by, so a change in its
+//! performance between releases shows up as a change in numbers: a
+//! workload loads tables of generated rows, then runs a mix of reads and
+//! writes over them from any number of clients and reports throughput and
+//! the latency percentiles of each.
+//!
+//! - `TpcB` is modelled on TPC-B: branches, tellers, accounts and a
+//!   history. A write moves an amount into an account, updating it, its
+//!   teller and its branch and logging it in the history, in one
+//!   transaction; a read looks up an account's balance.
+//! - `TpcH` is a much reduced TPC-H: orders and their line items. A read
+//!   is one of a few queries in SQL, looking up an order, filtering,
+//!   ordering and joining line items; a write adds an order with its line
+//!   items in a transaction.
+//!
+//! Rows are drawn from a seed, so a run with one client does the same
+//! work each time.
+
+use crate::database::{Connection, Database, RowId, TableId};
+use crate::error::FerroError;
+use crate::mapping::column_value;
+use crate::sql::SqlSession;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Accounts in each branch of `TpcB`, and tellers.
+const ACCOUNTS_PER_BRANCH: u64 = 1000;
+const TELLERS_PER_BRANCH: u64 = 10;
+/// Orders at scale 1 of `TpcH`, and the most line items one has.
+const ORDERS_PER_SCALE: u64 = 150;
+const MAX_LINE_ITEMS: u64 = 7;
+/// Rows loaded in each transaction.
+const LOAD_BATCH: usize = 500;
+
+/// What tables a benchmark loads and the operations it runs on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workload {
+    TpcB,
+    TpcH,
+}
+
+impl FromStr for Workload {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "").as_str() {
+            "tpcb" => Ok(Self::TpcB),
+            "tpch" => Ok(Self::TpcH),
+            _ => Err(format!("unknown workload {}; expected tpcb or tpch", s)),
+        }
+    }
+}
+
+impl Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::TpcB => "tpcb",
+            Self::TpcH => "tpch",
+        })
+    }
+}
+
+/// How a benchmark is run.
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    pub workload: Workload,
+    /// How much data to load: branches for `TpcB`, and hundreds and a half
+    /// of orders for `TpcH`
+    pub scale: u64,
+    /// Operations to run across all clients
+    pub operations: u64,
+    /// Connections running operations at once, each on a thread
+    pub clients: usize,
+    /// The share of operations that are reads, from 0 to 1
+    pub read_ratio: f64,
+    pub seed: u64,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            workload: Workload::TpcB,
+            scale: 1,
+            operations: 10_000,
+            clients: 1,
+            read_ratio: 0.5,
+            seed: 1,
+        }
+    }
+}
+
+/// How long the operations of one kind took that succeeded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Latencies {
+    pub count: u64,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Latencies {
+    fn new(mut samples: Vec<Duration>) -> Self {
+        samples.sort_unstable();
+        let at = |quantile: f64| {
+            let last = samples.len().saturating_sub(1);
+            samples
+                .get((last as f64 * quantile).round() as usize)
+                .copied()
+                .unwrap_or_default()
+        };
+        Self {
+            count: samples.len() as u64,
+            p50: at(0.5),
+            p95: at(0.95),
+            p99: at(0.99),
+            max: samples.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+impl Display for Latencies {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "{:>8}  p50 {:.3}ms  p95 {:.3}ms  p99 {:.3}ms  max {:.3}ms",
+            self.count,
+            ms(self.p50),
+            ms(self.p95),
+            ms(self.p99),
+            ms(self.max)
+        )
+    }
+}
+
+/// What a benchmark measured.
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    pub options: BenchOptions,
+    /// How long loading the tables took
+    pub load_time: Duration,
+    /// How long running the operations took, from the first client
+    /// starting to the last finishing
+    pub elapsed: Duration,
+    pub reads: Latencies,
+    pub writes: Latencies,
+    /// Operations that failed, such as on a lock conflict; a failed write
+    /// is rolled back
+    pub failed: u64,
+}
+
+impl BenchReport {
+    /// Operations that succeeded per second.
+    pub fn throughput(&self) -> f64 {
+        (self.reads.count + self.writes.count) as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let options = &self.options;
+        writeln!(
+            f,
+            "workload {} at scale {}, {} clients, {:.0}% reads, loaded in {:.3}s",
+            options.workload,
+            options.scale,
+            options.clients,
+            options.read_ratio * 100.0,
+            self.load_time.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "{} operations in {:.3}s: {:.1} per second, {} failed",
+            options.operations,
+            self.elapsed.as_secs_f64(),
+            self.throughput(),
+            self.failed
+        )?;
+        writeln!(f, "reads  {}", self.reads)?;
+        writeln!(f, "writes {}", self.writes)
+    }
+}
+
+/// A xorshift generator, so rows and operations follow from the seed.
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        // Xorshift never leaves zero
+        Self(seed.max(1))
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % n
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        (self.below(1 << 53) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+/// The tables of a `TpcB` run, with the rows of each by number.
+struct TpcB {
+    branches: Vec<RowId>,
+    tellers: Vec<RowId>,
+    accounts: Vec<RowId>,
+    history: TableId,
+}
+
+/// The tables of a `TpcH` run.
+struct TpcH {
+    orders: TableId,
+    line_items: TableId,
+    /// Orders loaded, numbered from 0
+    loaded: u64,
+}
+
+enum Tables {
+    TpcB(TpcB),
+    TpcH(TpcH),
+}
+
+impl BenchOptions {
+    /// Load the tables of the workload into new tables of `database`, then
+    /// run its operations and report how long they took.
+    pub fn run(&self, database: &Database) -> Result<BenchReport, FerroError> {
+        run(database, self)
+    }
+}
+
+fn run(database: &Database, options: &BenchOptions) -> Result<BenchReport, FerroError> {
+    let started = Instant::now();
+    let mut random = Random::new(options.seed);
+    let tables = match options.workload {
+        Workload::TpcB => Tables::TpcB(load_tpcb(database, options.scale, &mut random)?),
+        Workload::TpcH => Tables::TpcH(load_tpch(database, options.scale, &mut random)?),
+    };
+    let load_time = started.elapsed();
+
+    let clients = options.clients.max(1);
+    let started = Instant::now();
+    let results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..clients)
+            .map(|client| {
+                let tables = &tables;
+                // Spread the operations that don't divide evenly
+                let operations = options.operations / clients as u64
+                    + u64::from((client as u64) < options.operations % clients as u64);
+                let seed = options.seed.wrapping_add(client as u64 + 1);
+                scope.spawn(move || run_client(database, tables, options, client, operations, seed))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("benchmark client panicked"))
+            .collect()
+    });
+    let elapsed = started.elapsed();
+
+    let (mut reads, mut writes, mut failed) = (Vec::new(), Vec::new(), 0);
+    for result in results {
+        let client = result?;
+        reads.extend(client.reads);
+        writes.extend(client.writes);
+        failed += client.failed;
+    }
+    Ok(BenchReport {
+        options: options.clone(),
+        load_time,
+        elapsed,
+        reads: Latencies::new(reads),
+        writes: Latencies::new(writes),
+        failed,
+    })
+}
+
+#[derive(Default)]
+struct ClientResult {
+    reads: Vec<Duration>,
+    writes: Vec<Duration>,
+    failed: u64,
+}
+
+fn run_client(
+    database: &Database,
+    tables: &Tables,
+    options: &BenchOptions,
+    client: usize,
+    operations: u64,
+    seed: u64,
+) -> Result<ClientResult, FerroError> {
+    let mut random = Random::new(seed);
+    let mut result = ClientResult::default();
+    let mut connection = database.connect();
+    let mut session = SqlSession::new(database.connect());
+    for n in 0..operations {
+        let read = random.chance(options.read_ratio);
+        let started = Instant::now();
+        let ok = match (tables, read) {
+            (Tables::TpcB(tables), true) => tpcb_read(&mut connection, tables, &mut random),
+            (Tables::TpcB(tables), false) => tpcb_write(&mut connection, tables, &mut random),
+            (Tables::TpcH(tables), true) => tpch_read(&mut session, tables, &mut random),
+            (Tables::TpcH(tables), false) => {
+                // Each client numbers the orders it adds apart from the others'
+                let order = tables.loaded + n * options.clients as u64 + client as u64;
+                tpch_write(&mut session, tables, order, &mut random)
+            }
+        };
+        let took = started.elapsed();
+        match (ok, read) {
+            (true, true) => result.reads.push(took),
+            (true, false) => result.writes.push(took),
+            (false, _) => result.failed += 1,
+        }
+    }
+    Ok(result)
+}
+
+fn load_tpcb(database: &Database, scale: u64, random: &mut Random) -> Result<TpcB, FerroError> {
+    let mut connection = database.connect();
+    let mut load = |rows: Vec<String>| -> Result<Vec<RowId>, FerroError> {
+        let table = database.create_table()?;
+        let mut ids = Vec::with_capacity(rows.len());
+        for batch in rows.chunks(LOAD_BATCH) {
+            ids.extend(connection.insert_batch(table, batch)?);
+        }
+        Ok(ids)
+    };
+    let branches = load((0..scale).map(|b| branch(b, 0)).collect())?;
+    let tellers = (0..scale * TELLERS_PER_BRANCH)
+        .map(|t| teller(t, t / TELLERS_PER_BRANCH, 0))
+        .collect();
+    let tellers = load(tellers)?;
+    let accounts = (0..scale * ACCOUNTS_PER_BRANCH)
+        .map(|a| account(a, random.below(scale), 0))
+        .collect();
+    let accounts = load(accounts)?;
+    Ok(TpcB {
+        branches,
+        tellers,
+        accounts,
+        history: database.create_table()?,
+    })
+}
+
+fn branch(id: u64, balance: i64) -> String {
+    format!(r#"{{"bid": {}, "bbalance": {}}}"#, id, balance)
+}
+
+fn teller(id: u64, branch: u64, balance: i64) -> String {
+    format!(
+        r#"{{"tid": {}, "bid": {}, "tbalance": {}}}"#,
+        id, branch, balance
+    )
+}
+
+fn account(id: u64, branch: u64, balance: i64) -> String {
+    // TPC-B has accounts take 100 bytes
+    format!(
+        r#"{{"aid": {}, "bid": {}, "abalance": {}, "filler": "{}"}}"#,
+        id,
+        branch,
+        balance,
+        "x".repeat(40)
+    )
+}
+
+/// The number in `column` of `row`, which must have one.
+fn number(connection: &mut Connection, row: RowId, column: &str) -> Result<i64, FerroError> {
+    let data = connection.get(row)?.map(|row| row.data).unwrap_or_default();
+    Ok(column_value(&data, column)
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_default())
+}
+
+fn tpcb_read(connection: &mut Connection, tables: &TpcB, random: &mut Random) -> bool {
+    let row = tables.accounts[random.below(tables.accounts.len() as u64) as usize];
+    number(connection, row, "abalance").is_ok()
+}
+
+fn tpcb_write(connection: &mut Connection, tables: &TpcB, random: &mut Random) -> bool {
+    let aid = random.below(tables.accounts.len() as u64);
+    let tid = random.below(tables.tellers.len() as u64);
+    let bid = tid / TELLERS_PER_BRANCH;
+    let delta = random.below(10_000) as i64 - 5_000;
+    let transaction = |connection: &mut Connection| -> Result<(), FerroError> {
+        let account_row = tables.accounts[aid as usize];
+        let data = connection.get(account_row)?.map(|row| row.data);
+        let data = data.unwrap_or_default();
+        let column = |name| column_value(&data, name).and_then(|value| value.parse().ok());
+        let balance: i64 = column("abalance").unwrap_or_default();
+        let data = account(
+            aid,
+            column("bid").map_or(bid, |b| b as u64),
+            balance + delta,
+        );
+        connection.update(account_row, data.as_bytes())?;
+        let teller_row = tables.tellers[tid as usize];
+        let balance = number(connection, teller_row, "tbalance")?;
+        connection.update(teller_row, teller(tid, bid, balance + delta).as_bytes())?;
+        let branch_row = tables.branches[bid as usize];
+        let balance = number(connection, branch_row, "bbalance")?;
+        connection.update(branch_row, branch(bid, balance + delta).as_bytes())?;
+        let history = format!(
+            r#"{{"tid": {}, "bid": {}, "aid": {}, "delta": {}}}"#,
+            tid, bid, aid, delta
+        );
+        connection.insert(tables.history, history.as_bytes())?;
+        Ok(())
+    };
+    if connection.begin().is_err() {
+        return false;
+    }
+    match transaction(connection) {
+        Ok(()) => connection.commit().is_ok(),
+        Err(_) => {
+            let _ = connection.rollback();
+            false
+        }
+    }
+}
+
+const FLAGS: [&str; 3] = ["A", "N", "R"];
+
+fn order(key: u64, random: &mut Random) -> String {
+    format!(
+        r#"{{"orderkey": {}, "custkey": {}, "orderstatus": "{}", "orderdate": "{}"}}"#,
+        key,
+        random.below(1000),
+        ["O", "F"][random.below(2) as usize],
+        date(random)
+    )
+}
+
+fn items(order: u64, random: &mut Random) -> Vec<String> {
+    (1..=1 + random.below(MAX_LINE_ITEMS))
+        .map(|line| {
+            format!(
+                r#"{{"orderkey": {}, "linenumber": {}, "quantity": {}, "extendedprice": {}, "returnflag": "{}", "shipdate": "{}"}}"#,
+                order,
+                line,
+                1 + random.below(50),
+                100 + random.below(100_000),
+                FLAGS[random.below(3) as usize],
+                date(random)
+            )
+        })
+        .collect()
+}
+
+fn date(random: &mut Random) -> String {
+    format!(
+        "199{}-{:02}-{:02}",
+        2 + random.below(7),
+        1 + random.below(12),
+        1 + random.below(28)
+    )
+}
+
+fn load_tpch(database: &Database, scale: u64, random: &mut Random) -> Result<TpcH, FerroError> {
+    let orders = database.create_table()?;
+    let line_items = database.create_table()?;
+    let loaded = scale * ORDERS_PER_SCALE;
+    let mut connection = database.connect();
+    let (mut order_rows, mut item_rows) = (Vec::new(), Vec::new());
+    for key in 0..loaded {
+        order_rows.push(order(key, random));
+        item_rows.extend(items(key, random));
+    }
+    for batch in order_rows.chunks(LOAD_BATCH) {
+        connection.insert_batch(orders, batch)?;
+    }
+    for batch in item_rows.chunks(LOAD_BATCH) {
+        connection.insert_batch(line_items, batch)?;
+    }
+    Ok(TpcH {
+        orders,
+        line_items,
+        loaded,
+    })
+}
+
+fn tpch_read(session: &mut SqlSession, tables: &TpcH, random: &mut Random) -> bool {
+    let TpcH {
+        orders,
+        line_items,
+        loaded,
+    } = tables;
+    let sql = match random.below(4) {
+        0 => format!(
+            "SELECT * FROM {} WHERE orderkey = '{}'",
+            orders,
+            random.below(*loaded)
+        ),
+        1 => format!(
+            "SELECT * FROM {} WHERE returnflag = '{}' ORDER BY shipdate",
+            line_items,
+            FLAGS[random.below(3) as usize]
+        ),
+        2 => format!(
+            "SELECT * FROM {} WHERE EXISTS (SELECT * FROM {} WHERE orderkey = orderkey)",
+            orders, line_items
+        ),
+        _ => format!(
+            "SELECT * FROM {} JOIN {} ON orderkey = orderkey",
+            orders, line_items
+        ),
+    };
+    session
+        .execute(&sql)
+        .into_iter()
+        .all(|result| result.is_ok())
+}
+
+fn tpch_write(session: &mut SqlSession, tables: &TpcH, key: u64, random: &mut Random) -> bool {
+    let values = |rows: Vec<String>| {
+        let rows: Vec<_> = rows.iter().map(|row| format!("('{}')", row)).collect();
+        rows.join(", ")
+    };
+    let script = format!(
+        "BEGIN; INSERT INTO {} VALUES {}; INSERT INTO {} VALUES {}; COMMIT",
+        tables.orders,
+        values(vec![order(key, random)]),
+        tables.line_items,
+        values(items(key, random))
+    );
+    let results = session.execute_script(&script);
+    results.iter().all(Result::is_ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::open_logged;
+
+    #[test]
+    fn test_tpcb() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open_logged(dir.path());
+        let options = BenchOptions {
+            operations: 201,
+            clients: 2,
+            ..BenchOptions::default()
+        };
+        let report = options.run(&database).unwrap();
+        assert_eq!(
+            report.reads.count + report.writes.count + report.failed,
+            201
+        );
+        assert!(report.reads.count > 0 && report.writes.count > 0);
+        assert!(report.reads.p50 <= report.reads.p99 && report.reads.p99 <= report.reads.max);
+
+        // Every amount moved reached its account, teller and branch alike
+        let mut connection = database.connect();
+        let total = |connection: &mut Connection, table, column| -> i64 {
+            let rows = connection.scan(TableId(table)).unwrap();
+            rows.iter()
+                .map(|row| {
+                    column_value(&row.data, column)
+                        .unwrap()
+                        .parse::<i64>()
+                        .unwrap()
+                })
+                .sum()
+        };
+        let history = total(&mut connection, 4, "delta");
+        assert_eq!(total(&mut connection, 1, "bbalance"), history);
+        assert_eq!(total(&mut connection, 2, "tbalance"), history);
+        assert_eq!(total(&mut connection, 3, "abalance"), history);
+        let written = connection.scan(TableId(4)).unwrap().len() as u64;
+        assert_eq!(written, report.writes.count);
+        assert!(report.to_string().contains("201 operations"));
+    }
+
+    #[test]
+    fn test_tpch() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open_logged(dir.path());
+        let options = BenchOptions {
+            workload: "TPC-H".parse().unwrap(),
+            operations: 40,
+            read_ratio: 0.75,
+            ..BenchOptions::default()
+        };
+        let report = options.run(&database).unwrap();
+        assert_eq!(report.failed, 0);
+        assert_eq!(report.reads.count + report.writes.count, 40);
+        let orders = database.connect().scan(TableId(1)).unwrap().len() as u64;
+        assert_eq!(orders, ORDERS_PER_SCALE + report.writes.count);
+        assert!("tpcc".parse::<Workload>().is_err());
+    }
+}
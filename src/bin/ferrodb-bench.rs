@@ -0,0 +1,105 @@
+//! Measure the database under a synthetic workload: `ferrodb-bench
+//! [OPTION]... [DIR]`.
+//!
+//! The workload's tables are loaded into new tables of the database in
+//! `DIR`, which is created if needed, or of one kept in memory if no
+//! directory is given. Its operations are then run and the throughput and
+//! latency percentiles of its reads and writes printed, so runs of two
+//! releases can be compared.
+
+use ferrodb::{BenchOptions, Database, Workload, IN_MEMORY};
+use std::env;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+
+const USAGE: &str = "\
+usage: ferrodb-bench [OPTION]... [DIR]
+
+      --workload NAME      tpcb, the default, or tpch
+      --scale N            branches for tpcb, or 150s of orders for tpch (default 1)
+      --operations N       operations to run across all clients (default 10000)
+      --clients N          connections running operations at once (default 1)
+      --read-ratio R       share of operations that are reads, from 0 to 1 (default 0.5)
+      --seed N             seed the data and operations are drawn from (default 1)
+  -h, --help               show this help
+";
+
+#[derive(Debug, Default)]
+struct Args {
+    path: Option<PathBuf>,
+    options: BenchOptions,
+    help: bool,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        fn value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+            let value = value.ok_or_else(|| format!("{} needs a value", flag))?;
+            value
+                .parse()
+                .map_err(|_| format!("invalid value {} for {}", value, flag))
+        }
+
+        let mut args = args.into_iter();
+        let mut parsed = Args::default();
+        let options = &mut parsed.options;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--workload" => {
+                    let name = args.next().ok_or("--workload needs a value")?;
+                    options.workload = Workload::from_str(&name)?;
+                }
+                "--scale" => options.scale = value(&arg, args.next())?,
+                "--operations" => options.operations = value(&arg, args.next())?,
+                "--clients" => options.clients = value(&arg, args.next())?,
+                "--read-ratio" => {
+                    options.read_ratio = value(&arg, args.next())?;
+                    if !(0.0..=1.0).contains(&options.read_ratio) {
+                        return Err("--read-ratio must be from 0 to 1".to_string());
+                    }
+                }
+                "--seed" => options.seed = value(&arg, args.next())?,
+                "-h" | "--help" => parsed.help = true,
+                flag if flag.starts_with('-') => return Err(format!("unknown option {}", flag)),
+                _ if parsed.path.is_some() => {
+                    return Err("more than one directory given".to_string())
+                }
+                _ => parsed.path = Some(PathBuf::from(arg)),
+            }
+        }
+        if parsed.options.scale == 0 || parsed.options.clients == 0 {
+            return Err("--scale and --clients must be at least 1".to_string());
+        }
+        Ok(parsed)
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) if args.help => {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Ok(args) => args,
+        Err(e) => {
+            eprint!("ferrodb-bench: {}\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ferrodb-bench: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let path = args.path.unwrap_or_else(|| PathBuf::from(IN_MEMORY));
+    let database = Database::open(&path)?;
+    let report = args.options.run(&database)?;
+    print!("{}", report);
+    Ok(())
+}
@@ -0,0 +1,220 @@
+//! Serve a database over TCP: `ferrodb-server [--set SETTING=VALUE]...
+//! [config]`.
+//!
+//! The file may be YAML, TOML or JSON, as its extension or
+//! `--config-format` says. Settings are taken from the file, then
+//! `FERRODB_*` environment variables, then `--set`, each over those
+//! before; `--describe-config` prints where each one came from.
+//!
+//! The settings that can change while the database is open, as
+//! `ferrodb::RELOADABLE` lists them, are reloaded from the file on
+//! `SIGHUP` or when it changes. A reload that changes anything else is
+//! refused, and the server carries on as it was.
+//!
+//! Rows past the time in their table's TTL column are deleted every
+//! `storage.ttl_sweep.interval_ms`, when that is set.
+//!
+//! On `SIGTERM` or `SIGINT` the server stops accepting clients, gives open
+//! transactions up to `server.shutdown_timeout_ms` to finish, rolling back
+//! the rest, and checkpoints the database before exiting.
+
+use ferrodb::{init_logging, Config, ConfigFormat, Database, Server, TtlSweeper};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How often to look for a `SIGHUP` or a change to the file.
+const RELOAD_POLL: Duration = Duration::from_millis(500);
+
+const USAGE: &str = "\
+usage: ferrodb-server [OPTION]... [CONFIG]
+
+      --set SETTING=VALUE  set SETTING, such as storage.cache_size=100
+      --config-format FORMAT
+                           read CONFIG as yaml, toml or json, whatever its extension
+      --describe-config    print each setting and where it came from, and exit
+  -h, --help               show this help
+";
+
+/// How often to look for a `SIGTERM` or `SIGINT`.
+const STOP_POLL: Duration = Duration::from_millis(100);
+
+/// Set by the `SIGHUP` handler.
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+/// Set by the `SIGTERM` and `SIGINT` handler.
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn hangup(_: libc::c_int) {
+    HANGUP.store(true, Ordering::Release);
+}
+
+extern "C" fn stop(_: libc::c_int) {
+    STOP.store(true, Ordering::Release);
+}
+
+/// Have `signal` call `handler`.
+fn handle(signal: libc::c_int, handler: extern "C" fn(libc::c_int)) {
+    // SAFETY: the handlers only store to an atomic
+    unsafe {
+        libc::signal(signal, handler as libc::sighandler_t);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Args {
+    path: Option<PathBuf>,
+    format: Option<ConfigFormat>,
+    overrides: Vec<String>,
+    describe: bool,
+    help: bool,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let mut parsed = Args::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--set" => {
+                    let setting = args.next().ok_or("--set needs a value")?;
+                    parsed.overrides.push(setting);
+                }
+                "--config-format" => {
+                    let format = args.next().ok_or("--config-format needs a value")?;
+                    parsed.format = Some(ConfigFormat::parse(&format).map_err(|e| e.to_string())?);
+                }
+                "--describe-config" => parsed.describe = true,
+                "-h" | "--help" => parsed.help = true,
+                flag if flag.starts_with('-') => return Err(format!("unknown option {}", flag)),
+                _ if parsed.path.is_some() => return Err("more than one config given".to_string()),
+                _ => parsed.path = Some(PathBuf::from(arg)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn load(&self) -> Result<Config, ferrodb::ConfigError> {
+        Config::load(
+            self.path.as_deref(),
+            self.format,
+            env::vars(),
+            &self.overrides,
+        )
+    }
+
+    fn config(&self) -> Result<Config, ferrodb::ConfigError> {
+        let config = self.load()?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) if args.help => {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Ok(args) => args,
+        Err(e) => {
+            eprint!("ferrodb-server: {}\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ferrodb-server: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    // Described even if invalid, to help find out why
+    if args.describe {
+        print!("{}", args.load()?.describe_sources());
+        return Ok(());
+    }
+    let config = args.config()?;
+    init_logging(&config.logging)?;
+    let database = Arc::new(Database::with_config(&config)?);
+    let server = Server::spawn(database.clone(), &config.server)?;
+    let sweeper = config
+        .storage
+        .ttl_sweep
+        .map(|sweep| TtlSweeper::spawn(database.clone(), sweep));
+    eprintln!("ferrodb-server: listening on {}", server.local_addr());
+    if let Some(addr) = server.metrics_addr() {
+        eprintln!("ferrodb-server: serving metrics on http://{}/metrics", addr);
+    }
+    if let Some(addr) = database.replication_addr() {
+        eprintln!("ferrodb-server: accepting replicas on {}", addr);
+    }
+    handle(libc::SIGTERM, stop);
+    handle(libc::SIGINT, stop);
+    if args.path.is_some() {
+        handle(libc::SIGHUP, hangup);
+        let database = database.clone();
+        thread::spawn(move || watch(&args, &database));
+    }
+    while !STOP.load(Ordering::Acquire) {
+        thread::sleep(STOP_POLL);
+    }
+
+    eprintln!("ferrodb-server: stopping");
+    let timeout = Duration::from_millis(config.server.shutdown_timeout_ms);
+    let rolled_back = server.drain(timeout);
+    if rolled_back > 0 {
+        eprintln!(
+            "ferrodb-server: rolled back {} unfinished transactions",
+            rolled_back
+        );
+    }
+    drop(sweeper);
+    database.checkpoint()?;
+    Ok(())
+}
+
+/// Reload the file `args` names into `database` whenever it's asked for
+/// or changes, with the same overrides as at the start.
+fn watch(args: &Args, database: &Database) {
+    let Some(path) = args.path.as_deref() else {
+        return;
+    };
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last: Option<SystemTime> = modified(path);
+    loop {
+        thread::sleep(RELOAD_POLL);
+        let now = modified(path);
+        let changed = now.is_some() && now != last;
+        if !HANGUP.swap(false, Ordering::AcqRel) && !changed {
+            continue;
+        }
+        last = now;
+        let result = args
+            .config()
+            .map_err(Into::into)
+            .and_then(|config| database.reload(&config));
+        match result {
+            Ok(changes) if changes.is_empty() => {
+                eprintln!(
+                    "ferrodb-server: reloaded {}; nothing changed",
+                    path.display()
+                )
+            }
+            Ok(changes) => eprintln!(
+                "ferrodb-server: reloaded {}; changed {}",
+                path.display(),
+                changes.join(", ")
+            ),
+            Err(e) => eprintln!("ferrodb-server: not reloading {}: {}", path.display(), e),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! The shell's backslash commands, which act on the shell rather than
+//! being statements.
+
+use ferrodb::ResultFormat;
+
+pub const HELP: &str = "\
+\\dt                 list tables
+\\d TABLE            describe a table
+\\timing [on|off]    toggle or set showing how long statements take
+\\pset format NAME   print rows as aligned, unaligned, csv, json or msgpack
+\\a                  toggle between aligned and unaligned output
+\\import FILE TABLE  insert each line of FILE into TABLE as a row
+\\sqlite FILE        recreate the tables in FILE, a SQLite .dump, or - for stdin
+\\?                  show this help
+\\q                  quit
+";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    ListTables,
+    Describe(u32),
+    /// `None` toggles
+    Timing(Option<bool>),
+    Format(ResultFormat),
+    ToggleAligned,
+    Import {
+        path: String,
+        table: u32,
+    },
+    /// Import a SQLite dump, `-` standing for stdin
+    Sqlite(String),
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// Parse a line starting with `\`.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let words = words(line.trim().trim_start_matches('\\'))?;
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        let table = |word: &str| {
+            word.parse::<u32>()
+                .map_err(|_| format!("\"{}\" isn't a table", word))
+        };
+        Ok(match words.as_slice() {
+            ["dt"] | ["d"] => Command::ListTables,
+            ["d", name] => Command::Describe(table(name)?),
+            ["timing"] => Command::Timing(None),
+            ["timing", setting] => Command::Timing(Some(on_off(setting)?)),
+            ["pset", "format", name] => Command::Format(
+                ResultFormat::parse(name).ok_or_else(|| format!("unknown format \"{}\"", name))?,
+            ),
+            ["a"] => Command::ToggleAligned,
+            ["import", path, name] => Command::Import {
+                path: path.to_string(),
+                table: table(name)?,
+            },
+            ["sqlite", path] => Command::Sqlite(path.to_string()),
+            ["?"] => Command::Help,
+            ["q"] => Command::Quit,
+            [name, ..] if is_command(name) => {
+                return Err(format!("wrong arguments for \\{}; try \\?", name))
+            }
+            _ => return Err(format!("invalid command {}; try \\?", line.trim())),
+        })
+    }
+}
+
+fn is_command(name: &str) -> bool {
+    matches!(
+        name,
+        "dt" | "d" | "timing" | "pset" | "a" | "import" | "sqlite" | "?" | "q"
+    )
+}
+
+fn on_off(setting: &str) -> Result<bool, String> {
+    match setting.to_lowercase().as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("expected on or off, not \"{}\"", setting)),
+    }
+}
+
+/// Split a command's words on whitespace, keeping `'quoted words'` whole.
+fn words(text: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '\'' {
+            chars.next();
+            let mut word = String::new();
+            loop {
+                match chars.next() {
+                    // A doubled quote stands for one
+                    Some('\'') if chars.peek() == Some(&'\'') => {
+                        chars.next();
+                        word.push('\'');
+                    }
+                    Some('\'') => break,
+                    Some(c) => word.push(c),
+                    None => return Err("unterminated quoted string".to_string()),
+                }
+            }
+            words.push(word);
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            words.push(word);
+        }
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Command::parse("\\dt"), Ok(Command::ListTables));
+        assert_eq!(Command::parse("\\d 3 "), Ok(Command::Describe(3)));
+        assert_eq!(Command::parse("\\timing"), Ok(Command::Timing(None)));
+        assert_eq!(
+            Command::parse("\\timing OFF"),
+            Ok(Command::Timing(Some(false)))
+        );
+        assert_eq!(
+            Command::parse("\\pset format csv"),
+            Ok(Command::Format(ResultFormat::Csv))
+        );
+        assert_eq!(
+            Command::parse("\\import 'my file''s.txt' 2"),
+            Ok(Command::Import {
+                path: "my file's.txt".to_string(),
+                table: 2
+            })
+        );
+        assert!(Command::parse("\\d users").is_err());
+        assert!(Command::parse("\\pset format xml").is_err());
+        assert!(Command::parse("\\import 'open 2").is_err());
+        assert_eq!(
+            Command::parse("\\sqlite app.sql"),
+            Ok(Command::Sqlite("app.sql".to_string()))
+        );
+        assert!(Command::parse("\\frobnicate").is_err());
+    }
+}
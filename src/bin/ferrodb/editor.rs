@@ -0,0 +1,358 @@
+//! Reading lines from the terminal, with editing and history.
+//!
+//! On a terminal, input is read in raw mode and edited here: the arrow
+//! keys move through the line and the history, Home and End or Ctrl-A and
+//! Ctrl-E jump to either end, Ctrl-U and Ctrl-K cut before and after the
+//! cursor, Tab completes the word before the cursor, Ctrl-C abandons the
+//! line and Ctrl-D on an empty line ends input. Anywhere else lines are
+//! read as they come.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+pub enum Line {
+    Text(String),
+    /// Ctrl-C was pressed
+    Interrupted,
+    /// The input ended, or Ctrl-D was pressed on an empty line
+    Eof,
+}
+
+pub struct Editor {
+    history: Vec<String>,
+    /// Where history is kept between runs, one entry per line
+    history_path: Option<PathBuf>,
+    terminal: bool,
+}
+
+impl Editor {
+    pub fn new(history_path: Option<PathBuf>) -> Self {
+        let history = history_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        Self {
+            history,
+            history_path,
+            terminal: unsafe { libc::isatty(libc::STDIN_FILENO) } == 1,
+        }
+    }
+
+    /// Whether input comes from a terminal, which prompts are shown on.
+    pub fn is_terminal(&self) -> bool {
+        self.terminal
+    }
+
+    /// Read a line, completing words with `complete`, which is given the
+    /// line up to the cursor and returns the words that could end it.
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        complete: &dyn Fn(&str) -> Vec<String>,
+    ) -> io::Result<Line> {
+        if !self.terminal {
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line)? == 0 {
+                return Ok(Line::Eof);
+            }
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            return Ok(Line::Text(
+                line.strip_suffix('\r').unwrap_or(line).to_string(),
+            ));
+        }
+        let _raw = RawMode::enable()?;
+        self.edit(prompt, complete)
+    }
+
+    /// Add an entry, which mustn't span lines, to the history and to the
+    /// history file.
+    pub fn add_history(&mut self, entry: &str) {
+        if entry.trim().is_empty() || self.history.last().map(String::as_str) == Some(entry) {
+            return;
+        }
+        self.history.push(entry.to_string());
+        if let Some(path) = &self.history_path {
+            // History is a convenience, so failing to save it isn't an error
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", entry);
+            }
+        }
+    }
+
+    fn edit(&mut self, prompt: &str, complete: &dyn Fn(&str) -> Vec<String>) -> io::Result<Line> {
+        let mut out = io::stdout();
+        let mut line: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // Where in the history the line shown came from; the line being
+        // written is kept aside while browsing
+        let mut browsing = self.history.len();
+        let mut draft = Vec::new();
+        redraw(&mut out, prompt, &line, cursor)?;
+        loop {
+            let key = read_key()?;
+            match key {
+                Key::Enter => {
+                    write!(out, "\r\n")?;
+                    out.flush()?;
+                    return Ok(Line::Text(line.into_iter().collect()));
+                }
+                Key::Interrupt => {
+                    write!(out, "^C\r\n")?;
+                    out.flush()?;
+                    return Ok(Line::Interrupted);
+                }
+                Key::Eof if line.is_empty() => {
+                    write!(out, "\r\n")?;
+                    out.flush()?;
+                    return Ok(Line::Eof);
+                }
+                Key::Eof | Key::Delete if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                Key::Char(c) => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                }
+                Key::Backspace if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                }
+                Key::Left if cursor > 0 => cursor -= 1,
+                Key::Right if cursor < line.len() => cursor += 1,
+                Key::Home => cursor = 0,
+                Key::End => cursor = line.len(),
+                Key::CutBefore => {
+                    line.drain(..cursor);
+                    cursor = 0;
+                }
+                Key::CutAfter => line.truncate(cursor),
+                Key::Tab => {
+                    let before: String = line[..cursor].iter().collect();
+                    let candidates = complete(&before);
+                    let start = cursor
+                        - line[..cursor]
+                            .iter()
+                            .rev()
+                            .take_while(|&&c| is_word(c))
+                            .count();
+                    let common = common_prefix(&candidates);
+                    let mut completion: Vec<char> = common.chars().collect();
+                    if candidates.len() == 1 {
+                        completion.push(' ');
+                    }
+                    if candidates.len() == 1 || completion.len() > cursor - start {
+                        line.splice(start..cursor, completion.iter().copied());
+                        cursor = start + completion.len();
+                    } else if !candidates.is_empty() {
+                        // Nothing more in common, so show the choices
+                        write!(out, "\r\n{}\r\n", candidates.join("  "))?;
+                    }
+                }
+                Key::Up if browsing > 0 => {
+                    if browsing == self.history.len() {
+                        draft = line.clone();
+                    }
+                    browsing -= 1;
+                    line = self.history[browsing].chars().collect();
+                    cursor = line.len();
+                }
+                Key::Down if browsing < self.history.len() => {
+                    browsing += 1;
+                    line = match self.history.get(browsing) {
+                        Some(entry) => entry.chars().collect(),
+                        None => std::mem::take(&mut draft),
+                    };
+                    cursor = line.len();
+                }
+                _ => {}
+            }
+            redraw(&mut out, prompt, &line, cursor)?;
+        }
+    }
+}
+
+/// Whether `c` belongs to the word the cursor ends, as completion sees it.
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The longest start all of `words` share.
+fn common_prefix(words: &[String]) -> String {
+    let Some((first, rest)) = words.split_first() else {
+        return String::new();
+    };
+    let mut len = first.len();
+    for word in rest {
+        len = first
+            .char_indices()
+            .zip(word.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((i, a), _)| i + a.len_utf8())
+            .min(len);
+    }
+    first[..len].to_string()
+}
+
+fn redraw(out: &mut impl Write, prompt: &str, line: &[char], cursor: usize) -> io::Result<()> {
+    let text: String = line.iter().collect();
+    write!(out, "\r{}{}\x1b[K", prompt, text)?;
+    if cursor < line.len() {
+        write!(out, "\x1b[{}D", line.len() - cursor)?;
+    }
+    out.flush()
+}
+
+enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Interrupt,
+    Eof,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    CutBefore,
+    CutAfter,
+    Other,
+}
+
+fn read_byte() -> io::Result<u8> {
+    let mut byte = 0u8;
+    loop {
+        let read = unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) };
+        match read {
+            1 => return Ok(byte),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            _ => {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+        }
+    }
+}
+
+fn read_key() -> io::Result<Key> {
+    let byte = match read_byte() {
+        Ok(byte) => byte,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(Key::Eof),
+        Err(e) => return Err(e),
+    };
+    Ok(match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x01 => Key::Home,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x05 => Key::End,
+        0x08 | 0x7f => Key::Backspace,
+        0x0b => Key::CutAfter,
+        0x15 => Key::CutBefore,
+        0x1b => read_escape()?,
+        byte if byte < 0x20 => Key::Other,
+        byte => {
+            // The rest of a UTF-8 sequence
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.push(read_byte()?);
+            }
+            match String::from_utf8(bytes) {
+                Ok(text) => Key::Char(text.chars().next().unwrap()),
+                Err(_) => Key::Other,
+            }
+        }
+    })
+}
+
+/// The key of an escape sequence, such as `ESC [ A` for up.
+fn read_escape() -> io::Result<Key> {
+    if !matches!(read_byte()?, b'[' | b'O') {
+        return Ok(Key::Other);
+    }
+    Ok(match read_byte()? {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        digit @ b'0'..=b'9' => {
+            let mut code = vec![digit];
+            loop {
+                match read_byte()? {
+                    b'~' => break,
+                    byte if byte.is_ascii_digit() => code.push(byte),
+                    _ => return Ok(Key::Other),
+                }
+            }
+            match code.as_slice() {
+                b"1" | b"7" => Key::Home,
+                b"3" => Key::Delete,
+                b"4" | b"8" => Key::End,
+                _ => Key::Other,
+            }
+        }
+        _ => Key::Other,
+    })
+}
+
+/// Puts the terminal in raw mode until dropped.
+struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let original = termios;
+        // Keys arrive one at a time, unechoed, with Ctrl-C as a key rather
+        // than a signal; output is processed as usual
+        termios.c_iflag &= !(libc::ICRNL | libc::IXON);
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_common_prefix() {
+        let words = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        assert_eq!(common_prefix(&words(&["DEALLOCATE", "DELETE"])), "DE");
+        assert_eq!(common_prefix(&words(&["1", "10", "11"])), "1");
+        assert_eq!(common_prefix(&words(&["héllo", "hélp"])), "hél");
+        assert_eq!(common_prefix(&words(&["FROM"])), "FROM");
+        assert_eq!(common_prefix(&[]), "");
+    }
+}
@@ -0,0 +1,752 @@
+//! `ferrodb`, an interactive shell over a database directory.
+//!
+//! ```text
+//! ferrodb [-c SQL | -f FILE]... [--format FORMAT] [directory | config.{yaml,toml,json}]
+//! ferrodb restore --archive DIR [--to TIME | --lsn LSN] [backup | config.{yaml,toml,json}]
+//! ferrodb cdc [--from LSN] [--follow] [--format FORMAT] [directory | config.{yaml,toml,json}]
+//! ferrodb dump [directory | config.{yaml,toml,json}]
+//! ferrodb merge --into DIR full [incremental]...
+//! ferrodb fsck [--salvage] [directory | config.{yaml,toml,json}]
+//! ```
+//!
+//! Statements may span lines and run once one ends in `;`. Ctrl-C while a
+//! statement runs cancels it; while typing, it abandons the statement.
+//! Lines starting with `\` are commands to the shell, listed by `\?`.
+//! Rows are printed as the session's `output_format` says, which
+//! `--format`, `\pset format` and `SET output_format` all change.
+//! The shell holds the database's files, so its statements aren't held to
+//! any user's privileges.
+//!
+//! Given `-c` or `-f`, the shell runs the statements given, or those in
+//! the file, in order and exits, stopping at the first to fail. It exits
+//! with 0 once all succeed, 1 if one fails and 2 if it can't start.
+//!
+//! Given a tool's name first, `ferrodb` runs that instead of the shell;
+//! `restore` brings a backup up to date from the log archived since, or
+//! to a point in time, `cdc` prints the changes to rows the log holds,
+//! `dump` the SQL that recreates the database, which `-f` runs, `merge`
+//! combines incremental backups with the full one they build on and
+//! `fsck` checks the database's files for damage, or salvages them.
+
+mod commands;
+mod editor;
+mod tools;
+
+use commands::Command;
+use editor::{Editor, Line};
+use ferrodb::{
+    init_logging, CancelHandle, Config, ConfigFormat, Database, DatabaseError, ResultEncoder,
+    ResultFormat, SqlError, SqlSession, StatementResult, TableId,
+};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
+use tools::Tool;
+
+/// Set by SIGINT, which only arrives while a statement runs: the editor
+/// reads Ctrl-C as a key.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+type Results = Vec<Result<StatementResult, SqlError>>;
+
+type Job = Box<dyn FnOnce(&mut SqlSession) -> Results + Send>;
+
+/// Runs statements on a thread of its own, so the shell can cancel them.
+struct Worker {
+    jobs: Sender<Job>,
+    results: Receiver<(Results, ResultFormat)>,
+    cancel: CancelHandle,
+}
+
+impl Worker {
+    /// A worker whose session prints rows as `format` until its
+    /// `output_format` is set.
+    fn start(database: Arc<Database>, format: ResultFormat) -> Self {
+        let (jobs, received) = mpsc::channel::<Job>();
+        let (sender, results) = mpsc::channel();
+        let (handle, cancel) = mpsc::channel();
+        thread::spawn(move || {
+            let mut session = SqlSession::new(database.connect());
+            set_format(&mut session, format);
+            handle.send(session.connection().cancel_handle()).unwrap();
+            for job in received {
+                let results = job(&mut session);
+                if sender.send((results, session.output_format())).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            jobs,
+            results,
+            cancel: cancel.recv().expect("worker thread stopped"),
+        }
+    }
+
+    /// Run `job` in the session, cancelling it on SIGINT, and return its
+    /// results with the format the session prints rows in after it.
+    fn run(
+        &self,
+        job: impl FnOnce(&mut SqlSession) -> Results + Send + 'static,
+    ) -> (Results, ResultFormat) {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        self.jobs
+            .send(Box::new(job))
+            .expect("worker thread stopped");
+        loop {
+            match self.results.recv_timeout(Duration::from_millis(50)) {
+                Ok(results) => return results,
+                Err(RecvTimeoutError::Timeout) => {
+                    if INTERRUPTED.swap(false, Ordering::SeqCst) {
+                        self.cancel.cancel();
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => panic!("worker thread stopped"),
+            }
+        }
+    }
+}
+
+/// The worker running the shell's statements, and the settings its
+/// commands change.
+struct Shell {
+    database: Arc<Database>,
+    worker: Worker,
+    timing: bool,
+    /// The session's `output_format`, as of its last statement
+    format: ResultFormat,
+    /// Whether a statement or command has failed
+    failed: bool,
+}
+
+impl Shell {
+    fn execute(&mut self, sql: String) {
+        self.run(move |session| session.execute(&sql));
+    }
+
+    /// Run the statements and commands in `script`, stopping at the first
+    /// to fail, and return whether it quit.
+    fn script(&mut self, script: &str) -> bool {
+        let mut sql = String::new();
+        for line in script.lines() {
+            if sql.is_empty() && line.trim_start().starts_with('\\') {
+                if self.command(line) {
+                    return true;
+                }
+            } else {
+                sql.push_str(line);
+                sql.push('\n');
+                if is_complete(&sql) {
+                    self.execute(std::mem::take(&mut sql));
+                }
+            }
+            if self.failed {
+                return false;
+            }
+        }
+        // The last statement needn't end in `;`
+        if !sql.trim().is_empty() {
+            self.execute(sql);
+        }
+        false
+    }
+
+    /// Run a backslash command, returning whether to quit.
+    fn command(&mut self, line: &str) -> bool {
+        let command = match Command::parse(line) {
+            Ok(command) => command,
+            Err(e) => {
+                self.error(&e);
+                return false;
+            }
+        };
+        match command {
+            Command::ListTables => {
+                // A partitioned table's rows are counted with it, not in
+                // each of its partitions
+                let mut tables = self.database.tables();
+                let partitions: Vec<_> = tables
+                    .iter()
+                    .filter_map(|&table| self.database.partitions(table))
+                    .flatten()
+                    .collect();
+                tables.retain(|table| !partitions.contains(table));
+                self.run(move |session| vec![list_tables(session, &tables)]);
+            }
+            Command::Describe(table) => {
+                let result = describe(&self.database, TableId(table));
+                self.print(vec![result]);
+            }
+            Command::Timing(setting) => {
+                self.timing = setting.unwrap_or(!self.timing);
+                println!("Timing is {}.", if self.timing { "on" } else { "off" });
+            }
+            Command::Format(format) => self.format(format),
+            Command::ToggleAligned => self.format(match self.format {
+                ResultFormat::Aligned => ResultFormat::Unaligned,
+                _ => ResultFormat::Aligned,
+            }),
+            Command::Import { path, table } => match fs::read(&path) {
+                Ok(contents) => {
+                    self.run(move |session| vec![import(session, TableId(table), &contents)]);
+                }
+                Err(e) => self.error(&format!("{}: {}", path, e)),
+            },
+            Command::Sqlite(path) => {
+                let input: Box<dyn BufRead + Send> = if path == "-" {
+                    Box::new(BufReader::new(io::stdin()))
+                } else {
+                    match fs::File::open(&path) {
+                        Ok(file) => Box::new(BufReader::new(file)),
+                        Err(e) => {
+                            self.error(&format!("{}: {}", path, e));
+                            return false;
+                        }
+                    }
+                };
+                self.run(move |session| vec![import_sqlite(session, input)]);
+            }
+            Command::Help => print!("{}", commands::HELP),
+            Command::Quit => return true,
+        }
+        false
+    }
+
+    /// Print rows as `format`, as `SET output_format` would have them.
+    fn format(&mut self, format: ResultFormat) {
+        (_, self.format) = self.worker.run(move |session| {
+            set_format(session, format);
+            Vec::new()
+        });
+        println!("Output format is {}.", self.format.name());
+    }
+
+    /// Run `job` on the worker and print its results, and how long it
+    /// took if timing is on.
+    fn run(&mut self, job: impl FnOnce(&mut SqlSession) -> Results + Send + 'static) {
+        let start = Instant::now();
+        let results;
+        (results, self.format) = self.worker.run(job);
+        let elapsed = start.elapsed();
+        self.print(results);
+        if self.timing {
+            println!("Time: {:.3} ms", elapsed.as_secs_f64() * 1000.0);
+        }
+    }
+
+    fn print(&mut self, results: Results) {
+        for result in results {
+            match result {
+                Ok(result) => {
+                    // As print! would, short of panicking when stdout closes
+                    let _ = self.format.encode(&result, &mut io::stdout().lock());
+                }
+                Err(e) => self.error(&format!("ERROR:  {}", e.message())),
+            }
+        }
+    }
+
+    fn error(&mut self, message: &str) {
+        eprintln!("{}", message);
+        self.failed = true;
+    }
+}
+
+fn set_format(session: &mut SqlSession, format: ResultFormat) {
+    session
+        .set("output_format", format.name())
+        .expect("a format's name is a value of output_format");
+}
+
+/// Each table with the number of rows it holds.
+fn list_tables(session: &mut SqlSession, tables: &[TableId]) -> Result<StatementResult, SqlError> {
+    let mut rows = Vec::new();
+    for &table in tables {
+        let count = session.connection_mut().scan(table)?.len();
+        rows.push(vec![Some(table.to_string()), Some(count.to_string())]);
+    }
+    Ok(StatementResult {
+        columns: vec!["table".to_string(), "rows".to_string()],
+        tag: format!("SELECT {}", rows.len()),
+        rows,
+    })
+}
+
+/// A table's columns, which every table shares: rows are untyped, so all
+/// a table has is each row's id and its data.
+fn describe(database: &Database, table: TableId) -> Result<StatementResult, SqlError> {
+    if !database.tables().contains(&table) {
+        return Err(DatabaseError::NoSuchTable(table).into());
+    }
+    let rows = [("id", "row id"), ("data", "text")]
+        .iter()
+        .map(|(column, kind)| vec![Some(column.to_string()), Some(kind.to_string())])
+        .collect();
+    Ok(StatementResult {
+        columns: vec!["column".to_string(), "type".to_string()],
+        rows,
+        tag: "SELECT 2".to_string(),
+    })
+}
+
+/// Insert each line of `contents` into `table` as a row, all of them or,
+/// on failure, none.
+fn import(
+    session: &mut SqlSession,
+    table: TableId,
+    contents: &[u8],
+) -> Result<StatementResult, SqlError> {
+    let connection = session.connection_mut();
+    let implicit = !connection.in_transaction();
+    if implicit {
+        connection.begin()?;
+    }
+    let lines = contents
+        .split(|&byte| byte == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    let mut count = 0;
+    let inserted: Result<(), DatabaseError> =
+        lines.filter(|line| !line.is_empty()).try_for_each(|line| {
+            connection.insert(table, line)?;
+            count += 1;
+            Ok(())
+        });
+    match inserted {
+        Ok(()) if implicit => connection.commit()?,
+        Err(_) if implicit => connection.rollback()?,
+        _ => {}
+    }
+    inserted?;
+    Ok(StatementResult {
+        columns: Vec::new(),
+        rows: Vec::new(),
+        tag: format!("INSERT 0 {}", count),
+    })
+}
+
+/// Import the SQLite dump read from `input`, listing each of its tables
+/// with the table it became and how many rows went in.
+fn import_sqlite(
+    session: &mut SqlSession,
+    input: impl BufRead,
+) -> Result<StatementResult, SqlError> {
+    let tables = session.connection_mut().import_sqlite_dump(input)?;
+    let rows = tables
+        .into_iter()
+        .map(|imported| {
+            vec![
+                Some(imported.name),
+                Some(imported.table.to_string()),
+                Some(imported.rows.to_string()),
+            ]
+        })
+        .collect::<Vec<_>>();
+    Ok(StatementResult {
+        columns: ["sqlite table", "table", "rows"]
+            .iter()
+            .map(|column| column.to_string())
+            .collect(),
+        tag: format!("SELECT {}", rows.len()),
+        rows,
+    })
+}
+
+/// Whether `sql` ends a statement: it ends in `;` outside any string or
+/// comment.
+fn is_complete(sql: &str) -> bool {
+    let mut chars = sql.chars().peekable();
+    let mut quote = None;
+    let mut complete = false;
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '\'' | '"' => quote = Some(c),
+                '-' if chars.peek() == Some(&'-') => {
+                    for c in chars.by_ref() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                }
+                ';' => complete = true,
+                c if c.is_whitespace() => {}
+                _ => complete = false,
+            },
+        }
+        if quote.is_some() {
+            complete = false;
+        }
+    }
+    complete
+}
+
+fn open(args: &Args) -> Result<Database, String> {
+    let path = args.path.as_path();
+    let database = match config(args)? {
+        Some(config) => Database::with_config(&config),
+        None => Database::open(path),
+    };
+    database.map_err(|e| format!("could not open {}: {}", path.display(), e))
+}
+
+/// The configuration in the CONFIG file `args` name, with logging started
+/// as it says, or `None` for a DIRECTORY.
+fn config(args: &Args) -> Result<Option<Config>, String> {
+    let path = args.path.as_path();
+    let is_config = args.config_format.is_some() || ConfigFormat::from_path(path).is_some();
+    if !is_config && !args.overrides.is_empty() {
+        return Err("--set needs a CONFIG file".to_string());
+    }
+    if !is_config {
+        return Ok(None);
+    }
+    let config = Config::load(Some(path), args.config_format, env::vars(), &args.overrides)
+        .and_then(|config| config.validate().map(|()| config))
+        .map_err(|e| e.to_string())?;
+    init_logging(&config.logging).map_err(|e| e.to_string())?;
+    Ok(Some(config))
+}
+
+/// Run `tool` on the database `args` name.
+fn run_tool(tool: &Tool, args: &Args) -> ExitCode {
+    let config = config(args).map(|config| {
+        config.unwrap_or_else(|| {
+            let mut config = Config::default();
+            config.storage.db_path = args.path.to_string_lossy().into_owned();
+            config
+        })
+    });
+    match config.and_then(|config| tool.run(&config, args.format)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ferrodb: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+const USAGE: &str = "\
+usage: ferrodb [OPTION]... [DIRECTORY | CONFIG]
+       ferrodb restore --archive DIR [--to TIME | --lsn LSN] [OPTION]...
+                       [BACKUP | CONFIG]
+       ferrodb cdc [--from LSN] [--follow] [OPTION]... [DIRECTORY | CONFIG]
+       ferrodb dump [OPTION]... [DIRECTORY | CONFIG]
+       ferrodb merge --into DIR FULL [INCREMENTAL]...
+       ferrodb fsck [--salvage] [OPTION]... [DIRECTORY | CONFIG]
+
+  -c, --command SQL    run SQL, or a backslash command, and exit
+  -f, --file FILE      run the statements in FILE, or - for stdin, and exit
+      --format FORMAT  print rows as table, unaligned, csv, json or msgpack
+      --csv            print rows as CSV
+      --json           print rows as JSON
+      --set SETTING=VALUE
+                       set SETTING of CONFIG, such as storage.cache_size=100
+      --config-format FORMAT
+                       read CONFIG as yaml, toml or json, whatever its extension
+  -h, --help           show this help
+
+restore replays the log archived in DIR over BACKUP, a copy made with
+BACKUP TO, bringing it up to date, or only to the last transaction to
+commit by TIME, such as 2026-10-12 09:00:00Z, or before LSN.
+
+cdc prints the changes to rows committed from LSN on, as far as the log
+goes back, and with --follow keeps printing them as they commit.
+
+dump prints the SQL that recreates the database, for -f to run in a new
+one.
+
+merge combines FULL, a copy made with BACKUP TO, and the copies made
+after it with BACKUP TO ... INCREMENTAL FROM, each from the one before,
+into a full copy in DIR, which restore or the shell open like any other.
+
+fsck checks every page and the log of a database nothing else has open,
+printing what is damaged and exiting with 1 if anything is. With
+--salvage it rebuilds damaged pages from the rows still readable in them,
+emptying those it can't read at all, so the rest can be used.
+";
+
+/// What to run without a prompt.
+#[derive(Debug, PartialEq, Eq)]
+enum Script {
+    Command(String),
+    File(PathBuf),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    path: PathBuf,
+    scripts: Vec<Script>,
+    format: ResultFormat,
+    /// Settings given with `--set`, over the config's
+    overrides: Vec<String>,
+    /// How to read the config, if not as its extension says
+    config_format: Option<ConfigFormat>,
+    /// The tool to run instead of the shell
+    tool: Option<Tool>,
+    help: bool,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter().peekable();
+        let mut path = None;
+        let mut parsed = Args {
+            path: PathBuf::new(),
+            scripts: Vec::new(),
+            format: ResultFormat::Aligned,
+            overrides: Vec::new(),
+            config_format: None,
+            tool: args.peek().and_then(|name| Tool::named(name)),
+            help: false,
+        };
+        if parsed.tool.is_some() {
+            args.next();
+        }
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            if let Some(tool) = &mut parsed.tool {
+                if tool.option(&arg, &mut value)? {
+                    continue;
+                }
+            }
+            match arg.as_str() {
+                "-c" | "--command" => parsed.scripts.push(Script::Command(value()?)),
+                "-f" | "--file" => parsed.scripts.push(Script::File(value()?.into())),
+                "--format" => {
+                    let name = value()?;
+                    parsed.format = ResultFormat::parse(&name)
+                        .ok_or_else(|| format!("unknown format \"{}\"", name))?;
+                }
+                "--csv" => parsed.format = ResultFormat::Csv,
+                "--json" => parsed.format = ResultFormat::Json,
+                "--set" => parsed.overrides.push(value()?),
+                "--config-format" => {
+                    let format = ConfigFormat::parse(&value()?).map_err(|e| e.to_string())?;
+                    parsed.config_format = Some(format);
+                }
+                "-h" | "--help" => parsed.help = true,
+                flag if flag.starts_with('-') && flag != "-" => {
+                    return Err(format!("unknown option {}", flag))
+                }
+                _ if parsed.tool.as_mut().is_some_and(|tool| tool.operand(&arg)) => {}
+                _ if path.is_some() => return Err("more than one database given".to_string()),
+                _ => path = Some(PathBuf::from(arg)),
+            }
+        }
+        parsed.path = path.unwrap_or_else(|| Config::default().storage.db_path.into());
+        if let Some(tool) = &parsed.tool {
+            if !parsed.scripts.is_empty() {
+                return Err("-c and -f are only for the shell".to_string());
+            }
+            if !parsed.help {
+                tool.check()?;
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) if args.help => {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Ok(args) => args,
+        Err(e) => {
+            eprint!("ferrodb: {}\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    if let Some(tool) = &args.tool {
+        return run_tool(tool, &args);
+    }
+    let database = match open(&args) {
+        Ok(database) => Arc::new(database),
+        Err(e) => {
+            eprintln!("ferrodb: {}", e);
+            return ExitCode::from(2);
+        }
+    };
+    unsafe { libc::signal(libc::SIGINT, interrupt as *const () as libc::sighandler_t) };
+
+    let mut shell = Shell {
+        worker: Worker::start(database.clone(), args.format),
+        database,
+        timing: false,
+        format: args.format,
+        failed: false,
+    };
+    if args.scripts.is_empty() {
+        return interact(&mut shell);
+    }
+    for script in &args.scripts {
+        let text = match script {
+            Script::Command(sql) => sql.clone(),
+            Script::File(path) if path.as_os_str() == "-" => {
+                let mut text = String::new();
+                if let Err(e) = io::stdin().read_to_string(&mut text) {
+                    eprintln!("ferrodb: stdin: {}", e);
+                    return ExitCode::from(2);
+                }
+                text
+            }
+            Script::File(path) => match fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(e) => {
+                    eprintln!("ferrodb: {}: {}", path.display(), e);
+                    return ExitCode::from(2);
+                }
+            },
+        };
+        let quit = shell.script(&text);
+        if shell.failed {
+            return ExitCode::FAILURE;
+        }
+        if quit {
+            break;
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Read statements and commands from the terminal, or whatever stdin is,
+/// until it ends or `\\q`, carrying on past failures.
+fn interact(shell: &mut Shell) -> ExitCode {
+    let history = env::var_os("HOME").map(|home| Path::new(&home).join(".ferrodb_history"));
+    let mut editor = Editor::new(history);
+    let mut sql = String::new();
+    loop {
+        let prompt = if !editor.is_terminal() {
+            ""
+        } else if sql.is_empty() {
+            "ferrodb=> "
+        } else {
+            "ferrodb-> "
+        };
+        let complete = |before: &str| shell.database.complete(&format!("{}{}", sql, before));
+        let line = match editor.read_line(prompt, &complete) {
+            Ok(Line::Text(line)) => line,
+            Ok(Line::Interrupted) => {
+                sql.clear();
+                continue;
+            }
+            Ok(Line::Eof) => break,
+            Err(e) => {
+                eprintln!("ferrodb: {}", e);
+                return ExitCode::from(2);
+            }
+        };
+        if sql.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+        if sql.is_empty() && line.trim_start().starts_with('\\') {
+            editor.add_history(line.trim());
+            if shell.command(&line) {
+                break;
+            }
+            continue;
+        }
+        sql.push_str(&line);
+        sql.push('\n');
+        if !is_complete(&sql) {
+            continue;
+        }
+        editor.add_history(&sql.trim_end().replace('\n', " "));
+        shell.execute(std::mem::take(&mut sql));
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_complete() {
+        assert!(is_complete("BEGIN;"));
+        assert!(is_complete("BEGIN; COMMIT;  \n"));
+        assert!(is_complete("SELECT * FROM 1; -- done\n"));
+        assert!(!is_complete("INSERT INTO 1\n"));
+        assert!(!is_complete("INSERT INTO 1 VALUES ('a;\n"));
+        assert!(is_complete("INSERT INTO 1 VALUES ('a;\n');"));
+        assert!(!is_complete("BEGIN -- ;\n"));
+    }
+
+    #[test]
+    fn test_args() {
+        let parse = |args: &[&str]| Args::parse(args.iter().map(|arg| arg.to_string()));
+        let args = parse(&[
+            "-c",
+            "SELECT * FROM 1",
+            "-f",
+            "-",
+            "--csv",
+            "--set",
+            "storage.cache_size=100",
+            "db.yaml",
+        ])
+        .unwrap();
+        assert_eq!(
+            args,
+            Args {
+                path: PathBuf::from("db.yaml"),
+                scripts: vec![
+                    Script::Command("SELECT * FROM 1".to_string()),
+                    Script::File(PathBuf::from("-")),
+                ],
+                format: ResultFormat::Csv,
+                overrides: vec!["storage.cache_size=100".to_string()],
+                config_format: None,
+                tool: None,
+                help: false,
+            }
+        );
+        assert_eq!(
+            parse(&["--format", "table"]).unwrap().path,
+            PathBuf::from(Config::default().storage.db_path)
+        );
+        assert!(parse(&["-c"]).is_err());
+        assert!(parse(&["--format", "xml"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+        let args = parse(&["--config-format", "toml", "ferrodb.conf"]).unwrap();
+        assert_eq!(args.config_format, Some(ConfigFormat::Toml));
+        assert!(parse(&["--config-format", "ini"]).is_err());
+        assert!(parse(&["one", "two"]).is_err());
+
+        let args = parse(&["restore", "--archive", "archive", "backup"]).unwrap();
+        assert_eq!(
+            args.tool,
+            Some(Tool::Restore {
+                archive: Some(PathBuf::from("archive")),
+                target: None,
+            })
+        );
+        assert_eq!(args.path, PathBuf::from("backup"));
+        assert!(parse(&["restore", "backup"]).is_err());
+        assert!(parse(&["restore", "--archive", "archive", "-c", "SELECT 1"]).is_err());
+        assert!(parse(&["--archive", "archive"]).is_err());
+        let args = parse(&["merge", "full", "monday", "--into", "merged"]).unwrap();
+        assert_eq!(
+            args.tool,
+            Some(Tool::Merge {
+                chain: vec![PathBuf::from("full"), PathBuf::from("monday")],
+                into: Some(PathBuf::from("merged")),
+            })
+        );
+        assert!(parse(&["merge", "full"]).is_err());
+        // A tool is only named first
+        assert_eq!(parse(&["--csv", "restore"]).unwrap().tool, None);
+    }
+}
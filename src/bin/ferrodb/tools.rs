@@ -0,0 +1,285 @@
+//! The subcommands `ferrodb` runs in place of the shell, named by its first
+//! argument, which act on a database's files rather than through
+//! statements.
+
+use ferrodb::{Change, Config, Database, RestoreTarget, ResultFormat, StatementResult};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// How long `cdc --follow` waits before looking for newer changes.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Tool {
+    /// Replay the log archived in `archive` over a backup, bringing it up
+    /// to date or to `target`
+    Restore {
+        archive: Option<PathBuf>,
+        target: Option<RestoreTarget>,
+    },
+    /// Print the changes to rows committed from the log position `from`
+    /// on, then, with `follow`, those committed later
+    Cdc { from: u64, follow: bool },
+    /// Print the SQL that recreates the database
+    Dump,
+    /// Combine a full backup and the incremental backups taken after it,
+    /// in `chain`, into a full backup in `into`
+    Merge {
+        chain: Vec<PathBuf>,
+        into: Option<PathBuf>,
+    },
+    /// Check the database's files for damage, and with `salvage` rebuild
+    /// the damaged pages from what can still be read of them
+    Fsck { salvage: bool },
+}
+
+impl Tool {
+    /// The tool called `name`, before its options are given.
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "restore" => Some(Tool::Restore {
+                archive: None,
+                target: None,
+            }),
+            "cdc" => Some(Tool::Cdc {
+                from: 0,
+                follow: false,
+            }),
+            "dump" => Some(Tool::Dump),
+            "merge" => Some(Tool::Merge {
+                chain: Vec::new(),
+                into: None,
+            }),
+            "fsck" => Some(Tool::Fsck { salvage: false }),
+            _ => None,
+        }
+    }
+
+    /// Take `flag` if it is one of the tool's own options, reading its
+    /// value with `value`. False if the tool has no such option.
+    pub fn option(
+        &mut self,
+        flag: &str,
+        mut value: impl FnMut() -> Result<String, String>,
+    ) -> Result<bool, String> {
+        match (self, flag) {
+            (Tool::Restore { archive, .. }, "--archive") => *archive = Some(value()?.into()),
+            (Tool::Restore { target, .. }, "--to") => {
+                let time = value()?;
+                let parsed = RestoreTarget::parse_time(&time)
+                    .ok_or_else(|| format!("\"{}\" isn't a time", time))?;
+                *target = Some(parsed);
+            }
+            (Tool::Restore { target, .. }, "--lsn") => {
+                *target = Some(RestoreTarget::Lsn(lsn(&value()?)?))
+            }
+            (Tool::Cdc { from, .. }, "--from") => *from = lsn(&value()?)?,
+            (Tool::Cdc { follow, .. }, "--follow") => *follow = true,
+            (Tool::Merge { into, .. }, "--into") => *into = Some(value()?.into()),
+            (Tool::Fsck { salvage }, "--salvage") => *salvage = true,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Take `arg`, given other than as an option, if the tool reads such
+    /// arguments itself rather than as the database to act on.
+    pub fn operand(&mut self, arg: &str) -> bool {
+        match self {
+            Tool::Merge { chain, .. } => chain.push(arg.into()),
+            Tool::Restore { .. } | Tool::Cdc { .. } | Tool::Dump | Tool::Fsck { .. } => {
+                return false
+            }
+        }
+        true
+    }
+
+    /// Check the options every use of the tool needs were given.
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            Tool::Restore { archive: None, .. } => Err("restore needs --archive".to_string()),
+            Tool::Merge { into: None, .. } => Err("merge needs --into".to_string()),
+            Tool::Merge { chain, .. } if chain.is_empty() => {
+                Err("merge needs the backups to combine".to_string())
+            }
+            Tool::Restore { .. }
+            | Tool::Cdc { .. }
+            | Tool::Dump
+            | Tool::Merge { .. }
+            | Tool::Fsck { .. } => Ok(()),
+        }
+    }
+
+    /// Run the tool on the database `config` describes, printing rows as
+    /// `format` says.
+    pub fn run(&self, config: &Config, format: ResultFormat) -> Result<(), String> {
+        let path = &config.storage.db_path;
+        let open =
+            || Database::with_config(config).map_err(|e| format!("could not open {}: {}", path, e));
+        match self {
+            Tool::Restore { archive, target } => {
+                let archive = archive.as_ref().expect("checked by Tool::check");
+                let database = Database::restore(config, archive, *target)
+                    .map_err(|e| format!("could not restore {}: {}", path, e))?;
+                database.checkpoint().map_err(|e| e.to_string())?;
+                println!("restored {}", path);
+            }
+            Tool::Cdc { from, follow } => {
+                let database = open()?;
+                let mut changes = database.changes(*from);
+                loop {
+                    let rows = changes
+                        .by_ref()
+                        .map(|change| change.map(change_row))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|e| e.to_string())?;
+                    if !rows.is_empty() || !follow {
+                        let result = StatementResult {
+                            columns: ["lsn", "row", "change", "before", "after"]
+                                .map(String::from)
+                                .to_vec(),
+                            tag: format!("CHANGES {}", rows.len()),
+                            rows,
+                        };
+                        io::stdout()
+                            .write_all(&format.render(&result))
+                            .map_err(|e| e.to_string())?;
+                    }
+                    if !follow {
+                        break;
+                    }
+                    thread::sleep(FOLLOW_INTERVAL);
+                }
+            }
+            Tool::Dump => {
+                let database = open()?;
+                database
+                    .connect()
+                    .dump(io::stdout().lock())
+                    .map_err(|e| e.to_string())?;
+            }
+            Tool::Merge { chain, into } => {
+                let into = into.as_ref().expect("checked by Tool::check");
+                Database::merge_backups(chain, into)
+                    .map_err(|e| format!("could not merge into {}: {}", into.display(), e))?;
+                println!("merged {} backups into {}", chain.len(), into.display());
+            }
+            Tool::Fsck { salvage } => {
+                let report = Database::fsck(config, *salvage)
+                    .map_err(|e| format!("could not check {}: {}", path, e))?;
+                for problem in &report.problems {
+                    println!("{}", problem);
+                }
+                println!(
+                    "checked {} pages, found {} problems",
+                    report.pages,
+                    report.problems.len()
+                );
+                if *salvage {
+                    println!(
+                        "repaired {} pages, losing {} records",
+                        report.repaired, report.lost_records
+                    );
+                }
+                if !report.is_clean() {
+                    return Err(format!("{} is damaged", path));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A log position given as an option.
+fn lsn(text: &str) -> Result<u64, String> {
+    text.parse()
+        .map_err(|_| format!("\"{}\" isn't an LSN", text))
+}
+
+/// `change` as a row of `cdc`'s output.
+fn change_row(change: Change) -> Vec<Option<String>> {
+    let kind = match (&change.before, &change.after) {
+        (None, _) => "insert",
+        (Some(_), Some(_)) => "update",
+        (Some(_), None) => "delete",
+    };
+    let text = |data: Option<Vec<u8>>| data.map(|data| String::from_utf8_lossy(&data).into_owned());
+    vec![
+        Some(change.lsn.to_string()),
+        Some(change.row.to_string()),
+        Some(kind.to_string()),
+        text(change.before),
+        text(change.after),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options() {
+        let mut tool = Tool::named("restore").unwrap();
+        assert!(tool.check().is_err());
+        assert!(tool
+            .option("--archive", || Ok("archive".to_string()))
+            .unwrap());
+        assert!(!tool
+            .option("--csv", || unreachable!("not the tool's"))
+            .unwrap());
+        assert!(tool
+            .option("--archive", || Err("no value".to_string()))
+            .is_err());
+        assert!(tool.option("--lsn", || Ok("8616".to_string())).unwrap());
+        assert!(tool.option("--lsn", || Ok("soon".to_string())).is_err());
+        assert!(tool.option("--to", || Ok("monday".to_string())).is_err());
+        assert_eq!(
+            tool,
+            Tool::Restore {
+                archive: Some(PathBuf::from("archive")),
+                target: Some(RestoreTarget::Lsn(8616)),
+            }
+        );
+        assert!(tool
+            .option("--to", || Ok("2026-10-12 09:00:00Z".to_string()))
+            .unwrap());
+        assert!(tool.check().is_ok());
+        assert_eq!(Tool::named("shell"), None);
+        assert_eq!(Tool::named("dump"), Some(Tool::Dump));
+
+        let mut tool = Tool::named("cdc").unwrap();
+        assert!(tool.option("--from", || Ok("42".to_string())).unwrap());
+        assert!(tool.option("--follow", || unreachable!("a flag")).unwrap());
+        assert!(tool
+            .option("--archive", || Ok("archive".to_string()))
+            .is_ok_and(|taken| !taken));
+        assert_eq!(
+            tool,
+            Tool::Cdc {
+                from: 42,
+                follow: true
+            }
+        );
+        assert!(!tool.operand("db"));
+
+        let mut tool = Tool::named("merge").unwrap();
+        assert!(tool.option("--into", || Ok("merged".to_string())).unwrap());
+        assert!(tool.check().is_err());
+        assert!(tool.operand("full"));
+        assert!(tool.operand("monday"));
+        assert!(tool.check().is_ok());
+        assert_eq!(
+            tool,
+            Tool::Merge {
+                chain: vec![PathBuf::from("full"), PathBuf::from("monday")],
+                into: Some(PathBuf::from("merged")),
+            }
+        );
+        let mut tool = Tool::named("fsck").unwrap();
+        assert!(tool.option("--salvage", || unreachable!("a flag")).unwrap());
+        assert_eq!(tool, Tool::Fsck { salvage: true });
+        assert!(!tool.operand("db"));
+    }
+}
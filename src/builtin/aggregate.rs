@@ -0,0 +1,386 @@
+//! Aggregates, folding the data of a table's rows. Those on numbers read
+//! each row's as `math` does, making a float of floats and otherwise a
+//! decimal, except that `sum` of integers stays one while it fits and a
+//! standard deviation, seldom exact, is always a float; over no rows,
+//! every aggregate but `count` makes NULL.
+
+use super::math::{self, Decimal, Number, INEXACT_PLACES};
+use super::Aggregate;
+use crate::partition::compare;
+use crate::sql::SqlError;
+
+pub(super) const FUNCTIONS: &[Aggregate] = &[
+    Aggregate {
+        name: "array_agg",
+        arity: (0, 0),
+        fold: |rows, _| Ok((!rows.is_empty()).then(|| array(rows))),
+    },
+    Aggregate {
+        name: "avg",
+        arity: (0, 0),
+        fold: |rows, _| Ok(average(&numbers(rows)?)?.map(|avg| avg.to_string())),
+    },
+    Aggregate {
+        name: "bool_and",
+        arity: (0, 0),
+        fold: |rows, _| booleans(rows, true),
+    },
+    Aggregate {
+        name: "bool_or",
+        arity: (0, 0),
+        fold: |rows, _| booleans(rows, false),
+    },
+    Aggregate {
+        name: "count",
+        arity: (0, 0),
+        fold: |rows, _| Ok(Some(rows.len().to_string())),
+    },
+    Aggregate {
+        name: "max",
+        arity: (0, 0),
+        fold: |rows, _| {
+            let max = rows
+                .iter()
+                .max_by(|a, b| compare(a.as_bytes(), b.as_bytes()));
+            Ok(max.map(|max| max.to_string()))
+        },
+    },
+    Aggregate {
+        name: "min",
+        arity: (0, 0),
+        fold: |rows, _| {
+            let min = rows
+                .iter()
+                .min_by(|a, b| compare(a.as_bytes(), b.as_bytes()));
+            Ok(min.map(|min| min.to_string()))
+        },
+    },
+    Aggregate {
+        name: "stddev",
+        arity: (0, 0),
+        fold: |rows, _| deviation(rows, 1),
+    },
+    Aggregate {
+        name: "stddev_pop",
+        arity: (0, 0),
+        fold: |rows, _| deviation(rows, 0),
+    },
+    Aggregate {
+        name: "stddev_samp",
+        arity: (0, 0),
+        fold: |rows, _| deviation(rows, 1),
+    },
+    // The data, each after the delimiter but the first
+    Aggregate {
+        name: "string_agg",
+        arity: (1, 1),
+        fold: |rows, args| Ok((!rows.is_empty()).then(|| rows.join(args[0]))),
+    },
+    Aggregate {
+        name: "sum",
+        arity: (0, 0),
+        fold: |rows, _| Ok(sum(&numbers(rows)?)?.map(|sum| sum.to_string())),
+    },
+    Aggregate {
+        name: "var_pop",
+        arity: (0, 0),
+        fold: |rows, _| Ok(variance(&numbers(rows)?, 0)?.map(|variance| variance.to_string())),
+    },
+    Aggregate {
+        name: "var_samp",
+        arity: (0, 0),
+        fold: |rows, _| Ok(variance(&numbers(rows)?, 1)?.map(|variance| variance.to_string())),
+    },
+    Aggregate {
+        name: "variance",
+        arity: (0, 0),
+        fold: |rows, _| Ok(variance(&numbers(rows)?, 1)?.map(|variance| variance.to_string())),
+    },
+];
+
+fn numbers(rows: &[&str]) -> Result<Vec<Number>, SqlError> {
+    rows.iter().map(|row| math::number(row)).collect()
+}
+
+fn sum(numbers: &[Number]) -> Result<Option<Number>, SqlError> {
+    if numbers.is_empty() {
+        return Ok(None);
+    }
+    if numbers.iter().any(|n| matches!(n, Number::Float(_))) {
+        return Ok(Some(Number::Float(
+            numbers.iter().map(|n| n.to_f64()).sum(),
+        )));
+    }
+    let decimals: Vec<Decimal> = numbers
+        .iter()
+        .map(|n| match n {
+            Number::Integer(n) => Decimal::from(*n),
+            Number::Decimal(decimal) => *decimal,
+            Number::Float(_) => unreachable!("floats were summed as floats above"),
+        })
+        .collect();
+    let scale = decimals.iter().map(|decimal| decimal.scale).max().unwrap();
+    let mut digits: i128 = 0;
+    for decimal in decimals {
+        let term = decimal.rescale(scale)?.digits;
+        digits = digits.checked_add(term).ok_or_else(math::out_of_range)?;
+    }
+    let integers = numbers.iter().all(|n| matches!(n, Number::Integer(_)));
+    Ok(Some(match i64::try_from(digits) {
+        Ok(n) if integers => Number::Integer(n),
+        _ => Number::Decimal(Decimal { digits, scale }),
+    }))
+}
+
+/// The mean of `numbers`, as a decimal to at least 16 places unless
+/// they're floats.
+fn average(numbers: &[Number]) -> Result<Option<Number>, SqlError> {
+    let Some(sum) = sum(numbers)? else {
+        return Ok(None);
+    };
+    let count = numbers.len();
+    let sum = match sum {
+        Number::Float(x) => return Ok(Some(Number::Float(x / count as f64))),
+        Number::Integer(n) => Decimal::from(n),
+        Number::Decimal(decimal) => decimal,
+    };
+    let scale = sum.scale.max(INEXACT_PLACES as u32);
+    let digits = math::round_div(sum.rescale(scale)?.digits, count as i128);
+    Ok(Some(Number::Decimal(Decimal { digits, scale })))
+}
+
+/// How spread out `numbers` are: their variance, taken over `sample`
+/// fewer than there are, or NULL if that's none. Exact, as a decimal to at
+/// least 16 places, unless they're floats.
+fn variance(numbers: &[Number], sample: usize) -> Result<Option<Number>, SqlError> {
+    let Some(count) = numbers.len().checked_sub(sample).filter(|&count| count > 0) else {
+        return Ok(None);
+    };
+    if numbers.iter().any(|n| matches!(n, Number::Float(_))) {
+        let values: Vec<f64> = numbers.iter().map(|n| n.to_f64()).collect();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let squares: f64 = values.iter().map(|x| (x - mean) * (x - mean)).sum();
+        return Ok(Some(Number::Float(squares / count as f64)));
+    }
+    let decimals: Vec<Decimal> = numbers
+        .iter()
+        .map(|n| n.to_decimal().ok_or_else(math::out_of_range))
+        .collect::<Result<_, _>>()?;
+    let scale = decimals.iter().map(|decimal| decimal.scale).max().unwrap();
+    // n * sum(x^2) - sum(x)^2, over n times the count, all in units of
+    // the square of the numbers' scale
+    let (mut sum, mut squares) = (0i128, 0i128);
+    for decimal in decimals {
+        let x = decimal.rescale(scale)?.digits;
+        sum = sum.checked_add(x).ok_or_else(math::out_of_range)?;
+        let square = x.checked_mul(x).ok_or_else(math::out_of_range)?;
+        squares = squares.checked_add(square).ok_or_else(math::out_of_range)?;
+    }
+    let n = numbers.len() as i128;
+    let numerator = n
+        .checked_mul(squares)
+        .zip(sum.checked_mul(sum))
+        .and_then(|(a, b)| a.checked_sub(b))
+        .ok_or_else(math::out_of_range)?;
+    let places = (2 * scale).max(INEXACT_PLACES as u32);
+    let numerator = Decimal {
+        digits: numerator,
+        scale: 2 * scale,
+    }
+    .rescale(places)?
+    .digits;
+    let digits = math::round_div(numerator, n * count as i128);
+    Ok(Some(Number::Decimal(Decimal {
+        digits,
+        scale: places,
+    })))
+}
+
+/// The standard deviation of the numbers of `rows`, the square root of
+/// their variance, which as it's seldom exact is a float.
+fn deviation(rows: &[&str], sample: usize) -> Result<Option<String>, SqlError> {
+    let variance = variance(&numbers(rows)?, sample)?;
+    Ok(variance.map(|variance| Number::Float(variance.to_f64().sqrt()).to_string()))
+}
+
+/// Whether `all` the rows' data, or any, is true, read as a boolean
+/// written `true` or `false`, `t` or `f`, `yes` or `no`, `on` or `off`, or
+/// `1` or `0`.
+fn booleans(rows: &[&str], all: bool) -> Result<Option<String>, SqlError> {
+    if rows.is_empty() {
+        return Ok(None);
+    }
+    let values = rows
+        .iter()
+        .map(|row| match row.trim().to_lowercase().as_str() {
+            "true" | "t" | "yes" | "on" | "1" => Ok(true),
+            "false" | "f" | "no" | "off" | "0" => Ok(false),
+            _ => {
+                let message = format!("invalid input syntax for type boolean: \"{}\"", row);
+                Err(SqlError::new("22P02", message))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let result = if all {
+        values.iter().all(|&value| value)
+    } else {
+        values.iter().any(|&value| value)
+    };
+    Ok(Some(result.to_string()))
+}
+
+/// The data as a Postgres array, `{a,b}`, quoting any that would be read
+/// otherwise.
+fn array(rows: &[&str]) -> String {
+    let elements: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            let plain = !row.is_empty()
+                && !row.eq_ignore_ascii_case("NULL")
+                && !row.contains(|c: char| "{},\"\\".contains(c) || c.is_whitespace());
+            if plain {
+                row.to_string()
+            } else {
+                format!("\"{}\"", row.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+        })
+        .collect();
+    format!("{{{}}}", elements.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::tests::temp_database;
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_aggregates() {
+        let (_dir, database) = temp_database();
+        let [numbers, words, flags, empty] = [(); 4].map(|_| database.create_table().unwrap());
+        let mut session = SqlSession::new(database.connect());
+        let mut run = |sql: &str| session.execute(sql).pop().unwrap();
+        for (table, values) in [
+            (numbers, "('4'), ('2'), ('10'), ('4')"),
+            (words, r#"('b'), ('a c'), ('"d"'), ('')"#),
+            (flags, "('true'), ('f'), ('yes')"),
+        ] {
+            run(&format!("INSERT INTO {table} VALUES {values}")).unwrap();
+        }
+        let mut fold = |call: &str, table| match run(&format!("SELECT {call} FROM {table}")) {
+            Ok(mut result) => Ok(result.rows.remove(0).remove(0)),
+            Err(e) => Err(e.code()),
+        };
+
+        assert_eq!(fold("count(data)", numbers).unwrap().as_deref(), Some("4"));
+        assert_eq!(fold("sum(data)", numbers).unwrap().as_deref(), Some("20"));
+        assert_eq!(
+            fold("avg(data)", numbers).unwrap().as_deref(),
+            Some("5.0000000000000000")
+        );
+        assert_eq!(fold("min(data)", numbers).unwrap().as_deref(), Some("2"));
+        assert_eq!(fold("max(data)", numbers).unwrap().as_deref(), Some("10"));
+        assert_eq!(
+            fold("var_samp(data)", numbers).unwrap().as_deref(),
+            Some("12.0000000000000000")
+        );
+        assert_eq!(
+            fold("variance(data)", numbers).unwrap().as_deref(),
+            Some("12.0000000000000000")
+        );
+        assert_eq!(
+            fold("var_pop(data)", numbers).unwrap().as_deref(),
+            Some("9.0000000000000000")
+        );
+        assert_eq!(
+            fold("stddev_pop(data)", numbers).unwrap().as_deref(),
+            Some("3")
+        );
+        assert_eq!(
+            fold("stddev(data)", numbers).unwrap().as_deref(),
+            Some("3.4641016151377544")
+        );
+        assert_eq!(fold("sum(data)", words), Err("22P02"));
+
+        // In the order asked for, or the table's
+        assert_eq!(
+            fold("string_agg(data, ', ')", numbers).unwrap().as_deref(),
+            Some("4, 2, 10, 4")
+        );
+        assert_eq!(
+            fold("string_agg(data, '-' ORDER BY data DESC)", numbers)
+                .unwrap()
+                .as_deref(),
+            Some("10-4-4-2")
+        );
+        assert_eq!(
+            fold("array_agg(data ORDER BY data)", numbers)
+                .unwrap()
+                .as_deref(),
+            Some("{2,4,4,10}")
+        );
+        assert_eq!(
+            fold("array_agg(data)", words).unwrap().as_deref(),
+            Some(r#"{b,"a c","\"d\"",""}"#)
+        );
+        assert_eq!(fold("string_agg(data)", words), Err("42883"));
+
+        assert_eq!(
+            fold("bool_and(data)", flags).unwrap().as_deref(),
+            Some("false")
+        );
+        assert_eq!(
+            fold("bool_or(data)", flags).unwrap().as_deref(),
+            Some("true")
+        );
+        assert_eq!(fold("bool_or(data)", words), Err("22P02"));
+
+        // No rows make NULL, but for COUNT
+        for call in [
+            "sum(data)",
+            "avg(data)",
+            "string_agg(data, ',')",
+            "bool_and(data)",
+        ] {
+            assert_eq!(fold(call, empty), Ok(None));
+        }
+        assert_eq!(fold("count(data)", empty).unwrap().as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn test_variance_is_exact() {
+        let (_dir, database) = temp_database();
+        let [integers, decimals, floats] = [(); 3].map(|_| database.create_table().unwrap());
+        let mut session = SqlSession::new(database.connect());
+        let mut run = |sql: &str| session.execute(sql).pop().unwrap();
+        for (table, values) in [
+            (integers, "('1'), ('2'), ('4')"),
+            (decimals, "('-0.5'), ('1.25')"),
+            (floats, "('1e0'), ('2'), ('4')"),
+        ] {
+            run(&format!("INSERT INTO {table} VALUES {values}")).unwrap();
+        }
+        let mut fold = |call: &str, table| {
+            let mut result = run(&format!("SELECT {call} FROM {table}")).unwrap();
+            result.rows.remove(0).remove(0)
+        };
+
+        // Rounded in the last place rather than carrying a float's error
+        assert_eq!(
+            fold("var_samp(data)", integers).as_deref(),
+            Some("2.3333333333333333")
+        );
+        assert_eq!(
+            fold("var_pop(data)", integers).as_deref(),
+            Some("1.5555555555555556")
+        );
+        assert_eq!(
+            fold("var_samp(data)", decimals).as_deref(),
+            Some("1.5312500000000000")
+        );
+        assert_eq!(
+            fold("var_samp(data)", floats).as_deref(),
+            Some("2.333333333333333")
+        );
+        assert_eq!(fold("stddev_pop(data)", decimals).as_deref(), Some("0.875"));
+    }
+}
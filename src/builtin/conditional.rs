@@ -0,0 +1,158 @@
+//! Functions choosing among their arguments, which unlike others take
+//! NULLs as they come. Arguments are compared as numbers if every one is
+//! a number, as times if every one is a time, and otherwise as text, so
+//! that `greatest(9, 10)` is 10 and `nullif(1, 1.0)` NULL; what's chosen
+//! is given as it was written.
+
+use super::datetime::{self, Time};
+use super::math::{self, Number};
+use super::{Body, Function};
+use std::cmp::Ordering;
+
+pub(super) const FUNCTIONS: &[Function] = &[
+    // The first argument that isn't NULL
+    Function {
+        name: "coalesce",
+        arity: (1, usize::MAX),
+        body: Body::Lax(|args| Ok(args.iter().copied().flatten().next().map(String::from))),
+    },
+    Function {
+        name: "greatest",
+        arity: (1, usize::MAX),
+        body: Body::Lax(|args| Ok(extreme(args, Ordering::Greater))),
+    },
+    Function {
+        name: "least",
+        arity: (1, usize::MAX),
+        body: Body::Lax(|args| Ok(extreme(args, Ordering::Less))),
+    },
+    // NULL if the arguments are equal, and otherwise the first
+    Function {
+        name: "nullif",
+        arity: (2, 2),
+        body: Body::Lax(|args| {
+            let equal = match args {
+                [Some(a), Some(b)] => {
+                    let keys = keys(&[a, b]);
+                    cmp(&keys[0], &keys[1]) == Ordering::Equal
+                }
+                _ => false,
+            };
+            Ok(args[0].filter(|_| !equal).map(String::from))
+        }),
+    },
+];
+
+/// A value as it's compared with the others it's given with.
+enum Key<'a> {
+    Number(Number),
+    Time(Time),
+    Text(&'a str),
+}
+
+/// `values` as the kind they all are.
+fn keys<'a>(values: &[&'a str]) -> Vec<Key<'a>> {
+    let numbers: Result<Vec<_>, _> = values.iter().map(|value| math::number(value)).collect();
+    if let Ok(numbers) = numbers {
+        return numbers.into_iter().map(Key::Number).collect();
+    }
+    let times: Result<Vec<_>, _> = values.iter().map(|value| datetime::time(value)).collect();
+    if let Ok(times) = times {
+        return times.into_iter().map(Key::Time).collect();
+    }
+    values.iter().map(|value| Key::Text(value)).collect()
+}
+
+/// How keys of one kind compare.
+fn cmp(a: &Key, b: &Key) -> Ordering {
+    match (a, b) {
+        (Key::Number(a), Key::Number(b)) => math::compare(*a, *b),
+        (Key::Time(a), Key::Time(b)) => a.cmp(b),
+        (Key::Text(a), Key::Text(b)) => a.cmp(b),
+        _ => unreachable!("keys are all of one kind"),
+    }
+}
+
+/// The first of the arguments that aren't NULL that no other is
+/// `ordering` than, or NULL if they all are.
+fn extreme(args: &[Option<&str>], ordering: Ordering) -> Option<String> {
+    let values: Vec<&str> = args.iter().copied().flatten().collect();
+    let keys = keys(&values);
+    let mut best = 0;
+    for at in 1..keys.len() {
+        if cmp(&keys[at], &keys[best]) == ordering {
+            best = at;
+        }
+    }
+    values.get(best).map(|value| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtin::tests::{row, select};
+    use crate::database::tests::temp_database;
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_conditional_functions() {
+        let (_dir, database) = temp_database();
+        let mut session = SqlSession::new(database.connect());
+
+        assert_eq!(
+            select(
+                &mut session,
+                "coalesce(NULL, NULL, 'a', 'b'), coalesce(NULL), coalesce(substr(NULL, 1), 2)"
+            )
+            .unwrap(),
+            row([Some("a"), None, Some("2")])
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "nullif('a', 'a'), nullif(1, 1.0), nullif('a', 'b'), \
+                 nullif(NULL, 'a'), nullif('a', NULL)"
+            )
+            .unwrap(),
+            row([None, None, Some("a"), None, Some("a")])
+        );
+        // An empty string is a value like any other
+        assert_eq!(
+            select(
+                &mut session,
+                "coalesce(NULL, ''), coalesce('', 'a'), nullif('', ''), nullif('', 'a')"
+            )
+            .unwrap(),
+            row([Some(""), Some(""), None, Some("")])
+        );
+
+        // Numbers by value, times by when they are, and anything else as
+        // text
+        assert_eq!(
+            select(
+                &mut session,
+                "greatest(9, 10, 9.5), least(9, 10, 9.5), greatest(1e2, 99), least(2.50, 3)"
+            )
+            .unwrap(),
+            row(["10", "9", "1e2", "2.50"].map(Some))
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "greatest('2024-03-01T01:00:00+02:00', '2024-02-29T23:30:00Z'), \
+                 greatest('10', 'abc', NULL, '9')"
+            )
+            .unwrap(),
+            row(["2024-02-29T23:30:00Z", "abc"].map(Some))
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "least(2, 2.0), greatest(NULL, NULL), least('', NULL)"
+            )
+            .unwrap(),
+            row([Some("2"), None, Some("")])
+        );
+        assert_eq!(select(&mut session, "coalesce()"), Err("42883"));
+        assert_eq!(select(&mut session, "nullif('a')"), Err("42883"));
+    }
+}
@@ -0,0 +1,750 @@
+//! Functions on times and intervals. A time is written as in RFC 3339,
+//! as `now()` gives it, and made in UTC to the microsecond; an interval
+//! as Postgres writes one, such as `1 year 2 mons 3 days 04:05:06`, or in
+//! words, such as `90 minutes` or `2 weeks ago`. As in Postgres, an
+//! interval keeps its months, days and time apart, neither a month nor a
+//! day always being as long: adding one to a time moves it by the months
+//! first, keeping its day unless the month is shorter, then by the days
+//! and then the time.
+
+use super::math::Decimal;
+use super::{Body, Function};
+use crate::logging::{civil, days_from_civil, days_in_month, Timestamp};
+use crate::sql::SqlError;
+use std::fmt::{self, Display};
+use std::time::UNIX_EPOCH;
+
+pub(super) const FUNCTIONS: &[Function] = &[
+    // The calendar time from the second time to the first
+    Function {
+        name: "age",
+        arity: (2, 2),
+        body: Body::Strict(|args| Ok(age(time(args[0])?, time(args[1])?).to_string())),
+    },
+    Function {
+        name: "date_add",
+        arity: (2, 2),
+        body: Body::Strict(|args| Ok(time(args[0])?.add(interval(args[1])?)?.to_string())),
+    },
+    Function {
+        name: "date_part",
+        arity: (2, 2),
+        body: Body::Strict(date_part),
+    },
+    Function {
+        name: "date_subtract",
+        arity: (2, 2),
+        body: Body::Strict(|args| {
+            let interval = interval(args[1])?.negate();
+            Ok(time(args[0])?.add(interval)?.to_string())
+        }),
+    },
+    Function {
+        name: "date_trunc",
+        arity: (2, 2),
+        body: Body::Strict(|args| Ok(truncate(&field(args[0]), time(args[1])?)?.to_string())),
+    },
+    // As `EXTRACT(<field> FROM <value>)` is written
+    Function {
+        name: "extract",
+        arity: (2, 2),
+        body: Body::Strict(date_part),
+    },
+    // As `INTERVAL '<text>'` is written, giving it as Postgres writes it
+    Function {
+        name: "interval",
+        arity: (1, 1),
+        body: Body::Strict(|args| Ok(interval(args[0])?.to_string())),
+    },
+    Function {
+        name: "strftime",
+        arity: (2, 2),
+        body: Body::Strict(|args| strftime(args[0], time(args[1])?)),
+    },
+    // As `TIMESTAMP '<text>'` is written, giving it in UTC
+    Function {
+        name: "timestamp",
+        arity: (1, 1),
+        body: Body::Strict(|args| Ok(time(args[0])?.to_string())),
+    },
+];
+
+const SECOND: i64 = 1_000_000;
+const MINUTE: i64 = 60 * SECOND;
+const HOUR: i64 = 60 * MINUTE;
+const DAY: i64 = 24 * HOUR;
+
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// A time, in microseconds since 1970 began in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) struct Time(i64);
+
+/// A time's date, and how far into the day it is in microseconds.
+struct Date {
+    year: i64,
+    month: i64,
+    day: i64,
+    micros: i64,
+}
+
+/// A span of time, as Postgres keeps one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Interval {
+    months: i64,
+    days: i64,
+    micros: i64,
+}
+
+/// What a unit an interval is written in is of: some microseconds, days
+/// or months.
+enum Unit {
+    Micros(i64),
+    Days(i64),
+    Months(i64),
+}
+
+const UNITS: &[(&[&str], Unit)] = &[
+    (
+        &["microsecond", "microseconds", "us", "usec", "usecs"],
+        Unit::Micros(1),
+    ),
+    (
+        &["millisecond", "milliseconds", "ms", "msec", "msecs"],
+        Unit::Micros(1000),
+    ),
+    (
+        &["second", "seconds", "sec", "secs", "s"],
+        Unit::Micros(SECOND),
+    ),
+    (
+        &["minute", "minutes", "min", "mins", "m"],
+        Unit::Micros(MINUTE),
+    ),
+    (&["hour", "hours", "hr", "hrs", "h"], Unit::Micros(HOUR)),
+    (&["day", "days", "d"], Unit::Days(1)),
+    (&["week", "weeks", "w"], Unit::Days(7)),
+    (&["month", "months", "mon", "mons"], Unit::Months(1)),
+    (&["year", "years", "yr", "yrs", "y"], Unit::Months(12)),
+    (&["decade", "decades"], Unit::Months(120)),
+    (&["century", "centuries"], Unit::Months(1200)),
+    (&["millennium", "millennia"], Unit::Months(12000)),
+];
+
+pub(super) fn time(arg: &str) -> Result<Time, SqlError> {
+    let time = Timestamp::parse(arg.trim()).ok_or_else(|| {
+        let message = format!("invalid input syntax for type timestamp: \"{}\"", arg);
+        SqlError::new("22007", message)
+    })?;
+    Ok(Time(match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_micros() as i64,
+        Err(before) => -(before.duration().as_micros() as i64),
+    }))
+}
+
+fn interval(arg: &str) -> Result<Interval, SqlError> {
+    Interval::parse(arg).ok_or_else(|| {
+        let message = format!("invalid input syntax for type interval: \"{}\"", arg);
+        SqlError::new("22007", message)
+    })
+}
+
+/// A field or unit named as an argument, which may be in any case.
+fn field(arg: &str) -> String {
+    arg.trim().to_lowercase()
+}
+
+fn out_of_range() -> SqlError {
+    SqlError::new("22008", "timestamp out of range")
+}
+
+fn unknown_unit(field: &str, of: &str) -> SqlError {
+    let message = format!("unit \"{}\" not recognized for type {}", field, of);
+    SqlError::new("22023", message)
+}
+
+impl Time {
+    fn from_date(year: i64, month: i64, day: i64) -> Self {
+        Self(days_from_civil(year, month, day) * DAY)
+    }
+
+    fn date(self) -> Date {
+        let (year, month, day) = civil(self.days());
+        Date {
+            year,
+            month,
+            day,
+            micros: self.0.rem_euclid(DAY),
+        }
+    }
+
+    /// The days since 1970 began.
+    fn days(self) -> i64 {
+        self.0.div_euclid(DAY)
+    }
+
+    /// The day of the week, from 1 for Monday to 7 for Sunday.
+    fn weekday(self) -> i64 {
+        (self.days() + 3).rem_euclid(7) + 1
+    }
+
+    /// The day of the year, from 1.
+    fn day_of_year(self) -> i64 {
+        self.days() - days_from_civil(self.date().year, 1, 1) + 1
+    }
+
+    /// The ISO 8601 year and week, the first week of a year being the
+    /// one with its first Thursday.
+    fn week(self) -> (i64, i64) {
+        let thursday = Time((self.days() - self.weekday() + 4) * DAY);
+        (thursday.date().year, (thursday.day_of_year() - 1) / 7 + 1)
+    }
+
+    fn add(self, interval: Interval) -> Result<Self, SqlError> {
+        let date = self.date();
+        let months = (date.year * 12 + date.month - 1)
+            .checked_add(interval.months)
+            .filter(|months| months.abs() < 12 * 300_000)
+            .ok_or_else(out_of_range)?;
+        let (year, month) = (months.div_euclid(12), months.rem_euclid(12) + 1);
+        let day = date.day.min(days_in_month(year, month));
+        let moved = Self::from_date(year, month, day).0 + date.micros;
+        let time = interval
+            .days
+            .checked_mul(DAY)
+            .and_then(|days| moved.checked_add(days))
+            .and_then(|moved| moved.checked_add(interval.micros))
+            .map(Self)
+            .ok_or_else(out_of_range)?;
+        // Only the years RFC 3339 can write, so the time can be read back
+        match time.date().year {
+            1..=9999 => Ok(time),
+            _ => Err(out_of_range()),
+        }
+    }
+}
+
+impl Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let date = self.date();
+        let seconds = date.micros / SECOND;
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            date.year,
+            date.month,
+            date.day,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )?;
+        // To the millisecond as `now()` is, unless it's finer
+        match date.micros % SECOND {
+            micros if micros % 1000 == 0 => write!(f, ".{:03}Z", micros / 1000),
+            micros => write!(f, ".{:06}Z", micros),
+        }
+    }
+}
+
+impl Interval {
+    /// `text` as Postgres writes an interval, or as `<number> <unit>`
+    /// and `[-]<hours>:<minutes>[:<seconds>]`, in any order and as many
+    /// times as it likes, then optionally `ago`.
+    fn parse(text: &str) -> Option<Self> {
+        let text = text.to_lowercase();
+        let mut words = text.split_whitespace().peekable();
+        let mut interval = Self::default();
+        let mut empty = true;
+        while let Some(word) = words.next() {
+            if word == "ago" && words.peek().is_none() && !empty {
+                return Some(interval.negate());
+            }
+            if word.contains(':') {
+                interval.micros = interval.micros.checked_add(clock(word)?)?;
+            } else {
+                let unit = words.next()?;
+                let (_, unit) = UNITS.iter().find(|(names, _)| names.contains(&unit))?;
+                interval.add(word, unit)?;
+            }
+            empty = false;
+        }
+        (!empty).then_some(interval)
+    }
+
+    /// Add `amount` of `unit`, a fraction of a month being 30 days' and
+    /// of a day 24 hours'.
+    fn add(&mut self, amount: &str, unit: &Unit) -> Option<()> {
+        if let Ok(n) = amount.parse::<i64>() {
+            let (field, factor) = match unit {
+                Unit::Micros(factor) => (&mut self.micros, factor),
+                Unit::Days(factor) => (&mut self.days, factor),
+                Unit::Months(factor) => (&mut self.months, factor),
+            };
+            *field = field.checked_add(n.checked_mul(*factor)?)?;
+            return Some(());
+        }
+        let x: f64 = amount.parse().ok().filter(|x: &f64| x.is_finite())?;
+        let (months, days) = match unit {
+            Unit::Micros(factor) => (0.0, x * *factor as f64 / DAY as f64),
+            Unit::Days(factor) => (0.0, x * *factor as f64),
+            Unit::Months(factor) => {
+                let months = x * *factor as f64;
+                (months.trunc(), months.fract() * 30.0)
+            }
+        };
+        let whole = |x: f64| (x.abs() < 1e17).then_some(x as i64);
+        self.months = self.months.checked_add(whole(months)?)?;
+        self.days = self.days.checked_add(whole(days.trunc())?)?;
+        let micros = (days.fract() * DAY as f64).round();
+        self.micros = self.micros.checked_add(whole(micros)?)?;
+        Some(())
+    }
+
+    fn negate(self) -> Self {
+        Self {
+            months: self.months.saturating_neg(),
+            days: self.days.saturating_neg(),
+            micros: self.micros.saturating_neg(),
+        }
+    }
+}
+
+/// The microseconds of `[-]<hours>:<minutes>[:<seconds>[.<fraction>]]`.
+fn clock(word: &str) -> Option<i64> {
+    let (sign, word) = match word.strip_prefix('-') {
+        Some(word) => (-1, word),
+        None => (1, word.strip_prefix('+').unwrap_or(word)),
+    };
+    let number = |text: &str| -> Option<i64> {
+        let digits = !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit());
+        digits.then(|| text.parse().ok()).flatten()
+    };
+    let mut fields = word.split(':');
+    let hours = number(fields.next()?)?;
+    let minutes = number(fields.next()?)?;
+    let seconds = fields.next().unwrap_or("0");
+    let (seconds, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+    let seconds = number(seconds)?;
+    if fields.next().is_some() || minutes > 59 || seconds > 59 || fraction.len() > 6 {
+        return None;
+    }
+    let fraction = match fraction {
+        "" => 0,
+        fraction => number(&format!("{:0<6}", fraction))?,
+    };
+    let micros = hours
+        .checked_mul(HOUR)?
+        .checked_add(minutes * MINUTE + seconds * SECOND + fraction)?;
+    Some(sign * micros)
+}
+
+impl Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        let mut count = |n: i64, one: &str, many: &str| {
+            if n != 0 {
+                parts.push(format!("{} {}", n, if n.abs() == 1 { one } else { many }));
+            }
+        };
+        count(self.months / 12, "year", "years");
+        count(self.months % 12, "mon", "mons");
+        count(self.days, "day", "days");
+        if self.micros != 0 || parts.is_empty() {
+            let micros = self.micros.unsigned_abs();
+            let seconds = micros / SECOND as u64;
+            let mut time = format!(
+                "{}{:02}:{:02}:{:02}",
+                if self.micros < 0 { "-" } else { "" },
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            );
+            let fraction = micros % SECOND as u64;
+            if fraction != 0 {
+                let fraction = format!("{:06}", fraction);
+                time = format!("{}.{}", time, fraction.trim_end_matches('0'));
+            }
+            parts.push(time);
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// The years, months, days and time from `b` to `a`, as Postgres counts
+/// them: a month short of a day of the month makes that many days more
+/// than are left in `b`'s month.
+fn age(a: Time, b: Time) -> Interval {
+    if a < b {
+        return age(b, a).negate();
+    }
+    let (a, b) = (a.date(), b.date());
+    let mut micros = a.micros - b.micros;
+    let mut days = a.day - b.day;
+    let mut months = (a.year * 12 + a.month) - (b.year * 12 + b.month);
+    if micros < 0 {
+        micros += DAY;
+        days -= 1;
+    }
+    if days < 0 {
+        days += days_in_month(b.year, b.month);
+        months -= 1;
+    }
+    Interval {
+        months,
+        days,
+        micros,
+    }
+}
+
+/// `time` at the start of the `field` it's in, weeks starting on Mondays
+/// and centuries and millennia with their first year, as 2001.
+fn truncate(field: &str, time: Time) -> Result<Time, SqlError> {
+    let floor = |unit: i64| Time(time.0 - time.0.rem_euclid(unit));
+    let year = time.date().year;
+    Ok(match field {
+        "microsecond" | "microseconds" => time,
+        "millisecond" | "milliseconds" => floor(1000),
+        "second" | "seconds" => floor(SECOND),
+        "minute" | "minutes" => floor(MINUTE),
+        "hour" | "hours" => floor(HOUR),
+        "day" | "days" => floor(DAY),
+        "week" | "weeks" => Time((time.days() - time.weekday() + 1) * DAY),
+        "month" | "months" => Time::from_date(year, time.date().month, 1),
+        "quarter" => Time::from_date(year, (time.date().month - 1) / 3 * 3 + 1, 1),
+        "year" | "years" => Time::from_date(year, 1, 1),
+        "decade" | "decades" => Time::from_date(year - year.rem_euclid(10), 1, 1),
+        "century" | "centuries" => Time::from_date((year - 1).div_euclid(100) * 100 + 1, 1, 1),
+        "millennium" | "millennia" => Time::from_date((year - 1).div_euclid(1000) * 1000 + 1, 1, 1),
+        _ => return Err(unknown_unit(field, "timestamp")),
+    })
+}
+
+/// The field `args[0]` of the time or interval `args[1]`. Seconds come
+/// with their fraction, and days of the week count from 0 for Sunday, or
+/// from 1 for Monday as `isodow`.
+fn date_part(args: &[&str]) -> Result<String, SqlError> {
+    let field = field(args[0]);
+    match (time(args[1]), Interval::parse(args[1])) {
+        (Ok(time), _) => time_part(&field, time),
+        (Err(_), Some(interval)) => interval_part(&field, interval),
+        (Err(e), None) => Err(e),
+    }
+}
+
+/// `micros` as a decimal of `scale` places.
+fn exact(micros: i128, scale: u32) -> String {
+    Decimal {
+        digits: micros,
+        scale,
+    }
+    .to_string()
+}
+
+fn time_part(field: &str, time: Time) -> Result<String, SqlError> {
+    let date = time.date();
+    let seconds = i128::from(date.micros % MINUTE);
+    let year = date.year;
+    Ok(match field {
+        "microsecond" | "microseconds" => seconds.to_string(),
+        "millisecond" | "milliseconds" => exact(seconds, 3),
+        "second" | "seconds" => exact(seconds, 6),
+        "minute" | "minutes" => (date.micros / MINUTE % 60).to_string(),
+        "hour" | "hours" => (date.micros / HOUR).to_string(),
+        "day" | "days" => date.day.to_string(),
+        "dow" => (time.weekday() % 7).to_string(),
+        "isodow" => time.weekday().to_string(),
+        "doy" => time.day_of_year().to_string(),
+        "week" => time.week().1.to_string(),
+        "isoyear" => time.week().0.to_string(),
+        "month" | "months" => date.month.to_string(),
+        "quarter" => ((date.month - 1) / 3 + 1).to_string(),
+        "year" | "years" => year.to_string(),
+        "decade" | "decades" => year.div_euclid(10).to_string(),
+        "century" | "centuries" if year > 0 => ((year + 99) / 100).to_string(),
+        "century" | "centuries" => ((year - 100) / 100).to_string(),
+        "millennium" | "millennia" if year > 0 => ((year + 999) / 1000).to_string(),
+        "millennium" | "millennia" => ((year - 1000) / 1000).to_string(),
+        "epoch" => exact(time.0.into(), 6),
+        _ => return Err(unknown_unit(field, "timestamp")),
+    })
+}
+
+/// A field of an interval, whose epoch takes a year as 365.25 days and a
+/// month as 30.
+fn interval_part(field: &str, interval: Interval) -> Result<String, SqlError> {
+    let Interval {
+        months,
+        days,
+        micros,
+    } = interval;
+    let seconds = i128::from(micros % MINUTE);
+    Ok(match field {
+        "microsecond" | "microseconds" => seconds.to_string(),
+        "millisecond" | "milliseconds" => exact(seconds, 3),
+        "second" | "seconds" => exact(seconds, 6),
+        "minute" | "minutes" => (micros / MINUTE % 60).to_string(),
+        "hour" | "hours" => (micros / HOUR).to_string(),
+        "day" | "days" => days.to_string(),
+        "month" | "months" => (months % 12).to_string(),
+        "quarter" => ((months % 12) / 3 + 1).to_string(),
+        "year" | "years" => (months / 12).to_string(),
+        "decade" | "decades" => (months / 120).to_string(),
+        "century" | "centuries" => (months / 1200).to_string(),
+        "millennium" | "millennia" => (months / 12000).to_string(),
+        "epoch" => {
+            let year = 36525 * i128::from(DAY) / 100;
+            let epoch = i128::from(months / 12) * year
+                + i128::from(months % 12 * 30 + days) * i128::from(DAY)
+                + i128::from(micros);
+            exact(epoch, 6)
+        }
+        _ => return Err(unknown_unit(field, "interval")),
+    })
+}
+
+/// `time` written as `format` says, as C's `strftime` would in UTC: `%Y`
+/// for the year, `%m` the month, `%d` the day, `%H`, `%M` and `%S` the
+/// hour, minute and second, `%f` the microsecond, and so on.
+fn strftime(format: &str, time: Time) -> Result<String, SqlError> {
+    let date = time.date();
+    let seconds = date.micros / SECOND;
+    let hour = seconds / 3600;
+    let weekday = time.weekday();
+    let mut text = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+        let spec = chars.next();
+        text.push_str(&match spec {
+            Some('a') => WEEKDAYS[weekday as usize - 1][..3].to_string(),
+            Some('A') => WEEKDAYS[weekday as usize - 1].to_string(),
+            Some('b') => MONTHS[date.month as usize - 1][..3].to_string(),
+            Some('B') => MONTHS[date.month as usize - 1].to_string(),
+            Some('d') => format!("{:02}", date.day),
+            Some('e') => format!("{:2}", date.day),
+            Some('f') => format!("{:06}", date.micros % SECOND),
+            Some('F') => format!("{:04}-{:02}-{:02}", date.year, date.month, date.day),
+            Some('G') => time.week().0.to_string(),
+            Some('H') => format!("{:02}", hour),
+            Some('I') => format!("{:02}", (hour + 11) % 12 + 1),
+            Some('j') => format!("{:03}", time.day_of_year()),
+            Some('m') => format!("{:02}", date.month),
+            Some('M') => format!("{:02}", seconds / 60 % 60),
+            Some('p') => (if hour < 12 { "AM" } else { "PM" }).to_string(),
+            Some('s') => time.0.div_euclid(SECOND).to_string(),
+            Some('S') => format!("{:02}", seconds % 60),
+            Some('T') => format!("{:02}:{:02}:{:02}", hour, seconds / 60 % 60, seconds % 60),
+            Some('u') => weekday.to_string(),
+            Some('V') => format!("{:02}", time.week().1),
+            Some('w') => (weekday % 7).to_string(),
+            Some('y') => format!("{:02}", date.year.rem_euclid(100)),
+            Some('Y') => format!("{:04}", date.year),
+            Some('z') => "+0000".to_string(),
+            Some('Z') => "UTC".to_string(),
+            Some('%') => "%".to_string(),
+            _ => {
+                let spec = spec.map(String::from).unwrap_or_default();
+                let message = format!("invalid format specification \"%{}\"", spec);
+                return Err(SqlError::new("22007", message));
+            }
+        });
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtin::tests::{row, select};
+    use crate::database::tests::temp_database;
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_datetime_functions() {
+        let (_dir, database) = temp_database();
+        let mut session = SqlSession::new(database.connect());
+        let at = "'2024-03-15T10:30:45.5Z'";
+
+        assert_eq!(
+            select(
+                &mut session,
+                &format!(
+                    "date_trunc('hour', {at}), date_trunc('WEEK', {at}), \
+                 date_trunc('quarter', {at}), date_trunc('century', '2000-06-01')"
+                )
+            )
+            .unwrap(),
+            row([
+                "2024-03-15T10:00:00.000Z",
+                "2024-03-11T00:00:00.000Z",
+                "2024-01-01T00:00:00.000Z",
+                "1901-01-01T00:00:00.000Z"
+            ]
+            .map(Some))
+        );
+        assert_eq!(
+            select(&mut session, &format!("date_trunc('fortnight', {at})")),
+            Err("22023")
+        );
+        assert_eq!(
+            select(&mut session, "date_trunc('day', 'soon')"),
+            Err("22007")
+        );
+        let today = select(&mut session, "date_trunc('day', now())").unwrap();
+        assert!(today[0].as_deref().unwrap().ends_with("T00:00:00.000Z"));
+
+        assert_eq!(
+            select(
+                &mut session,
+                &format!(
+                    "EXTRACT(year FROM {at}), date_part('second', {at}), extract(DOW FROM {at}), \
+                 extract(doy FROM {at}), extract(quarter FROM {at})"
+                )
+            )
+            .unwrap(),
+            row(["2024", "45.500000", "5", "75", "1"].map(Some))
+        );
+        // 2021 began in the last ISO week of 2020
+        assert_eq!(
+            select(
+                &mut session,
+                "extract(week FROM '2021-01-01'), extract(isoyear FROM '2021-01-01')"
+            )
+            .unwrap(),
+            row(["53", "2020"].map(Some))
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "extract(epoch FROM '1969-12-31T00:00:00Z'), \
+                 extract(hour FROM INTERVAL '1 day 02:30:00'), \
+                 extract(epoch FROM INTERVAL '1 year 1 day')"
+            )
+            .unwrap(),
+            row(["-86400.000000", "2", "31644000.000000"].map(Some))
+        );
+
+        assert_eq!(
+            select(
+                &mut session,
+                "INTERVAL '90 minutes', INTERVAL '1.5 days', interval('2 weeks ago'), \
+                 INTERVAL '1 year 14 mons 3 days 04:05:06.5', INTERVAL '0 s'"
+            )
+            .unwrap(),
+            row([
+                "01:30:00",
+                "1 day 12:00:00",
+                "-14 days",
+                "2 years 2 mons 3 days 04:05:06.5",
+                "00:00:00"
+            ]
+            .map(Some))
+        );
+        assert_eq!(select(&mut session, "INTERVAL 'soon'"), Err("22007"));
+        assert_eq!(select(&mut session, "INTERVAL '1 fortnight'"), Err("22007"));
+
+        // Months first, keeping the day unless the month is shorter
+        assert_eq!(
+            select(
+                &mut session,
+                "date_add('2024-01-31T12:00:00Z', '1 month'), \
+                 date_subtract('2024-03-01', INTERVAL '1 day 1 hour'), \
+                 date_add('2024-02-29', INTERVAL '1 year -1 day')"
+            )
+            .unwrap(),
+            row([
+                "2024-02-29T12:00:00.000Z",
+                "2024-02-28T23:00:00.000Z",
+                "2025-02-27T00:00:00.000Z"
+            ]
+            .map(Some))
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "age('2001-04-10', '1957-06-13'), age('2024-01-01', '2024-01-01T12:00:00Z')"
+            )
+            .unwrap(),
+            row(["43 years 9 mons 27 days", "-12:00:00"].map(Some))
+        );
+
+        assert_eq!(
+            select(
+                &mut session,
+                &format!(
+                    "strftime('%Y/%m/%d %H:%M:%S.%f %a %B %j %I%p %%', {at}), \
+                 TIMESTAMP '2024-03-15 10:30:00+02:00'"
+                )
+            )
+            .unwrap(),
+            row([
+                "2024/03/15 10:30:45.500000 Fri March 075 10AM %",
+                "2024-03-15T08:30:00.000Z"
+            ]
+            .map(Some))
+        );
+        assert_eq!(
+            select(&mut session, &format!("strftime('%Q', {at})")),
+            Err("22007")
+        );
+        assert_eq!(
+            select(&mut session, "strftime('%', '2024-03-15')"),
+            Err("22007")
+        );
+        assert_eq!(
+            select(&mut session, "date_add(NULL, '1 day')").unwrap(),
+            row([None])
+        );
+    }
+
+    #[test]
+    fn test_date_arithmetic_stays_in_range() {
+        let (_dir, database) = temp_database();
+        let mut session = SqlSession::new(database.connect());
+
+        assert_eq!(
+            select(
+                &mut session,
+                "date_add('9999-12-31T23:59:59Z', '1 second'), date_add('9999-06-01', '1 year')"
+            ),
+            Err("22008")
+        );
+        assert_eq!(
+            select(&mut session, "date_subtract('0001-01-01', '1 day')"),
+            Err("22008")
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "date_add('2024-01-01', '-2023 years'), date_add('9998-12-31', '1 year')"
+            )
+            .unwrap(),
+            row(["0001-01-01T00:00:00.000Z", "9999-12-31T00:00:00.000Z"].map(Some))
+        );
+    }
+}
@@ -0,0 +1,541 @@
+//! Functions on numbers, which are read from their text as one of three
+//! kinds: an integer, whole and in 64 bits as `INT` and `BIGINT` are; a
+//! decimal, written with a point and exact to 38 digits as `NUMERIC` and
+//! `DECIMAL` are; or a float, written with an exponent or as `Infinity`
+//! or `NaN`, as `DOUBLE PRECISION` is. As in Postgres, an integer with a
+//! decimal makes a decimal and either with a float a float, and what has
+//! no exact answer, such as `sqrt`, is worked out as a float: a float or
+//! integer's as one, and a decimal's as a decimal to 16 places.
+
+use super::{integer, Body, Function};
+use crate::sql::SqlError;
+use std::cmp::Ordering;
+use std::fmt::{self, Display};
+
+pub(super) const FUNCTIONS: &[Function] = &[
+    Function {
+        name: "ceil",
+        arity: (1, 1),
+        body: Body::Strict(|args| Ok(ceil(number(args[0])?).to_string())),
+    },
+    Function {
+        name: "ceiling",
+        arity: (1, 1),
+        body: Body::Strict(|args| Ok(ceil(number(args[0])?).to_string())),
+    },
+    Function {
+        name: "exp",
+        arity: (1, 1),
+        body: Body::Strict(|args| inexact(number(args[0])?, f64::exp)),
+    },
+    Function {
+        name: "floor",
+        arity: (1, 1),
+        body: Body::Strict(|args| Ok(floor(number(args[0])?).to_string())),
+    },
+    Function {
+        name: "ln",
+        arity: (1, 1),
+        body: Body::Strict(|args| log(None, number(args[0])?)),
+    },
+    // The logarithm to base 10, or to the base given first
+    Function {
+        name: "log",
+        arity: (1, 2),
+        body: Body::Strict(|args| match args {
+            [x] => log(Some(Number::Integer(10)), number(x)?),
+            [base, x] => log(Some(number(base)?), number(x)?),
+            _ => unreachable!("log takes one or two arguments"),
+        }),
+    },
+    Function {
+        name: "mod",
+        arity: (2, 2),
+        body: Body::Strict(|args| modulo(number(args[0])?, number(args[1])?)),
+    },
+    Function {
+        name: "pow",
+        arity: (2, 2),
+        body: Body::Strict(|args| power(number(args[0])?, number(args[1])?)),
+    },
+    Function {
+        name: "power",
+        arity: (2, 2),
+        body: Body::Strict(|args| power(number(args[0])?, number(args[1])?)),
+    },
+    // From 0 up to but not including 1
+    Function {
+        name: "random",
+        arity: (0, 0),
+        body: Body::Strict(|_| {
+            let mut bytes = [0; 8];
+            getrandom::getrandom(&mut bytes).expect("no random source");
+            let bits = u64::from_le_bytes(bytes) >> 11;
+            Ok(Number::Float(bits as f64 / (1u64 << 53) as f64).to_string())
+        }),
+    },
+    Function {
+        name: "round",
+        arity: (1, 2),
+        body: Body::Strict(|args| {
+            let x = number(args[0])?;
+            match args.get(1) {
+                Some(places) => Ok(round_to(x, integer(places)?)?.to_string()),
+                None => Ok(round(x).to_string()),
+            }
+        }),
+    },
+    Function {
+        name: "sqrt",
+        arity: (1, 1),
+        body: Body::Strict(|args| match number(args[0])? {
+            x if x.to_f64() < 0.0 => Err(SqlError::new(
+                "2201F",
+                "cannot take square root of a negative number",
+            )),
+            x => inexact(x, f64::sqrt),
+        }),
+    },
+];
+
+/// The most digits a decimal has.
+const MAX_DIGITS: u32 = 38;
+
+/// The places a decimal worked out as a float is given to.
+pub(super) const INEXACT_PLACES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Number {
+    Integer(i64),
+    Decimal(Decimal),
+    Float(f64),
+}
+
+/// `digits` over 10 to the `scale`, so 1.50 is 150 to a scale of 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Decimal {
+    pub(super) digits: i128,
+    pub(super) scale: u32,
+}
+
+/// How `a` and `b` compare, made the same kind as they would be to add
+/// them.
+pub(super) fn compare(a: Number, b: Number) -> Ordering {
+    match (a, b) {
+        (Number::Integer(a), Number::Integer(b)) => a.cmp(&b),
+        (Number::Float(_), _) | (_, Number::Float(_)) => a.to_f64().total_cmp(&b.to_f64()),
+        _ => {
+            let (x, y) = (a.to_decimal().unwrap(), b.to_decimal().unwrap());
+            let scale = x.scale.max(y.scale);
+            match (x.rescale(scale), y.rescale(scale)) {
+                (Ok(x), Ok(y)) => x.digits.cmp(&y.digits),
+                _ => a.to_f64().total_cmp(&b.to_f64()),
+            }
+        }
+    }
+}
+
+/// `arg` as a number of the kind it's written as.
+pub(super) fn number(arg: &str) -> Result<Number, SqlError> {
+    let text = arg.trim();
+    if let Ok(n) = text.parse() {
+        return Ok(Number::Integer(n));
+    }
+    let float = text.contains(['e', 'E'])
+        || ["infinity", "+infinity", "-infinity", "nan"].contains(&&*text.to_lowercase());
+    if float {
+        if let Ok(x) = text.parse() {
+            return Ok(Number::Float(x));
+        }
+    } else if let Some(decimal) = Decimal::parse(text) {
+        return Ok(Number::Decimal(decimal));
+    }
+    let message = format!("invalid input syntax for type numeric: \"{}\"", arg);
+    Err(SqlError::new("22P02", message))
+}
+
+pub(super) fn out_of_range() -> SqlError {
+    SqlError::new("22003", "value out of range: overflow")
+}
+
+fn division_by_zero() -> SqlError {
+    SqlError::new("22012", "division by zero")
+}
+
+impl Number {
+    pub(super) fn to_f64(self) -> f64 {
+        match self {
+            Self::Integer(n) => n as f64,
+            Self::Decimal(decimal) => decimal.to_f64(),
+            Self::Float(x) => x,
+        }
+    }
+
+    pub(super) fn to_decimal(self) -> Option<Decimal> {
+        match self {
+            Self::Integer(n) => Some(Decimal::from(n)),
+            Self::Decimal(decimal) => Some(decimal),
+            Self::Float(x) => Decimal::parse(&x.to_string()),
+        }
+    }
+}
+
+impl Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(n) => write!(f, "{}", n),
+            Self::Decimal(decimal) => write!(f, "{}", decimal),
+            Self::Float(x) if x.is_nan() => write!(f, "NaN"),
+            Self::Float(x) if x.is_infinite() => {
+                write!(f, "{}Infinity", if *x < 0.0 { "-" } else { "" })
+            }
+            Self::Float(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl Decimal {
+    /// A number written with digits and at most one point, and a sign.
+    fn parse(text: &str) -> Option<Self> {
+        let unsigned = text.strip_prefix(['-', '+']).unwrap_or(text);
+        let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if whole.len() + fraction.len() == 0 || !all_digits(whole) || !all_digits(fraction) {
+            return None;
+        }
+        let digits: i128 = format!("{}{}", whole, fraction).parse().ok()?;
+        let decimal = Self {
+            digits: if text.starts_with('-') {
+                -digits
+            } else {
+                digits
+            },
+            scale: fraction.len() as u32,
+        };
+        (decimal.scale <= MAX_DIGITS).then_some(decimal)
+    }
+
+    pub(super) fn to_f64(self) -> f64 {
+        self.digits as f64 / 10f64.powi(self.scale as i32)
+    }
+
+    /// `x` to 16 places, or as many as 38 digits leave.
+    pub(super) fn approximate(x: f64) -> Result<Self, SqlError> {
+        if !x.is_finite() || x.abs() >= 1e37 {
+            return Err(out_of_range());
+        }
+        let whole = x.abs().log10().floor().max(0.0) as usize + 1;
+        let places = INEXACT_PLACES.min(MAX_DIGITS as usize - whole);
+        Self::parse(&format!("{:.*}", places, x)).ok_or_else(out_of_range)
+    }
+
+    /// The decimal to `scale` places, rounded half away from zero.
+    pub(super) fn rescale(self, scale: u32) -> Result<Self, SqlError> {
+        let digits = if scale >= self.scale {
+            let factor = 10i128.checked_pow(scale - self.scale);
+            factor.and_then(|factor| self.digits.checked_mul(factor))
+        } else {
+            Some(match 10i128.checked_pow(self.scale - scale) {
+                Some(factor) => round_div(self.digits, factor),
+                None => 0,
+            })
+        };
+        match digits {
+            Some(digits) if scale <= MAX_DIGITS => Ok(Self { digits, scale }),
+            _ => Err(out_of_range()),
+        }
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(n: i64) -> Self {
+        Self {
+            digits: n.into(),
+            scale: 0,
+        }
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.digits < 0 { "-" } else { "" };
+        let digits = self.digits.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        if scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - scale);
+        write!(f, "{}{}.{}", sign, whole, fraction)
+    }
+}
+
+/// `n` over `d`, rounded half away from zero.
+pub(super) fn round_div(n: i128, d: i128) -> i128 {
+    let (quotient, remainder) = (n / d, n % d);
+    if remainder.unsigned_abs() * 2 >= d.unsigned_abs() {
+        quotient + n.signum()
+    } else {
+        quotient
+    }
+}
+
+/// A float worked out from `x`, of the kind it makes, a decimal as one.
+fn inexact(x: Number, f: impl Fn(f64) -> f64) -> Result<String, SqlError> {
+    let result = f(x.to_f64());
+    if result.is_infinite() && x.to_f64().is_finite() {
+        return Err(out_of_range());
+    }
+    Ok(match x {
+        Number::Decimal(_) => Decimal::approximate(result)?.to_string(),
+        _ => Number::Float(result).to_string(),
+    })
+}
+
+/// The logarithm of `x` to `base`, or the natural one for `None`.
+fn log(base: Option<Number>, x: Number) -> Result<String, SqlError> {
+    for n in base.iter().chain([&x]) {
+        let message = match n.to_f64() {
+            0.0 => "cannot take logarithm of zero",
+            n if n < 0.0 => "cannot take logarithm of a negative number",
+            _ => continue,
+        };
+        return Err(SqlError::new("2201E", message));
+    }
+    let Some(base) = base else {
+        return inexact(x, f64::ln);
+    };
+    if base.to_f64() == 1.0 {
+        return Err(division_by_zero());
+    }
+    let result = x.to_f64().log10() / base.to_f64().log10();
+    match (base, x) {
+        (Number::Float(_), _) | (_, Number::Float(_)) => Ok(Number::Float(result).to_string()),
+        (Number::Decimal(_), _) | (_, Number::Decimal(_)) => {
+            Ok(Decimal::approximate(result)?.to_string())
+        }
+        _ => Ok(Number::Float(result).to_string()),
+    }
+}
+
+fn floor(x: Number) -> Number {
+    match x {
+        Number::Integer(_) => x,
+        Number::Decimal(decimal) => Number::Decimal(Decimal {
+            digits: decimal.digits.div_euclid(10i128.pow(decimal.scale)),
+            scale: 0,
+        }),
+        Number::Float(x) => Number::Float(x.floor()),
+    }
+}
+
+fn ceil(x: Number) -> Number {
+    match x {
+        Number::Integer(_) => x,
+        Number::Decimal(decimal) => Number::Decimal(Decimal {
+            digits: -(-decimal.digits).div_euclid(10i128.pow(decimal.scale)),
+            scale: 0,
+        }),
+        Number::Float(x) => Number::Float(x.ceil()),
+    }
+}
+
+/// `x` rounded to a whole number: a decimal's half away from zero, and a
+/// float's half to even, as in Postgres.
+fn round(x: Number) -> Number {
+    match x {
+        Number::Integer(_) => x,
+        Number::Decimal(decimal) => Number::Decimal(Decimal {
+            digits: round_div(decimal.digits, 10i128.pow(decimal.scale)),
+            scale: 0,
+        }),
+        Number::Float(x) => Number::Float(x.round_ties_even()),
+    }
+}
+
+/// `x` as a decimal rounded half away from zero to `places`, or to tens,
+/// hundreds and so on for fewer than none.
+fn round_to(x: Number, places: i64) -> Result<Number, SqlError> {
+    let decimal = x.to_decimal().ok_or_else(out_of_range)?;
+    let Ok(places) = u32::try_from(places) else {
+        let tens = u32::try_from(places.unsigned_abs()).unwrap_or(u32::MAX);
+        let Some(factor) = 10i128.checked_pow(tens) else {
+            return Ok(Number::Decimal(Decimal::from(0)));
+        };
+        let whole = decimal.rescale(0)?.digits;
+        let digits = round_div(whole, factor)
+            .checked_mul(factor)
+            .ok_or_else(out_of_range)?;
+        return Ok(Number::Decimal(Decimal { digits, scale: 0 }));
+    };
+    Ok(Number::Decimal(decimal.rescale(places)?))
+}
+
+/// The remainder of `x` over `y`, with the sign of `x`.
+fn modulo(x: Number, y: Number) -> Result<String, SqlError> {
+    let result = match (x, y) {
+        (Number::Integer(x), Number::Integer(y)) => {
+            if y == 0 {
+                return Err(division_by_zero());
+            }
+            Number::Integer(x.checked_rem(y).unwrap_or(0))
+        }
+        (Number::Float(_), _) | (_, Number::Float(_)) => {
+            if y.to_f64() == 0.0 {
+                return Err(division_by_zero());
+            }
+            Number::Float(x.to_f64() % y.to_f64())
+        }
+        _ => {
+            let (x, y) = (x.to_decimal().unwrap(), y.to_decimal().unwrap());
+            let scale = x.scale.max(y.scale);
+            let (x, y) = (x.rescale(scale)?, y.rescale(scale)?);
+            if y.digits == 0 {
+                return Err(division_by_zero());
+            }
+            Number::Decimal(Decimal {
+                digits: x.digits % y.digits,
+                scale,
+            })
+        }
+    };
+    Ok(result.to_string())
+}
+
+/// `x` to the power `y`, exactly for a decimal to a whole power that
+/// fits.
+fn power(x: Number, y: Number) -> Result<String, SqlError> {
+    let (base, exponent) = (x.to_f64(), y.to_f64());
+    if base == 0.0 && exponent < 0.0 {
+        let message = "zero raised to a negative power is undefined";
+        return Err(SqlError::new("2201F", message));
+    }
+    if base < 0.0 && exponent.fract() != 0.0 {
+        let message = "a negative number raised to a non-integer power yields a complex result";
+        return Err(SqlError::new("2201F", message));
+    }
+    let decimal = match (x, y) {
+        (Number::Float(_), _) | (_, Number::Float(_)) => None,
+        (Number::Decimal(decimal), _) | (_, Number::Decimal(decimal)) => Some(decimal),
+        _ => None,
+    };
+    let Some(decimal) = decimal else {
+        return inexact(Number::Float(base), |base| base.powf(exponent));
+    };
+    let exact = match y {
+        Number::Integer(n) => u32::try_from(n).ok().and_then(|n| exact_power(x, n)),
+        _ => None,
+    };
+    match exact {
+        Some(result) => Ok(result.to_string()),
+        None => inexact(Number::Decimal(decimal), |_| base.powf(exponent)),
+    }
+}
+
+/// `x`, an integer or decimal, to the power `n`, if it fits in a decimal.
+fn exact_power(x: Number, n: u32) -> Option<Decimal> {
+    let x = x.to_decimal()?;
+    Some(Decimal {
+        digits: x.digits.checked_pow(n)?,
+        scale: x
+            .scale
+            .checked_mul(n)
+            .filter(|&scale| scale <= MAX_DIGITS)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtin::tests::{row, select};
+    use crate::database::tests::temp_database;
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_math_functions() {
+        let (_dir, database) = temp_database();
+        let mut session = SqlSession::new(database.connect());
+
+        // Integers stay integers, decimals keep their places and floats
+        // round half to even
+        assert_eq!(
+            select(
+                &mut session,
+                "round(7), round(2.5), round(-2.5), round(2.5e0), round(3.5e0)"
+            )
+            .unwrap(),
+            row(["7", "3", "-3", "2", "4"].map(Some))
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "round(3.14159, 2), round(2, 2), round(1250.5, -2), round(-0.125, 2)"
+            )
+            .unwrap(),
+            row(["3.14", "2.00", "1300", "-0.13"].map(Some))
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "floor(-2.5), ceil(-2.5), ceiling(2.1), floor(7), floor(-2.5e0)"
+            )
+            .unwrap(),
+            row(["-3", "-2", "3", "7", "-3"].map(Some))
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "mod(7, 3), mod(-7, 3), mod(7.5, 2), mod(7, 2.25), mod(7.5e0, 2)"
+            )
+            .unwrap(),
+            row(["1", "-1", "1.5", "0.25", "1.5"].map(Some))
+        );
+        assert_eq!(select(&mut session, "mod(1, 0)"), Err("22012"));
+        assert_eq!(select(&mut session, "mod(1.5, 0.0)"), Err("22012"));
+
+        // Powers of decimals are exact where they can be
+        assert_eq!(
+            select(
+                &mut session,
+                "power(2, 10), pow(1.5, 2), power(2, 0.5), power(4.0, 0.5)"
+            )
+            .unwrap(),
+            row(["1024", "2.25", "1.4142135623730951", "2.0000000000000000"].map(Some))
+        );
+        assert_eq!(select(&mut session, "power(0, -1)"), Err("2201F"));
+        assert_eq!(select(&mut session, "power(-8, 0.5)"), Err("2201F"));
+        assert_eq!(select(&mut session, "power(10, 400)"), Err("22003"));
+
+        assert_eq!(
+            select(
+                &mut session,
+                "sqrt(16), sqrt(2.0), exp(0), ln(1), log(1000), log(2, 8)"
+            )
+            .unwrap(),
+            row(["4", "1.4142135623730951", "1", "0", "3", "3"].map(Some))
+        );
+        assert_eq!(select(&mut session, "sqrt(-1)"), Err("2201F"));
+        assert_eq!(select(&mut session, "ln(0)"), Err("2201E"));
+        assert_eq!(select(&mut session, "log(-1)"), Err("2201E"));
+        assert_eq!(select(&mut session, "exp(1000)"), Err("22003"));
+        assert_eq!(select(&mut session, "sqrt('four')"), Err("22P02"));
+        assert_eq!(
+            select(&mut session, "round(NULL, 2), mod(1, NULL)").unwrap(),
+            row([None, None])
+        );
+        assert_eq!(
+            select(&mut session, "round(9223372036854775808.5)").unwrap(),
+            row(["9223372036854775809"].map(Some))
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "round(170141183460469231731687303715884105727, -1)"
+            ),
+            Err("22003")
+        );
+
+        let random = select(&mut session, "random()").unwrap().remove(0).unwrap();
+        let random: f64 = random.parse().unwrap();
+        assert!((0.0..1.0).contains(&random));
+    }
+}
@@ -0,0 +1,259 @@
+//! The functions SQL has built in, called as `name(<value>, ...)` as
+//! registered ones are; a function the database has registered under the
+//! same name is called instead. Those on the session, such as
+//! `current_user`, and on the time its transaction started, such as
+//! `now()`, are worked out from the `Context` the statement runs in, so
+//! they give the same value throughout it; `current_user`,
+//! `session_user`, `current_catalog` and `current_timestamp` may also be
+//! written without parentheses, as SQL writes them.
+//! Aggregates, such as `sum` and `string_agg`, fold a table's rows as
+//! registered ones do, and likewise give way to one by the same name.
+//!
+//! A function given a NULL returns NULL without being called, unless it's
+//! one of those, such as `concat`, that say what they make of one. Text
+//! is taken a character at a time, however many bytes each is.
+
+mod aggregate;
+mod conditional;
+mod datetime;
+mod math;
+mod string;
+
+use crate::database::Database;
+use crate::logging::Timestamp;
+use crate::sql::SqlError;
+use std::time::SystemTime;
+
+/// What a statement runs in: the database, and the session running it.
+pub(crate) struct Context<'a> {
+    pub(crate) database: &'a Database,
+    /// The session's user; none for a session held to no user's
+    /// privileges
+    pub(crate) user: Option<String>,
+    /// The database the client named when it connected, or empty
+    pub(crate) catalog: String,
+    /// When the open transaction started, or this statement if there's
+    /// none
+    pub(crate) transaction_started: SystemTime,
+    pub(crate) statement_started: SystemTime,
+}
+
+/// A built-in function.
+struct Function {
+    name: &'static str,
+    /// The fewest arguments it takes, and the most
+    arity: (usize, usize),
+    body: Body,
+}
+
+type StrictFn = fn(&[&str]) -> Result<String, SqlError>;
+
+type LaxFn = fn(&[Option<&str>]) -> Result<Option<String>, SqlError>;
+
+enum Body {
+    /// Worked out from the session the statement runs in
+    Session(fn(&Context) -> String),
+    /// Called on its arguments unless one is NULL
+    Strict(StrictFn),
+    /// Called on its arguments whatever they are
+    Lax(LaxFn),
+}
+
+const SESSION: &[Function] = &[
+    Function {
+        name: "clock_timestamp",
+        arity: (0, 0),
+        body: Body::Session(|_| Timestamp(SystemTime::now()).to_string()),
+    },
+    Function {
+        name: "current_catalog",
+        arity: (0, 0),
+        body: Body::Session(|context| context.catalog.clone()),
+    },
+    Function {
+        name: "current_database",
+        arity: (0, 0),
+        body: Body::Session(|context| context.catalog.clone()),
+    },
+    Function {
+        name: "current_timestamp",
+        arity: (0, 0),
+        body: Body::Session(|context| Timestamp(context.transaction_started).to_string()),
+    },
+    Function {
+        name: "current_user",
+        arity: (0, 0),
+        body: Body::Session(|context| context.user.clone().unwrap_or_default()),
+    },
+    Function {
+        name: "now",
+        arity: (0, 0),
+        body: Body::Session(|context| Timestamp(context.transaction_started).to_string()),
+    },
+    Function {
+        name: "session_user",
+        arity: (0, 0),
+        body: Body::Session(|context| context.user.clone().unwrap_or_default()),
+    },
+    Function {
+        name: "statement_timestamp",
+        arity: (0, 0),
+        body: Body::Session(|context| Timestamp(context.statement_started).to_string()),
+    },
+    Function {
+        name: "transaction_timestamp",
+        arity: (0, 0),
+        body: Body::Session(|context| Timestamp(context.transaction_started).to_string()),
+    },
+];
+
+/// A built-in aggregate.
+struct Aggregate {
+    name: &'static str,
+    /// The fewest arguments it takes after the data, and the most
+    arity: (usize, usize),
+    /// The result of the rows' data, given the arguments
+    fold: FoldFn,
+}
+
+type FoldFn = fn(&[&str], &[&str]) -> Result<Option<String>, SqlError>;
+
+/// Every library of built-in functions.
+const LIBRARIES: &[&[Function]] = &[
+    SESSION,
+    conditional::FUNCTIONS,
+    datetime::FUNCTIONS,
+    math::FUNCTIONS,
+    string::FUNCTIONS,
+];
+
+/// The result of the built-in function `name` on `args`, or `None` if
+/// there's none by that name taking as many.
+pub(crate) fn call(
+    context: &Context,
+    name: &str,
+    args: &[Option<String>],
+) -> Option<Result<Option<String>, SqlError>> {
+    let function = LIBRARIES.iter().copied().flatten().find(|function| {
+        function.name.eq_ignore_ascii_case(name)
+            && (function.arity.0..=function.arity.1).contains(&args.len())
+    })?;
+    let args: Vec<_> = args.iter().map(Option::as_deref).collect();
+    Some(match function.body {
+        Body::Session(body) => Ok(Some(body(context))),
+        Body::Strict(body) => match args.iter().copied().collect::<Option<Vec<_>>>() {
+            Some(args) => body(&args).map(Some),
+            None => Ok(None),
+        },
+        Body::Lax(body) => body(&args),
+    })
+}
+
+/// The result of the built-in aggregate `name` on `rows`, given `args`
+/// after their data, or `None` if there's none by that name taking as
+/// many.
+pub(crate) fn aggregate(
+    name: &str,
+    args: &[String],
+    rows: &[&str],
+) -> Option<Result<Option<String>, SqlError>> {
+    let aggregate = aggregate::FUNCTIONS.iter().find(|aggregate| {
+        aggregate.name.eq_ignore_ascii_case(name)
+            && (aggregate.arity.0..=aggregate.arity.1).contains(&args.len())
+    })?;
+    let args: Vec<_> = args.iter().map(String::as_str).collect();
+    Some((aggregate.fold)(rows, &args))
+}
+
+/// `arg` as an integer, as a function takes one.
+fn integer(arg: &str) -> Result<i64, SqlError> {
+    arg.trim().parse().map_err(|_| {
+        let message = format!("invalid input syntax for type integer: \"{}\"", arg);
+        SqlError::new("22P02", message)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::tests::temp_database;
+    use crate::sql::{SqlError, SqlSession, StatementResult};
+    use std::thread;
+    use std::time::Duration;
+
+    /// A row as results hold it, from its values or `None` for NULL.
+    pub(super) fn row<const N: usize>(values: [Option<&str>; N]) -> Vec<Option<String>> {
+        values.map(|value| value.map(String::from)).to_vec()
+    }
+
+    /// The row `SELECT values` returns in `session`, or the SQLSTATE of
+    /// its error.
+    pub(super) fn select(
+        session: &mut SqlSession,
+        values: &str,
+    ) -> Result<Vec<Option<String>>, &'static str> {
+        match session.execute(&format!("SELECT {values}")).pop() {
+            Some(Ok(mut result)) => Ok(result.rows.remove(0)),
+            Some(Err(e)) => Err(e.code()),
+            None => unreachable!("a SELECT is one statement, so has one result"),
+        }
+    }
+
+    #[test]
+    fn test_context_functions() {
+        let (_dir, database) = temp_database();
+        let table = database.create_table().unwrap();
+        let audit = database.create_table().unwrap();
+        database.create_user("alice", "secret", true).unwrap();
+        let mut session = SqlSession::for_user(database.connect(), "alice");
+        session.set("database", "shop").unwrap();
+        let mut run = |sql: &str| -> Result<StatementResult, SqlError> {
+            session.execute(sql).pop().unwrap()
+        };
+        let value = |result: Result<StatementResult, SqlError>| {
+            result.unwrap().rows.remove(0).remove(0).unwrap()
+        };
+
+        let context = run("SELECT CURRENT_USER, session_user(), current_database(), 'a'").unwrap();
+        assert_eq!(
+            context.columns,
+            [
+                "current_user",
+                "session_user",
+                "current_database",
+                "?column?"
+            ]
+        );
+        assert_eq!(
+            context.rows,
+            [row(["alice", "alice", "shop", "a"].map(Some))]
+        );
+        assert_eq!(value(run("SELECT current_catalog")), "shop");
+        assert_eq!(run("SELECT now('x')").unwrap_err().code(), "42883");
+
+        // A transaction's time is when it started, the statement's when it
+        // did
+        run("BEGIN").unwrap();
+        let started = value(run("SELECT now()"));
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(value(run("SELECT CURRENT_TIMESTAMP")), started);
+        assert_eq!(value(run("SELECT transaction_timestamp()")), started);
+        assert!(value(run("SELECT statement_timestamp()")) > started);
+        assert!(value(run("SELECT clock_timestamp()")) > started);
+        run("COMMIT").unwrap();
+        thread::sleep(Duration::from_millis(5));
+        assert!(value(run("SELECT now()")) > started);
+
+        // Triggers see the session of the statement firing them
+        run(&format!(
+            "CREATE TRIGGER stamp AFTER INSERT ON {table} EXECUTE INSERT INTO {audit} VALUES (CURRENT_USER)"
+        ))
+        .unwrap();
+        run(&format!("INSERT INTO {table} VALUES (now())")).unwrap();
+        let stamped = run(&format!("SELECT * FROM {audit}")).unwrap().rows;
+        assert_eq!(stamped[0][1].as_deref(), Some("alice"));
+
+        // A function the database registers is called in place of one built in
+        database.register_scalar_fn("current_database", |_| "other".to_string());
+        assert_eq!(value(run("SELECT current_database()")), "other");
+    }
+}
@@ -0,0 +1,264 @@
+//! Functions on text, counting in characters as Postgres does.
+
+use super::{integer, Body, Function};
+use crate::sql::SqlError;
+
+pub(super) const FUNCTIONS: &[Function] = &[
+    Function {
+        name: "btrim",
+        arity: (1, 2),
+        body: Body::Strict(|args| Ok(trim(args, true, true))),
+    },
+    // NULLs are left out, rather than making the result NULL
+    Function {
+        name: "concat",
+        arity: (1, usize::MAX),
+        body: Body::Lax(|args| Ok(Some(args.iter().copied().flatten().collect()))),
+    },
+    Function {
+        name: "instr",
+        arity: (2, 2),
+        body: Body::Strict(|args| Ok(position(args[0], args[1]).to_string())),
+    },
+    Function {
+        name: "lpad",
+        arity: (2, 3),
+        body: Body::Strict(|args| pad(args, true)),
+    },
+    Function {
+        name: "ltrim",
+        arity: (1, 2),
+        body: Body::Strict(|args| Ok(trim(args, true, false))),
+    },
+    Function {
+        name: "replace",
+        arity: (3, 3),
+        body: Body::Strict(|args| match args[1] {
+            "" => Ok(args[0].to_string()),
+            from => Ok(args[0].replace(from, args[2])),
+        }),
+    },
+    Function {
+        name: "rpad",
+        arity: (2, 3),
+        body: Body::Strict(|args| pad(args, false)),
+    },
+    Function {
+        name: "rtrim",
+        arity: (1, 2),
+        body: Body::Strict(|args| Ok(trim(args, false, true))),
+    },
+    Function {
+        name: "split_part",
+        arity: (3, 3),
+        body: Body::Strict(split_part),
+    },
+    Function {
+        name: "strpos",
+        arity: (2, 2),
+        body: Body::Strict(|args| Ok(position(args[0], args[1]).to_string())),
+    },
+    Function {
+        name: "substr",
+        arity: (2, 3),
+        body: Body::Strict(substr),
+    },
+    Function {
+        name: "substring",
+        arity: (2, 3),
+        body: Body::Strict(substr),
+    },
+    Function {
+        name: "trim",
+        arity: (1, 2),
+        body: Body::Strict(|args| Ok(trim(args, true, true))),
+    },
+];
+
+/// The characters of `args[0]` from the one at `args[1]`, counting from
+/// 1, to the end or for `args[2]` of them. Those before the first count
+/// too, as in Postgres, so `substr('abc', 0, 2)` is `a`.
+fn substr(args: &[&str]) -> Result<String, SqlError> {
+    let start = integer(args[1])?;
+    let end = match args.get(2) {
+        Some(count) => match integer(count)? {
+            count if count < 0 => {
+                let message = "negative substring length not allowed";
+                return Err(SqlError::new("22011", message));
+            }
+            count => start.saturating_add(count),
+        },
+        None => i64::MAX,
+    };
+    let skip = usize::try_from(start.max(1) - 1).unwrap_or(usize::MAX);
+    let take = usize::try_from(end.max(1) - start.max(1)).unwrap_or(usize::MAX);
+    Ok(args[0].chars().skip(skip).take(take).collect())
+}
+
+/// `args[0]` without the characters of `args[1]`, or spaces, at its
+/// start and its end as asked.
+fn trim(args: &[&str], start: bool, end: bool) -> String {
+    let characters = args.get(1).copied().unwrap_or(" ");
+    let trimmed = |c: char| characters.contains(c);
+    let mut text = args[0];
+    if start {
+        text = text.trim_start_matches(trimmed);
+    }
+    if end {
+        text = text.trim_end_matches(trimmed);
+    }
+    text.to_string()
+}
+
+/// `args[0]` made `args[1]` characters long, filled out at its start or
+/// end with `args[2]`, or spaces, repeated, or cut short at its end.
+fn pad(args: &[&str], start: bool) -> Result<String, SqlError> {
+    let length = usize::try_from(integer(args[1])?).unwrap_or(0);
+    let fill = args.get(2).copied().unwrap_or(" ");
+    let text: String = args[0].chars().take(length).collect();
+    let missing = length - text.chars().count();
+    if fill.is_empty() || missing == 0 {
+        return Ok(text);
+    }
+    let filling: String = fill.chars().cycle().take(missing).collect();
+    Ok(if start {
+        filling + &text
+    } else {
+        text + &filling
+    })
+}
+
+/// Where `part` first comes in `text`, counting characters from 1, or 0
+/// if it doesn't.
+fn position(text: &str, part: &str) -> usize {
+    match text.find(part) {
+        Some(at) => text[..at].chars().count() + 1,
+        None => 0,
+    }
+}
+
+/// The field of `args[0]` split at each `args[1]` that `args[2]` counts
+/// to, from 1 at the start or -1 at the end, or empty if there are fewer.
+fn split_part(args: &[&str]) -> Result<String, SqlError> {
+    let (text, delimiter) = (args[0], args[1]);
+    let n = integer(args[2])?;
+    if n == 0 {
+        return Err(SqlError::new("22023", "field position must not be zero"));
+    }
+    let fields: Vec<&str> = match delimiter {
+        "" => vec![text],
+        _ => text.split(delimiter).collect(),
+    };
+    let at = match n {
+        n if n > 0 => usize::try_from(n - 1).ok(),
+        n => usize::try_from(n.unsigned_abs())
+            .ok()
+            .and_then(|back| fields.len().checked_sub(back)),
+    };
+    Ok(at
+        .and_then(|at| fields.get(at))
+        .copied()
+        .unwrap_or_default()
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtin::tests::{row, select};
+    use crate::database::tests::temp_database;
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_string_functions() {
+        let (_dir, database) = temp_database();
+        let table = database.create_table().unwrap();
+        let mut session = SqlSession::new(database.connect());
+
+        assert_eq!(
+            select(
+                &mut session,
+                "substr('héllo', 2, 3), substr('héllo', 3), substring('abc', 0, 2)"
+            ),
+            Ok(row(["éll", "llo", "a"].map(Some)))
+        );
+        assert_eq!(
+            select(&mut session, "substr('abc', 5), substr('abc', -1, 3)").unwrap(),
+            row(["", "a"].map(Some))
+        );
+        assert_eq!(select(&mut session, "substr('abc', 1, '-1')"), Err("22011"));
+        assert_eq!(select(&mut session, "substr('abc', 'one')"), Err("22P02"));
+
+        assert_eq!(
+            select(
+                &mut session,
+                "trim('  ü  '), ltrim('xxüxx', 'x'), rtrim('xxüxx', 'x'), btrim('üaü', 'ü')"
+            )
+            .unwrap(),
+            row(["ü", "üxx", "xxü", "a"].map(Some))
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "replace('añoaño', 'ñ', 'nn'), replace('abc', '', 'x')"
+            )
+            .unwrap(),
+            row(["annoanno", "abc"].map(Some))
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "lpad('日本', 5, 'ab'), rpad('日本', 4), lpad('日本語', 2), rpad('x', 3, '')"
+            )
+            .unwrap(),
+            row(["aba日本", "日本  ", "日本", "x"].map(Some))
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "instr('日本語', '語'), strpos('abc', 'z'), instr('abc', '')"
+            )
+            .unwrap(),
+            row(["3", "0", "1"].map(Some))
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "split_part('a,b,,ç', ',', 4), split_part('a,b', ',', -1), \
+                 split_part('a,b', ',', 3), split_part('a,b', '', 1)"
+            )
+            .unwrap(),
+            row(["ç", "b", "", "a,b"].map(Some))
+        );
+        assert_eq!(
+            select(&mut session, "split_part('a,b', ',', 0)"),
+            Err("22023")
+        );
+
+        // NULL makes NULL, except that CONCAT leaves it out
+        assert_eq!(
+            select(
+                &mut session,
+                "substr(NULL, 1), replace('a', NULL, 'b'), lpad('a', NULL)"
+            )
+            .unwrap(),
+            row([None, None, None])
+        );
+        assert_eq!(
+            select(
+                &mut session,
+                "concat('a', NULL, 'ç', 'd'), concat(NULL), concat('', '')"
+            )
+            .unwrap(),
+            row([Some("açd"), Some(""), Some("")])
+        );
+        let insert = format!("INSERT INTO {table} VALUES (replace('a', NULL, 'b'))");
+        let mut other = SqlSession::new(database.connect());
+        let error = other.execute(&insert).pop().unwrap().unwrap_err();
+        assert_eq!(error.code(), "22004");
+        assert_eq!(
+            select(&mut session, "substr(NULL, 'one')").unwrap(),
+            row([None])
+        );
+        assert_eq!(select(&mut session, "substr('a', 1, 2, 3)"), Err("42883"));
+    }
+}
@@ -1,6 +1,12 @@
+mod toml;
+
+use crate::logging::LogLevel;
 use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
@@ -8,14 +14,415 @@ use thiserror::Error;
 pub struct Config {
     pub storage: StorageConfig,
     pub logging: LoggingConfig,
+    #[serde(default)]
+    pub transactions: TransactionConfig,
+    #[serde(default)]
+    pub server: ServerConfig,
+    /// Where DDL, privilege changes and logins are recorded; not recorded
+    /// when absent
+    #[serde(default)]
+    pub audit: Option<AuditConfig>,
+    /// What each user's sessions may use of a shared server
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    /// Where each setting not left at its default came from
+    #[serde(skip)]
+    sources: Sources,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct StorageConfig {
+    /// Directory holding the catalog and per-table data files, or
+    /// `:memory:` to keep them in memory only
     pub db_path: String,
     pub page_size: u64,
     pub cache_size: usize,
+    #[serde(default)]
+    pub eviction_policy: EvictionPolicyKind,
+    /// Number of accesses tracked per page by the `lru_k` policy
+    #[serde(default = "default_lru_k")]
+    pub lru_k: usize,
+    /// Pages to load ahead of a detected sequential scan; 0 disables it
+    #[serde(default)]
+    pub read_ahead: usize,
+    /// Background writer for dirty pages; disabled when absent
+    #[serde(default)]
+    pub flusher: Option<FlusherConfig>,
+    #[serde(default)]
+    pub durability: Durability,
+    /// Pages to extend a data file by when it runs out of space
+    #[serde(default = "default_growth_chunk_pages")]
+    pub growth_chunk_pages: u64,
+    #[serde(default)]
+    pub io_mode: IoMode,
+    /// Upgrade databases in an older format on open; they are refused when
+    /// absent
+    #[serde(default)]
+    pub migration: Option<MigrationMode>,
+    /// Compress pages on disk. Fixed when the database is created.
+    #[serde(default)]
+    pub compression: Compression,
+    /// Percent of each table page to fill with new rows, leaving the rest
+    /// for those on it to grow into; a table's own `fill_factor` overrides
+    /// it
+    #[serde(default = "default_fill_factor")]
+    pub fill_factor: u8,
+    /// Encrypt pages on disk; plaintext when absent. Fixed when the database
+    /// is created.
+    #[serde(default)]
+    pub encryption: Option<EncryptionConfig>,
+    /// Write-ahead log for recoverable page changes; a database enables it
+    /// with the default settings when absent. Fixed when the database is
+    /// created.
+    #[serde(default)]
+    pub wal: Option<WalConfig>,
+    /// Streaming the log to or from other servers; needs the log enabled
+    #[serde(default)]
+    pub replication: Option<ReplicationConfig>,
+    /// Background deletion of rows past the time in their table's
+    /// `ttl_column`, as the server runs it; disabled when absent
+    #[serde(default)]
+    pub ttl_sweep: Option<TtlSweepConfig>,
+    /// Answer `COUNT(*)` at once, without waiting for transactions writing
+    /// to the table, from the rows kept count of or the table's statistics
+    #[serde(default)]
+    pub approximate_counts: bool,
+    /// Temporary files for sorts and joins too big to hold in memory;
+    /// they're held however big when absent
+    #[serde(default)]
+    pub spill: Option<SpillConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct WalConfig {
+    /// Bytes per log segment file before a new one is started
+    #[serde(default = "default_wal_segment_size")]
+    pub segment_size: u64,
+    /// How often to checkpoint in the background; only on request when
+    /// absent
+    #[serde(default)]
+    pub checkpoint_interval_ms: Option<u64>,
+    /// How long a commit waits for others to share its log sync while other
+    /// transactions are running; syncs straight away when absent
+    #[serde(default)]
+    pub commit_delay_us: Option<u64>,
+    /// How long to keep log segments once recovery no longer needs them,
+    /// so pages can still be read as they were; removed straight away when
+    /// absent
+    #[serde(default)]
+    pub retention_ms: Option<u64>,
+    /// Directory to copy each finished log segment to, for point-in-time
+    /// recovery from a backup; not archived when absent
+    #[serde(default)]
+    pub archive_dir: Option<String>,
+    /// Log the whole of a page the first time it changes after each
+    /// checkpoint, and only the bytes that changed after that until the
+    /// next; otherwise a page is only logged whole the first time it
+    /// changes in each segment. A replica must have its primary's setting
+    #[serde(default = "default_full_page_writes")]
+    pub full_page_writes: bool,
+}
+
+fn default_wal_segment_size() -> u64 {
+    16 * 1024 * 1024
+}
+
+fn default_full_page_writes() -> bool {
+    true
+}
+
+impl Default for WalConfig {
+    fn default() -> Self {
+        Self {
+            segment_size: default_wal_segment_size(),
+            checkpoint_interval_ms: None,
+            commit_delay_us: None,
+            retention_ms: None,
+            archive_dir: None,
+            full_page_writes: default_full_page_writes(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ReplicationConfig {
+    /// Address to accept replicas on, such as `0.0.0.0:5433`
+    #[serde(default)]
+    pub listen: Option<String>,
+    /// Address of the primary to follow as a read-only replica
+    #[serde(default)]
+    pub primary: Option<String>,
+    /// How often to check for new log when caught up, and to retry a lost
+    /// connection
+    #[serde(default = "default_replication_poll_ms")]
+    pub poll_interval_ms: u64,
+}
+
+fn default_replication_poll_ms() -> u64 {
+    100
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptionConfig {
+    /// 256-bit key as 64 hex digits. Read from the `FERRODB_ENCRYPTION_KEY`
+    /// environment variable when absent, keeping it out of the file.
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    /// LZ4, when ferrodb is built with the compression feature
+    Lz,
+}
+
+/// How an older database is upgraded to the current on-disk format.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationMode {
+    /// Rewrite the database directory directly. Cheapest, but a failure part
+    /// way through can leave the database unusable.
+    InPlace,
+    /// Migrate a copy of the directory and swap it in once every step has
+    /// succeeded, keeping the original alongside as `<db_path>.v<version>`.
+    Copy,
+}
+
+/// How the data files are opened.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IoMode {
+    /// Reads and writes go through the OS page cache
+    #[default]
+    Buffered,
+    /// Bypass the OS page cache (`O_DIRECT`), so pages are cached only by the
+    /// buffer pool. Requires a page size that is a multiple of 4096.
+    Direct,
+    /// Buffered, but every write waits for the device (`O_DSYNC`)
+    Dsync,
+    /// Map each file into memory, so reading a page is a copy rather than
+    /// a seek and a read; a full-durability flush is an `msync`
+    Mmap,
+}
+
+fn default_growth_chunk_pages() -> u64 {
+    8
+}
+
+/// How far a flush pushes written pages before returning.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Durability {
+    /// Sync the file to stable storage, surviving power loss
+    #[default]
+    Full,
+    /// Hand pages to the OS, surviving a process crash but not power loss
+    Os,
+    /// Leave pages in the write buffer until it fills; fastest, but a crash
+    /// can lose any write
+    None,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct FlusherConfig {
+    /// How often the background writer wakes up
+    pub interval_ms: u64,
+    /// Only flush once at least this percentage of the cache is dirty;
+    /// flush on every wake-up when absent
+    #[serde(default)]
+    pub dirty_threshold_percent: Option<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TtlSweepConfig {
+    /// How often to look for expired rows
+    pub interval_ms: u64,
+    /// Rows to delete in each transaction, so that a sweep only holds a
+    /// table's lock for a moment at a time
+    #[serde(default = "default_ttl_batch_size")]
+    pub batch_size: usize,
+}
+
+fn default_ttl_batch_size() -> usize {
+    100
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SpillConfig {
+    /// Directory for the temporary files, which no other database may
+    /// use, as files left in it are removed on opening; `temp` in
+    /// `db_path` when absent
+    #[serde(default)]
+    pub temp_dir: Option<String>,
+    /// Bytes of rows a sort or hash join holds before writing them out
+    #[serde(default = "default_work_mem")]
+    pub work_mem: u64,
+    /// Bytes the temporary files may take up between them, past which a
+    /// statement writing more fails; unlimited when absent
+    #[serde(default)]
+    pub temp_limit: Option<u64>,
+}
+
+fn default_work_mem() -> u64 {
+    4 * 1024 * 1024
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicyKind {
+    #[default]
+    Lru,
+    Clock,
+    LruK,
+}
+
+fn default_fill_factor() -> u8 {
+    100
+}
+
+fn default_lru_k() -> usize {
+    2
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TransactionConfig {
+    /// How long to wait for a conflicting lock before giving up; waits
+    /// until it is released when absent
+    #[serde(default)]
+    pub lock_timeout_ms: Option<u64>,
+    /// Roll back transactions left unused for this long, releasing their
+    /// locks; they may stay open indefinitely when absent
+    #[serde(default)]
+    pub idle_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address to accept clients on
+    #[serde(default = "default_server_listen")]
+    pub listen: String,
+    /// Sessions served at once; clients connecting beyond this wait for one
+    /// to end
+    #[serde(default = "default_server_workers")]
+    pub workers: usize,
+    /// Password clients must give until the database has users; any is
+    /// accepted when absent
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub protocol: WireProtocol,
+    /// Address to serve metrics on over HTTP, at `/metrics`, for
+    /// Prometheus to scrape; not served when absent
+    #[serde(default)]
+    pub metrics_listen: Option<String>,
+    /// How long a stopping server waits for open transactions to finish
+    /// before rolling them back
+    #[serde(default = "default_shutdown_timeout_ms")]
+    pub shutdown_timeout_ms: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WireProtocol {
+    /// ferrodb's own protocol, spoken by `Client`
+    #[default]
+    Native,
+    /// Enough of PostgreSQL's protocol for `psql` and Postgres drivers to
+    /// run simple queries
+    Postgres,
+}
+
+fn default_server_listen() -> String {
+    "127.0.0.1:5480".to_string()
+}
+
+fn default_server_workers() -> usize {
+    8
+}
+
+fn default_shutdown_timeout_ms() -> u64 {
+    30_000
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: default_server_listen(),
+            workers: default_server_workers(),
+            password: None,
+            protocol: WireProtocol::Native,
+            metrics_listen: None,
+            shutdown_timeout_ms: default_shutdown_timeout_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
+    /// File the audit trail is appended to, kept apart from the log
+    pub file: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// The limits of users not named in `users`
+    #[serde(default)]
+    pub default: UserLimits,
+    /// Limits for particular users, each over those in `default`
+    #[serde(default)]
+    pub users: BTreeMap<String, UserLimits>,
+}
+
+impl LimitsConfig {
+    /// The limits of `user`: their own where they have them, and the
+    /// default ones for the rest.
+    pub fn for_user(&self, user: &str) -> UserLimits {
+        let default = self.default;
+        match self.users.get(user) {
+            Some(limits) => UserLimits {
+                max_connections: limits.max_connections.or(default.max_connections),
+                max_query_memory: limits.max_query_memory.or(default.max_query_memory),
+                max_rows: limits.max_rows.or(default.max_rows),
+                statement_timeout_ms: limits.statement_timeout_ms.or(default.statement_timeout_ms),
+            },
+            None => default,
+        }
+    }
+}
+
+/// What one user's sessions may use; each is unlimited when absent. The
+/// limits on a statement apply to SQL, not to the requests of ferrodb's
+/// own protocol.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct UserLimits {
+    /// Sessions the user may have open on a server at once
+    #[serde(default)]
+    pub max_connections: Option<u32>,
+    /// Bytes of rows a statement may return
+    #[serde(default)]
+    pub max_query_memory: Option<u64>,
+    /// Rows a statement may return
+    #[serde(default)]
+    pub max_rows: Option<u64>,
+    /// Cancel statements running longer than this, however long the
+    /// session's `statement_timeout` allows
+    #[serde(default)]
+    pub statement_timeout_ms: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
@@ -26,15 +433,35 @@ pub struct LoggingConfig {
     pub max_size_mb: u64,
     pub rotate: bool,
     pub max_files: u32,
+    /// Log statements that take longer than this as slow, with their text;
+    /// not logged when absent
+    #[serde(default)]
+    pub slow_query_ms: Option<u64>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             storage: StorageConfig {
-                db_path: "./ferrodb/database.fdb".to_string(),
+                db_path: "./ferrodb/data".to_string(),
                 page_size: 4096,
                 cache_size: 10,
+                eviction_policy: EvictionPolicyKind::Lru,
+                lru_k: default_lru_k(),
+                read_ahead: 0,
+                flusher: None,
+                durability: Durability::Full,
+                growth_chunk_pages: default_growth_chunk_pages(),
+                io_mode: IoMode::Buffered,
+                migration: None,
+                compression: Compression::None,
+                fill_factor: default_fill_factor(),
+                encryption: None,
+                wal: None,
+                replication: None,
+                ttl_sweep: None,
+                approximate_counts: false,
+                spill: None,
             },
             logging: LoggingConfig {
                 level: "info".to_string(),
@@ -42,7 +469,13 @@ impl Default for Config {
                 max_size_mb: 100,
                 rotate: true,
                 max_files: 5,
+                slow_query_ms: None,
             },
+            transactions: TransactionConfig::default(),
+            server: ServerConfig::default(),
+            audit: None,
+            limits: LimitsConfig::default(),
+            sources: Sources::default(),
         }
     }
 }
@@ -54,12 +487,174 @@ pub enum ConfigError {
 
     #[error("Invalid YAML: {0}")]
     InvalidYaml(String),
+
+    #[error("Invalid TOML: {0}")]
+    InvalidToml(String),
+
+    #[error("Invalid JSON: {0}")]
+    InvalidJson(String),
+
+    #[error("Unknown config format \"{0}\"; expected yaml, toml or json")]
+    UnknownFormat(String),
+
+    #[error("{0} can't be changed without a restart")]
+    NotReloadable(String),
+
+    #[error("Unknown setting {0}")]
+    UnknownSetting(String),
+
+    #[error("Invalid setting {0}; expected SETTING=VALUE")]
+    InvalidOverride(String),
+
+    #[error("Invalid configuration: {}", list(.0))]
+    Invalid(Vec<ConfigViolation>),
+}
+
+fn list(violations: &[ConfigViolation]) -> String {
+    let violations: Vec<String> = violations.iter().map(ToString::to_string).collect();
+    violations.join("; ")
+}
+
+/// A setting that breaks one of the rules `Config::validate` checks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigViolation {
+    /// The setting's path, such as `storage.page_size`
+    pub setting: String,
+    pub message: String,
+    /// Where the setting's value came from, such as `file db.yaml`
+    pub source: String,
+}
+
+impl Display for ConfigViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}) {}", self.setting, self.source, self.message)
+    }
+}
+
+/// The `storage.db_path` of a database kept only in memory, lost once it
+/// is closed.
+pub const IN_MEMORY: &str = ":memory:";
+
+/// The smallest page size `Config::validate` allows.
+pub const MIN_PAGE_SIZE: u64 = 512;
+
+/// The largest page size `Config::validate` allows, as a record's length
+/// takes 30 bits of its slot.
+pub const MAX_PAGE_SIZE: u64 = 1 << 29;
+
+/// The smallest fill factor, of a table or `storage.fill_factor`, allowed.
+pub const MIN_FILL_FACTOR: u8 = 10;
+
+/// The languages a config file can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// The format named `name`, such as `toml`.
+    pub fn parse(name: &str) -> Result<Self, ConfigError> {
+        match name.to_lowercase().as_str() {
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "toml" => Ok(ConfigFormat::Toml),
+            "json" => Ok(ConfigFormat::Json),
+            _ => Err(ConfigError::UnknownFormat(name.to_string())),
+        }
+    }
+
+    /// The format `path`'s extension names, if it names one.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+        Self::parse(extension).ok()
+    }
+
+    fn read(self, text: &str) -> Result<Value, ConfigError> {
+        match self {
+            ConfigFormat::Yaml => serde_yaml::from_str(text).map_err(|e| {
+                ConfigError::InvalidYaml(format!("Invalid YAML in config file: {}", e))
+            }),
+            ConfigFormat::Toml => toml::parse(text).map_err(ConfigError::InvalidToml),
+            ConfigFormat::Json => {
+                serde_json::from_str(text).map_err(|e| ConfigError::InvalidJson(e.to_string()))
+            }
+        }
+    }
+}
+
+/// Environment variables that start with this, and have `__` between
+/// the parts of a setting's path, override the setting:
+/// `FERRODB_STORAGE__CACHE_SIZE` is `storage.cache_size`.
+pub const ENV_PREFIX: &str = "FERRODB_";
+
+/// Where a setting's value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Source {
+    File(PathBuf),
+    /// An environment variable, by name
+    Env(String),
+    /// An override, as given to `--set`
+    Override,
+    /// A `SET GLOBAL` statement
+    Statement,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "file {}", path.display()),
+            Source::Env(name) => write!(f, "env {}", name),
+            Source::Override => write!(f, "--set"),
+            Source::Statement => write!(f, "SET GLOBAL"),
+        }
+    }
+}
+
+/// The source of each setting set by one, by path.
+#[derive(Debug, Clone, Default)]
+struct Sources(BTreeMap<String, Source>);
+
+// Where settings came from doesn't make them any different
+impl PartialEq for Sources {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
 }
 
+impl Eq for Sources {}
+
+/// The settings `Database::reload` can change while the database is
+/// open, as `Config::changes` names them.
+pub const RELOADABLE: [&str; 3] = [
+    "logging.level",
+    "logging.slow_query_ms",
+    "storage.cache_size",
+];
+
 impl Config {
-    /// Initialize the configuration, optionally from a YAML file
+    /// Initialize the configuration, optionally from a file, with the
+    /// process's `FERRODB_*` environment variables over it
     pub fn new<P: AsRef<Path>>(config_path: Option<P>) -> Result<Self, ConfigError> {
-        let mut config = Config::default();
+        Self::load(config_path, None, std::env::vars(), &[])
+    }
+
+    /// Layer the configuration from its sources, each over those before:
+    /// the defaults, the file if there is one, in `format` or else as its
+    /// extension says, YAML without one it knows, the `ENV_PREFIX`
+    /// variables in `env`, then `overrides` given as `SETTING=VALUE`, such
+    /// as `storage.cache_size=20`. Values from the environment and
+    /// overrides are YAML, so `20` is a number and `{file: audit.log}` a
+    /// section.
+    pub fn load<P: AsRef<Path>>(
+        config_path: Option<P>,
+        format: Option<ConfigFormat>,
+        env: impl IntoIterator<Item = (String, String)>,
+        overrides: &[String],
+    ) -> Result<Self, ConfigError> {
+        let mut value = serde_yaml::to_value(Config::default())
+            .map_err(|e| ConfigError::InvalidYaml(e.to_string()))?;
+        let mut sources = BTreeMap::new();
 
         // If config path is provided, override defaults with file values
         if let Some(path) = config_path {
@@ -70,14 +665,425 @@ impl Config {
                     e
                 ))
             })?;
+            let format = format
+                .or_else(|| ConfigFormat::from_path(path.as_ref()))
+                .unwrap_or(ConfigFormat::Yaml);
+            let file = format.read(&config_str)?;
+            // An empty file sets nothing
+            if !file.is_null() {
+                let source = Source::File(path.as_ref().to_path_buf());
+                merge(&mut value, file, "", &source, &mut sources);
+            }
+        }
 
-            config = serde_yaml::from_str(&config_str).map_err(|e| {
-                ConfigError::InvalidYaml(format!("Invalid YAML in config file: {}", e))
+        let mut variables: Vec<_> = env
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX) && name.contains("__"))
+            .collect();
+        // In a fixed order, whatever order the environment is in
+        variables.sort();
+        for (name, text) in variables {
+            let path = name[ENV_PREFIX.len()..].to_lowercase().replace("__", ".");
+            let setting = serde_yaml::from_str(&text).map_err(|e| {
+                ConfigError::InvalidYaml(format!("Invalid YAML in {}: {}", name, e))
             })?;
+            set(&mut value, &path, setting, &Source::Env(name), &mut sources);
+        }
+        for text in overrides {
+            let invalid = || ConfigError::InvalidOverride(text.clone());
+            let (path, setting) = text.split_once('=').ok_or_else(invalid)?;
+            let setting = serde_yaml::from_str(setting).map_err(|_| invalid())?;
+            set(&mut value, path, setting, &Source::Override, &mut sources);
         }
 
+        let mut config: Config = serde_yaml::from_value(value)
+            .map_err(|e| ConfigError::InvalidYaml(format!("Invalid configuration: {}", e)))?;
+        config.sources = Sources(sources);
         Ok(config)
     }
+
+    /// Check the settings against the rules the database relies on, such
+    /// as a page size that is a power of two and paths it can write to,
+    /// returning every setting that breaks one, not just the first.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut violations = Vec::new();
+        let mut check = |ok: bool, setting: &str, message: &str| {
+            if !ok {
+                violations.push(ConfigViolation {
+                    setting: setting.to_string(),
+                    message: message.to_string(),
+                    source: self.source(setting),
+                });
+            }
+        };
+
+        let storage = &self.storage;
+        check(
+            (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&storage.page_size)
+                && storage.page_size.is_power_of_two(),
+            "storage.page_size",
+            "must be a power of two from 512 to 512 MiB",
+        );
+        if storage.io_mode == IoMode::Direct {
+            check(
+                storage.page_size.is_multiple_of(4096),
+                "storage.page_size",
+                "must be a multiple of 4096 for direct I/O",
+            );
+        }
+        check(
+            storage.cache_size > 0,
+            "storage.cache_size",
+            "must be greater than 0",
+        );
+        check(
+            (MIN_FILL_FACTOR..=100).contains(&storage.fill_factor),
+            "storage.fill_factor",
+            "must be from 10 to 100",
+        );
+        if storage.eviction_policy == EvictionPolicyKind::LruK {
+            check(storage.lru_k > 0, "storage.lru_k", "must be greater than 0");
+        }
+        if let Some(flusher) = &storage.flusher {
+            check(
+                flusher
+                    .dirty_threshold_percent
+                    .is_none_or(|percent| percent <= 100),
+                "storage.flusher.dirty_threshold_percent",
+                "must be at most 100",
+            );
+        }
+        if let Some(sweep) = &storage.ttl_sweep {
+            check(
+                sweep.interval_ms > 0,
+                "storage.ttl_sweep.interval_ms",
+                "must be greater than 0",
+            );
+            check(
+                sweep.batch_size > 0,
+                "storage.ttl_sweep.batch_size",
+                "must be greater than 0",
+            );
+        }
+        check(
+            storage.replication.is_none() || storage.wal.is_some(),
+            "storage.replication",
+            "needs storage.wal",
+        );
+        let in_memory = storage.db_path == IN_MEMORY;
+        check(
+            in_memory || writable(Path::new(&storage.db_path), true),
+            "storage.db_path",
+            "must be a directory that can be written or created",
+        );
+        if let Some(dir) = storage
+            .wal
+            .as_ref()
+            .and_then(|wal| wal.archive_dir.as_ref())
+        {
+            check(
+                !in_memory,
+                "storage.wal.archive_dir",
+                "can't be used with a database in memory",
+            );
+            check(
+                in_memory || writable(Path::new(dir), true),
+                "storage.wal.archive_dir",
+                "must be a directory that can be written or created",
+            );
+        }
+
+        if let Some(spill) = &storage.spill {
+            check(
+                spill.work_mem > 0,
+                "storage.spill.work_mem",
+                "must be greater than 0",
+            );
+            match &spill.temp_dir {
+                Some(dir) => check(
+                    writable(Path::new(dir), true),
+                    "storage.spill.temp_dir",
+                    "must be a directory that can be written or created",
+                ),
+                None => check(
+                    !in_memory,
+                    "storage.spill.temp_dir",
+                    "must be given for a database in memory",
+                ),
+            }
+        }
+
+        let level = LogLevel::parse(&self.logging.level);
+        check(
+            level.is_ok(),
+            "logging.level",
+            "must be off, error, warn, info, debug or trace",
+        );
+        if matches!(level, Ok(Some(_))) {
+            check(
+                writable(Path::new(&self.logging.file), false),
+                "logging.file",
+                "must be a file that can be written or created",
+            );
+        }
+        if let Some(audit) = &self.audit {
+            check(
+                writable(Path::new(&audit.file), false),
+                "audit.file",
+                "must be a file that can be written or created",
+            );
+        }
+        check(
+            self.server.workers > 0,
+            "server.workers",
+            "must be greater than 0",
+        );
+        let limits = &self.limits;
+        let users = limits
+            .users
+            .iter()
+            .map(|(user, limits)| (format!("users.{}", user), limits));
+        for (name, limits) in [("default".to_string(), &limits.default)]
+            .into_iter()
+            .chain(users)
+        {
+            let setting = |field| format!("limits.{}.{}", name, field);
+            check(
+                limits.max_connections != Some(0),
+                &setting("max_connections"),
+                "must be greater than 0",
+            );
+            check(
+                limits.statement_timeout_ms != Some(0),
+                &setting("statement_timeout_ms"),
+                "must be greater than 0",
+            );
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(violations))
+        }
+    }
+
+    /// Where the setting at `path` came from, as `describe_sources` says.
+    fn source(&self, path: &str) -> String {
+        match self.sources.0.get(path) {
+            Some(source) => source.to_string(),
+            None => "default".to_string(),
+        }
+    }
+
+    /// Every setting, one to a line, with its value and where it came
+    /// from, the file, an environment variable or an override, if not left
+    /// at its default. Passwords and keys are hidden.
+    pub fn describe_sources(&self) -> String {
+        let value = serde_yaml::to_value(self).unwrap_or_default();
+        let mut settings = Vec::new();
+        leaves("", &value, &mut settings);
+        let mut out = String::new();
+        for (path, value) in settings {
+            let secret = path.ends_with("password") || path.ends_with(".key");
+            let text = match serde_yaml::to_string(value) {
+                _ if secret && !value.is_null() => "********".to_string(),
+                Ok(text) => text.trim_end().to_string(),
+                Err(_) => "?".to_string(),
+            };
+            out.push_str(&format!("{}: {} ({})\n", path, text, self.source(&path)));
+        }
+        out
+    }
+
+    /// The settings that differ in `other`, named by their path such as
+    /// `storage.cache_size`, in the order they're declared. A section
+    /// present in only one of them is named as a whole.
+    pub fn changes(&self, other: &Config) -> Vec<String> {
+        let mut changes = Vec::new();
+        let (Ok(ours), Ok(theirs)) = (serde_yaml::to_value(self), serde_yaml::to_value(other))
+        else {
+            return changes;
+        };
+        differences("", &ours, &theirs, &mut changes);
+        changes
+    }
+
+    /// The path of the setting `name`, either its path or the last part of
+    /// it, preferring those in `RELOADABLE` where that names several.
+    pub fn setting_path(&self, name: &str) -> Result<String, ConfigError> {
+        let value = serde_yaml::to_value(self).unwrap_or_default();
+        let mut settings = Vec::new();
+        leaves("", &value, &mut settings);
+        let named = |path: &str| path == name || path.rsplit('.').next() == Some(name);
+        let mut paths: Vec<_> = settings
+            .into_iter()
+            .map(|(path, _)| path)
+            .filter(|path| named(path))
+            .collect();
+        if let Some(path) = RELOADABLE.iter().find(|path| named(path)) {
+            return Ok(path.to_string());
+        }
+        match (paths.pop(), paths.is_empty()) {
+            (Some(path), true) => Ok(path),
+            _ => Err(ConfigError::UnknownSetting(name.to_string())),
+        }
+    }
+
+    /// The configuration with the setting at `path` set to `value`, YAML
+    /// as in an override, as `SET GLOBAL` sets it. Only the rules on that
+    /// setting are checked.
+    pub fn with_setting(&self, path: &str, value: &str) -> Result<Config, ConfigError> {
+        let mut tree =
+            serde_yaml::to_value(self).map_err(|e| ConfigError::InvalidYaml(e.to_string()))?;
+        let value = serde_yaml::from_str(value)
+            .map_err(|e| ConfigError::InvalidYaml(format!("Invalid value for {}: {}", path, e)))?;
+        let mut sources = self.sources.0.clone();
+        set(&mut tree, path, value, &Source::Statement, &mut sources);
+        let mut config: Config = serde_yaml::from_value(tree)
+            .map_err(|e| ConfigError::InvalidYaml(format!("Invalid configuration: {}", e)))?;
+        config.sources = Sources(sources);
+        if let Err(ConfigError::Invalid(violations)) = config.validate() {
+            let violations: Vec<_> = violations
+                .into_iter()
+                .filter(|violation| violation.setting == path)
+                .collect();
+            if !violations.is_empty() {
+                return Err(ConfigError::Invalid(violations));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Check that `other` only changes settings in `RELOADABLE`, returning
+    /// those it changes.
+    pub fn reload_changes(&self, other: &Config) -> Result<Vec<String>, ConfigError> {
+        let changes = self.changes(other);
+        match changes
+            .iter()
+            .find(|change| !RELOADABLE.contains(&change.as_str()))
+        {
+            Some(change) => Err(ConfigError::NotReloadable(change.clone())),
+            None => Ok(changes),
+        }
+    }
+}
+
+/// Whether `path` is a directory, or a file, that can be written, or
+/// could be created in the nearest directory above it that exists.
+fn writable(path: &Path, dir: bool) -> bool {
+    if path.as_os_str().is_empty() {
+        return false;
+    }
+    if let Ok(metadata) = fs::metadata(path) {
+        return metadata.is_dir() == dir && can_write(path);
+    }
+    let parent = path
+        .ancestors()
+        .skip(1)
+        // A relative path's last ancestor is empty
+        .map(|ancestor| match ancestor.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => ancestor,
+        })
+        .find(|ancestor| ancestor.exists());
+    parent.is_some_and(|parent| parent.is_dir() && can_write(parent))
+}
+
+#[cfg(unix)]
+fn can_write(path: &Path) -> bool {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+#[cfg(not(unix))]
+fn can_write(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| !metadata.permissions().readonly())
+}
+
+fn child(path: &str, key: &Value) -> String {
+    let name = key.as_str().unwrap_or_default();
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+/// Lay `over` on top of `base`, section by section, recording `source`
+/// as where each setting it holds came from.
+fn merge(
+    base: &mut Value,
+    over: Value,
+    path: &str,
+    source: &Source,
+    sources: &mut BTreeMap<String, Source>,
+) {
+    match (base, over) {
+        (Value::Mapping(base), Value::Mapping(over)) => {
+            for (key, value) in over {
+                let path = child(path, &key);
+                let base = base.entry(key).or_insert(Value::Null);
+                merge(base, value, &path, source, sources);
+            }
+        }
+        // A section that was absent takes the settings given
+        (base @ Value::Null, over @ Value::Mapping(_)) => {
+            *base = Value::Mapping(Mapping::new());
+            merge(base, over, path, source, sources);
+        }
+        (base, over) => {
+            *base = over;
+            sources.insert(path.to_string(), source.clone());
+        }
+    }
+}
+
+/// Set the setting at the dotted `path` to `value`.
+fn set(
+    base: &mut Value,
+    path: &str,
+    value: Value,
+    source: &Source,
+    sources: &mut BTreeMap<String, Source>,
+) {
+    let over = path.rsplit('.').fold(value, |value, key| {
+        let mut section = Mapping::new();
+        section.insert(Value::String(key.to_string()), value);
+        Value::Mapping(section)
+    });
+    merge(base, over, "", source, sources);
+}
+
+/// The settings in `value` that aren't sections, by path.
+fn leaves<'a>(path: &str, value: &'a Value, out: &mut Vec<(String, &'a Value)>) {
+    match value {
+        Value::Mapping(section) if !section.is_empty() => {
+            for (key, value) in section {
+                leaves(&child(path, key), value, out);
+            }
+        }
+        _ => out.push((path.to_string(), value)),
+    }
+}
+
+fn differences(path: &str, a: &Value, b: &Value, out: &mut Vec<String>) {
+    let (Value::Mapping(a), Value::Mapping(b)) = (a, b) else {
+        if a != b {
+            out.push(path.to_string());
+        }
+        return;
+    };
+    let keys = a.keys().chain(b.keys().filter(|key| !a.contains_key(*key)));
+    for key in keys {
+        let path = child(path, key);
+        let null = Value::Null;
+        let (a, b) = (a.get(key).unwrap_or(&null), b.get(key).unwrap_or(&null));
+        differences(&path, a, b, out);
+    }
 }
 
 #[cfg(test)]
@@ -89,16 +1095,18 @@ mod tests {
     fn test_default() {
         let config = Config::new(None::<&str>).unwrap();
 
-        assert_eq!(config.storage.db_path, "./ferrodb/database.fdb");
+        assert_eq!(config.storage.db_path, "./ferrodb/data");
         assert_eq!(config.storage.page_size, 4096);
         assert_eq!(config.storage.cache_size, 10);
+        assert_eq!(config.storage.eviction_policy, EvictionPolicyKind::Lru);
+        assert_eq!(config.storage.durability, Durability::Full);
     }
 
     #[test]
     fn test_load_config() {
         let config_content = r#"
             storage:
-                db_path: "/var/lib/ferrodb/data.fdb"
+                db_path: "/var/lib/ferrodb/data"
                 page_size: 8192
                 cache_size: 20
             logging:
@@ -114,16 +1122,436 @@ mod tests {
 
         let config = Config::new(Some(temp_file.path())).unwrap();
 
-        assert_eq!(config.storage.db_path, "/var/lib/ferrodb/data.fdb");
+        assert_eq!(config.storage.db_path, "/var/lib/ferrodb/data");
         assert_eq!(config.storage.page_size, 8192);
         assert_eq!(config.storage.cache_size, 20);
         assert_eq!(config.logging.level, "debug");
         assert_eq!(config.logging.file, "/var/log/ferrodb/db.log");
         assert_eq!(config.logging.max_size_mb, 200);
-        assert_eq!(config.logging.rotate, true);
+        assert!(config.logging.rotate);
         assert_eq!(config.logging.max_files, 10);
     }
 
+    #[test]
+    fn test_storage_options() {
+        let config_content = r#"
+            storage:
+                db_path: "/var/lib/ferrodb/data"
+                page_size: 8192
+                cache_size: 20
+                eviction_policy: lru_k
+                lru_k: 3
+                durability: os
+                io_mode: direct
+                migration: copy
+                compression: lz
+                wal: {}
+            logging:
+                level: "debug"
+                file: "/var/log/ferrodb/db.log"
+                max_size_mb: 200
+                rotate: true
+                max_files: 10
+        "#;
+
+        let temp_file = NamedTempFile::new().unwrap();
+        fs::write(&temp_file, config_content).unwrap();
+
+        let config = Config::new(Some(temp_file.path())).unwrap();
+        assert_eq!(config.storage.eviction_policy, EvictionPolicyKind::LruK);
+        assert_eq!(config.storage.lru_k, 3);
+        assert_eq!(config.storage.durability, Durability::Os);
+        assert_eq!(config.storage.io_mode, IoMode::Direct);
+        assert_eq!(config.storage.migration, Some(MigrationMode::Copy));
+        assert_eq!(config.storage.compression, Compression::Lz);
+        assert_eq!(
+            config.storage.wal,
+            Some(WalConfig {
+                segment_size: default_wal_segment_size(),
+                checkpoint_interval_ms: None,
+                commit_delay_us: None,
+                retention_ms: None,
+                archive_dir: None,
+                full_page_writes: true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_flusher() {
+        let config_content = r#"
+            storage:
+                db_path: "/var/lib/ferrodb/data"
+                page_size: 8192
+                cache_size: 20
+                flusher:
+                    interval_ms: 500
+                    dirty_threshold_percent: 25
+            logging:
+                level: "debug"
+                file: "/var/log/ferrodb/db.log"
+                max_size_mb: 200
+                rotate: true
+                max_files: 10
+        "#;
+
+        let temp_file = NamedTempFile::new().unwrap();
+        fs::write(&temp_file, config_content).unwrap();
+
+        let config = Config::new(Some(temp_file.path())).unwrap();
+        assert_eq!(
+            config.storage.flusher,
+            Some(FlusherConfig {
+                interval_ms: 500,
+                dirty_threshold_percent: Some(25),
+            })
+        );
+    }
+
+    #[test]
+    fn test_transactions() {
+        let config_content = r#"
+            storage:
+                db_path: "/var/lib/ferrodb/data"
+                page_size: 8192
+                cache_size: 20
+            logging:
+                level: "debug"
+                file: "/var/log/ferrodb/db.log"
+                max_size_mb: 200
+                rotate: true
+                max_files: 10
+            transactions:
+                lock_timeout_ms: 250
+                idle_timeout_ms: 60000
+        "#;
+
+        let temp_file = NamedTempFile::new().unwrap();
+        fs::write(&temp_file, config_content).unwrap();
+
+        let config = Config::new(Some(temp_file.path())).unwrap();
+        assert_eq!(config.transactions.lock_timeout_ms, Some(250));
+        assert_eq!(config.transactions.idle_timeout_ms, Some(60000));
+        assert_eq!(Config::default().transactions.lock_timeout_ms, None);
+    }
+
+    #[test]
+    fn test_replication() {
+        let config_content = r#"
+            storage:
+                db_path: "/var/lib/ferrodb/replica"
+                page_size: 8192
+                cache_size: 20
+                wal: {}
+                replication:
+                    primary: "10.0.0.1:5433"
+            logging:
+                level: "debug"
+                file: "/var/log/ferrodb/db.log"
+                max_size_mb: 200
+                rotate: true
+                max_files: 10
+        "#;
+
+        let temp_file = NamedTempFile::new().unwrap();
+        fs::write(&temp_file, config_content).unwrap();
+
+        let config = Config::new(Some(temp_file.path())).unwrap();
+        assert_eq!(
+            config.storage.replication,
+            Some(ReplicationConfig {
+                listen: None,
+                primary: Some("10.0.0.1:5433".to_string()),
+                poll_interval_ms: default_replication_poll_ms(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_reload_changes() {
+        let config = Config::default();
+        let mut other = config.clone();
+        assert_eq!(config.reload_changes(&other).unwrap(), Vec::<String>::new());
+
+        other.logging.level = "debug".to_string();
+        other.logging.slow_query_ms = Some(500);
+        other.storage.cache_size = 100;
+        assert_eq!(
+            config.reload_changes(&other).unwrap(),
+            [
+                "storage.cache_size",
+                "logging.level",
+                "logging.slow_query_ms"
+            ]
+        );
+
+        other.storage.page_size = 8192;
+        other.storage.wal = Some(WalConfig::default());
+        assert_eq!(
+            config.changes(&other),
+            [
+                "storage.page_size",
+                "storage.cache_size",
+                "storage.wal",
+                "logging.level",
+                "logging.slow_query_ms"
+            ]
+        );
+        let result = config.reload_changes(&other);
+        assert!(
+            matches!(result, Err(ConfigError::NotReloadable(path)) if path == "storage.page_size")
+        );
+    }
+
+    #[test]
+    fn test_with_setting() {
+        let config = Config::default();
+        assert_eq!(
+            config.setting_path("cache_size").unwrap(),
+            "storage.cache_size"
+        );
+        assert_eq!(
+            config.setting_path("logging.level").unwrap(),
+            "logging.level"
+        );
+        assert!(matches!(
+            config.setting_path("cache"),
+            Err(ConfigError::UnknownSetting(name)) if name == "cache"
+        ));
+        // Named by the last part of more than one path
+        let mut audited = config.clone();
+        audited.audit = Some(AuditConfig {
+            file: "audit.log".to_string(),
+        });
+        assert!(matches!(
+            audited.setting_path("file"),
+            Err(ConfigError::UnknownSetting(_))
+        ));
+        assert_eq!(audited.setting_path("audit.file").unwrap(), "audit.file");
+
+        let changed = config.with_setting("storage.cache_size", "20").unwrap();
+        assert_eq!(changed.storage.cache_size, 20);
+        assert_eq!(
+            config.reload_changes(&changed).unwrap(),
+            ["storage.cache_size"]
+        );
+        assert!(changed
+            .describe_sources()
+            .contains("storage.cache_size: 20 (SET GLOBAL)\n"));
+        assert!(matches!(
+            config.with_setting("storage.cache_size", "0"),
+            Err(ConfigError::Invalid(violations)) if violations[0].setting == "storage.cache_size"
+        ));
+        assert!(matches!(
+            config.with_setting("storage.cache_size", "[1"),
+            Err(ConfigError::InvalidYaml(_))
+        ));
+    }
+
+    #[test]
+    fn test_sources() {
+        let temp_file = NamedTempFile::new().unwrap();
+        fs::write(
+            &temp_file,
+            "storage: {db_path: /data, page_size: 8192, cache_size: 20}\nserver: {password: hunter2}\n",
+        )
+        .unwrap();
+        let env = [
+            ("FERRODB_STORAGE__CACHE_SIZE", "30"),
+            ("FERRODB_STORAGE__WAL__SEGMENT_SIZE", "1024"),
+            ("FERRODB_ENCRYPTION_KEY", "not a setting"),
+            ("HOME", "/root"),
+        ];
+        let env = env.map(|(name, value)| (name.to_string(), value.to_string()));
+        let overrides = [
+            "storage.cache_size=40".to_string(),
+            "audit={file: /audit.log}".to_string(),
+        ];
+        let config = Config::load(Some(temp_file.path()), None, env, &overrides).unwrap();
+        assert_eq!(config.storage.page_size, 8192);
+        assert_eq!(config.storage.cache_size, 40);
+        assert_eq!(config.storage.wal.as_ref().unwrap().segment_size, 1024);
+        // Sections the file leaves out keep their defaults
+        assert_eq!(config.logging, Config::default().logging);
+        assert_eq!(config.audit.as_ref().unwrap().file, "/audit.log");
+
+        let file = temp_file.path().display();
+        let described = config.describe_sources();
+        for line in [
+            format!("storage.page_size: 8192 (file {})", file),
+            "storage.cache_size: 40 (--set)".to_string(),
+            "storage.wal.segment_size: 1024 (env FERRODB_STORAGE__WAL__SEGMENT_SIZE)".to_string(),
+            "storage.wal.retention_ms: null (default)".to_string(),
+            format!("server.password: ******** (file {})", file),
+            "logging.level: info (default)".to_string(),
+            "audit.file: /audit.log (--set)".to_string(),
+        ] {
+            assert!(
+                described.lines().any(|l| l == line),
+                "{}\n{}",
+                line,
+                described
+            );
+        }
+
+        let bad = |overrides: &[&str]| {
+            let overrides: Vec<String> = overrides.iter().map(|o| o.to_string()).collect();
+            Config::load(None::<&str>, None, [], &overrides).unwrap_err()
+        };
+        assert!(matches!(
+            bad(&["storage.cache_size"]),
+            ConfigError::InvalidOverride(_)
+        ));
+        assert!(matches!(
+            bad(&["storage.cache_size=lots"]),
+            ConfigError::InvalidYaml(_)
+        ));
+        assert!(matches!(
+            bad(&["storage.bogus=1"]),
+            ConfigError::InvalidYaml(_)
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.db_path = dir.path().join("data").to_string_lossy().into_owned();
+        config.logging.file = dir
+            .path()
+            .join("logs/ferrodb.log")
+            .to_string_lossy()
+            .into_owned();
+        config.validate().unwrap();
+
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let overrides = [
+            "storage.page_size=1000".to_string(),
+            "logging.level=loud".to_string(),
+            "storage.fill_factor=5".to_string(),
+            format!("audit.file={}", file.join("audit.log").display()),
+        ];
+        let mut config = Config::load(None::<&str>, None, [], &overrides).unwrap();
+        config.storage.db_path = file.to_string_lossy().into_owned();
+        config.storage.cache_size = 0;
+        let Err(ConfigError::Invalid(violations)) = config.validate() else {
+            panic!("expected the config to be invalid");
+        };
+        let settings: Vec<_> = violations.iter().map(|v| v.setting.as_str()).collect();
+        assert_eq!(
+            settings,
+            [
+                "storage.page_size",
+                "storage.cache_size",
+                "storage.fill_factor",
+                "storage.db_path",
+                "logging.level",
+                "audit.file"
+            ]
+        );
+        assert_eq!(
+            violations[0].to_string(),
+            "storage.page_size (--set) must be a power of two from 512 to 512 MiB"
+        );
+        assert_eq!(violations[1].source, "default");
+    }
+
+    #[test]
+    fn test_limits() {
+        let config_content = r#"
+            storage:
+                db_path: "./data"
+                page_size: 4096
+                cache_size: 10
+            logging:
+                level: "info"
+                file: "./log.log"
+                max_size_mb: 100
+                rotate: true
+                max_files: 5
+            limits:
+                default:
+                    max_connections: 2
+                    max_rows: 1000
+                users:
+                    batch:
+                        max_rows: 1000000
+                        statement_timeout_ms: 60000
+                    report:
+                        max_connections: 0
+        "#;
+        let temp_file = NamedTempFile::new().unwrap();
+        fs::write(&temp_file, config_content).unwrap();
+        let config = Config::new(Some(temp_file.path())).unwrap();
+
+        // A user's own limits are over the default ones
+        let batch = config.limits.for_user("batch");
+        assert_eq!(batch.max_connections, Some(2));
+        assert_eq!(batch.max_rows, Some(1_000_000));
+        assert_eq!(batch.statement_timeout_ms, Some(60_000));
+        assert_eq!(batch.max_query_memory, None);
+        assert_eq!(config.limits.for_user("alice"), config.limits.default);
+        assert_eq!(
+            Config::default().limits.for_user("alice"),
+            UserLimits::default()
+        );
+
+        let Err(ConfigError::Invalid(violations)) = config.validate() else {
+            panic!("expected the config to be invalid");
+        };
+        let settings: Vec<_> = violations.iter().map(|v| v.setting.as_str()).collect();
+        assert!(settings.contains(&"limits.users.report.max_connections"));
+    }
+
+    #[test]
+    fn test_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            (
+                "ferrodb.yaml",
+                "storage: {db_path: /data, page_size: 8192, cache_size: 20}\nserver: {workers: 2}\n",
+            ),
+            (
+                "ferrodb.toml",
+                "[storage]\ndb_path = \"/data\"\npage_size = 8192\ncache_size = 20\n\n[server]\nworkers = 2\n",
+            ),
+            (
+                "ferrodb.json",
+                r#"{"storage": {"db_path": "/data", "page_size": 8192, "cache_size": 20}, "server": {"workers": 2}}"#,
+            ),
+        ];
+        let mut expected = Config::default();
+        expected.storage.db_path = "/data".to_string();
+        expected.storage.page_size = 8192;
+        expected.storage.cache_size = 20;
+        expected.server.workers = 2;
+        for (name, text) in files {
+            let path = dir.path().join(name);
+            fs::write(&path, text).unwrap();
+            assert_eq!(Config::load(Some(&path), None, [], &[]).unwrap(), expected);
+        }
+
+        // Its extension doesn't say, so it's taken as YAML unless told
+        let path = dir.path().join("ferrodb.conf");
+        fs::write(&path, files[1].1).unwrap();
+        let result = Config::load(Some(&path), None, [], &[]);
+        assert!(matches!(result, Err(ConfigError::InvalidYaml(_))));
+        let format = Some(ConfigFormat::parse("TOML").unwrap());
+        assert_eq!(
+            Config::load(Some(&path), format, [], &[]).unwrap(),
+            expected
+        );
+
+        let result = Config::load(Some(&path), Some(ConfigFormat::Json), [], &[]);
+        assert!(matches!(result, Err(ConfigError::InvalidJson(_))));
+        // YAML that isn't JSON isn't read as JSON
+        fs::write(&path, files[0].1).unwrap();
+        let result = Config::load(Some(&path), Some(ConfigFormat::Json), [], &[]);
+        assert!(matches!(result, Err(ConfigError::InvalidJson(_))));
+        assert!(ConfigFormat::parse("ini").is_err());
+    }
+
     #[test]
     fn test_invalid_yaml() {
         let invalid_content = "invalid: yaml: : content";
@@ -138,7 +1566,7 @@ mod tests {
     fn test_unknown_keys() {
         let config_content = r#"
             storage:
-                db_path: "/var/lib/ferrodb/data.fdb"
+                db_path: "/var/lib/ferrodb/data"
                 page_size: 8192
                 unknown_key: "should fail"
             logging:
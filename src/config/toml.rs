@@ -0,0 +1,133 @@
+//! TOML config files, read into the same `Value` a YAML file is, so the
+//! file is layered and checked as one would be. Dates and times are read
+//! as the strings they are written as, as no setting takes one.
+
+use serde_yaml::{Mapping, Number, Value};
+
+/// Read `text`, failing with the line it went wrong on.
+pub(super) fn parse(text: &str) -> Result<Value, String> {
+    let table: ::toml::Table = text
+        .parse()
+        .map_err(|e: ::toml::de::Error| match e.span() {
+            Some(span) => {
+                let line = text[..span.start].matches('\n').count() + 1;
+                format!("line {}: {}", line, e.message().trim_end())
+            }
+            None => e.message().trim_end().to_string(),
+        })?;
+    Ok(value(::toml::Value::Table(table)))
+}
+
+fn value(value: ::toml::Value) -> Value {
+    match value {
+        ::toml::Value::String(s) => Value::String(s),
+        ::toml::Value::Integer(n) => Value::Number(Number::from(n)),
+        ::toml::Value::Float(n) => Value::Number(Number::from(n)),
+        ::toml::Value::Boolean(b) => Value::Bool(b),
+        ::toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        ::toml::Value::Array(values) => {
+            Value::Sequence(values.into_iter().map(self::value).collect())
+        }
+        ::toml::Value::Table(table) => Value::Mapping(
+            table
+                .into_iter()
+                .map(|(key, v)| (Value::String(key), self::value(v)))
+                .collect::<Mapping>(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = r#"
+            # The data
+            [storage]
+            db_path = "/var/lib/ferrodb \"data\""
+            page_size = 8_192
+            wal = { segment_size = 1024, archive_dir = 'C:\archive' }
+            flusher.interval_ms = 100
+
+            [logging]   # where it goes
+            level = "debug"
+            rotate = true
+            ratio = -0.5
+            tags = [
+                "a", "b",  # trailing commas are fine
+            ]
+        "#;
+        let expected: Value = serde_yaml::from_str(
+            r#"
+            storage:
+                db_path: /var/lib/ferrodb "data"
+                page_size: 8192
+                wal: {segment_size: 1024, archive_dir: 'C:\archive'}
+                flusher: {interval_ms: 100}
+            logging:
+                level: debug
+                rotate: true
+                ratio: -0.5
+                tags: [a, b]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(parse(text).unwrap(), expected);
+
+        assert_eq!(
+            parse("a = 1\na = 2").unwrap_err(),
+            "line 2: duplicate key `a` in document root"
+        );
+        assert_eq!(
+            parse("[a]\nb = \"open\nc = 1").unwrap_err(),
+            "line 2: invalid basic string"
+        );
+        assert_eq!(
+            parse("a = 1 b").unwrap_err(),
+            "line 1: expected newline, `#`"
+        );
+    }
+
+    #[test]
+    fn test_parse_all_of_toml() {
+        let text = r#"
+            hex = 0xff
+            octal = 0o17
+            binary = 0b101
+            not_a_number = nan
+            multi_line = """
+            one
+            two"""
+            raw = '''
+            C:\data'''
+            born = 1979-05-27T07:32:00Z
+            day = 1979-05-27
+
+            [[servers]]
+            port = 1
+            [[servers]]
+            port = 2
+        "#;
+        let Value::Mapping(parsed) = parse(text).unwrap() else {
+            panic!("not a table");
+        };
+        let get = |key: &str| parsed.get(key).unwrap().clone();
+        assert_eq!(get("hex"), Value::from(255));
+        assert_eq!(get("octal"), Value::from(15));
+        assert_eq!(get("binary"), Value::from(5));
+        assert!(get("not_a_number").as_f64().unwrap().is_nan());
+        assert_eq!(
+            get("multi_line"),
+            Value::from("            one\n            two")
+        );
+        assert_eq!(get("raw"), Value::from("            C:\\data"));
+        assert_eq!(get("born"), Value::from("1979-05-27T07:32:00Z"));
+        assert_eq!(get("day"), Value::from("1979-05-27"));
+        assert_eq!(
+            get("servers"),
+            serde_yaml::from_str::<Value>("[{port: 1}, {port: 2}]").unwrap()
+        );
+    }
+}
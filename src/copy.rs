@@ -0,0 +1,616 @@
+//! `COPY`: loading tables from CSV or Parquet files, and writing query
+//! results to CSV, JSON or Parquet files.
+//!
+//! A file is read a record at a time and loaded in batches with
+//! `Connection::insert_batch`, so neither the file nor the rows made from
+//! it are ever held whole. Tables have one column of data, so each record
+//! must have exactly one field. Results are written a row at a time as
+//! they are scanned, with both their columns, `id` and `data`.
+
+use crate::database::{Connection, DatabaseError, Row, RowId, TableId};
+use crate::encoding::json_string;
+#[cfg(feature = "parquet")]
+use crate::parquet::{ParquetError, ParquetReader, ParquetWriter};
+use crate::syntax::{CopyFormat, CopyOptions};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Bytes, Write};
+#[cfg(feature = "parquet")]
+use std::io::{Read, Seek};
+use std::iter::Peekable;
+use std::path::Path;
+use thiserror::Error;
+
+/// Records inserted at a time.
+const BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Error)]
+pub(crate) enum CopyError {
+    #[error("could not open \"{path}\": {error}")]
+    Open { path: String, error: io::Error },
+
+    #[error("could not open \"{path}\" for writing: {error}")]
+    Create { path: String, error: io::Error },
+
+    #[error("could not read the file: {0}")]
+    Read(#[from] io::Error),
+
+    #[error("could not write the file: {0}")]
+    Write(io::Error),
+
+    #[error("line {line}: {message}")]
+    BadRecord { line: u64, message: String },
+
+    /// A bad row of a file that isn't made of lines, counted from 1
+    #[cfg(feature = "parquet")]
+    #[error("row {row}: {message}")]
+    BadRow { row: u64, message: String },
+
+    #[cfg(feature = "parquet")]
+    #[error("{0}")]
+    BadFile(String),
+
+    #[error("{0}")]
+    Unsupported(String),
+
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+}
+
+#[cfg(feature = "parquet")]
+impl From<ParquetError> for CopyError {
+    fn from(error: ParquetError) -> Self {
+        match error {
+            ParquetError::Io(error) => CopyError::Read(error),
+            ParquetError::Unsupported(_) => CopyError::Unsupported(error.to_string()),
+            ParquetError::Invalid(_) => CopyError::BadFile(error.to_string()),
+        }
+    }
+}
+
+/// Rows to load, each with where it is in the file, to say if it's bad.
+trait Rows {
+    fn row(&mut self) -> Result<Option<(u64, Vec<u8>)>, CopyError>;
+
+    /// The error for the bad row at `at`.
+    fn bad(&self, at: u64, message: String) -> CopyError;
+}
+
+/// A record's fields, and the line it starts on.
+pub(crate) type Record = (u64, Vec<Vec<u8>>);
+
+/// Reads the records of a CSV file: fields separated by a delimiter,
+/// quoted if they hold one, a quote or a line break, and records
+/// separated by `\n` or `\r\n`.
+pub(crate) struct CsvReader<R: BufRead> {
+    bytes: Peekable<Bytes<R>>,
+    delimiter: u8,
+    quote: u8,
+    escape: u8,
+    /// The line read up to
+    line: u64,
+}
+
+impl<R: BufRead> CsvReader<R> {
+    pub(crate) fn new(input: R, options: &CopyOptions) -> Self {
+        Self {
+            bytes: input.bytes().peekable(),
+            delimiter: options.delimiter,
+            quote: options.quote,
+            escape: options.escape,
+            line: 1,
+        }
+    }
+
+    /// The next record and the line it starts on, or `None` at the end.
+    pub(crate) fn record(&mut self) -> Result<Option<Record>, CopyError> {
+        if self.peek()?.is_none() {
+            return Ok(None);
+        }
+        let line = self.line;
+        let mut fields = Vec::new();
+        loop {
+            let (field, end) = self.field(line)?;
+            fields.push(field);
+            if end != Some(self.delimiter) {
+                return Ok(Some((line, fields)));
+            }
+        }
+    }
+
+    /// The next field, and what ended it: the delimiter, a line break, or
+    /// `None` for the end of the file.
+    fn field(&mut self, line: u64) -> Result<(Vec<u8>, Option<u8>), CopyError> {
+        let mut field = Vec::new();
+        if self.peek()? == Some(self.quote) {
+            self.next()?;
+            loop {
+                match self.next()? {
+                    Some(byte) if byte == self.escape && self.escaped()? => {
+                        field.extend(self.next()?);
+                    }
+                    Some(byte) if byte == self.quote => break,
+                    Some(byte) => field.push(byte),
+                    None => {
+                        return Err(CopyError::BadRecord {
+                            line,
+                            message: "unterminated quoted field".to_string(),
+                        })
+                    }
+                }
+            }
+            return match self.end()? {
+                Some(end) => Ok((field, end)),
+                None => Err(CopyError::BadRecord {
+                    line: self.line,
+                    message: "unexpected character after a quoted field".to_string(),
+                }),
+            };
+        }
+        loop {
+            if let Some(end) = self.end()? {
+                return Ok((field, end));
+            }
+            field.extend(self.next()?);
+        }
+    }
+
+    /// Consume the end of a field if it comes next, returning what ended
+    /// it, or `None` if it doesn't.
+    fn end(&mut self) -> Result<Option<Option<u8>>, CopyError> {
+        match self.peek()? {
+            None => Ok(Some(None)),
+            Some(byte) if byte == self.delimiter => {
+                self.next()?;
+                Ok(Some(Some(byte)))
+            }
+            Some(b'\n') => {
+                self.next()?;
+                Ok(Some(Some(b'\n')))
+            }
+            Some(b'\r') => {
+                self.next()?;
+                if self.peek()? == Some(b'\n') {
+                    self.next()?;
+                }
+                Ok(Some(Some(b'\n')))
+            }
+            Some(_) => Ok(None),
+        }
+    }
+
+    /// Whether a quote or escape comes next, which an escape before it
+    /// in a quoted field stands for.
+    fn escaped(&mut self) -> Result<bool, CopyError> {
+        let next = self.peek()?;
+        Ok(next == Some(self.quote) || next == Some(self.escape))
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, CopyError> {
+        match self.bytes.peek() {
+            None => Ok(None),
+            Some(Ok(byte)) => Ok(Some(*byte)),
+            Some(Err(_)) => Err(self.bytes.next().unwrap().unwrap_err().into()),
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<u8>, CopyError> {
+        let byte = self.bytes.next().transpose()?;
+        // A lone `\r` ends a line too, but `\r\n` is one line break
+        if byte == Some(b'\n') || (byte == Some(b'\r') && self.peek()? != Some(b'\n')) {
+            self.line += 1;
+        }
+        Ok(byte)
+    }
+}
+
+impl<R: BufRead> Rows for CsvReader<R> {
+    fn row(&mut self) -> Result<Option<(u64, Vec<u8>)>, CopyError> {
+        let Some((line, mut fields)) = self.record()? else {
+            return Ok(None);
+        };
+        if fields.len() != 1 {
+            let message = format!("expected 1 field, found {}", fields.len());
+            return Err(self.bad(line, message));
+        }
+        Ok(Some((line, fields.remove(0))))
+    }
+
+    fn bad(&self, line: u64, message: String) -> CopyError {
+        CopyError::BadRecord { line, message }
+    }
+}
+
+/// The values of a Parquet file's column, numbered from 1.
+#[cfg(feature = "parquet")]
+struct ParquetRows<R: Read + Seek> {
+    reader: ParquetReader<R>,
+    row: u64,
+}
+
+#[cfg(feature = "parquet")]
+impl<R: Read + Seek> Rows for ParquetRows<R> {
+    fn row(&mut self) -> Result<Option<(u64, Vec<u8>)>, CopyError> {
+        let Some(value) = self.reader.value()? else {
+            return Ok(None);
+        };
+        self.row += 1;
+        match value {
+            Some(value) => Ok(Some((self.row, value))),
+            None => Err(self.bad(self.row, "the value is null".to_string())),
+        }
+    }
+
+    fn bad(&self, row: u64, message: String) -> CopyError {
+        CopyError::BadRow { row, message }
+    }
+}
+
+/// Fail unless this build can read and write files in the format
+/// `options` give.
+fn check_format(options: &CopyOptions) -> Result<(), CopyError> {
+    if cfg!(not(feature = "parquet")) && options.format == CopyFormat::Parquet {
+        return Err(CopyError::Unsupported(
+            "COPY with Parquet files needs ferrodb built with the parquet feature".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Load the file at `path` into `table`, all of it or, if a record is
+/// bad, none, returning the number of rows loaded.
+pub(crate) fn copy_from(
+    connection: &mut Connection,
+    table: TableId,
+    path: &Path,
+    options: &CopyOptions,
+) -> Result<u64, CopyError> {
+    check_format(options)?;
+    let file = File::open(path).map_err(|error| CopyError::Open {
+        path: path.display().to_string(),
+        error,
+    })?;
+    let mut rows: Box<dyn Rows> = match options.format {
+        CopyFormat::Csv => {
+            let mut reader = CsvReader::new(BufReader::new(file), options);
+            if options.header {
+                reader.record()?;
+            }
+            Box::new(reader)
+        }
+        #[cfg(feature = "parquet")]
+        CopyFormat::Parquet => Box::new(ParquetRows {
+            reader: ParquetReader::open(BufReader::new(file))?,
+            row: 0,
+        }),
+        format => unreachable!("COPY FROM doesn't parse for {:?}", format),
+    };
+
+    let implicit = !connection.in_transaction();
+    if implicit {
+        connection.begin()?;
+    }
+    let loaded = load(connection, table, rows.as_mut());
+    match loaded {
+        Ok(_) if implicit => connection.commit()?,
+        Err(_) if implicit => connection.rollback()?,
+        _ => {}
+    }
+    loaded
+}
+
+fn load(
+    connection: &mut Connection,
+    table: TableId,
+    rows: &mut dyn Rows,
+) -> Result<u64, CopyError> {
+    let mut count = 0;
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut positions = Vec::with_capacity(BATCH_SIZE);
+    loop {
+        let row = rows.row()?;
+        let done = row.is_none();
+        if let Some((at, row)) = row {
+            batch.push(row);
+            positions.push(at);
+        }
+        if batch.len() == BATCH_SIZE || (done && !batch.is_empty()) {
+            match connection.insert_batch(table, &batch) {
+                Ok(ids) => count += ids.len() as u64,
+                // Rows are checked in order before any goes in, so the
+                // first of its size failed
+                Err(e @ DatabaseError::RowTooLarge { size, .. }) => {
+                    let at = batch.iter().position(|row| row.len() == size).unwrap();
+                    return Err(rows.bad(positions[at], e.to_string()));
+                }
+                Err(e) => return Err(e.into()),
+            }
+            batch.clear();
+            positions.clear();
+        }
+        if done {
+            return Ok(count);
+        }
+    }
+}
+
+/// Write the rows of `table`, or only `row` if given, to the file at
+/// `path`, returning how many there were.
+pub(crate) fn copy_to(
+    connection: &mut Connection,
+    table: TableId,
+    row: Option<RowId>,
+    path: &Path,
+    options: &CopyOptions,
+) -> Result<u64, CopyError> {
+    check_format(options)?;
+    let file = File::create(path).map_err(|error| CopyError::Create {
+        path: path.display().to_string(),
+        error,
+    })?;
+    let out = BufWriter::new(file);
+    let (count, mut out) = match options.format {
+        #[cfg(feature = "parquet")]
+        CopyFormat::Parquet => {
+            let mut writer = ParquetWriter::new(out).map_err(CopyError::Write)?;
+            let count = each_row(connection, table, row, |row| {
+                let id = row.id.to_string();
+                writer
+                    .row(id.as_bytes(), &row.data)
+                    .map_err(CopyError::Write)
+            })?;
+            (count, writer.finish().map_err(CopyError::Write)?)
+        }
+        _ => {
+            let mut writer = RowWriter { out, options };
+            if options.format == CopyFormat::Csv && options.header {
+                writer.csv(&[b"id", b"data"]).map_err(CopyError::Write)?;
+            }
+            let count = each_row(connection, table, row, |row| {
+                writer.row(&row).map_err(CopyError::Write)
+            })?;
+            (count, writer.out)
+        }
+    };
+    out.flush().map_err(CopyError::Write)?;
+    Ok(count)
+}
+
+/// Pass the rows of `table`, or only `row` if given, to `each`, returning
+/// how many there were.
+fn each_row(
+    connection: &mut Connection,
+    table: TableId,
+    row: Option<RowId>,
+    mut each: impl FnMut(Row) -> Result<(), CopyError>,
+) -> Result<u64, CopyError> {
+    let mut count = 0;
+    let mut each = |row| {
+        count += 1;
+        each(row)
+    };
+    match row {
+        Some(row) => connection.get(row)?.map_or(Ok(()), &mut each)?,
+        None => connection.scan_each(table, &mut each)?,
+    }
+    Ok(count)
+}
+
+struct RowWriter<'a, W: Write> {
+    out: W,
+    options: &'a CopyOptions,
+}
+
+impl<W: Write> RowWriter<'_, W> {
+    fn row(&mut self, row: &Row) -> io::Result<()> {
+        let id = row.id.to_string();
+        match self.options.format {
+            CopyFormat::Csv => self.csv(&[id.as_bytes(), &row.data]),
+            CopyFormat::Json => writeln!(
+                self.out,
+                "{{\"id\":{},\"data\":{}}}",
+                json_string(&id),
+                json_string(&String::from_utf8_lossy(&row.data))
+            ),
+            CopyFormat::Parquet => unreachable!("Parquet is written by ParquetWriter"),
+        }
+    }
+
+    /// A CSV line, quoting fields that hold the delimiter, the quote or a
+    /// line break, and escaping any quote or escape in them.
+    fn csv(&mut self, fields: &[&[u8]]) -> io::Result<()> {
+        let CopyOptions {
+            delimiter,
+            quote,
+            escape,
+            ..
+        } = *self.options;
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                self.out.write_all(&[delimiter])?;
+            }
+            if !field
+                .iter()
+                .any(|&byte| matches!(byte, b'\r' | b'\n') || byte == delimiter || byte == quote)
+            {
+                self.out.write_all(field)?;
+                continue;
+            }
+            let mut quoted = vec![quote];
+            for &byte in *field {
+                if byte == quote || byte == escape {
+                    quoted.push(escape);
+                }
+                quoted.push(byte);
+            }
+            quoted.push(quote);
+            self.out.write_all(&quoted)?;
+        }
+        self.out.write_all(b"\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_config;
+    use crate::database::Database;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_copy_from() {
+        let records = |text: &str, delimiter| {
+            let options = CopyOptions {
+                delimiter,
+                ..CopyOptions::default()
+            };
+            let mut reader = CsvReader::new(Cursor::new(text.as_bytes().to_vec()), &options);
+            let mut records = Vec::new();
+            while let Some((line, fields)) = reader.record()? {
+                let fields: Vec<String> = fields
+                    .into_iter()
+                    .map(|field| String::from_utf8(field).unwrap())
+                    .collect();
+                records.push((line, fields));
+            }
+            Ok::<_, CopyError>(records)
+        };
+        assert_eq!(
+            records("a,b\r\n\"c,\"\"d\"\"\",\n\"two\nlines\"\n\nlast", b',').unwrap(),
+            vec![
+                (1, vec!["a".to_string(), "b".to_string()]),
+                (2, vec!["c,\"d\"".to_string(), "".to_string()]),
+                (3, vec!["two\nlines".to_string()]),
+                (5, vec!["".to_string()]),
+                (6, vec!["last".to_string()]),
+            ]
+        );
+        assert_eq!(
+            records("a;b", b';').unwrap(),
+            vec![(1, vec!["a".to_string(), "b".to_string()])]
+        );
+        assert!(matches!(
+            records("ok\n\"open", b','),
+            Err(CopyError::BadRecord { line: 2, .. })
+        ));
+        assert!(matches!(
+            records("\"x\"y", b','),
+            Err(CopyError::BadRecord { line: 1, .. })
+        ));
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(&dir.path().join("db"));
+        config.storage.page_size = 128;
+        let database = Database::with_config(&config).unwrap();
+        let table = database.create_table().unwrap();
+        let mut connection = database.connect();
+
+        let path = dir.path().join("in.csv");
+        let mut text = "data\n".to_string();
+        for i in 0..2500 {
+            text.push_str(&format!("\"row {}\"\n", i));
+        }
+        std::fs::write(&path, &text).unwrap();
+        let options = CopyOptions {
+            header: true,
+            ..CopyOptions::default()
+        };
+        assert_eq!(
+            copy_from(&mut connection, table, &path, &options).unwrap(),
+            2500
+        );
+        let rows = connection.scan(table).unwrap();
+        assert_eq!(rows.len(), 2500);
+        assert_eq!(rows[2499].data, b"row 2499");
+
+        // A bad record loads nothing, and says where it is
+        std::fs::write(&path, "fine\nfine\ntoo,many\n").unwrap();
+        let error = copy_from(&mut connection, table, &path, &CopyOptions::default());
+        assert!(matches!(error, Err(CopyError::BadRecord { line: 3, .. })));
+        std::fs::write(&path, format!("fine\n{}\n", "x".repeat(200))).unwrap();
+        let error = copy_from(&mut connection, table, &path, &CopyOptions::default());
+        assert!(matches!(error, Err(CopyError::BadRecord { line: 2, .. })));
+        assert_eq!(connection.scan(table).unwrap().len(), 2500);
+    }
+
+    #[test]
+    fn test_copy_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(&dir.path().join("db"));
+        config.storage.page_size = 128;
+        let database = Database::with_config(&config).unwrap();
+        let table = database.create_table().unwrap();
+        let mut connection = database.connect();
+        let ids = connection
+            .insert_batch(table, &["plain", "a,b", "say \"hi\\\"", "two\nlines"])
+            .unwrap();
+
+        let path = dir.path().join("out");
+        let mut copy = |row, options: CopyOptions| {
+            let count = copy_to(&mut connection, table, row, &path, &options).unwrap();
+            (count, std::fs::read_to_string(&path).unwrap())
+        };
+        let options = CopyOptions {
+            header: true,
+            ..CopyOptions::default()
+        };
+        assert_eq!(
+            copy(None, options),
+            (
+                4,
+                concat!(
+                    "id,data\n",
+                    "1:0:0,plain\n",
+                    "1:0:1,\"a,b\"\n",
+                    "1:0:2,\"say \"\"hi\\\"\"\"\n",
+                    "1:0:3,\"two\nlines\"\n",
+                )
+                .to_string()
+            )
+        );
+        let options = CopyOptions {
+            delimiter: b';',
+            escape: b'\\',
+            ..CopyOptions::default()
+        };
+        assert_eq!(
+            copy(Some(ids[2]), options.clone()),
+            (1, "1:0:2;\"say \\\"hi\\\\\\\"\"\n".to_string())
+        );
+        // What's written reads back the same
+        let text = copy(None, options.clone()).1;
+        let mut reader = CsvReader::new(Cursor::new(text.into_bytes()), &options);
+        assert_eq!(reader.record().unwrap().unwrap().1[1], b"plain");
+        assert_eq!(reader.record().unwrap().unwrap().1[1], b"a,b");
+        assert_eq!(reader.record().unwrap().unwrap().1[1], b"say \"hi\\\"");
+        let options = CopyOptions {
+            format: CopyFormat::Json,
+            ..CopyOptions::default()
+        };
+        assert_eq!(
+            copy(Some(ids[3]), options).1,
+            "{\"id\":\"1:0:3\",\"data\":\"two\\nlines\"}\n"
+        );
+
+        // Parquet files load back what was written, given the feature
+        let options = CopyOptions {
+            format: CopyFormat::Parquet,
+            ..CopyOptions::default()
+        };
+        let copied = copy_to(&mut connection, table, None, &path, &options);
+        if cfg!(not(feature = "parquet")) {
+            assert!(matches!(copied, Err(CopyError::Unsupported(_))));
+            return;
+        }
+        assert_eq!(copied.unwrap(), 4);
+        let loaded = database.create_table().unwrap();
+        assert_eq!(
+            copy_from(&mut connection, loaded, &path, &options).unwrap(),
+            4
+        );
+        let mut data = |table| {
+            let rows = connection.scan(table).unwrap();
+            rows.into_iter().map(|row| row.data).collect::<Vec<_>>()
+        };
+        assert_eq!(data(loaded), data(table));
+    }
+}
@@ -0,0 +1,210 @@
+//! Cursors, which hand out the rows of a query a `FETCH` at a time. A
+//! cursor over a table's rows in the order they're stored reads them as
+//! they're fetched, carrying on after the last row it handed out, so a
+//! client can page through a table of any size; one over rows that have
+//! to be sorted, looked up or joined first has them worked out when it's
+//! declared.
+
+use crate::database::{Connection, RowId, TableId};
+use crate::sql::{text, SqlError, StatementResult};
+use std::collections::VecDeque;
+
+pub(crate) enum Cursor {
+    /// The rows of `table` after `after`, read as they're fetched
+    Scan {
+        table: TableId,
+        after: Option<RowId>,
+        done: bool,
+    },
+    /// The rows of a query, worked out when the cursor was declared
+    Rows {
+        columns: Vec<String>,
+        rows: VecDeque<Vec<Option<String>>>,
+    },
+}
+
+impl Cursor {
+    pub(crate) fn scan(table: TableId) -> Self {
+        Self::Scan {
+            table,
+            after: None,
+            done: false,
+        }
+    }
+
+    pub(crate) fn rows(result: StatementResult) -> Self {
+        Self::Rows {
+            columns: result.columns,
+            rows: result.rows.into(),
+        }
+    }
+
+    /// The next `count` rows, or all that are left for `None`, with the
+    /// columns the query returns them in.
+    pub(crate) fn fetch(
+        &mut self,
+        connection: &mut Connection,
+        count: Option<u64>,
+    ) -> Result<StatementResult, SqlError> {
+        let limit = count.map_or(usize::MAX, |count| {
+            usize::try_from(count).unwrap_or(usize::MAX)
+        });
+        let (columns, rows): (_, Vec<_>) = match self {
+            Self::Scan { table, after, done } => {
+                let rows = if *done {
+                    Vec::new()
+                } else {
+                    connection.scan_after(*table, *after, limit)?
+                };
+                *done |= rows.len() < limit;
+                if let Some(last) = rows.last() {
+                    *after = Some(last.id);
+                }
+                let rows = rows.into_iter().map(text).collect();
+                (vec!["id".to_string(), "data".to_string()], rows)
+            }
+            Self::Rows { columns, rows } => {
+                let taken = rows.drain(..limit.min(rows.len())).collect();
+                (columns.clone(), taken)
+            }
+        };
+        Ok(StatementResult {
+            tag: format!("FETCH {}", rows.len()),
+            columns,
+            rows,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::tests::{temp_database, test_config};
+    use crate::database::Database;
+    use crate::partition::{PartitionScheme, Partitioning};
+    use crate::sql::{SqlError, SqlSession, StatementResult};
+    use crate::table_options::TableOptions;
+
+    #[test]
+    fn test_cursors() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        config.storage.page_size = 128;
+        let database = Database::with_config(&config).unwrap();
+        let table = database.create_table().unwrap();
+        let mut session = SqlSession::new(database.connect());
+        let mut run = |sql: &str| -> Result<StatementResult, SqlError> {
+            session.execute(sql).pop().unwrap()
+        };
+        for n in 0..30 {
+            run(&format!(
+                r#"INSERT INTO {table} VALUES ('{{"n": {}}}')"#,
+                n % 7
+            ))
+            .unwrap();
+        }
+        let data = |result: StatementResult| -> Vec<String> {
+            result
+                .rows
+                .into_iter()
+                .map(|mut row| row.pop().unwrap().unwrap())
+                .collect()
+        };
+        let all = data(run(&format!("SELECT * FROM {table}")).unwrap());
+        let scanned = || {
+            let mut stats = database.table_stats().into_iter();
+            stats.find(|(id, _)| *id == table).unwrap().1.rows_scanned
+        };
+
+        // Cursors belong to a transaction
+        let declare = format!("DECLARE c CURSOR FOR SELECT * FROM {table}");
+        assert_eq!(run(&declare).unwrap_err().code(), "25P01");
+        run("BEGIN").unwrap();
+        assert_eq!(run(&declare).unwrap().tag, "DECLARE CURSOR");
+        assert_eq!(run(&declare).unwrap_err().code(), "42P03");
+
+        // A table's rows are read as they're fetched, carrying on from
+        // the last
+        let before = scanned();
+        let first = run("FETCH 4 FROM c").unwrap();
+        assert_eq!(first.columns, ["id", "data"]);
+        assert_eq!(first.tag, "FETCH 4");
+        assert_eq!(scanned() - before, 4);
+        let mut fetched = data(first);
+        fetched.extend(data(run("FETCH NEXT FROM c").unwrap()));
+        let rest = run("FETCH ALL FROM c").unwrap();
+        assert_eq!(rest.tag, "FETCH 25");
+        fetched.extend(data(rest));
+        assert_eq!(fetched, all);
+        assert_eq!(run("FETCH 10 FROM c").unwrap().tag, "FETCH 0");
+
+        // Other queries' rows are worked out up front
+        let sorted = format!("SELECT * FROM {table} WHERE n = '3' ORDER BY id DESC");
+        let found = data(run(&sorted).unwrap());
+        run(&format!("DECLARE found CURSOR FOR {sorted}")).unwrap();
+        let mut fetched = data(run("FETCH 3 FROM found").unwrap());
+        fetched.extend(data(run("FETCH 3 FROM found").unwrap()));
+        assert_eq!(fetched, found);
+        let join = format!("SELECT * FROM {table} JOIN {table} ON id = id");
+        run(&format!("DECLARE joined CURSOR FOR {join}")).unwrap();
+        assert_eq!(run("FETCH 2 FROM joined").unwrap().columns.len(), 4);
+
+        assert_eq!(run("CLOSE found").unwrap().tag, "CLOSE CURSOR");
+        assert_eq!(run("FETCH found").unwrap_err().code(), "34000");
+        assert_eq!(run("CLOSE found").unwrap_err().code(), "34000");
+        assert_eq!(run("CLOSE ALL").unwrap().tag, "CLOSE CURSOR ALL");
+        assert_eq!(run("FETCH joined").unwrap_err().code(), "34000");
+
+        // And close when it ends
+        run(&declare).unwrap();
+        run("COMMIT").unwrap();
+        assert_eq!(run("FETCH c").unwrap_err().code(), "34000");
+
+        // A partitioned table's rows are paged through a partition at a
+        // time
+        let partitioned = database
+            .create_partitioned_table(
+                TableOptions::default(),
+                Partitioning {
+                    column: Some("n".to_string()),
+                    scheme: PartitionScheme::Hash(3),
+                },
+            )
+            .unwrap();
+        let mut connection = database.connect();
+        for n in 0..20 {
+            let row = format!(r#"{{"n": {}}}"#, n);
+            connection.insert(partitioned, row.as_bytes()).unwrap();
+        }
+        let mut after = None;
+        let mut paged = Vec::new();
+        loop {
+            let rows = connection.scan_after(partitioned, after, 3).unwrap();
+            let Some(last) = rows.last() else {
+                break;
+            };
+            after = Some(last.id);
+            paged.extend(rows);
+        }
+        assert_eq!(paged, connection.scan(partitioned).unwrap());
+    }
+    #[test]
+    fn test_cursor_privileges() {
+        let (_dir, database) = temp_database();
+        let table = database.create_table().unwrap();
+        database.create_user("root", "pw", true).unwrap();
+        database.create_user("alice", "pw", false).unwrap();
+        let mut session = SqlSession::for_user(database.connect(), "alice");
+        session.execute("BEGIN").pop().unwrap().unwrap();
+
+        // A cursor's query is held to the same privileges as run alone,
+        // whether its rows are read as fetched or up front
+        for query in [
+            format!("SELECT * FROM {table}"),
+            format!("SELECT * FROM {table} ORDER BY data"),
+        ] {
+            let declare = format!("DECLARE c CURSOR FOR {query}");
+            let error = session.execute(&declare).pop().unwrap().unwrap_err();
+            assert_eq!(error.code(), "42501");
+        }
+    }
+}
@@ -0,0 +1,2536 @@
+//! The embedded API: open a database in a directory and read and write
+//! records through connections.
+
+use crate::activity::Activity;
+use crate::audit::{AuditEvent, AuditLog};
+use crate::config::{Config, ConfigError, UserLimits, WalConfig};
+use crate::external::ExternalTable;
+use crate::function::Functions;
+use crate::index::{BloomFilter, FullTextIndex, UniqueIndex};
+use crate::logging::{self, log, span, LogLevel, LoggingError, Span};
+use crate::metrics::{Metrics, QueryCounters, TableCounters, TableStats};
+use crate::overflow::{self, Stub};
+use crate::partition::Partitions;
+use crate::spill::TempSpace;
+use crate::statistics::TableStatistics;
+use crate::storage::{
+    self, AsOf, BufferStats, ChangeEvent, ChangeStream, FaultInjector, FileId, FsckReport,
+    LockMode, LockTarget, Lsn, Page, PageDecodeError, PageIOError, PageId, PageManager,
+    PageManagerBuilder, PageManagerError, Record, RecordKind, RowChange, SlotId, SlottedPage,
+    Transaction, TransactionError, TransactionInfo, TransactionManager,
+};
+use crate::table_options::TableOptions;
+use crate::trigger::Trigger;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::io;
+use std::net::SocketAddr;
+use std::ops::{ControlFlow, Range};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DatabaseError {
+    #[error("Page manager error: {0}")]
+    PageManagerError(#[from] PageManagerError),
+
+    #[error("Transaction error: {0}")]
+    TransactionError(#[from] TransactionError),
+
+    #[error("Page error: {0}")]
+    PageDecodeError(#[from] PageDecodeError),
+
+    #[error("No table {0}")]
+    NoSuchTable(TableId),
+
+    #[error("No row {0}")]
+    NoSuchRow(RowId),
+
+    #[error("Invalid row id {0:?}")]
+    InvalidRowId(String),
+
+    /// `column` is the row's largest column and its size, if it is a JSON
+    /// object whose text values didn't free up enough room moved out
+    #[error("A row of {size} bytes doesn't fit in the {max} a page has room for{}", column_note(.column))]
+    RowTooLarge {
+        size: usize,
+        max: usize,
+        column: Option<(String, usize)>,
+    },
+
+    #[error("A transaction is already in progress")]
+    TransactionInProgress,
+
+    #[error("No transaction is in progress")]
+    NoTransaction,
+
+    #[error("User {0} already exists")]
+    UserExists(String),
+
+    #[error("No user {0}")]
+    NoSuchUser(String),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("Cancelled")]
+    Cancelled,
+
+    #[error("Operation panicked: {0}")]
+    Panicked(String),
+
+    #[error("Fill factor {0} isn't from 10 to 100")]
+    InvalidFillFactor(u8),
+
+    #[error("Trigger {0} already exists")]
+    TriggerExists(String),
+
+    #[error("No trigger {0}")]
+    NoSuchTrigger(String),
+
+    #[error("Index {0} already exists")]
+    IndexExists(String),
+
+    #[error("No index {0}")]
+    NoSuchIndex(String),
+
+    #[error("Duplicate key {key} violates unique constraint {constraint}")]
+    UniqueViolation { constraint: String, key: String },
+
+    #[error("Row has no key for primary key {0}")]
+    MissingKey(String),
+
+    #[error("Table {0} already has a primary key")]
+    PrimaryKeyExists(TableId),
+
+    #[error("Table {0} has no full-text index")]
+    NoFullTextIndex(TableId),
+
+    #[error("Invalid partitioning: {0}")]
+    InvalidPartitioning(String),
+
+    #[error("Row {0} would move to another partition")]
+    PartitionKeyChanged(RowId),
+
+    #[error("Table {0} is a partition, so its rows are written through its table")]
+    PartitionWrite(TableId),
+
+    #[error("Catalog record is corrupted")]
+    CorruptedCatalog,
+
+    #[error("Table {0} is external, so its rows can't be changed")]
+    ReadOnlyTable(TableId),
+
+    #[error("External table {table}: {message}")]
+    External { table: TableId, message: String },
+
+    #[error("Could not open the audit log: {0}")]
+    AuditLog(io::Error),
+
+    #[error("Could not use a temporary file: {0}")]
+    Spill(io::Error),
+
+    #[error("Temporary files would take up more than {0} bytes")]
+    TempSpaceFull(u64),
+
+    #[error("Configuration error: {0}")]
+    Config(#[from] ConfigError),
+
+    #[error("Logging error: {0}")]
+    Logging(#[from] LoggingError),
+}
+
+fn column_note(column: &Option<(String, usize)>) -> String {
+    match column {
+        Some((name, size)) => format!("; column \"{}\" alone takes {}", name, size),
+        None => String::new(),
+    }
+}
+
+/// Identifies a table, which keeps its rows in a file of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TableId(pub u32);
+
+impl TableId {
+    /// The database's own records, such as its users, kept in the catalog
+    /// file after the superblock. Only the crate's system connections can
+    /// reach it.
+    pub(crate) const CATALOG: TableId = TableId(FileId::CATALOG.0);
+}
+
+impl Display for TableId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Identifies a row: its table, and the page and slot holding it. A row
+/// keeps its id until it is deleted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RowId {
+    pub table: TableId,
+    pub page_no: u64,
+    pub slot: u32,
+}
+
+impl Display for RowId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.table, self.page_no, self.slot)
+    }
+}
+
+impl FromStr for RowId {
+    type Err = DatabaseError;
+
+    /// Parse a row id in the form it is displayed in, `table:page:slot`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DatabaseError::InvalidRowId(s.to_string());
+        let mut parts = s.split(':');
+        let mut next = || parts.next().ok_or_else(invalid);
+        let (table, page_no, slot) = (next()?, next()?, next()?);
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            table: TableId(table.parse().map_err(|_| invalid())?),
+            page_no: page_no.parse().map_err(|_| invalid())?,
+            slot: slot.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// A row read from a table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub id: RowId,
+    pub data: Vec<u8>,
+}
+
+/// How far `Database::restore` replays the archived log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreTarget {
+    /// Up to the transactions that committed at or before this time
+    Time(SystemTime),
+    /// Up to just before the record at this LSN, as `Database::backup`
+    /// returns them
+    Lsn(u64),
+}
+
+impl RestoreTarget {
+    /// The target at the time `text` gives, as `SELECT ... AS OF
+    /// TIMESTAMP` takes it.
+    pub fn parse_time(text: &str) -> Option<Self> {
+        logging::Timestamp::parse(text.trim()).map(RestoreTarget::Time)
+    }
+}
+
+/// A change to a row by a committed transaction, as `Database::changes`
+/// reads it from the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub row: RowId,
+    /// The LSN of the transaction's commit, which orders it among others
+    pub lsn: u64,
+    /// When the transaction committed
+    pub time: SystemTime,
+    /// The row before the change, or `None` if it was inserted
+    pub before: Option<Vec<u8>>,
+    /// The row after the change, or `None` if it was deleted
+    pub after: Option<Vec<u8>>,
+}
+
+/// The changes `Database::changes` reads, following the log: `next`
+/// returns `None` once they have caught up with it, and can be called
+/// again later for newer commits.
+pub struct Changes<'a> {
+    database: &'a Database,
+    stream: ChangeStream<'a>,
+}
+
+impl Changes<'_> {
+    fn change(&self, event: ChangeEvent) -> Result<Change, DatabaseError> {
+        let table = TableId(event.page_id.file.0);
+        let (before, after) = match event.change {
+            RowChange::Insert { after } => (None, Some(after)),
+            RowChange::Update { before, after } => (Some(before), Some(after)),
+            RowChange::Delete { before } => (Some(before), None),
+        };
+        // A spilled row's values are in overflow records on other pages,
+        // read as they were on that side of the commit
+        let pages = self.database.pages();
+        let cancelled = AtomicBool::new(false);
+        let data = |record: Option<Record>, as_of| match record {
+            Some(Record {
+                kind: RecordKind::Spilled,
+                data,
+            }) => {
+                let old = |page_no| read_as_of(pages, &cancelled, page_id(table, page_no), as_of);
+                join_spilled(&data, old).map(Some)
+            }
+            record => Ok(record.map(|record| record.data)),
+        };
+        Ok(Change {
+            row: RowId {
+                table,
+                page_no: event.page_id.page_no,
+                slot: event.slot.0,
+            },
+            lsn: event.commit_lsn.0,
+            time: SystemTime::UNIX_EPOCH + Duration::from_micros(event.commit_time),
+            before: data(before, AsOf::Lsn(event.commit_lsn))?,
+            after: data(after, AsOf::Lsn(Lsn(event.commit_lsn.0 + 1)))?,
+        })
+    }
+}
+
+impl Iterator for Changes<'_> {
+    type Item = Result<Change, DatabaseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let event = match self.stream.next()? {
+                Ok(event) => event,
+                Err(e) => return Some(Err(PageManagerError::from(e).into())),
+            };
+            // The catalog's records describe tables, not rows of one
+            if event.page_id.file != FileId::CATALOG {
+                return Some(self.change(event));
+            }
+        }
+    }
+}
+
+/// A database opened from a directory, to be shared by the connections
+/// using it. Changes are logged, so the database recovers from a crash
+/// the next time it is opened.
+pub struct Database {
+    transactions: TransactionManager,
+    /// The catalog's external tables, read when the database opens
+    external: RwLock<HashMap<TableId, Arc<ExternalTable>>>,
+    /// The catalog's table options, read when the database opens
+    pub(crate) table_options: RwLock<HashMap<TableId, TableOptions>>,
+    /// `storage.fill_factor`, for tables that don't give their own
+    pub(crate) fill_factor: u8,
+    /// The catalog's triggers in name order, read when the database opens
+    pub(crate) triggers: RwLock<Vec<Trigger>>,
+    /// The functions the embedder registered for SQL to call
+    pub(crate) functions: RwLock<Functions>,
+    /// The catalog's full-text indexes, built when the database opens
+    pub(crate) fulltext: RwLock<Vec<FullTextIndex>>,
+    /// The catalog's unique indexes, built when the database opens
+    pub(crate) unique: RwLock<Vec<UniqueIndex>>,
+    /// A Bloom filter of each page of the tables stored with them, by
+    /// page number
+    pub(crate) bloom_filters: RwLock<HashMap<TableId, Vec<BloomFilter>>>,
+    /// The catalog's partitioned tables, read when the database opens
+    pub(crate) partitions: RwLock<HashMap<TableId, Partitions>>,
+    /// What `ANALYZE` last found of each table analyzed since opening
+    pub(crate) statistics: RwLock<HashMap<TableId, TableStatistics>>,
+    /// The committed rows of each table counted since opening, kept up to
+    /// date as transactions writing to it commit
+    row_counts: RwLock<HashMap<TableId, u64>>,
+    /// `storage.approximate_counts`
+    approximate_counts: bool,
+    /// Where sorts and joins spill to, with `storage.spill`
+    temp_space: Option<TempSpace>,
+    queries: QueryCounters,
+    /// The reads and writes of each table read or written since opening
+    table_counters: RwLock<HashMap<TableId, TableCounters>>,
+    audit: Option<AuditLog>,
+    activity: Activity,
+    /// The configuration in use, as opened or last reloaded
+    config: Mutex<Config>,
+    /// `logging.slow_query_ms`, or `u64::MAX` when absent
+    slow_query_ms: AtomicU64,
+}
+
+impl Database {
+    /// Open the database in `path`, creating it if needed, with the default
+    /// configuration and the write-ahead log enabled.
+    /// A `path` of `IN_MEMORY` keeps it in memory until it's dropped.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DatabaseError> {
+        let mut config = Config::default();
+        config.storage.db_path = path.as_ref().to_string_lossy().into_owned();
+        config.storage.wal = Some(WalConfig {
+            checkpoint_interval_ms: Some(60_000),
+            ..WalConfig::default()
+        });
+        Self::with_config(&config)
+    }
+
+    /// Open the database described by `config`, enabling the write-ahead
+    /// log with its default settings if `config` leaves it out.
+    pub fn with_config(config: &Config) -> Result<Self, DatabaseError> {
+        let config = logged(config);
+        Self::open_pages(&config, PageManagerBuilder::from_config(&config.storage))
+    }
+
+    /// Open the database described by `config` as `with_config` does,
+    /// injecting `faults` into the I/O of its files and log. A test drops
+    /// it after a simulated crash and opens it again to recover.
+    pub fn with_faults(config: &Config, faults: Arc<FaultInjector>) -> Result<Self, DatabaseError> {
+        let config = logged(config);
+        let pages = PageManagerBuilder::from_config(&config.storage).faults(faults);
+        Self::open_pages(&config, pages)
+    }
+
+    /// Open a database restored from a backup, the copy `BACKUP TO` or
+    /// `backup` made in `config`'s `storage.db_path`, replaying the log
+    /// archived in `archive_dir` since, up to `target` or to its end. A
+    /// target before the backup ended can't be reached. Only for the first
+    /// open after restoring: the database should then archive to a fresh
+    /// directory, as the old one holds history it no longer shares.
+    pub fn restore(
+        config: &Config,
+        archive_dir: impl AsRef<Path>,
+        target: Option<RestoreTarget>,
+    ) -> Result<Self, DatabaseError> {
+        let config = logged(config);
+        let target = target.map(|target| match target {
+            RestoreTarget::Time(time) => AsOf::Time(time),
+            RestoreTarget::Lsn(lsn) => AsOf::Lsn(Lsn(lsn)),
+        });
+        let pages = PageManagerBuilder::from_config(&config.storage).restore(archive_dir, target);
+        Self::open_pages(&config, pages)
+    }
+
+    /// Check the database `config` describes for damage, and with
+    /// `salvage` rebuild the pages found damaged, as `fsck` does. The
+    /// files are read as they are, without recovering from a crash, so
+    /// nothing else may have the database open.
+    pub fn fsck(config: &Config, salvage: bool) -> Result<FsckReport, DatabaseError> {
+        let config = logged(config);
+        let pages = PageManagerBuilder::from_config(&config.storage)
+            .recover(false)
+            .build()?;
+        Ok(storage::fsck(&pages, salvage)?)
+    }
+
+    fn open_pages(config: &Config, pages: PageManagerBuilder) -> Result<Self, DatabaseError> {
+        let pages = Arc::new(pages.build()?);
+        let transactions = TransactionManager::new(pages, config.transactions)?;
+        let database = Self {
+            transactions,
+            external: RwLock::default(),
+            table_options: RwLock::default(),
+            fill_factor: config.storage.fill_factor,
+            triggers: RwLock::default(),
+            functions: RwLock::default(),
+            fulltext: RwLock::default(),
+            unique: RwLock::default(),
+            bloom_filters: RwLock::default(),
+            partitions: RwLock::default(),
+            statistics: RwLock::default(),
+            row_counts: RwLock::default(),
+            approximate_counts: config.storage.approximate_counts,
+            temp_space: match &config.storage.spill {
+                Some(spill) => Some(
+                    TempSpace::open(spill, &config.storage.db_path)
+                        .map_err(DatabaseError::Spill)?,
+                ),
+                None => None,
+            },
+            queries: QueryCounters::default(),
+            table_counters: RwLock::default(),
+            activity: Activity::default(),
+            audit: match &config.audit {
+                Some(audit) => Some(AuditLog::open(audit).map_err(DatabaseError::AuditLog)?),
+                None => None,
+            },
+            config: Mutex::new(config.clone()),
+            slow_query_ms: AtomicU64::new(config.logging.slow_query_ms.unwrap_or(u64::MAX)),
+        };
+        for table in database.read_external_tables()? {
+            database.add_external_table(table);
+        }
+        for (table, options) in database.read_table_options()? {
+            let bloom_filter = options.bloom_filter;
+            database.set_table_options(table, options);
+            if bloom_filter {
+                database.build_bloom_filters(table)?;
+            }
+        }
+        database.read_partitions()?;
+        for trigger in database.read_triggers()? {
+            database.add_trigger(trigger);
+        }
+        database.read_fulltext_indexes()?;
+        database.read_unique_indexes()?;
+        let recovery = database.pages().recovery();
+        log!(
+            Info,
+            "database opened",
+            path = config.storage.db_path,
+            redone = recovery.redone,
+            undone = recovery.undone,
+        );
+        Ok(database)
+    }
+
+    /// Start a connection, which runs one transaction at a time.
+    pub fn connect(&self) -> Connection<'_> {
+        Connection {
+            database: self,
+            transaction: None,
+            counted: HashMap::new(),
+            savepoints: Vec::new(),
+            system: false,
+            cancelled: Arc::default(),
+        }
+    }
+
+    /// Start a connection that can also reach `TableId::CATALOG`.
+    pub(crate) fn connect_system(&self) -> Connection<'_> {
+        Connection {
+            system: true,
+            ..self.connect()
+        }
+    }
+
+    /// Create a new, empty table.
+    pub fn create_table(&self) -> Result<TableId, DatabaseError> {
+        Ok(TableId(self.pages().create_file()?.0))
+    }
+
+    pub(crate) fn add_external_table(&self, table: ExternalTable) {
+        let mut external = self.external.write().unwrap();
+        external.insert(table.table, Arc::new(table));
+    }
+
+    /// The external table `table` is, if it is one.
+    pub(crate) fn external_table(&self, table: TableId) -> Option<Arc<ExternalTable>> {
+        self.external.read().unwrap().get(&table).cloned()
+    }
+
+    /// The tables in the database, in id order.
+    pub fn tables(&self) -> Vec<TableId> {
+        self.pages()
+            .files()
+            .file_ids()
+            .into_iter()
+            .filter(|&file_id| file_id != FileId::CATALOG)
+            .map(|file_id| TableId(file_id.0))
+            .collect()
+    }
+
+    /// Where sorts and joins too big for `storage.spill.work_mem` write
+    /// their rows, if anywhere.
+    pub(crate) fn temp_space(&self) -> Option<&TempSpace> {
+        self.temp_space.as_ref()
+    }
+
+    /// The committed rows of `table`, if they've been counted since
+    /// opening.
+    fn row_count(&self, table: TableId) -> Option<u64> {
+        self.row_counts.read().unwrap().get(&table).copied()
+    }
+
+    /// Count `added` rows more in `table`, or fewer if negative, if its
+    /// rows are being counted.
+    fn add_rows(&self, table: TableId, added: i64) {
+        if added == 0 {
+            return;
+        }
+        if let Some(count) = self.row_counts.write().unwrap().get_mut(&table) {
+            *count = count.saturating_add_signed(added);
+        }
+    }
+
+    /// Stop counting `table`'s rows, as they may have changed uncounted;
+    /// its next count scans it again.
+    fn forget_row_count(&self, table: TableId) {
+        self.row_counts.write().unwrap().remove(&table);
+    }
+
+    /// The rows `table` may have by its statistics: as many to a page as
+    /// it had when analyzed.
+    fn estimated_row_count(&self, table: TableId) -> Option<u64> {
+        let (rows, pages) = self.analyzed_size(table)?;
+        let now = self.pages().files().page_count(FileId(table.0)).ok()?;
+        (pages > 0).then(|| (rows as f64 / pages as f64 * now as f64).round() as u64)
+    }
+
+    /// The pages `table` takes, across its partitions if it has them, as
+    /// far as its files or statistics say, since pages only reach its
+    /// files when written out; and its rows if they're known without
+    /// reading it: kept count of since opening, or estimated from its
+    /// statistics.
+    pub(crate) fn table_size(&self, table: TableId) -> (u64, Option<u64>) {
+        let tables = self.partitions(table).unwrap_or_else(|| vec![table]);
+        let mut pages = 0;
+        let mut rows = Some(0);
+        for table in tables {
+            let written = self.pages().files().page_count(FileId(table.0));
+            let analyzed = self.analyzed_size(table);
+            let table_pages = written
+                .unwrap_or(0)
+                .max(analyzed.map_or(0, |(_, pages)| pages));
+            let estimated = analyzed.and_then(|(rows, pages)| {
+                (pages > 0)
+                    .then(|| (rows as f64 / pages as f64 * table_pages as f64).round() as u64)
+            });
+            let table_rows = self.row_count(table).or(estimated);
+            rows = rows.zip(table_rows).map(|(rows, more)| rows + more);
+            pages += table_pages;
+        }
+        (pages, rows)
+    }
+
+    /// Keep the indexes on a row's table up to date with it, just written.
+    fn row_written(&self, row: RowId, data: &[u8]) {
+        self.index_row(row, data);
+        self.add_to_bloom_filter(row, data);
+    }
+
+    /// Write every changed page and log a checkpoint, so opening the
+    /// database again has nothing to recover, then flush its files as far
+    /// as `storage.durability` asks.
+    pub fn checkpoint(&self) -> Result<(), DatabaseError> {
+        self.pages().checkpoint()?;
+        self.pages().flush()?;
+        Ok(())
+    }
+
+    /// The address replicas connect to, if the database accepts them.
+    pub fn replication_addr(&self) -> Option<SocketAddr> {
+        self.pages().replication_addr()
+    }
+
+    /// Copy the database to the directory `dest` while it stays in use,
+    /// returning the LSN opening the copy recovers it to. With the log
+    /// archived since, `restore` brings the copy further forward.
+    pub fn backup(&self, dest: impl AsRef<Path>) -> Result<u64, DatabaseError> {
+        Ok(self.pages().backup(dest)?.0)
+    }
+
+    /// Like `backup`, but only the pages changed since the backup in
+    /// `base`, full or incremental, are copied. `merge_backups` turns a
+    /// chain of them back into a full backup.
+    pub fn incremental_backup(
+        &self,
+        dest: impl AsRef<Path>,
+        base: impl AsRef<Path>,
+    ) -> Result<u64, DatabaseError> {
+        Ok(self.pages().incremental_backup(dest, base)?.0)
+    }
+
+    /// Combine a full backup and the incremental backups taken after it,
+    /// each on top of the one before, into a full backup at `dest` as of
+    /// the last of them, which opens or restores as any other.
+    pub fn merge_backups(
+        chain: &[impl AsRef<Path>],
+        dest: impl AsRef<Path>,
+    ) -> Result<(), DatabaseError> {
+        Ok(storage::merge(chain, dest)?)
+    }
+
+    /// The changes to rows committed from the log position `from` on, in
+    /// commit order, such as to copy them elsewhere. Those a transaction
+    /// made before `from` are missed, so start where none was running,
+    /// such as the LSN a backup returned.
+    pub fn changes(&self, from: u64) -> Changes<'_> {
+        let wal = self.pages().wal().expect("a database always logs");
+        Changes {
+            database: self,
+            stream: ChangeStream::new(wal, Lsn(from)),
+        }
+    }
+
+    /// The global ids of the transactions prepared with
+    /// `Connection::prepare` and not yet finished, sorted.
+    pub fn prepared(&self) -> Vec<String> {
+        self.transactions.prepared()
+    }
+
+    /// Commit the transaction prepared as `gid`, the second phase of
+    /// two-phase commit.
+    pub fn commit_prepared(&self, gid: &str) -> Result<(), DatabaseError> {
+        self.transactions.commit_prepared(gid)?;
+        Ok(())
+    }
+
+    /// Roll back the transaction prepared as `gid`.
+    pub fn rollback_prepared(&self, gid: &str) -> Result<(), DatabaseError> {
+        self.transactions.rollback_prepared(gid)?;
+        Ok(())
+    }
+
+    /// Snapshot the counts of queries run and of the pages and log they
+    /// went through.
+    pub fn metrics(&self) -> Metrics {
+        let mut metrics = Metrics::from_counters(&self.queries);
+        let buffer = self.pages().stats();
+        metrics.buffer_hits = buffer.hits;
+        metrics.buffer_misses = buffer.misses;
+        if let Some(wal) = self.pages().wal() {
+            metrics.wal_bytes = wal.end().0;
+            metrics.wal_syncs = wal.stats().syncs;
+        }
+        metrics.active_transactions = self.transactions.transactions().len();
+        for (_, stats) in self.table_stats() {
+            metrics.tables.add(&stats);
+        }
+        metrics
+    }
+
+    /// The reads and writes of each table since the database opened, in
+    /// id order. A partitioned table's are its partitions'.
+    pub fn table_stats(&self) -> Vec<(TableId, TableStats)> {
+        let counters = self.table_counters.read().unwrap();
+        self.tables()
+            .into_iter()
+            .map(|table| {
+                let stats = counters.get(&table).map(TableCounters::stats);
+                (table, stats.unwrap_or_default())
+            })
+            .collect()
+    }
+
+    /// The buffer pool's counters and occupancy, and how many pages of
+    /// each table it holds and how many of those are dirty, in id order.
+    pub(crate) fn buffer_stats(&self) -> (BufferStats, Vec<(TableId, usize, usize)>) {
+        let cached = self.pages().cached_by_file();
+        let tables = self
+            .tables()
+            .into_iter()
+            .map(|table| {
+                let (pages, dirty) = cached.get(&FileId(table.0)).copied().unwrap_or_default();
+                (table, pages, dirty)
+            })
+            .collect();
+        (self.pages().stats(), tables)
+    }
+
+    /// The transactions still running, prepared ones among them, in id
+    /// order.
+    pub(crate) fn transactions(&self) -> Vec<TransactionInfo> {
+        self.transactions.transactions()
+    }
+
+    /// Add to `table`'s counters with `count`.
+    fn count_access(&self, table: TableId, count: impl FnOnce(&TableCounters)) {
+        if let Some(counters) = self.table_counters.read().unwrap().get(&table) {
+            return count(counters);
+        }
+        count(
+            self.table_counters
+                .write()
+                .unwrap()
+                .entry(table)
+                .or_default(),
+        );
+    }
+
+    /// Record `event` by `user` in the audit log, if there is one.
+    pub(crate) fn audit(&self, user: Option<&str>, event: AuditEvent, outcome: Result<(), &str>) {
+        if let Some(audit) = &self.audit {
+            audit.record(user, event, outcome);
+        }
+    }
+
+    /// The statements running in the database's SQL sessions.
+    pub(crate) fn activity(&self) -> &Activity {
+        &self.activity
+    }
+
+    /// Count a query, such as a statement, that took `elapsed`, logging
+    /// it as slow if it took longer than `logging.slow_query_ms`.
+    pub(crate) fn record_query(&self, elapsed: Duration, succeeded: bool, query: &str) {
+        self.queries.record(elapsed, succeeded);
+        let slow_query_ms = self.slow_query_ms.load(Ordering::Relaxed);
+        if elapsed.as_millis() > u128::from(slow_query_ms) {
+            log!(
+                Warn,
+                "slow query",
+                elapsed_ms = elapsed.as_millis(),
+                query = query
+            );
+        }
+    }
+
+    /// Apply the settings of `config` that can change while the database
+    /// is open, those in `RELOADABLE`, returning the ones that
+    /// changed. Nothing is changed if another setting differs from those
+    /// in use, or a new one is invalid. The log level is the process's,
+    /// shared with any other database in it.
+    pub fn reload(&self, config: &Config) -> Result<Vec<String>, DatabaseError> {
+        let config = &logged(config);
+        let mut current = self.config.lock().unwrap();
+        let result = self.apply(&current, config);
+        match &result {
+            Ok(changes) => {
+                *current = config.clone();
+                log!(Info, "configuration reloaded", changed = changes.join(","));
+            }
+            Err(e) => log!(Warn, "configuration not reloaded", error = e),
+        }
+        result
+    }
+
+    /// Change the setting `name`, by its path or the last part of it, to
+    /// `value`, given as YAML, as `reload` would. Returns the settings that
+    /// changed.
+    pub fn set_global(&self, name: &str, value: &str) -> Result<Vec<String>, DatabaseError> {
+        let mut current = self.config.lock().unwrap();
+        let path = current.setting_path(name)?;
+        let config = current.with_setting(&path, value)?;
+        let changes = self.apply(&current, &config)?;
+        *current = config;
+        log!(Info, "setting changed", setting = path, value = value);
+        Ok(changes)
+    }
+
+    /// The limits `user`'s sessions are held to.
+    pub fn limits(&self, user: &str) -> UserLimits {
+        self.config.lock().unwrap().limits.for_user(user)
+    }
+
+    fn apply(&self, current: &Config, config: &Config) -> Result<Vec<String>, DatabaseError> {
+        let changes = current.reload_changes(config)?;
+        let changed = |setting| changes.iter().any(|change| change == setting);
+        // Checked first, so nothing changes if it's wrong
+        LogLevel::parse(&config.logging.level)?;
+        if changed("storage.cache_size") {
+            self.pages().resize_cache(config.storage.cache_size)?;
+        }
+        if changed("logging.level") {
+            logging::set_level(&config.logging)?;
+        }
+        let slow_query_ms = config.logging.slow_query_ms.unwrap_or(u64::MAX);
+        self.slow_query_ms.store(slow_query_ms, Ordering::Relaxed);
+        Ok(changes)
+    }
+
+    pub(crate) fn pages(&self) -> &PageManager {
+        self.transactions.pages()
+    }
+}
+
+/// Cancels what a connection is doing from another thread.
+#[derive(Debug, Clone)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    /// Make the connection's running operation, or its next one if none is
+    /// running, fail with `Cancelled` at the next page it reads. An
+    /// operation waiting for a lock carries on waiting until it gets it.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// Take back a cancel that hasn't yet applied to any operation.
+    pub(crate) fn reset(&self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// A session with a database. Each operation outside an explicit
+/// transaction runs in one of its own and commits straight away; between
+/// `begin` and `commit` or `rollback`, operations belong to one
+/// transaction. Dropping the connection rolls back an unfinished one.
+///
+/// A transaction locks each table it reads shared and each it writes
+/// exclusively until it finishes, so concurrent transactions only run
+/// side by side over different tables, or while only reading.
+pub struct Connection<'a> {
+    database: &'a Database,
+    transaction: Option<Transaction>,
+    /// The rows the open transaction added to each table it wrote, less
+    /// those it deleted
+    counted: HashMap<TableId, i64>,
+    /// The open transaction's savepoints, oldest first, each with `counted`
+    /// as it was then
+    savepoints: Vec<(String, HashMap<TableId, i64>)>,
+    system: bool,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<'a> Connection<'a> {
+    pub fn begin(&mut self) -> Result<(), DatabaseError> {
+        if self.transaction.is_some() {
+            return Err(DatabaseError::TransactionInProgress);
+        }
+        self.transaction = Some(self.database.transactions.begin()?);
+        Ok(())
+    }
+
+    pub fn commit(&mut self) -> Result<(), DatabaseError> {
+        let transaction = self
+            .transaction
+            .take()
+            .ok_or(DatabaseError::NoTransaction)?;
+        let _span = span!(Debug, "commit", txn = transaction.id());
+        // Counted while the tables are still locked, so that no count is
+        // read in between
+        let counted = std::mem::take(&mut self.counted);
+        self.savepoints.clear();
+        for (&table, &added) in &counted {
+            self.database.add_rows(table, added);
+        }
+        if let Err(e) = transaction.commit() {
+            for &table in counted.keys() {
+                self.database.forget_row_count(table);
+            }
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    pub fn rollback(&mut self) -> Result<(), DatabaseError> {
+        let transaction = self
+            .transaction
+            .take()
+            .ok_or(DatabaseError::NoTransaction)?;
+        self.counted.clear();
+        self.savepoints.clear();
+        transaction.rollback()?;
+        Ok(())
+    }
+
+    /// Prepare the open transaction to commit as `gid`, the first phase of
+    /// two-phase commit, ending it on this connection. It holds its locks
+    /// until `Database::commit_prepared` or `rollback_prepared` finishes
+    /// it, from any connection and even after a restart.
+    pub fn prepare(&mut self, gid: &str) -> Result<(), DatabaseError> {
+        let transaction = self
+            .transaction
+            .take()
+            .ok_or(DatabaseError::NoTransaction)?;
+        // Whether its rows are ever added is only known once it's finished
+        for table in std::mem::take(&mut self.counted).into_keys() {
+            self.database.forget_row_count(table);
+        }
+        self.savepoints.clear();
+        transaction.prepare(gid)?;
+        Ok(())
+    }
+
+    /// Mark the current point of the open transaction as `name`, so the
+    /// changes after it can be undone on their own with `rollback_to`. A
+    /// savepoint with the same name as an earlier one hides it until
+    /// released.
+    pub fn savepoint(&mut self, name: &str) -> Result<(), DatabaseError> {
+        let transaction = self
+            .transaction
+            .as_mut()
+            .ok_or(DatabaseError::NoTransaction)?;
+        transaction.savepoint(name);
+        self.savepoints
+            .push((name.to_string(), self.counted.clone()));
+        Ok(())
+    }
+
+    /// Undo the open transaction's changes since the savepoint `name`,
+    /// keeping the savepoint and forgetting later ones.
+    pub fn rollback_to(&mut self, name: &str) -> Result<(), DatabaseError> {
+        let transaction = self
+            .transaction
+            .as_mut()
+            .ok_or(DatabaseError::NoTransaction)?;
+        transaction.rollback_to(name)?;
+        let index = self.find_savepoint(name);
+        self.savepoints.truncate(index + 1);
+        self.counted = self.savepoints[index].1.clone();
+        Ok(())
+    }
+
+    /// Forget the savepoint `name` and every one after it, keeping their
+    /// changes in the open transaction.
+    pub fn release(&mut self, name: &str) -> Result<(), DatabaseError> {
+        let transaction = self
+            .transaction
+            .as_mut()
+            .ok_or(DatabaseError::NoTransaction)?;
+        transaction.release(name)?;
+        let index = self.find_savepoint(name);
+        self.savepoints.truncate(index);
+        Ok(())
+    }
+
+    /// The index of the savepoint `name`, which the transaction has.
+    fn find_savepoint(&self, name: &str) -> usize {
+        self.savepoints
+            .iter()
+            .rposition(|(savepoint, _)| savepoint == name)
+            .expect("the transaction has the savepoint")
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle(self.cancelled.clone())
+    }
+
+    pub(crate) fn database(&self) -> &'a Database {
+        self.database
+    }
+
+    /// Whether a transaction begun with `begin` is still open.
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    /// Add a row to `table`, returning its id.
+    pub fn insert(&mut self, table: TableId, data: &[u8]) -> Result<RowId, DatabaseError> {
+        self.check_writable(table)?;
+        self.database.check_not_partition(table)?;
+        match self.database.partition_for(table, data) {
+            Some(partition) => self.insert_row(partition, data),
+            None => self.insert_row(table, data),
+        }
+    }
+
+    /// Add a row to `table`, which holds rows itself rather than placing
+    /// them in partitions.
+    fn insert_row(&mut self, table: TableId, data: &[u8]) -> Result<RowId, DatabaseError> {
+        let database = self.database;
+        let page_size = database.pages().page_size();
+        let reserved = database.reserved_space(table);
+        let moved = overflow::plan(data, SlottedPage::max_record_size(page_size))?;
+        let operation = |transaction: &mut Transaction, cancelled: &AtomicBool| {
+            database.check_unique(transaction, cancelled, table, &[(data, None)])?;
+            let place = Placing {
+                table,
+                page_size,
+                reserved,
+            };
+            let row = if moved.is_empty() {
+                place.record(transaction, cancelled, 0, data, RecordKind::Row)?
+            } else {
+                let stub = place.spill(transaction, cancelled, data, &moved)?;
+                place.record(transaction, cancelled, 0, &stub, RecordKind::Spilled)?
+            };
+            database.row_written(row, data);
+            Ok(row)
+        };
+        let row = self.run_counted(table, LockMode::Exclusive, operation, |_| 1)?;
+        database.count_access(table, |counters| counters.inserted(1));
+        Ok(row)
+    }
+
+    /// Add `rows` to `table` in order, returning their ids. Rather than
+    /// looking for room from the table's first page for each row as
+    /// `insert` does, each goes on the page the one before it went on or a
+    /// later one, and each page is read and written once, so loading many
+    /// rows takes time in proportion to their number.
+    pub fn insert_batch<R: AsRef<[u8]>>(
+        &mut self,
+        table: TableId,
+        rows: &[R],
+    ) -> Result<Vec<RowId>, DatabaseError> {
+        self.check_writable(table)?;
+        self.database.check_not_partition(table)?;
+        match self.database.partitions(table) {
+            Some(partitions) => self.insert_into_partitions(partitions, table, rows),
+            None => self.insert_rows(table, rows),
+        }
+    }
+
+    /// Add `rows` to `table` as `insert_batch` does, `table` holding rows
+    /// itself rather than placing them in partitions.
+    pub(crate) fn insert_rows<R: AsRef<[u8]>>(
+        &mut self,
+        table: TableId,
+        rows: &[R],
+    ) -> Result<Vec<RowId>, DatabaseError> {
+        let database = self.database;
+        let page_size = database.pages().page_size();
+        let reserved = database.reserved_space(table);
+        let max = SlottedPage::max_record_size(page_size);
+        let moved = rows
+            .iter()
+            .map(|data| overflow::plan(data.as_ref(), max))
+            .collect::<Result<Vec<_>, _>>()?;
+        let operation = |transaction: &mut Transaction, cancelled: &AtomicBool| {
+            let keyed: Vec<_> = rows.iter().map(|data| (data.as_ref(), None)).collect();
+            database.check_unique(transaction, cancelled, table, &keyed)?;
+            let mut ids = Vec::with_capacity(rows.len());
+            let mut page_no = 0;
+            let mut page = read(transaction, cancelled, page_id(table, page_no))?
+                .unwrap_or_else(|| SlottedPage::new(page_size));
+            let mut dirty = false;
+            for (data, moved) in rows.iter().zip(&moved) {
+                let data = data.as_ref();
+                let (record, kind) = if moved.is_empty() {
+                    (Cow::Borrowed(data), RecordKind::Row)
+                } else {
+                    // Its values may go on the page in hand, read again after
+                    if dirty {
+                        let full = std::mem::replace(&mut page, SlottedPage::new(page_size));
+                        transaction.write(page_id(table, page_no), full.into_page())?;
+                        dirty = false;
+                    }
+                    let place = Placing {
+                        table,
+                        page_size,
+                        reserved,
+                    };
+                    let stub = place.spill(transaction, cancelled, data, moved)?;
+                    page = read(transaction, cancelled, page_id(table, page_no))?
+                        .unwrap_or_else(|| SlottedPage::new(page_size));
+                    (Cow::Owned(stub), RecordKind::Spilled)
+                };
+                loop {
+                    if let Some(slot) = page.insert_as(&record, reserved, kind)? {
+                        dirty = true;
+                        let row = RowId {
+                            table,
+                            page_no,
+                            slot: slot.0,
+                        };
+                        database.row_written(row, data);
+                        ids.push(row);
+                        break;
+                    }
+                    if page.is_empty()? {
+                        return Err(too_large(&record, page_size));
+                    }
+                    let full = std::mem::replace(
+                        &mut page,
+                        read(transaction, cancelled, page_id(table, page_no + 1))?
+                            .unwrap_or_else(|| SlottedPage::new(page_size)),
+                    );
+                    if dirty {
+                        transaction.write(page_id(table, page_no), full.into_page())?;
+                    }
+                    page_no += 1;
+                    dirty = false;
+                }
+            }
+            if dirty {
+                transaction.write(page_id(table, page_no), page.into_page())?;
+            }
+            Ok(ids)
+        };
+        let added = |ids: &Vec<RowId>| ids.len() as i64;
+        let ids = self.run_counted(table, LockMode::Exclusive, operation, added)?;
+        database.count_access(table, |counters| counters.inserted(ids.len() as u64));
+        Ok(ids)
+    }
+
+    /// The row with id `row`, if it exists.
+    pub fn get(&mut self, row: RowId) -> Result<Option<Row>, DatabaseError> {
+        if let Some(external) = self.database.external_table(row.table) {
+            let mut found = None;
+            self.run(row.table, LockMode::Shared, |_, cancelled| {
+                external.scan(Some(row.page_no), cancelled, |read| {
+                    let passed = read.id.slot >= row.slot;
+                    if read.id == row {
+                        found = Some(read);
+                    }
+                    !passed
+                })
+            })?;
+            return Ok(found);
+        }
+        let found = self.run(row.table, LockMode::Shared, |transaction, cancelled| {
+            let data = row_at(transaction, cancelled, row)?;
+            Ok(data.map(|data| Row { id: row, data }))
+        })?;
+        let fetched = u64::from(found.is_some());
+        self.database
+            .count_access(row.table, |counters| counters.fetched(fetched));
+        Ok(found)
+    }
+
+    /// The rows on the pages of `table` that `pages` picks once the table
+    /// is locked, in the order it picks them.
+    pub(crate) fn rows_on_pages(
+        &mut self,
+        table: TableId,
+        pages: impl FnOnce() -> Vec<u64>,
+    ) -> Result<Vec<Row>, DatabaseError> {
+        let rows = self.run(table, LockMode::Shared, |transaction, cancelled| {
+            let mut rows = Vec::new();
+            for page_no in pages() {
+                let Some(page) = read(transaction, cancelled, page_id(table, page_no))? else {
+                    continue;
+                };
+                for (slot, record) in page.records()? {
+                    rows.push(Row {
+                        id: RowId {
+                            table,
+                            page_no,
+                            slot: slot.0,
+                        },
+                        data: row_data(transaction, cancelled, table, &page, slot, record)?,
+                    });
+                }
+            }
+            Ok(rows)
+        })?;
+        let fetched = rows.len() as u64;
+        self.database
+            .count_access(table, |counters| counters.fetched(fetched));
+        Ok(rows)
+    }
+
+    /// Replace the contents of a row, keeping its id.
+    pub fn update(&mut self, row: RowId, data: &[u8]) -> Result<(), DatabaseError> {
+        self.check_writable(row.table)?;
+        self.database.check_partition(row, data)?;
+        let database = self.database;
+        let page_size = database.pages().page_size();
+        let moved = overflow::plan(data, SlottedPage::max_record_size(page_size))?;
+        self.run(row.table, LockMode::Exclusive, |transaction, cancelled| {
+            let page_id = page_id(row.table, row.page_no);
+            let slot = SlotId(row.slot);
+            let old = {
+                let page =
+                    read(transaction, cancelled, page_id)?.ok_or(DatabaseError::NoSuchRow(row))?;
+                match (page.get(slot)?, page.kind(slot)?) {
+                    (Some(record), Some(RecordKind::Spilled)) => Some(Stub::decode(record)?),
+                    (Some(_), Some(RecordKind::Row)) => None,
+                    _ => return Err(DatabaseError::NoSuchRow(row)),
+                }
+            };
+            database.check_unique(transaction, cancelled, row.table, &[(data, Some(row))])?;
+            let (record, kind) = if moved.is_empty() {
+                (Cow::Borrowed(data), RecordKind::Row)
+            } else {
+                let place = Placing {
+                    table: row.table,
+                    page_size,
+                    reserved: 0,
+                };
+                let stub = place.spill(transaction, cancelled, data, &moved)?;
+                (Cow::Owned(stub), RecordKind::Spilled)
+            };
+            // Read again, as the values moved out may have gone on it
+            let mut page =
+                read(transaction, cancelled, page_id)?.ok_or(DatabaseError::NoSuchRow(row))?;
+            let size = page.get(slot)?.map_or(0, <[u8]>::len);
+            // Rows don't move between pages, which would change their ids
+            if !page.update_as(slot, &record, kind)? {
+                if kind == RecordKind::Spilled {
+                    free(transaction, cancelled, row.table, &Stub::decode(&record)?)?;
+                }
+                return Err(DatabaseError::RowTooLarge {
+                    size: record.len(),
+                    max: size + page.free_space()?,
+                    column: None,
+                });
+            }
+            transaction.write(page_id, page.into_page())?;
+            if let Some(old) = old {
+                free(transaction, cancelled, row.table, &old)?;
+            }
+            database.row_written(row, data);
+            Ok(())
+        })?;
+        database.count_access(row.table, TableCounters::updated);
+        Ok(())
+    }
+
+    /// Delete a row. Returns whether it existed.
+    pub fn delete(&mut self, row: RowId) -> Result<bool, DatabaseError> {
+        self.check_writable(row.table)?;
+        let operation = |transaction: &mut Transaction, cancelled: &AtomicBool| {
+            let page_id = page_id(row.table, row.page_no);
+            let slot = SlotId(row.slot);
+            let Some(mut page) = read(transaction, cancelled, page_id)? else {
+                return Ok(false);
+            };
+            let spilled = match (page.get(slot)?, page.kind(slot)?) {
+                (Some(record), Some(RecordKind::Spilled)) => Some(Stub::decode(record)?),
+                (Some(_), Some(RecordKind::Row)) => None,
+                _ => return Ok(false),
+            };
+            page.delete(slot)?;
+            transaction.write(page_id, page.into_page())?;
+            if let Some(stub) = spilled {
+                free(transaction, cancelled, row.table, &stub)?;
+            }
+            Ok(true)
+        };
+        let added = |&deleted: &bool| -i64::from(deleted);
+        let deleted = self.run_counted(row.table, LockMode::Exclusive, operation, added)?;
+        if deleted {
+            self.database
+                .count_access(row.table, TableCounters::deleted);
+        }
+        Ok(deleted)
+    }
+
+    /// Every row of `table` as it was at `as_of`, in id order, read from
+    /// its pages as the log says they were then. Fails with
+    /// `HistoryUnavailable` once the log no longer reaches back that far.
+    pub(crate) fn scan_as_of(
+        &mut self,
+        table: TableId,
+        as_of: AsOf,
+    ) -> Result<Vec<Row>, DatabaseError> {
+        if self.database.external_table(table).is_some() {
+            // Its rows are in files the log knows nothing of
+            return Err(PageManagerError::HistoryUnavailable.into());
+        }
+        let tables = self
+            .database
+            .partitions(table)
+            .unwrap_or_else(|| vec![table]);
+        let pages = self.database.pages();
+        let mut rows = Vec::new();
+        for table in tables {
+            self.run(table, LockMode::Shared, |_, cancelled| {
+                let old = |page_no| read_as_of(pages, cancelled, page_id(table, page_no), as_of);
+                for page_no in 0.. {
+                    let Some(page) = old(page_no)? else {
+                        break;
+                    };
+                    for (slot, record) in page.records()? {
+                        let data = match page.kind(slot)? {
+                            Some(RecordKind::Spilled) => join_spilled(record, old)?,
+                            _ => record.to_vec(),
+                        };
+                        let id = RowId {
+                            table,
+                            page_no,
+                            slot: slot.0,
+                        };
+                        rows.push(Row { id, data });
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        Ok(rows)
+    }
+
+    /// Compact every page of `table`, or of its partitions, moving each
+    /// page's records together to reclaim the space of deleted ones for
+    /// rows inserted later. Returns how many bytes were reclaimed.
+    pub fn vacuum(&mut self, table: TableId) -> Result<usize, DatabaseError> {
+        self.check_writable(table)?;
+        let tables = self
+            .database
+            .partitions(table)
+            .unwrap_or_else(|| vec![table]);
+        let mut reclaimed = 0;
+        for table in tables {
+            reclaimed += self.run(table, LockMode::Exclusive, |transaction, cancelled| {
+                let mut reclaimed = 0;
+                for page_no in 0.. {
+                    let page_id = page_id(table, page_no);
+                    let Some(mut page) = read(transaction, cancelled, page_id)? else {
+                        break;
+                    };
+                    let freed = page.compact()?;
+                    if freed > 0 {
+                        transaction.write(page_id, page.into_page())?;
+                        reclaimed += freed;
+                    }
+                }
+                Ok(reclaimed)
+            })?;
+        }
+        Ok(reclaimed)
+    }
+
+    /// Every row of `table`, in id order.
+    pub fn scan(&mut self, table: TableId) -> Result<Vec<Row>, DatabaseError> {
+        let mut rows = Vec::new();
+        self.scan_each(table, |row| {
+            rows.push(row);
+            Ok::<_, DatabaseError>(())
+        })?;
+        Ok(rows)
+    }
+
+    /// Pass every row of `table` to `each` in id order, stopping at the
+    /// first error, without holding more than a page of them at a time.
+    /// A partitioned table's rows are its partitions', in turn.
+    pub fn scan_each<E: From<DatabaseError>>(
+        &mut self,
+        table: TableId,
+        mut each: impl FnMut(Row) -> Result<(), E>,
+    ) -> Result<(), E> {
+        self.scan_after_each(table, None, |row| {
+            each(row).map(|()| ControlFlow::Continue(()))
+        })
+    }
+
+    /// Up to `limit` rows of `table` that come after the row `after` in
+    /// the order `scan` returns them, or its first rows if `after` is
+    /// `None`, reading from the page `after` is on and no further than
+    /// the last row's. Paging through a table by passing the last row of
+    /// each page back reads it once however many pages it takes, and
+    /// carries on from where it was whatever was written in between. An
+    /// external table's files are read from the start each time.
+    pub fn scan_after(
+        &mut self,
+        table: TableId,
+        after: Option<RowId>,
+        limit: usize,
+    ) -> Result<Vec<Row>, DatabaseError> {
+        let mut rows = Vec::new();
+        if limit == 0 {
+            return Ok(rows);
+        }
+        self.scan_after_each(table, after, |row| {
+            rows.push(row);
+            Ok::<_, DatabaseError>(if rows.len() < limit {
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            })
+        })?;
+        Ok(rows)
+    }
+
+    /// Pass the rows of `table` after `after` to `each`, as `scan_each`
+    /// does, until it breaks.
+    fn scan_after_each<E: From<DatabaseError>>(
+        &mut self,
+        table: TableId,
+        after: Option<RowId>,
+        mut each: impl FnMut(Row) -> Result<ControlFlow<()>, E>,
+    ) -> Result<(), E> {
+        // Set if `each` fails or breaks, which ends the scan as a success
+        let mut stopped = None;
+        let stop = |result: Result<ControlFlow<()>, E>| match result {
+            Ok(ControlFlow::Continue(())) => None,
+            Ok(ControlFlow::Break(())) => Some(Ok(())),
+            Err(e) => Some(Err(e)),
+        };
+        // The rows read from the table being scanned
+        let mut scanned = 0;
+        if let Some(external) = self.database.external_table(table) {
+            // Its files are read from the start, since lines don't say
+            // where they are
+            self.run(table, LockMode::Shared, |_, cancelled| {
+                external.scan(None, cancelled, |row| {
+                    if after.is_some_and(|after| row.id <= after) {
+                        return true;
+                    }
+                    scanned += 1;
+                    stopped = stop(each(row));
+                    stopped.is_none()
+                })
+            })?;
+            self.database
+                .count_access(table, |counters| counters.scanned(scanned));
+            return stopped.unwrap_or(Ok(()));
+        }
+        let mut tables = self
+            .database
+            .partitions(table)
+            .unwrap_or_else(|| vec![table]);
+        if let Some(after) = after {
+            // The partitions before the one `after` is in were read
+            let position = tables.iter().position(|&table| table == after.table);
+            tables.drain(..position.unwrap_or(0));
+        }
+        for table in tables {
+            scanned = 0;
+            let after = after.filter(|after| after.table == table);
+            let first_page = after.map_or(0, |after| after.page_no);
+            self.run(table, LockMode::Shared, |transaction, cancelled| {
+                for page_no in first_page.. {
+                    let Some(page) = read(transaction, cancelled, page_id(table, page_no))? else {
+                        break;
+                    };
+                    for (slot, record) in page.records()? {
+                        let id = RowId {
+                            table,
+                            page_no,
+                            slot: slot.0,
+                        };
+                        if after.is_some_and(|after| id <= after) {
+                            continue;
+                        }
+                        let row = Row {
+                            id,
+                            data: row_data(transaction, cancelled, table, &page, slot, record)?,
+                        };
+                        scanned += 1;
+                        stopped = stop(each(row));
+                        if stopped.is_some() {
+                            return Ok(());
+                        }
+                    }
+                }
+                Ok(())
+            })?;
+            self.database
+                .count_access(table, |counters| counters.scanned(scanned));
+            if stopped.is_some() {
+                break;
+            }
+        }
+        stopped.unwrap_or(Ok(()))
+    }
+
+    /// The number of rows in `table`, as the open transaction sees them.
+    ///
+    /// Each table's rows are counted by scanning it the first time, then
+    /// kept count of as transactions writing to it commit, so later counts
+    /// read nothing. With `storage.approximate_counts` a count doesn't wait
+    /// for transactions writing to the table, so it may take in rows they
+    /// have yet to commit, and a table not counted yet is estimated from
+    /// its statistics if it has them.
+    pub fn count(&mut self, table: TableId) -> Result<u64, DatabaseError> {
+        if let Some(partitions) = self.database.partitions(table) {
+            let mut count = 0;
+            for partition in partitions {
+                count += self.count(partition)?;
+            }
+            return Ok(count);
+        }
+        if self.database.external_table(table).is_some() {
+            let mut count = 0;
+            self.scan_each(table, |_| {
+                count += 1;
+                Ok::<_, DatabaseError>(())
+            })?;
+            return Ok(count);
+        }
+        let database = self.database;
+        let own = self.counted.get(&table).copied().unwrap_or(0);
+        if database.approximate_counts {
+            if let Some(count) = database.row_count(table) {
+                return Ok(count.saturating_add_signed(own));
+            }
+            if let Some(count) = database.estimated_row_count(table) {
+                return Ok(count);
+            }
+        }
+        // A replica's pages are written from its primary's log, not by
+        // transactions keeping count
+        let kept = !database.pages().is_replica();
+        self.run(table, LockMode::Shared, |transaction, cancelled| {
+            if let Some(count) = database.row_count(table).filter(|_| kept) {
+                return Ok(count.saturating_add_signed(own));
+            }
+            let mut count = 0;
+            for page_no in 0.. {
+                let Some(page) = read(transaction, cancelled, page_id(table, page_no))? else {
+                    break;
+                };
+                count += page.records()?.len() as u64;
+            }
+            if kept {
+                let committed = count.saturating_add_signed(-own);
+                database
+                    .row_counts
+                    .write()
+                    .unwrap()
+                    .insert(table, committed);
+            }
+            Ok(count)
+        })
+    }
+
+    /// Fail unless `table`'s rows can be changed: an external table's are
+    /// its files'.
+    fn check_writable(&self, table: TableId) -> Result<(), DatabaseError> {
+        match self.database.external_table(table) {
+            Some(_) => Err(DatabaseError::ReadOnlyTable(table)),
+            None => Ok(()),
+        }
+    }
+
+    /// Lock `table` exclusively for the rest of the open transaction, so
+    /// that what is read from it before writing can't change meanwhile.
+    pub(crate) fn lock_exclusive(&mut self, table: TableId) -> Result<(), DatabaseError> {
+        self.run(table, LockMode::Exclusive, |_, _| Ok(()))
+    }
+
+    /// Run `operation` on `table` in the open transaction, or in one of its
+    /// own that commits if the operation succeeds.
+    fn run<T>(
+        &mut self,
+        table: TableId,
+        mode: LockMode,
+        operation: impl FnOnce(&mut Transaction, &AtomicBool) -> Result<T, DatabaseError>,
+    ) -> Result<T, DatabaseError> {
+        self.run_counted(table, mode, operation, |_| 0)
+    }
+
+    /// Run `operation` as `run` does, counting the rows `added` says its
+    /// result added to `table`, or took from it if negative.
+    fn run_counted<T>(
+        &mut self,
+        table: TableId,
+        mode: LockMode,
+        operation: impl FnOnce(&mut Transaction, &AtomicBool) -> Result<T, DatabaseError>,
+        added: impl FnOnce(&T) -> i64,
+    ) -> Result<T, DatabaseError> {
+        let span = span!(Debug, "operation", table = table);
+        let result = self.run_uncancelled(&span, table, mode, operation, added);
+        // A cancel only applies to the one operation
+        self.cancelled.store(false, Ordering::Release);
+        result
+    }
+
+    fn run_uncancelled<T>(
+        &mut self,
+        span: &Span,
+        table: TableId,
+        mode: LockMode,
+        operation: impl FnOnce(&mut Transaction, &AtomicBool) -> Result<T, DatabaseError>,
+        added: impl FnOnce(&T) -> i64,
+    ) -> Result<T, DatabaseError> {
+        if table == TableId::CATALOG && !self.system {
+            return Err(DatabaseError::NoSuchTable(table));
+        }
+        self.database
+            .pages()
+            .files()
+            .file(FileId(table.0))
+            .map_err(|_| DatabaseError::NoSuchTable(table))?;
+        if let Some(transaction) = &mut self.transaction {
+            span.record("txn", &transaction.id());
+            transaction.lock(LockTarget::Table(FileId(table.0)), mode)?;
+            let result = operation(transaction, &self.cancelled);
+            match &result {
+                Ok(result) => *self.counted.entry(table).or_default() += added(result),
+                // What it wrote before failing stays in the transaction,
+                // uncounted
+                Err(_) if mode == LockMode::Exclusive => self.database.forget_row_count(table),
+                Err(_) => {}
+            }
+            return result;
+        }
+        let mut transaction = self.database.transactions.begin()?;
+        span.record("txn", &transaction.id());
+        transaction.lock(LockTarget::Table(FileId(table.0)), mode)?;
+        let result = operation(&mut transaction, &self.cancelled)?;
+        self.database.add_rows(table, added(&result));
+        if let Err(e) = transaction.commit() {
+            self.database.forget_row_count(table);
+            return Err(e.into());
+        }
+        Ok(result)
+    }
+}
+
+/// `config`, with the write-ahead log a database needs enabled.
+fn logged(config: &Config) -> Config {
+    let mut config = config.clone();
+    config.storage.wal.get_or_insert_with(WalConfig::default);
+    config
+}
+
+fn page_id(table: TableId, page_no: u64) -> PageId {
+    // Page 0 of the catalog is its superblock
+    let first = if table == TableId::CATALOG { 1 } else { 0 };
+    PageId::new(FileId(table.0), first + page_no)
+}
+
+/// A page of a table, or `None` past the end of its file. Fails with
+/// `Cancelled` once `cancelled` is set.
+fn read(
+    transaction: &Transaction,
+    cancelled: &AtomicBool,
+    page_id: PageId,
+) -> Result<Option<SlottedPage>, DatabaseError> {
+    if cancelled.load(Ordering::Acquire) {
+        return Err(DatabaseError::Cancelled);
+    }
+    let _span = span!(Trace, "read_page", page = page_id);
+    let bytes = match transaction.read(page_id) {
+        Ok(page) => page.page().as_bytes().to_vec(),
+        Err(TransactionError::PageManagerError(PageManagerError::PageIOError(
+            PageIOError::PageNotFound(_),
+        ))) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Some(SlottedPage::from_page(Page::new(bytes))?))
+}
+
+/// The data of the row `row`, if it exists, as `transaction` sees it.
+pub(crate) fn row_at(
+    transaction: &Transaction,
+    cancelled: &AtomicBool,
+    row: RowId,
+) -> Result<Option<Vec<u8>>, DatabaseError> {
+    let Some(page) = read(transaction, cancelled, page_id(row.table, row.page_no))? else {
+        return Ok(None);
+    };
+    let slot = SlotId(row.slot);
+    match (page.get(slot)?, page.kind(slot)?) {
+        (Some(record), Some(RecordKind::Spilled)) => {
+            unspill(transaction, cancelled, row.table, record).map(Some)
+        }
+        (Some(record), Some(RecordKind::Row)) => Ok(Some(record.to_vec())),
+        _ => Ok(None),
+    }
+}
+
+/// The row `record`, in `slot` of `page` of `table`, holds.
+fn row_data(
+    transaction: &Transaction,
+    cancelled: &AtomicBool,
+    table: TableId,
+    page: &SlottedPage,
+    slot: SlotId,
+    record: &[u8],
+) -> Result<Vec<u8>, DatabaseError> {
+    match page.kind(slot)? {
+        Some(RecordKind::Spilled) => unspill(transaction, cancelled, table, record),
+        _ => Ok(record.to_vec()),
+    }
+}
+
+/// The whole row a spilled row's `record` in `table` stands for, its
+/// values read back from their overflow records.
+fn unspill(
+    transaction: &Transaction,
+    cancelled: &AtomicBool,
+    table: TableId,
+    record: &[u8],
+) -> Result<Vec<u8>, DatabaseError> {
+    join_spilled(record, |page_no| {
+        read(transaction, cancelled, page_id(table, page_no))
+    })
+}
+
+/// The whole row a spilled row's `record` stands for, its values read
+/// back from the overflow records on the pages of its table `page` reads.
+fn join_spilled(
+    record: &[u8],
+    page: impl Fn(u64) -> Result<Option<SlottedPage>, DatabaseError>,
+) -> Result<Vec<u8>, DatabaseError> {
+    Stub::decode(record)?.join(|(page_no, slot)| {
+        let page = page(page_no)?;
+        let piece = match &page {
+            Some(page) if page.kind(SlotId(slot))? == Some(RecordKind::Overflow) => {
+                page.get(SlotId(slot))?
+            }
+            _ => None,
+        };
+        let missing = || PageDecodeError::Corrupted("spilled value missing".to_string());
+        Ok(piece.ok_or_else(missing)?.to_vec())
+    })
+}
+
+/// A page of a table as it was at `as_of`, rebuilt from the log, or
+/// `None` past the end of its file. Fails with `Cancelled` once
+/// `cancelled` is set.
+fn read_as_of(
+    pages: &PageManager,
+    cancelled: &AtomicBool,
+    page_id: PageId,
+    as_of: AsOf,
+) -> Result<Option<SlottedPage>, DatabaseError> {
+    if cancelled.load(Ordering::Acquire) {
+        return Err(DatabaseError::Cancelled);
+    }
+    match pages.page_as_of(page_id, as_of) {
+        Ok(page) => Ok(Some(SlottedPage::from_page(page)?)),
+        Err(PageManagerError::PageIOError(PageIOError::PageNotFound(_))) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Delete the overflow records a spilled row's values went to.
+fn free(
+    transaction: &mut Transaction,
+    cancelled: &AtomicBool,
+    table: TableId,
+    stub: &Stub,
+) -> Result<(), DatabaseError> {
+    for (page_no, slot) in stub.pieces() {
+        let page_id = page_id(table, page_no);
+        if let Some(mut page) = read(transaction, cancelled, page_id)? {
+            if page.delete(SlotId(slot))? {
+                transaction.write(page_id, page.into_page())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The error for a `record` that doesn't fit an empty page.
+fn too_large(record: &[u8], page_size: usize) -> DatabaseError {
+    DatabaseError::RowTooLarge {
+        size: record.len(),
+        max: SlottedPage::max_record_size(page_size),
+        column: None,
+    }
+}
+
+/// Where records of a table go: on the first page with room for them.
+struct Placing {
+    table: TableId,
+    page_size: usize,
+    /// Bytes kept free on pages already holding records
+    reserved: usize,
+}
+
+impl Placing {
+    /// Write `record` of `kind` to the first page from `page_no` on with
+    /// room for it, returning where it went.
+    fn record(
+        &self,
+        transaction: &mut Transaction,
+        cancelled: &AtomicBool,
+        mut page_no: u64,
+        record: &[u8],
+        kind: RecordKind,
+    ) -> Result<RowId, DatabaseError> {
+        loop {
+            let page_id = page_id(self.table, page_no);
+            let mut page = read(transaction, cancelled, page_id)?
+                .unwrap_or_else(|| SlottedPage::new(self.page_size));
+            if let Some(slot) = page.insert_as(record, self.reserved, kind)? {
+                transaction.write(page_id, page.into_page())?;
+                return Ok(RowId {
+                    table: self.table,
+                    page_no,
+                    slot: slot.0,
+                });
+            }
+            if page.is_empty()? {
+                return Err(too_large(record, self.page_size));
+            }
+            page_no += 1;
+        }
+    }
+
+    /// Move the values `moved` out of the row `data` to overflow records,
+    /// returning the record to store the row as.
+    fn spill(
+        &self,
+        transaction: &mut Transaction,
+        cancelled: &AtomicBool,
+        data: &[u8],
+        moved: &[Range<usize>],
+    ) -> Result<Vec<u8>, DatabaseError> {
+        let max = SlottedPage::max_record_size(self.page_size);
+        let mut stub = Stub {
+            values: Vec::with_capacity(moved.len()),
+            rest: Vec::with_capacity(max),
+        };
+        let mut from = 0;
+        for value in moved {
+            stub.rest.extend_from_slice(&data[from..value.start]);
+            from = value.end;
+            let mut pieces = Vec::new();
+            for piece in overflow::pieces(&data[value.clone()], max) {
+                let at = self.record(transaction, cancelled, 0, piece, RecordKind::Overflow)?;
+                pieces.push((at.page_no, at.slot));
+            }
+            stub.values.push((value.start, pieces));
+        }
+        stub.rest.extend_from_slice(&data[from..]);
+        Ok(stub.encode())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::IN_MEMORY;
+    use crate::storage::Problem;
+    use std::fs;
+    use std::os::unix::fs::FileExt;
+    use tempfile::TempDir;
+
+    fn open(dir: &Path) -> Database {
+        let mut config = Config::default();
+        config.storage.db_path = dir.to_str().unwrap().to_string();
+        config.storage.page_size = 128;
+        Database::with_config(&config).unwrap()
+    }
+
+    /// The config of a database in `dir` that logs to a WAL, for tests
+    /// to change as they need.
+    pub(crate) fn test_config(dir: &Path) -> Config {
+        let mut config = Config::default();
+        config.storage.db_path = dir.to_str().unwrap().to_string();
+        config.storage.wal = Some(WalConfig::default());
+        config
+    }
+
+    /// A database in `dir` opened with `test_config`.
+    pub(crate) fn open_logged(dir: &Path) -> Database {
+        Database::with_config(&test_config(dir)).unwrap()
+    }
+
+    /// A database opened with `test_config` in a new temporary
+    /// directory, which lasts as long as it's kept.
+    pub(crate) fn temp_database() -> (TempDir, Database) {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open_logged(dir.path());
+        (dir, database)
+    }
+
+    #[test]
+    fn test_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open(dir.path());
+        let table = database.create_table().unwrap();
+        let mut connection = database.connect();
+
+        // Enough rows to spill onto a second page
+        let ids: Vec<_> = (0..10u8)
+            .map(|i| connection.insert(table, &[i; 16]).unwrap())
+            .collect();
+        assert!(ids.iter().any(|id| id.page_no == 1));
+        assert_eq!(connection.scan(table).unwrap().len(), 10);
+
+        connection.update(ids[3], b"three").unwrap();
+        assert_eq!(connection.get(ids[3]).unwrap().unwrap().data, b"three");
+        assert!(connection.delete(ids[4]).unwrap());
+        assert!(!connection.delete(ids[4]).unwrap());
+        assert_eq!(connection.get(ids[4]).unwrap(), None);
+        assert!(matches!(
+            connection.update(ids[4], b"gone"),
+            Err(DatabaseError::NoSuchRow(_))
+        ));
+        assert!(matches!(
+            connection.insert(TableId(99), b"x"),
+            Err(DatabaseError::NoSuchTable(_))
+        ));
+        connection.cancel_handle().cancel();
+        assert!(matches!(
+            connection.scan(table),
+            Err(DatabaseError::Cancelled)
+        ));
+        assert_eq!(connection.scan(table).unwrap().len(), 9);
+        assert!(matches!(
+            connection.scan(TableId::CATALOG),
+            Err(DatabaseError::NoSuchTable(_))
+        ));
+        assert!(matches!(
+            connection.insert(table, &[0; 200]),
+            Err(DatabaseError::RowTooLarge {
+                size: 200,
+                max: 92,
+                column: None
+            })
+        ));
+    }
+
+    #[test]
+    fn test_insert_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open(dir.path());
+        let table = database.create_table().unwrap();
+        let mut connection = database.connect();
+        let first = connection.insert(table, b"first").unwrap();
+
+        let rows: Vec<_> = (0..20u8).map(|i| vec![i; 16]).collect();
+        let ids = connection.insert_batch(table, &rows).unwrap();
+        assert_eq!(ids.len(), 20);
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids[0] > first);
+        let scanned = connection.scan(table).unwrap();
+        assert_eq!(scanned.len(), 21);
+        assert_eq!(
+            scanned[1..].iter().map(|row| row.id).collect::<Vec<_>>(),
+            ids
+        );
+        assert_eq!(scanned[20].data, rows[19]);
+
+        // All or nothing, as any operation
+        let rows = [vec![1; 16], vec![0; 200]];
+        assert!(matches!(
+            connection.insert_batch(table, &rows),
+            Err(DatabaseError::RowTooLarge { size: 200, .. })
+        ));
+        assert_eq!(connection.scan(table).unwrap().len(), 21);
+    }
+
+    #[test]
+    fn test_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let (table, kept) = {
+            let database = open(dir.path());
+            let table = database.create_table().unwrap();
+            let mut connection = database.connect();
+            let kept = connection.insert(table, b"kept").unwrap();
+
+            connection.begin().unwrap();
+            assert!(matches!(
+                connection.begin(),
+                Err(DatabaseError::TransactionInProgress)
+            ));
+            connection.insert(table, b"undone").unwrap();
+            connection.update(kept, b"changed").unwrap();
+            connection.rollback().unwrap();
+            assert!(matches!(
+                connection.commit(),
+                Err(DatabaseError::NoTransaction)
+            ));
+
+            // Rolled back when the connection is dropped
+            connection.begin().unwrap();
+            connection.delete(kept).unwrap();
+            drop(connection);
+            (table, kept)
+        };
+
+        let database = open(dir.path());
+        assert_eq!(database.tables(), vec![table]);
+        let rows = database.connect().scan(table).unwrap();
+        assert_eq!(
+            rows,
+            vec![Row {
+                id: kept,
+                data: b"kept".to_vec()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open(dir.path());
+        let table = database.create_table().unwrap();
+        let mut connection = database.connect();
+        let rows: Vec<_> = (0..20u8).map(|i| [i; 16]).collect();
+        let ids = connection.insert_batch(table, &rows).unwrap();
+        assert_eq!(connection.count(table).unwrap(), 20);
+        assert_eq!(database.row_count(table), Some(20));
+
+        // Kept count of from then on, as transactions commit
+        connection.insert(table, b"one more").unwrap();
+        connection.delete(ids[0]).unwrap();
+        connection.delete(ids[0]).unwrap();
+        assert_eq!(database.row_count(table), Some(20));
+        connection.begin().unwrap();
+        connection.insert_batch(table, &rows[..5]).unwrap();
+        assert_eq!(connection.count(table).unwrap(), 25);
+        connection.rollback().unwrap();
+        assert_eq!(database.row_count(table), Some(20));
+        connection.begin().unwrap();
+        connection.delete(ids[1]).unwrap();
+        connection.commit().unwrap();
+        assert_eq!(database.connect().count(table).unwrap(), 19);
+        assert_eq!(connection.scan(table).unwrap().len(), 19);
+
+        // Failing part way through a transaction leaves it to be counted
+        // again
+        connection.begin().unwrap();
+        connection.insert(table, b"kept").unwrap();
+        assert!(connection.update(ids[2], &[0; 90]).is_err());
+        assert_eq!(database.row_count(table), None);
+        assert_eq!(connection.count(table).unwrap(), 20);
+        connection.commit().unwrap();
+        assert_eq!(database.row_count(table), Some(20));
+
+        // Approximate counts are estimated from statistics when there's no
+        // count kept
+        drop(connection);
+        database.checkpoint().unwrap();
+        drop(database);
+        let mut config = test_config(dir.path());
+        config.storage.page_size = 128;
+        config.storage.approximate_counts = true;
+        let database = Database::with_config(&config).unwrap();
+        database.analyze(table).unwrap();
+        let mut connection = database.connect();
+        connection.insert_batch(table, &rows).unwrap();
+        // As many rows to a page as when analyzed, on the pages added since
+        let estimate = connection.count(table).unwrap();
+        assert!((21..=60).contains(&estimate), "{}", estimate);
+        assert_eq!(database.row_count(table), None);
+    }
+
+    #[test]
+    fn test_large_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open(dir.path());
+        let table = database.create_table().unwrap();
+        let mut connection = database.connect();
+        // Records of every kind left on the table's pages
+        let records = |database: &Database| {
+            database.checkpoint().unwrap();
+            let pages = database.pages();
+            let count = pages.files().page_count(FileId(table.0)).unwrap();
+            (0..count)
+                .map(|page_no| {
+                    let page = pages.get_page(page_id(table, page_no)).unwrap();
+                    let bytes = page.page().as_bytes().to_vec();
+                    SlottedPage::from_page(Page::new(bytes))
+                        .unwrap()
+                        .entries()
+                        .unwrap()
+                        .len()
+                })
+                .sum::<usize>()
+        };
+
+        // The long text goes to pages of its own, and comes back whole
+        let long = format!(r#"{{"id": 1, "text": "{}"}}"#, "x".repeat(300));
+        let small = connection.insert(table, b"small").unwrap();
+        let row = connection.insert(table, long.as_bytes()).unwrap();
+        assert_eq!(connection.get(row).unwrap().unwrap().data, long.as_bytes());
+        let scanned: Vec<_> = connection.scan(table).unwrap();
+        assert_eq!(scanned.len(), 2);
+        assert_eq!(scanned[1].data, long.as_bytes());
+        assert_eq!(connection.count(table).unwrap(), 2);
+        assert_eq!(records(&database), 2 + 4);
+
+        // Updated in place, spilled or not, and its old values freed
+        let longer = format!(r#"{{"id": 2, "text": "{}"}}"#, "y".repeat(400));
+        connection.update(row, longer.as_bytes()).unwrap();
+        assert_eq!(
+            connection.get(row).unwrap().unwrap().data,
+            longer.as_bytes()
+        );
+        assert_eq!(records(&database), 2 + 5);
+        connection.update(row, br#"{"id": 3}"#).unwrap();
+        assert_eq!(records(&database), 2);
+        connection.update(row, long.as_bytes()).unwrap();
+        let batch = connection
+            .insert_batch(table, &[longer.as_bytes(), b"after"])
+            .unwrap();
+        assert_eq!(
+            connection.get(batch[0]).unwrap().unwrap().data,
+            longer.as_bytes()
+        );
+        assert!(connection.delete(row).unwrap());
+        assert!(connection.delete(batch[0]).unwrap());
+        assert_eq!(records(&database), 2);
+        assert_eq!(connection.get(small).unwrap().unwrap().data, b"small");
+
+        // What can't be moved out is too large, naming its largest column
+        let numbers = format!(
+            r#"{{"text": "{}", "numbers": [{}]}}"#,
+            "z".repeat(50),
+            "1, ".repeat(40)
+        );
+        let error = connection.insert(table, numbers.as_bytes()).unwrap_err();
+        assert!(matches!(
+            &error,
+            DatabaseError::RowTooLarge { max: 92, column: Some((name, 122)), .. } if name == "numbers"
+        ));
+        assert!(error
+            .to_string()
+            .ends_with("; column \"numbers\" alone takes 122"));
+        assert!(connection
+            .insert_batch(table, &[b"ok", numbers.as_bytes()])
+            .is_err());
+        assert_eq!(connection.count(table).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_table_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open(dir.path());
+        let table = database.create_table().unwrap();
+        let untouched = database.create_table().unwrap();
+        let mut connection = database.connect();
+        let ids = connection.insert_batch(table, &[b"a", b"b", b"c"]).unwrap();
+        connection.insert(table, b"d").unwrap();
+        connection.update(ids[0], b"e").unwrap();
+        assert!(connection.delete(ids[1]).unwrap());
+        assert!(!connection.delete(ids[1]).unwrap());
+        assert_eq!(connection.scan(table).unwrap().len(), 3);
+        assert!(connection.get(ids[2]).unwrap().is_some());
+        assert!(connection.get(ids[1]).unwrap().is_none());
+
+        let stats = TableStats {
+            seq_scans: 1,
+            rows_scanned: 3,
+            index_scans: 2,
+            rows_fetched: 1,
+            rows_inserted: 4,
+            rows_updated: 1,
+            rows_deleted: 1,
+        };
+        assert_eq!(
+            database.table_stats(),
+            vec![(table, stats), (untouched, TableStats::default())]
+        );
+        assert_eq!(database.metrics().tables, stats);
+
+        // Listed in SQL as well, a row for each table
+        let mut session = crate::sql::SqlSession::new(connection);
+        let result = session
+            .execute("SELECT * FROM information_schema.table_stats")
+            .remove(0)
+            .unwrap();
+        assert_eq!(result.columns.len(), 8);
+        assert_eq!(result.rows[0][0], Some(table.0.to_string()));
+        let counts = ["1", "3", "2", "1", "4", "1", "1"].map(|count| Some(count.to_string()));
+        assert_eq!(result.rows[0][1..], counts);
+        assert!(result.rows[1][1..]
+            .iter()
+            .all(|count| count.as_deref() == Some("0")));
+    }
+
+    #[test]
+    fn test_vacuum() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open(dir.path());
+        let table = database.create_table().unwrap();
+        let mut connection = database.connect();
+        let ids = connection
+            .insert_batch(table, &[b"aaaa", b"bbbb", b"cccc", b"dddd"])
+            .unwrap();
+        connection.delete(ids[0]).unwrap();
+        connection.delete(ids[2]).unwrap();
+        assert_eq!(connection.vacuum(table).unwrap(), 8);
+        assert_eq!(connection.vacuum(table).unwrap(), 0);
+        let rows: Vec<_> = connection.scan(table).unwrap();
+        assert_eq!(
+            rows.iter().map(|row| row.id).collect::<Vec<_>>(),
+            [ids[1], ids[3]]
+        );
+        assert_eq!(rows[1].data, b"dddd");
+
+        // Every table when none is named, in SQL
+        connection.delete(ids[1]).unwrap();
+        let mut session = crate::sql::SqlSession::new(connection);
+        let result = session.execute("VACUUM").remove(0).unwrap();
+        assert_eq!(result.tag, "VACUUM");
+        assert_eq!(session.connection_mut().vacuum(table).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let live = tempfile::tempdir().unwrap();
+        let archive = tempfile::tempdir().unwrap();
+        let backups = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.db_path = live.path().to_str().unwrap().to_string();
+        config.storage.page_size = 128;
+        config.storage.wal = Some(WalConfig {
+            segment_size: 512,
+            archive_dir: Some(archive.path().to_str().unwrap().to_string()),
+            ..WalConfig::default()
+        });
+        let database = Database::with_config(&config).unwrap();
+        let table = database.create_table().unwrap();
+        let mut connection = database.connect();
+        connection.insert(table, b"before").unwrap();
+        let backup = |name: &str| backups.path().join(name);
+        let first = database.backup(backup("monday")).unwrap();
+        database.backup(backup("monday_again")).unwrap();
+        database.backup(backup("too_early")).unwrap();
+
+        // Enough to fill and archive a few segments
+        for _ in 0..10 {
+            connection.insert(table, b"after").unwrap();
+        }
+        let middle = database.backup(backup("tuesday")).unwrap();
+        for _ in 0..10 {
+            connection.insert(table, b"later").unwrap();
+        }
+        drop(connection);
+        drop(database);
+
+        config.storage.wal.as_mut().unwrap().archive_dir = None;
+        let restore = |name: &str, target| {
+            let mut config = config.clone();
+            config.storage.db_path = backup(name).to_str().unwrap().to_string();
+            Database::restore(&config, archive.path(), target)
+        };
+        let restored = restore("monday", Some(RestoreTarget::Lsn(middle))).unwrap();
+        let rows = restored.connect().scan(table).unwrap();
+        assert_eq!(rows[0].data, b"before");
+        assert_eq!(rows.len(), 11);
+        assert_eq!(rows[10].data, b"after");
+
+        // Without a target, as far as the archive goes
+        let restored = restore("monday_again", None).unwrap();
+        assert!(restored.connect().scan(table).unwrap().len() > 11);
+
+        // The backup already holds everything logged before it ended
+        assert!(matches!(
+            restore("too_early", Some(RestoreTarget::Lsn(first))),
+            Err(DatabaseError::PageManagerError(
+                PageManagerError::HistoryUnavailable
+            ))
+        ));
+        assert_eq!(
+            RestoreTarget::parse_time("1970-01-01 00:00:01Z"),
+            Some(RestoreTarget::Time(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1)
+            ))
+        );
+        assert_eq!(RestoreTarget::parse_time("tuesday"), None);
+    }
+
+    #[test]
+    fn test_incremental_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = tempfile::tempdir().unwrap();
+        let backup = |name: &str| backups.path().join(name);
+        let database = open(dir.path());
+        let table = database.create_table().unwrap();
+        let mut connection = database.connect();
+        connection.insert(table, b"monday").unwrap();
+        database.backup(backup("full")).unwrap();
+        connection.insert(table, b"tuesday").unwrap();
+        database
+            .incremental_backup(backup("tuesday"), backup("full"))
+            .unwrap();
+        connection.insert(table, b"wednesday").unwrap();
+        database
+            .incremental_backup(backup("wednesday"), backup("tuesday"))
+            .unwrap();
+        connection.insert(table, b"thursday").unwrap();
+
+        // Each backup builds on the one before
+        assert!(
+            Database::merge_backups(&[backup("full"), backup("wednesday")], backup("broken"))
+                .is_err()
+        );
+        Database::merge_backups(
+            &[backup("full"), backup("tuesday"), backup("wednesday")],
+            backup("merged"),
+        )
+        .unwrap();
+        let merged = open(&backup("merged"));
+        let rows = merged.connect().scan(table).unwrap();
+        let days: Vec<_> = rows.iter().map(|row| row.data.as_slice()).collect();
+        assert_eq!(days, [&b"monday"[..], b"tuesday", b"wednesday"]);
+    }
+
+    #[test]
+    fn test_fsck() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.db_path = dir.path().to_str().unwrap().to_string();
+        config.storage.page_size = 128;
+        let database = Database::with_config(&config).unwrap();
+        let table = database.create_table().unwrap();
+        let mut connection = database.connect();
+        for _ in 0..10 {
+            connection.insert(table, b"row").unwrap();
+        }
+        drop(connection);
+        database.checkpoint().unwrap();
+        drop(database);
+        assert!(Database::fsck(&config, false).unwrap().is_clean());
+
+        // A stored length longer than the page
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .open(dir.path().join("1.fdb"))
+            .unwrap();
+        file.write_all_at(&[0xff; 4], 4).unwrap();
+        let report = Database::fsck(&config, false).unwrap();
+        assert!(matches!(
+            &report.problems[..],
+            [Problem::UnreadablePage { page_id, .. }] if page_id.page_no == 0
+        ));
+        let report = Database::fsck(&config, true).unwrap();
+        assert_eq!(report.repaired, 1);
+        assert!(Database::fsck(&config, false).unwrap().is_clean());
+        let database = Database::with_config(&config).unwrap();
+        assert!(database.connect().scan(table).unwrap().len() < 10);
+    }
+
+    #[test]
+    fn test_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open(dir.path());
+        let start = database.pages().wal().unwrap().end().0;
+        let table = database.create_table().unwrap();
+        let mut connection = database.connect();
+        let id = connection.insert(table, b"a").unwrap();
+        connection.update(id, b"b").unwrap();
+        connection.delete(id).unwrap();
+        let long = format!(r#"{{"id": 1, "text": "{}"}}"#, "x".repeat(300));
+        let spilled = connection.insert(table, long.as_bytes()).unwrap();
+
+        let mut changes = database.changes(start);
+        let changes: Vec<_> = changes.by_ref().map(|change| change.unwrap()).collect();
+        let rows: Vec<_> = changes
+            .iter()
+            .map(|change| {
+                (
+                    change.row,
+                    change.before.as_deref(),
+                    change.after.as_deref(),
+                )
+            })
+            .collect();
+        // Nothing of creating the table, and the spilled row whole
+        assert_eq!(
+            rows,
+            [
+                (id, None, Some(&b"a"[..])),
+                (id, Some(&b"a"[..]), Some(&b"b"[..])),
+                (id, Some(&b"b"[..]), None),
+                (spilled, None, Some(long.as_bytes())),
+            ]
+        );
+        assert!(changes.windows(2).all(|pair| pair[0].lsn < pair[1].lsn));
+        assert!(changes[0].time <= SystemTime::now());
+
+        // Later commits are picked up where the stream left off
+        let mut stream = database.changes(changes[3].lsn + 1);
+        assert!(stream.next().is_none());
+        connection.delete(spilled).unwrap();
+        let change = stream.next().unwrap().unwrap();
+        assert_eq!(change.before.as_deref(), Some(long.as_bytes()));
+        assert_eq!(change.after, None);
+    }
+
+    #[test]
+    fn test_buffer_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open(dir.path());
+        let table = database.create_table().unwrap();
+        let empty = database.create_table().unwrap();
+        let mut connection = database.connect();
+        connection.insert(table, b"a").unwrap();
+        connection.scan(table).unwrap();
+        let (pool, tables) = database.buffer_stats();
+        assert!(pool.hits > 0);
+        assert_eq!(tables, [(table, 1, 1), (empty, 0, 0)]);
+
+        // Listed in SQL after a row for the pool as a whole
+        let mut session = crate::sql::SqlSession::new(connection);
+        let result = session
+            .execute("SELECT * FROM information_schema.buffer_stats")
+            .remove(0)
+            .unwrap();
+        assert_eq!(result.columns.len(), 6);
+        assert_eq!(result.rows.len(), 3);
+        assert_eq!(result.rows[0][0], None);
+        assert!(result.rows[0][5].as_deref().unwrap().starts_with("0."));
+        let table_row = ["1", "1", "1"].map(|count| Some(count.to_string()));
+        assert_eq!(result.rows[1][..3], table_row);
+        assert_eq!(result.rows[1][3..], [None, None, None]);
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let database = open(Path::new(IN_MEMORY));
+        let table = database.create_table().unwrap();
+        let mut connection = database.connect();
+        // More pages than the cache holds, so some are evicted and read back
+        let rows: Vec<_> = (0..100u8).map(|i| [i; 40]).collect();
+        connection.insert_batch(table, &rows).unwrap();
+        connection.begin().unwrap();
+        connection.insert(table, b"undone").unwrap();
+        connection.rollback().unwrap();
+        let scanned = connection.scan(table).unwrap();
+        assert_eq!(scanned.len(), 100);
+        assert_eq!(scanned[99].data, rows[99]);
+        database.pages().checkpoint().unwrap();
+        assert!(!Path::new(IN_MEMORY).exists());
+
+        // It can still be copied to disk, and opened from there
+        let dir = tempfile::tempdir().unwrap();
+        database.pages().backup(dir.path()).unwrap();
+        drop(connection);
+        drop(database);
+        let database = open(dir.path());
+        assert_eq!(database.connect().scan(table).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open(dir.path());
+        let mut config = database.config.lock().unwrap().clone();
+        assert!(database.reload(&config).unwrap().is_empty());
+
+        config.storage.cache_size = 50;
+        config.logging.slow_query_ms = Some(0);
+        assert_eq!(
+            database.reload(&config).unwrap(),
+            ["storage.cache_size", "logging.slow_query_ms"]
+        );
+        assert_eq!(database.pages().stats().capacity, 50);
+        assert_eq!(database.slow_query_ms.load(Ordering::Relaxed), 0);
+
+        // Nothing is applied when anything can't be
+        let mut changed = config.clone();
+        changed.storage.cache_size = 5;
+        changed.storage.page_size = 256;
+        let result = database.reload(&changed);
+        assert!(matches!(
+            result,
+            Err(DatabaseError::Config(ConfigError::NotReloadable(setting)))
+                if setting == "storage.page_size"
+        ));
+        let mut changed = config.clone();
+        changed.storage.cache_size = 5;
+        changed.logging.level = "loud".to_string();
+        let result = database.reload(&changed);
+        assert!(matches!(result, Err(DatabaseError::Logging(_))));
+        assert_eq!(database.pages().stats().capacity, 50);
+
+        config.logging.slow_query_ms = None;
+        database.reload(&config).unwrap();
+        assert_eq!(database.slow_query_ms.load(Ordering::Relaxed), u64::MAX);
+    }
+
+    #[test]
+    fn test_set_global() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open(dir.path());
+        let mut session = crate::sql::SqlSession::new(database.connect());
+        let mut code = |sql: &str| {
+            session
+                .execute(sql)
+                .remove(0)
+                .err()
+                .map(|e| e.code().to_string())
+        };
+
+        // The pool grows or shrinks while the database is open
+        assert_eq!(code("SET GLOBAL cache_size = 20"), None);
+        assert_eq!(database.pages().stats().capacity, 20);
+        let config = database.config.lock().unwrap().clone();
+        assert_eq!(config.storage.cache_size, 20);
+        assert!(config
+            .describe_sources()
+            .contains("storage.cache_size: 20 (SET GLOBAL)\n"));
+
+        // Nothing changes if the setting can't be
+        assert_eq!(code("SET GLOBAL cache_size TO 0").as_deref(), Some("22023"));
+        assert_eq!(
+            code("SET GLOBAL page_size = 8192").as_deref(),
+            Some("55P02")
+        );
+        assert_eq!(code("SET GLOBAL cache = 1").as_deref(), Some("42704"));
+        assert_eq!(database.pages().stats().capacity, 20);
+
+        assert_eq!(
+            database.set_global("storage.cache_size", "30").unwrap(),
+            ["storage.cache_size"]
+        );
+        assert_eq!(database.pages().stats().capacity, 30);
+
+        // Only superusers may
+        database.create_user("bob", "pw", false).unwrap();
+        let mut bob = crate::sql::SqlSession::for_user(database.connect(), "bob");
+        let result = bob.execute("SET GLOBAL cache_size = 10").remove(0);
+        assert_eq!(result.unwrap_err().code(), "42501");
+        assert_eq!(database.pages().stats().capacity, 30);
+    }
+
+    #[test]
+    fn test_slow_query_log_redacts_passwords() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(&dir.path().join("db"));
+        config.logging.level = "warn".to_string();
+        config.logging.file = dir.path().join("ferrodb.log").to_str().unwrap().to_string();
+        config.logging.slow_query_ms = Some(0);
+        let _logger = logging::TEST_LOGGER
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        logging::init_logging(&config.logging).unwrap();
+        let database = Database::with_config(&config).unwrap();
+        let mut session = crate::sql::SqlSession::new(database.connect());
+        session
+            .execute("CREATE USER bob WITH PASSWORD 'hunter2'")
+            .pop()
+            .unwrap()
+            .unwrap();
+        config.logging.level = "off".to_string();
+        logging::init_logging(&config.logging).unwrap();
+
+        let log = std::fs::read_to_string(&config.logging.file).unwrap();
+        let line = log
+            .lines()
+            .find(|line| line.contains("slow query") && line.contains("CREATE USER bob"))
+            .unwrap();
+        assert!(line.contains("PASSWORD '***'"));
+        assert!(!log.contains("hunter2"));
+    }
+}
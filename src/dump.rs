@@ -0,0 +1,326 @@
+//! Dumping a database as the SQL that recreates it: `CREATE TABLE` for
+//! each table, `INSERT` for its rows, then its indexes and triggers, so
+//! that running the dump in a new database with `ferrodb -f` copies it
+//! whatever the two store their pages like.
+//!
+//! Tables are named by number, which a new database gives them in the
+//! order they are created, a partitioned table's partitions straight
+//! after it, so the dump creates them in id order for each to keep its
+//! number. Rows are inserted into a partitioned table rather than its
+//! partitions, which place them anew, and get new ids. Users, their
+//! privileges and registered functions aren't carried over.
+
+use crate::config::Compression;
+use crate::database::{Connection, DatabaseError, RowId, TableId};
+use crate::external::ColumnType;
+use crate::partition::PartitionScheme;
+use crate::syntax::{Statement, Value};
+use crate::trigger::Timing;
+use std::io::{self, Write};
+use thiserror::Error;
+
+/// Rows inserted by each `INSERT`.
+const BATCH_SIZE: usize = 100;
+
+#[derive(Debug, Error)]
+pub enum DumpError {
+    #[error("could not write the dump: {0}")]
+    Write(#[from] io::Error),
+
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+
+    /// A row a SQL string can't hold: one that isn't UTF-8, or holds a
+    /// line break or NUL
+    #[error("row {0} can't be written as a SQL string")]
+    Unrepresentable(RowId),
+}
+
+/// What a dump held.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DumpStats {
+    pub tables: usize,
+    pub rows: usize,
+}
+
+impl Connection<'_> {
+    /// Write the SQL that recreates the database to `out`. Rows written
+    /// meanwhile may or may not be included, unless the dump runs in a
+    /// transaction.
+    pub fn dump(&mut self, out: impl Write) -> Result<DumpStats, DumpError> {
+        let database = self.database();
+        let mut out = io::BufWriter::new(out);
+        let mut stats = DumpStats::default();
+
+        // Partitions are created with their tables
+        let tables: Vec<_> = database
+            .tables()
+            .into_iter()
+            .filter(|&table| database.parent_table(table) == table)
+            .collect();
+        for &table in &tables {
+            writeln!(out, "{};", create_table(self, table))?;
+            stats.tables += 1;
+        }
+        for &table in &tables {
+            if database.external_table(table).is_some() {
+                continue;
+            }
+            let mut batch = Vec::new();
+            self.scan_each(table, |row| {
+                let data = std::str::from_utf8(&row.data)
+                    .ok()
+                    .filter(|data| !data.contains(['\n', '\0']))
+                    .ok_or(DumpError::Unrepresentable(row.id))?;
+                batch.push(format!("({})", quote(data)));
+                stats.rows += 1;
+                if batch.len() == BATCH_SIZE {
+                    insert(&mut out, table, &mut batch)?;
+                }
+                Ok::<_, DumpError>(())
+            })?;
+            insert(&mut out, table, &mut batch)?;
+        }
+
+        for index in database.unique.read().unwrap().iter() {
+            let kind = if index.primary {
+                "PRIMARY KEY"
+            } else {
+                "UNIQUE INDEX"
+            };
+            let column = index.column.as_deref().unwrap_or("data");
+            let table = index.table;
+            writeln!(
+                out,
+                "CREATE {} {} ON {} ({});",
+                kind, index.name, table, column
+            )?;
+        }
+        for index in database.fulltext.read().unwrap().iter() {
+            let table = index.table;
+            writeln!(
+                out,
+                "CREATE FULLTEXT INDEX {} ON {} (data);",
+                index.name, table
+            )?;
+        }
+        for trigger in database.triggers.read().unwrap().iter() {
+            let timing = match trigger.timing {
+                Timing::Before => "BEFORE",
+                Timing::After => "AFTER",
+            };
+            writeln!(
+                out,
+                "CREATE TRIGGER {} {} {} ON {} FOR EACH ROW EXECUTE {};",
+                trigger.name,
+                timing,
+                trigger.event.name(),
+                trigger.table,
+                action(&trigger.action),
+            )?;
+        }
+        out.flush()?;
+        Ok(stats)
+    }
+}
+
+/// Write an `INSERT` of the rows in `batch` into `table`, if there are
+/// any, and empty it.
+fn insert(out: &mut impl Write, table: TableId, batch: &mut Vec<String>) -> io::Result<()> {
+    if !batch.is_empty() {
+        writeln!(out, "INSERT INTO {} VALUES {};", table, batch.join(", "))?;
+        batch.clear();
+    }
+    Ok(())
+}
+
+/// The `CREATE TABLE` or `CREATE EXTERNAL TABLE` that makes `table`.
+fn create_table(connection: &Connection, table: TableId) -> String {
+    let database = connection.database();
+    if let Some(external) = database.external_table(table) {
+        let columns: Vec<_> = external
+            .columns
+            .iter()
+            .map(|column| {
+                let kind = match column.kind {
+                    ColumnType::Text => "TEXT",
+                    ColumnType::Integer => "INTEGER",
+                    ColumnType::Real => "REAL",
+                    ColumnType::Boolean => "BOOLEAN",
+                };
+                format!("{} {}", column.name, kind)
+            })
+            .collect();
+        let options = &external.options;
+        let byte = |byte: u8| quote(&char::from(byte).to_string());
+        return format!(
+            "CREATE EXTERNAL TABLE ({}) LOCATION {} WITH (HEADER {}, DELIMITER {}, QUOTE {}, ESCAPE {})",
+            columns.join(", "),
+            quote(&external.location.to_string_lossy()),
+            if options.header { "TRUE" } else { "FALSE" },
+            byte(options.delimiter),
+            byte(options.quote),
+            byte(options.escape),
+        );
+    }
+
+    let mut sql = "CREATE TABLE".to_string();
+    let options = database.table_options(table);
+    let mut given = Vec::new();
+    if let Some(fill_factor) = options.fill_factor {
+        given.push(format!("FILL_FACTOR = {}", fill_factor));
+    }
+    if let Some(compression) = options.compression {
+        let name = match compression {
+            Compression::Lz => "lz",
+            Compression::None => "none",
+        };
+        given.push(format!("COMPRESSION = {}", quote(name)));
+    }
+    if options.bloom_filter {
+        given.push("BLOOM_FILTER = TRUE".to_string());
+    }
+    if let Some(column) = &options.ttl_column {
+        given.push(format!("TTL_COLUMN = {}", quote(column)));
+    }
+    if !given.is_empty() {
+        sql.push_str(&format!(" WITH ({})", given.join(", ")));
+    }
+    if let Some(partitioning) = database.partitioning(table) {
+        let key = partitioning.column.as_deref().unwrap_or("data");
+        match partitioning.scheme {
+            PartitionScheme::Hash(count) => sql.push_str(&format!(
+                " PARTITION BY HASH ({}) PARTITIONS {}",
+                key, count
+            )),
+            PartitionScheme::Range(bounds) => {
+                let bounds: Vec<_> = bounds.iter().map(|bound| quote(bound)).collect();
+                sql.push_str(&format!(
+                    " PARTITION BY RANGE ({}) SPLIT AT ({})",
+                    key,
+                    bounds.join(", ")
+                ));
+            }
+        }
+    }
+    sql
+}
+
+/// A trigger's action, an `Insert`, `Update` or `Delete`.
+fn action(action: &Statement) -> String {
+    match action {
+        Statement::Insert { table, values } => {
+            let values: Vec<_> = values.iter().map(|v| format!("({})", value(v))).collect();
+            format!("INSERT INTO {} VALUES {}", table, values.join(", "))
+        }
+        Statement::Update {
+            table,
+            row,
+            value: data,
+        } => format!(
+            "UPDATE {} SET data = {} WHERE id = {}",
+            table,
+            value(data),
+            value(row)
+        ),
+        Statement::Delete { table, row } => {
+            format!("DELETE FROM {} WHERE id = {}", table, value(row))
+        }
+        _ => unreachable!("a trigger's action is a write"),
+    }
+}
+
+/// `value` as it would be written in a statement.
+fn value(value: &Value) -> String {
+    match value {
+        Value::String(text) => quote(text),
+        Value::Param(n) => format!("${}", n),
+        Value::New => "NEW".to_string(),
+        Value::Old => "OLD".to_string(),
+        Value::Null => "NULL".to_string(),
+        // A keyword, so only read before a string
+        Value::Call { name, args } if name == "timestamp" && args.len() == 1 => {
+            format!("TIMESTAMP {}", self::value(&args[0]))
+        }
+        Value::Call { name, args } => {
+            let args: Vec<_> = args.iter().map(self::value).collect();
+            format!("{}({})", name, args.join(", "))
+        }
+    }
+}
+
+/// `text` as a SQL string, its quotes doubled.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::open_logged;
+    use crate::sql::SqlSession;
+    use std::fs;
+
+    #[test]
+    fn test_dump() {
+        let (old_dir, new_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let people = old_dir.path().join("people.csv");
+        fs::write(&people, "ann\n").unwrap();
+        let old = open_logged(&old_dir.path().join("db"));
+        let mut session = SqlSession::new(old.connect());
+        let sql = format!(
+            "CREATE TABLE WITH (FILL_FACTOR = 80, COMPRESSION = 'none');
+             CREATE TABLE PARTITION BY RANGE (day) SPLIT AT ('2026-01-01');
+             CREATE EXTERNAL TABLE (name TEXT) LOCATION '{}';
+             CREATE UNIQUE INDEX letters ON 1 (data);
+             CREATE FULLTEXT INDEX words ON 1 (data);
+             CREATE TRIGGER copy AFTER INSERT ON 1 EXECUTE INSERT INTO 2 VALUES (NEW);
+             INSERT INTO 1 VALUES ('a'), ('it''s');
+             INSERT INTO 2 VALUES ('{{\"day\": \"2026-01-02\"}}')",
+            people.display()
+        );
+        for result in session.execute(&sql) {
+            result.unwrap();
+        }
+        let mut dumped = Vec::new();
+        let stats = session.connection_mut().dump(&mut dumped).unwrap();
+        assert_eq!(stats, DumpStats { tables: 3, rows: 5 });
+        let dumped = String::from_utf8(dumped).unwrap();
+        assert_eq!(
+            dumped,
+            format!(
+                "CREATE TABLE WITH (FILL_FACTOR = 80, COMPRESSION = 'none');
+CREATE TABLE PARTITION BY RANGE (day) SPLIT AT ('2026-01-01');
+CREATE EXTERNAL TABLE (name TEXT) LOCATION '{}' \
+WITH (HEADER FALSE, DELIMITER ',', QUOTE '\"', ESCAPE '\"');
+INSERT INTO 1 VALUES ('a'), ('it''s');
+INSERT INTO 2 VALUES ('a'), ('it''s'), ('{{\"day\": \"2026-01-02\"}}');
+CREATE UNIQUE INDEX letters ON 1 (data);
+CREATE FULLTEXT INDEX words ON 1 (data);
+CREATE TRIGGER copy AFTER INSERT ON 1 FOR EACH ROW EXECUTE INSERT INTO 2 VALUES (NEW);
+",
+                people.display()
+            )
+        );
+
+        // Running the dump makes the same database, triggers only
+        // created once the rows are in
+        let new = open_logged(new_dir.path());
+        let mut session = SqlSession::new(new.connect());
+        for result in session.execute_script(&dumped) {
+            result.unwrap();
+        }
+        let mut again = Vec::new();
+        session.connection_mut().dump(&mut again).unwrap();
+        assert_eq!(String::from_utf8(again).unwrap(), dumped);
+
+        session
+            .connection_mut()
+            .insert(TableId(1), b"c\nd")
+            .unwrap();
+        assert!(matches!(
+            session.connection_mut().dump(io::sink()),
+            Err(DumpError::Unrepresentable(_))
+        ));
+    }
+}
@@ -0,0 +1,385 @@
+//! Encoding statement results for whoever reads them: laid out for
+//! people as psql lays out tables, or for programs as CSV, JSON or
+//! MessagePack. The shell prints results with them, and the server
+//! encodes the results of SQL a client sends as the client asks, so that
+//! neither has rows to re-format itself.
+//!
+//! A NULL is left empty in a table, as psql leaves it, and is an
+//! unquoted empty field in CSV, where an empty string is quoted, and
+//! `null` or nil in JSON and MessagePack.
+//!
+//! A `ResultFormat` is one of the encodings built in; anything else that
+//! implements `ResultEncoder` can encode results too.
+
+use crate::sql::StatementResult;
+use std::io::{self, Write};
+
+/// Writes statement results in some encoding.
+pub trait ResultEncoder {
+    fn encode(&self, result: &StatementResult, out: &mut dyn Write) -> io::Result<()>;
+}
+
+/// The encodings built in. Results without rows are their command tag on
+/// a line in every format but MessagePack, which always encodes the
+/// result whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultFormat {
+    /// Columns padded to line up, with a row count
+    Aligned,
+    /// Values separated by `|`, with a row count
+    Unaligned,
+    Csv,
+    /// An array of JSON objects, one per row, keyed by column
+    Json,
+    /// A map of `columns`, an array of strings, `rows`, an array of arrays
+    /// of strings or nil, and `tag`, a string
+    MessagePack,
+}
+
+impl ResultFormat {
+    pub const ALL: [ResultFormat; 5] = [
+        ResultFormat::Aligned,
+        ResultFormat::Unaligned,
+        ResultFormat::Csv,
+        ResultFormat::Json,
+        ResultFormat::MessagePack,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "aligned" | "table" => Some(ResultFormat::Aligned),
+            "unaligned" => Some(ResultFormat::Unaligned),
+            "csv" => Some(ResultFormat::Csv),
+            "json" => Some(ResultFormat::Json),
+            "msgpack" | "messagepack" => Some(ResultFormat::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ResultFormat::Aligned => "aligned",
+            ResultFormat::Unaligned => "unaligned",
+            ResultFormat::Csv => "csv",
+            ResultFormat::Json => "json",
+            ResultFormat::MessagePack => "msgpack",
+        }
+    }
+
+    /// `result` encoded, as bytes.
+    pub fn render(self, result: &StatementResult) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(result, &mut out)
+            .expect("writing to a Vec doesn't fail");
+        out
+    }
+}
+
+impl ResultEncoder for ResultFormat {
+    fn encode(&self, result: &StatementResult, out: &mut dyn Write) -> io::Result<()> {
+        if *self == ResultFormat::MessagePack {
+            return out.write_all(&message_pack(result));
+        }
+        let text = if result.columns.is_empty() {
+            format!("{}\n", result.tag)
+        } else {
+            match self {
+                ResultFormat::Aligned => aligned(result),
+                ResultFormat::Unaligned => unaligned(result),
+                ResultFormat::Csv => csv(result),
+                _ => json(result),
+            }
+        };
+        out.write_all(text.as_bytes())
+    }
+}
+
+/// An aligned table with a row count and a blank line after.
+fn aligned(result: &StatementResult) -> String {
+    let mut widths: Vec<usize> = result.columns.iter().map(|c| width(c)).collect();
+    for row in &result.rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(self::width(shown(value)));
+        }
+    }
+
+    let mut out = String::new();
+    // Headings are centred, values left aligned
+    let headings: Vec<String> = result
+        .columns
+        .iter()
+        .zip(&widths)
+        .map(|(column, &width)| {
+            let left = (width - self::width(column)) / 2;
+            pad(&format!("{}{}", " ".repeat(left), column), width)
+        })
+        .collect();
+    line(&mut out, &headings);
+    let rule: Vec<String> = widths.iter().map(|&width| "-".repeat(width + 2)).collect();
+    out.push_str(&rule.join("+"));
+    out.push('\n');
+    for row in &result.rows {
+        let values: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, &width)| pad(shown(value), width))
+            .collect();
+        line(&mut out, &values);
+    }
+    out.push_str(&row_count(result));
+    out.push('\n');
+    out
+}
+
+fn unaligned(result: &StatementResult) -> String {
+    let mut out = result.columns.join("|") + "\n";
+    for row in &result.rows {
+        let values: Vec<&str> = row.iter().map(shown).collect();
+        out.push_str(&values.join("|"));
+        out.push('\n');
+    }
+    out.push_str(&row_count(result));
+    out
+}
+
+fn csv(result: &StatementResult) -> String {
+    let line = |values: Vec<Option<&str>>| {
+        let values: Vec<String> = values.into_iter().map(csv_field).collect();
+        values.join(",") + "\n"
+    };
+    let mut out = line(result.columns.iter().map(|c| Some(c.as_str())).collect());
+    for row in &result.rows {
+        out.push_str(&line(row.iter().map(Option::as_deref).collect()));
+    }
+    out
+}
+
+/// A CSV field, quoted if it's empty or holds a delimiter, quote or line
+/// break, so that only a NULL is left empty.
+fn csv_field(value: Option<&str>) -> String {
+    let Some(value) = value else {
+        return String::new();
+    };
+    if value.is_empty() || value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn json(result: &StatementResult) -> String {
+    let rows: Vec<String> = result
+        .rows
+        .iter()
+        .map(|row| {
+            let fields: Vec<String> = result
+                .columns
+                .iter()
+                .zip(row)
+                .map(|(column, value)| {
+                    let value = value.as_deref().map_or("null".to_string(), json_string);
+                    format!("{}:{}", json_string(column), value)
+                })
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        })
+        .collect();
+    format!("[{}]\n", rows.join(",\n "))
+}
+
+/// `text` as a JSON string.
+pub(crate) fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn message_pack(result: &StatementResult) -> Vec<u8> {
+    let mut out = vec![0x83];
+    pack_str(&mut out, "columns");
+    pack_strs(&mut out, &result.columns);
+    pack_str(&mut out, "rows");
+    pack_len(&mut out, result.rows.len(), 0x90, 0xdc);
+    for row in &result.rows {
+        pack_len(&mut out, row.len(), 0x90, 0xdc);
+        for value in row {
+            match value {
+                Some(value) => pack_str(&mut out, value),
+                None => out.push(0xc0),
+            }
+        }
+    }
+    pack_str(&mut out, "tag");
+    pack_str(&mut out, &result.tag);
+    out
+}
+
+fn pack_strs(out: &mut Vec<u8>, strs: &[String]) {
+    pack_len(out, strs.len(), 0x90, 0xdc);
+    for s in strs {
+        pack_str(out, s);
+    }
+}
+
+fn pack_str(out: &mut Vec<u8>, s: &str) {
+    match s.len() {
+        len @ 0..=31 => out.push(0xa0 | len as u8),
+        len @ 32..=0xff => out.extend([0xd9, len as u8]),
+        len => pack_len(out, len, 0, 0xda),
+    }
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// The length of an array, or with no `fixed` marker a string's longer
+/// than a byte holds: in the marker's low bits if it has one and the
+/// length fits, else after `wide`, a 16-bit marker one below the 32-bit.
+fn pack_len(out: &mut Vec<u8>, len: usize, fixed: u8, wide: u8) {
+    match len {
+        0..=15 if fixed != 0 => out.push(fixed | len as u8),
+        0..=0xffff => {
+            out.push(wide);
+            out.extend((len as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(wide + 1);
+            out.extend((len as u32).to_be_bytes());
+        }
+    }
+}
+
+fn row_count(result: &StatementResult) -> String {
+    let count = result.rows.len();
+    format!("({} {})\n", count, if count == 1 { "row" } else { "rows" })
+}
+
+fn line(out: &mut String, cells: &[String]) {
+    let cells: Vec<String> = cells.iter().map(|cell| format!(" {} ", cell)).collect();
+    out.push_str(cells.join("|").trim_end());
+    out.push('\n');
+}
+
+/// A value as a table shows it.
+fn shown(value: &Option<String>) -> &str {
+    value.as_deref().unwrap_or_default()
+}
+
+/// The columns `text` takes up, counting characters rather than bytes.
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+fn pad(text: &str, width: usize) -> String {
+    format!(
+        "{}{}",
+        text,
+        " ".repeat(width.saturating_sub(self::width(text)))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let result = StatementResult {
+            columns: vec!["id".to_string(), "data".to_string()],
+            rows: vec![
+                vec![Some("1:0:0".to_string()), Some("héllo".to_string())],
+                vec![Some("1:0:1".to_string()), Some("a, \"b\"".to_string())],
+            ],
+            tag: "SELECT 2".to_string(),
+        };
+        let text = |format: ResultFormat| String::from_utf8(format.render(&result)).unwrap();
+        assert_eq!(
+            text(ResultFormat::Aligned),
+            concat!(
+                "  id   |  data\n",
+                "-------+--------\n",
+                " 1:0:0 | héllo\n",
+                " 1:0:1 | a, \"b\"\n",
+                "(2 rows)\n",
+                "\n",
+            )
+        );
+        assert_eq!(
+            text(ResultFormat::Unaligned),
+            "id|data\n1:0:0|héllo\n1:0:1|a, \"b\"\n(2 rows)\n"
+        );
+        assert_eq!(
+            text(ResultFormat::Csv),
+            "id,data\n1:0:0,héllo\n1:0:1,\"a, \"\"b\"\"\"\n"
+        );
+        assert_eq!(
+            text(ResultFormat::Json),
+            concat!(
+                "[{\"id\":\"1:0:0\",\"data\":\"héllo\"},\n",
+                " {\"id\":\"1:0:1\",\"data\":\"a, \\\"b\\\"\"}]\n",
+            )
+        );
+
+        let begin = StatementResult {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            tag: "BEGIN".to_string(),
+        };
+        assert_eq!(ResultFormat::Csv.render(&begin), b"BEGIN\n");
+        let mut expected = vec![0x83, 0xa7];
+        expected.extend(b"columns");
+        expected.extend([0x90, 0xa4]);
+        expected.extend(b"rows");
+        expected.extend([0x90, 0xa3]);
+        expected.extend(b"tag");
+        expected.push(0xa5);
+        expected.extend(b"BEGIN");
+        assert_eq!(ResultFormat::MessagePack.render(&begin), expected);
+
+        // Strings and arrays too long for their length to fit the marker
+        let long = StatementResult {
+            columns: vec!["x".repeat(40)],
+            rows: vec![vec![Some("y".repeat(300))]; 16],
+            tag: "SELECT 16".to_string(),
+        };
+        let packed = ResultFormat::MessagePack.render(&long);
+        assert_eq!(&packed[9..12], &[0x91, 0xd9, 40]);
+        assert_eq!(&packed[52..58], &[0xa4, b'r', b'o', b'w', b's', 0xdc]);
+        assert_eq!(&packed[58..64], &[0, 16, 0x91, 0xda, 0x01, 0x2c]);
+
+        for format in ResultFormat::ALL {
+            assert_eq!(ResultFormat::parse(format.name()), Some(format));
+        }
+    }
+
+    #[test]
+    fn test_encode_null() {
+        let result = StatementResult {
+            columns: vec!["a".to_string(), "b".to_string()],
+            rows: vec![vec![None, Some(String::new())]],
+            tag: "SELECT 1".to_string(),
+        };
+        let text = |format: ResultFormat| String::from_utf8(format.render(&result)).unwrap();
+
+        // Only where the format can tell them apart
+        assert_eq!(
+            text(ResultFormat::Aligned),
+            " a | b\n---+---\n   |\n(1 row)\n\n"
+        );
+        assert_eq!(text(ResultFormat::Unaligned), "a|b\n|\n(1 row)\n");
+        assert_eq!(text(ResultFormat::Csv), "a,b\n,\"\"\n");
+        assert_eq!(text(ResultFormat::Json), "[{\"a\":null,\"b\":\"\"}]\n");
+        let packed = ResultFormat::MessagePack.render(&result);
+        let rows = packed.windows(5).position(|w| w == b"\xa4rows").unwrap();
+        assert_eq!(&packed[rows + 5..rows + 9], &[0x91, 0x92, 0xc0, 0xa0]);
+    }
+}
@@ -0,0 +1,215 @@
+//! One error type for everything the crate reports, for callers that would
+//! rather handle a single one: each error has a stable `ErrorCode`, the
+//! number the server protocol sends, and the SQLSTATE code Postgres would
+//! report for it, and a statement that didn't parse also says where.
+
+use crate::config::ConfigError;
+use crate::database::DatabaseError;
+use crate::logging::LoggingError;
+use crate::server::{ClientError, ErrorCode};
+use crate::sql::{self, SqlError};
+use crate::sqlite::SqliteImportError;
+use crate::storage::{PageIOError, PageManagerError, TransactionError};
+use std::fmt::{self, Display};
+use thiserror::Error;
+
+/// A place in a statement's text, counting lines and columns from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The text in a statement from `start` up to `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceSpan {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Display for SourceSpan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.start.line, self.start.column)
+    }
+}
+
+/// Any error the crate reports, each kind converting into it with `?`.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum FerroError {
+    /// A statement that didn't parse
+    #[error("{message} at {span}")]
+    Syntax { message: String, span: SourceSpan },
+
+    /// A statement that failed as it ran
+    #[error(transparent)]
+    Statement(SqlError),
+
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+
+    #[error(transparent)]
+    Storage(#[from] PageManagerError),
+
+    #[error(transparent)]
+    Transaction(#[from] TransactionError),
+
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+
+    #[error(transparent)]
+    Import(#[from] SqliteImportError),
+
+    #[error(transparent)]
+    Client(#[from] ClientError),
+
+    #[error(transparent)]
+    Logging(#[from] LoggingError),
+}
+
+impl From<SqlError> for FerroError {
+    fn from(error: SqlError) -> Self {
+        match error.span() {
+            Some(span) => Self::Syntax {
+                message: error.message().to_string(),
+                span,
+            },
+            None => Self::Statement(error),
+        }
+    }
+}
+
+impl FerroError {
+    /// The error's code, as the server protocol sends it.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Syntax { .. } | Self::Import(_) => ErrorCode::Statement,
+            Self::Statement(error) => match error.code() {
+                "42P01" => ErrorCode::NoSuchTable,
+                "P0002" => ErrorCode::NoSuchRow,
+                "54000" => ErrorCode::RowTooLarge,
+                "42501" => ErrorCode::PermissionDenied,
+                "23505" => ErrorCode::UniqueViolation,
+                "53300" | "53400" => ErrorCode::ResourceLimit,
+                "25001" | "25P01" | "25P02" => ErrorCode::TransactionState,
+                "55P03" => ErrorCode::Conflict,
+                // Storage and file failures
+                "XX000" | "58030" => ErrorCode::Internal,
+                _ => ErrorCode::Statement,
+            },
+            Self::Database(error) => match error {
+                DatabaseError::NoSuchTable(_) => ErrorCode::NoSuchTable,
+                DatabaseError::NoSuchRow(_) => ErrorCode::NoSuchRow,
+                DatabaseError::RowTooLarge { .. } => ErrorCode::RowTooLarge,
+                DatabaseError::PermissionDenied(_) => ErrorCode::PermissionDenied,
+                DatabaseError::UniqueViolation { .. } => ErrorCode::UniqueViolation,
+                DatabaseError::TransactionInProgress | DatabaseError::NoTransaction => {
+                    ErrorCode::TransactionState
+                }
+                DatabaseError::TransactionError(error) => transaction_code(error),
+                _ => ErrorCode::Internal,
+            },
+            Self::Transaction(error) => transaction_code(error),
+            Self::Client(ClientError::Refused { code, .. } | ClientError::Query { code, .. }) => {
+                *code
+            }
+            Self::Client(_) => ErrorCode::Protocol,
+            Self::Storage(_) | Self::Config(_) | Self::Logging(_) => ErrorCode::Internal,
+        }
+    }
+
+    /// The SQLSTATE code Postgres would report for the error.
+    pub fn sqlstate(&self) -> &'static str {
+        match self {
+            Self::Syntax { .. } => "42601",
+            Self::Statement(error) => error.code(),
+            Self::Database(error) => sql::sqlstate(error),
+            Self::Transaction(error) => sql::transaction_sqlstate(error),
+            Self::Storage(PageManagerError::PageIOError(PageIOError::IoError(_))) => "58030",
+            Self::Storage(_) | Self::Logging(_) => "XX000",
+            Self::Config(error) => sql::config_sqlstate(error),
+            Self::Import(error) => sql::import_sqlstate(error),
+            Self::Client(ClientError::IoError(_)) => "08006",
+            Self::Client(ClientError::UnexpectedMessage) => "08P01",
+            Self::Client(ClientError::Refused { code, .. } | ClientError::Query { code, .. }) => {
+                code.sqlstate()
+            }
+        }
+    }
+
+    /// Where in a statement that didn't parse it went wrong.
+    pub fn span(&self) -> Option<SourceSpan> {
+        match self {
+            Self::Syntax { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+}
+
+fn transaction_code(error: &TransactionError) -> ErrorCode {
+    match error {
+        TransactionError::LockError(_) | TransactionError::IdleTimeout(_) => ErrorCode::Conflict,
+        _ => ErrorCode::Internal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::temp_database;
+    use crate::database::TableId;
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_ferro_error() {
+        let (_dir, database) = temp_database();
+        let mut session = SqlSession::new(database.connect());
+        let mut failed =
+            |sql: &str| FerroError::from(session.execute(sql).pop().unwrap().unwrap_err());
+
+        // A statement that doesn't parse says where
+        let error = failed("SELECT * FROM 1;\nSELECT * FRUM 1");
+        assert_eq!(error.code(), ErrorCode::Statement);
+        assert_eq!(error.sqlstate(), "42601");
+        let span = error.span().unwrap();
+        assert_eq!(
+            span.start,
+            Position {
+                line: 2,
+                column: 10
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Expected FROM, found FRUM at line 2, column 10"
+        );
+        let error = failed("SELECT 'open");
+        assert_eq!(error.span().unwrap().start.line, 1);
+
+        let error = failed("SELECT * FROM 7");
+        assert!(matches!(error, FerroError::Statement(_)));
+        assert_eq!(error.code(), ErrorCode::NoSuchTable);
+        assert_eq!(error.sqlstate(), "42P01");
+        assert_eq!(error.span(), None);
+
+        // Each kind of error keeps its own message
+        let error = FerroError::from(database.connect().commit().unwrap_err());
+        assert_eq!(error.code(), ErrorCode::TransactionState);
+        assert_eq!(error.sqlstate(), "25P01");
+        assert_eq!(error.to_string(), "No transaction is in progress");
+        let error = FerroError::from(database.connect().scan(TableId(9)).unwrap_err());
+        assert_eq!(
+            (error.code(), error.sqlstate()),
+            (ErrorCode::NoSuchTable, "42P01")
+        );
+        let refused = ClientError::Refused {
+            code: ErrorCode::Authentication,
+            message: "wrong password".to_string(),
+        };
+        let error = FerroError::from(refused);
+        assert_eq!(
+            (error.code(), error.sqlstate()),
+            (ErrorCode::Authentication, "28P01")
+        );
+    }
+}
@@ -0,0 +1,397 @@
+//! External tables, whose rows are read from CSV files on disk each time
+//! the table is scanned rather than loaded into it.
+//!
+//! An external table is a table like any other with no rows of its own,
+//! and a catalog record naming its columns and where its files are: a
+//! directory, whose `.csv` files are read in name order, or a single
+//! file. Each record of a file must have a field for each column that
+//! parses as its type, checked as it's read; a row's data is a JSON
+//! object of the record's fields by column name, with empty fields of
+//! columns other than text as `null`. A row's id is the table, the
+//! file's place in that order and the record's in the file.
+
+use crate::copy::CsvReader;
+use crate::database::{Database, DatabaseError, Row, RowId, TableId};
+use crate::encoding::json_string;
+use crate::syntax::CopyOptions;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Marks a catalog record as an external table.
+const EXTERNAL: u8 = 3;
+
+/// What an external table's column holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ColumnType {
+    Text,
+    Integer,
+    Real,
+    Boolean,
+}
+
+impl ColumnType {
+    /// The type a name in `CREATE EXTERNAL TABLE` stands for.
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        Some(match name.to_uppercase().as_str() {
+            "TEXT" | "VARCHAR" => ColumnType::Text,
+            "INT" | "INTEGER" | "BIGINT" => ColumnType::Integer,
+            "REAL" | "FLOAT" | "DOUBLE" => ColumnType::Real,
+            "BOOL" | "BOOLEAN" => ColumnType::Boolean,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ColumnType::Text => "text",
+            ColumnType::Integer => "an integer",
+            ColumnType::Real => "a real number",
+            ColumnType::Boolean => "a boolean",
+        }
+    }
+
+    /// `field` as JSON, or why it isn't of this type.
+    fn json(self, field: &[u8]) -> Result<String, String> {
+        let text = std::str::from_utf8(field).map_err(|_| "the field isn't UTF-8".to_string())?;
+        if text.is_empty() && self != ColumnType::Text {
+            return Ok("null".to_string());
+        }
+        let bad = || format!("{:?} isn't {}", text, self.name());
+        Ok(match self {
+            ColumnType::Text => json_string(text),
+            ColumnType::Integer => text.parse::<i64>().map_err(|_| bad())?.to_string(),
+            ColumnType::Real => match text.parse::<f64>() {
+                Ok(number) if number.is_finite() => format!("{:?}", number),
+                _ => return Err(bad()),
+            },
+            ColumnType::Boolean => match text.to_lowercase().as_str() {
+                "true" | "t" | "yes" | "y" | "on" | "1" => "true".to_string(),
+                "false" | "f" | "no" | "n" | "off" | "0" => "false".to_string(),
+                _ => return Err(bad()),
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Column {
+    pub(crate) name: String,
+    pub(crate) kind: ColumnType,
+}
+
+/// An external table record: `EXTERNAL`, the table, the location's length
+/// and bytes, whether files have a header, the delimiter, quote and
+/// escape, then the number of columns and each one's name's length and
+/// bytes and its type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExternalTable {
+    pub(crate) table: TableId,
+    pub(crate) location: PathBuf,
+    pub(crate) columns: Vec<Column>,
+    pub(crate) options: CopyOptions,
+}
+
+impl ExternalTable {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![EXTERNAL];
+        bytes.write_u32::<BigEndian>(self.table.0).unwrap();
+        let location = self.location.to_string_lossy();
+        bytes.write_u16::<BigEndian>(location.len() as u16).unwrap();
+        bytes.extend_from_slice(location.as_bytes());
+        bytes.push(self.options.header as u8);
+        bytes.extend([
+            self.options.delimiter,
+            self.options.quote,
+            self.options.escape,
+        ]);
+        bytes
+            .write_u16::<BigEndian>(self.columns.len() as u16)
+            .unwrap();
+        for column in &self.columns {
+            bytes
+                .write_u16::<BigEndian>(column.name.len() as u16)
+                .unwrap();
+            bytes.extend_from_slice(column.name.as_bytes());
+            bytes.push(column.kind as u8);
+        }
+        bytes
+    }
+
+    /// The external table a catalog record holds, or `None` if it holds
+    /// something else.
+    fn decode(bytes: &[u8]) -> Result<Option<Self>, DatabaseError> {
+        if bytes.first() != Some(&EXTERNAL) {
+            return Ok(None);
+        }
+        let decode = || -> std::io::Result<Self> {
+            let mut cursor = Cursor::new(&bytes[1..]);
+            let string = |cursor: &mut Cursor<&[u8]>| -> std::io::Result<String> {
+                let mut bytes = vec![0; cursor.read_u16::<BigEndian>()? as usize];
+                cursor.read_exact(&mut bytes)?;
+                String::from_utf8(bytes).map_err(|_| std::io::ErrorKind::InvalidData.into())
+            };
+            let table = TableId(cursor.read_u32::<BigEndian>()?);
+            let location = PathBuf::from(string(&mut cursor)?);
+            let options = CopyOptions {
+                header: cursor.read_u8()? != 0,
+                delimiter: cursor.read_u8()?,
+                quote: cursor.read_u8()?,
+                escape: cursor.read_u8()?,
+                ..CopyOptions::default()
+            };
+            let mut columns = Vec::new();
+            for _ in 0..cursor.read_u16::<BigEndian>()? {
+                let name = string(&mut cursor)?;
+                let kind = match cursor.read_u8()? {
+                    0 => ColumnType::Text,
+                    1 => ColumnType::Integer,
+                    2 => ColumnType::Real,
+                    3 => ColumnType::Boolean,
+                    _ => return Err(std::io::ErrorKind::InvalidData.into()),
+                };
+                columns.push(Column { name, kind });
+            }
+            Ok(Self {
+                table,
+                location,
+                columns,
+                options,
+            })
+        };
+        decode()
+            .map(Some)
+            .map_err(|_| DatabaseError::CorruptedCatalog)
+    }
+
+    /// Pass each row of the table's files to `each`, or only those of the
+    /// file numbered `only`, until it returns false. Fails with
+    /// `Cancelled` once `cancelled` is set.
+    pub(crate) fn scan(
+        &self,
+        only: Option<u64>,
+        cancelled: &AtomicBool,
+        mut each: impl FnMut(Row) -> bool,
+    ) -> Result<(), DatabaseError> {
+        for (file_no, path) in self.files()?.into_iter().enumerate() {
+            let file_no = file_no as u64;
+            if only.is_some_and(|only| only != file_no) {
+                continue;
+            }
+            let bad = |message: String| DatabaseError::External {
+                table: self.table,
+                message: format!("\"{}\": {}", path.display(), message),
+            };
+            let file = File::open(&path).map_err(|e| bad(e.to_string()))?;
+            let mut reader = CsvReader::new(BufReader::new(file), &self.options);
+            let mut record = || reader.record().map_err(|e| bad(e.to_string()));
+            if self.options.header {
+                if let Some((line, header)) = record()? {
+                    self.check_header(&header)
+                        .map_err(|message| bad(format!("line {}: {}", line, message)))?;
+                }
+            }
+            let mut slot = 0u32;
+            while let Some((line, fields)) = record()? {
+                if cancelled.load(Ordering::Acquire) {
+                    return Err(DatabaseError::Cancelled);
+                }
+                let data = self
+                    .json(&fields)
+                    .map_err(|message| bad(format!("line {}: {}", line, message)))?;
+                let id = RowId {
+                    table: self.table,
+                    page_no: file_no,
+                    slot,
+                };
+                if !each(Row { id, data }) {
+                    return Ok(());
+                }
+                slot = slot
+                    .checked_add(1)
+                    .ok_or_else(|| bad("the file has too many records".to_string()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The files at the table's location, in the order rows are read.
+    fn files(&self) -> Result<Vec<PathBuf>, DatabaseError> {
+        let bad = |e: std::io::Error| DatabaseError::External {
+            table: self.table,
+            message: format!("could not read \"{}\": {}", self.location.display(), e),
+        };
+        if !self.location.is_dir() {
+            return Ok(vec![self.location.clone()]);
+        }
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.location).map_err(bad)? {
+            let path = entry.map_err(bad)?.path();
+            let csv = path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+            if csv && path.is_file() {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    fn check_header(&self, header: &[Vec<u8>]) -> Result<(), String> {
+        let names = self.columns.iter().map(|column| column.name.as_bytes());
+        if header.len() != self.columns.len()
+            || !names
+                .zip(header)
+                .all(|(name, field)| name.eq_ignore_ascii_case(field))
+        {
+            let header: Vec<_> = header
+                .iter()
+                .map(|field| String::from_utf8_lossy(field))
+                .collect();
+            let columns: Vec<_> = self.columns.iter().map(|column| &column.name[..]).collect();
+            return Err(format!(
+                "the header names columns {} rather than {}",
+                header.join(", "),
+                columns.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// A record's fields as a row's data.
+    fn json(&self, fields: &[Vec<u8>]) -> Result<Vec<u8>, String> {
+        if fields.len() != self.columns.len() {
+            return Err(format!(
+                "expected {} fields, found {}",
+                self.columns.len(),
+                fields.len()
+            ));
+        }
+        let mut out = String::from("{");
+        for (i, (column, field)) in self.columns.iter().zip(fields).enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let value = column
+                .kind
+                .json(field)
+                .map_err(|message| format!("column {}: {}", column.name, message))?;
+            out.push_str(&json_string(&column.name));
+            out.push(':');
+            out.push_str(&value);
+        }
+        out.push('}');
+        Ok(out.into_bytes())
+    }
+}
+
+impl Database {
+    /// Create a table whose rows are read from the CSV files at
+    /// `location`, as `options` says to read them.
+    pub(crate) fn create_external_table(
+        &self,
+        columns: Vec<Column>,
+        location: &Path,
+        options: CopyOptions,
+    ) -> Result<TableId, DatabaseError> {
+        // Relative to where the table was created, not where it's read
+        let location = std::path::absolute(location).unwrap_or_else(|_| location.to_path_buf());
+        let table = self.create_table()?;
+        let external = ExternalTable {
+            table,
+            location,
+            columns,
+            options,
+        };
+        self.connect_system()
+            .insert(TableId::CATALOG, &external.encode())?;
+        self.add_external_table(external);
+        Ok(table)
+    }
+
+    /// Every external table in the catalog.
+    pub(crate) fn read_external_tables(&self) -> Result<Vec<ExternalTable>, DatabaseError> {
+        let mut tables = Vec::new();
+        for row in self.connect_system().scan(TableId::CATALOG)? {
+            if let Some(table) = ExternalTable::decode(&row.data)? {
+                tables.push(table);
+            }
+        }
+        Ok(tables)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_config;
+
+    #[test]
+    fn test_external_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(&dir.path().join("db"));
+        let files = dir.path().join("files");
+        fs::create_dir(&files).unwrap();
+        fs::write(files.join("b.csv"), "name,age,score,admin\ncarol,,0.5,no\n").unwrap();
+        fs::write(
+            files.join("a.CSV"),
+            "name,age,score,admin\n\"a, b\",30,1e3,true\n",
+        )
+        .unwrap();
+        fs::write(files.join("notes.txt"), "not read").unwrap();
+
+        let columns = [
+            ("name", ColumnType::Text),
+            ("age", ColumnType::Integer),
+            ("score", ColumnType::Real),
+            ("admin", ColumnType::Boolean),
+        ]
+        .map(|(name, kind)| Column {
+            name: name.to_string(),
+            kind,
+        });
+        let options = CopyOptions {
+            header: true,
+            ..CopyOptions::default()
+        };
+        let table = {
+            let database = Database::with_config(&config).unwrap();
+            database
+                .create_external_table(columns.to_vec(), &files, options)
+                .unwrap()
+        };
+
+        // The table outlives the database being opened
+        let database = Database::with_config(&config).unwrap();
+        let mut connection = database.connect();
+        let rows = connection.scan(table).unwrap();
+        let data: Vec<_> = rows
+            .iter()
+            .map(|row| String::from_utf8(row.data.clone()).unwrap())
+            .collect();
+        assert_eq!(
+            data,
+            [
+                r#"{"name":"a, b","age":30,"score":1000.0,"admin":true}"#,
+                r#"{"name":"carol","age":null,"score":0.5,"admin":false}"#,
+            ]
+        );
+        assert_eq!(rows[1].id.to_string(), format!("{}:1:0", table));
+        assert_eq!(connection.get(rows[1].id).unwrap(), Some(rows[1].clone()));
+        assert!(matches!(
+            connection.insert(table, b"x"),
+            Err(DatabaseError::ReadOnlyTable(_))
+        ));
+
+        // Files are checked against the columns as they're read
+        fs::write(files.join("c.csv"), "name,age,score,admin\ndave,old,1,no\n").unwrap();
+        let error = connection.scan(table).unwrap_err().to_string();
+        assert!(error.ends_with("c.csv\": line 2: column age: \"old\" isn't an integer"));
+        fs::write(files.join("c.csv"), "name,score\n").unwrap();
+        let error = connection.scan(table).unwrap_err().to_string();
+        assert!(error.contains("the header names columns name, score"));
+    }
+}
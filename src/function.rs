@@ -0,0 +1,169 @@
+//! Functions an embedder registers on a database for SQL to call: scalar
+//! functions, called on values as `name(<value>, ...)` wherever a value
+//! may go, and aggregates, folding a table's rows into one value as
+//! `SELECT name(data) FROM <table>`.
+//!
+//! Functions take and return text, as every value in SQL is; rows whose
+//! data isn't UTF-8 are passed to them converted lossily, and a scalar
+//! function called on a NULL returns NULL without being run. Names are
+//! case-insensitive, and registering one again replaces it. They live as
+//! long as the `Database` does, and aren't stored in the catalog.
+
+use crate::database::Database;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+type ScalarFn = dyn Fn(&[String]) -> String + Send + Sync;
+
+type StepFn = dyn Fn(String, &str) -> String + Send + Sync;
+
+/// An aggregate: the value it starts from, and how each row's data
+/// changes it.
+struct AggregateFn {
+    initial: String,
+    step: Box<StepFn>,
+}
+
+/// The functions registered on a database, by lowercase name.
+#[derive(Default)]
+pub(crate) struct Functions {
+    scalar: HashMap<String, Arc<ScalarFn>>,
+    aggregate: HashMap<String, Arc<AggregateFn>>,
+}
+
+impl Database {
+    /// Let SQL call `function` on its arguments' values as `name(...)`.
+    pub fn register_scalar_fn(
+        &self,
+        name: &str,
+        function: impl Fn(&[String]) -> String + Send + Sync + 'static,
+    ) {
+        let mut functions = self.functions.write().unwrap();
+        functions
+            .scalar
+            .insert(name.to_lowercase(), Arc::new(function));
+    }
+
+    /// Let SQL fold a table's rows as `SELECT name(data) FROM <table>`:
+    /// starting from `initial`, `step` is given the value so far and each
+    /// row's data in turn, returning the next.
+    pub fn register_aggregate_fn(
+        &self,
+        name: &str,
+        initial: &str,
+        step: impl Fn(String, &str) -> String + Send + Sync + 'static,
+    ) {
+        let aggregate = AggregateFn {
+            initial: initial.to_string(),
+            step: Box::new(step),
+        };
+        let mut functions = self.functions.write().unwrap();
+        functions
+            .aggregate
+            .insert(name.to_lowercase(), Arc::new(aggregate));
+    }
+
+    /// Call the scalar function `name`, or return `None` if there's none.
+    /// It isn't called if an argument is NULL, which makes its result
+    /// NULL.
+    pub(crate) fn call_scalar_fn(
+        &self,
+        name: &str,
+        args: &[Option<String>],
+    ) -> Option<Option<String>> {
+        // Not holding the lock while it runs, so it may register others
+        let function = self
+            .functions
+            .read()
+            .unwrap()
+            .scalar
+            .get(&name.to_lowercase())
+            .cloned()?;
+        let args: Option<Vec<String>> = args.iter().cloned().collect();
+        Some(args.map(|args| function(&args)))
+    }
+
+    /// Fold `rows` with the aggregate `name`, or return `None` if there's
+    /// none.
+    pub(crate) fn call_aggregate_fn<'r>(
+        &self,
+        name: &str,
+        rows: impl IntoIterator<Item = &'r [u8]>,
+    ) -> Option<String> {
+        let aggregate = self
+            .functions
+            .read()
+            .unwrap()
+            .aggregate
+            .get(&name.to_lowercase())
+            .cloned()?;
+        Some(
+            rows.into_iter()
+                .fold(aggregate.initial.clone(), |value, data| {
+                    (aggregate.step)(value, &String::from_utf8_lossy(data))
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::tests::temp_database;
+    use crate::database::TableId;
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_functions() {
+        let (_dir, database) = temp_database();
+        database.register_scalar_fn("UPPER", |args| args.concat().to_uppercase());
+        database.register_scalar_fn("tag", |args| format!("<{}>", args.join("|")));
+        database.register_aggregate_fn("total", "0", |total, data| {
+            let total: u64 = total.parse().unwrap();
+            (total + data.parse::<u64>().unwrap_or(0)).to_string()
+        });
+
+        let mut session = SqlSession::new(database.connect());
+        for result in session.execute(
+            "CREATE TABLE; CREATE TABLE;
+             CREATE TRIGGER copy AFTER INSERT ON 1 EXECUTE INSERT INTO 2 VALUES (tag(NEW, upper(NEW)));
+             INSERT INTO 1 VALUES (upper('a', 'b')), (tag());
+             PREPARE put AS INSERT INTO 1 VALUES (tag($1, 'x'));
+             EXECUTE put ('y')",
+        ) {
+            result.unwrap();
+        }
+        let data = |table| -> Vec<String> {
+            let rows = database.connect().scan(TableId(table)).unwrap();
+            rows.into_iter()
+                .map(|row| String::from_utf8(row.data).unwrap())
+                .collect()
+        };
+        assert_eq!(data(1), vec!["AB", "<>", "<y|x>"]);
+        assert_eq!(data(2), vec!["<AB|AB>", "<<>|<>>", "<<y|x>|<Y|X>>"]);
+        // A trigger's calls are kept in the catalog as they were given
+        let copy = database.trigger("copy").unwrap();
+        assert_eq!(database.read_triggers().unwrap(), vec![copy]);
+
+        for result in session.execute("INSERT INTO 2 VALUES ('4'), ('38')") {
+            result.unwrap();
+        }
+        let result = session
+            .execute("SELECT Total(data) FROM 2")
+            .remove(0)
+            .unwrap();
+        assert_eq!(
+            (result.columns, result.rows),
+            (
+                vec!["total".to_string()],
+                vec![vec![Some("42".to_string())]]
+            )
+        );
+        for sql in [
+            "INSERT INTO 2 VALUES (lower('a'))",
+            "SELECT median(data) FROM 2",
+        ] {
+            let missing = session.execute(sql).remove(0);
+            assert_eq!(missing.unwrap_err().code(), "42883");
+        }
+    }
+}
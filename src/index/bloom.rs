@@ -0,0 +1,300 @@
+use crate::database::{Connection, Database, DatabaseError, Row, RowId, TableId};
+use crate::statistics::Predicate;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Bits in each page's filter. With the few dozen rows a page holds, one
+/// in a hundred or so lookups of a missing row still reads the page.
+const BITS: usize = 1024;
+
+/// Bits set for each row.
+const HASHES: u64 = 4;
+
+/// A Bloom filter of the data of the rows on one page: a row that was
+/// added is always said to maybe be there, and most that weren't are
+/// said not to be.
+#[derive(Debug, Clone)]
+pub(crate) struct BloomFilter {
+    bits: [u64; BITS / 64],
+}
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self {
+            bits: [0; BITS / 64],
+        }
+    }
+}
+
+impl BloomFilter {
+    /// The bits for `data`, by double hashing the two halves of one hash.
+    fn bits(data: &[u8]) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        let hash = hasher.finish();
+        let (h1, h2) = (hash & 0xFFFF_FFFF, (hash >> 32) | 1);
+        (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % BITS as u64) as usize)
+    }
+
+    pub(crate) fn add(&mut self, data: &[u8]) {
+        for bit in Self::bits(data) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    pub(crate) fn may_contain(&self, data: &[u8]) -> bool {
+        Self::bits(data).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+
+/// How `find` reads a table for the rows with some data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+    /// Every page in order, which read-ahead makes the cheapest way to
+    /// read most of them
+    Scan,
+    /// Only the pages whose Bloom filters may hold the data
+    BloomFilter,
+}
+
+impl Database {
+    /// How to find the rows of `table` with `data`: through its Bloom
+    /// filters, unless its statistics expect so many rows that reading
+    /// their pages one at a time would cost more than a scan.
+    pub(crate) fn find_access(&self, table: TableId, data: &[u8]) -> Access {
+        if !self.has_bloom_filters(table) {
+            return Access::Scan;
+        }
+        let Some((rows, pages)) = self.analyzed_size(table) else {
+            return Access::BloomFilter;
+        };
+        let data = String::from_utf8_lossy(data);
+        let matching = self.selectivity(table, None, Predicate::Equal(&data)) * rows as f64;
+        // Each matching row may be on a page of its own, read at random
+        if matching * 2.0 >= pages as f64 {
+            Access::Scan
+        } else {
+            Access::BloomFilter
+        }
+    }
+
+    /// How `find_with` reads `table` for the rows with `data`: as
+    /// `access` says, if it's given and the table can be, or as
+    /// `find_access` chooses. A partitioned table counts as read through
+    /// Bloom filters only if every partition looked through is.
+    pub(crate) fn access_with(
+        &self,
+        table: TableId,
+        data: &[u8],
+        access: Option<Access>,
+    ) -> Access {
+        if let Some(partitions) = self.partitions_with(table, None, data) {
+            let filtered = partitions
+                .into_iter()
+                .all(|partition| self.access_with(partition, data, access) == Access::BloomFilter);
+            return if filtered {
+                Access::BloomFilter
+            } else {
+                Access::Scan
+            };
+        }
+        match access {
+            Some(Access::BloomFilter) if !self.has_bloom_filters(table) => Access::Scan,
+            Some(access) => access,
+            None => self.find_access(table, data),
+        }
+    }
+
+    /// Build the Bloom filters of `table`'s pages from the rows it has, as
+    /// the database opens.
+    ///
+    /// Like the full-text indexes, filters are kept in memory and added to
+    /// as rows are written, but never cleared, as the writes may yet be
+    /// rolled back. A row deleted or changed since only makes its page
+    /// read for nothing.
+    pub(crate) fn build_bloom_filters(&self, table: TableId) -> Result<(), DatabaseError> {
+        let mut filters: Vec<BloomFilter> = Vec::new();
+        self.connect_system().scan_each(table, |row| {
+            let page_no = row.id.page_no as usize;
+            if filters.len() <= page_no {
+                filters.resize_with(page_no + 1, BloomFilter::default);
+            }
+            filters[page_no].add(&row.data);
+            Ok::<_, DatabaseError>(())
+        })?;
+        self.bloom_filters.write().unwrap().insert(table, filters);
+        Ok(())
+    }
+
+    fn has_bloom_filters(&self, table: TableId) -> bool {
+        self.bloom_filters.read().unwrap().contains_key(&table)
+    }
+
+    /// Add a row just written to its page's filter, if its table keeps
+    /// them.
+    pub(crate) fn add_to_bloom_filter(&self, row: RowId, data: &[u8]) {
+        let mut tables = self.bloom_filters.write().unwrap();
+        if let Some(filters) = tables.get_mut(&row.table) {
+            let page_no = row.page_no as usize;
+            if filters.len() <= page_no {
+                filters.resize_with(page_no + 1, BloomFilter::default);
+            }
+            filters[page_no].add(data);
+        }
+    }
+
+    /// The pages of `table` that may hold a row of `data`, or `None` if it
+    /// keeps no filters.
+    fn pages_with(&self, table: TableId, data: &[u8]) -> Option<Vec<u64>> {
+        let tables = self.bloom_filters.read().unwrap();
+        let filters = tables.get(&table)?;
+        let pages = filters.iter().enumerate();
+        Some(
+            pages
+                .filter(|(_, filter)| filter.may_contain(data))
+                .map(|(page_no, _)| page_no as u64)
+                .collect(),
+        )
+    }
+}
+
+impl Connection<'_> {
+    /// The rows of `table` whose data is `data`. A table stored with
+    /// `bloom_filter` set only reads the pages its filters say may hold
+    /// them, unless its statistics expect them on most pages; others are
+    /// scanned. A table partitioned by its data only has
+    /// the partition `data` belongs in looked through.
+    pub fn find(&mut self, table: TableId, data: &[u8]) -> Result<Vec<Row>, DatabaseError> {
+        self.find_with(table, data, None)
+    }
+
+    /// The rows of `table` whose data is `data`, read as `access_with`
+    /// says.
+    pub(crate) fn find_with(
+        &mut self,
+        table: TableId,
+        data: &[u8],
+        access: Option<Access>,
+    ) -> Result<Vec<Row>, DatabaseError> {
+        let database = self.database();
+        if let Some(partitions) = database.partitions_with(table, None, data) {
+            let mut rows = Vec::new();
+            for partition in partitions {
+                rows.extend(self.find_with(partition, data, access)?);
+            }
+            return Ok(rows);
+        }
+        let rows = match database.access_with(table, data, access) {
+            // Their filters are read once the table is locked, so they
+            // cover every row it holds
+            Access::BloomFilter => self.rows_on_pages(table, || {
+                database.pages_with(table, data).unwrap_or_default()
+            })?,
+            Access::Scan => self.scan(table)?,
+        };
+        Ok(rows.into_iter().filter(|row| row.data == data).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_config;
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_bloom_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        config.storage.page_size = 512;
+        // The pages a lookup asked the buffer pool for
+        let pages_read = |database: &Database, table, data: &str| {
+            let before = database.metrics();
+            let rows = database.connect().find(table, data.as_bytes()).unwrap();
+            let after = database.metrics();
+            let read =
+                after.buffer_hits + after.buffer_misses - before.buffer_hits - before.buffer_misses;
+            (rows.len(), read)
+        };
+        let rows: Vec<String> = (0..200).map(|i| format!("key {i}")).collect();
+
+        let (filtered, plain) = {
+            let database = Database::with_config(&config).unwrap();
+            let mut session = SqlSession::new(database.connect());
+            let mut tables = Vec::new();
+            for sql in ["CREATE TABLE (BLOOM_FILTER = TRUE)", "CREATE TABLE"] {
+                let result = session.execute(sql).remove(0).unwrap();
+                tables.push(TableId(
+                    result.rows[0][0].as_ref().unwrap().parse().unwrap(),
+                ));
+            }
+            let mut connection = database.connect();
+            for &table in &tables {
+                connection.insert_batch(table, &rows).unwrap();
+                connection.insert(table, b"key 7").unwrap();
+            }
+            let pages = database.bloom_filters.read().unwrap()[&tables[0]].len() as u64;
+            // A missing row reads no page of a filtered table, but all of
+            // another
+            assert_eq!(pages_read(&database, tables[0], "key 7"), (2, 2));
+            assert_eq!(pages_read(&database, tables[0], "nothing"), (0, 0));
+            assert!(pages_read(&database, tables[1], "nothing").1 >= pages);
+            let result = session.execute("SELECT * FROM 1 WHERE data = 'key 150'");
+            assert_eq!(
+                result[0].as_ref().unwrap().rows[0][1].as_deref(),
+                Some("key 150")
+            );
+            database.checkpoint().unwrap();
+            (tables[0], tables[1])
+        };
+
+        // The filters are built again once the database is opened again
+        let database = Database::with_config(&config).unwrap();
+        assert!(database.table_options(filtered).bloom_filter);
+        assert!(!database.table_options(plain).bloom_filter);
+        assert_eq!(pages_read(&database, filtered, "key 199").0, 1);
+        assert_eq!(pages_read(&database, filtered, "nothing"), (0, 0));
+
+        // Once analyzed, a row on most pages is found by a scan instead
+        let common = ["common"; 150];
+        database.connect().insert_batch(filtered, &common).unwrap();
+        assert_eq!(
+            database.find_access(filtered, b"common"),
+            Access::BloomFilter
+        );
+        database.analyze(filtered).unwrap();
+        assert_eq!(database.find_access(filtered, b"common"), Access::Scan);
+        assert_eq!(
+            database.find_access(filtered, b"key 3"),
+            Access::BloomFilter
+        );
+        assert_eq!(database.find_access(plain, b"key 3"), Access::Scan);
+        assert_eq!(pages_read(&database, filtered, "common").0, 150);
+
+        // Hints override the choice
+        let hinted = |sql: &str| {
+            let before = database.metrics();
+            let mut session = SqlSession::new(database.connect());
+            let rows = session.execute(sql).remove(0).unwrap().rows;
+            let after = database.metrics();
+            let read =
+                after.buffer_hits + after.buffer_misses - before.buffer_hits - before.buffer_misses;
+            (rows.len(), read)
+        };
+        let sql = format!(
+            "SELECT /*+ FULL */ * FROM {} WHERE data = 'nothing'",
+            filtered
+        );
+        assert!(hinted(&sql).1 > 0);
+        let sql = format!(
+            "SELECT /*+ BLOOM_FILTER({}) */ * FROM {} WHERE data = 'common'",
+            filtered, filtered
+        );
+        assert_eq!(hinted(&sql).0, 150);
+        let sql = format!(
+            "SELECT /*+ BLOOM_FILTER */ * FROM {} WHERE data = 'key 3'",
+            plain
+        );
+        assert_eq!(hinted(&sql).0, 1);
+    }
+}
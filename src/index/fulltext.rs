@@ -0,0 +1,309 @@
+use crate::database::{Connection, Database, DatabaseError, Row, RowId, TableId};
+use std::collections::{BTreeSet, HashMap};
+
+/// Marks a catalog record as a full-text index: the table, then the
+/// index's name.
+const FULLTEXT: u8 = 6;
+
+/// An inverted index from the words in a table's rows to the rows they
+/// appear in, kept in memory and built from the table when the database
+/// opens.
+///
+/// Rows written since are added as they're written, but never taken out
+/// again, as a transaction that deletes or changes one may yet be rolled
+/// back. So the rows a word leads to are a superset of those it's in, and
+/// a search reads each to check.
+#[derive(Debug)]
+pub(crate) struct FullTextIndex {
+    pub(crate) name: String,
+    pub(crate) table: TableId,
+    postings: HashMap<String, BTreeSet<RowId>>,
+    /// Every row indexed, standing for the table's size when ranking
+    rows: BTreeSet<RowId>,
+}
+
+/// A row a search found, and how well it matched.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Match {
+    pub(crate) row: Row,
+    pub(crate) rank: f64,
+}
+
+/// The words in `text`, lowercased: runs of letters and digits.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+impl FullTextIndex {
+    fn new(name: String, table: TableId) -> Self {
+        Self {
+            name,
+            table,
+            postings: HashMap::new(),
+            rows: BTreeSet::new(),
+        }
+    }
+
+    fn add(&mut self, row: RowId, data: &[u8]) {
+        self.rows.insert(row);
+        for word in words(&String::from_utf8_lossy(data)) {
+            self.postings.entry(word).or_default().insert(row);
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![FULLTEXT];
+        bytes.extend(self.table.0.to_be_bytes());
+        bytes.extend_from_slice(self.name.as_bytes());
+        bytes
+    }
+
+    /// The name and table of the index a catalog record holds, or `None`
+    /// if it holds something else.
+    fn decode(bytes: &[u8]) -> Result<Option<(String, TableId)>, DatabaseError> {
+        if bytes.first() != Some(&FULLTEXT) {
+            return Ok(None);
+        }
+        let Some((&[a, b, c, d], name)) = bytes[1..].split_first_chunk::<4>() else {
+            return Err(DatabaseError::CorruptedCatalog);
+        };
+        let name = String::from_utf8(name.to_vec()).map_err(|_| DatabaseError::CorruptedCatalog)?;
+        Ok(Some((name, TableId(u32::from_be_bytes([a, b, c, d])))))
+    }
+}
+
+/// Every full-text index in the catalog, with the record holding it.
+fn indexes(connection: &mut Connection) -> Result<Vec<(Row, String, TableId)>, DatabaseError> {
+    let mut indexes = Vec::new();
+    for row in connection.scan(TableId::CATALOG)? {
+        if let Some((name, table)) = FullTextIndex::decode(&row.data)? {
+            indexes.push((row, name, table));
+        }
+    }
+    Ok(indexes)
+}
+
+impl Database {
+    /// Index the words of `table`'s rows as `name`, for `search`.
+    pub(crate) fn create_fulltext_index(
+        &self,
+        name: &str,
+        table: TableId,
+    ) -> Result<(), DatabaseError> {
+        if !self.tables().contains(&table) {
+            return Err(DatabaseError::NoSuchTable(table));
+        }
+        let mut connection = self.connect_system();
+        connection.begin()?;
+        connection.lock_exclusive(TableId::CATALOG)?;
+        if indexes(&mut connection)?
+            .iter()
+            .any(|(_, other, _)| other == name)
+            || self.unique_index_table(name).is_some()
+        {
+            return Err(DatabaseError::IndexExists(name.to_string()));
+        }
+        let mut index = FullTextIndex::new(name.to_string(), table);
+        connection.insert(TableId::CATALOG, &index.encode())?;
+        // The scan holds the table's lock until the index is in place, so
+        // no row written meanwhile is missed
+        for row in connection.scan(table)? {
+            index.add(row.id, &row.data);
+        }
+        self.fulltext.write().unwrap().push(index);
+        if let Err(e) = connection.commit() {
+            let mut indexes = self.fulltext.write().unwrap();
+            indexes.retain(|index| index.name != name);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Remove the full-text index `name`.
+    pub(crate) fn drop_fulltext_index(&self, name: &str) -> Result<(), DatabaseError> {
+        let mut connection = self.connect_system();
+        connection.begin()?;
+        connection.lock_exclusive(TableId::CATALOG)?;
+        let (row, _, _) = indexes(&mut connection)?
+            .into_iter()
+            .find(|(_, other, _)| other == name)
+            .ok_or_else(|| DatabaseError::NoSuchIndex(name.to_string()))?;
+        connection.delete(row.id)?;
+        connection.commit()?;
+        let mut indexes = self.fulltext.write().unwrap();
+        indexes.retain(|index| index.name != name);
+        Ok(())
+    }
+
+    /// The table the full-text index `name` is on, if there is one.
+    pub(crate) fn fulltext_index_table(&self, name: &str) -> Option<TableId> {
+        let indexes = self.fulltext.read().unwrap();
+        let index = indexes.iter().find(|index| index.name == name)?;
+        Some(index.table)
+    }
+
+    /// Add a row just written to the indexes on its table, or on the table
+    /// it's a partition of.
+    pub(crate) fn index_row(&self, row: RowId, data: &[u8]) {
+        let table = self.parent_table(row.table);
+        let mut indexes = self.fulltext.write().unwrap();
+        for index in indexes.iter_mut().filter(|index| index.table == table) {
+            index.add(row, data);
+        }
+        drop(indexes);
+        self.index_unique(table, row, data);
+    }
+
+    /// Build every full-text index in the catalog.
+    pub(crate) fn read_fulltext_indexes(&self) -> Result<(), DatabaseError> {
+        let mut connection = self.connect_system();
+        for (_, name, table) in indexes(&mut connection)? {
+            let mut index = FullTextIndex::new(name, table);
+            for row in connection.scan(table)? {
+                index.add(row.id, &row.data);
+            }
+            self.fulltext.write().unwrap().push(index);
+        }
+        Ok(())
+    }
+}
+
+impl Connection<'_> {
+    /// The rows of `table` holding any of the words in `terms`, best
+    /// first, through its full-text index.
+    ///
+    /// Rows are ranked by TF-IDF: each word found adds the times it's in
+    /// the row, weighted by how few of the table's rows have it.
+    pub(crate) fn search(
+        &mut self,
+        table: TableId,
+        terms: &str,
+    ) -> Result<Vec<Match>, DatabaseError> {
+        let terms: BTreeSet<String> = words(terms).collect();
+        let (candidates, indexed) = {
+            let indexes = self.database().fulltext.read().unwrap();
+            let index = indexes
+                .iter()
+                .find(|index| index.table == table)
+                .ok_or(DatabaseError::NoFullTextIndex(table))?;
+            let candidates: BTreeSet<RowId> = terms
+                .iter()
+                .filter_map(|term| index.postings.get(term))
+                .flatten()
+                .copied()
+                .collect();
+            (candidates, index.rows.len())
+        };
+
+        let mut found = Vec::new();
+        let mut rows_with = HashMap::<&str, usize>::new();
+        for id in candidates {
+            let Some(row) = self.get(id)? else {
+                continue;
+            };
+            let mut counts = HashMap::<&str, usize>::new();
+            for word in words(&String::from_utf8_lossy(&row.data)) {
+                if let Some(term) = terms.get(&word) {
+                    *counts.entry(term.as_str()).or_default() += 1;
+                }
+            }
+            if counts.is_empty() {
+                continue;
+            }
+            for &term in counts.keys() {
+                *rows_with.entry(term).or_default() += 1;
+            }
+            found.push((row, counts));
+        }
+
+        let mut matches: Vec<Match> = found
+            .into_iter()
+            .map(|(row, counts)| {
+                let rank = counts
+                    .iter()
+                    .map(|(term, &count)| {
+                        let idf = (1.0 + indexed as f64 / rows_with[term] as f64).ln();
+                        count as f64 * idf
+                    })
+                    .sum();
+                Match { row, rank }
+            })
+            .collect();
+        matches.sort_by(|a, b| b.rank.total_cmp(&a.rank).then(a.row.id.cmp(&b.row.id)));
+        Ok(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_config;
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_fulltext_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(dir.path());
+        let found = |session: &mut SqlSession, terms: &str| -> Vec<String> {
+            let sql = format!("SELECT * FROM 1 WHERE MATCH(data) AGAINST ('{terms}')");
+            let result = session.execute(&sql).remove(0).unwrap();
+            result
+                .rows
+                .into_iter()
+                .map(|row| row[1].clone().unwrap())
+                .collect()
+        };
+
+        let (cat, dog) = {
+            let database = Database::with_config(&config).unwrap();
+            let table = database.create_table().unwrap();
+            let mut connection = database.connect();
+            let cat = connection.insert(table, b"The cat sat.").unwrap();
+            let mut session = SqlSession::new(database.connect());
+            for result in session.execute(
+                "CREATE FULLTEXT INDEX notes ON 1 (data);
+                 INSERT INTO 1 VALUES ('A dog, a DOG and a cat'), ('Birds sing')",
+            ) {
+                result.unwrap();
+            }
+            let dog = found(&mut session, "dog")[0].clone();
+            assert_eq!(dog, "A dog, a DOG and a cat");
+            // Rows with more of the rarer words rank higher
+            assert_eq!(
+                found(&mut session, "cat dog"),
+                vec!["A dog, a DOG and a cat", "The cat sat."]
+            );
+            assert!(found(&mut session, "fish").is_empty());
+            let again = session.execute("CREATE FULLTEXT INDEX notes ON 1 (data)");
+            assert_eq!(again[0].as_ref().unwrap_err().code(), "42P07");
+
+            // A change is searched as it stands, and one rolled back as it was
+            connection.update(cat, b"The hat").unwrap();
+            assert_eq!(found(&mut session, "cat"), vec![dog.clone()]);
+            assert_eq!(found(&mut session, "hat"), vec!["The hat"]);
+            let birds = found(&mut session, "birds");
+            assert_eq!(birds.len(), 1);
+            connection.begin().unwrap();
+            for row in connection.scan(table).unwrap() {
+                connection.delete(row.id).unwrap();
+            }
+            connection.rollback().unwrap();
+            assert_eq!(found(&mut session, "birds"), birds);
+            (cat, dog)
+        };
+
+        // The index is built again once the database is opened again
+        let database = Database::with_config(&config).unwrap();
+        let mut session = SqlSession::new(database.connect());
+        assert_eq!(found(&mut session, "dog"), vec![dog]);
+        let matches = database.connect().search(TableId(1), "HAT").unwrap();
+        assert_eq!(matches[0].row.id, cat);
+        assert!(matches[0].rank > 0.0);
+        session.execute("DROP INDEX notes").remove(0).unwrap();
+        let sql = "SELECT * FROM 1 WHERE MATCH(data) AGAINST ('dog')";
+        let missing = session.execute(sql).remove(0);
+        assert_eq!(missing.unwrap_err().code(), "42704");
+    }
+}
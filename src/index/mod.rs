@@ -0,0 +1,9 @@
+//! Indexes over a table's rows, kept up to date as rows are written.
+
+mod bloom;
+mod fulltext;
+mod unique;
+
+pub(crate) use bloom::{Access, BloomFilter};
+pub(crate) use fulltext::FullTextIndex;
+pub(crate) use unique::UniqueIndex;
@@ -0,0 +1,368 @@
+use crate::database::{row_at, Connection, Database, DatabaseError, Row, RowId, TableId};
+use crate::mapping::column_value;
+use crate::storage::Transaction;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::atomic::AtomicBool;
+
+/// Marks a catalog record as a unique index: the table, whether it's the
+/// table's primary key, the length of the index's name and the name, then
+/// the column it keys rows by, if not their data.
+const UNIQUE: u8 = 8;
+
+/// An index from the keys of a table's rows to the rows holding them,
+/// through which `UNIQUE` and `PRIMARY KEY` are kept: a row's key is its
+/// data, or the value of one of its columns, and no two rows may share
+/// one. Kept in memory and built from the table when the database opens.
+///
+/// Like a full-text index, rows are added as they're written but never
+/// taken out, so the rows a key leads to are a superset of those holding
+/// it, and a write reads each, as its transaction sees it, to check.
+#[derive(Debug)]
+pub(crate) struct UniqueIndex {
+    pub(crate) name: String,
+    pub(crate) table: TableId,
+    /// The column rows are keyed by, or `None` for their whole data
+    pub(crate) column: Option<String>,
+    /// Whether it's the table's primary key, which every row must have
+    pub(crate) primary: bool,
+    keys: HashMap<Vec<u8>, BTreeSet<RowId>>,
+}
+
+/// The key of the row `data` by `column`, or by its data if `None`.
+fn key(column: &Option<String>, data: &[u8]) -> Option<Vec<u8>> {
+    match column {
+        Some(column) => column_value(data, column).map(String::into_bytes),
+        None => Some(data.to_vec()),
+    }
+}
+
+impl UniqueIndex {
+    fn new(name: String, table: TableId, column: Option<String>, primary: bool) -> Self {
+        Self {
+            name,
+            table,
+            column,
+            primary,
+            keys: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, row: RowId, data: &[u8]) {
+        if let Some(key) = key(&self.column, data) {
+            self.keys.entry(key).or_default().insert(row);
+        }
+    }
+
+    fn violation(&self, key: &[u8]) -> DatabaseError {
+        DatabaseError::UniqueViolation {
+            constraint: self.name.clone(),
+            key: String::from_utf8_lossy(key).into_owned(),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![UNIQUE];
+        bytes.extend(self.table.0.to_be_bytes());
+        bytes.push(u8::from(self.primary));
+        bytes.extend((self.name.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(self.name.as_bytes());
+        if let Some(column) = &self.column {
+            bytes.extend_from_slice(column.as_bytes());
+        }
+        bytes
+    }
+
+    /// The index a catalog record holds, without its keys, or `None` if it
+    /// holds something else.
+    fn decode(bytes: &[u8]) -> Result<Option<Self>, DatabaseError> {
+        if bytes.first() != Some(&UNIQUE) {
+            return Ok(None);
+        }
+        let Some((&[a, b, c, d, primary, e, f], rest)) = bytes[1..].split_first_chunk::<7>() else {
+            return Err(DatabaseError::CorruptedCatalog);
+        };
+        let length = u16::from_be_bytes([e, f]) as usize;
+        if rest.len() < length {
+            return Err(DatabaseError::CorruptedCatalog);
+        }
+        let text = |bytes: &[u8]| {
+            String::from_utf8(bytes.to_vec()).map_err(|_| DatabaseError::CorruptedCatalog)
+        };
+        let column = &rest[length..];
+        Ok(Some(Self::new(
+            text(&rest[..length])?,
+            TableId(u32::from_be_bytes([a, b, c, d])),
+            (!column.is_empty()).then(|| text(column)).transpose()?,
+            primary != 0,
+        )))
+    }
+}
+
+/// Every unique index in the catalog, without its keys, with the record
+/// holding it.
+fn indexes(connection: &mut Connection) -> Result<Vec<(Row, UniqueIndex)>, DatabaseError> {
+    let mut indexes = Vec::new();
+    for row in connection.scan(TableId::CATALOG)? {
+        if let Some(index) = UniqueIndex::decode(&row.data)? {
+            indexes.push((row, index));
+        }
+    }
+    Ok(indexes)
+}
+
+impl Database {
+    /// Index `table`'s rows as `name` by `column`, or by their data, so no
+    /// two may have the same key. A primary key also has every row have
+    /// one, and a table may have only one primary key.
+    pub(crate) fn create_unique_index(
+        &self,
+        name: &str,
+        table: TableId,
+        column: Option<String>,
+        primary: bool,
+    ) -> Result<(), DatabaseError> {
+        if !self.tables().contains(&table) {
+            return Err(DatabaseError::NoSuchTable(table));
+        }
+        let mut connection = self.connect_system();
+        connection.begin()?;
+        connection.lock_exclusive(TableId::CATALOG)?;
+        let existing = indexes(&mut connection)?;
+        if existing.iter().any(|(_, other)| other.name == name)
+            || self.fulltext_index_table(name).is_some()
+        {
+            return Err(DatabaseError::IndexExists(name.to_string()));
+        }
+        if primary
+            && existing
+                .iter()
+                .any(|(_, other)| other.primary && other.table == table)
+        {
+            return Err(DatabaseError::PrimaryKeyExists(table));
+        }
+        let mut index = UniqueIndex::new(name.to_string(), table, column, primary);
+        connection.insert(TableId::CATALOG, &index.encode())?;
+        // The scan holds the table's lock until the index is in place, so
+        // no row written meanwhile is missed
+        for row in connection.scan(table)? {
+            match key(&index.column, &row.data) {
+                Some(key) if index.keys.contains_key(&key) => return Err(index.violation(&key)),
+                None if primary => {
+                    return Err(DatabaseError::MissingKey(name.to_string()));
+                }
+                _ => index.add(row.id, &row.data),
+            }
+        }
+        self.unique.write().unwrap().push(index);
+        if let Err(e) = connection.commit() {
+            let mut indexes = self.unique.write().unwrap();
+            indexes.retain(|index| index.name != name);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Remove the unique index `name`.
+    pub(crate) fn drop_unique_index(&self, name: &str) -> Result<(), DatabaseError> {
+        let mut connection = self.connect_system();
+        connection.begin()?;
+        connection.lock_exclusive(TableId::CATALOG)?;
+        let (row, _) = indexes(&mut connection)?
+            .into_iter()
+            .find(|(_, other)| other.name == name)
+            .ok_or_else(|| DatabaseError::NoSuchIndex(name.to_string()))?;
+        connection.delete(row.id)?;
+        connection.commit()?;
+        let mut indexes = self.unique.write().unwrap();
+        indexes.retain(|index| index.name != name);
+        Ok(())
+    }
+
+    /// The table the unique index `name` is on, if there is one.
+    pub(crate) fn unique_index_table(&self, name: &str) -> Option<TableId> {
+        let indexes = self.unique.read().unwrap();
+        let index = indexes.iter().find(|index| index.name == name)?;
+        Some(index.table)
+    }
+
+    /// Add a row just written to the unique indexes on `table`.
+    pub(crate) fn index_unique(&self, table: TableId, row: RowId, data: &[u8]) {
+        let mut indexes = self.unique.write().unwrap();
+        for index in indexes.iter_mut().filter(|index| index.table == table) {
+            index.add(row, data);
+        }
+    }
+
+    /// Check `rows`, about to be written to `table` in `transaction`,
+    /// against the unique indexes on it, or on the table it's a partition
+    /// of: each is its data and, if it's being updated, its id. Fails with
+    /// `UniqueViolation` if one would have the key of another row, as the
+    /// transaction sees it, or of another of `rows`, and `MissingKey` if
+    /// one lacks its primary key.
+    pub(crate) fn check_unique(
+        &self,
+        transaction: &Transaction,
+        cancelled: &AtomicBool,
+        table: TableId,
+        rows: &[(&[u8], Option<RowId>)],
+    ) -> Result<(), DatabaseError> {
+        let table = self.parent_table(table);
+        // The rows that may hold each key, read once the index is let go
+        let mut probes = Vec::new();
+        {
+            let indexes = self.unique.read().unwrap();
+            for index in indexes.iter().filter(|index| index.table == table) {
+                let mut seen = HashSet::new();
+                for &(data, updated) in rows {
+                    let Some(key) = key(&index.column, data) else {
+                        if index.primary {
+                            return Err(DatabaseError::MissingKey(index.name.clone()));
+                        }
+                        continue;
+                    };
+                    if !seen.insert(key.clone()) {
+                        return Err(index.violation(&key));
+                    }
+                    let holders: Vec<RowId> = index
+                        .keys
+                        .get(&key)
+                        .into_iter()
+                        .flatten()
+                        .filter(|&&row| Some(row) != updated)
+                        .copied()
+                        .collect();
+                    if !holders.is_empty() {
+                        probes.push((index.violation(&key), index.column.clone(), key, holders));
+                    }
+                }
+            }
+        }
+        for (violation, column, wanted, holders) in probes {
+            for row in holders {
+                let Some(data) = row_at(transaction, cancelled, row)? else {
+                    continue;
+                };
+                if key(&column, &data).as_ref() == Some(&wanted) {
+                    return Err(violation);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Build every unique index in the catalog.
+    pub(crate) fn read_unique_indexes(&self) -> Result<(), DatabaseError> {
+        let mut connection = self.connect_system();
+        for (_, mut index) in indexes(&mut connection)? {
+            for row in connection.scan(index.table)? {
+                index.add(row.id, &row.data);
+            }
+            self.unique.write().unwrap().push(index);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::{temp_database, test_config};
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_unique_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(dir.path());
+        let run = |session: &mut SqlSession, sql: &str| session.execute(sql).remove(0);
+
+        let alice = {
+            let database = Database::with_config(&config).unwrap();
+            let table = database.create_table().unwrap();
+            let mut connection = database.connect();
+            let alice = connection
+                .insert(table, br#"{"email": "alice@example.com"}"#)
+                .unwrap();
+            let mut session = SqlSession::new(database.connect());
+            run(&mut session, "CREATE UNIQUE INDEX emails ON 1 (email)").unwrap();
+            let duplicate = connection.insert(table, br#"{"email": "alice@example.com", "n": 2}"#);
+            assert!(matches!(
+                duplicate,
+                Err(DatabaseError::UniqueViolation { constraint, key })
+                    if constraint == "emails" && key == "alice@example.com"
+            ));
+            let sql = r#"INSERT INTO 1 VALUES ('{"email": "alice@example.com"}')"#;
+            assert_eq!(run(&mut session, sql).unwrap_err().code(), "23505");
+            // Rows without the column don't clash
+            connection.insert(table, b"{}").unwrap();
+            connection.insert(table, b"{}").unwrap();
+
+            // Nor do rows in the same batch
+            let batch = [
+                br#"{"email": "bob@example.com"}"#.as_slice(),
+                br#"{"email": "bob@example.com"}"#,
+            ];
+            let clash = connection.insert_batch(table, &batch);
+            assert!(matches!(clash, Err(DatabaseError::UniqueViolation { .. })));
+            let bob = connection.insert_batch(table, &batch[..1]).unwrap()[0];
+
+            // A row keeps its own key, but may not take another's
+            connection
+                .update(alice, br#"{"email": "alice@example.com", "n": 1}"#)
+                .unwrap();
+            let taken = connection.update(bob, br#"{"email": "alice@example.com"}"#);
+            assert!(matches!(taken, Err(DatabaseError::UniqueViolation { .. })));
+
+            // A key deleted, or written and rolled back, is free again
+            connection.begin().unwrap();
+            connection
+                .insert(table, br#"{"email": "carol@example.com"}"#)
+                .unwrap();
+            connection.rollback().unwrap();
+            connection.delete(bob).unwrap();
+            connection
+                .insert(table, br#"{"email": "carol@example.com"}"#)
+                .unwrap();
+            connection
+                .insert(table, br#"{"email": "bob@example.com"}"#)
+                .unwrap();
+
+            // An index can't be made over rows already sharing a key
+            let sql = "CREATE UNIQUE INDEX whole ON 1 (data)";
+            assert_eq!(run(&mut session, sql).unwrap_err().code(), "23505");
+            alice
+        };
+
+        // The index is built again once the database is opened again
+        let database = Database::with_config(&config).unwrap();
+        let mut connection = database.connect();
+        let duplicate = connection.insert(TableId(1), br#"{"email": "alice@example.com"}"#);
+        assert!(matches!(
+            duplicate,
+            Err(DatabaseError::UniqueViolation { .. })
+        ));
+        let mut session = SqlSession::new(database.connect());
+        run(&mut session, "DROP INDEX emails").unwrap();
+        connection
+            .update(alice, br#"{"email": "bob@example.com"}"#)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_primary_key() {
+        let (_dir, database) = temp_database();
+        let table = database.create_table().unwrap();
+        let mut session = SqlSession::new(database.connect());
+        let mut run = |sql: &str| session.execute(sql).remove(0);
+        run("CREATE PRIMARY KEY users_pkey ON 1 (id)").unwrap();
+        run(r#"INSERT INTO 1 VALUES ('{"id": 1}'), ('{"id": 2}')"#).unwrap();
+        let missing = run(r#"INSERT INTO 1 VALUES ('{"name": "x"}')"#);
+        assert_eq!(missing.unwrap_err().code(), "23502");
+        let duplicate = run(r#"INSERT INTO 1 VALUES ('{"id": 2}')"#);
+        assert_eq!(duplicate.unwrap_err().code(), "23505");
+        let again = run("CREATE PRIMARY KEY other ON 1 (data)");
+        assert_eq!(again.unwrap_err().code(), "42P16");
+        let taken = run("CREATE UNIQUE INDEX users_pkey ON 1 (data)");
+        assert_eq!(taken.unwrap_err().code(), "42P07");
+        assert_eq!(database.connect().scan(table).unwrap().len(), 2);
+    }
+}
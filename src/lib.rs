@@ -1,3 +1,65 @@
+mod activity;
+mod asynchronous;
+mod audit;
+mod auth;
+mod bench;
+mod builtin;
 mod config;
+mod copy;
+mod cursor;
+mod database;
+mod dump;
+mod encoding;
+mod error;
+mod external;
+mod function;
+mod index;
+mod logging;
+mod mapping;
+mod metrics;
+mod overflow;
+#[cfg(feature = "parquet")]
+mod parquet;
+mod partition;
+mod plan;
+mod server;
+mod spill;
+mod sql;
+mod sqlite;
+mod statistics;
 mod storage;
 mod syntax;
+mod table_options;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod trigger;
+mod ttl;
+mod variables;
+
+pub use asynchronous::{AsyncConnection, Pending};
+pub use auth::Privilege;
+pub use bench::{BenchOptions, BenchReport, Latencies, Workload};
+pub use config::{
+    Config, ConfigError, ConfigFormat, ConfigViolation, LimitsConfig, ServerConfig, SpillConfig,
+    TtlSweepConfig, UserLimits, WireProtocol, IN_MEMORY, RELOADABLE,
+};
+pub use database::{
+    CancelHandle, Change, Changes, Connection, Database, DatabaseError, RestoreTarget, Row, RowId,
+    TableId,
+};
+pub use dump::{DumpError, DumpStats};
+pub use encoding::{ResultEncoder, ResultFormat};
+pub use error::{FerroError, Position, SourceSpan};
+pub use logging::{init_logging, LogLevel, LoggingError};
+pub use mapping::RowMappingError;
+pub use metrics::{Metrics, TableStats, LATENCY_BUCKETS};
+pub use partition::{PartitionScheme, Partitioning, MAX_PARTITIONS};
+pub use server::{Client, ClientError, ErrorCode, Outcome, QueryResult, Request, Server};
+pub use sql::{SqlError, SqlSession, StatementResult};
+pub use sqlite::{ImportedTable, SqliteImportError};
+pub use storage::{
+    Fault, FaultInjector, FaultPoint, FsckReport, PageDecodeError, PageManagerError, Problem,
+    TransactionError,
+};
+pub use table_options::TableOptions;
+pub use ttl::TtlSweeper;
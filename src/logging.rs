@@ -0,0 +1,674 @@
+//! Logging what the database and server do, as `LoggingConfig` says: at
+//! its level and above, to its file, rotated by size.
+//!
+//! Each event is a line of its time in UTC, its level, the module it
+//! came from, its message and its fields as `key=value`, values quoted
+//! when they hold spaces or quotes:
+//!
+//! ```text
+//! 2026-10-14T09:30:00.125Z INFO ferrodb::server: session started id=1 user=alice
+//! ```
+//!
+//! Work that takes time, such as a statement, the operations it makes and
+//! the pages they read, is a span, made by `span!`. Events logged while a
+//! span is open on their thread carry it, and each span logs its closing
+//! with how long it was open, so that with the level at `debug`, or
+//! `trace` for pages, the log is a timeline of where a statement spent
+//! its time:
+//!
+//! ```text
+//! 2026-10-14T09:30:00.126Z TRACE statement{id=4 session=1}:operation{table=1 txn=9}:read_page{page=1:0}: ferrodb::database: close elapsed_us=41
+//! 2026-10-14T09:30:00.126Z DEBUG statement{id=4 session=1}:operation{table=1 txn=9}: ferrodb::database: close elapsed_us=187
+//! 2026-10-14T09:30:00.126Z DEBUG statement{id=4 session=1}: ferrodb::sql: close elapsed_us=233
+//! ```
+//!
+//! With `rotate`, a file that would grow past `max_size_mb` is renamed
+//! `FILE.1` first, an older `FILE.1` `FILE.2` and so on, keeping at most
+//! `max_files` of them; without, the file grows as it will. Nothing is
+//! logged until `init_logging` is called, and failing to write a line
+//! never fails what was being logged.
+
+use crate::config::LoggingConfig;
+use std::cell::RefCell;
+use std::fmt::{self, Display};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// The logger `init_logging` installed, if any.
+static LOGGER: Mutex<Option<Logger>> = Mutex::new(None);
+
+/// The installed logger's level, so events below it are passed over
+/// without taking the lock. 0 is off.
+static LEVEL: AtomicU8 = AtomicU8::new(0);
+
+/// Held by tests that install a logger, as every thread logs to the one
+/// installed.
+#[cfg(test)]
+pub(crate) static TEST_LOGGER: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    /// The spans open on this thread, outermost first, as they're written
+    static SPANS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Debug, Error)]
+pub enum LoggingError {
+    #[error("unknown log level \"{0}\"; expected off, error, warn, info, debug or trace")]
+    InvalidLevel(String),
+
+    #[error("could not open the log file {path}: {source}")]
+    Open { path: PathBuf, source: io::Error },
+}
+
+/// How much is logged, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// The level named `name`, or `None` for `off`.
+    pub fn parse(name: &str) -> Result<Option<Self>, LoggingError> {
+        match name.to_lowercase().as_str() {
+            "off" | "none" => Ok(None),
+            "error" => Ok(Some(LogLevel::Error)),
+            "warn" | "warning" => Ok(Some(LogLevel::Warn)),
+            "info" => Ok(Some(LogLevel::Info)),
+            "debug" => Ok(Some(LogLevel::Debug)),
+            "trace" => Ok(Some(LogLevel::Trace)),
+            _ => Err(LoggingError::InvalidLevel(name.to_string())),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Trace => "TRACE",
+        }
+    }
+}
+
+/// Start logging as `config` says, in place of any logging started
+/// before.
+pub fn init_logging(config: &LoggingConfig) -> Result<(), LoggingError> {
+    let logger = match LogLevel::parse(&config.level)? {
+        Some(level) => Some(Logger::open(config, level)?),
+        None => None,
+    };
+    let level = logger.as_ref().map_or(0, |logger| logger.level as u8);
+    *LOGGER.lock().unwrap() = logger;
+    LEVEL.store(level, Ordering::Release);
+    Ok(())
+}
+
+/// Change the level logged at to `config`'s, keeping the same file, or
+/// start or stop logging as `init_logging` does if it is or was `off`.
+pub(crate) fn set_level(config: &LoggingConfig) -> Result<(), LoggingError> {
+    let level = LogLevel::parse(&config.level)?;
+    let mut logger = LOGGER.lock().unwrap();
+    match (logger.as_mut(), level) {
+        (Some(logger), Some(level)) => {
+            logger.level = level;
+            LEVEL.store(level as u8, Ordering::Release);
+            Ok(())
+        }
+        _ => {
+            drop(logger);
+            init_logging(config)
+        }
+    }
+}
+
+/// Whether events at `level` are logged.
+pub(crate) fn enabled(level: LogLevel) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Acquire)
+}
+
+/// Log an event to the installed logger. The `log!` macro calls this for
+/// events at a level that is logged.
+pub(crate) fn emit(level: LogLevel, target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    let spans = SPANS.with(|spans| spans.borrow().join(":"));
+    if let Some(logger) = LOGGER.lock().unwrap().as_mut() {
+        let _ = logger.log(SystemTime::now(), level, &spans, target, message, fields);
+    }
+}
+
+/// Log `message` at a level, from the module it's written in, with
+/// fields given as `key = value`:
+///
+/// ```ignore
+/// log!(Info, "session started", id = id, user = user);
+/// ```
+macro_rules! log {
+    ($level:ident, $message:expr $(, $key:ident = $value:expr)* $(,)?) => {
+        if $crate::logging::enabled($crate::logging::LogLevel::$level) {
+            $crate::logging::emit(
+                $crate::logging::LogLevel::$level,
+                module_path!(),
+                &$message,
+                &[$((stringify!($key), &$value as &dyn ::std::fmt::Display)),*],
+            );
+        }
+    };
+}
+pub(crate) use log;
+
+/// Open a span at a level, from the module it's written in, with fields
+/// given as `key = value`, until the span returned is dropped:
+///
+/// ```ignore
+/// let _span = span!(Debug, "statement", id = id);
+/// ```
+macro_rules! span {
+    ($level:ident, $name:expr $(, $key:ident = $value:expr)* $(,)?) => {
+        $crate::logging::Span::enter(
+            $crate::logging::LogLevel::$level,
+            module_path!(),
+            $name,
+            &[$((stringify!($key), &$value as &dyn ::std::fmt::Display)),*],
+        )
+    };
+}
+pub(crate) use span;
+
+/// An open span, made by `span!`, which closes when dropped. Spans close
+/// in the opposite order to how they were opened, as guards on the stack
+/// do.
+pub(crate) struct Span {
+    /// `None` if the span isn't logged
+    level: Option<LogLevel>,
+    target: &'static str,
+    /// Where the span is in `SPANS`
+    depth: usize,
+    opened: Instant,
+}
+
+impl Span {
+    pub(crate) fn enter(
+        level: LogLevel,
+        target: &'static str,
+        name: &str,
+        fields: &[(&str, &dyn Display)],
+    ) -> Self {
+        let opened = Instant::now();
+        if !enabled(level) {
+            return Self {
+                level: None,
+                target,
+                depth: 0,
+                opened,
+            };
+        }
+        let span = span_text(name, fields);
+        let depth = SPANS.with(|spans| {
+            let mut spans = spans.borrow_mut();
+            spans.push(span);
+            spans.len() - 1
+        });
+        Self {
+            level: Some(level),
+            target,
+            depth,
+            opened,
+        }
+    }
+
+    /// Add a field learned once the span was open, such as the
+    /// transaction an operation began.
+    pub(crate) fn record(&self, key: &str, value: &dyn Display) {
+        if self.level.is_none() {
+            return;
+        }
+        SPANS.with(|spans| {
+            let span = &mut spans.borrow_mut()[self.depth];
+            let field = fields_text(&[(key, value)]);
+            if span.ends_with('}') {
+                span.insert_str(span.len() - 1, &field);
+            } else {
+                span.push('{');
+                span.push_str(field.trim_start());
+                span.push('}');
+            }
+        });
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(level) = self.level else {
+            return;
+        };
+        let elapsed = self.opened.elapsed().as_micros();
+        emit(level, self.target, "close", &[("elapsed_us", &elapsed)]);
+        SPANS.with(|spans| spans.borrow_mut().truncate(self.depth));
+    }
+}
+
+/// A span as it's written: its name, and its fields in braces.
+fn span_text(name: &str, fields: &[(&str, &dyn Display)]) -> String {
+    if fields.is_empty() {
+        return name.to_string();
+    }
+    format!("{}{{{}}}", name, fields_text(fields).trim_start())
+}
+
+/// `fields` as they're written after a message, each after a space.
+fn fields_text(fields: &[(&str, &dyn Display)]) -> String {
+    let mut text = String::new();
+    for (key, value) in fields {
+        text.push_str(&format!(" {}={}", key, quoted(&value.to_string())));
+    }
+    text
+}
+
+/// Writes events to a log file, rotating it as configured.
+struct Logger {
+    level: LogLevel,
+    path: PathBuf,
+    file: File,
+    /// How long the file is
+    size: u64,
+    /// The size past which the file is rotated, if it is
+    max_size: Option<u64>,
+    max_files: u32,
+}
+
+impl Logger {
+    fn open(config: &LoggingConfig, level: LogLevel) -> Result<Self, LoggingError> {
+        let path = PathBuf::from(&config.file);
+        let file = append(&path).map_err(|source| LoggingError::Open {
+            path: path.clone(),
+            source,
+        })?;
+        let size = file.metadata().map_or(0, |metadata| metadata.len());
+        Ok(Self {
+            level,
+            path,
+            file,
+            size,
+            // A limit of 0 is none
+            max_size: match config.max_size_mb {
+                0 => None,
+                mb if config.rotate => Some(mb.saturating_mul(1024 * 1024)),
+                _ => None,
+            },
+            max_files: config.max_files,
+        })
+    }
+
+    /// Write an event, in the open `spans` written as they're logged.
+    fn log(
+        &mut self,
+        time: SystemTime,
+        level: LogLevel,
+        spans: &str,
+        target: &str,
+        message: &str,
+        fields: &[(&str, &dyn Display)],
+    ) -> io::Result<()> {
+        if level > self.level {
+            return Ok(());
+        }
+        let mut line = format!("{} {} ", Timestamp(time), level.name());
+        if !spans.is_empty() {
+            line.push_str(spans);
+            line.push_str(": ");
+        }
+        line.push_str(&format!("{}: {}", target, message));
+        line.push_str(&fields_text(fields));
+        line.push('\n');
+        let len = line.len() as u64;
+        // A line longer than the limit still goes in a file of its own
+        if self
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + len > max)
+        {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Shift the file and the older files along one, dropping the oldest
+    /// past `max_files`, and start the file again.
+    fn rotate(&mut self) -> io::Result<()> {
+        let numbered = |n: u32| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", n));
+            PathBuf::from(path)
+        };
+        match self.max_files {
+            0 => fs::remove_file(&self.path)?,
+            max => {
+                let _ = fs::remove_file(numbered(max));
+                for n in (1..max).rev() {
+                    let _ = fs::rename(numbered(n), numbered(n + 1));
+                }
+                fs::rename(&self.path, numbered(1))?;
+            }
+        }
+        self.file = append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// `value`, in quotes with its quotes and backslashes escaped if it's
+/// empty or holds spaces, quotes or `=`.
+pub(crate) fn quoted(value: &str) -> String {
+    if !value.is_empty()
+        && !value.contains(|c: char| c.is_whitespace() || c.is_control() || "\"=".contains(c))
+    {
+        return value.to_string();
+    }
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A time written as in RFC 3339, in UTC to the millisecond.
+pub(crate) struct Timestamp(pub(crate) SystemTime);
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since = self.0.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since.as_secs();
+        let (year, month, day) = civil((secs / 86_400) as i64);
+        let time = secs % 86_400;
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            year,
+            month,
+            day,
+            time / 3600,
+            time / 60 % 60,
+            time % 60,
+            since.subsec_millis()
+        )
+    }
+}
+
+impl Timestamp {
+    /// The time `text` gives as in RFC 3339: a date, then optionally `T`
+    /// or a space and a time to the second or a fraction of one, then `Z`
+    /// or an offset from UTC. A time without either is in UTC.
+    pub(crate) fn parse(text: &str) -> Option<SystemTime> {
+        let (date, time) = text.split_once(['T', 't', ' ']).unwrap_or((text, ""));
+        let (time, offset) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+            (time, 0)
+        } else if let Some(at) = time.rfind(['+', '-']) {
+            let (hours, minutes) = time[at + 1..].split_once(':')?;
+            let offset = digits(hours, 2)? * 3600 + digits(minutes, 2)? * 60;
+            let sign = if time[at..].starts_with('-') { -1 } else { 1 };
+            (&time[..at], sign * offset)
+        } else {
+            (time, 0)
+        };
+
+        let mut date = date.split('-');
+        let year = digits(date.next()?, 4)?;
+        let month = digits(date.next()?, 2)?;
+        let day = digits(date.next()?, 2)?;
+        if date.next().is_some() || !(1..=12).contains(&month) {
+            return None;
+        }
+        if day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        let (mut seconds, mut nanos) = (0, 0);
+        if !time.is_empty() {
+            let (whole, fraction) = time.split_once('.').unwrap_or((time, ""));
+            let mut fields = whole.split(':');
+            let hour = digits(fields.next()?, 2)?;
+            let minute = digits(fields.next()?, 2)?;
+            let second = match fields.next() {
+                Some(second) => digits(second, 2)?,
+                None => 0,
+            };
+            if fields.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+                return None;
+            }
+            seconds = hour * 3600 + minute * 60 + second;
+            if !fraction.is_empty() {
+                let fraction = &fraction[..fraction.len().min(9)];
+                nanos = digits(fraction, fraction.len())? * 10_i64.pow(9 - fraction.len() as u32);
+            }
+        }
+
+        let secs = days_from_civil(year, month, day) * 86_400 + seconds - offset;
+        let nanos = Duration::from_nanos(nanos as u64);
+        Some(match u64::try_from(secs) {
+            Ok(secs) => UNIX_EPOCH + Duration::from_secs(secs) + nanos,
+            Err(_) => UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + nanos,
+        })
+    }
+}
+
+/// `text` as a number, if it's `len` decimal digits.
+fn digits(text: &str, len: usize) -> Option<i64> {
+    if text.len() != len || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+pub(crate) fn days_in_month(year: i64, month: i64) -> i64 {
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The days from 1970-01-01 to a date, by Howard Hinnant's
+/// `days_from_civil`.
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The year, month and day `days` after 1970-01-01, by Howard Hinnant's
+/// `civil_from_days`.
+pub(crate) fn civil(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_logger() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logs").join("ferrodb.log");
+        let config = LoggingConfig {
+            level: "debug".to_string(),
+            file: path.to_string_lossy().into_owned(),
+            max_size_mb: 1,
+            rotate: true,
+            max_files: 2,
+            slow_query_ms: None,
+        };
+        let mut logger = Logger::open(&config, LogLevel::Info).unwrap();
+        let time = UNIX_EPOCH + Duration::from_millis(1_791_970_200_125);
+        let user = "bob \"b\"".to_string();
+        let fields: [(&str, &dyn Display); 2] = [("id", &1), ("user", &user)];
+        logger
+            .log(
+                time,
+                LogLevel::Info,
+                "",
+                "ferrodb::server",
+                "started",
+                &fields,
+            )
+            .unwrap();
+        logger
+            .log(time, LogLevel::Debug, "", "ferrodb::server", "hidden", &[])
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "2026-10-14T09:30:00.125Z INFO ferrodb::server: started id=1 user=\"bob \\\"b\\\"\"\n"
+        );
+
+        // Lines past a megabyte go to a new file, and only two old ones
+        // are kept
+        let message = "x".repeat(400 * 1024);
+        for _ in 0..9 {
+            logger
+                .log(time, LogLevel::Error, "", "ferrodb", &message, &[])
+                .unwrap();
+        }
+        let numbered = |n: u32| PathBuf::from(format!("{}.{}", path.display(), n));
+        let lines = |path: PathBuf| fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(lines(path.clone()), 1);
+        assert_eq!(lines(numbered(1)), 2);
+        assert_eq!(lines(numbered(2)), 2);
+        assert!(!numbered(3).exists());
+
+        assert!(matches!(
+            LogLevel::parse("loud"),
+            Err(LoggingError::InvalidLevel(_))
+        ));
+        assert_eq!(LogLevel::parse("OFF").unwrap(), None);
+        assert_eq!(civil(0), (1970, 1, 1));
+        assert_eq!(civil(11_016), (2000, 2, 29));
+        assert_eq!(civil(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn test_spans() {
+        let _logger = TEST_LOGGER.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ferrodb.log");
+        let mut config = LoggingConfig {
+            level: "debug".to_string(),
+            file: path.to_string_lossy().into_owned(),
+            max_size_mb: 1,
+            rotate: false,
+            max_files: 1,
+            slow_query_ms: None,
+        };
+        init_logging(&config).unwrap();
+        {
+            let outer = span!(Debug, "test_outer", id = 3);
+            outer.record("txn", &9);
+            let _inner = span!(Debug, "test_inner");
+            // Too fine for the level, so not among the spans at all
+            let _hidden = span!(Trace, "test_hidden", page = 1);
+            log!(Info, "test event", n = 1);
+        }
+        log!(Info, "test after");
+        config.level = "off".to_string();
+        init_logging(&config).unwrap();
+
+        // Other tests' threads may log here too, each with its own spans
+        let log = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log
+            .lines()
+            .filter(|line| line.contains("test_") || line.contains("test after"))
+            .map(|line| line.split_once(' ').unwrap().1)
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "INFO test_outer{id=3 txn=9}:test_inner: ferrodb::logging::tests: test event n=1"
+        );
+        // Spans close innermost first, with how long they were open
+        let (closing, elapsed) = lines[1].rsplit_once('=').unwrap();
+        assert_eq!(
+            closing,
+            "DEBUG test_outer{id=3 txn=9}:test_inner: ferrodb::logging::tests: close elapsed_us"
+        );
+        assert!(elapsed.parse::<u64>().is_ok());
+        assert!(lines[2].starts_with(
+            "DEBUG test_outer{id=3 txn=9}: ferrodb::logging::tests: close elapsed_us="
+        ));
+        assert_eq!(lines[3], "INFO ferrodb::logging::tests: test after");
+
+        // Spans aren't kept while nothing is logged
+        let _span = span!(Debug, "test_unlogged");
+        SPANS.with(|spans| assert!(spans.borrow().is_empty()));
+        assert_eq!(span_text("statement", &[("id", &3)]), "statement{id=3}");
+        assert_eq!(span_text("parse", &[]), "parse");
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_791_970_200_125);
+        assert_eq!(Timestamp::parse("2026-10-14T09:30:00.125Z"), Some(time));
+        assert_eq!(
+            Timestamp::parse("2026-10-14 11:30:00.125+02:00"),
+            Some(time)
+        );
+        assert_eq!(
+            Timestamp::parse("2000-02-29"),
+            Some(UNIX_EPOCH + Duration::from_secs(11_016 * 86_400))
+        );
+        assert_eq!(
+            Timestamp::parse("1969-12-31T23:59"),
+            Some(UNIX_EPOCH - Duration::from_secs(60))
+        );
+        for invalid in [
+            "2001-02-29",
+            "2026-13-01",
+            "2026-10-14T25:00",
+            "yesterday",
+            "17",
+        ] {
+            assert_eq!(Timestamp::parse(invalid), None, "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Mapping rows to and from application types with serde.
+//!
+//! A mapped row's data is a JSON object of its columns by name, as rows
+//! imported from SQLite and the rows of external tables are, so a struct
+//! is read from a row by matching its fields to the columns and written
+//! as one the same way. Reading checks each column against its field's
+//! type, so a row that doesn't fit fails rather than reading as
+//! something else: text where a number is wanted, a missing column
+//! without a default, or data that isn't an object at all.
+
+use crate::database::{Connection, DatabaseError, RowId, TableId};
+use crate::encoding::json_string;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_yaml::Value;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RowMappingError {
+    /// A row that doesn't fit the type it was read as
+    #[error("row {row}: {message}")]
+    Decode { row: RowId, message: String },
+
+    /// A value with no JSON object to write it as
+    #[error("could not encode the row: {0}")]
+    Encode(String),
+
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+}
+
+impl Connection<'_> {
+    /// The rows of `table`, each read as a `T` by column name.
+    pub fn query_as<T: DeserializeOwned>(
+        &mut self,
+        table: TableId,
+    ) -> Result<Vec<T>, RowMappingError> {
+        self.scan(table)?
+            .into_iter()
+            .map(|row| {
+                let decode = |message: String| RowMappingError::Decode {
+                    row: row.id,
+                    message,
+                };
+                // JSON is YAML, but YAML holds more than objects
+                if !row.data.trim_ascii_start().starts_with(b"{") {
+                    return Err(decode("not a JSON object".to_string()));
+                }
+                serde_yaml::from_slice(&row.data).map_err(|e| decode(e.to_string()))
+            })
+            .collect()
+    }
+
+    /// Insert `value` into `table` as a JSON object of its fields.
+    pub fn insert_from<T: Serialize>(
+        &mut self,
+        table: TableId,
+        value: &T,
+    ) -> Result<RowId, RowMappingError> {
+        let value = serde_yaml::to_value(value).map_err(|e| encode(e.to_string()))?;
+        if !value.is_mapping() {
+            return Err(encode("only structs and maps have columns".to_string()));
+        }
+        let mut data = String::new();
+        json(&value, &mut data)?;
+        Ok(self.insert(table, data.as_bytes())?)
+    }
+}
+
+/// The columns of a row of `data`, if it's a JSON object.
+fn object(data: &[u8]) -> Option<serde_yaml::Mapping> {
+    // JSON is YAML, but YAML holds more than objects
+    if !data.trim_ascii_start().starts_with(b"{") {
+        return None;
+    }
+    serde_yaml::from_slice(data).ok()
+}
+
+/// A column's value as text, if it's a string, number or boolean.
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// The value of `column` in a row of `data` as text, if the row is a JSON
+/// object with a string, number or boolean there.
+pub(crate) fn column_value(data: &[u8], column: &str) -> Option<String> {
+    text(object(data)?.get(column)?)
+}
+
+/// Each column of a row of `data` with a string, number or boolean, by
+/// name and as text, in the order they're written; none if the row isn't
+/// a JSON object.
+pub(crate) fn column_values(data: &[u8]) -> Vec<(String, String)> {
+    let Some(row) = object(data) else {
+        return Vec::new();
+    };
+    row.iter()
+        .filter_map(|(name, value)| Some((name.as_str()?.to_string(), text(value)?)))
+        .collect()
+}
+
+fn encode(message: String) -> RowMappingError {
+    RowMappingError::Encode(message)
+}
+
+/// Write `value` to `out` as JSON. Enum variants with data are objects of
+/// the data by variant name, as serde writes them in JSON.
+fn json(value: &Value, out: &mut String) -> Result<(), RowMappingError> {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(number) => match (number.as_u64(), number.as_i64(), number.as_f64()) {
+            (Some(n), _, _) => out.push_str(&n.to_string()),
+            (_, Some(n), _) => out.push_str(&n.to_string()),
+            (_, _, Some(n)) if n.is_finite() => out.push_str(&format!("{:?}", n)),
+            _ => return Err(encode(format!("{} is not a JSON number", number))),
+        },
+        Value::String(s) => out.push_str(&json_string(s)),
+        Value::Sequence(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                json(value, out)?;
+            }
+            out.push(']');
+        }
+        Value::Mapping(mapping) => {
+            out.push('{');
+            for (i, (key, value)) in mapping.iter().enumerate() {
+                let Value::String(key) = key else {
+                    return Err(encode("column names must be strings".to_string()));
+                };
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&json_string(key));
+                out.push(':');
+                json(value, out)?;
+            }
+            out.push('}');
+        }
+        Value::Tagged(tagged) => {
+            let variant = tagged.tag.to_string();
+            out.push('{');
+            out.push_str(&json_string(variant.trim_start_matches('!')));
+            out.push(':');
+            json(&tagged.value, out)?;
+            out.push('}');
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Track {
+        title: String,
+        seconds: u32,
+        rating: Option<f64>,
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn test_query_as() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = Database::open(dir.path().join("test.fdb")).unwrap();
+        let mut connection = database.connect();
+        let table = database.create_table().unwrap();
+
+        let track = Track {
+            title: "So \"What\"".to_string(),
+            seconds: 562,
+            rating: Some(4.5),
+            tags: vec!["modal".to_string()],
+        };
+        let row = connection.insert_from(table, &track).unwrap();
+        assert_eq!(
+            connection.get(row).unwrap().unwrap().data,
+            br#"{"title":"So \"What\"","seconds":562,"rating":4.5,"tags":["modal"]}"#
+        );
+        // Columns come by name, in any order, and missing options are none
+        connection
+            .insert(table, br#"{"tags":[],"seconds":1,"title":"Blue"}"#)
+            .unwrap();
+        let tracks: Vec<Track> = connection.query_as(table).unwrap();
+        assert_eq!(tracks[0], track);
+        assert_eq!(tracks[1].rating, None);
+
+        let wrong = connection
+            .insert(table, br#"{"title":"Blue","seconds":"1","tags":[]}"#)
+            .unwrap();
+        match connection.query_as::<Track>(table) {
+            Err(RowMappingError::Decode { row, message }) => {
+                assert_eq!(row, wrong);
+                assert!(message.contains("seconds"), "{}", message);
+            }
+            other => panic!("expected a decode error, got {:?}", other),
+        }
+        connection.delete(wrong).unwrap();
+        let text = connection.insert(table, b"seconds: 1").unwrap();
+        assert!(matches!(
+            connection.query_as::<Track>(table),
+            Err(RowMappingError::Decode { row, .. }) if row == text
+        ));
+
+        assert!(matches!(
+            connection.insert_from(table, &5),
+            Err(RowMappingError::Encode(_))
+        ));
+    }
+}
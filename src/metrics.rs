@@ -0,0 +1,341 @@
+//! Counting queries and how long they take, and reporting them with the
+//! buffer pool's and log's own counters as Prometheus scrapes them.
+//!
+//! Each `Database` keeps a `QueryCounters` that sessions add to as they
+//! run statements; `Database::metrics` takes a snapshot of everything,
+//! and `Metrics::prometheus` writes it in Prometheus' text exposition
+//! format, as the server's `/metrics` endpoint serves it. Throughput is
+//! left to Prometheus, to take as the rate of `ferrodb_queries_total`.
+//!
+//! Each table read or written has `TableCounters` of its own too, which
+//! `Database::table_stats` lists as `information_schema.table_stats` does
+//! and the metrics total.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the latency histogram's buckets, in seconds.
+pub const LATENCY_BUCKETS: [f64; 10] = [0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0];
+
+/// Running totals of the queries run against a database.
+#[derive(Default)]
+pub(crate) struct QueryCounters {
+    queries: AtomicU64,
+    failed: AtomicU64,
+    /// Queries that took no longer than each of `LATENCY_BUCKETS`, not
+    /// counting those within a smaller bound
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    micros: AtomicU64,
+}
+
+impl QueryCounters {
+    /// Count a query that took `elapsed`.
+    pub(crate) fn record(&self, elapsed: Duration, succeeded: bool) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        if !succeeded {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.micros.fetch_add(micros, Ordering::Relaxed);
+    }
+}
+
+/// Running totals of the reads and writes of one table.
+#[derive(Default)]
+pub(crate) struct TableCounters {
+    seq_scans: AtomicU64,
+    rows_scanned: AtomicU64,
+    index_scans: AtomicU64,
+    rows_fetched: AtomicU64,
+    rows_inserted: AtomicU64,
+    rows_updated: AtomicU64,
+    rows_deleted: AtomicU64,
+}
+
+impl TableCounters {
+    /// Count a scan of the whole table that read `rows`.
+    pub(crate) fn scanned(&self, rows: u64) {
+        self.seq_scans.fetch_add(1, Ordering::Relaxed);
+        self.rows_scanned.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    /// Count a lookup that read only some of the table's pages and found
+    /// `rows`.
+    pub(crate) fn fetched(&self, rows: u64) {
+        self.index_scans.fetch_add(1, Ordering::Relaxed);
+        self.rows_fetched.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    pub(crate) fn inserted(&self, rows: u64) {
+        self.rows_inserted.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    pub(crate) fn updated(&self) {
+        self.rows_updated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn deleted(&self) {
+        self.rows_deleted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> TableStats {
+        TableStats {
+            seq_scans: self.seq_scans.load(Ordering::Relaxed),
+            rows_scanned: self.rows_scanned.load(Ordering::Relaxed),
+            index_scans: self.index_scans.load(Ordering::Relaxed),
+            rows_fetched: self.rows_fetched.load(Ordering::Relaxed),
+            rows_inserted: self.rows_inserted.load(Ordering::Relaxed),
+            rows_updated: self.rows_updated.load(Ordering::Relaxed),
+            rows_deleted: self.rows_deleted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The reads and writes of a table since the database opened, or of every
+/// table together. Rows written by transactions that rolled back count
+/// too.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableStats {
+    /// Reads of every row
+    pub seq_scans: u64,
+    /// Rows those read
+    pub rows_scanned: u64,
+    /// Reads of some rows, by their ids or Bloom filters
+    pub index_scans: u64,
+    /// Rows those found
+    pub rows_fetched: u64,
+    pub rows_inserted: u64,
+    pub rows_updated: u64,
+    pub rows_deleted: u64,
+}
+
+impl TableStats {
+    /// Add `other`'s counts to these.
+    pub fn add(&mut self, other: &TableStats) {
+        self.seq_scans += other.seq_scans;
+        self.rows_scanned += other.rows_scanned;
+        self.index_scans += other.index_scans;
+        self.rows_fetched += other.rows_fetched;
+        self.rows_inserted += other.rows_inserted;
+        self.rows_updated += other.rows_updated;
+        self.rows_deleted += other.rows_deleted;
+    }
+}
+
+/// A point-in-time snapshot of a database's activity, from
+/// `Database::metrics`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    /// Statements and requests run, including those that failed
+    pub queries: u64,
+    pub failed_queries: u64,
+    /// Queries that took no longer than each of `LATENCY_BUCKETS`,
+    /// cumulatively, as Prometheus counts them
+    pub latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    /// Time spent running queries
+    pub latency_sum: Duration,
+    /// Page requests served from the buffer pool
+    pub buffer_hits: u64,
+    /// Page requests that had to read from disk
+    pub buffer_misses: u64,
+    /// Bytes of write-ahead log written, 0 without one
+    pub wal_bytes: u64,
+    /// Log syncs to the OS or stable storage
+    pub wal_syncs: u64,
+    pub active_transactions: usize,
+    /// The reads and writes of every table
+    pub tables: TableStats,
+}
+
+impl Metrics {
+    pub(crate) fn from_counters(counters: &QueryCounters) -> Self {
+        let mut latency_buckets = [0; LATENCY_BUCKETS.len()];
+        let mut total = 0;
+        for (bucket, counter) in latency_buckets.iter_mut().zip(&counters.buckets) {
+            total += counter.load(Ordering::Relaxed);
+            *bucket = total;
+        }
+        Self {
+            queries: counters.queries.load(Ordering::Relaxed),
+            failed_queries: counters.failed.load(Ordering::Relaxed),
+            latency_buckets,
+            latency_sum: Duration::from_micros(counters.micros.load(Ordering::Relaxed)),
+            ..Default::default()
+        }
+    }
+
+    /// Fraction of page requests served from the buffer pool, or 0 before
+    /// any requests have been made.
+    pub fn buffer_hit_ratio(&self) -> f64 {
+        let requests = self.buffer_hits + self.buffer_misses;
+        if requests == 0 {
+            0.0
+        } else {
+            self.buffer_hits as f64 / requests as f64
+        }
+    }
+
+    /// The metrics in Prometheus' text exposition format.
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        metric(
+            "ferrodb_queries_total",
+            "counter",
+            "Statements and requests run.",
+            self.queries.to_string(),
+        );
+        metric(
+            "ferrodb_failed_queries_total",
+            "counter",
+            "Statements and requests that failed.",
+            self.failed_queries.to_string(),
+        );
+        metric(
+            "ferrodb_buffer_hits_total",
+            "counter",
+            "Page requests served from the buffer pool.",
+            self.buffer_hits.to_string(),
+        );
+        metric(
+            "ferrodb_buffer_misses_total",
+            "counter",
+            "Page requests read from disk.",
+            self.buffer_misses.to_string(),
+        );
+        metric(
+            "ferrodb_buffer_hit_ratio",
+            "gauge",
+            "Fraction of page requests served from the buffer pool.",
+            self.buffer_hit_ratio().to_string(),
+        );
+        metric(
+            "ferrodb_wal_bytes_total",
+            "counter",
+            "Bytes of write-ahead log written.",
+            self.wal_bytes.to_string(),
+        );
+        metric(
+            "ferrodb_wal_syncs_total",
+            "counter",
+            "Syncs of the write-ahead log.",
+            self.wal_syncs.to_string(),
+        );
+        metric(
+            "ferrodb_active_transactions",
+            "gauge",
+            "Transactions running.",
+            self.active_transactions.to_string(),
+        );
+        let tables = [
+            (
+                "seq_scans",
+                "Reads of every row of a table.",
+                self.tables.seq_scans,
+            ),
+            (
+                "rows_scanned",
+                "Rows read by scans of whole tables.",
+                self.tables.rows_scanned,
+            ),
+            (
+                "index_scans",
+                "Reads of some rows of a table, by id or Bloom filter.",
+                self.tables.index_scans,
+            ),
+            (
+                "rows_fetched",
+                "Rows found by reads of some rows.",
+                self.tables.rows_fetched,
+            ),
+            ("rows_inserted", "Rows inserted.", self.tables.rows_inserted),
+            ("rows_updated", "Rows updated.", self.tables.rows_updated),
+            ("rows_deleted", "Rows deleted.", self.tables.rows_deleted),
+        ];
+        for (name, help, value) in tables {
+            metric(
+                &format!("ferrodb_{}_total", name),
+                "counter",
+                help,
+                value.to_string(),
+            );
+        }
+
+        let name = "ferrodb_query_duration_seconds";
+        let _ = writeln!(out, "# HELP {} Time taken to run queries.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.latency_buckets) {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.queries);
+        let _ = writeln!(out, "{}_sum {}", name, self.latency_sum.as_secs_f64());
+        let _ = writeln!(out, "{}_count {}", name, self.queries);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        let counters = QueryCounters::default();
+        counters.record(Duration::from_micros(200), true);
+        counters.record(Duration::from_millis(3), true);
+        counters.record(Duration::from_secs(20), false);
+        let mut metrics = Metrics::from_counters(&counters);
+        assert_eq!(metrics.queries, 3);
+        assert_eq!(metrics.failed_queries, 1);
+        // The slowest is past every bound, so only in `+Inf`
+        assert_eq!(metrics.latency_buckets, [1, 1, 2, 2, 2, 2, 2, 2, 2, 2]);
+        assert_eq!(metrics.latency_sum, Duration::from_micros(20_003_200));
+
+        metrics.buffer_hits = 3;
+        metrics.buffer_misses = 1;
+        let text = metrics.prometheus();
+        assert!(text.contains("# TYPE ferrodb_queries_total counter\nferrodb_queries_total 3\n"));
+        assert!(text.contains("ferrodb_buffer_hit_ratio 0.75\n"));
+        assert!(text.contains("ferrodb_query_duration_seconds_bucket{le=\"0.005\"} 2\n"));
+        assert!(text.contains("ferrodb_query_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("ferrodb_query_duration_seconds_sum 20.0032\n"));
+        assert!(text.contains("ferrodb_seq_scans_total 0\n"));
+    }
+
+    #[test]
+    fn test_table_counters() {
+        let counters = TableCounters::default();
+        counters.scanned(10);
+        counters.scanned(0);
+        counters.fetched(2);
+        counters.inserted(3);
+        counters.updated();
+        counters.deleted();
+        let stats = counters.stats();
+        assert_eq!(
+            stats,
+            TableStats {
+                seq_scans: 2,
+                rows_scanned: 10,
+                index_scans: 1,
+                rows_fetched: 2,
+                rows_inserted: 3,
+                rows_updated: 1,
+                rows_deleted: 1,
+            }
+        );
+        let mut total = stats;
+        total.add(&stats);
+        assert_eq!(total.rows_scanned, 20);
+        assert_eq!(total.rows_deleted, 2);
+    }
+}
@@ -0,0 +1,326 @@
+//! Rows too large for a page. A row takes at most the largest record a
+//! page holds; one larger that is a JSON object has its longest text
+//! values moved out, a page-sized piece at a time, to overflow records of
+//! its table, until what is left fits. The row's own record then holds
+//! the rest of it and where each value was cut out and went, and is read
+//! back whole. Any other row that large fails with `RowTooLarge`, naming
+//! the column that takes the most room.
+//!
+//! A spilled row's record is, big-endian:
+//!
+//! | Size | Field                                                       |
+//! |------|-------------------------------------------------------------|
+//! | 2    | Number of values moved out                                  |
+//! |      | For each, in the order they were in the row:                |
+//! | 4    | Offset of the value in the whole row                        |
+//! | 2    | Number of pieces                                            |
+//! | 12   | Page number (8) and slot (4) of each piece, in order        |
+//! | rest | The row without its values                                  |
+
+use crate::database::DatabaseError;
+use crate::storage::PageDecodeError;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Cursor, Read};
+use std::ops::Range;
+
+/// Where an overflow record is in its table: page number and slot.
+pub(crate) type Piece = (u64, u32);
+
+/// A column of a JSON object row.
+struct Member {
+    name: String,
+    /// Where its value is in the row
+    value: Range<usize>,
+    text: bool,
+}
+
+/// The record of a spilled row.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Stub {
+    /// Each value moved out, by its offset in the whole row, with the
+    /// pieces it went to
+    pub(crate) values: Vec<(usize, Vec<Piece>)>,
+    /// The row without them
+    pub(crate) rest: Vec<u8>,
+}
+
+impl Stub {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.rest.len() + 64);
+        out.write_u16::<BigEndian>(self.values.len() as u16)
+            .unwrap();
+        for (at, pieces) in &self.values {
+            out.write_u32::<BigEndian>(*at as u32).unwrap();
+            out.write_u16::<BigEndian>(pieces.len() as u16).unwrap();
+            for &(page_no, slot) in pieces {
+                out.write_u64::<BigEndian>(page_no).unwrap();
+                out.write_u32::<BigEndian>(slot).unwrap();
+            }
+        }
+        out.extend_from_slice(&self.rest);
+        out
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> Result<Self, DatabaseError> {
+        let corrupted = |_| PageDecodeError::Corrupted("spilled row cut short".to_string());
+        let mut input = Cursor::new(data);
+        let count = input.read_u16::<BigEndian>().map_err(corrupted)?;
+        let mut values = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let at = input.read_u32::<BigEndian>().map_err(corrupted)? as usize;
+            let count = input.read_u16::<BigEndian>().map_err(corrupted)?;
+            let mut pieces = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let page_no = input.read_u64::<BigEndian>().map_err(corrupted)?;
+                pieces.push((page_no, input.read_u32::<BigEndian>().map_err(corrupted)?));
+            }
+            values.push((at, pieces));
+        }
+        let mut rest = Vec::new();
+        input.read_to_end(&mut rest).map_err(corrupted)?;
+        Ok(Self { values, rest })
+    }
+
+    /// Every overflow record the row's values went to.
+    pub(crate) fn pieces(&self) -> impl Iterator<Item = Piece> + '_ {
+        self.values
+            .iter()
+            .flat_map(|(_, pieces)| pieces.iter().copied())
+    }
+
+    /// The whole row, reading each piece of its values with `piece`.
+    pub(crate) fn join(
+        &self,
+        mut piece: impl FnMut(Piece) -> Result<Vec<u8>, DatabaseError>,
+    ) -> Result<Vec<u8>, DatabaseError> {
+        let mut row = Vec::new();
+        let mut rest = self.rest.as_slice();
+        for (at, pieces) in &self.values {
+            let before = at.checked_sub(row.len()).filter(|&len| len <= rest.len());
+            let Some(before) = before else {
+                let reason = "spilled value out of place".to_string();
+                return Err(PageDecodeError::Corrupted(reason).into());
+            };
+            row.extend_from_slice(&rest[..before]);
+            rest = &rest[before..];
+            for &at in pieces {
+                row.extend(piece(at)?);
+            }
+        }
+        row.extend_from_slice(rest);
+        Ok(row)
+    }
+}
+
+/// The bytes a spilled row's record takes to move `values` out of a row
+/// of `len` bytes, with pieces of at most `max` bytes.
+fn stub_size(len: usize, values: &[Range<usize>], max: usize) -> usize {
+    let moved: usize = values.iter().map(Range::len).sum();
+    let references: usize = values.iter().map(|value| reference_size(value, max)).sum();
+    2 + len - moved + references
+}
+
+/// The bytes pointing to `value` takes, moved out in pieces of at most
+/// `max` bytes.
+fn reference_size(value: &Range<usize>, max: usize) -> usize {
+    4 + 2 + 12 * value.len().div_ceil(max)
+}
+
+/// The values to move out of the row `data` for what's left to take no
+/// more than `max` bytes, in the order they're in it: none if it already
+/// fits, otherwise its longest text values.
+pub(crate) fn plan(data: &[u8], max: usize) -> Result<Vec<Range<usize>>, DatabaseError> {
+    if data.len() <= max {
+        return Ok(Vec::new());
+    }
+    let too_large = |column| DatabaseError::RowTooLarge {
+        size: data.len(),
+        max,
+        column,
+    };
+    let Some(mut members) = members(data) else {
+        return Err(too_large(None));
+    };
+    members.sort_by_key(|member| std::cmp::Reverse(member.value.len()));
+    let mut moved = Vec::new();
+    for member in &members {
+        // Moving it out would only take more room
+        if !member.text || member.value.len() <= reference_size(&member.value, max) {
+            continue;
+        }
+        moved.push(member.value.clone());
+        if stub_size(data.len(), &moved, max) <= max {
+            moved.sort_by_key(|value| value.start);
+            return Ok(moved);
+        }
+    }
+    let largest = members.iter().find(|member| !moved.contains(&member.value));
+    Err(too_large(
+        largest.map(|member| (member.name.clone(), member.value.len())),
+    ))
+}
+
+/// The pieces of at most `max` bytes a value is moved out in.
+pub(crate) fn pieces(value: &[u8], max: usize) -> impl Iterator<Item = &[u8]> {
+    value.chunks(max)
+}
+
+/// The columns of `data`, if it's a JSON object.
+fn members(data: &[u8]) -> Option<Vec<Member>> {
+    let mut at = skip_space(data, 0);
+    if data.get(at) != Some(&b'{') {
+        return None;
+    }
+    at = skip_space(data, at + 1);
+    let mut members = Vec::new();
+    if data.get(at) == Some(&b'}') {
+        return (skip_space(data, at + 1) == data.len()).then_some(members);
+    }
+    loop {
+        if data.get(at) != Some(&b'"') {
+            return None;
+        }
+        let name_end = skip_string(data, at)?;
+        let name = String::from_utf8_lossy(&data[at + 1..name_end - 1]).into_owned();
+        at = skip_space(data, name_end);
+        if data.get(at) != Some(&b':') {
+            return None;
+        }
+        let start = skip_space(data, at + 1);
+        let end = skip_value(data, start)?;
+        members.push(Member {
+            name,
+            value: start..end,
+            text: data[start] == b'"',
+        });
+        at = skip_space(data, end);
+        match data.get(at)? {
+            b',' => at = skip_space(data, at + 1),
+            b'}' => break,
+            _ => return None,
+        }
+    }
+    (skip_space(data, at + 1) == data.len()).then_some(members)
+}
+
+fn skip_space(data: &[u8], mut at: usize) -> usize {
+    while data.get(at).is_some_and(u8::is_ascii_whitespace) {
+        at += 1;
+    }
+    at
+}
+
+/// The end of the string starting with the quote at `at`.
+fn skip_string(data: &[u8], mut at: usize) -> Option<usize> {
+    at += 1;
+    loop {
+        match data.get(at)? {
+            b'\\' => at += 2,
+            b'"' => return Some(at + 1),
+            _ => at += 1,
+        }
+    }
+}
+
+/// The end of the value starting at `at`.
+fn skip_value(data: &[u8], mut at: usize) -> Option<usize> {
+    match data.get(at)? {
+        b'"' => skip_string(data, at),
+        b'{' | b'[' => {
+            let mut depth = 0;
+            loop {
+                match data.get(at)? {
+                    b'"' => {
+                        at = skip_string(data, at)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(at + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                at += 1;
+            }
+        }
+        _ => {
+            let start = at;
+            while data
+                .get(at)
+                .is_some_and(|byte| !b",}] \t\r\n".contains(byte))
+            {
+                at += 1;
+            }
+            (at > start).then_some(at)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan() {
+        let long = "x".repeat(300);
+        let row = format!(
+            r#"{{"id": 1, "name": "{}", "tags": ["a", "b"], "note": "short"}}"#,
+            long
+        );
+        let data = row.as_bytes();
+        assert_eq!(plan(b"small", 100).unwrap(), vec![]);
+        // Only the long value need go, though it takes pieces
+        let moved = plan(data, 150).unwrap();
+        assert_eq!(moved.len(), 1);
+        assert_eq!(&data[moved[0].clone()], format!("\"{}\"", long).as_bytes());
+        assert!(stub_size(data.len(), &moved, 150) <= 150);
+
+        // What's left too large names its largest column
+        let row = format!(r#"{{"name": "{}", "list": [{}1]}}"#, long, "1, ".repeat(40));
+        assert!(matches!(
+            plan(row.as_bytes(), 150),
+            Err(DatabaseError::RowTooLarge { size, max: 150, column: Some((name, 123)) })
+                if size == row.len() && name == "list"
+        ));
+        assert!(matches!(
+            plan(&[b'x'; 200], 100),
+            Err(DatabaseError::RowTooLarge {
+                size: 200,
+                max: 100,
+                column: None
+            })
+        ));
+    }
+
+    #[test]
+    fn test_stub() {
+        let row = br#"{"a": "0123456789", "b": 2, "c": "abcdefghij"}"#;
+        let values = [6..18, 28..40];
+        let mut pieces = Vec::new();
+        let mut stub = Stub {
+            values: Vec::new(),
+            rest: Vec::new(),
+        };
+        let mut from = 0;
+        for value in values {
+            stub.rest.extend_from_slice(&row[from..value.start]);
+            from = value.end;
+            let mut placed = Vec::new();
+            for piece in super::pieces(&row[value.clone()], 5) {
+                placed.push((pieces.len() as u64, 0));
+                pieces.push(piece.to_vec());
+            }
+            stub.values.push((value.start, placed));
+        }
+        stub.rest.extend_from_slice(&row[from..]);
+
+        let stub = Stub::decode(&stub.encode()).unwrap();
+        assert_eq!(stub.pieces().count(), 6);
+        let joined = stub.join(|(page_no, _)| Ok(pieces[page_no as usize].clone()));
+        assert_eq!(joined.unwrap(), row);
+        assert!(Stub::decode(&[0, 1, 0]).is_err());
+    }
+}
@@ -0,0 +1,820 @@
+//! Reading and writing Parquet files, for `COPY`, without the Arrow
+//! stack: files of flat columns only.
+//!
+//! A file is `PAR1`, row groups, each a chunk of pages per column, then
+//! the file's metadata, its length and `PAR1` again. Metadata and page
+//! headers are Thrift structures, in `thrift`.
+//!
+//! Files are written with an `id` and a `data` column, both required byte
+//! arrays, in row groups of uncompressed, plainly encoded pages. What most
+//! writers produce can be read: plain or dictionary encoded pages,
+//! version 1 or 2, uncompressed or compressed with Snappy, of any type
+//! but `INT96`, optional or required.
+
+mod snappy;
+mod thrift;
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use thiserror::Error;
+use thrift::{Struct, Value};
+
+const MAGIC: &[u8; 4] = b"PAR1";
+
+/// About how many bytes of values a row group holds before it's written.
+const ROW_GROUP_SIZE: usize = 4 << 20;
+
+// Physical types
+const BOOLEAN: i64 = 0;
+const INT32: i64 = 1;
+const INT64: i64 = 2;
+const INT96: i64 = 3;
+const FLOAT: i64 = 4;
+const DOUBLE: i64 = 5;
+const BYTE_ARRAY: i64 = 6;
+const FIXED_LEN_BYTE_ARRAY: i64 = 7;
+
+// Encodings
+const PLAIN: i64 = 0;
+const PLAIN_DICTIONARY: i64 = 2;
+const RLE: i64 = 3;
+const RLE_DICTIONARY: i64 = 8;
+
+// Page types
+const DATA_PAGE: i64 = 0;
+const DICTIONARY_PAGE: i64 = 2;
+const DATA_PAGE_V2: i64 = 3;
+
+// Repetitions
+const REQUIRED: i64 = 0;
+const OPTIONAL: i64 = 1;
+const REPEATED: i64 = 2;
+
+const UNCOMPRESSED: i64 = 0;
+const SNAPPY: i64 = 1;
+
+#[derive(Debug, Error)]
+pub(crate) enum ParquetError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("invalid Parquet file: {0}")]
+    Invalid(String),
+
+    #[error("Parquet files with {0} aren't supported")]
+    Unsupported(String),
+}
+
+impl ParquetError {
+    fn invalid(message: impl Into<String>) -> Self {
+        Self::Invalid(message.into())
+    }
+}
+
+/// Writes rows of an id and data to a Parquet file.
+pub(crate) struct ParquetWriter<W: Write> {
+    out: W,
+    /// Bytes written so far, where the next page goes
+    offset: u64,
+    /// The plainly encoded values of the row group being built
+    ids: Vec<u8>,
+    data: Vec<u8>,
+    rows_in_group: i64,
+    row_groups: Vec<Value>,
+    rows: i64,
+}
+
+impl<W: Write> ParquetWriter<W> {
+    pub(crate) fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(MAGIC)?;
+        Ok(Self {
+            out,
+            offset: MAGIC.len() as u64,
+            ids: Vec::new(),
+            data: Vec::new(),
+            rows_in_group: 0,
+            row_groups: Vec::new(),
+            rows: 0,
+        })
+    }
+
+    pub(crate) fn row(&mut self, id: &[u8], data: &[u8]) -> io::Result<()> {
+        for (values, value) in [(&mut self.ids, id), (&mut self.data, data)] {
+            values.extend((value.len() as u32).to_le_bytes());
+            values.extend(value);
+        }
+        self.rows_in_group += 1;
+        if self.ids.len() + self.data.len() >= ROW_GROUP_SIZE {
+            self.row_group()?;
+        }
+        Ok(())
+    }
+
+    /// Write what's left and the file's metadata, returning the output.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        if self.rows_in_group > 0 {
+            self.row_group()?;
+        }
+        let string = |name: &str| {
+            Value::Struct(Struct::new([
+                (1, Value::I32(BYTE_ARRAY as i32)),
+                (3, Value::I32(REQUIRED as i32)),
+                (4, Value::Binary(name.as_bytes().to_vec())),
+                // The UTF8 converted type, and the STRING logical type
+                (6, Value::I32(0)),
+                (
+                    10,
+                    Value::Struct(Struct::new([(1, Value::Struct(Struct::default()))])),
+                ),
+            ]))
+        };
+        let schema = vec![
+            Value::Struct(Struct::new([
+                (4, Value::Binary(b"schema".to_vec())),
+                (5, Value::I32(2)),
+            ])),
+            string("id"),
+            // Rows hold bytes, which needn't be text
+            Value::Struct(Struct::new([
+                (1, Value::I32(BYTE_ARRAY as i32)),
+                (3, Value::I32(REQUIRED as i32)),
+                (4, Value::Binary(b"data".to_vec())),
+            ])),
+        ];
+        let created_by = format!("ferrodb version {}", env!("CARGO_PKG_VERSION"));
+        let metadata = Struct::new([
+            (1, Value::I32(1)),
+            (2, Value::List(schema)),
+            (3, Value::I64(self.rows)),
+            (4, Value::List(std::mem::take(&mut self.row_groups))),
+            (6, Value::Binary(created_by.into_bytes())),
+        ]);
+        let mut footer = Vec::new();
+        metadata.encode(&mut footer);
+        let len = footer.len() as u32;
+        footer.extend(len.to_le_bytes());
+        footer.extend(MAGIC);
+        self.out.write_all(&footer)?;
+        Ok(self.out)
+    }
+
+    fn row_group(&mut self) -> io::Result<()> {
+        let start = self.offset;
+        let ids = std::mem::take(&mut self.ids);
+        let data = std::mem::take(&mut self.data);
+        let columns = vec![self.column("id", &ids)?, self.column("data", &data)?];
+        let size = (self.offset - start) as i64;
+        self.row_groups.push(Value::Struct(Struct::new([
+            (1, Value::List(columns)),
+            (2, Value::I64(size)),
+            (3, Value::I64(self.rows_in_group)),
+            (5, Value::I64(start as i64)),
+            (6, Value::I64(size)),
+            (7, Value::I16(self.row_groups.len() as i16)),
+        ])));
+        self.rows += self.rows_in_group;
+        self.rows_in_group = 0;
+        Ok(())
+    }
+
+    /// Write a column chunk of one page, returning its `ColumnChunk`.
+    fn column(&mut self, name: &str, values: &[u8]) -> io::Result<Value> {
+        let count = Value::I32(self.rows_in_group as i32);
+        let header = Struct::new([
+            (1, Value::I32(DATA_PAGE as i32)),
+            (2, Value::I32(values.len() as i32)),
+            (3, Value::I32(values.len() as i32)),
+            (
+                5,
+                Value::Struct(Struct::new([
+                    (1, count),
+                    (2, Value::I32(PLAIN as i32)),
+                    (3, Value::I32(RLE as i32)),
+                    (4, Value::I32(RLE as i32)),
+                ])),
+            ),
+        ]);
+        let mut page = Vec::new();
+        header.encode(&mut page);
+        page.extend(values);
+        let start = self.offset as i64;
+        self.out.write_all(&page)?;
+        self.offset += page.len() as u64;
+
+        let size = Value::I64(page.len() as i64);
+        let metadata = Struct::new([
+            (1, Value::I32(BYTE_ARRAY as i32)),
+            (
+                2,
+                Value::List(vec![Value::I32(PLAIN as i32), Value::I32(RLE as i32)]),
+            ),
+            (
+                3,
+                Value::List(vec![Value::Binary(name.as_bytes().to_vec())]),
+            ),
+            (4, Value::I32(UNCOMPRESSED as i32)),
+            (5, Value::I64(self.rows_in_group)),
+            (6, size.clone()),
+            (7, size),
+            (9, Value::I64(start)),
+        ]);
+        Ok(Value::Struct(Struct::new([
+            (2, Value::I64(start)),
+            (3, Value::Struct(metadata)),
+        ])))
+    }
+}
+
+/// Reads one column of a Parquet file a page at a time: the one named
+/// `data`, or the only one there is.
+pub(crate) struct ParquetReader<R: Read + Seek> {
+    input: R,
+    kind: i64,
+    /// The byte length of a `FIXED_LEN_BYTE_ARRAY`
+    type_length: usize,
+    optional: bool,
+    /// The column's chunk in each row group
+    chunks: Vec<Chunk>,
+    next_chunk: usize,
+    /// Values left to read in the current chunk
+    left: i64,
+    codec: i64,
+    dictionary: Vec<Vec<u8>>,
+    /// The values of the page read last, `None` for nulls
+    values: std::vec::IntoIter<Option<Vec<u8>>>,
+}
+
+struct Chunk {
+    start: u64,
+    values: i64,
+    codec: i64,
+}
+
+impl<R: Read + Seek> ParquetReader<R> {
+    pub(crate) fn open(mut input: R) -> Result<Self, ParquetError> {
+        let size = input.seek(SeekFrom::End(0))?;
+        if size < 12 {
+            return Err(ParquetError::invalid("it's too short"));
+        }
+        let mut magic = [0; 4];
+        input.seek(SeekFrom::Start(0))?;
+        input.read_exact(&mut magic)?;
+        // The metadata's length and the magic again end the file
+        let mut tail = [0; 8];
+        input.seek(SeekFrom::End(-8))?;
+        input.read_exact(&mut tail)?;
+        if magic != *MAGIC || tail[4..] != *MAGIC {
+            return Err(ParquetError::invalid("it doesn't start and end with PAR1"));
+        }
+        let len = u32::from_le_bytes(tail[..4].try_into().unwrap()) as u64;
+        if len + 12 > size {
+            return Err(ParquetError::invalid(
+                "its metadata is longer than the file",
+            ));
+        }
+        input.seek(SeekFrom::Start(size - 8 - len))?;
+        let mut footer = vec![0; len as usize];
+        input.read_exact(&mut footer)?;
+        let metadata = thrift::read_struct(&mut footer.as_slice())?;
+
+        let missing = |what: &str| ParquetError::invalid(format!("its metadata has no {}", what));
+        let schema = metadata.structs(2).ok_or_else(|| missing("schema"))?;
+        let columns = schema.get(1..).unwrap_or_default();
+        if columns.iter().any(|column| column.int(5).unwrap_or(0) > 0) {
+            return Err(ParquetError::Unsupported("nested columns".to_string()));
+        }
+        let names: Vec<&[u8]> = columns
+            .iter()
+            .map(|column| column.binary(4).unwrap_or_default())
+            .collect();
+        let index = match names.iter().position(|&name| name == b"data") {
+            Some(index) => index,
+            None if columns.len() == 1 => 0,
+            None => {
+                return Err(ParquetError::invalid(format!(
+                    "it has {} columns and none is named data",
+                    columns.len()
+                )))
+            }
+        };
+        let column = columns[index];
+        let kind = column.int(1).ok_or_else(|| missing("column type"))?;
+        if kind == INT96 {
+            return Err(ParquetError::Unsupported("INT96 columns".to_string()));
+        }
+        let optional = match column.int(3) {
+            Some(REPEATED) => {
+                return Err(ParquetError::Unsupported("repeated columns".to_string()))
+            }
+            repetition => repetition == Some(OPTIONAL),
+        };
+
+        let mut chunks = Vec::new();
+        for row_group in metadata.structs(4).ok_or_else(|| missing("row groups"))? {
+            let chunk = row_group
+                .structs(1)
+                .and_then(|columns| columns.get(index).copied())
+                .and_then(|chunk| chunk.get(3))
+                .ok_or_else(|| missing("column chunk"))?;
+            let data = chunk.int(9).ok_or_else(|| missing("data page offset"))?;
+            // Some writers record a dictionary offset of 0 for none
+            let start = match chunk.int(11) {
+                Some(dictionary) if dictionary > 0 => dictionary.min(data),
+                _ => data,
+            };
+            chunks.push(Chunk {
+                start: u64::try_from(start).map_err(|_| missing("valid page offset"))?,
+                values: chunk.int(5).ok_or_else(|| missing("value count"))?,
+                codec: chunk.int(4).unwrap_or(UNCOMPRESSED),
+            });
+        }
+        Ok(Self {
+            input,
+            kind,
+            type_length: column.int(2).unwrap_or(0).max(0) as usize,
+            optional,
+            chunks,
+            next_chunk: 0,
+            left: 0,
+            codec: UNCOMPRESSED,
+            dictionary: Vec::new(),
+            values: Vec::new().into_iter(),
+        })
+    }
+
+    /// The next value, `None` if it's null, or `None` at the end.
+    pub(crate) fn value(&mut self) -> Result<Option<Option<Vec<u8>>>, ParquetError> {
+        loop {
+            if let Some(value) = self.values.next() {
+                return Ok(Some(value));
+            }
+            if self.left <= 0 {
+                let Some(chunk) = self.chunks.get(self.next_chunk) else {
+                    return Ok(None);
+                };
+                self.input.seek(SeekFrom::Start(chunk.start))?;
+                self.left = chunk.values;
+                self.codec = chunk.codec;
+                self.dictionary.clear();
+                self.next_chunk += 1;
+                continue;
+            }
+            self.page()?;
+        }
+    }
+
+    /// Read the next page of the current chunk.
+    fn page(&mut self) -> Result<(), ParquetError> {
+        let header = thrift::read_struct(&mut self.input)?;
+        let missing = |what: &str| ParquetError::invalid(format!("a page header has no {}", what));
+        let size = |n: Option<i64>| {
+            n.and_then(|n| usize::try_from(n).ok())
+                .ok_or_else(|| missing("valid size"))
+        };
+        let uncompressed = size(header.int(2))?;
+        let mut page = Vec::new();
+        let compressed = size(header.int(3))?;
+        (&mut self.input)
+            .take(compressed as u64)
+            .read_to_end(&mut page)?;
+        if page.len() != compressed {
+            return Err(ParquetError::invalid(
+                "a page runs past the end of the file",
+            ));
+        }
+
+        let values = match header.int(1) {
+            Some(DICTIONARY_PAGE) => {
+                let dictionary = header.get(7).ok_or_else(|| missing("dictionary header"))?;
+                let count = size(dictionary.int(1))?;
+                if !matches!(dictionary.int(2), Some(PLAIN | PLAIN_DICTIONARY)) {
+                    return Err(self.encoding(dictionary.int(2)));
+                }
+                let page = self.decompress(page, uncompressed)?;
+                self.dictionary = self.plain(&page, count)?;
+                return Ok(());
+            }
+            Some(DATA_PAGE) => {
+                let data = header.get(5).ok_or_else(|| missing("data page header"))?;
+                let count = size(data.int(1))?;
+                let page = self.decompress(page, uncompressed)?;
+                // Definition levels come first, after their length
+                let (defined, rest) = if self.optional {
+                    let len = page
+                        .get(..4)
+                        .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize)
+                        .ok_or_else(|| ParquetError::invalid("a page ends in its levels"))?;
+                    let levels = page
+                        .get(4..4 + len)
+                        .ok_or_else(|| ParquetError::invalid("a page ends in its levels"))?;
+                    (hybrid(levels, 1, count)?, &page[4 + len..])
+                } else {
+                    (vec![1; count], &page[..])
+                };
+                self.values(data.int(2), &defined, rest)?
+            }
+            Some(DATA_PAGE_V2) => {
+                let data = header.get(8).ok_or_else(|| missing("data page header"))?;
+                let count = size(data.int(1))?;
+                let repetition = size(data.int(6))?;
+                let definition = size(data.int(5))?;
+                let levels = page
+                    .get(repetition..repetition + definition)
+                    .ok_or_else(|| ParquetError::invalid("a page ends in its levels"))?;
+                let defined = if self.optional {
+                    hybrid(levels, 1, count)?
+                } else {
+                    vec![1; count]
+                };
+                let rest = page[repetition + definition..].to_vec();
+                let rest = match data.bool(7).unwrap_or(true) {
+                    // The levels are never compressed
+                    true => {
+                        let uncompressed = uncompressed
+                            .checked_sub(repetition + definition)
+                            .ok_or_else(|| missing("valid size"))?;
+                        self.decompress(rest, uncompressed)?
+                    }
+                    false => rest,
+                };
+                self.values(data.int(4), &defined, &rest)?
+            }
+            // Index pages, which nothing writes, hold no values
+            _ => return Ok(()),
+        };
+        self.left -= values.len() as i64;
+        self.values = values.into_iter();
+        Ok(())
+    }
+
+    fn decompress(&self, page: Vec<u8>, uncompressed: usize) -> Result<Vec<u8>, ParquetError> {
+        let page = match self.codec {
+            UNCOMPRESSED => page,
+            SNAPPY => snappy::decompress(&page)?,
+            codec => {
+                let name = [
+                    "UNCOMPRESSED",
+                    "SNAPPY",
+                    "GZIP",
+                    "LZO",
+                    "BROTLI",
+                    "LZ4",
+                    "ZSTD",
+                    "LZ4_RAW",
+                ]
+                .get(codec as usize)
+                .unwrap_or(&"unknown");
+                return Err(ParquetError::Unsupported(format!("{} compression", name)));
+            }
+        };
+        if page.len() != uncompressed {
+            return Err(ParquetError::invalid(
+                "a page isn't the size its header gives",
+            ));
+        }
+        Ok(page)
+    }
+
+    /// A page's values, given its encoding, which of them are defined and
+    /// the encoded values of those.
+    fn values(
+        &self,
+        encoding: Option<i64>,
+        defined: &[u32],
+        data: &[u8],
+    ) -> Result<Vec<Option<Vec<u8>>>, ParquetError> {
+        let count = defined.iter().filter(|&&level| level == 1).count();
+        let mut values = match encoding {
+            Some(PLAIN) => self.plain(data, count)?,
+            Some(PLAIN_DICTIONARY | RLE_DICTIONARY) => {
+                let (&width, indices) = data.split_first().unwrap_or((&0, &[]));
+                hybrid(indices, width, count)?
+                    .into_iter()
+                    .map(|index| {
+                        self.dictionary.get(index as usize).cloned().ok_or_else(|| {
+                            ParquetError::invalid("a dictionary index is out of range")
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+            encoding => return Err(self.encoding(encoding)),
+        }
+        .into_iter();
+        Ok(defined
+            .iter()
+            .map(|&level| if level == 1 { values.next() } else { None })
+            .collect())
+    }
+
+    /// `count` plainly encoded values, as bytes: byte arrays as they are,
+    /// and other types as text.
+    fn plain(&self, data: &[u8], count: usize) -> Result<Vec<Vec<u8>>, ParquetError> {
+        let ends = || ParquetError::invalid("a page ends in its values");
+        let mut values = Vec::with_capacity(count.min(data.len()));
+        let mut at = 0;
+        let mut take = |n: usize| {
+            let bytes = data.get(at..at + n).ok_or_else(ends)?;
+            at += n;
+            Ok::<_, ParquetError>(bytes)
+        };
+        for i in 0..count {
+            let value = match self.kind {
+                BOOLEAN => {
+                    let byte = *data.get(i / 8).ok_or_else(ends)?;
+                    let value = byte >> (i % 8) & 1 == 1;
+                    value.to_string().into_bytes()
+                }
+                INT32 => i32::from_le_bytes(take(4)?.try_into().unwrap())
+                    .to_string()
+                    .into_bytes(),
+                INT64 => i64::from_le_bytes(take(8)?.try_into().unwrap())
+                    .to_string()
+                    .into_bytes(),
+                FLOAT => f32::from_le_bytes(take(4)?.try_into().unwrap())
+                    .to_string()
+                    .into_bytes(),
+                DOUBLE => f64::from_le_bytes(take(8)?.try_into().unwrap())
+                    .to_string()
+                    .into_bytes(),
+                FIXED_LEN_BYTE_ARRAY => take(self.type_length)?.to_vec(),
+                _ => {
+                    let len = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+                    take(len)?.to_vec()
+                }
+            };
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+    fn encoding(&self, encoding: Option<i64>) -> ParquetError {
+        let name = [
+            "PLAIN",
+            "GROUP_VAR_INT",
+            "PLAIN_DICTIONARY",
+            "RLE",
+            "BIT_PACKED",
+            "DELTA_BINARY_PACKED",
+            "DELTA_LENGTH_BYTE_ARRAY",
+            "DELTA_BYTE_ARRAY",
+            "RLE_DICTIONARY",
+            "BYTE_STREAM_SPLIT",
+        ]
+        .get(encoding.unwrap_or(-1) as usize)
+        .unwrap_or(&"unknown");
+        ParquetError::Unsupported(format!("{} encoded pages", name))
+    }
+}
+
+/// `count` numbers of `width` bits in the hybrid of run-length encoding
+/// and bit packing Parquet uses for levels and dictionary indices: runs,
+/// each a varint header whose low bit is clear for a repeated value and
+/// set for groups of eight packed values.
+fn hybrid(data: &[u8], width: u8, count: usize) -> Result<Vec<u32>, ParquetError> {
+    if width > 32 {
+        return Err(ParquetError::invalid("a bit width is over 32"));
+    }
+    let ends = || ParquetError::invalid("a page ends in a run");
+    let mut input = data;
+    let mut values = Vec::with_capacity(count);
+    while values.len() < count {
+        let header = thrift::read_varint(&mut input).map_err(|_| ends())?;
+        let len = usize::try_from(header >> 1).map_err(|_| ends())?;
+        if header & 1 == 0 {
+            let bytes = usize::from(width).div_ceil(8);
+            let value = input.get(..bytes).ok_or_else(ends)?;
+            input = &input[bytes..];
+            let value = value
+                .iter()
+                .rev()
+                .fold(0u32, |value, &byte| value << 8 | u32::from(byte));
+            let len = len.min(count - values.len());
+            values.extend(std::iter::repeat_n(value, len));
+        } else {
+            let bytes = len.checked_mul(usize::from(width)).ok_or_else(ends)?;
+            let packed = input.get(..bytes).ok_or_else(ends)?;
+            input = &input[bytes..];
+            for i in 0..len.saturating_mul(8).min(count - values.len()) {
+                let mut value = 0;
+                for bit in 0..usize::from(width) {
+                    let at = i * usize::from(width) + bit;
+                    value |= u32::from(packed[at / 8] >> (at % 8) & 1) << bit;
+                }
+                values.push(value);
+            }
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_parquet() {
+        let mut writer = ParquetWriter::new(Vec::new()).unwrap();
+        let big = vec![b'x'; ROW_GROUP_SIZE / 2];
+        let rows: Vec<(String, Vec<u8>)> = (0..5)
+            .map(|i| (format!("1:0:{}", i), format!("row {}", i).into_bytes()))
+            .chain([
+                ("1:1:0".to_string(), big.clone()),
+                ("1:2:0".to_string(), big),
+            ])
+            .chain([("1:3:0".to_string(), vec![0xff, 0])])
+            .collect();
+        for (id, data) in &rows {
+            writer.row(id.as_bytes(), data).unwrap();
+        }
+        let file = writer.finish().unwrap();
+
+        let mut reader = ParquetReader::open(Cursor::new(file)).unwrap();
+        // The big rows end the first row group, so there are two
+        assert_eq!(reader.chunks.len(), 2);
+        let mut read = Vec::new();
+        while let Some(value) = reader.value().unwrap() {
+            read.push(value.unwrap());
+        }
+        let data: Vec<Vec<u8>> = rows.into_iter().map(|(_, data)| data).collect();
+        assert_eq!(read, data);
+        assert!(ParquetReader::open(Cursor::new(b"PAR1 nonsense PAR1".to_vec())).is_err());
+
+        // What other writers make: an optional column, "name", compressed
+        // with Snappy, its chunk a dictionary and then pages of each version
+        let snappy =
+            |bytes: &[u8]| [&[bytes.len() as u8, (bytes.len() as u8 - 1) << 2], bytes].concat();
+        let int = |n: i64| Value::I32(n as i32);
+        let page = |header: Struct, data: &[u8]| {
+            let mut page = Vec::new();
+            header.encode(&mut page);
+            page.extend(data);
+            page
+        };
+        let dictionary = b"\x01\0\0\0a\x02\0\0\0bb";
+        let dictionary = page(
+            Struct::new([
+                (1, int(DICTIONARY_PAGE)),
+                (2, int(11)),
+                (3, int(13)),
+                (
+                    7,
+                    Value::Struct(Struct::new([(1, int(2)), (2, int(PLAIN))])),
+                ),
+            ]),
+            &snappy(dictionary),
+        );
+        // "bb", null, "a", "bb": the levels saying which are null, packed,
+        // then the dictionary indices of the others, packed, compressed
+        let v2 = page(
+            Struct::new([
+                (1, int(DATA_PAGE_V2)),
+                (2, int(5)),
+                (3, int(7)),
+                (
+                    8,
+                    Value::Struct(Struct::new([
+                        (1, int(4)),
+                        (2, int(1)),
+                        (3, int(4)),
+                        (4, int(RLE_DICTIONARY)),
+                        (5, int(2)),
+                        (6, int(0)),
+                    ])),
+                ),
+            ]),
+            &[&[3, 0b1101][..], &snappy(&[1, 3, 0b101])].concat(),
+        );
+        // "c", "dd", plainly encoded, after a run of two levels of 1
+        let v1 = page(
+            Struct::new([
+                (1, int(DATA_PAGE)),
+                (2, int(17)),
+                (3, int(19)),
+                (
+                    5,
+                    Value::Struct(Struct::new([
+                        (1, int(2)),
+                        (2, int(PLAIN)),
+                        (3, int(RLE)),
+                        (4, int(RLE)),
+                    ])),
+                ),
+            ]),
+            &snappy(b"\x02\0\0\0\x04\x01\x01\0\0\0c\x02\0\0\0dd"),
+        );
+        let data_offset = 4 + dictionary.len() as i64;
+        let chunk = Struct::new([
+            (1, int(BYTE_ARRAY)),
+            (3, Value::List(vec![Value::Binary(b"name".to_vec())])),
+            (4, int(SNAPPY)),
+            (5, Value::I64(6)),
+            (9, Value::I64(data_offset)),
+            (11, Value::I64(4)),
+        ]);
+        let metadata = Struct::new([
+            (1, int(1)),
+            (
+                2,
+                Value::List(vec![
+                    Value::Struct(Struct::new([
+                        (4, Value::Binary(b"schema".to_vec())),
+                        (5, int(1)),
+                    ])),
+                    Value::Struct(Struct::new([
+                        (1, int(BYTE_ARRAY)),
+                        (3, int(OPTIONAL)),
+                        (4, Value::Binary(b"name".to_vec())),
+                    ])),
+                ]),
+            ),
+            (3, Value::I64(6)),
+            (
+                4,
+                Value::List(vec![Value::Struct(Struct::new([
+                    (
+                        1,
+                        Value::List(vec![Value::Struct(Struct::new([(
+                            3,
+                            Value::Struct(chunk),
+                        )]))]),
+                    ),
+                    (3, Value::I64(6)),
+                ]))]),
+            ),
+        ]);
+        let mut file = [&MAGIC[..], &dictionary, &v2, &v1].concat();
+        let mut footer = Vec::new();
+        metadata.encode(&mut footer);
+        file.extend(&footer);
+        file.extend((footer.len() as u32).to_le_bytes());
+        file.extend(MAGIC);
+
+        let mut reader = ParquetReader::open(Cursor::new(file)).unwrap();
+        let mut read = Vec::new();
+        while let Some(value) = reader.value().unwrap() {
+            read.push(value.map(|value| String::from_utf8(value).unwrap()));
+        }
+        let expected = [
+            Some("bb"),
+            None,
+            Some("a"),
+            Some("bb"),
+            Some("c"),
+            Some("dd"),
+        ];
+        assert_eq!(read, expected.map(|value| value.map(str::to_string)));
+    }
+
+    /// Every value of the file `bytes`, as text.
+    fn read_all(bytes: &[u8]) -> Vec<Option<String>> {
+        let mut reader = ParquetReader::open(Cursor::new(bytes)).unwrap();
+        let mut read = Vec::new();
+        while let Some(value) = reader.value().unwrap() {
+            read.push(value.map(|value| String::from_utf8(value).unwrap()));
+        }
+        read
+    }
+
+    #[test]
+    fn test_fixtures() {
+        // Written by testdata/generate.py, which shares no code with the
+        // reader, in the layouts other writers use
+        let words = ["apple", "banana", "cherry", "damson", "elderberry"];
+        let expected: Vec<_> = (0..600)
+            .map(|i| (i % 7 != 3).then(|| words[i * i % 5].to_string()))
+            .collect();
+        assert_eq!(
+            read_all(include_bytes!("testdata/plain_dictionary.parquet")),
+            expected
+        );
+
+        let expected: Vec<_> = (0..450)
+            .map(|i| {
+                let null = (200..250).contains(&i) || i % 10 == 0;
+                let value = if i < 200 {
+                    f64::from(i % 4) * 0.5
+                } else {
+                    f64::from(i) / 8.0
+                };
+                (!null).then(|| value.to_string())
+            })
+            .collect();
+        assert_eq!(
+            read_all(include_bytes!("testdata/data_page_v2.parquet")),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_hybrid() {
+        // A run of five 3s, then a group of eight packed 2-bit values
+        let data = [5 << 1, 3, (1 << 1) | 1, 0b0001_0100, 0b0100_0100];
+        assert_eq!(
+            hybrid(&data, 2, 13).unwrap(),
+            [3, 3, 3, 3, 3, 0, 1, 1, 0, 0, 1, 0, 1]
+        );
+        // A run is cut short at the count
+        assert_eq!(hybrid(&data[..2], 2, 3).unwrap(), [3, 3, 3]);
+        assert!(hybrid(&data[..2], 2, 6).is_err());
+    }
+}
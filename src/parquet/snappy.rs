@@ -0,0 +1,107 @@
+//! Snappy decompression, for the pages most Parquet writers compress by
+//! default.
+//!
+//! A block is its uncompressed length as a varint, then elements: a tag
+//! byte whose low two bits say whether literal bytes follow or a copy of
+//! bytes already output, from an offset back, repeats them.
+
+use super::thrift::read_varint;
+use super::ParquetError;
+
+pub(crate) fn decompress(input: &[u8]) -> Result<Vec<u8>, ParquetError> {
+    let corrupt = || ParquetError::invalid("a Snappy block is corrupt");
+    let mut input = input;
+    let len = read_varint(&mut input).map_err(|_| corrupt())?;
+    let len = usize::try_from(len).map_err(|_| corrupt())?;
+    // Each byte of input makes at most 64 of output, so a larger length
+    // is corrupt rather than something to allocate
+    if len > input.len().saturating_mul(64) {
+        return Err(corrupt());
+    }
+    let mut out = Vec::with_capacity(len);
+    let mut at = 0;
+    // Take the next `n` bytes of input as a little-endian number
+    let take = |at: &mut usize, n: usize| -> Result<usize, ParquetError> {
+        let bytes = input.get(*at..*at + n).ok_or_else(corrupt)?;
+        *at += n;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| value << 8 | usize::from(byte)))
+    };
+    while at < input.len() {
+        let tag = input[at];
+        at += 1;
+        let (copy_len, offset) = match tag & 3 {
+            0 => {
+                let literal = match tag >> 2 {
+                    // Longer literals give their length in 1 to 4 bytes
+                    n @ 60.. => take(&mut at, usize::from(n) - 59)?,
+                    n => usize::from(n),
+                } + 1;
+                let bytes = input.get(at..at + literal).ok_or_else(corrupt)?;
+                out.extend_from_slice(bytes);
+                at += literal;
+                continue;
+            }
+            1 => {
+                let low = take(&mut at, 1)?;
+                (
+                    4 + usize::from(tag >> 2 & 7),
+                    usize::from(tag >> 5) << 8 | low,
+                )
+            }
+            2 => (usize::from(tag >> 2) + 1, take(&mut at, 2)?),
+            _ => (usize::from(tag >> 2) + 1, take(&mut at, 4)?),
+        };
+        if offset == 0 || offset > out.len() || out.len() + copy_len > len {
+            return Err(corrupt());
+        }
+        // The copy may overlap what it writes, repeating a short run
+        let start = out.len() - offset;
+        for i in 0..copy_len {
+            out.push(out[start + i]);
+        }
+    }
+    if out.len() != len {
+        return Err(corrupt());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompress() {
+        // "abcabcabcabcXYZ": 3 literals, a copy of 9 from 3 back, then 3
+        // more literals
+        let block = [
+            15,
+            2 << 2,
+            b'a',
+            b'b',
+            b'c',
+            1 | ((9 - 4) << 2),
+            3,
+            2 << 2,
+            b'X',
+            b'Y',
+            b'Z',
+        ];
+        assert_eq!(decompress(&block).unwrap(), b"abcabcabcabcXYZ");
+
+        // A literal of 61 bytes, its length in a byte after the tag, and a
+        // copy with a two-byte offset
+        let mut block = vec![66, 60 << 2, 60];
+        block.extend([b'x'; 61]);
+        block.extend([2 | (4 << 2), 61, 0]);
+        assert_eq!(decompress(&block).unwrap(), [b'x'; 66]);
+
+        // Copies from before the start, and lengths that don't add up
+        assert!(decompress(&[4, 1, 1]).is_err());
+        assert!(decompress(&[3, 0, b'a']).is_err());
+        assert!(decompress(&[2, 8, b'a', b'b']).is_err());
+    }
+}
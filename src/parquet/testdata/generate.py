@@ -0,0 +1,422 @@
+#!/usr/bin/env python3
+"""Writes the Parquet files the reader's tests read.
+
+The files are laid out as other writers lay theirs out, rather than as
+ferrodb writes its own, and this script shares no code with the reader:
+its Thrift encoding, run-length and bit-packed hybrid and Snappy
+compression are written here from the format's specifications.
+
+- plain_dictionary.parquet: two row groups of an `id` INT64 and an
+  optional UTF-8 `data` column, both dictionary encoded in version 1
+  pages compressed with Snappy, PLAIN_DICTIONARY as older writers give
+  it, with statistics and encoding stats in the metadata.
+- data_page_v2.parquet: an optional DOUBLE column in version 2 pages,
+  RLE_DICTIONARY encoded until the writer falls back to PLAIN, with a
+  page of only nulls and one whose values are stored uncompressed.
+
+Run it from this directory to write them again.
+"""
+
+import struct
+
+# Thrift compact protocol types
+BOOL_TRUE, BOOL_FALSE, BYTE, I16, I32, I64, BINARY, LIST, STRUCT = 1, 2, 3, 4, 5, 6, 8, 9, 12
+
+# Parquet enums
+INT64, DOUBLE, BYTE_ARRAY = 2, 5, 6
+REQUIRED, OPTIONAL = 0, 1
+PLAIN, PLAIN_DICTIONARY, RLE, RLE_DICTIONARY = 0, 2, 3, 8
+DATA_PAGE, DICTIONARY_PAGE, DATA_PAGE_V2 = 0, 2, 3
+SNAPPY = 1
+UTF8 = 0
+
+CREATED_BY = "ferrodb test fixture generator"
+
+
+def varint(n):
+    out = bytearray()
+    while n >= 0x80:
+        out.append(n & 0x7F | 0x80)
+        n >>= 7
+    out.append(n)
+    return bytes(out)
+
+
+def zigzag(n):
+    return (n << 1) ^ (n >> 63)
+
+
+# Thrift: a structure is a list of (field id, type, value), a list value
+# a (element type, values) pair.
+def thrift_value(kind, value):
+    if kind in (BYTE,):
+        return bytes([value & 0xFF])
+    if kind in (I16, I32, I64):
+        return varint(zigzag(value))
+    if kind == BINARY:
+        value = value.encode() if isinstance(value, str) else value
+        return varint(len(value)) + value
+    if kind == LIST:
+        element, values = value
+        if len(values) < 15:
+            out = bytes([len(values) << 4 | element])
+        else:
+            out = bytes([0xF0 | element]) + varint(len(values))
+        return out + b"".join(thrift_value(element, v) for v in values)
+    if kind == STRUCT:
+        return thrift_struct(value)
+    raise ValueError(kind)
+
+
+def thrift_struct(fields):
+    out = bytearray()
+    last = 0
+    for field, kind, value in fields:
+        if value is None:
+            continue
+        if kind == BOOL_TRUE:
+            kind, value = (BOOL_TRUE if value else BOOL_FALSE), None
+        delta = field - last
+        if 0 < delta < 16:
+            out.append(delta << 4 | kind)
+        else:
+            out.append(kind)
+            out += varint(zigzag(field))
+        last = field
+        if value is not None:
+            out += thrift_value(kind, value)
+    out.append(0)
+    return bytes(out)
+
+
+def hybrid(values, width):
+    """Values in the run-length and bit-packed hybrid: runs of eight or
+    more repeats as runs, the rest bit packed in groups of eight."""
+    out = bytearray()
+    packed = []
+
+    def flush():
+        if not packed:
+            return
+        groups = (len(packed) + 7) // 8
+        padded = packed + [0] * (groups * 8 - len(packed))
+        bits = 0
+        for i, value in enumerate(padded):
+            bits |= value << (i * width)
+        out.extend(varint(groups << 1 | 1))
+        out.extend(bits.to_bytes(groups * width, "little"))
+        packed.clear()
+
+    i = 0
+    while i < len(values):
+        run = 1
+        while i + run < len(values) and values[i + run] == values[i]:
+            run += 1
+        # Pad the packed values to a whole group from the run first
+        while run >= 8 and len(packed) % 8:
+            packed.append(values[i])
+            i += 1
+            run -= 1
+        if run >= 8:
+            flush()
+            out.extend(varint(run << 1))
+            out.extend(values[i].to_bytes((width + 7) // 8, "little"))
+        else:
+            packed.extend(values[i : i + run])
+        i += run
+    flush()
+    return bytes(out)
+
+
+def snappy(data):
+    """Snappy compression, copying back any match of four or more bytes."""
+    out = bytearray(varint(len(data)))
+
+    def literal(chunk):
+        while chunk:
+            piece, chunk = chunk[:65536], chunk[65536:]
+            n = len(piece) - 1
+            if n < 60:
+                out.append(n << 2)
+            elif n < 256:
+                out.extend([60 << 2, n])
+            else:
+                out.extend(bytes([61 << 2]) + n.to_bytes(2, "little"))
+            out.extend(piece)
+
+    def copy(offset, length):
+        while length > 0:
+            if 4 <= length <= 11 and offset < 2048:
+                out.extend([(offset >> 8) << 5 | (length - 4) << 2 | 1, offset & 0xFF])
+                return
+            # Leave at least four for a last short copy
+            n = 64 if length > 68 else (length - 4 if length > 64 else length)
+            out.extend(bytes([(n - 1) << 2 | 2]) + offset.to_bytes(2, "little"))
+            length -= n
+
+    seen = {}
+    start = i = 0
+    while i + 4 <= len(data):
+        key = data[i : i + 4]
+        match = seen.get(key)
+        seen[key] = i
+        if match is None or i - match > 65535:
+            i += 1
+            continue
+        length = 4
+        while i + length < len(data) and data[match + length] == data[i + length]:
+            length += 1
+        literal(data[start:i])
+        copy(i - match, length)
+        i += length
+        start = i
+    literal(data[start:])
+    return bytes(out)
+
+
+def plain(kind, values):
+    if kind == INT64:
+        return b"".join(struct.pack("<q", v) for v in values)
+    if kind == DOUBLE:
+        return b"".join(struct.pack("<d", v) for v in values)
+    return b"".join(struct.pack("<I", len(v)) + v for v in values)
+
+
+def statistics(kind, values, nulls):
+    if not values:
+        return [(3, I64, nulls)]
+    low, high = plain(kind, [min(values)]), plain(kind, [max(values)])
+    if kind == BYTE_ARRAY:
+        low, high = low[4:], high[4:]
+    return [(3, I64, nulls), (5, BINARY, high), (6, BINARY, low)]
+
+
+class Column:
+    """A column chunk being written: its pages and what its metadata
+    records of them."""
+
+    def __init__(self, name, kind, optional):
+        self.name, self.kind, self.optional = name, kind, optional
+        self.pages = []
+        self.uncompressed = 0
+        self.encodings = set()
+        self.stats = []
+        self.has_dictionary = False
+
+    def page(self, header, data, uncompressed, page_type, encoding):
+        header = thrift_struct(header)
+        self.pages.append((page_type, header + data))
+        self.uncompressed += len(header) + uncompressed
+        self.encodings.add(encoding)
+        self.stats.append((page_type, encoding))
+
+    def dictionary(self, values, encoding):
+        data = plain(self.kind, values)
+        compressed = snappy(data)
+        self.has_dictionary = True
+        self.page(
+            [
+                (1, I32, DICTIONARY_PAGE),
+                (2, I32, len(data)),
+                (3, I32, len(compressed)),
+                (7, STRUCT, [(1, I32, len(values)), (2, I32, encoding), (3, BOOL_TRUE, False)]),
+            ],
+            compressed,
+            len(data),
+            DICTIONARY_PAGE,
+            encoding,
+        )
+
+    def encode(self, values, encoding, dictionary):
+        present = [v for v in values if v is not None]
+        if encoding == PLAIN:
+            return plain(self.kind, present)
+        width = max(len(dictionary) - 1, 0).bit_length()
+        return bytes([width]) + hybrid([dictionary.index(v) for v in present], width)
+
+    def data_page_v1(self, values, encoding, dictionary=None):
+        present = [v for v in values if v is not None]
+        data = self.encode(values, encoding, dictionary)
+        if self.optional:
+            levels = hybrid([int(v is not None) for v in values], 1)
+            data = struct.pack("<I", len(levels)) + levels + data
+        compressed = snappy(data)
+        header = [
+            (1, I32, DATA_PAGE),
+            (2, I32, len(data)),
+            (3, I32, len(compressed)),
+            (
+                5,
+                STRUCT,
+                [
+                    (1, I32, len(values)),
+                    (2, I32, encoding),
+                    (3, I32, RLE),
+                    (4, I32, RLE),
+                    (5, STRUCT, statistics(self.kind, present, len(values) - len(present))),
+                ],
+            ),
+        ]
+        self.encodings.add(RLE)
+        self.page(header, compressed, len(data), DATA_PAGE, encoding)
+
+    def data_page_v2(self, values, encoding, dictionary=None, compress=True):
+        present = [v for v in values if v is not None]
+        data = self.encode(values, encoding, dictionary)
+        levels = hybrid([int(v is not None) for v in values], 1) if self.optional else b""
+        stored = snappy(data) if compress else data
+        header = [
+            (1, I32, DATA_PAGE_V2),
+            (2, I32, len(levels) + len(data)),
+            (3, I32, len(levels) + len(stored)),
+            (
+                8,
+                STRUCT,
+                [
+                    (1, I32, len(values)),
+                    (2, I32, len(values) - len(present)),
+                    (3, I32, len(values)),
+                    (4, I32, encoding),
+                    (5, I32, len(levels)),
+                    (6, I32, 0),
+                    (7, BOOL_TRUE, compress),
+                    (8, STRUCT, statistics(self.kind, present, len(values) - len(present))),
+                ],
+            ),
+        ]
+        self.encodings.add(RLE)
+        self.page(header, levels + stored, len(levels) + len(data), DATA_PAGE_V2, encoding)
+
+    def write(self, out, values):
+        """Write the pages at the end of `out`, returning the chunk's
+        metadata."""
+        start = len(out)
+        dictionary_offset = data_offset = None
+        for page_type, page in self.pages:
+            if page_type == DICTIONARY_PAGE:
+                dictionary_offset = len(out)
+            elif data_offset is None:
+                data_offset = len(out)
+            out += page
+        present = [v for v in values if v is not None]
+        counts = {}
+        for key in self.stats:
+            counts[key] = counts.get(key, 0) + 1
+        metadata = [
+            (1, I32, self.kind),
+            (2, LIST, (I32, sorted(self.encodings))),
+            (3, LIST, (BINARY, [self.name])),
+            (4, I32, SNAPPY),
+            (5, I64, len(values)),
+            (6, I64, self.uncompressed),
+            (7, I64, len(out) - start),
+            (9, I64, data_offset),
+            (11, I64, dictionary_offset),
+            (12, STRUCT, statistics(self.kind, present, len(values) - len(present))),
+            (
+                13,
+                LIST,
+                (
+                    STRUCT,
+                    [
+                        [(1, I32, page_type), (2, I32, encoding), (3, I32, count)]
+                        for (page_type, encoding), count in sorted(counts.items())
+                    ],
+                ),
+            ),
+        ]
+        return [(2, I64, start), (3, STRUCT, metadata)], self.uncompressed, len(out) - start
+
+
+def schema_element(column):
+    element = [
+        (1, I32, column.kind),
+        (3, I32, OPTIONAL if column.optional else REQUIRED),
+        (4, BINARY, column.name),
+    ]
+    if column.kind == BYTE_ARRAY:
+        element += [(6, I32, UTF8), (10, STRUCT, [(1, STRUCT, [])])]
+    return element
+
+
+def write_file(path, version, row_groups, key_values):
+    """Write `row_groups`, each a list of (column, values) pairs whose
+    pages are already built."""
+    out = bytearray(b"PAR1")
+    groups = []
+    for ordinal, columns in enumerate(row_groups):
+        start = len(out)
+        chunks, size = [], 0
+        for column, values in columns:
+            chunk, uncompressed, _ = column.write(out, values)
+            chunks.append(chunk)
+            size += uncompressed
+        rows = len(columns[0][1])
+        groups.append(
+            [
+                (1, LIST, (STRUCT, chunks)),
+                (2, I64, size),
+                (3, I64, rows),
+                (5, I64, start),
+                (6, I64, len(out) - start),
+                (7, I16, ordinal),
+            ]
+        )
+    first = [column for column, _ in row_groups[0]]
+    schema = [[(4, BINARY, "schema"), (5, I32, len(first))]]
+    schema += [schema_element(column) for column in first]
+    metadata = thrift_struct(
+        [
+            (1, I32, version),
+            (2, LIST, (STRUCT, schema)),
+            (3, I64, sum(len(columns[0][1]) for columns in row_groups)),
+            (4, LIST, (STRUCT, groups)),
+            (5, LIST, (STRUCT, [[(1, BINARY, k), (2, BINARY, v)] for k, v in key_values])),
+            (6, BINARY, CREATED_BY),
+            (7, LIST, (STRUCT, [[(1, STRUCT, [])] for _ in first])),
+        ]
+    )
+    out += metadata + struct.pack("<I", len(metadata)) + b"PAR1"
+    with open(path, "wb") as f:
+        f.write(out)
+
+
+WORDS = [b"apple", b"banana", b"cherry", b"damson", b"elderberry"]
+
+
+def plain_dictionary():
+    row_groups = []
+    for group in range(2):
+        rows = range(group * 300, group * 300 + 300)
+        ids = [i // 3 for i in rows]
+        data = [None if i % 7 == 3 else WORDS[i * i % 5] for i in rows]
+        id_column = Column("id", INT64, False)
+        data_column = Column("data", BYTE_ARRAY, True)
+        for column, values in ((id_column, ids), (data_column, data)):
+            dictionary = sorted({v for v in values if v is not None}, key=values.index)
+            column.dictionary(dictionary, PLAIN_DICTIONARY)
+            for page in range(0, 300, 128):
+                column.data_page_v1(values[page : page + 128], PLAIN_DICTIONARY, dictionary)
+        row_groups.append([(id_column, ids), (data_column, data)])
+    write_file("plain_dictionary.parquet", 1, row_groups, [("fixture", "plain_dictionary")])
+
+
+def data_page_v2():
+    values = [
+        None if 200 <= i < 250 or i % 10 == 0 else (i % 4 * 0.5 if i < 200 else i / 8)
+        for i in range(450)
+    ]
+    column = Column("value", DOUBLE, True)
+    dictionary = [0.5, 1.0, 1.5, 0.0]
+    column.dictionary(dictionary, PLAIN)
+    column.data_page_v2(values[0:100], RLE_DICTIONARY, dictionary)
+    column.data_page_v2(values[100:200], RLE_DICTIONARY, dictionary)
+    column.data_page_v2(values[200:250], RLE_DICTIONARY, dictionary)
+    column.data_page_v2(values[250:400], PLAIN)
+    column.data_page_v2(values[400:450], PLAIN, compress=False)
+    write_file("data_page_v2.parquet", 2, [[(column, values)]], [("fixture", "data_page_v2")])
+
+
+if __name__ == "__main__":
+    plain_dictionary()
+    data_page_v2()
@@ -0,0 +1,346 @@
+//! The Thrift compact protocol, in which Parquet files record their
+//! metadata: enough to write the structures ferrodb writes, and to read
+//! any structure into a tree whose fields are looked up by id, so that
+//! fields newer writers add are passed over.
+
+use super::ParquetError;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// How deeply structures may nest before a file is taken to be corrupt
+/// rather than read until the stack overflows.
+const MAX_DEPTH: usize = 32;
+
+// Type ids on the wire. A boolean field's value is its type id.
+const TRUE: u8 = 1;
+const FALSE: u8 = 2;
+const BYTE: u8 = 3;
+const I16: u8 = 4;
+const I32: u8 = 5;
+const I64: u8 = 6;
+const DOUBLE: u8 = 7;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const SET: u8 = 10;
+const MAP: u8 = 11;
+const STRUCT: u8 = 12;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Bool(bool),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Double(f64),
+    Binary(Vec<u8>),
+    /// A list or set
+    List(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Struct(Struct),
+}
+
+impl Value {
+    fn type_id(&self) -> u8 {
+        match self {
+            Value::Bool(true) => TRUE,
+            Value::Bool(false) => FALSE,
+            Value::I8(_) => BYTE,
+            Value::I16(_) => I16,
+            Value::I32(_) => I32,
+            Value::I64(_) => I64,
+            Value::Double(_) => DOUBLE,
+            Value::Binary(_) => BINARY,
+            Value::List(_) => LIST,
+            Value::Map(_) => MAP,
+            Value::Struct(_) => STRUCT,
+        }
+    }
+
+    fn int(&self) -> Option<i64> {
+        match *self {
+            Value::I8(n) => Some(n.into()),
+            Value::I16(n) => Some(n.into()),
+            Value::I32(n) => Some(n.into()),
+            Value::I64(n) => Some(n),
+            _ => None,
+        }
+    }
+}
+
+/// A structure's fields by id.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Struct(pub(crate) BTreeMap<i16, Value>);
+
+impl Struct {
+    pub(crate) fn new(fields: impl IntoIterator<Item = (i16, Value)>) -> Self {
+        Self(fields.into_iter().collect())
+    }
+
+    /// An integer field of any width.
+    pub(crate) fn int(&self, id: i16) -> Option<i64> {
+        self.0.get(&id)?.int()
+    }
+
+    pub(crate) fn bool(&self, id: i16) -> Option<bool> {
+        match self.0.get(&id)? {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn binary(&self, id: i16) -> Option<&[u8]> {
+        match self.0.get(&id)? {
+            Value::Binary(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn list(&self, id: i16) -> Option<&[Value]> {
+        match self.0.get(&id)? {
+            Value::List(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get(&self, id: i16) -> Option<&Struct> {
+        match self.0.get(&id)? {
+            Value::Struct(fields) => Some(fields),
+            _ => None,
+        }
+    }
+
+    /// A list of structures, as Parquet's metadata holds.
+    pub(crate) fn structs(&self, id: i16) -> Option<Vec<&Struct>> {
+        self.list(id)?
+            .iter()
+            .map(|value| match value {
+                Value::Struct(fields) => Some(fields),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        let mut last = 0;
+        for (&id, value) in &self.0 {
+            let delta = id.wrapping_sub(last);
+            if (1..=15).contains(&delta) {
+                out.push((delta as u8) << 4 | value.type_id());
+            } else {
+                out.push(value.type_id());
+                varint(out, zigzag(id.into()));
+            }
+            last = id;
+            // A boolean field's value is in its type
+            if !matches!(value, Value::Bool(_)) {
+                encode(value, out);
+            }
+        }
+        out.push(0);
+    }
+}
+
+fn encode(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Bool(value) => out.push(if *value { TRUE } else { FALSE }),
+        Value::I8(n) => out.push(*n as u8),
+        Value::I16(n) => varint(out, zigzag((*n).into())),
+        Value::I32(n) => varint(out, zigzag((*n).into())),
+        Value::I64(n) => varint(out, zigzag(*n)),
+        Value::Double(n) => out.extend(n.to_le_bytes()),
+        Value::Binary(bytes) => {
+            varint(out, bytes.len() as u64);
+            out.extend(bytes);
+        }
+        Value::List(values) => {
+            // An empty list's element type goes unread, so any will do
+            let element = values.first().map_or(STRUCT, |value| match value {
+                Value::Bool(_) => TRUE,
+                value => value.type_id(),
+            });
+            if values.len() < 15 {
+                out.push((values.len() as u8) << 4 | element);
+            } else {
+                out.push(0xf0 | element);
+                varint(out, values.len() as u64);
+            }
+            for value in values {
+                encode(value, out);
+            }
+        }
+        Value::Map(entries) => {
+            varint(out, entries.len() as u64);
+            if let Some((key, value)) = entries.first() {
+                out.push(key.type_id() << 4 | value.type_id());
+            }
+            for (key, value) in entries {
+                encode(key, out);
+                encode(value, out);
+            }
+        }
+        Value::Struct(fields) => fields.encode(out),
+    }
+}
+
+/// Read a structure from `input`.
+pub(crate) fn read_struct(input: &mut impl Read) -> Result<Struct, ParquetError> {
+    read_fields(input, 0)
+}
+
+fn read_fields(input: &mut impl Read, depth: usize) -> Result<Struct, ParquetError> {
+    if depth > MAX_DEPTH {
+        return Err(ParquetError::invalid("metadata nests too deeply"));
+    }
+    let mut fields = BTreeMap::new();
+    let mut last: i16 = 0;
+    loop {
+        let header = byte(input)?;
+        if header == 0 {
+            return Ok(Struct(fields));
+        }
+        let kind = header & 0x0f;
+        let id = match header >> 4 {
+            0 => i16::try_from(unzigzag(read_varint(input)?))
+                .map_err(|_| ParquetError::invalid("a field id is out of range"))?,
+            delta => last.wrapping_add(delta.into()),
+        };
+        last = id;
+        let value = match kind {
+            TRUE => Value::Bool(true),
+            FALSE => Value::Bool(false),
+            kind => read_value(input, kind, depth)?,
+        };
+        fields.insert(id, value);
+    }
+}
+
+fn read_value(input: &mut impl Read, kind: u8, depth: usize) -> Result<Value, ParquetError> {
+    Ok(match kind {
+        // Booleans in lists and maps are a byte each
+        TRUE | FALSE => Value::Bool(byte(input)? == TRUE),
+        BYTE => Value::I8(byte(input)? as i8),
+        I16 => Value::I16(unzigzag(read_varint(input)?) as i16),
+        I32 => Value::I32(unzigzag(read_varint(input)?) as i32),
+        I64 => Value::I64(unzigzag(read_varint(input)?)),
+        DOUBLE => {
+            let mut bytes = [0; 8];
+            input.read_exact(&mut bytes)?;
+            Value::Double(f64::from_le_bytes(bytes))
+        }
+        BINARY => {
+            let len = read_varint(input)?;
+            let mut bytes = Vec::new();
+            input.take(len).read_to_end(&mut bytes)?;
+            if bytes.len() as u64 != len {
+                return Err(ParquetError::invalid("metadata ends in a string"));
+            }
+            Value::Binary(bytes)
+        }
+        LIST | SET => {
+            let header = byte(input)?;
+            let len = match header >> 4 {
+                15 => read_varint(input)?,
+                len => len.into(),
+            };
+            let mut values = Vec::new();
+            for _ in 0..len {
+                values.push(read_value(input, header & 0x0f, depth + 1)?);
+            }
+            Value::List(values)
+        }
+        MAP => {
+            let len = read_varint(input)?;
+            let types = if len > 0 { byte(input)? } else { 0 };
+            let mut entries = Vec::new();
+            for _ in 0..len {
+                let key = read_value(input, types >> 4, depth + 1)?;
+                let value = read_value(input, types & 0x0f, depth + 1)?;
+                entries.push((key, value));
+            }
+            Value::Map(entries)
+        }
+        STRUCT => Value::Struct(read_fields(input, depth + 1)?),
+        kind => {
+            return Err(ParquetError::invalid(format!(
+                "metadata has a value of unknown type {}",
+                kind
+            )))
+        }
+    })
+}
+
+fn byte(input: &mut impl Read) -> Result<u8, ParquetError> {
+    let mut byte = [0];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// An unsigned LEB128 varint, as Thrift and Parquet's encodings use.
+pub(crate) fn read_varint(input: &mut impl Read) -> Result<u64, ParquetError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = byte(input)?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(ParquetError::invalid("a varint is too long"))
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thrift() {
+        let list: Vec<Value> = (0..20).map(Value::I32).collect();
+        let value = Struct::new([
+            (1, Value::I32(-3)),
+            (2, Value::Bool(true)),
+            (3, Value::Binary(b"name".to_vec())),
+            // Too far from the last to be written as a delta
+            (40, Value::I64(1 << 40)),
+            (41, Value::List(list.clone())),
+            (
+                42,
+                Value::Struct(Struct::new([(1, Value::Bool(false)), (5, Value::I16(7))])),
+            ),
+            (43, Value::List(vec![Value::Bool(true), Value::Bool(false)])),
+            (
+                44,
+                Value::Map(vec![(Value::Binary(b"k".to_vec()), Value::Double(0.5))]),
+            ),
+        ]);
+        let mut bytes = Vec::new();
+        value.encode(&mut bytes);
+        // Field 1, one after none, an i32 of -3 zigzagged to 5
+        assert_eq!(&bytes[..2], &[0x15, 0x05]);
+        let read = read_struct(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read, value);
+        assert_eq!(read.int(40), Some(1 << 40));
+        assert_eq!(read.get(42).unwrap().bool(1), Some(false));
+        assert_eq!(read.list(41).unwrap(), list.as_slice());
+
+        assert!(read_struct(&mut &bytes[..bytes.len() - 1]).is_err());
+        let deep = [0x1c; 64];
+        assert!(read_struct(&mut deep.as_slice()).is_err());
+    }
+}
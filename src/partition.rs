@@ -0,0 +1,511 @@
+//! Partitioned tables, as `CREATE TABLE ... PARTITION BY` makes them: a
+//! table whose rows are kept in partitions, each a table of its own, by
+//! the value of a key.
+//!
+//! A row's key is its data, or one column of it where rows are JSON
+//! objects, as those imported from SQLite or mapped with serde are. Rows
+//! go to a partition by a hash of their key, or by the range it falls in.
+//! Writes to the table go to the partition each row's key places it in,
+//! and reads go through the partitions in turn; a row's id is the one it
+//! has in its partition. Looking up the rows with a key reads only the
+//! partition it places them in.
+//!
+//! Each partition is stored as the table's options say, with its own
+//! Bloom filters if it asks for them. The partitions are listed in a
+//! catalog record of their own, read back each time the database opens.
+
+use crate::database::{Connection, Database, DatabaseError, Row, RowId, TableId};
+use crate::mapping::column_value;
+use crate::storage::crc32;
+use crate::table_options::TableOptions;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::cmp::Ordering;
+use std::io::{self, Cursor, Read};
+
+/// Marks a catalog record as a partitioned table.
+const PARTITIONS: u8 = 7;
+
+/// The most partitions a table may have.
+pub const MAX_PARTITIONS: u32 = 1024;
+
+/// How a partitioned table places its rows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partitioning {
+    /// The column whose value is a row's key, or `None` for its whole
+    /// data. A row without the column has an empty key.
+    pub column: Option<String>,
+    pub scheme: PartitionScheme,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionScheme {
+    /// Over this many partitions, by a hash of the key
+    Hash(u32),
+    /// Split at each of these keys, in ascending order, into one partition
+    /// more than there are of them. Each holds the keys from the bound
+    /// before it up to, but not including, its own.
+    Range(Vec<String>),
+}
+
+impl Partitioning {
+    fn count(&self) -> usize {
+        match &self.scheme {
+            PartitionScheme::Hash(count) => *count as usize,
+            PartitionScheme::Range(bounds) => bounds.len() + 1,
+        }
+    }
+
+    fn validate(&self) -> Result<(), DatabaseError> {
+        let invalid = |message: &str| Err(DatabaseError::InvalidPartitioning(message.to_string()));
+        if self.column.as_ref().is_some_and(String::is_empty) {
+            return invalid("a column needs a name");
+        }
+        if self.count() == 0 || self.count() > MAX_PARTITIONS as usize {
+            return invalid("a table has from 1 to 1024 partitions");
+        }
+        if let PartitionScheme::Range(bounds) = &self.scheme {
+            let ascending = bounds
+                .windows(2)
+                .all(|pair| compare(pair[0].as_bytes(), pair[1].as_bytes()) == Ordering::Less);
+            if !ascending {
+                return invalid("range bounds must ascend");
+            }
+        }
+        Ok(())
+    }
+
+    /// The key of a row of `data`.
+    fn key(&self, data: &[u8]) -> Vec<u8> {
+        match &self.column {
+            Some(column) => column_value(data, column).unwrap_or_default().into_bytes(),
+            None => data.to_vec(),
+        }
+    }
+
+    /// Which of the partitions holds the rows with `key`.
+    fn place(&self, key: &[u8]) -> usize {
+        match &self.scheme {
+            PartitionScheme::Hash(count) => (crc32(key) % count) as usize,
+            PartitionScheme::Range(bounds) => {
+                bounds.partition_point(|bound| compare(bound.as_bytes(), key) != Ordering::Greater)
+            }
+        }
+    }
+}
+
+/// The order of keys in ranges: numbers first, by value, then anything
+/// else byte by byte, so that `9` comes before `10` and dates in ISO
+/// form in time order.
+pub(crate) fn compare(a: &[u8], b: &[u8]) -> Ordering {
+    let number = |key: &[u8]| {
+        let number: f64 = std::str::from_utf8(key).ok()?.parse().ok()?;
+        number.is_finite().then_some(number)
+    };
+    match (number(a), number(b)) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.cmp(b),
+    }
+}
+
+/// A partitioned table's partitions, in the order its scheme numbers them.
+#[derive(Debug, Clone)]
+pub(crate) struct Partitions {
+    table: TableId,
+    partitioning: Partitioning,
+    tables: Vec<TableId>,
+}
+
+impl Partitions {
+    /// A partitioned table record: `PARTITIONS`, the table, the column's
+    /// length and name with `u16::MAX` for none, the scheme, the
+    /// partitions' count and tables, then for ranges each bound's length
+    /// and bytes.
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![PARTITIONS];
+        bytes.write_u32::<BigEndian>(self.table.0).unwrap();
+        match &self.partitioning.column {
+            Some(column) => {
+                bytes.write_u16::<BigEndian>(column.len() as u16).unwrap();
+                bytes.extend_from_slice(column.as_bytes());
+            }
+            None => bytes.write_u16::<BigEndian>(u16::MAX).unwrap(),
+        }
+        bytes.push(match self.partitioning.scheme {
+            PartitionScheme::Hash(_) => 0,
+            PartitionScheme::Range(_) => 1,
+        });
+        bytes
+            .write_u32::<BigEndian>(self.tables.len() as u32)
+            .unwrap();
+        for table in &self.tables {
+            bytes.write_u32::<BigEndian>(table.0).unwrap();
+        }
+        if let PartitionScheme::Range(bounds) = &self.partitioning.scheme {
+            for bound in bounds {
+                bytes.write_u32::<BigEndian>(bound.len() as u32).unwrap();
+                bytes.extend_from_slice(bound.as_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// The partitioned table a catalog record holds, or `None` if it holds
+    /// something else.
+    fn decode(bytes: &[u8]) -> Result<Option<Self>, DatabaseError> {
+        if bytes.first() != Some(&PARTITIONS) {
+            return Ok(None);
+        }
+        let decode = || -> io::Result<Self> {
+            let mut cursor = Cursor::new(&bytes[1..]);
+            let table = TableId(cursor.read_u32::<BigEndian>()?);
+            let column = match cursor.read_u16::<BigEndian>()? {
+                u16::MAX => None,
+                len => Some(read_string(&mut cursor, len as usize)?),
+            };
+            let hash = match cursor.read_u8()? {
+                0 => true,
+                1 => false,
+                _ => return Err(io::ErrorKind::InvalidData.into()),
+            };
+            let count = cursor.read_u32::<BigEndian>()?;
+            if count == 0 || count > MAX_PARTITIONS {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+            let tables = (0..count)
+                .map(|_| Ok(TableId(cursor.read_u32::<BigEndian>()?)))
+                .collect::<io::Result<_>>()?;
+            let scheme = if hash {
+                PartitionScheme::Hash(count)
+            } else {
+                let bounds = (1..count)
+                    .map(|_| {
+                        let len = cursor.read_u32::<BigEndian>()?;
+                        read_string(&mut cursor, len as usize)
+                    })
+                    .collect::<io::Result<_>>()?;
+                PartitionScheme::Range(bounds)
+            };
+            Ok(Self {
+                table,
+                partitioning: Partitioning { column, scheme },
+                tables,
+            })
+        };
+        decode()
+            .map(Some)
+            .map_err(|_| DatabaseError::CorruptedCatalog)
+    }
+}
+
+fn read_string(cursor: &mut Cursor<&[u8]>, len: usize) -> io::Result<String> {
+    let mut bytes = vec![0; len];
+    cursor.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| io::ErrorKind::InvalidData.into())
+}
+
+impl Database {
+    /// Create a new, empty table keeping its rows in partitions as
+    /// `partitioning` says, each stored as `options` say.
+    pub fn create_partitioned_table(
+        &self,
+        options: TableOptions,
+        partitioning: Partitioning,
+    ) -> Result<TableId, DatabaseError> {
+        partitioning.validate()?;
+        let table = self.create_table_with(options.clone())?;
+        let tables = (0..partitioning.count())
+            .map(|_| self.create_table_with(options.clone()))
+            .collect::<Result<_, _>>()?;
+        let partitions = Partitions {
+            table,
+            partitioning,
+            tables,
+        };
+        self.connect_system()
+            .insert(TableId::CATALOG, &partitions.encode())?;
+        self.add_partitions(partitions);
+        Ok(table)
+    }
+
+    fn add_partitions(&self, partitions: Partitions) {
+        let mut tables = self.partitions.write().unwrap();
+        tables.insert(partitions.table, partitions);
+    }
+
+    /// How `table` places its rows in partitions, if it does.
+    pub fn partitioning(&self, table: TableId) -> Option<Partitioning> {
+        let tables = self.partitions.read().unwrap();
+        Some(tables.get(&table)?.partitioning.clone())
+    }
+
+    /// The partitions of `table`, or `None` if it isn't partitioned.
+    pub fn partitions(&self, table: TableId) -> Option<Vec<TableId>> {
+        let tables = self.partitions.read().unwrap();
+        Some(tables.get(&table)?.tables.clone())
+    }
+
+    /// The partition of `table` a row of `data` goes in, or `None` if it
+    /// isn't partitioned.
+    pub(crate) fn partition_for(&self, table: TableId, data: &[u8]) -> Option<TableId> {
+        let tables = self.partitions.read().unwrap();
+        let partitions = tables.get(&table)?;
+        let key = partitions.partitioning.key(data);
+        Some(partitions.tables[partitions.partitioning.place(&key)])
+    }
+
+    /// The partitions of `table` that may hold rows whose `column`, or
+    /// whose data for `None`, is `value`: the one it places them in if
+    /// that's its key, or all of them. `None` if it isn't partitioned.
+    pub(crate) fn partitions_with(
+        &self,
+        table: TableId,
+        column: Option<&str>,
+        value: &[u8],
+    ) -> Option<Vec<TableId>> {
+        let tables = self.partitions.read().unwrap();
+        let partitions = tables.get(&table)?;
+        if partitions.partitioning.column.as_deref() != column {
+            return Some(partitions.tables.clone());
+        }
+        Some(vec![
+            partitions.tables[partitions.partitioning.place(value)],
+        ])
+    }
+
+    /// The table `table` is a partition of, or `table` itself if it's
+    /// none's.
+    pub(crate) fn parent_table(&self, table: TableId) -> TableId {
+        let tables = self.partitions.read().unwrap();
+        tables
+            .values()
+            .find(|partitions| partitions.tables.contains(&table))
+            .map_or(table, |partitions| partitions.table)
+    }
+
+    /// Fail if changing `row` to `data` would change its partition, which
+    /// would change its id.
+    pub(crate) fn check_partition(&self, row: RowId, data: &[u8]) -> Result<(), DatabaseError> {
+        let table = self.parent_table(row.table);
+        match self.partition_for(table, data) {
+            Some(partition) if partition != row.table => {
+                Err(DatabaseError::PartitionKeyChanged(row))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail if `table` is a partition: its rows are only written through
+    /// the table it partitions, which places them.
+    pub(crate) fn check_not_partition(&self, table: TableId) -> Result<(), DatabaseError> {
+        match self.parent_table(table) {
+            parent if parent != table => Err(DatabaseError::PartitionWrite(table)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every partitioned table in the catalog.
+    pub(crate) fn read_partitions(&self) -> Result<(), DatabaseError> {
+        for row in self.connect_system().scan(TableId::CATALOG)? {
+            if let Some(partitions) = Partitions::decode(&row.data)? {
+                self.add_partitions(partitions);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Connection<'_> {
+    /// The rows of `table` whose `column` is `value`, as `column_value`
+    /// reads it. A table partitioned by `column` only has the partition
+    /// `value` belongs in read.
+    pub fn find_by(
+        &mut self,
+        table: TableId,
+        column: &str,
+        value: &str,
+    ) -> Result<Vec<Row>, DatabaseError> {
+        let database = self.database();
+        let tables = database
+            .partitions_with(table, Some(column), value.as_bytes())
+            .unwrap_or_else(|| vec![table]);
+        let mut rows = Vec::new();
+        for table in tables {
+            self.scan_each(table, |row| {
+                if column_value(&row.data, column).as_deref() == Some(value) {
+                    rows.push(row);
+                }
+                Ok::<_, DatabaseError>(())
+            })?;
+        }
+        Ok(rows)
+    }
+
+    /// Add `rows` to the partitions of `table` as `insert_batch` does,
+    /// all or none of them, returning their ids in the order given.
+    pub(crate) fn insert_into_partitions<R: AsRef<[u8]>>(
+        &mut self,
+        partitions: Vec<TableId>,
+        table: TableId,
+        rows: &[R],
+    ) -> Result<Vec<RowId>, DatabaseError> {
+        let database = self.database();
+        // The rows each partition gets, by their place in `rows`
+        let mut batches = vec![Vec::new(); partitions.len()];
+        for (i, data) in rows.iter().enumerate() {
+            let partition = database.partition_for(table, data.as_ref()).unwrap();
+            let at = partitions.iter().position(|&p| p == partition).unwrap();
+            batches[at].push(i);
+        }
+        let own = !self.in_transaction();
+        if own {
+            self.begin()?;
+        }
+        let mut ids = vec![None; rows.len()];
+        let mut inserted = || -> Result<(), DatabaseError> {
+            for (&partition, batch) in partitions.iter().zip(&batches) {
+                if batch.is_empty() {
+                    continue;
+                }
+                let data: Vec<&[u8]> = batch.iter().map(|&i| rows[i].as_ref()).collect();
+                for (&i, id) in batch.iter().zip(self.insert_rows(partition, &data)?) {
+                    ids[i] = Some(id);
+                }
+            }
+            Ok(())
+        };
+        let result = inserted();
+        if own {
+            match &result {
+                Ok(()) => self.commit()?,
+                Err(_) => self.rollback()?,
+            }
+        }
+        result?;
+        Ok(ids.into_iter().map(Option::unwrap).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_config;
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_partitioning() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        config.storage.page_size = 512;
+        let order = |day: u32| format!(r#"{{"day": "2024-01-{day:02}", "n": {day}}}"#);
+        // The pages a lookup asked the buffer pool for
+        let pages_read = |database: &Database, sql: &str| {
+            let before = database.metrics();
+            let mut session = SqlSession::new(database.connect());
+            let rows = session.execute(sql).remove(0).unwrap().rows;
+            let after = database.metrics();
+            let read =
+                after.buffer_hits + after.buffer_misses - before.buffer_hits - before.buffer_misses;
+            (rows.len(), read)
+        };
+
+        let (by_range, by_hash) = {
+            let database = Database::with_config(&config).unwrap();
+            let mut session = SqlSession::new(database.connect());
+            let mut tables = Vec::new();
+            for sql in [
+                "CREATE TABLE PARTITION BY RANGE (day) SPLIT AT ('2024-01-10', '2024-01-20')",
+                "CREATE TABLE WITH (BLOOM_FILTER = TRUE) PARTITION BY HASH (data) PARTITIONS 4",
+            ] {
+                let result = session.execute(sql).remove(0).unwrap();
+                tables.push(TableId(
+                    result.rows[0][0].as_ref().unwrap().parse().unwrap(),
+                ));
+            }
+            let (by_range, by_hash) = (tables[0], tables[1]);
+            let partitions = database.partitions(by_range).unwrap();
+            assert_eq!(partitions.len(), 3);
+            assert!(
+                database
+                    .table_options(database.partitions(by_hash).unwrap()[0])
+                    .bloom_filter
+            );
+
+            let mut connection = database.connect();
+            let orders: Vec<String> = (1..=28).map(order).collect();
+            let ids = connection.insert_batch(by_range, &orders).unwrap();
+            // Each row is in the partition its day's range places it in
+            assert_eq!(ids[0].table, partitions[0]);
+            assert_eq!(ids[9].table, partitions[1]);
+            assert_eq!(ids[27].table, partitions[2]);
+            assert_eq!(connection.scan(partitions[1]).unwrap().len(), 10);
+            let scanned = connection.scan(by_range).unwrap();
+            assert_eq!(scanned.iter().map(|row| row.id).collect::<Vec<_>>(), ids);
+            let row = connection.insert(by_range, order(5).as_bytes()).unwrap();
+            assert_eq!(row.table, partitions[0]);
+            assert!(matches!(
+                connection.update(row, order(15).as_bytes()),
+                Err(DatabaseError::PartitionKeyChanged(_))
+            ));
+            connection.update(row, order(6).as_bytes()).unwrap();
+            assert_eq!(connection.count(by_range).unwrap(), 29);
+
+            let keys: Vec<String> = (0..100).map(|i| format!("key {i}")).collect();
+            connection.insert_batch(by_hash, &keys).unwrap();
+            database.checkpoint().unwrap();
+            (by_range, by_hash)
+        };
+
+        // The partitions stand once the database is opened again, and a
+        // lookup by key reads only the partition it's in
+        let database = Database::with_config(&config).unwrap();
+        assert_eq!(
+            database.partitioning(by_range),
+            Some(Partitioning {
+                column: Some("day".to_string()),
+                scheme: PartitionScheme::Range(vec![
+                    "2024-01-10".to_string(),
+                    "2024-01-20".to_string()
+                ]),
+            })
+        );
+        let day = format!("SELECT * FROM {} WHERE day = '2024-01-15'", by_range);
+        let n = format!("SELECT * FROM {} WHERE n = '15'", by_range);
+        let (found, pruned) = pages_read(&database, &day);
+        let (also_found, scanned) = pages_read(&database, &n);
+        assert_eq!((found, also_found), (1, 1));
+        assert!(pruned < scanned);
+        let key = format!("SELECT * FROM {} WHERE data = 'key 42'", by_hash);
+        assert_eq!(pages_read(&database, &key).0, 1);
+        let mut session = SqlSession::new(database.connect());
+        let result = session.execute(&format!("SELECT * FROM {}", by_hash));
+        assert_eq!(result[0].as_ref().unwrap().rows.len(), 100);
+
+        let invalid = session.execute("CREATE TABLE PARTITION BY RANGE (data) SPLIT AT ('b', 'a')");
+        assert_eq!(invalid[0].as_ref().unwrap_err().code(), "22023");
+
+        // A partition's rows are only written through its table, which
+        // places them where a lookup by key finds them
+        let partition = database.partitions(by_hash).unwrap()[1];
+        let row = session.execute(&key).remove(0).unwrap().rows.remove(0);
+        let row = row[0].as_ref().unwrap();
+        for sql in [
+            format!("INSERT INTO {} VALUES ('y')", partition),
+            format!("UPDATE {} SET data = 'y' WHERE id = '{}'", partition, row),
+            format!("DELETE FROM {} WHERE id = '{}'", partition, row),
+        ] {
+            assert_eq!(
+                session.execute(&sql)[0].as_ref().unwrap_err().code(),
+                "42809"
+            );
+        }
+        let mut connection = database.connect();
+        assert!(matches!(
+            connection.insert_batch(partition, &["y"]),
+            Err(DatabaseError::PartitionWrite(_))
+        ));
+        let result = session.execute(&format!("DELETE FROM {} WHERE id = '{}'", by_hash, row));
+        assert_eq!(result[0].as_ref().unwrap().tag, "DELETE 1");
+    }
+}
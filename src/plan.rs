@@ -0,0 +1,1060 @@
+//! Plans for reading a table, as `EXPLAIN` shows them: how its rows are
+//! read, and whether they are then sorted for an `ORDER BY`.
+//!
+//! Each way of reading rows delivers them in an order of its own, and a
+//! sort that would leave them in it is skipped. A table's pages are read
+//! in turn, so its rows come in id order, which is reversed if the
+//! reverse is asked for, and rows found by their data or a column all
+//! share its value. A partitioned table's rows come a partition at a
+//! time, so they're sorted. Likewise a join merges its tables' rows if
+//! both come in the order of their keys, rather than hashing them.
+//!
+//! Each step is estimated to give so many rows at so much cost, counted
+//! as Postgres counts it: a page read costs 1, and handling a row a
+//! hundredth of that. A table's rows are taken to be as many as have been
+//! counted, or as its statistics say, or else as fill its pages with rows
+//! of `DEFAULT_ROW_SIZE` bytes, and an equality to match the share of them
+//! its statistics say. Pages only reach a table's file when written out,
+//! so one with none there and no statistics is taken to have
+//! `DEFAULT_PAGES`. `to_json` gives a plan with its estimates as a
+//! tree, in the form Postgres's `EXPLAIN (FORMAT JSON)` does.
+
+use crate::database::{Database, DatabaseError, Row, TableId};
+use crate::encoding::json_string;
+use crate::index::Access;
+use crate::logging::log;
+use crate::mapping::column_value;
+use crate::partition::compare;
+use crate::spill::{self, TempFile};
+use crate::statistics::{Predicate, DEFAULT_EQUALITY_SELECTIVITY};
+use crate::syntax::{Key, Order};
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+/// The most files a hash join spills each table's rows to.
+const MAX_SPILL_FILES: u64 = 64;
+
+/// The cost of reading a page, and of handling a row or comparing two
+/// in a sort, as Postgres's `seq_page_cost`, `cpu_tuple_cost` and twice
+/// its `cpu_operator_cost`.
+const PAGE_COST: f64 = 1.0;
+const ROW_COST: f64 = 0.01;
+const COMPARE_COST: f64 = 0.005;
+
+/// The pages a table is taken to have when nothing says how many, as
+/// Postgres takes one never vacuumed to, and the bytes a row is taken to
+/// take in a table whose rows are unknown.
+const DEFAULT_PAGES: u64 = 10;
+const DEFAULT_ROW_SIZE: u64 = 100;
+
+/// The share of rows a semi-join is taken to keep, or an anti-join.
+const SEMI_JOIN_SELECTIVITY: f64 = 0.5;
+
+/// What a step of a plan is expected to take: the rows it gives, and the
+/// cost of them all, with that of the steps before it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Estimate {
+    pub(crate) rows: f64,
+    pub(crate) cost: f64,
+}
+
+/// How a table's rows are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Read {
+    /// The one row with an id
+    Row,
+    Scan,
+    /// Only the pages whose Bloom filters may hold the rows' data
+    BloomFilter,
+}
+
+impl From<Access> for Read {
+    fn from(access: Access) -> Self {
+        match access {
+            Access::Scan => Read::Scan,
+            Access::BloomFilter => Read::BloomFilter,
+        }
+    }
+}
+
+/// How the rows read are put in the order asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Sort {
+    /// They're read in it
+    Elided,
+    /// They're read in its reverse
+    Reversed,
+    Sorted,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Plan {
+    pub(crate) table: TableId,
+    pub(crate) read: Read,
+    /// What the rows read must be equal to a value in
+    pub(crate) filter: Option<Key>,
+    pub(crate) order: Option<(Order, Sort)>,
+    /// What reading the rows takes, before any sort
+    pub(crate) estimate: Estimate,
+}
+
+impl Plan {
+    pub(crate) fn new(
+        database: &Database,
+        table: TableId,
+        read: Read,
+        filter: Option<Key>,
+        order: Option<Order>,
+    ) -> Self {
+        let in_id_order = database.partitions(table).is_none();
+        let order = order.map(|order| {
+            // One row, or rows alike in the key
+            let sort = if read == Read::Row || filter.as_ref() == Some(&order.key) {
+                Sort::Elided
+            } else if order.key == Key::Id && in_id_order {
+                if order.descending {
+                    Sort::Reversed
+                } else {
+                    Sort::Elided
+                }
+            } else {
+                Sort::Sorted
+            };
+            (order, sort)
+        });
+        let estimate = read_estimate(database, table, read, filter.as_ref(), None);
+        Self {
+            table,
+            read,
+            filter,
+            order,
+            estimate,
+        }
+    }
+
+    /// The plan, its rows estimated to match the filter's `value` as the
+    /// table's statistics say rather than by a fixed share.
+    pub(crate) fn filtering(mut self, database: &Database, value: &str) -> Self {
+        let filter = self.filter.as_ref();
+        self.estimate = read_estimate(database, self.table, self.read, filter, Some(value));
+        self
+    }
+
+    /// What the whole plan takes, with the sort if there is one.
+    pub(crate) fn total(&self) -> Estimate {
+        match self.order {
+            Some((_, Sort::Sorted)) => {
+                let Estimate { rows, cost } = self.estimate;
+                let compares = rows * rows.max(2.0).log2();
+                Estimate {
+                    rows,
+                    cost: cost + compares * COMPARE_COST,
+                }
+            }
+            _ => self.estimate,
+        }
+    }
+
+    /// Put `rows`, read as the plan says, in its order. Keys are ordered as
+    /// range partitions' are, and rows without the column come last, or
+    /// first when descending.
+    /// Sorts too big for `storage.spill.work_mem` spill to temporary
+    /// files.
+    pub(crate) fn order(
+        &self,
+        database: &Database,
+        mut rows: Vec<Row>,
+    ) -> Result<Vec<Row>, DatabaseError> {
+        let Some((order, sort)) = &self.order else {
+            return Ok(rows);
+        };
+        match sort {
+            Sort::Elided => Ok(rows),
+            Sort::Reversed => {
+                rows.reverse();
+                Ok(rows)
+            }
+            Sort::Sorted => {
+                let keyed: Vec<_> = rows
+                    .into_iter()
+                    .map(|row| (key_text(&order.key, &row), row))
+                    .collect();
+                let sorted =
+                    spill::sort(database.temp_space(), keyed, |(a_key, a), (b_key, b)| {
+                        let ordering = match (a_key, b_key) {
+                            _ if order.key == Key::Id => a.id.cmp(&b.id),
+                            (Some(a), Some(b)) => compare(a, b),
+                            (Some(_), None) => Ordering::Less,
+                            (None, Some(_)) => Ordering::Greater,
+                            (None, None) => Ordering::Equal,
+                        };
+                        if order.descending {
+                            ordering.reverse()
+                        } else {
+                            ordering
+                        }
+                    })?;
+                Ok(sorted.into_iter().map(|(_, row)| row).collect())
+            }
+        }
+    }
+
+    /// The plan's steps as `EXPLAIN` lists them, each after the one it
+    /// takes its rows from.
+    pub(crate) fn lines(&self) -> Vec<String> {
+        let mut read = match self.read {
+            Read::Row => format!("Row lookup on table {}", self.table),
+            Read::Scan => format!("Scan of table {}", self.table),
+            Read::BloomFilter => format!("Bloom filter lookup on table {}", self.table),
+        };
+        if let Some(filter) = &self.filter {
+            read.push_str(&format!(" (filter: {} = <value>)", key_name(filter)));
+        }
+        let Some((order, sort)) = &self.order else {
+            return vec![read];
+        };
+        let direction = if order.descending { " DESC" } else { "" };
+        match sort {
+            Sort::Elided => vec![format!("{} (sort elided)", read)],
+            Sort::Reversed => vec![format!("{} backwards (sort elided)", read)],
+            Sort::Sorted => vec![
+                format!("Sort by {}{}", key_name(&order.key), direction),
+                format!("  -> {}", read),
+            ],
+        }
+    }
+
+    /// The plan as a tree in JSON, with its estimates.
+    pub(crate) fn to_json(&self) -> String {
+        self.node().to_json()
+    }
+
+    fn node(&self) -> Node {
+        let node_type = match self.read {
+            Read::Row => "Row Lookup",
+            Read::Scan => "Seq Scan",
+            Read::BloomFilter => "Bloom Filter Scan",
+        };
+        let mut read =
+            Node::new(node_type, self.estimate, 0.0).number("Table", self.table.0 as f64);
+        if let Some(filter) = &self.filter {
+            read = read.text("Filter Key", key_name(filter));
+        }
+        let Some((order, sort)) = &self.order else {
+            return read;
+        };
+        let direction = if order.descending { " DESC" } else { "" };
+        match sort {
+            Sort::Elided => read.flag("Sort Elided", true),
+            Sort::Reversed => read
+                .text("Scan Direction", "Backward")
+                .flag("Sort Elided", true),
+            Sort::Sorted => {
+                let total = self.total();
+                let key = format!("{}{}", key_name(&order.key), direction);
+                // Sorting gives no row until it has read them all
+                Node::new("Sort", total, total.cost)
+                    .raw("Sort Key", format!("[{}]", json_string(&key)))
+                    .plan(read)
+            }
+        }
+    }
+}
+
+/// The rows and cost of reading `table` as `read` says, keeping those
+/// equal to `value` in `filter`, or to one unknown for `None`.
+fn read_estimate(
+    database: &Database,
+    table: TableId,
+    read: Read,
+    filter: Option<&Key>,
+    value: Option<&str>,
+) -> Estimate {
+    if read == Read::Row {
+        return Estimate {
+            rows: 1.0,
+            cost: PAGE_COST + ROW_COST,
+        };
+    }
+    let (pages, rows) = match database.table_size(table) {
+        (0, None) => (DEFAULT_PAGES, None),
+        size => size,
+    };
+    let page_size = database.pages().page_size() as u64;
+    let rows = rows.unwrap_or(pages * page_size / DEFAULT_ROW_SIZE) as f64;
+    let selectivity = match (filter, value) {
+        (None, _) => 1.0,
+        (Some(Key::Id), _) => 1.0 / rows.max(1.0),
+        (Some(_), None) => DEFAULT_EQUALITY_SELECTIVITY,
+        (Some(Key::Data), Some(value)) => {
+            database.selectivity(table, None, Predicate::Equal(value))
+        }
+        (Some(Key::Column(column)), Some(value)) => {
+            database.selectivity(table, Some(column), Predicate::Equal(value))
+        }
+    };
+    let matching = rows * selectivity;
+    let (pages_read, rows_read) = match read {
+        // At most a page for each row found, each read whole
+        Read::BloomFilter => {
+            let pages_read = matching.ceil().clamp(1.0, pages.max(1) as f64);
+            (pages_read, rows * pages_read / pages.max(1) as f64)
+        }
+        _ => (pages as f64, rows),
+    };
+    Estimate {
+        rows: matching,
+        cost: pages_read * PAGE_COST + rows_read * ROW_COST,
+    }
+}
+
+/// A step of a plan as `to_json` writes it: its properties, in order,
+/// each already JSON, and the steps it takes its rows from.
+struct Node {
+    properties: Vec<(&'static str, String)>,
+    plans: Vec<Node>,
+}
+
+impl Node {
+    fn new(node_type: &str, estimate: Estimate, startup: f64) -> Self {
+        Self {
+            properties: vec![
+                ("Node Type", json_string(node_type)),
+                ("Startup Cost", format!("{:.2}", startup)),
+                ("Total Cost", format!("{:.2}", estimate.cost)),
+                ("Plan Rows", format!("{:.0}", estimate.rows.ceil())),
+            ],
+            plans: Vec::new(),
+        }
+    }
+
+    fn raw(mut self, name: &'static str, json: String) -> Self {
+        self.properties.push((name, json));
+        self
+    }
+
+    fn text(self, name: &'static str, value: &str) -> Self {
+        self.raw(name, json_string(value))
+    }
+
+    fn number(self, name: &'static str, value: f64) -> Self {
+        self.raw(name, value.to_string())
+    }
+
+    fn flag(self, name: &'static str, value: bool) -> Self {
+        self.raw(name, value.to_string())
+    }
+
+    fn plan(mut self, node: Node) -> Self {
+        self.plans.push(node);
+        self
+    }
+
+    /// The tree under this node, as one plan in a list, as Postgres gives
+    /// the plan of each statement explained.
+    fn to_json(&self) -> String {
+        let mut out = String::from("[\n  {\n    \"Plan\": ");
+        self.write(&mut out, 2);
+        out.push_str("\n  }\n]");
+        out
+    }
+
+    fn write(&self, out: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth + 1);
+        out.push_str("{\n");
+        for (n, (name, value)) in self.properties.iter().enumerate() {
+            if n > 0 {
+                out.push_str(",\n");
+            }
+            out.push_str(&format!("{}{}: {}", indent, json_string(name), value));
+        }
+        if !self.plans.is_empty() {
+            out.push_str(&format!(",\n{}\"Plans\": [", indent));
+            for (n, plan) in self.plans.iter().enumerate() {
+                out.push_str(if n > 0 { ", " } else { "" });
+                plan.write(out, depth + 1);
+            }
+            out.push(']');
+        }
+        out.push_str(&format!("\n{}}}", "  ".repeat(depth)));
+    }
+}
+
+/// How a join finds the rows of its tables whose keys are equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum JoinMethod {
+    /// Build a hash table of the right table's rows by key, and look up
+    /// each of the left's in it
+    Hash,
+    /// Walk the two tables' rows side by side, both read in key order,
+    /// without holding either by key
+    Merge,
+}
+
+/// A join of two tables' rows on their keys being equal.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Join {
+    pub(crate) method: JoinMethod,
+    pub(crate) left: Plan,
+    pub(crate) right: Plan,
+    pub(crate) left_key: Key,
+    pub(crate) right_key: Key,
+}
+
+impl Join {
+    /// A join of the rows of `left` and `right` whose keys are equal,
+    /// merged if `merge` allows it and both tables are read in the order
+    /// of their keys, which for now is when both are `id`, or else hashed.
+    pub(crate) fn new(
+        database: &Database,
+        left: TableId,
+        left_key: Key,
+        right: TableId,
+        right_key: Key,
+        merge: bool,
+    ) -> Self {
+        let ordered = |table, key: &Key| {
+            let order = Order {
+                key: key.clone(),
+                descending: false,
+            };
+            Plan::new(database, table, Read::Scan, None, Some(order))
+        };
+        let in_order = |plan: &Plan| matches!(plan.order, Some((_, Sort::Elided)));
+        let (left_plan, right_plan) = (ordered(left, &left_key), ordered(right, &right_key));
+        if merge && in_order(&left_plan) && in_order(&right_plan) {
+            return Self {
+                method: JoinMethod::Merge,
+                left: left_plan,
+                right: right_plan,
+                left_key,
+                right_key,
+            };
+        }
+        Self {
+            method: JoinMethod::Hash,
+            left: Plan::new(database, left, Read::Scan, None, None),
+            right: Plan::new(database, right, Read::Scan, None, None),
+            left_key,
+            right_key,
+        }
+    }
+
+    /// The pairs of `left` and `right`, read as the plan says, whose keys
+    /// are equal, with those of the left's first rows first. A hash join
+    /// whose right rows are too many for `storage.spill.work_mem` writes
+    /// both tables' rows out to temporary files by their keys' hashes,
+    /// and joins the rows of each file in turn, in whatever order that
+    /// leaves them.
+    pub(crate) fn join(
+        &self,
+        database: &Database,
+        left: Vec<Row>,
+        right: Vec<Row>,
+    ) -> Result<Vec<(Row, Row)>, DatabaseError> {
+        let keyed = |rows: Vec<Row>, key: &Key| -> Vec<(Vec<u8>, Row)> {
+            let rows = rows.into_iter();
+            rows.filter_map(|row| Some((key_text(key, &row)?, row)))
+                .collect()
+        };
+        let left = keyed(left, &self.left_key);
+        let right = keyed(right, &self.right_key);
+        let mut joined = Vec::new();
+        match self.method {
+            JoinMethod::Hash => {
+                let held: u64 = right
+                    .iter()
+                    .map(|(key, row)| spill::size(Some(key), row))
+                    .sum();
+                let Some(space) = database
+                    .temp_space()
+                    .filter(|space| held > space.work_mem())
+                else {
+                    hash_join(&left, &right, &mut joined);
+                    return Ok(joined);
+                };
+                // Enough files that each one's right rows fit, if their
+                // keys are spread evenly
+                let files = (held / space.work_mem() + 1).min(MAX_SPILL_FILES) as usize;
+                let spill = |rows: Vec<(Vec<u8>, Row)>| -> Result<Vec<TempFile>, DatabaseError> {
+                    let mut spilled = (0..files)
+                        .map(|_| space.create())
+                        .collect::<Result<Vec<_>, _>>()?;
+                    for (key, row) in rows {
+                        let mut hasher = DefaultHasher::new();
+                        key.hash(&mut hasher);
+                        let file = (hasher.finish() % files as u64) as usize;
+                        spilled[file].write(Some(&key), &row)?;
+                    }
+                    Ok(spilled)
+                };
+                let (mut left, mut right) = (spill(left)?, spill(right)?);
+                log!(Debug, "hash join spilled", files = files);
+                let unspill = |file: &mut TempFile| -> Result<Vec<(Vec<u8>, Row)>, DatabaseError> {
+                    file.read()?
+                        .map(|row| row.map(|(key, row)| (key.unwrap_or_default(), row)))
+                        .collect()
+                };
+                for (left, right) in left.iter_mut().zip(&mut right) {
+                    hash_join(&unspill(left)?, &unspill(right)?, &mut joined);
+                }
+            }
+            JoinMethod::Merge => {
+                let ids = self.left_key == Key::Id && self.right_key == Key::Id;
+                let cmp = |(a_key, a): &(Vec<u8>, Row), (b_key, b): &(Vec<u8>, Row)| {
+                    if ids {
+                        a.id.cmp(&b.id)
+                    } else {
+                        compare(a_key, b_key)
+                    }
+                };
+                let (mut i, mut j) = (0, 0);
+                while i < left.len() && j < right.len() {
+                    match cmp(&left[i], &right[j]) {
+                        Ordering::Less => i += 1,
+                        Ordering::Greater => j += 1,
+                        Ordering::Equal => {
+                            // Every pair from the runs of rows with the key
+                            let run = |rows: &[(Vec<u8>, Row)], from: usize, first| {
+                                let more =
+                                    rows[from..].iter().position(|row| cmp(row, first).is_ne());
+                                from + more.unwrap_or(rows.len() - from)
+                            };
+                            let (left_end, right_end) =
+                                (run(&left, i, &right[j]), run(&right, j, &left[i]));
+                            for (_, row) in &left[i..left_end] {
+                                for (_, other) in &right[j..right_end] {
+                                    joined.push((row.clone(), other.clone()));
+                                }
+                            }
+                            (i, j) = (left_end, right_end);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(joined)
+    }
+
+    /// The join as `EXPLAIN` lists it, followed by the plans of its
+    /// tables.
+    pub(crate) fn lines(&self) -> Vec<String> {
+        let method = match self.method {
+            JoinMethod::Hash => "Hash join",
+            JoinMethod::Merge => "Merge join",
+        };
+        let on = format!(
+            "{} = {}",
+            key_name(&self.left_key),
+            key_name(&self.right_key)
+        );
+        let mut lines = vec![format!("{} on {}", method, on)];
+        lines.extend(inputs(&self.left, &self.right));
+        lines
+    }
+
+    /// What the join takes: each row of the larger table is taken to
+    /// match one of the other's. A hash join first builds its table of
+    /// the right's rows.
+    pub(crate) fn estimate(&self) -> (Estimate, f64) {
+        let (left, right) = (self.left.total(), self.right.total());
+        let handled = (left.rows + right.rows) * ROW_COST;
+        let startup = match self.method {
+            JoinMethod::Hash => right.cost + right.rows * ROW_COST,
+            JoinMethod::Merge => 0.0,
+        };
+        let estimate = Estimate {
+            rows: left.rows.max(right.rows),
+            cost: left.cost + right.cost + handled,
+        };
+        (estimate, startup)
+    }
+
+    /// The join as a tree in JSON, with its estimates.
+    pub(crate) fn to_json(&self) -> String {
+        let node_type = match self.method {
+            JoinMethod::Hash => "Hash Join",
+            JoinMethod::Merge => "Merge Join",
+        };
+        let (estimate, startup) = self.estimate();
+        let on = format!(
+            "{} = {}",
+            key_name(&self.left_key),
+            key_name(&self.right_key)
+        );
+        Node::new(node_type, estimate, startup)
+            .text("Join Type", "Inner")
+            .text("Join Cond", &on)
+            .plan(self.left.node())
+            .plan(self.right.node())
+            .to_json()
+    }
+}
+
+/// Add the pairs of `left` and `right` whose keys are equal to `joined`,
+/// looking the left's keys up in a hash table of the right's.
+fn hash_join(left: &[(Vec<u8>, Row)], right: &[(Vec<u8>, Row)], joined: &mut Vec<(Row, Row)>) {
+    let mut built: HashMap<&[u8], Vec<&Row>> = HashMap::new();
+    for (key, row) in right {
+        built.entry(key).or_default().push(row);
+    }
+    for (key, row) in left {
+        for &other in built.get(key.as_slice()).into_iter().flatten() {
+            joined.push((row.clone(), other.clone()));
+        }
+    }
+}
+
+/// A semi-join, keeping the rows of the left table whose keys are those of
+/// some row of the right, or for an anti-join, of none. Only the right
+/// table's keys are held, in a hash table, each once.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SemiJoin {
+    pub(crate) left: Plan,
+    pub(crate) right: Plan,
+    pub(crate) left_key: Key,
+    pub(crate) right_key: Key,
+    pub(crate) anti: bool,
+    /// Whether for `EXISTS` rather than `IN`
+    pub(crate) exists: bool,
+}
+
+impl SemiJoin {
+    pub(crate) fn new(
+        database: &Database,
+        left: TableId,
+        left_key: Key,
+        right: TableId,
+        right_key: Key,
+        anti: bool,
+        exists: bool,
+    ) -> Self {
+        Self {
+            left: Plan::new(database, left, Read::Scan, None, None),
+            right: Plan::new(database, right, Read::Scan, None, None),
+            left_key,
+            right_key,
+            anti,
+            exists,
+        }
+    }
+
+    /// Those of `left` to keep, by the keys of `right`, in their order.
+    /// As in SQL, where a row without a key has a null one, only a
+    /// `NOT EXISTS` keeps rows without keys, and a `NOT IN` keeps none at
+    /// all if `right` has such a row.
+    pub(crate) fn filter(&self, left: Vec<Row>, right: Vec<Row>) -> Vec<Row> {
+        let mut keys = HashSet::new();
+        let mut null = false;
+        for row in &right {
+            match key_text(&self.right_key, row) {
+                Some(key) => {
+                    keys.insert(key);
+                }
+                None => null = true,
+            }
+        }
+        if self.anti && !self.exists && null {
+            return Vec::new();
+        }
+        left.into_iter()
+            .filter(|row| match key_text(&self.left_key, row) {
+                Some(key) => keys.contains(&key) != self.anti,
+                None => self.anti && self.exists,
+            })
+            .collect()
+    }
+
+    /// The semi-join as `EXPLAIN` lists it, followed by the plans of its
+    /// tables.
+    pub(crate) fn lines(&self) -> Vec<String> {
+        let kind = if self.anti { "anti" } else { "semi" };
+        let on = format!(
+            "{} = {}",
+            key_name(&self.left_key),
+            key_name(&self.right_key)
+        );
+        let mut lines = vec![format!("Hash {} join on {}", kind, on)];
+        lines.extend(inputs(&self.left, &self.right));
+        lines
+    }
+
+    /// What the semi-join takes, keeping a fixed share of the left's rows
+    /// after building its table of the right's keys.
+    pub(crate) fn estimate(&self) -> (Estimate, f64) {
+        let (left, right) = (self.left.total(), self.right.total());
+        let startup = right.cost + right.rows * ROW_COST;
+        let estimate = Estimate {
+            rows: left.rows * SEMI_JOIN_SELECTIVITY,
+            cost: startup + left.cost + left.rows * ROW_COST,
+        };
+        (estimate, startup)
+    }
+
+    /// The semi-join as a tree in JSON, with its estimates.
+    pub(crate) fn to_json(&self) -> String {
+        let (estimate, startup) = self.estimate();
+        let on = format!(
+            "{} = {}",
+            key_name(&self.left_key),
+            key_name(&self.right_key)
+        );
+        Node::new("Hash Join", estimate, startup)
+            .text("Join Type", if self.anti { "Anti" } else { "Semi" })
+            .text("Join Cond", &on)
+            .text("Subquery", if self.exists { "EXISTS" } else { "IN" })
+            .plan(self.left.node())
+            .plan(self.right.node())
+            .to_json()
+    }
+}
+
+/// The lines of the plans of a join's tables, under its own.
+fn inputs(left: &Plan, right: &Plan) -> Vec<String> {
+    let mut lines = Vec::new();
+    for plan in [left, right] {
+        for (n, line) in plan.lines().into_iter().enumerate() {
+            let arrow = if n == 0 { "  -> " } else { "     " };
+            lines.push(format!("{}{}", arrow, line));
+        }
+    }
+    lines
+}
+
+/// A row's value for `key` as text, if it has one.
+fn key_text(key: &Key, row: &Row) -> Option<Vec<u8>> {
+    match key {
+        Key::Id => Some(row.id.to_string().into_bytes()),
+        Key::Data => Some(row.data.clone()),
+        Key::Column(column) => column_value(&row.data, column).map(String::into_bytes),
+    }
+}
+
+fn key_name(key: &Key) -> &str {
+    match key {
+        Key::Id => "id",
+        Key::Data => "data",
+        Key::Column(column) => column,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::temp_database;
+    use crate::database::RowId;
+    use crate::partition::{PartitionScheme, Partitioning};
+    use crate::sql::SqlSession;
+    use crate::table_options::TableOptions;
+
+    #[test]
+    fn test_plan() {
+        let (_dir, database) = temp_database();
+        let table = database.create_table().unwrap();
+        let partitioned = database
+            .create_partitioned_table(
+                TableOptions::default(),
+                Partitioning {
+                    column: Some("n".to_string()),
+                    scheme: PartitionScheme::Hash(3),
+                },
+            )
+            .unwrap();
+        let mut session = SqlSession::new(database.connect());
+        // The last column of each row `sql` returns
+        let mut query = |sql: &str| -> Vec<String> {
+            let result = session.execute(sql).remove(0).unwrap();
+            let rows = result.rows.into_iter();
+            rows.map(|mut row| row.pop().unwrap().unwrap()).collect()
+        };
+        for n in [3, 10, 1] {
+            query(&format!(r#"INSERT INTO {table} VALUES ('{{"n": {n}}}')"#));
+            query(&format!(
+                r#"INSERT INTO {partitioned} VALUES ('{{"n": {n}}}')"#
+            ));
+        }
+        query(&format!("INSERT INTO {table} VALUES ('{{}}')"));
+
+        // A table's rows are read in id order, so sorting by it is skipped
+        let by_id = format!("SELECT * FROM {table} ORDER BY id DESC");
+        assert_eq!(
+            query(&by_id),
+            [r#"{}"#, r#"{"n": 1}"#, r#"{"n": 10}"#, r#"{"n": 3}"#]
+        );
+        assert_eq!(
+            query(&format!("EXPLAIN {by_id}")),
+            [format!("Scan of table {table} backwards (sort elided)")]
+        );
+        let by_n = format!("SELECT * FROM {table} ORDER BY n");
+        assert_eq!(
+            query(&by_n),
+            [r#"{"n": 1}"#, r#"{"n": 3}"#, r#"{"n": 10}"#, r#"{}"#]
+        );
+        assert_eq!(
+            query(&format!("EXPLAIN {by_n}")),
+            [
+                "Sort by n".to_string(),
+                format!("  -> Scan of table {table}")
+            ]
+        );
+        // Rows found by a column all have the same value in it
+        let found = format!("EXPLAIN SELECT * FROM {table} WHERE n = '3' ORDER BY n DESC");
+        assert_eq!(
+            query(&found),
+            [format!(
+                "Scan of table {table} (filter: n = <value>) (sort elided)"
+            )]
+        );
+
+        // A partitioned table's rows are read a partition at a time
+        let by_id = format!("SELECT * FROM {partitioned} ORDER BY id");
+        assert_eq!(query(&format!("EXPLAIN {by_id}"))[0], "Sort by id");
+        let ids: Vec<_> = session.execute(&by_id).remove(0).unwrap().rows;
+        let mut sorted: Vec<_> = database.connect().scan(partitioned).unwrap();
+        sorted.sort_by_key(|row| row.id);
+        let sorted: Vec<_> = sorted.iter().map(|row| row.id.to_string()).collect();
+        assert_eq!(
+            ids.iter().map(|row| row[0].as_deref()).collect::<Vec<_>>(),
+            sorted
+                .iter()
+                .map(|id| Some(id.as_str()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    /// A table of authors, ann, bo and cy, and one of posts by them, as
+    /// `{"n": N, "author": ID}`, four by ann and bo and one by no one.
+    fn authors_and_posts(database: &Database) -> (TableId, TableId) {
+        let (authors, posts) = (
+            database.create_table().unwrap(),
+            database.create_table().unwrap(),
+        );
+        let mut session = SqlSession::new(database.connect());
+        let ids: Vec<_> = ["ann", "bo", "cy"]
+            .iter()
+            .map(|name| {
+                let sql = format!("INSERT INTO {authors} VALUES ('{name}')");
+                let mut result = session.execute(&sql).remove(0).unwrap();
+                result.rows.remove(0).remove(0).unwrap()
+            })
+            .collect();
+        for (n, author) in [(1, &ids[1]), (2, &ids[0]), (3, &ids[1]), (4, &ids[1])] {
+            let row = format!(r#"{{"n": {n}, "author": "{author}"}}"#);
+            session
+                .execute(&format!("INSERT INTO {posts} VALUES ('{row}')"))
+                .remove(0)
+                .unwrap();
+        }
+        session
+            .execute(&format!(r#"INSERT INTO {posts} VALUES ('{{"n": 5}}')"#))
+            .remove(0)
+            .unwrap();
+        (authors, posts)
+    }
+
+    #[test]
+    fn test_join() {
+        let (_dir, database) = temp_database();
+        let (authors, posts) = authors_and_posts(&database);
+        let mut session = SqlSession::new(database.connect());
+        let mut query = |sql: &str| session.execute(sql).remove(0).unwrap().rows;
+
+        // Rows whose keys match, hashed, leaving out those without a key
+        let join = format!("SELECT * FROM {posts} JOIN {authors} ON author = id");
+        let joined: Vec<_> = query(&join)
+            .into_iter()
+            .map(|row| {
+                (
+                    column_value(row[1].as_deref().unwrap().as_bytes(), "n").unwrap(),
+                    row[3].clone().unwrap(),
+                )
+            })
+            .collect();
+        let pair = |n: &str, name: &str| (n.to_string(), name.to_string());
+        assert_eq!(
+            joined,
+            [
+                pair("1", "bo"),
+                pair("2", "ann"),
+                pair("3", "bo"),
+                pair("4", "bo")
+            ]
+        );
+        let plan = query(&format!("EXPLAIN {join}"));
+        assert_eq!(plan[0], [Some("Hash join on author = id".to_string())]);
+
+        // Read in key order, merged instead
+        let join = format!("SELECT * FROM {authors} JOIN {authors} ON id = id");
+        let merged = query(&join);
+        assert_eq!(merged.len(), 3);
+        assert!(merged.iter().all(|row| row[0] == row[2]));
+        assert_eq!(
+            query(&format!("EXPLAIN {join}")),
+            [
+                "Merge join on id = id".to_string(),
+                format!("  -> Scan of table {authors} (sort elided)"),
+                format!("  -> Scan of table {authors} (sort elided)"),
+            ]
+            .map(|line| vec![Some(line)])
+        );
+        let join = format!("SELECT * FROM {authors} JOIN {posts} ON id = id");
+        assert!(query(&join).is_empty());
+    }
+
+    #[test]
+    fn test_merge_join() {
+        let (_dir, database) = temp_database();
+        let table = database.create_table().unwrap();
+        let key = || Key::Column("k".to_string());
+        let mut join = Join::new(&database, table, key(), table, key(), true);
+        join.method = JoinMethod::Merge;
+        let rows = |keys: &[&str]| -> Vec<Row> {
+            let keys = keys.iter().enumerate();
+            keys.map(|(slot, key)| Row {
+                id: RowId {
+                    table,
+                    page_no: 0,
+                    slot: slot as u32,
+                },
+                data: format!(r#"{{"k": {key}}}"#).into_bytes(),
+            })
+            .collect()
+        };
+
+        // Every pair from the runs of equal keys, skipping keys on one side
+        // only and rows without a key
+        let left = rows(&["1", "2", "2", "4", "null"]);
+        let right = rows(&["2", "2", "2", "3", "4"]);
+        let joined = join.join(&database, left, right).unwrap();
+        let slots: Vec<_> = joined
+            .iter()
+            .map(|(row, other)| (row.id.slot, other.id.slot))
+            .collect();
+        assert_eq!(
+            slots,
+            [(1, 0), (1, 1), (1, 2), (2, 0), (2, 1), (2, 2), (3, 4)]
+        );
+
+        // The same pairs a hash join finds
+        let left = rows(&["1", "2", "2", "4", "null"]);
+        let right = rows(&["2", "2", "2", "3", "4"]);
+        join.method = JoinMethod::Hash;
+        let mut hashed = join.join(&database, left, right).unwrap();
+        hashed.sort_by_key(|(row, other)| (row.id, other.id));
+        assert_eq!(hashed, joined);
+    }
+
+    #[test]
+    fn test_semi_join() {
+        let (_dir, database) = temp_database();
+        let (authors, posts) = authors_and_posts(&database);
+        let mut session = SqlSession::new(database.connect());
+        let mut query = |sql: &str| session.execute(sql).remove(0).unwrap().rows;
+        let names = |rows: Vec<Vec<Option<String>>>| -> Vec<String> {
+            rows.into_iter()
+                .map(|mut row| row.pop().unwrap().unwrap())
+                .collect()
+        };
+
+        // Rows with a match, or without one, found from the keys alone
+        let posted = format!("SELECT * FROM {authors} WHERE id IN (SELECT author FROM {posts})");
+        assert_eq!(names(query(&posted)), ["ann", "bo"]);
+        assert_eq!(
+            query(&format!("EXPLAIN {posted}"))[0],
+            [Some("Hash semi join on id = author".to_string())]
+        );
+        let posted = format!(
+            "SELECT * FROM {authors} WHERE EXISTS (SELECT * FROM {posts} WHERE author = id)"
+        );
+        assert_eq!(names(query(&posted)), ["ann", "bo"]);
+        let idle = format!(
+            "SELECT * FROM {authors} WHERE NOT EXISTS (SELECT * FROM {posts} WHERE author = id)"
+        );
+        assert_eq!(names(query(&idle)), ["cy"]);
+        assert_eq!(
+            query(&format!("EXPLAIN {idle}"))[0],
+            [Some("Hash anti join on id = author".to_string())]
+        );
+
+        // The post without an author makes the answer unknown
+        let idle = format!("SELECT * FROM {authors} WHERE id NOT IN (SELECT author FROM {posts})");
+        assert!(query(&idle).is_empty());
+        // And has no author among any, but exists without one
+        let no_author =
+            format!("SELECT * FROM {posts} WHERE author NOT IN (SELECT id FROM {authors})");
+        assert!(query(&no_author).is_empty());
+        let no_author = format!(
+            "SELECT * FROM {posts} WHERE NOT EXISTS (SELECT * FROM {authors} WHERE id = author)"
+        );
+        assert_eq!(names(query(&no_author)), [r#"{"n": 5}"#]);
+    }
+
+    #[test]
+    fn test_plan_json() {
+        let (_dir, database) = temp_database();
+        let (table, other) = (
+            database.create_table().unwrap(),
+            database.create_table().unwrap(),
+        );
+        let mut session = SqlSession::new(database.connect());
+        for n in 0..200 {
+            let sql = format!(r#"INSERT INTO {table} VALUES ('{{"n": {}}}')"#, n % 10);
+            session.execute(&sql).remove(0).unwrap();
+        }
+        let mut explain = |sql: &str| -> String {
+            let result = session.execute(sql).remove(0).unwrap();
+            let rows: Vec<_> = result
+                .rows
+                .into_iter()
+                .map(|mut row| row.remove(0).unwrap())
+                .collect();
+            rows.join("\n")
+        };
+
+        let sorted = explain(&format!(
+            "EXPLAIN (FORMAT JSON) SELECT * FROM {table} ORDER BY n DESC"
+        ));
+        assert!(sorted.starts_with("[\n  {\n    \"Plan\": {\n      \"Node Type\": \"Sort\""));
+        assert!(sorted.contains(r#""Sort Key": ["n DESC"]"#));
+        assert!(sorted.contains(r#""Node Type": "Seq Scan""#));
+        assert!(sorted.contains(&format!(r#""Table": {table}"#)));
+        assert!(sorted.ends_with("}\n]"));
+        // Nothing has been written out or analyzed, so the table is taken
+        // to have its default pages
+        assert!(sorted.contains(r#""Total Cost": 14.08"#));
+
+        // Once analyzed, its rows are known, and how few an equality keeps
+        explain(&format!("ANALYZE {table}"));
+        let scan = explain(&format!("EXPLAIN (FORMAT JSON) SELECT * FROM {table}"));
+        assert!(scan.contains(r#""Plan Rows": 200"#));
+        assert!(!scan.contains("Sort"));
+        let found = explain(&format!(
+            "EXPLAIN (FORMAT JSON) SELECT * FROM {table} WHERE n = '3' ORDER BY id DESC"
+        ));
+        assert!(found.contains(r#""Plan Rows": 20,"#));
+        assert!(found.contains(r#""Filter Key": "n""#));
+        assert!(found.contains(r#""Scan Direction": "Backward""#));
+        assert!(found.contains(r#""Sort Elided": true"#));
+
+        let join = explain(&format!(
+            "EXPLAIN (FORMAT JSON) SELECT * FROM {table} JOIN {other} ON n = id"
+        ));
+        assert!(join.contains(r#""Node Type": "Hash Join""#));
+        assert!(join.contains(r#""Join Cond": "n = id""#));
+        assert_eq!(join.matches(r#""Node Type": "Seq Scan""#).count(), 2);
+        let semi = explain(&format!(
+            "EXPLAIN (FORMAT JSON) SELECT * FROM {table} WHERE n NOT IN (SELECT id FROM {other})"
+        ));
+        assert!(semi.contains(r#""Join Type": "Anti""#));
+        assert!(semi.contains(r#""Subquery": "IN""#));
+
+        // Text is the default
+        let text = format!("SELECT * FROM {table} ORDER BY n");
+        assert_eq!(
+            explain(&format!("EXPLAIN (FORMAT TEXT) {text}")),
+            explain(&format!("EXPLAIN {text}"))
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use super::protocol::{ClientMessage, ServerMessage};
+use super::{ErrorCode, Outcome, Request};
+use crate::database::Row;
+use crate::encoding::ResultFormat;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Connection refused ({code:?}): {message}")]
+    Refused { code: ErrorCode, message: String },
+
+    #[error("Query failed ({code:?}): {message}")]
+    Query { code: ErrorCode, message: String },
+
+    #[error("Unexpected message from the server")]
+    UnexpectedMessage,
+}
+
+/// What a query returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryResult {
+    pub rows: Vec<Row>,
+    pub outcome: Outcome,
+}
+
+/// A session with a `Server`.
+pub struct Client {
+    input: BufReader<TcpStream>,
+    out: BufWriter<TcpStream>,
+}
+
+impl Client {
+    /// Connect to the server at `addr` and start a session.
+    pub fn connect(
+        addr: impl ToSocketAddrs,
+        user: &str,
+        password: &str,
+    ) -> Result<Self, ClientError> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        let mut client = Self {
+            input: BufReader::new(stream.try_clone()?),
+            out: BufWriter::new(stream),
+        };
+        let startup = ClientMessage::Startup {
+            user: user.to_string(),
+            password: password.to_string(),
+        };
+        startup.write_to(&mut client.out)?;
+        client.out.flush()?;
+        match ServerMessage::read_from(&mut client.input)? {
+            ServerMessage::Ready => Ok(client),
+            ServerMessage::Error { code, message } => Err(ClientError::Refused { code, message }),
+            _ => Err(ClientError::UnexpectedMessage),
+        }
+    }
+
+    /// Run `request`, waiting for its rows and outcome.
+    pub fn query(&mut self, request: &Request) -> Result<QueryResult, ClientError> {
+        ClientMessage::Query(request.clone()).write_to(&mut self.out)?;
+        self.out.flush()?;
+        let mut rows = Vec::new();
+        loop {
+            match ServerMessage::read_from(&mut self.input)? {
+                ServerMessage::Row(row) => rows.push(row),
+                ServerMessage::Complete(outcome) => return Ok(QueryResult { rows, outcome }),
+                ServerMessage::Error { code, message } => {
+                    return Err(ClientError::Query { code, message })
+                }
+                ServerMessage::Ready | ServerMessage::Output(_) => {
+                    return Err(ClientError::UnexpectedMessage)
+                }
+            }
+        }
+    }
+
+    /// Run the statements in `sql`, returning each one's result in
+    /// `format`, or the error of the first to fail.
+    pub fn sql(&mut self, sql: &str, format: ResultFormat) -> Result<Vec<Vec<u8>>, ClientError> {
+        let message = ClientMessage::Sql {
+            sql: sql.to_string(),
+            format,
+        };
+        message.write_to(&mut self.out)?;
+        self.out.flush()?;
+        let mut outputs = Vec::new();
+        loop {
+            match ServerMessage::read_from(&mut self.input)? {
+                ServerMessage::Output(output) => outputs.push(output),
+                ServerMessage::Complete(Outcome::Done) => return Ok(outputs),
+                ServerMessage::Error { code, message } => {
+                    return Err(ClientError::Query { code, message })
+                }
+                _ => return Err(ClientError::UnexpectedMessage),
+            }
+        }
+    }
+
+    /// End the session, rolling back any transaction left open.
+    pub fn close(mut self) -> Result<(), ClientError> {
+        ClientMessage::Terminate.write_to(&mut self.out)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+//! Serving the database's metrics over HTTP, for Prometheus to scrape.
+//!
+//! Just enough of HTTP/1.1 for a scraper: `GET /metrics` is answered with
+//! `Database::metrics` in the text exposition format, with the sessions
+//! connected, and anything else with an error. Each connection is closed
+//! after its one response, and requests are answered one at a time.
+
+use super::{Shared, ACCEPT_POLL};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long a scraper has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Listen on `listen` and answer scrapes until the server stops.
+pub(super) fn spawn(shared: Arc<Shared>, listen: &str) -> io::Result<(SocketAddr, JoinHandle<()>)> {
+    let listener = TcpListener::bind(listen)?;
+    listener.set_nonblocking(true)?;
+    let addr = listener.local_addr()?;
+    let handle = thread::spawn(move || {
+        while !shared.stop.load(Ordering::Acquire) {
+            match listener.accept() {
+                // A scraper that goes away just misses its answer
+                Ok((stream, _)) => {
+                    let _ = answer(&shared, &stream);
+                }
+                Err(_) => thread::sleep(ACCEPT_POLL),
+            }
+        }
+    });
+    Ok((addr, handle))
+}
+
+fn answer(shared: &Shared, stream: &TcpStream) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut input = BufReader::new(stream);
+    let mut request = String::new();
+    input.read_line(&mut request)?;
+    // The headers don't matter, but are read so the client isn't reset
+    let mut header = String::new();
+    while input.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let mut body = shared.database.metrics().prometheus();
+            body.push_str("# HELP ferrodb_sessions Sessions connected.\n");
+            body.push_str("# TYPE ferrodb_sessions gauge\n");
+            body.push_str(&format!(
+                "ferrodb_sessions {}\n",
+                shared.sessions.list().len()
+            ));
+            ("200 OK", body)
+        }
+        (Some("GET"), Some(_)) => ("404 Not Found", "not found\n".to_string()),
+        (Some(_), Some(_)) => ("405 Method Not Allowed", "only GET\n".to_string()),
+        _ => ("400 Bad Request", "bad request\n".to_string()),
+    };
+    let mut out = stream;
+    write!(
+        out,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::tests::test_config;
+    use crate::database::Database;
+    use crate::server::{Client, Request, Server};
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::Arc;
+
+    fn get(addr: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_metrics_endpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        config.storage.page_size = 128;
+        config.server.listen = "127.0.0.1:0".to_string();
+        config.server.metrics_listen = Some("127.0.0.1:0".to_string());
+        let database = Database::with_config(&config).unwrap();
+        let server = Server::spawn(Arc::new(database), &config.server).unwrap();
+        let addr = server.metrics_addr().unwrap();
+
+        let mut client = Client::connect(server.local_addr(), "alice", "").unwrap();
+        client.query(&Request::CreateTable).unwrap();
+        client.query(&Request::Commit).unwrap_err();
+
+        let response = get(addr, "GET /metrics HTTP/1.1\r\nHost: ferrodb\r\n\r\n");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        assert!(body.contains("\nferrodb_queries_total 2\n"), "{}", body);
+        assert!(body.contains("\nferrodb_failed_queries_total 1\n"));
+        assert!(body.contains("\nferrodb_sessions 1\n"));
+
+        assert!(get(addr, "GET / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        assert!(get(addr, "POST /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+        client.close().unwrap();
+    }
+}
@@ -0,0 +1,647 @@
+//! Serving a database to clients over TCP.
+
+mod client;
+mod metrics;
+mod postgres;
+mod protocol;
+mod session;
+
+pub use client::{Client, ClientError, QueryResult};
+pub use protocol::{ErrorCode, Outcome, Request};
+pub use session::SessionInfo;
+
+use crate::asynchronous::Pending;
+use crate::audit::AuditEvent;
+use crate::auth::{self, Privilege};
+use crate::config::{ServerConfig, WireProtocol};
+use crate::database::{Connection, Database, DatabaseError, Row};
+use crate::encoding::ResultFormat;
+use crate::error::FerroError;
+use crate::logging::log;
+use crate::table_options::TableOptions;
+use protocol::{ClientMessage, ServerMessage};
+use session::{Session, Sessions, FAILED};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often the accept loop checks whether to stop.
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+/// How often `drain` checks for transactions still open.
+const DRAIN_POLL: Duration = Duration::from_millis(20);
+
+/// Serves a database over TCP, speaking the protocol described in
+/// `protocol`, or in Postgres compatibility mode that of `postgres`.
+///
+/// Clients are accepted by one thread and handed to a fixed pool of
+/// workers, each serving one session at a time over its own `Connection`.
+/// Clients beyond the size of the pool wait, connected, for a worker to
+/// become free. Each session keeps its own variables and prepared
+/// statements; `sessions` lists who is connected. Stopping the server
+/// closes every session, rolling back their open transactions; `drain`
+/// first gives them a while to finish.
+///
+/// Once the database has users, clients log in as one of them. Until then
+/// any user name is accepted, with the configured password if there is one.
+/// Each query is checked against the privileges of the session's user.
+///
+/// With `metrics_listen` configured, the database's metrics are also
+/// served over HTTP, as `metrics` describes.
+pub struct Server {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    acceptor: Option<JoinHandle<()>>,
+    workers: Vec<JoinHandle<()>>,
+    metrics: Option<(SocketAddr, JoinHandle<()>)>,
+}
+
+struct Shared {
+    database: Arc<Database>,
+    password: Option<String>,
+    protocol: WireProtocol,
+    stop: AtomicBool,
+    sessions: Sessions,
+}
+
+impl Shared {
+    /// Whether clients must give a password to start a session.
+    fn needs_password(&self) -> bool {
+        self.password.is_some() || !matches!(self.database.users(), Ok(users) if users.is_empty())
+    }
+
+    /// Whether `user` may start a session with `password`.
+    fn authenticate(&self, user: &str, password: &str) -> bool {
+        let authenticated = match self.database.users() {
+            Ok(users) if users.is_empty() => self
+                .password
+                .as_ref()
+                .is_none_or(|expected| auth::password_matches(expected, password)),
+            Ok(_) => self.database.authenticate(user, password).unwrap_or(false),
+            Err(_) => false,
+        };
+        let outcome = if authenticated {
+            Ok(())
+        } else {
+            log!(Warn, "authentication failed", user = user);
+            Err("wrong user or password")
+        };
+        self.database.audit(Some(user), AuditEvent::Login, outcome);
+        authenticated
+    }
+
+    /// Start session `id` for `user`, unless they already have as many
+    /// open as their `max_connections` allows.
+    fn admit(&self, id: u64, user: &str) -> Result<(), String> {
+        let max = self.database.limits(user).max_connections;
+        if self.sessions.admit(id, user, max) {
+            return Ok(());
+        }
+        log!(Warn, "too many sessions", user = user);
+        Err(format!("too many connections for role \"{}\"", user))
+    }
+}
+
+impl Server {
+    /// Listen on the address in `config` and start serving.
+    pub fn spawn(database: Arc<Database>, config: &ServerConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind(&config.listen)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        log!(
+            Info,
+            "listening",
+            addr = addr,
+            workers = config.workers.max(1)
+        );
+        let shared = Arc::new(Shared {
+            database,
+            password: config.password.clone(),
+            protocol: config.protocol,
+            stop: AtomicBool::new(false),
+            sessions: Sessions::default(),
+        });
+
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..config.workers.max(1))
+            .map(|_| {
+                let (shared, receiver) = (shared.clone(), receiver.clone());
+                thread::spawn(move || work(&shared, &receiver))
+            })
+            .collect();
+        let acceptor = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let mut next_session = 0;
+                while !shared.stop.load(Ordering::Acquire) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            next_session += 1;
+                            if sender.send((next_session, stream)).is_err() {
+                                break;
+                            }
+                        }
+                        Err(_) => thread::sleep(ACCEPT_POLL),
+                    }
+                }
+            })
+        };
+        let metrics = match &config.metrics_listen {
+            Some(listen) => Some(metrics::spawn(shared.clone(), listen)?),
+            None => None,
+        };
+        Ok(Self {
+            addr,
+            shared,
+            acceptor: Some(acceptor),
+            workers,
+            metrics,
+        })
+    }
+
+    /// The address clients connect to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The address metrics are served on, if they are.
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics.as_ref().map(|(addr, _)| *addr)
+    }
+
+    /// The sessions currently connected, in the order they connected.
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        self.shared.sessions.list()
+    }
+
+    /// Stop serving as dropping the server does, but without blocking the
+    /// caller: resolves once every session has closed.
+    pub fn shutdown(self) -> Pending<()> {
+        let (pending, completer) = Pending::new();
+        thread::spawn(move || {
+            drop(self);
+            completer.complete(());
+        });
+        pending
+    }
+
+    /// Stop accepting clients, wait up to `timeout` for every open
+    /// transaction to finish, then stop as dropping the server does,
+    /// rolling back those that haven't. Returns how many were rolled back.
+    pub fn drain(mut self, timeout: Duration) -> usize {
+        self.shared.stop.store(true, Ordering::Release);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+        self.shared.sessions.refuse_new();
+        let deadline = Instant::now() + timeout;
+        let open = || {
+            let sessions = self.sessions();
+            sessions.iter().filter(|info| info.in_transaction).count()
+        };
+        while open() > 0 && Instant::now() < deadline {
+            thread::sleep(DRAIN_POLL);
+        }
+        let rolled_back = open();
+        log!(Info, "stopping", rolled_back = rolled_back);
+        rolled_back
+    }
+
+    /// Serve until the process is stopped.
+    pub fn wait(mut self) {
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+        if let Some((_, metrics)) = self.metrics.take() {
+            let _ = metrics.join();
+        }
+        self.shared.sessions.close_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Serve sessions in turn until the acceptor stops.
+fn work(shared: &Shared, receiver: &Mutex<Receiver<(u64, TcpStream)>>) {
+    loop {
+        let next = receiver.lock().unwrap().recv();
+        let Ok((id, stream)) = next else {
+            return;
+        };
+        // Sessions taken up once the server is stopping are dropped
+        if !shared.sessions.register(id, &stream) {
+            continue;
+        }
+        let peer = stream
+            .peer_addr()
+            .map_or_else(|_| "unknown".to_string(), |addr| addr.to_string());
+        log!(Info, "session started", id = id, peer = peer);
+        // A client that goes away just ends its session
+        let result = match shared.protocol {
+            WireProtocol::Native => serve(shared, &stream, id),
+            WireProtocol::Postgres => postgres::serve(shared, &stream, id),
+        };
+        if let Err(e) = result {
+            log!(Debug, "session lost", id = id, error = e);
+        }
+        shared.sessions.remove(id);
+        log!(Info, "session ended", id = id);
+    }
+}
+
+fn serve(shared: &Shared, stream: &TcpStream, id: u64) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    let mut input = BufReader::new(stream);
+    let mut out = BufWriter::new(stream);
+
+    let (user, refusal) = match ClientMessage::read_from(&mut input) {
+        Ok(Some(ClientMessage::Startup { user, password })) => {
+            if !shared.authenticate(&user, &password) {
+                let message = "wrong user or password".to_string();
+                (user, Some((ErrorCode::Authentication, message)))
+            } else if let Err(message) = shared.admit(id, &user) {
+                (user, Some((ErrorCode::ResourceLimit, message)))
+            } else {
+                (user, None)
+            }
+        }
+        Ok(None) => return Ok(()),
+        Ok(Some(_)) => (
+            String::new(),
+            Some((ErrorCode::Protocol, "expected startup".to_string())),
+        ),
+        Err(e) => (String::new(), Some((ErrorCode::Protocol, e.to_string()))),
+    };
+    if let Some((code, message)) = refusal {
+        ServerMessage::Error { code, message }.write_to(&mut out)?;
+        return out.flush();
+    }
+    ServerMessage::Ready.write_to(&mut out)?;
+    out.flush()?;
+
+    let connection = shared.database.connect();
+    let mut session = Session::start(id, &shared.sessions, connection, &user, "");
+    loop {
+        match ClientMessage::read_from(&mut input) {
+            Ok(Some(ClientMessage::Query(request))) => {
+                let database = &shared.database;
+                let ran = session.guarded(|session| run(database, session, request, &mut out));
+                let Some(ran) = ran else {
+                    return fail(&mut out);
+                };
+                ran?;
+                session.sync();
+            }
+            Ok(Some(ClientMessage::Sql { sql, format })) => {
+                let ran = session.guarded(|session| run_sql(session, &sql, format, &mut out));
+                let Some(ran) = ran else {
+                    return fail(&mut out);
+                };
+                ran?;
+                session.sync();
+            }
+            Ok(Some(ClientMessage::Terminate)) | Ok(None) => return Ok(()),
+            Ok(Some(ClientMessage::Startup { .. })) => {
+                let message = "already started".to_string();
+                let code = ErrorCode::Protocol;
+                ServerMessage::Error { code, message }.write_to(&mut out)?;
+                return out.flush();
+            }
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                let message = e.to_string();
+                let code = ErrorCode::Protocol;
+                ServerMessage::Error { code, message }.write_to(&mut out)?;
+                return out.flush();
+            }
+            Err(e) => return Err(e),
+        }
+        out.flush()?;
+    }
+}
+
+/// Tell the client its session failed, as it's about to end.
+fn fail(out: &mut impl Write) -> io::Result<()> {
+    let code = ErrorCode::Internal;
+    let message = FAILED.to_string();
+    ServerMessage::Error { code, message }.write_to(out)?;
+    out.flush()
+}
+
+/// Run one query, replying with its rows and outcome or its error.
+fn run(
+    database: &Database,
+    session: &mut Session,
+    request: Request,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let started = Instant::now();
+    let name = request.name();
+    let create_table = request == Request::CreateTable;
+    let result = authorize(database, session.user(), &request).and_then(|_| match request {
+        Request::CreateTable => database
+            .create_table_as(session.user(), TableOptions::default())
+            .map(|table| (Vec::new(), Outcome::Table(table))),
+        request => execute(database, session.sql.connection_mut(), request),
+    });
+    database.record_query(started.elapsed(), result.is_ok(), name);
+    if create_table {
+        let error = result.as_ref().err().map(DatabaseError::to_string);
+        let outcome = error.as_deref().map_or(Ok(()), Err);
+        let event = AuditEvent::Statement("CREATE TABLE");
+        database.audit(Some(session.user()), event, outcome);
+    }
+    match result {
+        Ok((rows, outcome)) => {
+            for row in rows {
+                ServerMessage::Row(row).write_to(out)?;
+            }
+            ServerMessage::Complete(outcome).write_to(out)
+        }
+        Err(e) => {
+            let error = FerroError::from(e);
+            let code = error.code();
+            let message = error.to_string();
+            ServerMessage::Error { code, message }.write_to(out)
+        }
+    }
+}
+
+/// Run the statements in `sql`, replying with each one's result in
+/// `format` until one fails.
+fn run_sql(
+    session: &mut Session,
+    sql: &str,
+    format: ResultFormat,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    for result in session.sql.execute(sql) {
+        match result {
+            Ok(result) => ServerMessage::Output(format.render(&result)).write_to(out)?,
+            Err(e) => {
+                // Which, for a statement that doesn't parse, says where
+                let error = FerroError::from(e);
+                let code = error.code();
+                let message = error.to_string();
+                return ServerMessage::Error { code, message }.write_to(out);
+            }
+        }
+    }
+    ServerMessage::Complete(Outcome::Done).write_to(out)
+}
+
+/// Check that `user` may make `request`. Creating a table is checked as it
+/// runs.
+fn authorize(database: &Database, user: &str, request: &Request) -> Result<(), DatabaseError> {
+    let (privilege, table) = match request {
+        Request::Insert { table, .. } => (Privilege::Insert, *table),
+        Request::Get(row) => (Privilege::Select, row.table),
+        Request::Scan(table) => (Privilege::Select, *table),
+        Request::Update { row, .. } => (Privilege::Update, row.table),
+        Request::Delete(row) => (Privilege::Delete, row.table),
+        Request::Begin | Request::Commit | Request::Rollback | Request::CreateTable => {
+            return Ok(())
+        }
+    };
+    database.check_privilege(user, privilege, Some(table))
+}
+
+/// Run `request` on `connection`, returning the rows it read and its
+/// outcome.
+pub(crate) fn execute(
+    database: &Database,
+    connection: &mut Connection,
+    request: Request,
+) -> Result<(Vec<Row>, Outcome), DatabaseError> {
+    match request {
+        Request::Begin => connection.begin().map(|_| (Vec::new(), Outcome::Done)),
+        Request::Commit => connection.commit().map(|_| (Vec::new(), Outcome::Done)),
+        Request::Rollback => connection.rollback().map(|_| (Vec::new(), Outcome::Done)),
+        Request::CreateTable => database
+            .create_table()
+            .map(|table| (Vec::new(), Outcome::Table(table))),
+        Request::Insert { table, data } => connection
+            .insert(table, &data)
+            .map(|row| (Vec::new(), Outcome::Inserted(row))),
+        Request::Get(row) => connection
+            .get(row)
+            .map(|row| (row.into_iter().collect(), Outcome::Done)),
+        Request::Update { row, data } => connection
+            .update(row, &data)
+            .map(|_| (Vec::new(), Outcome::Done)),
+        Request::Delete(row) => connection
+            .delete(row)
+            .map(|existed| (Vec::new(), Outcome::Deleted(existed))),
+        Request::Scan(table) => connection.scan(table).map(|rows| (rows, Outcome::Done)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asynchronous::tests::block_on;
+    use crate::config::{LimitsConfig, UserLimits};
+    use crate::database::tests::test_config;
+    use crate::database::{Row, TableId};
+
+    fn spawn(dir: &std::path::Path, password: Option<&str>) -> Server {
+        spawn_with(dir, password, LimitsConfig::default())
+    }
+
+    fn spawn_with(dir: &std::path::Path, password: Option<&str>, limits: LimitsConfig) -> Server {
+        let mut config = test_config(dir);
+        config.limits = limits;
+        config.storage.page_size = 128;
+        config.server.listen = "127.0.0.1:0".to_string();
+        config.server.workers = 2;
+        config.server.password = password.map(str::to_string);
+        let database = Database::with_config(&config).unwrap();
+        Server::spawn(Arc::new(database), &config.server).unwrap()
+    }
+
+    #[test]
+    fn test_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = spawn(dir.path(), Some("secret"));
+        let addr = server.local_addr();
+        assert!(matches!(
+            Client::connect(addr, "alice", "guess"),
+            Err(ClientError::Refused {
+                code: ErrorCode::Authentication,
+                ..
+            })
+        ));
+
+        let mut client = Client::connect(addr, "alice", "secret").unwrap();
+        let Outcome::Table(table) = client.query(&Request::CreateTable).unwrap().outcome else {
+            panic!("expected a table");
+        };
+        client.query(&Request::Begin).unwrap();
+        let insert = Request::Insert {
+            table,
+            data: b"hello".to_vec(),
+        };
+        let Outcome::Inserted(row) = client.query(&insert).unwrap().outcome else {
+            panic!("expected a row id");
+        };
+
+        // Another session waits on the lock held by the open transaction
+        // until it commits
+        let reader = thread::spawn(move || {
+            let mut other = Client::connect(addr, "bob", "secret").unwrap();
+            other.query(&Request::Scan(table)).unwrap().rows
+        });
+        thread::sleep(Duration::from_millis(50));
+        client.query(&Request::Commit).unwrap();
+        assert_eq!(
+            reader.join().unwrap(),
+            vec![Row {
+                id: row,
+                data: b"hello".to_vec()
+            }]
+        );
+
+        // Failed queries leave the session usable
+        assert!(matches!(
+            client.query(&Request::Scan(TableId(99))),
+            Err(ClientError::Query {
+                code: ErrorCode::NoSuchTable,
+                ..
+            })
+        ));
+        assert!(matches!(
+            client.query(&Request::Commit),
+            Err(ClientError::Query {
+                code: ErrorCode::TransactionState,
+                ..
+            })
+        ));
+        assert_eq!(client.query(&Request::Get(row)).unwrap().rows.len(), 1);
+
+        // SQL results come back encoded as asked, up to the first failure
+        let sql = format!("SELECT * FROM {}; SELECT * FROM 99", table.0);
+        assert!(matches!(
+            client.sql(&sql, ResultFormat::Csv),
+            Err(ClientError::Query {
+                code: ErrorCode::NoSuchTable,
+                ..
+            })
+        ));
+        let sql = format!("SELECT * FROM {}", table.0);
+        assert_eq!(
+            client.sql(&sql, ResultFormat::Csv).unwrap(),
+            vec![format!("id,data\n{},hello\n", row).into_bytes()]
+        );
+        client.query(&Request::Begin).unwrap();
+        let sessions = server.sessions();
+        let session = sessions.iter().find(|info| info.user == "alice").unwrap();
+        assert!(session.in_transaction);
+        client.close().unwrap();
+        block_on(server.shutdown());
+    }
+
+    #[test]
+    fn test_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut limits = LimitsConfig::default();
+        limits.default.max_connections = Some(1);
+        let alice = UserLimits {
+            max_rows: Some(2),
+            ..UserLimits::default()
+        };
+        limits.users.insert("alice".to_string(), alice);
+        let server = spawn_with(dir.path(), None, limits);
+        let addr = server.local_addr();
+
+        // Each user has as many sessions as they may
+        let mut client = Client::connect(addr, "alice", "").unwrap();
+        assert!(matches!(
+            Client::connect(addr, "alice", ""),
+            Err(ClientError::Refused {
+                code: ErrorCode::ResourceLimit,
+                ..
+            })
+        ));
+        let bob = Client::connect(addr, "bob", "").unwrap();
+        bob.close().unwrap();
+
+        // And is sent as many rows
+        let Outcome::Table(table) = client.query(&Request::CreateTable).unwrap().outcome else {
+            panic!("expected a table");
+        };
+        let mut rows = Vec::new();
+        for _ in 0..3 {
+            let data = b"hello".to_vec();
+            let insert = Request::Insert { table, data };
+            let Outcome::Inserted(row) = client.query(&insert).unwrap().outcome else {
+                panic!("expected a row id");
+            };
+            rows.push(row);
+        }
+        let sql = format!("SELECT * FROM {}", table.0);
+        assert!(matches!(
+            client.sql(&sql, ResultFormat::Csv),
+            Err(ClientError::Query {
+                code: ErrorCode::ResourceLimit,
+                ..
+            })
+        ));
+        let sql = format!("SELECT * FROM {} WHERE id = '{}'", table.0, rows[0]);
+        assert_eq!(client.sql(&sql, ResultFormat::Csv).unwrap().len(), 1);
+        client.close().unwrap();
+        block_on(server.shutdown());
+    }
+
+    #[test]
+    fn test_drain() {
+        let dir = tempfile::tempdir().unwrap();
+        let insert = |client: &mut Client, table| {
+            let data = b"hello".to_vec();
+            client.query(&Request::Insert { table, data }).unwrap();
+        };
+        let server = spawn(dir.path(), None);
+        let addr = server.local_addr();
+        let mut client = Client::connect(addr, "alice", "").unwrap();
+        let Outcome::Table(table) = client.query(&Request::CreateTable).unwrap().outcome else {
+            panic!("expected a table");
+        };
+
+        // A transaction that finishes in time is kept
+        client.query(&Request::Begin).unwrap();
+        insert(&mut client, table);
+        let committer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            client.query(&Request::Commit).unwrap();
+            client
+        });
+        assert_eq!(server.drain(Duration::from_secs(10)), 0);
+        assert!(Client::connect(addr, "bob", "").is_err());
+        let mut client = committer.join().unwrap();
+        assert!(client.query(&Request::Scan(table)).is_err());
+
+        // One that doesn't is rolled back
+        let server = spawn(dir.path(), None);
+        let mut client = Client::connect(server.local_addr(), "alice", "").unwrap();
+        client.query(&Request::Begin).unwrap();
+        insert(&mut client, table);
+        assert_eq!(server.drain(Duration::from_millis(50)), 1);
+        let server = spawn(dir.path(), None);
+        let mut client = Client::connect(server.local_addr(), "alice", "").unwrap();
+        assert_eq!(client.query(&Request::Scan(table)).unwrap().rows.len(), 1);
+    }
+}
@@ -0,0 +1,482 @@
+//! Serving sessions over PostgreSQL's frontend/backend protocol, version
+//! 3.0, so `psql` and Postgres drivers can connect.
+//!
+//! Startup is answered with cleartext password authentication when the
+//! server needs a password, and SSL and GSSAPI encryption are declined.
+//! Simple queries run in the session's `SqlSession`; results are sent as
+//! text columns, with row contents that aren't UTF-8 converted lossily.
+//! The extended query protocol and cancellation aren't supported: extended
+//! queries fail until the next Sync, and cancel requests are ignored.
+
+use super::session::{Session, FAILED};
+use super::Shared;
+use crate::database::Connection;
+use crate::sql::StatementResult;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+
+/// The largest message accepted, so a bad length can't exhaust memory.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+const PROTOCOL_VERSION: i32 = 3 << 16;
+const SSL_REQUEST: i32 = 80877103;
+const GSSENC_REQUEST: i32 = 80877104;
+const CANCEL_REQUEST: i32 = 80877102;
+
+/// The type of every column sent: `text`.
+const TEXT_OID: i32 = 25;
+
+/// Parameters reported to clients at startup, which drivers rely on.
+const PARAMETERS: &[(&str, &str)] = &[
+    ("server_version", "14.0 (ferrodb)"),
+    ("server_encoding", "UTF8"),
+    ("client_encoding", "UTF8"),
+    ("DateStyle", "ISO, MDY"),
+    ("integer_datetimes", "on"),
+    ("standard_conforming_strings", "on"),
+];
+
+pub(super) fn serve(shared: &Shared, stream: &TcpStream, id: u64) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    let mut input = BufReader::new(stream);
+    let mut out = BufWriter::new(stream);
+
+    let Some((user, database)) = start(&mut input, &mut out)? else {
+        return Ok(());
+    };
+    if shared.needs_password() {
+        send(&mut out, b'R', &3i32.to_be_bytes())?;
+        out.flush()?;
+        let password = match read_message(&mut input)? {
+            Some((b'p', body)) => body,
+            _ => return Ok(()),
+        };
+        let password = String::from_utf8_lossy(password.strip_suffix(b"\0").unwrap_or(&password));
+        if !shared.authenticate(&user, &password) {
+            let message = format!("password authentication failed for user \"{}\"", user);
+            send_error(&mut out, "FATAL", "28P01", &message)?;
+            return out.flush();
+        }
+    }
+    if let Err(message) = shared.admit(id, &user) {
+        send_error(&mut out, "FATAL", "53300", &message)?;
+        return out.flush();
+    }
+    send(&mut out, b'R', &0i32.to_be_bytes())?;
+    for (name, value) in PARAMETERS {
+        let mut body = Vec::new();
+        put_cstr(&mut body, name);
+        put_cstr(&mut body, value);
+        send(&mut out, b'S', &body)?;
+    }
+    let mut key = Vec::new();
+    key.write_i32::<BigEndian>(id as i32).unwrap();
+    key.write_i32::<BigEndian>(0).unwrap();
+    send(&mut out, b'K', &key)?;
+
+    let connection = shared.database.connect();
+    let mut session = Session::start(id, &shared.sessions, connection, &user, &database);
+    // None of these is one the session checks, so none is refused
+    let reported = PARAMETERS.iter().copied();
+    for (name, value) in reported.chain([("user", user.as_str()), ("database", &database)]) {
+        session.sql.set(name, value).ok();
+    }
+    send_ready(&mut out, session.sql.connection())?;
+    out.flush()?;
+    // Set when an extended query fails, until the client syncs
+    let mut failed = false;
+    loop {
+        let Some((tag, body)) = read_message(&mut input)? else {
+            return Ok(());
+        };
+        match tag {
+            b'Q' => {
+                let sql = String::from_utf8_lossy(body.strip_suffix(b"\0").unwrap_or(&body));
+                let Some(ran) = session.guarded(|session| query(session, &sql, &mut out)) else {
+                    send_error(&mut out, "FATAL", "XX000", FAILED)?;
+                    return out.flush();
+                };
+                ran?;
+                session.sync();
+                send_ready(&mut out, session.sql.connection())?;
+            }
+            b'X' => return Ok(()),
+            b'P' | b'B' | b'D' | b'E' | b'C' | b'H' | b'F' => {
+                if !failed {
+                    let message = "the extended query protocol isn't supported";
+                    send_error(&mut out, "ERROR", "0A000", message)?;
+                    failed = true;
+                }
+            }
+            b'S' => {
+                failed = false;
+                send_ready(&mut out, session.sql.connection())?;
+            }
+            tag => {
+                let message = format!("unexpected message {:?}", tag as char);
+                send_error(&mut out, "FATAL", "08P01", &message)?;
+                return out.flush();
+            }
+        }
+        out.flush()?;
+    }
+}
+
+/// Read the startup message, declining encryption along the way, and
+/// return the user and database it names; `None` if the client gave up.
+fn start(input: &mut impl Read, out: &mut impl Write) -> io::Result<Option<(String, String)>> {
+    loop {
+        let len = match input.read_i32::<BigEndian>() {
+            Ok(len) => len as usize,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if !(8..=MAX_MESSAGE_SIZE).contains(&len) {
+            return Ok(None);
+        }
+        let mut body = vec![0; len - 4];
+        input.read_exact(&mut body)?;
+        let code = (&body[..4]).read_i32::<BigEndian>()?;
+        match code {
+            SSL_REQUEST | GSSENC_REQUEST => {
+                out.write_all(b"N")?;
+                out.flush()?;
+            }
+            CANCEL_REQUEST => return Ok(None),
+            PROTOCOL_VERSION => {
+                let mut fields = body[4..].split(|&byte| byte == 0);
+                let (mut user, mut database) = (String::new(), None);
+                while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+                    let value = String::from_utf8_lossy(value).into_owned();
+                    match name {
+                        b"user" => user = value,
+                        b"database" => database = Some(value),
+                        _ => {}
+                    }
+                }
+                // As in Postgres, the database defaults to the user's name
+                let database = database.unwrap_or_else(|| user.clone());
+                return Ok(Some((user, database)));
+            }
+            _ => {
+                let message = format!("unsupported protocol version {}", code);
+                send_error(out, "FATAL", "0A000", &message)?;
+                out.flush()?;
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Run the statements of a simple query in turn, sending the results of
+/// each, up to the first to fail.
+fn query(session: &mut Session, sql: &str, out: &mut impl Write) -> io::Result<()> {
+    let results = session.sql.execute(sql);
+    if results.is_empty() {
+        return send(out, b'I', &[]);
+    }
+    for result in results {
+        match result {
+            Ok(result) => send_result(out, result)?,
+            Err(e) => send_error(out, "ERROR", e.code(), e.message())?,
+        }
+    }
+    Ok(())
+}
+
+fn send_result(out: &mut impl Write, result: StatementResult) -> io::Result<()> {
+    let StatementResult { columns, rows, tag } = result;
+    if !columns.is_empty() {
+        let mut body = Vec::new();
+        body.write_i16::<BigEndian>(columns.len() as i16).unwrap();
+        for column in columns {
+            put_cstr(&mut body, &column);
+            body.write_i32::<BigEndian>(0).unwrap();
+            body.write_i16::<BigEndian>(0).unwrap();
+            body.write_i32::<BigEndian>(TEXT_OID).unwrap();
+            body.write_i16::<BigEndian>(-1).unwrap();
+            body.write_i32::<BigEndian>(-1).unwrap();
+            body.write_i16::<BigEndian>(0).unwrap();
+        }
+        send(out, b'T', &body)?;
+    }
+    for row in rows {
+        let mut body = Vec::new();
+        body.write_i16::<BigEndian>(row.len() as i16).unwrap();
+        for value in row {
+            // A NULL is a length of -1 with no bytes
+            match value {
+                Some(value) => {
+                    body.write_i32::<BigEndian>(value.len() as i32).unwrap();
+                    body.extend_from_slice(value.as_bytes());
+                }
+                None => body.write_i32::<BigEndian>(-1).unwrap(),
+            }
+        }
+        send(out, b'D', &body)?;
+    }
+    let mut body = Vec::new();
+    put_cstr(&mut body, &tag);
+    send(out, b'C', &body)
+}
+
+/// A message's tag and body, or `None` at the end of the stream.
+fn read_message(input: &mut impl Read) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let tag = match input.read_u8() {
+        Ok(tag) => tag,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let len = input.read_i32::<BigEndian>()? as usize;
+    if !(4..=MAX_MESSAGE_SIZE).contains(&len) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes", len),
+        ));
+    }
+    let mut body = vec![0; len - 4];
+    input.read_exact(&mut body)?;
+    Ok(Some((tag, body)))
+}
+
+fn send(out: &mut impl Write, tag: u8, body: &[u8]) -> io::Result<()> {
+    out.write_u8(tag)?;
+    out.write_i32::<BigEndian>(body.len() as i32 + 4)?;
+    out.write_all(body)
+}
+
+fn send_ready(out: &mut impl Write, connection: &Connection) -> io::Result<()> {
+    let status = if connection.in_transaction() {
+        b'T'
+    } else {
+        b'I'
+    };
+    send(out, b'Z', &[status])
+}
+
+fn send_error(out: &mut impl Write, severity: &str, code: &str, message: &str) -> io::Result<()> {
+    let mut body = Vec::new();
+    for (field, value) in [
+        (b'S', severity),
+        (b'V', severity),
+        (b'C', code),
+        (b'M', message),
+    ] {
+        body.push(field);
+        put_cstr(&mut body, value);
+    }
+    body.push(0);
+    send(out, b'E', &body)
+}
+
+fn put_cstr(body: &mut Vec<u8>, value: &str) {
+    body.extend_from_slice(value.as_bytes());
+    body.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WireProtocol;
+    use crate::database::tests::test_config;
+    use crate::database::Database;
+    use crate::server::Server;
+    use std::sync::Arc;
+
+    /// Just enough of a Postgres client to follow the conversation.
+    struct PgClient {
+        stream: TcpStream,
+    }
+
+    impl PgClient {
+        fn connect(server: &Server, user: &str, password: &str) -> (Self, Vec<(u8, Vec<u8>)>) {
+            let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+            let mut ssl = Vec::new();
+            ssl.write_i32::<BigEndian>(8).unwrap();
+            ssl.write_i32::<BigEndian>(SSL_REQUEST).unwrap();
+            stream.write_all(&ssl).unwrap();
+            assert_eq!(stream.read_u8().unwrap(), b'N');
+
+            let mut body = Vec::new();
+            body.write_i32::<BigEndian>(PROTOCOL_VERSION).unwrap();
+            for field in ["user", user, "database", "ferrodb", ""] {
+                put_cstr(&mut body, field);
+            }
+            stream
+                .write_i32::<BigEndian>(body.len() as i32 + 4)
+                .unwrap();
+            stream.write_all(&body).unwrap();
+            let mut client = Self { stream };
+            assert_eq!(client.read(), (b'R', 3i32.to_be_bytes().to_vec()));
+            let mut body = Vec::new();
+            put_cstr(&mut body, password);
+            send(&mut client.stream, b'p', &body).unwrap();
+            let replies = client.until_ready();
+            (client, replies)
+        }
+
+        fn query(&mut self, sql: &str) -> Vec<(u8, Vec<u8>)> {
+            let mut body = Vec::new();
+            put_cstr(&mut body, sql);
+            send(&mut self.stream, b'Q', &body).unwrap();
+            self.until_ready()
+        }
+
+        fn read(&mut self) -> (u8, Vec<u8>) {
+            read_message(&mut self.stream).unwrap().unwrap()
+        }
+
+        /// Messages up to and including ReadyForQuery, or an error that
+        /// ends the session.
+        fn until_ready(&mut self) -> Vec<(u8, Vec<u8>)> {
+            let mut messages = Vec::new();
+            loop {
+                let Some(message) = read_message(&mut self.stream).unwrap() else {
+                    return messages;
+                };
+                let tag = message.0;
+                messages.push(message);
+                if tag == b'Z' {
+                    return messages;
+                }
+            }
+        }
+    }
+
+    fn tags(messages: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        messages.iter().map(|(tag, _)| *tag).collect()
+    }
+
+    /// The values of a DataRow.
+    fn values(body: &[u8]) -> Vec<String> {
+        let mut body = body;
+        let count = body.read_i16::<BigEndian>().unwrap();
+        (0..count)
+            .map(|_| {
+                let len = body.read_i32::<BigEndian>().unwrap() as usize;
+                let (value, rest) = body.split_at(len);
+                body = rest;
+                String::from_utf8(value.to_vec()).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_postgres_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        config.storage.page_size = 128;
+        config.server.listen = "127.0.0.1:0".to_string();
+        config.server.password = Some("secret".to_string());
+        config.server.protocol = WireProtocol::Postgres;
+        let database = Database::with_config(&config).unwrap();
+        let server = Server::spawn(Arc::new(database), &config.server).unwrap();
+
+        let (_, replies) = PgClient::connect(&server, "alice", "wrong");
+        assert_eq!(tags(&replies), vec![b'E']);
+
+        let (mut client, replies) = PgClient::connect(&server, "alice", "secret");
+        assert_eq!(replies[0], (b'R', 0i32.to_be_bytes().to_vec()));
+        assert_eq!(replies.last().unwrap(), &(b'Z', b"I".to_vec()));
+
+        let replies = client.query("CREATE TABLE");
+        assert_eq!(tags(&replies), b"TDCZ");
+        let table = values(&replies[1].1).remove(0);
+
+        let replies = client.query(&format!(
+            "BEGIN; INSERT INTO {} VALUES ('one'), ('two')",
+            table
+        ));
+        assert_eq!(tags(&replies), b"CTDDCZ");
+        assert_eq!(replies.last().unwrap().1, b"T");
+        let first = values(&replies[2].1).remove(0);
+        let replies = client.query(&format!(
+            "UPDATE {table} SET data = 'uno' WHERE id = '{first}'; COMMIT"
+        ));
+        assert_eq!(tags(&replies), b"CCZ");
+
+        let replies = client.query(&format!("SELECT * FROM {}", table));
+        assert_eq!(tags(&replies), b"TDDCZ");
+        assert_eq!(
+            values(&replies[1].1),
+            vec![first.clone(), "uno".to_string()]
+        );
+        assert_eq!(replies[3].1, b"SELECT 2\0");
+
+        // An error stops the rest of the query
+        let replies = client.query("SELECT * FROM 99; COMMIT");
+        assert_eq!(tags(&replies), b"EZ");
+        assert_eq!(tags(&client.query("SELEKT")), b"EZ");
+        assert_eq!(tags(&client.query(";")), b"IZ");
+        assert_eq!(tags(&client.query("rollback")), b"EZ");
+
+        // A NULL has a length of -1, and an empty string of 0
+        let replies = client.query("SELECT nullif('a', 'a'), ''");
+        assert_eq!(tags(&replies), b"TDCZ");
+        assert_eq!(replies[1].1, [0, 2, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+
+        // Session state
+        let replies = client.query("SET search_path = 'public'; SHOW search_path; SHOW database");
+        assert_eq!(tags(&replies), b"CTDCTDCZ");
+        assert_eq!(values(&replies[2].1), vec!["public"]);
+        assert_eq!(values(&replies[5].1), vec!["ferrodb"]);
+        let replies = client.query(&format!(
+            "PREPARE put AS INSERT INTO {table} VALUES ($1); EXECUTE put ('three')"
+        ));
+        assert_eq!(tags(&replies), b"CTDCZ");
+        assert_eq!(replies[3].1, b"INSERT 0 1\0");
+        assert_eq!(tags(&client.query("EXECUTE put")), b"EZ");
+        assert_eq!(
+            tags(&client.query("DEALLOCATE put; EXECUTE put ('x')")),
+            b"CEZ"
+        );
+
+        // A cursor pages through rows a query at a time
+        let replies = client.query(&format!(
+            "BEGIN; DECLARE c CURSOR FOR SELECT * FROM {table}; FETCH 2 FROM c"
+        ));
+        assert_eq!(tags(&replies), b"CCTDDCZ");
+        assert_eq!(replies[5].1, b"FETCH 2\0");
+        let replies = client.query("FETCH 2 FROM c");
+        assert_eq!(tags(&replies), b"TDCZ");
+        assert_eq!(values(&replies[1].1)[1], "three");
+        assert_eq!(tags(&client.query("COMMIT; FETCH c")), b"CEZ");
+
+        client.query("BEGIN");
+        let sessions = server.sessions();
+        let session = sessions.iter().find(|info| info.in_transaction).unwrap();
+        assert_eq!(
+            (session.user.as_str(), session.database.as_str()),
+            ("alice", "ferrodb")
+        );
+        client.query("ROLLBACK");
+
+        // Once there are users, clients log in as one of them
+        let replies = client.query("CREATE USER alice WITH PASSWORD 'changed' SUPERUSER");
+        assert_eq!(replies[0], (b'C', b"CREATE ROLE\0".to_vec()));
+        assert_eq!(tags(&client.query("CREATE USER alice PASSWORD 'x'")), b"EZ");
+        client.query("CREATE USER bob PASSWORD 'pw'");
+        let (_, replies) = PgClient::connect(&server, "alice", "secret");
+        assert_eq!(tags(&replies), vec![b'E']);
+        let (_, replies) = PgClient::connect(&server, "alice", "changed");
+        assert_eq!(replies[0], (b'R', 0i32.to_be_bytes().to_vec()));
+
+        // Other users need privileges granted
+        let (mut bob, _) = PgClient::connect(&server, "bob", "pw");
+        let select = format!("SELECT * FROM {}", table);
+        let replies = bob.query(&select);
+        assert_eq!(tags(&replies), b"EZ");
+        assert!(replies[0].1.windows(5).any(|code| code == b"42501"));
+        assert_eq!(tags(&bob.query("GRANT ALL ON ALL TABLES TO bob")), b"EZ");
+        let replies = client.query(&format!("GRANT SELECT ON TABLE {} TO bob", table));
+        assert_eq!(replies[0], (b'C', b"GRANT\0".to_vec()));
+        assert_eq!(tags(&bob.query(&select)), b"TDDDCZ");
+        assert_eq!(
+            tags(&bob.query(&format!("DELETE FROM {table} WHERE id = '{first}'"))),
+            b"EZ"
+        );
+        assert_eq!(tags(&bob.query("CREATE TABLE")), b"EZ");
+    }
+}
@@ -0,0 +1,449 @@
+//! The wire protocol spoken between `Server` and `Client`.
+//!
+//! Every message is a tag byte, the length of its body as a big-endian
+//! u32, then the body. Within a body, integers are big-endian, strings and
+//! byte strings are a u32 length followed by that many bytes, and a row id
+//! is its table as a u32, page as a u64 and slot as a u32.
+//!
+//! | Tag | From   | Message   | Body                                  |
+//! |-----|--------|-----------|---------------------------------------|
+//! | `S` | client | Startup   | User name, password                   |
+//! | `Q` | client | Query     | Request code and its fields           |
+//! | `L` | client | Sql       | Result format code, SQL               |
+//! | `X` | client | Terminate | Empty                                 |
+//! | `R` | server | Ready     | Empty                                 |
+//! | `D` | server | Row       | Row id, contents                      |
+//! | `O` | server | Output    | A statement's result, encoded         |
+//! | `C` | server | Complete  | Outcome code and its fields           |
+//! | `E` | server | Error     | Error code, message                   |
+//!
+//! A client opens with Startup, answered with Ready, or with Error before
+//! the server closes the connection if it is refused. Each Query is then
+//! answered with a Row for each row it read, followed by Complete, or by
+//! Error if it failed; the session carries on after a failed query.
+//! Sql runs the statements in its SQL as the `ferrodb` shell does, with
+//! the session's variables and prepared statements, and is answered with
+//! an Output for each statement's result in the format asked for, then
+//! Complete with Done, or Error at the first statement to fail.
+//! Terminate, or closing the connection, ends the session and rolls back
+//! any transaction left open. A message the server can't decode is
+//! answered with a protocol Error and the connection is closed.
+//!
+//! | Code | Request      | Fields           | Complete with |
+//! |------|--------------|------------------|---------------|
+//! | `b`  | Begin        |                  | Done          |
+//! | `c`  | Commit       |                  | Done          |
+//! | `r`  | Rollback     |                  | Done          |
+//! | `t`  | Create table |                  | Table         |
+//! | `i`  | Insert       | Table, contents  | Inserted      |
+//! | `g`  | Get          | Row id           | Done          |
+//! | `u`  | Update       | Row id, contents | Done          |
+//! | `d`  | Delete       | Row id           | Deleted       |
+//! | `s`  | Scan         | Table            | Done          |
+//!
+//! | Code | Outcome  | Fields                     |
+//! |------|----------|----------------------------|
+//! | 0    | Done     |                            |
+//! | 1    | Table    | Table as a u32             |
+//! | 2    | Inserted | Row id                     |
+//! | 3    | Deleted  | 1 if the row existed, or 0 |
+//!
+//! Results are formatted as `ResultFormat`s are: 0 aligned, 1 unaligned,
+//! 2 CSV, 3 JSON and 4 MessagePack.
+
+use crate::database::{Row, RowId, TableId};
+use crate::encoding::ResultFormat;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read, Write};
+
+/// The largest message body accepted, so a bad length can't exhaust memory.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+const STARTUP: u8 = b'S';
+const QUERY: u8 = b'Q';
+const SQL: u8 = b'L';
+const TERMINATE: u8 = b'X';
+const READY: u8 = b'R';
+const ROW: u8 = b'D';
+const OUTPUT: u8 = b'O';
+const COMPLETE: u8 = b'C';
+const ERROR: u8 = b'E';
+
+/// An operation run by a query, as on a `Connection`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Begin,
+    Commit,
+    Rollback,
+    CreateTable,
+    Insert { table: TableId, data: Vec<u8> },
+    Get(RowId),
+    Update { row: RowId, data: Vec<u8> },
+    Delete(RowId),
+    Scan(TableId),
+}
+
+/// What a query did, besides the rows it read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    Table(TableId),
+    Inserted(RowId),
+    Deleted(bool),
+}
+
+/// Why a query or session failed. Each keeps its number, which is what
+/// goes over the wire, from one release to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// A message was malformed or out of turn
+    Protocol = 1,
+    /// Startup was refused
+    Authentication = 2,
+    NoSuchTable = 3,
+    NoSuchRow = 4,
+    RowTooLarge = 5,
+    /// Begin inside a transaction, or commit or rollback outside one
+    TransactionState = 6,
+    /// A lock couldn't be taken, or the transaction was rolled back for
+    /// being idle
+    Conflict = 7,
+    /// Anything else, such as a storage failure
+    Internal = 8,
+    /// The session's user lacks the privilege the query needs
+    PermissionDenied = 9,
+    /// A statement doesn't parse, or can't run as written
+    Statement = 10,
+    /// A write would give a row the key of another in a unique index
+    UniqueViolation = 11,
+    /// A configured limit was reached, such as the sessions a user may
+    /// have open or the rows a statement may send them
+    ResourceLimit = 12,
+}
+
+impl ErrorCode {
+    /// The SQLSTATE code of the class of errors the code stands for.
+    pub fn sqlstate(self) -> &'static str {
+        match self {
+            Self::Protocol => "08P01",
+            Self::Authentication => "28P01",
+            Self::NoSuchTable => "42P01",
+            Self::NoSuchRow => "P0002",
+            Self::RowTooLarge => "54000",
+            Self::TransactionState => "25000",
+            Self::Conflict => "55P03",
+            Self::Internal => "XX000",
+            Self::PermissionDenied => "42501",
+            Self::Statement => "42000",
+            Self::UniqueViolation => "23505",
+            Self::ResourceLimit => "53000",
+        }
+    }
+
+    fn from_u16(code: u16) -> Option<Self> {
+        Some(match code {
+            1 => Self::Protocol,
+            2 => Self::Authentication,
+            3 => Self::NoSuchTable,
+            4 => Self::NoSuchRow,
+            5 => Self::RowTooLarge,
+            6 => Self::TransactionState,
+            7 => Self::Conflict,
+            8 => Self::Internal,
+            9 => Self::PermissionDenied,
+            10 => Self::Statement,
+            11 => Self::UniqueViolation,
+            12 => Self::ResourceLimit,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum ClientMessage {
+    Startup { user: String, password: String },
+    Query(Request),
+    Sql { sql: String, format: ResultFormat },
+    Terminate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum ServerMessage {
+    Ready,
+    Row(Row),
+    Output(Vec<u8>),
+    Complete(Outcome),
+    Error { code: ErrorCode, message: String },
+}
+
+impl ClientMessage {
+    pub(super) fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut body = Vec::new();
+        let tag = match self {
+            Self::Startup { user, password } => {
+                put_bytes(&mut body, user.as_bytes());
+                put_bytes(&mut body, password.as_bytes());
+                STARTUP
+            }
+            Self::Query(request) => {
+                request.encode(&mut body);
+                QUERY
+            }
+            Self::Sql { sql, format } => {
+                body.push(*format as u8);
+                put_bytes(&mut body, sql.as_bytes());
+                SQL
+            }
+            Self::Terminate => TERMINATE,
+        };
+        write_message(out, tag, &body)
+    }
+
+    /// The next message, or `None` if the client closed the connection.
+    pub(super) fn read_from(input: &mut impl Read) -> io::Result<Option<Self>> {
+        let Some((tag, body)) = read_message(input)? else {
+            return Ok(None);
+        };
+        let mut body = body.as_slice();
+        let message = match tag {
+            STARTUP => Self::Startup {
+                user: get_string(&mut body)?,
+                password: get_string(&mut body)?,
+            },
+            QUERY => Self::Query(Request::decode(&mut body)?),
+            SQL => {
+                let code = body.read_u8()?;
+                Self::Sql {
+                    format: *ResultFormat::ALL
+                        .get(usize::from(code))
+                        .ok_or_else(|| invalid(format!("unknown result format {}", code)))?,
+                    sql: get_string(&mut body)?,
+                }
+            }
+            TERMINATE => Self::Terminate,
+            tag => return Err(invalid(format!("unknown message {:?}", tag as char))),
+        };
+        finish(body)?;
+        Ok(Some(message))
+    }
+}
+
+impl ServerMessage {
+    pub(super) fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut body = Vec::new();
+        let tag = match self {
+            Self::Ready => READY,
+            Self::Row(row) => {
+                put_row_id(&mut body, row.id);
+                put_bytes(&mut body, &row.data);
+                ROW
+            }
+            Self::Output(output) => {
+                put_bytes(&mut body, output);
+                OUTPUT
+            }
+            Self::Complete(outcome) => {
+                outcome.encode(&mut body);
+                COMPLETE
+            }
+            Self::Error { code, message } => {
+                body.write_u16::<BigEndian>(*code as u16).unwrap();
+                put_bytes(&mut body, message.as_bytes());
+                ERROR
+            }
+        };
+        write_message(out, tag, &body)
+    }
+
+    /// The next message; the server closing the connection is an error.
+    pub(super) fn read_from(input: &mut impl Read) -> io::Result<Self> {
+        let (tag, body) = read_message(input)?.ok_or(io::ErrorKind::UnexpectedEof)?;
+        let mut body = body.as_slice();
+        let message = match tag {
+            READY => Self::Ready,
+            ROW => Self::Row(Row {
+                id: get_row_id(&mut body)?,
+                data: get_bytes(&mut body)?,
+            }),
+            OUTPUT => Self::Output(get_bytes(&mut body)?),
+            COMPLETE => Self::Complete(Outcome::decode(&mut body)?),
+            ERROR => {
+                let code = body.read_u16::<BigEndian>()?;
+                Self::Error {
+                    code: ErrorCode::from_u16(code)
+                        .ok_or_else(|| invalid(format!("unknown error code {}", code)))?,
+                    message: get_string(&mut body)?,
+                }
+            }
+            tag => return Err(invalid(format!("unknown message {:?}", tag as char))),
+        };
+        finish(body)?;
+        Ok(message)
+    }
+}
+
+impl Request {
+    /// What the request does, as the log names it.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Begin => "begin",
+            Self::Commit => "commit",
+            Self::Rollback => "rollback",
+            Self::CreateTable => "create table",
+            Self::Insert { .. } => "insert",
+            Self::Get(_) => "get",
+            Self::Update { .. } => "update",
+            Self::Delete(_) => "delete",
+            Self::Scan(_) => "scan",
+        }
+    }
+
+    fn encode(&self, body: &mut Vec<u8>) {
+        match self {
+            Self::Begin => body.push(b'b'),
+            Self::Commit => body.push(b'c'),
+            Self::Rollback => body.push(b'r'),
+            Self::CreateTable => body.push(b't'),
+            Self::Insert { table, data } => {
+                body.push(b'i');
+                body.write_u32::<BigEndian>(table.0).unwrap();
+                put_bytes(body, data);
+            }
+            Self::Get(row) => {
+                body.push(b'g');
+                put_row_id(body, *row);
+            }
+            Self::Update { row, data } => {
+                body.push(b'u');
+                put_row_id(body, *row);
+                put_bytes(body, data);
+            }
+            Self::Delete(row) => {
+                body.push(b'd');
+                put_row_id(body, *row);
+            }
+            Self::Scan(table) => {
+                body.push(b's');
+                body.write_u32::<BigEndian>(table.0).unwrap();
+            }
+        }
+    }
+
+    fn decode(body: &mut &[u8]) -> io::Result<Self> {
+        Ok(match body.read_u8()? {
+            b'b' => Self::Begin,
+            b'c' => Self::Commit,
+            b'r' => Self::Rollback,
+            b't' => Self::CreateTable,
+            b'i' => Self::Insert {
+                table: TableId(body.read_u32::<BigEndian>()?),
+                data: get_bytes(body)?,
+            },
+            b'g' => Self::Get(get_row_id(body)?),
+            b'u' => Self::Update {
+                row: get_row_id(body)?,
+                data: get_bytes(body)?,
+            },
+            b'd' => Self::Delete(get_row_id(body)?),
+            b's' => Self::Scan(TableId(body.read_u32::<BigEndian>()?)),
+            code => return Err(invalid(format!("unknown request {:?}", code as char))),
+        })
+    }
+}
+
+impl Outcome {
+    fn encode(&self, body: &mut Vec<u8>) {
+        match self {
+            Self::Done => body.push(0),
+            Self::Table(table) => {
+                body.push(1);
+                body.write_u32::<BigEndian>(table.0).unwrap();
+            }
+            Self::Inserted(row) => {
+                body.push(2);
+                put_row_id(body, *row);
+            }
+            Self::Deleted(existed) => {
+                body.push(3);
+                body.push(*existed as u8);
+            }
+        }
+    }
+
+    fn decode(body: &mut &[u8]) -> io::Result<Self> {
+        Ok(match body.read_u8()? {
+            0 => Self::Done,
+            1 => Self::Table(TableId(body.read_u32::<BigEndian>()?)),
+            2 => Self::Inserted(get_row_id(body)?),
+            3 => Self::Deleted(body.read_u8()? != 0),
+            code => return Err(invalid(format!("unknown outcome {}", code))),
+        })
+    }
+}
+
+fn write_message(out: &mut impl Write, tag: u8, body: &[u8]) -> io::Result<()> {
+    out.write_u8(tag)?;
+    out.write_u32::<BigEndian>(body.len() as u32)?;
+    out.write_all(body)
+}
+
+/// A message's tag and body, or `None` at the end of the stream.
+fn read_message(input: &mut impl Read) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let tag = match input.read_u8() {
+        Ok(tag) => tag,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let len = input.read_u32::<BigEndian>()? as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(invalid(format!("message of {} bytes is too large", len)));
+    }
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    Ok(Some((tag, body)))
+}
+
+fn put_bytes(body: &mut Vec<u8>, bytes: &[u8]) {
+    body.write_u32::<BigEndian>(bytes.len() as u32).unwrap();
+    body.extend_from_slice(bytes);
+}
+
+fn get_bytes(body: &mut &[u8]) -> io::Result<Vec<u8>> {
+    let len = body.read_u32::<BigEndian>()? as usize;
+    if len > body.len() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let (bytes, rest) = body.split_at(len);
+    *body = rest;
+    Ok(bytes.to_vec())
+}
+
+fn get_string(body: &mut &[u8]) -> io::Result<String> {
+    String::from_utf8(get_bytes(body)?).map_err(|e| invalid(e.to_string()))
+}
+
+fn put_row_id(body: &mut Vec<u8>, row: RowId) {
+    body.write_u32::<BigEndian>(row.table.0).unwrap();
+    body.write_u64::<BigEndian>(row.page_no).unwrap();
+    body.write_u32::<BigEndian>(row.slot).unwrap();
+}
+
+fn get_row_id(body: &mut &[u8]) -> io::Result<RowId> {
+    Ok(RowId {
+        table: TableId(body.read_u32::<BigEndian>()?),
+        page_no: body.read_u64::<BigEndian>()?,
+        slot: body.read_u32::<BigEndian>()?,
+    })
+}
+
+/// Fail if a message's body has bytes left over.
+fn finish(body: &[u8]) -> io::Result<()> {
+    if !body.is_empty() {
+        return Err(invalid(format!("{} unexpected bytes", body.len())));
+    }
+    Ok(())
+}
+
+fn invalid(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
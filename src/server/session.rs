@@ -0,0 +1,222 @@
+use crate::database::Connection;
+use crate::logging::log;
+use crate::sql::SqlSession;
+use crate::storage::panic_message;
+use std::collections::HashMap;
+use std::net::{Shutdown, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// What a client is told when its session fails.
+pub(super) const FAILED: &str =
+    "internal error; the transaction was rolled back and the session ended";
+
+/// What other threads can see of a session, from `Server::sessions`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    pub id: u64,
+    /// Empty until the client has started the session
+    pub user: String,
+    /// The database the client asked for; empty if it didn't name one
+    pub database: String,
+    pub started: SystemTime,
+    pub in_transaction: bool,
+}
+
+/// The sessions running on a server, shared by its workers.
+#[derive(Default)]
+pub(super) struct Sessions {
+    inner: Mutex<Registry>,
+}
+
+#[derive(Default)]
+struct Registry {
+    sessions: HashMap<u64, (SessionInfo, TcpStream)>,
+    /// Set once the server is stopping; no sessions are added after
+    closed: bool,
+}
+
+impl Sessions {
+    /// Track a new session on `stream`, unless the server is stopping.
+    pub(super) fn register(&self, id: u64, stream: &TcpStream) -> bool {
+        let mut registry = self.inner.lock().unwrap();
+        if registry.closed {
+            return false;
+        }
+        let Ok(stream) = stream.try_clone() else {
+            return false;
+        };
+        let info = SessionInfo {
+            id,
+            user: String::new(),
+            database: String::new(),
+            started: SystemTime::now(),
+            in_transaction: false,
+        };
+        registry.sessions.insert(id, (info, stream));
+        true
+    }
+
+    /// Count session `id` as one of `user`'s, unless they already have
+    /// `max` others open.
+    pub(super) fn admit(&self, id: u64, user: &str, max: Option<u32>) -> bool {
+        let mut registry = self.inner.lock().unwrap();
+        let open = registry
+            .sessions
+            .values()
+            .filter(|(info, _)| info.id != id && info.user == user)
+            .count();
+        if max.is_some_and(|max| open >= max as usize) {
+            return false;
+        }
+        if let Some((info, _)) = registry.sessions.get_mut(&id) {
+            info.user = user.to_string();
+        }
+        true
+    }
+
+    pub(super) fn remove(&self, id: u64) {
+        self.inner.lock().unwrap().sessions.remove(&id);
+    }
+
+    pub(super) fn update(&self, id: u64, update: impl FnOnce(&mut SessionInfo)) {
+        if let Some((info, _)) = self.inner.lock().unwrap().sessions.get_mut(&id) {
+            update(info);
+        }
+    }
+
+    pub(super) fn list(&self) -> Vec<SessionInfo> {
+        let registry = self.inner.lock().unwrap();
+        let mut sessions: Vec<_> = registry
+            .sessions
+            .values()
+            .map(|(info, _)| info.clone())
+            .collect();
+        sessions.sort_by_key(|info| info.id);
+        sessions
+    }
+
+    /// Refuse new sessions, leaving those running alone.
+    pub(super) fn refuse_new(&self) {
+        self.inner.lock().unwrap().closed = true;
+    }
+
+    /// Close every session and refuse new ones.
+    pub(super) fn close_all(&self) {
+        let mut registry = self.inner.lock().unwrap();
+        registry.closed = true;
+        for (_, stream) in registry.sessions.values() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// One client's session: its connection to the database, with the
+/// session variables and prepared statements of `SqlSession`. It belongs
+/// to the worker serving the client, and keeps what other threads see of
+/// it in `Sessions` up to date.
+pub(super) struct Session<'a> {
+    id: u64,
+    sessions: &'a Sessions,
+    pub(super) sql: SqlSession<'a>,
+}
+
+impl<'a> Session<'a> {
+    /// Start session `id` for `user` once the client is authenticated.
+    pub(super) fn start(
+        id: u64,
+        sessions: &'a Sessions,
+        connection: Connection<'a>,
+        user: &str,
+        database: &str,
+    ) -> Self {
+        sessions.update(id, |info| {
+            info.user = user.to_string();
+            info.database = database.to_string();
+        });
+        Self {
+            id,
+            sessions,
+            sql: SqlSession::for_user(connection, user),
+        }
+    }
+
+    /// The user the session was started for, whose privileges it has.
+    pub(super) fn user(&self) -> &str {
+        self.sql.user().unwrap_or_default()
+    }
+
+    /// Run `query`, catching a panic part way through. The open
+    /// transaction is then rolled back through the log and `None`
+    /// returned, and the session should end with `FAILED`: its worker
+    /// carries on with the next, and the pages the query held with it.
+    pub(super) fn guarded<T>(&mut self, query: impl FnOnce(&mut Self) -> T) -> Option<T> {
+        let panic = match panic::catch_unwind(AssertUnwindSafe(|| query(self))) {
+            Ok(result) => return Some(result),
+            Err(panic) => panic,
+        };
+        let message = panic_message(&*panic);
+        log!(Error, "session failed", id = self.id, panic = message);
+        let connection = self.sql.connection_mut();
+        if connection.in_transaction() {
+            if let Err(e) = connection.rollback() {
+                log!(Error, "could not roll back", id = self.id, error = e);
+            }
+        }
+        self.sync();
+        None
+    }
+
+    /// Publish whether the connection is in a transaction, after it may
+    /// have changed.
+    pub(super) fn sync(&self) {
+        let in_transaction = self.sql.connection().in_transaction();
+        self.sessions
+            .update(self.id, |info| info.in_transaction = in_transaction);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_config;
+    use crate::database::Database;
+    use crate::storage::{FileId, PageId};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_guarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        config.storage.page_size = 128;
+        let database = Database::with_config(&config).unwrap();
+        let table = database.create_table().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let sessions = Sessions::default();
+        assert!(sessions.register(1, &stream));
+        let mut session = Session::start(1, &sessions, database.connect(), "alice", "");
+
+        assert_eq!(session.guarded(|_| 7), Some(7));
+        let failed = session.guarded(|session| {
+            let connection = session.sql.connection_mut();
+            connection.begin().unwrap();
+            let row = connection.insert(table, b"lost").unwrap();
+            session.sync();
+            // Panic holding the page, poisoning its lock
+            let page_id = PageId::new(FileId(table.0), row.page_no);
+            let page = database.pages().get_page(page_id).unwrap();
+            let _locked = page.page_mut();
+            panic!("bad query");
+        });
+        assert_eq!(failed, None::<()>);
+        assert!(!sessions.list()[0].in_transaction);
+
+        // The insert is undone, and the page is still usable
+        let mut connection = database.connect();
+        assert!(connection.scan(table).unwrap().is_empty());
+        connection.insert(table, b"kept").unwrap();
+        assert_eq!(connection.scan(table).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,345 @@
+//! Temporary files for sorts and hash joins with more rows than
+//! `storage.spill.work_mem` holds, which write them out in parts and read
+//! them back a part at a time.
+//!
+//! The files are kept in `storage.spill.temp_dir` only while a statement
+//! uses them, so any there as the database opens were left by a process
+//! that ended first, and are removed. Their sizes count against
+//! `temp_limit` until they're removed.
+
+use crate::config::SpillConfig;
+use crate::database::{DatabaseError, Row, RowId, TableId};
+use crate::logging::log;
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::Arc;
+
+/// What the files' names begin with.
+const PREFIX: &str = "spill-";
+
+/// A row with the key it's sorted or joined by.
+pub(crate) type Keyed = (Option<Vec<u8>>, Row);
+
+/// The bytes `row` and its `key` take up in a temporary file.
+pub(crate) fn size(key: Option<&[u8]>, row: &Row) -> u64 {
+    let key = key.map_or(0, <[u8]>::len);
+    (4 + key + 4 + 8 + 4 + 4 + row.data.len()) as u64
+}
+
+/// The directory temporary files are made in, and the bytes they take up.
+pub(crate) struct TempSpace {
+    dir: PathBuf,
+    work_mem: u64,
+    limit: Option<u64>,
+    used: Arc<AtomicU64>,
+    next: AtomicU64,
+}
+
+impl TempSpace {
+    /// Open the directory `config` names, or `temp` in `db_path`, creating
+    /// it if needed, and remove the files left in it.
+    pub(crate) fn open(config: &SpillConfig, db_path: &str) -> io::Result<Self> {
+        let dir = match &config.temp_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => Path::new(db_path).join("temp"),
+        };
+        fs::create_dir_all(&dir)?;
+        let mut removed = 0;
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with(PREFIX) {
+                fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            log!(
+                Info,
+                "left temporary files removed",
+                files = removed,
+                dir = dir.display(),
+            );
+        }
+        Ok(Self {
+            dir,
+            work_mem: config.work_mem,
+            limit: config.temp_limit,
+            used: Arc::default(),
+            next: AtomicU64::new(0),
+        })
+    }
+
+    /// The bytes of rows to hold before writing them out.
+    pub(crate) fn work_mem(&self) -> u64 {
+        self.work_mem
+    }
+
+    /// The bytes the files there are take up.
+    #[cfg(test)]
+    pub(crate) fn used(&self) -> u64 {
+        self.used.load(atomic::Ordering::SeqCst)
+    }
+
+    /// A new, empty file, removed once dropped.
+    pub(crate) fn create(&self) -> Result<TempFile, DatabaseError> {
+        let n = self.next.fetch_add(1, atomic::Ordering::Relaxed);
+        let path = self.dir.join(format!("{}{}", PREFIX, n));
+        let file = File::create(&path).map_err(DatabaseError::Spill)?;
+        Ok(TempFile {
+            path,
+            writer: BufWriter::new(file),
+            written: 0,
+            used: self.used.clone(),
+            limit: self.limit,
+        })
+    }
+}
+
+/// A temporary file of rows and their keys. Each is a key's length, or
+/// `u32::MAX` for none, and bytes, the row's table, page and slot, and
+/// its data's length and bytes.
+pub(crate) struct TempFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    written: u64,
+    used: Arc<AtomicU64>,
+    limit: Option<u64>,
+}
+
+impl TempFile {
+    /// Append `row` and its `key`, unless the files would take up more
+    /// than the limit.
+    pub(crate) fn write(&mut self, key: Option<&[u8]>, row: &Row) -> Result<(), DatabaseError> {
+        let size = size(key, row);
+        // Counted even if refused, as the file's size is given back
+        self.written += size;
+        let used = self.used.fetch_add(size, atomic::Ordering::SeqCst) + size;
+        if let Some(limit) = self.limit.filter(|&limit| used > limit) {
+            return Err(DatabaseError::TempSpaceFull(limit));
+        }
+        let mut record = Vec::with_capacity(size as usize);
+        match key {
+            Some(key) => {
+                record.extend((key.len() as u32).to_le_bytes());
+                record.extend(key);
+            }
+            None => record.extend(u32::MAX.to_le_bytes()),
+        }
+        record.extend(row.id.table.0.to_le_bytes());
+        record.extend(row.id.page_no.to_le_bytes());
+        record.extend(row.id.slot.to_le_bytes());
+        record.extend((row.data.len() as u32).to_le_bytes());
+        record.extend(&row.data);
+        self.writer.write_all(&record).map_err(DatabaseError::Spill)
+    }
+
+    /// The rows written so far, in the order they were.
+    pub(crate) fn read(&mut self) -> Result<TempRows, DatabaseError> {
+        self.writer.flush().map_err(DatabaseError::Spill)?;
+        let file = File::open(&self.path).map_err(DatabaseError::Spill)?;
+        Ok(TempRows {
+            reader: BufReader::new(file),
+        })
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        self.used.fetch_sub(self.written, atomic::Ordering::SeqCst);
+    }
+}
+
+/// The rows of a temporary file, read back.
+pub(crate) struct TempRows {
+    reader: BufReader<File>,
+}
+
+impl TempRows {
+    fn record(&mut self) -> io::Result<Option<Keyed>> {
+        let mut u32_bytes = [0; 4];
+        match self.reader.read_exact(&mut u32_bytes) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let mut bytes = |len: usize| -> io::Result<Vec<u8>> {
+            let mut bytes = vec![0; len];
+            self.reader.read_exact(&mut bytes)?;
+            Ok(bytes)
+        };
+        let key = match u32::from_le_bytes(u32_bytes) {
+            u32::MAX => None,
+            len => Some(bytes(len as usize)?),
+        };
+        let id = bytes(16)?;
+        let data_len = u32::from_le_bytes(bytes(4)?.try_into().unwrap());
+        let data = bytes(data_len as usize)?;
+        let id = RowId {
+            table: TableId(u32::from_le_bytes(id[0..4].try_into().unwrap())),
+            page_no: u64::from_le_bytes(id[4..12].try_into().unwrap()),
+            slot: u32::from_le_bytes(id[12..16].try_into().unwrap()),
+        };
+        Ok(Some((key, Row { id, data })))
+    }
+}
+
+impl Iterator for TempRows {
+    type Item = Result<Keyed, DatabaseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.record().map_err(DatabaseError::Spill).transpose()
+    }
+}
+
+/// `rows` sorted by `cmp`, keeping equal rows in the order given. With
+/// more of them than `space` holds, they're sorted a part at a time, each
+/// part written to a file of its own, and the parts merged.
+pub(crate) fn sort(
+    space: Option<&TempSpace>,
+    mut rows: Vec<Keyed>,
+    cmp: impl Fn(&Keyed, &Keyed) -> Ordering,
+) -> Result<Vec<Keyed>, DatabaseError> {
+    let total: u64 = rows
+        .iter()
+        .map(|(key, row)| size(key.as_deref(), row))
+        .sum();
+    let Some(space) = space.filter(|space| total > space.work_mem) else {
+        rows.sort_by(&cmp);
+        return Ok(rows);
+    };
+    let mut runs = Vec::new();
+    let mut run = Vec::new();
+    let mut held = 0;
+    let count = rows.len();
+    for (n, row) in rows.into_iter().enumerate() {
+        held += size(row.0.as_deref(), &row.1);
+        run.push(row);
+        if held >= space.work_mem || n + 1 == count {
+            run.sort_by(&cmp);
+            let mut file = space.create()?;
+            for (key, row) in run.drain(..) {
+                file.write(key.as_deref(), &row)?;
+            }
+            runs.push(file);
+            held = 0;
+        }
+    }
+    log!(Debug, "sort spilled", rows = count, runs = runs.len());
+
+    let mut readers = runs
+        .iter_mut()
+        .map(TempFile::read)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut heads = readers
+        .iter_mut()
+        .map(|reader| reader.next().transpose())
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut sorted = Vec::with_capacity(count);
+    loop {
+        // The earliest run's row of those that are equal
+        let mut first: Option<usize> = None;
+        for (i, head) in heads.iter().enumerate() {
+            let Some(head) = head else { continue };
+            let earlier = match first {
+                Some(first) => cmp(head, heads[first].as_ref().unwrap()) == Ordering::Less,
+                None => true,
+            };
+            if earlier {
+                first = Some(i);
+            }
+        }
+        let Some(i) = first else {
+            return Ok(sorted);
+        };
+        let next = readers[i].next().transpose()?;
+        sorted.extend(std::mem::replace(&mut heads[i], next));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_config;
+    use crate::database::Database;
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_spill() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp = dir.path().join("temp");
+        fs::create_dir_all(&temp).unwrap();
+        fs::write(temp.join("spill-7"), b"left").unwrap();
+        fs::write(temp.join("kept"), b"not ours").unwrap();
+        let mut config = test_config(dir.path());
+        config.storage.spill = Some(SpillConfig {
+            temp_dir: None,
+            work_mem: 256,
+            temp_limit: Some(64 * 1024),
+        });
+        let database = Database::with_config(&config).unwrap();
+        assert!(!temp.join("spill-7").exists());
+        assert!(temp.join("kept").exists());
+
+        let table = database.create_table().unwrap();
+        let other = database.create_table().unwrap();
+        let mut session = SqlSession::new(database.connect());
+        for n in (0..100).rev() {
+            let row = format!(r#"{{"n": {}, "m": {}}}"#, n, n % 10);
+            session.execute(&format!("INSERT INTO {table} VALUES ('{row}')"));
+        }
+        for m in 0..10 {
+            let row = format!(r#"{{"m": {m}}}"#);
+            session.execute(&format!("INSERT INTO {other} VALUES ('{row}')"));
+        }
+        let mut query = |sql: &str| session.execute(sql).remove(0);
+
+        // Sorted in runs, and merged as if sorted at once
+        let sorted = query(&format!("SELECT * FROM {table} ORDER BY n DESC")).unwrap();
+        let ns: Vec<_> = sorted
+            .rows
+            .iter()
+            .map(|row| {
+                crate::mapping::column_value(row[1].as_ref().unwrap().as_bytes(), "n").unwrap()
+            })
+            .collect();
+        let expected: Vec<_> = (0..100).rev().map(|n| n.to_string()).collect();
+        assert_eq!(ns, expected);
+        let joined = query(&format!("SELECT * FROM {table} JOIN {other} ON m = m")).unwrap();
+        assert_eq!(joined.rows.len(), 100);
+        assert!(joined.rows.iter().all(|row| {
+            let m = |data: &Option<String>| {
+                crate::mapping::column_value(data.as_ref().unwrap().as_bytes(), "m")
+            };
+            m(&row[1]) == m(&row[3])
+        }));
+        let space = database.temp_space().unwrap();
+        assert_eq!(space.used(), 0);
+        assert_eq!(fs::read_dir(&temp).unwrap().count(), 1);
+
+        // No more than the limit is written, and what was is given back
+        let mut file = space.create().unwrap();
+        let row = Row {
+            id: RowId {
+                table: TableId(0),
+                page_no: 0,
+                slot: 0,
+            },
+            data: vec![0; 40 * 1024],
+        };
+        file.write(None, &row).unwrap();
+        let mut second = space.create().unwrap();
+        assert!(matches!(
+            second.write(Some(b"key"), &row),
+            Err(DatabaseError::TempSpaceFull(65536))
+        ));
+        let read: Vec<_> = file.read().unwrap().map(Result::unwrap).collect();
+        assert_eq!(read, [(None, row)]);
+        drop((file, second));
+        assert_eq!(space.used(), 0);
+        let again = query(&format!("SELECT * FROM {table} ORDER BY n")).unwrap();
+        assert_eq!(again.rows.len(), 100);
+    }
+}
@@ -0,0 +1,2484 @@
+//! Running the statements of `syntax::Statement` over a connection, as the
+//! Postgres server mode and the `ferrodb` shell do.
+
+use crate::audit::AuditEvent;
+use crate::auth::Privilege;
+use crate::builtin::{self, Context};
+use crate::config::{Compression, ConfigError, UserLimits};
+use crate::copy::{copy_from, copy_to, CopyError};
+use crate::cursor::Cursor;
+use crate::database::{Connection, Database, DatabaseError, Row, RowId, TableId};
+use crate::encoding::ResultFormat;
+use crate::error::SourceSpan;
+use crate::logging::{span, Timestamp};
+use crate::partition::PartitionScheme;
+use crate::plan::{Join, Plan, Read, SemiJoin, Sort};
+use crate::sqlite::SqliteImportError;
+use crate::storage::{AsOf, LockMode, Lsn, TransactionError};
+use crate::syntax::{
+    parse_script, parse_spanned, redact_passwords, AsOfPoint, ExplainFormat, Key, Order, Statement,
+    SyntaxError, Value,
+};
+use crate::table_options::TableOptions;
+use crate::trigger::{Event, Timing, Trigger, MAX_DEPTH};
+use crate::variables::{parse_bool, parse_duration, variable, VARIABLES};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The savepoint a batch run in the session's transaction is undone to,
+/// named so no statement can name it.
+const BATCH_SAVEPOINT: &str = "batch start";
+
+/// A statement that failed, with the SQLSTATE code Postgres would report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlError {
+    code: &'static str,
+    message: String,
+    span: Option<SourceSpan>,
+}
+
+impl SqlError {
+    /// The five-character SQLSTATE code.
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Where in its text a statement that didn't parse went wrong.
+    pub fn span(&self) -> Option<SourceSpan> {
+        self.span
+    }
+
+    pub(crate) fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            span: None,
+        }
+    }
+}
+
+impl Display for SqlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for SqlError {}
+
+impl From<CopyError> for SqlError {
+    fn from(error: CopyError) -> Self {
+        let code = match error {
+            CopyError::Open { .. }
+            | CopyError::Create { .. }
+            | CopyError::Read(_)
+            | CopyError::Write(_) => "58030",
+            CopyError::BadRecord { .. } => "22P04",
+            #[cfg(feature = "parquet")]
+            CopyError::BadRow { .. } | CopyError::BadFile(_) => "22P04",
+            CopyError::Unsupported(_) => "0A000",
+            CopyError::Database(error) => return error.into(),
+        };
+        Self::new(code, error.to_string())
+    }
+}
+
+impl From<SqliteImportError> for SqlError {
+    fn from(error: SqliteImportError) -> Self {
+        Self::new(import_sqlstate(&error), error.to_string())
+    }
+}
+
+impl From<DatabaseError> for SqlError {
+    fn from(error: DatabaseError) -> Self {
+        Self::new(sqlstate(&error), error.to_string())
+    }
+}
+
+impl From<SyntaxError> for SqlError {
+    fn from(error: SyntaxError) -> Self {
+        Self {
+            code: "42601",
+            message: error.error.to_string(),
+            span: error.span,
+        }
+    }
+}
+
+/// The SQLSTATE code for an import that failed.
+pub(crate) fn import_sqlstate(error: &SqliteImportError) -> &'static str {
+    match error {
+        SqliteImportError::Read(_) => "58030",
+        SqliteImportError::Invalid { .. } => "42601",
+        SqliteImportError::Database(error) => sqlstate(error),
+    }
+}
+
+/// The SQLSTATE code for a setting that couldn't be read or changed.
+pub(crate) fn config_sqlstate(error: &ConfigError) -> &'static str {
+    match error {
+        ConfigError::NotReloadable(_) => "55P02",
+        ConfigError::UnknownSetting(_) => "42704",
+        _ => "22023",
+    }
+}
+
+/// The SQLSTATE code for a transaction that failed.
+pub(crate) fn transaction_sqlstate(error: &TransactionError) -> &'static str {
+    match error {
+        TransactionError::LockError(_) | TransactionError::IdleTimeout(_) => "55P03",
+        TransactionError::NoSuchSavepoint(_) => "3B001",
+        TransactionError::GidInUse(_) => "42710",
+        TransactionError::NoSuchPrepared(_) => "42704",
+        _ => "XX000",
+    }
+}
+
+/// The SQLSTATE code Postgres would report for `error`.
+pub(crate) fn sqlstate(error: &DatabaseError) -> &'static str {
+    match error {
+        DatabaseError::NoSuchTable(_) => "42P01",
+        DatabaseError::NoSuchRow(_) => "P0002",
+        DatabaseError::InvalidRowId(_) => "22P02",
+        DatabaseError::InvalidFillFactor(_) | DatabaseError::InvalidPartitioning(_) => "22023",
+        DatabaseError::PartitionKeyChanged(_) => "23514",
+        DatabaseError::UniqueViolation { .. } => "23505",
+        DatabaseError::MissingKey(_) => "23502",
+        DatabaseError::PrimaryKeyExists(_) => "42P16",
+        DatabaseError::RowTooLarge { .. } => "54000",
+        DatabaseError::TransactionInProgress => "25001",
+        DatabaseError::NoTransaction => "25P01",
+        DatabaseError::UserExists(_) => "42710",
+        DatabaseError::NoSuchUser(_) => "42704",
+        DatabaseError::TriggerExists(_) => "42710",
+        DatabaseError::NoSuchTrigger(_) => "42704",
+        DatabaseError::IndexExists(_) => "42P07",
+        DatabaseError::NoSuchIndex(_) | DatabaseError::NoFullTextIndex(_) => "42704",
+        DatabaseError::PermissionDenied(_) => "42501",
+        DatabaseError::Cancelled => "57014",
+        DatabaseError::ReadOnlyTable(_) | DatabaseError::PartitionWrite(_) => "42809",
+        DatabaseError::External { .. } => "22P04",
+        DatabaseError::AuditLog(_) | DatabaseError::Spill(_) => "58030",
+        DatabaseError::TempSpaceFull(_) => "53400",
+        DatabaseError::Config(error) => config_sqlstate(error),
+        DatabaseError::TransactionError(error) => transaction_sqlstate(error),
+        _ => "XX000",
+    }
+}
+
+/// What a statement returned: its columns and rows as text, `None` where a
+/// value is NULL, and the command tag describing what it did, such as
+/// `INSERT 0 2`. Statements that return no rows have no columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Option<String>>>,
+    pub tag: String,
+}
+
+impl StatementResult {
+    fn done(tag: &str) -> Self {
+        Self {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            tag: tag.to_string(),
+        }
+    }
+}
+
+/// Runs statements over a connection, keeping session variables and
+/// prepared statements. A session for a user is held to the user's
+/// privileges; one without, as the embedded shell opens, isn't, since
+/// whoever opens the database's files can already read and write them.
+/// It's held to the user's limits too, as they were when it started.
+pub struct SqlSession<'a> {
+    connection: Connection<'a>,
+    id: u64,
+    user: Option<String>,
+    variables: BTreeMap<String, String>,
+    prepared: HashMap<String, Statement>,
+    /// The open transaction's cursors, by name
+    cursors: HashMap<String, Cursor>,
+    limits: UserLimits,
+    /// When the open transaction started, once a statement has run in it
+    transaction_started: Option<SystemTime>,
+    /// When the statement running, or the last to run, started
+    statement_started: SystemTime,
+}
+
+impl<'a> SqlSession<'a> {
+    /// A session that isn't held to any user's privileges.
+    pub fn new(connection: Connection<'a>) -> Self {
+        Self {
+            id: connection.database().activity().next_session(),
+            connection,
+            user: None,
+            variables: BTreeMap::new(),
+            prepared: HashMap::new(),
+            cursors: HashMap::new(),
+            limits: UserLimits::default(),
+            transaction_started: None,
+            statement_started: SystemTime::now(),
+        }
+    }
+
+    /// A session for `user`, checked against their privileges.
+    pub fn for_user(connection: Connection<'a>, user: &str) -> Self {
+        let mut session = Self::new(connection);
+        session.limits = session.connection.database().limits(user);
+        session.user = Some(user.to_string());
+        session
+    }
+
+    /// The session's id, as `information_schema.active_queries` lists its
+    /// statements under.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    pub fn connection(&self) -> &Connection<'a> {
+        &self.connection
+    }
+
+    pub fn connection_mut(&mut self) -> &mut Connection<'a> {
+        &mut self.connection
+    }
+
+    /// Set a session variable; names are case-insensitive. A value for
+    /// one of the variables the session consults itself, such as
+    /// `statement_timeout`, must be one of its own, and is kept as `SHOW`
+    /// shows it.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), SqlError> {
+        let value = match variable(name) {
+            Some(variable) => variable.normalize(value).ok_or_else(|| {
+                let message = format!("invalid value for parameter \"{}\": \"{}\"", name, value);
+                SqlError::new("22023", message)
+            })?,
+            None => value.to_string(),
+        };
+        self.variables.insert(name.to_lowercase(), value);
+        Ok(())
+    }
+
+    /// A session variable's value, or its default if it's one the session
+    /// consults itself and hasn't been set.
+    pub fn variable(&self, name: &str) -> Option<&str> {
+        match self.variables.get(&name.to_lowercase()) {
+            Some(value) => Some(value),
+            None => variable(name).map(|variable| variable.default),
+        }
+    }
+
+    /// Every session variable with a value, in name order.
+    pub fn variables(&self) -> Vec<(String, String)> {
+        let mut variables = self.variables.clone();
+        for variable in VARIABLES {
+            variables
+                .entry(variable.name.to_string())
+                .or_insert_with(|| variable.default.to_string());
+        }
+        variables.into_iter().collect()
+    }
+
+    /// How rows are to be printed, as `output_format` says.
+    pub fn output_format(&self) -> ResultFormat {
+        self.variable("output_format")
+            .and_then(ResultFormat::parse)
+            .unwrap_or(ResultFormat::Aligned)
+    }
+
+    /// The shorter of the session's `statement_timeout` and the user's.
+    fn statement_timeout(&self) -> Option<Duration> {
+        let session = self
+            .variable("statement_timeout")
+            .and_then(parse_duration)
+            .filter(|timeout| !timeout.is_zero());
+        let user = self.limits.statement_timeout_ms.map(Duration::from_millis);
+        session.into_iter().chain(user).min()
+    }
+
+    /// Check that `result`'s rows take no more memory than the user may
+    /// use, and unless only `memory` is checked, that there are no more of
+    /// them than the user may be sent.
+    fn check_limits(&self, result: &StatementResult, memory: bool) -> Result<(), SqlError> {
+        let user = self.user.as_deref().unwrap_or_default();
+        if let Some(max) = self.limits.max_query_memory {
+            let bytes: usize = result
+                .rows
+                .iter()
+                .flatten()
+                .flatten()
+                .map(String::len)
+                .sum();
+            if bytes as u64 > max {
+                let message = format!(
+                    "statement's rows take more than the {} bytes user \"{}\" may use",
+                    max, user
+                );
+                return Err(SqlError::new("53400", message));
+            }
+        }
+        match self.limits.max_rows {
+            Some(max) if !memory && result.rows.len() as u64 > max => {
+                let message = format!(
+                    "statement returned more than the {} rows user \"{}\" may be sent; \
+                     page through them with a cursor",
+                    max, user
+                );
+                Err(SqlError::new("53400", message))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// What the running statement's functions see of the session.
+    fn context(&self) -> Context<'a> {
+        Context {
+            database: self.connection.database(),
+            user: self.user.clone(),
+            catalog: self.variable("database").unwrap_or_default().to_string(),
+            transaction_started: self.transaction_started.unwrap_or(self.statement_started),
+            statement_started: self.statement_started,
+        }
+    }
+
+    fn merge_joins(&self) -> bool {
+        self.variable("enable_mergejoin")
+            .and_then(parse_bool)
+            .unwrap_or(true)
+    }
+
+    /// Run the statements in `sql` in turn, stopping at the first to fail,
+    /// whose error is the last result.
+    pub fn execute(&mut self, sql: &str) -> Vec<Result<StatementResult, SqlError>> {
+        let started = Instant::now();
+        let parsed = {
+            let _span = span!(Debug, "parse");
+            parse_spanned(sql)
+        };
+        let statements = match parsed {
+            Ok(statements) => statements,
+            Err(e) => {
+                self.record_unparsed(sql, started.elapsed());
+                return vec![Err(e.into())];
+            }
+        };
+        let mut results = Vec::new();
+        for statement in statements {
+            let result = self.execute_statement(sql, statement);
+            let failed = result.is_err();
+            results.push(result);
+            if failed {
+                break;
+            }
+        }
+        results
+    }
+
+    /// Run the statements in `sql` in turn, as `execute` does, but with a
+    /// result for every one: one that fails, even to parse, leaves the
+    /// rest to run. A failure inside a transaction the script began rolls
+    /// it back, and the statements up to the `COMMIT` or `ROLLBACK` that
+    /// would have ended it fail without running, so none of them are
+    /// written apart from the rest.
+    pub fn execute_script(&mut self, sql: &str) -> Vec<Result<StatementResult, SqlError>> {
+        let started = Instant::now();
+        let parsed = {
+            let _span = span!(Debug, "parse");
+            parse_script(sql)
+        };
+        let statements = match parsed {
+            Ok(statements) => statements,
+            Err(e) => {
+                self.record_unparsed(sql, started.elapsed());
+                return vec![Err(e.into())];
+            }
+        };
+        let mut results = Vec::with_capacity(statements.len());
+        let mut failed_transaction = false;
+        for statement in statements {
+            let result = match statement {
+                Err(e) => {
+                    self.record_unparsed(sql, Duration::ZERO);
+                    Err(e.into())
+                }
+                Ok(Statement::Commit | Statement::Rollback | Statement::PrepareTransaction(_))
+                    if failed_transaction =>
+                {
+                    failed_transaction = false;
+                    Ok(StatementResult::done("ROLLBACK"))
+                }
+                Ok(_) if failed_transaction => Err(SqlError::new(
+                    "25P02",
+                    "Statements are skipped until the failed transaction ends",
+                )),
+                Ok(statement) => self.execute_statement(sql, statement),
+            };
+            if result.is_err() && self.connection.in_transaction() {
+                self.connection.rollback().ok();
+                failed_transaction = true;
+            }
+            results.push(result);
+        }
+        results
+    }
+
+    /// Count `sql`, which took `elapsed` to fail to parse, as a failed
+    /// query.
+    fn record_unparsed(&self, sql: &str, elapsed: Duration) {
+        let database = self.connection.database();
+        database.record_query(elapsed, false, &redact_passwords(sql));
+    }
+
+    /// Run `statement` from `sql`, listed as running while it does.
+    fn execute_statement(
+        &mut self,
+        sql: &str,
+        statement: Statement,
+    ) -> Result<StatementResult, SqlError> {
+        let database = self.connection.database();
+        let cancel = self.connection.cancel_handle();
+        let sql = redact_passwords(sql);
+        let query = database
+            .activity()
+            .start(self.id, self.user.as_deref(), &sql, cancel);
+        let started = Instant::now();
+        self.statement_started = SystemTime::now();
+        let span = span!(Debug, "statement", id = query, session = self.id);
+        let result = match self.statement_timeout() {
+            Some(timeout) => self.run_timed(statement, timeout),
+            None => self.run(statement),
+        };
+        let result = result.and_then(|result| {
+            self.check_limits(&result, false)?;
+            Ok(result)
+        });
+        drop(span);
+        database.activity().finish(query);
+        database.record_query(started.elapsed(), result.is_ok(), &sql);
+        result
+    }
+
+    /// Run `statement`, cancelled as `KILL QUERY` would cancel it if it's
+    /// still running after `timeout`.
+    fn run_timed(
+        &mut self,
+        statement: Statement,
+        timeout: Duration,
+    ) -> Result<StatementResult, SqlError> {
+        let cancel = self.connection.cancel_handle();
+        let (finished, waiting) = mpsc::channel::<()>();
+        thread::scope(|scope| {
+            let timer = scope.spawn(move || {
+                let timed_out = waiting.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout);
+                if timed_out {
+                    cancel.cancel();
+                }
+                timed_out
+            });
+            let result = self.run(statement);
+            drop(finished);
+            if !timer.join().unwrap() {
+                return result;
+            }
+            // A cancel too late to apply mustn't apply to the next statement
+            self.connection.cancel_handle().reset();
+            match result {
+                Err(e) if e.code == "57014" => Err(SqlError::new(
+                    "57014",
+                    "canceling statement due to statement timeout",
+                )),
+                result => result,
+            }
+        })
+    }
+
+    /// Run the statements in `batch` all or nothing, in the session's
+    /// transaction or one of their own, so that many writes share a commit
+    /// and its WAL flush; a failure undoes the batch but leaves the
+    /// session's transaction open. Each distinct text is parsed once
+    /// however often it repeats, and inserts into one table in a row are
+    /// written together. Returns a result for each statement, or the
+    /// first error.
+    pub fn execute_batch<S: AsRef<str>>(
+        &mut self,
+        batch: &[S],
+    ) -> Result<Vec<StatementResult>, SqlError> {
+        let mut parsed: HashMap<&str, Vec<Statement>> = HashMap::new();
+        let mut statements = Vec::with_capacity(batch.len());
+        for sql in batch {
+            let sql = sql.as_ref();
+            if !parsed.contains_key(sql) {
+                let started = Instant::now();
+                let parsed_sql = parse_spanned(sql)
+                    .inspect_err(|_| self.record_unparsed(sql, started.elapsed()))?;
+                parsed.insert(sql, parsed_sql);
+            }
+            statements.extend(parsed[sql].iter().cloned());
+        }
+
+        let text: Vec<_> = batch.iter().map(AsRef::as_ref).collect();
+        let text = text.join("; ");
+        let text = redact_passwords(&text);
+        let database = self.connection.database();
+        let cancel = self.connection.cancel_handle();
+        let query = database
+            .activity()
+            .start(self.id, self.user.as_deref(), &text, cancel);
+        let started = Instant::now();
+        self.statement_started = SystemTime::now();
+        let span = span!(Debug, "batch", id = query, session = self.id);
+        let result = if self.connection.in_transaction() {
+            // Undone on failure without ending the session's transaction
+            self.connection
+                .savepoint(BATCH_SAVEPOINT)
+                .map_err(SqlError::from)
+                .and_then(|()| {
+                    let result = self.run_batch(statements);
+                    if result.is_err() {
+                        self.connection.rollback_to(BATCH_SAVEPOINT)?;
+                    }
+                    self.connection.release(BATCH_SAVEPOINT)?;
+                    result
+                })
+        } else {
+            self.connection
+                .begin()
+                .map_err(SqlError::from)
+                .and_then(|()| match self.run_batch(statements) {
+                    Ok(results) => {
+                        self.connection.commit()?;
+                        Ok(results)
+                    }
+                    Err(e) => {
+                        self.connection.rollback()?;
+                        Err(e)
+                    }
+                })
+        };
+        drop(span);
+        database.activity().finish(query);
+        database.record_query(started.elapsed(), result.is_ok(), &text);
+        result
+    }
+
+    fn run_batch(&mut self, statements: Vec<Statement>) -> Result<Vec<StatementResult>, SqlError> {
+        let mut results = Vec::with_capacity(statements.len());
+        let mut statements = statements.into_iter().peekable();
+        while let Some(statement) = statements.next() {
+            let (table, values) = match statement {
+                Statement::Begin
+                | Statement::Commit
+                | Statement::Rollback
+                | Statement::Savepoint(_)
+                | Statement::RollbackTo(_)
+                | Statement::Release(_)
+                | Statement::PrepareTransaction(_)
+                | Statement::CommitPrepared(_)
+                | Statement::RollbackPrepared(_) => {
+                    let message = "a batch runs in a single transaction";
+                    return Err(SqlError::new("25001", message));
+                }
+                Statement::Insert { table, values }
+                    if self
+                        .connection
+                        .database()
+                        .triggers(TableId(table), Event::Insert)
+                        .is_empty() =>
+                {
+                    (table, values)
+                }
+                statement => {
+                    results.push(self.run(statement)?);
+                    continue;
+                }
+            };
+            let context = self.context();
+            if let Some(user) = &self.user {
+                let database = context.database;
+                database.check_privilege(user, Privilege::Insert, Some(TableId(table)))?;
+            }
+            // The inserts into the table that follow go in with this one
+            let mut counts = vec![values.len()];
+            let mut rows = values;
+            while let Some(Statement::Insert { values, .. }) = statements.next_if(
+                |next| matches!(next, Statement::Insert { table: other, .. } if *other == table),
+            ) {
+                counts.push(values.len());
+                rows.extend(values);
+            }
+            let rows = rows
+                .iter()
+                .map(|value| evaluate(&context, value, None, None))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut ids = self
+                .connection
+                .insert_batch(TableId(table), &rows)?
+                .into_iter();
+            for count in counts {
+                let rows: Vec<_> = ids
+                    .by_ref()
+                    .take(count)
+                    .map(|id| vec![Some(id.to_string())])
+                    .collect();
+                results.push(StatementResult {
+                    columns: columns(&["id"]),
+                    tag: format!("INSERT 0 {}", rows.len()),
+                    rows,
+                });
+            }
+        }
+        Ok(results)
+    }
+
+    /// Run `statement`, recording it in the audit log if it changes the
+    /// schema, users or privileges.
+    fn run(&mut self, statement: Statement) -> Result<StatementResult, SqlError> {
+        // Cursors last until the transaction they were declared in ends
+        if !self.connection.in_transaction() {
+            self.cursors.clear();
+            self.transaction_started = None;
+        }
+        let result = match audited(&statement) {
+            Some(text) => {
+                let result = self.run_statement(statement);
+                let outcome = result.as_ref().map(|_| ()).map_err(SqlError::message);
+                let database = self.connection.database();
+                database.audit(self.user.as_deref(), AuditEvent::Statement(&text), outcome);
+                result
+            }
+            None => self.run_statement(statement),
+        };
+        // A transaction started as its first statement did, as in Postgres
+        if self.connection.in_transaction() && self.transaction_started.is_none() {
+            self.transaction_started = Some(self.statement_started);
+        }
+        result
+    }
+
+    fn run_statement(&mut self, statement: Statement) -> Result<StatementResult, SqlError> {
+        let database = self.connection.database();
+        if let Some(user) = &self.user {
+            authorize(database, user, &statement)?;
+        }
+        let merge = self.merge_joins();
+        let context = self.context();
+        let connection = &mut self.connection;
+        let done = StatementResult::done;
+        Ok(match statement {
+            Statement::Begin => {
+                connection.begin()?;
+                done("BEGIN")
+            }
+            Statement::Commit => {
+                connection.commit()?;
+                done("COMMIT")
+            }
+            Statement::Rollback => {
+                connection.rollback()?;
+                done("ROLLBACK")
+            }
+            Statement::Savepoint(name) => {
+                connection.savepoint(&name)?;
+                done("SAVEPOINT")
+            }
+            Statement::RollbackTo(name) => {
+                connection.rollback_to(&name)?;
+                done("ROLLBACK")
+            }
+            Statement::Release(name) => {
+                connection.release(&name)?;
+                done("RELEASE")
+            }
+            Statement::PrepareTransaction(gid) => {
+                connection.prepare(&gid)?;
+                done("PREPARE TRANSACTION")
+            }
+            Statement::CommitPrepared(gid) => {
+                outside_transaction(connection, "COMMIT PREPARED")?;
+                database.commit_prepared(&gid)?;
+                done("COMMIT PREPARED")
+            }
+            Statement::RollbackPrepared(gid) => {
+                outside_transaction(connection, "ROLLBACK PREPARED")?;
+                database.rollback_prepared(&gid)?;
+                done("ROLLBACK PREPARED")
+            }
+            Statement::CreateTable(options) => {
+                let table = match &self.user {
+                    Some(user) => database.create_table_as(user, options)?,
+                    None => database.create_table_with(options)?,
+                };
+                StatementResult {
+                    columns: columns(&["table"]),
+                    rows: vec![vec![Some(table.to_string())]],
+                    tag: "CREATE TABLE".to_string(),
+                }
+            }
+            Statement::CreatePartitionedTable {
+                options,
+                partitioning,
+            } => {
+                let table = match &self.user {
+                    Some(user) => {
+                        database.create_partitioned_table_as(user, options, partitioning)?
+                    }
+                    None => database.create_partitioned_table(options, partitioning)?,
+                };
+                StatementResult {
+                    columns: columns(&["table"]),
+                    rows: vec![vec![Some(table.to_string())]],
+                    tag: "CREATE TABLE".to_string(),
+                }
+            }
+            Statement::CreateExternalTable {
+                columns: external,
+                location,
+                options,
+            } => {
+                let table =
+                    database.create_external_table(external, Path::new(&location), options)?;
+                StatementResult {
+                    columns: columns(&["table"]),
+                    rows: vec![vec![Some(table.to_string())]],
+                    tag: "CREATE EXTERNAL TABLE".to_string(),
+                }
+            }
+            Statement::Insert { table, values } => {
+                let values = values
+                    .iter()
+                    .map(|value| evaluate(&context, value, None, None))
+                    .collect::<Result<Vec<_>, _>>()?;
+                // All the rows or none
+                let ids = atomically(connection, |connection| {
+                    values
+                        .iter()
+                        .map(|value| insert_row(connection, &context, TableId(table), value, 0))
+                        .collect::<Result<Vec<_>, _>>()
+                })?;
+                let rows: Vec<_> = ids
+                    .into_iter()
+                    .map(|id| vec![Some(id.to_string())])
+                    .collect();
+                StatementResult {
+                    columns: columns(&["id"]),
+                    tag: format!("INSERT 0 {}", rows.len()),
+                    rows,
+                }
+            }
+            Statement::Select { table, row, order } => {
+                let read = if row.is_some() { Read::Row } else { Read::Scan };
+                let plan = Plan::new(database, TableId(table), read, None, order);
+                let rows = plan.order(database, select(connection, &context, table, row)?)?;
+                let rows: Vec<_> = rows.into_iter().map(text).collect();
+                StatementResult {
+                    columns: columns(&["id", "data"]),
+                    tag: format!("SELECT {}", rows.len()),
+                    rows,
+                }
+            }
+            Statement::SelectAsOf {
+                table,
+                as_of,
+                order,
+            } => {
+                let as_of = match as_of {
+                    AsOfPoint::Timestamp(time) => match Timestamp::parse(time.trim()) {
+                        Some(time) => AsOf::Time(time),
+                        None => {
+                            let message =
+                                format!("invalid input syntax for type timestamp: \"{}\"", time);
+                            return Err(SqlError::new("22007", message));
+                        }
+                    },
+                    AsOfPoint::Lsn(lsn) => AsOf::Lsn(Lsn(lsn)),
+                };
+                let plan = Plan::new(database, TableId(table), Read::Scan, None, order);
+                let rows = connection.scan_as_of(TableId(table), as_of)?;
+                let rows: Vec<_> = plan.order(database, rows)?.into_iter().map(text).collect();
+                StatementResult {
+                    columns: columns(&["id", "data"]),
+                    tag: format!("SELECT {}", rows.len()),
+                    rows,
+                }
+            }
+            Statement::Search { table, terms } => {
+                let terms = text_of(&context, &terms)?;
+                let rows: Vec<_> = connection
+                    .search(TableId(table), &terms)?
+                    .into_iter()
+                    .map(|found| {
+                        let mut row = text(found.row);
+                        row.push(Some(format!("{:.4}", found.rank)));
+                        row
+                    })
+                    .collect();
+                StatementResult {
+                    columns: columns(&["id", "data", "rank"]),
+                    tag: format!("SELECT {}", rows.len()),
+                    rows,
+                }
+            }
+            Statement::Find {
+                table,
+                column,
+                value,
+                access,
+                order,
+            } => {
+                let table = TableId(table);
+                let (rows, plan) = match column {
+                    Some(column) => {
+                        let value = text_of(&context, &value)?;
+                        let rows = connection.find_by(table, &column, &value)?;
+                        let filter = Some(Key::Column(column));
+                        (rows, Plan::new(database, table, Read::Scan, filter, order))
+                    }
+                    None => {
+                        let data = evaluate(&context, &value, None, None)?;
+                        let read = database.access_with(table, &data, access).into();
+                        let rows = connection.find_with(table, &data, access)?;
+                        let filter = Some(Key::Data);
+                        (rows, Plan::new(database, table, read, filter, order))
+                    }
+                };
+                let rows = plan.order(database, rows)?;
+                let rows: Vec<_> = rows.into_iter().map(text).collect();
+                StatementResult {
+                    columns: columns(&["id", "data"]),
+                    tag: format!("SELECT {}", rows.len()),
+                    rows,
+                }
+            }
+            Statement::Aggregate {
+                function,
+                args,
+                order,
+                table,
+                row,
+            } => {
+                let read = if row.is_some() { Read::Row } else { Read::Scan };
+                let plan = Plan::new(database, TableId(table), read, None, order);
+                let rows = plan.order(database, select(connection, &context, table, row)?)?;
+                // Registered aggregates take no arguments
+                let registered = match args.is_empty() {
+                    true => database
+                        .call_aggregate_fn(&function, rows.iter().map(|row| row.data.as_slice())),
+                    false => None,
+                };
+                let value = match registered {
+                    Some(value) => Some(value),
+                    None => {
+                        let args = args
+                            .iter()
+                            .map(|arg| text_of(&context, arg))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        let data: Vec<_> = rows
+                            .iter()
+                            .map(|row| String::from_utf8_lossy(&row.data))
+                            .collect();
+                        let data: Vec<_> = data.iter().map(|data| data.as_ref()).collect();
+                        builtin::aggregate(&function, &args, &data)
+                            .ok_or_else(|| no_function(&function))??
+                    }
+                };
+                StatementResult {
+                    columns: vec![function],
+                    rows: vec![vec![value]],
+                    tag: "SELECT 1".to_string(),
+                }
+            }
+            Statement::Values(values) => {
+                let names = values.iter().map(|value| match value {
+                    Value::Call { name, .. } => name.clone(),
+                    _ => "?column?".to_string(),
+                });
+                let row = values
+                    .iter()
+                    .map(|value| {
+                        let data = nullable(&context, value, None, None)?;
+                        Ok(data.map(|data| String::from_utf8_lossy(&data).into_owned()))
+                    })
+                    .collect::<Result<_, SqlError>>()?;
+                StatementResult {
+                    columns: names.collect(),
+                    rows: vec![row],
+                    tag: "SELECT 1".to_string(),
+                }
+            }
+            Statement::Count(table) => StatementResult {
+                columns: columns(&["count"]),
+                rows: vec![vec![Some(connection.count(TableId(table))?.to_string())]],
+                tag: "SELECT 1".to_string(),
+            },
+            Statement::Update { table, row, value } => {
+                let row = row_to_write(database, table, &text_of(&context, &row)?)?;
+                let value = evaluate(&context, &value, None, None)?;
+                let updated = atomically(connection, |connection| {
+                    update_row(connection, &context, row, &value, 0)
+                })?;
+                done(&format!("UPDATE {}", updated as u8))
+            }
+            Statement::Delete { table, row } => {
+                let row = row_to_write(database, table, &text_of(&context, &row)?)?;
+                let deleted = atomically(connection, |connection| {
+                    delete_row(connection, &context, row, 0)
+                })?;
+                done(&format!("DELETE {}", deleted as u8))
+            }
+            Statement::Set { name, value } => {
+                self.set(&name, &value)?;
+                done("SET")
+            }
+            Statement::SetGlobal { name, value } => {
+                database.set_global(&name, &value)?;
+                done("SET")
+            }
+            Statement::ActiveQueries => {
+                // Others' statements are for superusers alone to see
+                let everyone = match &self.user {
+                    Some(user) => database.check_superuser(user).is_ok(),
+                    None => true,
+                };
+                let rows: Vec<_> = database
+                    .activity()
+                    .list()
+                    .into_iter()
+                    .filter(|query| everyone || query.user == self.user)
+                    .map(|query| {
+                        vec![
+                            Some(query.id.to_string()),
+                            Some(query.session.to_string()),
+                            query.user,
+                            Some(Timestamp(query.started).to_string()),
+                            Some(query.state.name().to_string()),
+                            Some(query.query),
+                        ]
+                    })
+                    .collect();
+                StatementResult {
+                    columns: columns(&["id", "session", "user", "started", "state", "query"]),
+                    tag: format!("SELECT {}", rows.len()),
+                    rows,
+                }
+            }
+            Statement::TableStats => {
+                let rows: Vec<_> = database
+                    .table_stats()
+                    .into_iter()
+                    .map(|(table, stats)| {
+                        let counts = [
+                            table.0.into(),
+                            stats.seq_scans,
+                            stats.rows_scanned,
+                            stats.index_scans,
+                            stats.rows_fetched,
+                            stats.rows_inserted,
+                            stats.rows_updated,
+                            stats.rows_deleted,
+                        ];
+                        counts.iter().map(|count| Some(count.to_string())).collect()
+                    })
+                    .collect();
+                StatementResult {
+                    columns: columns(&[
+                        "table",
+                        "seq_scans",
+                        "rows_scanned",
+                        "index_scans",
+                        "rows_fetched",
+                        "rows_inserted",
+                        "rows_updated",
+                        "rows_deleted",
+                    ]),
+                    tag: format!("SELECT {}", rows.len()),
+                    rows,
+                }
+            }
+            Statement::Transactions => {
+                let rows: Vec<_> = database
+                    .transactions()
+                    .into_iter()
+                    .map(|transaction| {
+                        let locks: Vec<_> = transaction
+                            .locks
+                            .iter()
+                            .map(|(target, mode)| match mode {
+                                LockMode::Shared => format!("{} shared", target),
+                                LockMode::Exclusive => format!("{} exclusive", target),
+                            })
+                            .collect();
+                        vec![
+                            Some(transaction.id.to_string()),
+                            Some(Timestamp(transaction.started).to_string()),
+                            Some(transaction.idle.as_millis().to_string()),
+                            Some(locks.join(", ")),
+                            Some(transaction.wal_bytes.to_string()),
+                            transaction.gid,
+                        ]
+                    })
+                    .collect();
+                StatementResult {
+                    columns: columns(&["id", "started", "idle_ms", "locks", "wal_bytes", "gid"]),
+                    tag: format!("SELECT {}", rows.len()),
+                    rows,
+                }
+            }
+            Statement::BufferStats => {
+                // The pool as a whole first, then each table's pages in it
+                let (pool, tables) = database.buffer_stats();
+                let mut rows = vec![vec![
+                    None,
+                    Some(pool.cached_pages.to_string()),
+                    Some(pool.dirty_pages.to_string()),
+                    Some(pool.hits.to_string()),
+                    Some(pool.misses.to_string()),
+                    Some(format!("{:.3}", pool.hit_ratio())),
+                ]];
+                rows.extend(tables.into_iter().map(|(table, cached, dirty)| {
+                    let counts = [table.0 as usize, cached, dirty];
+                    let mut row: Vec<_> = counts.iter().map(|n| Some(n.to_string())).collect();
+                    row.extend([None, None, None]);
+                    row
+                }));
+                StatementResult {
+                    columns: columns(&[
+                        "table",
+                        "cached_pages",
+                        "dirty_pages",
+                        "hits",
+                        "misses",
+                        "hit_ratio",
+                    ]),
+                    tag: format!("SELECT {}", rows.len()),
+                    rows,
+                }
+            }
+            Statement::KillQuery(id) => {
+                let query = database.activity().get(id).ok_or_else(|| no_query(id))?;
+                // Users may kill their own statements, and superusers anyone's
+                if let Some(user) = &self.user {
+                    if query.user.as_ref() != Some(user) {
+                        database.check_superuser(user)?;
+                    }
+                }
+                if !database.activity().kill(id) {
+                    return Err(no_query(id));
+                }
+                done("KILL")
+            }
+            Statement::Show(name) => {
+                let value = self.variable(&name).ok_or_else(|| {
+                    let message = format!("unrecognized configuration parameter \"{}\"", name);
+                    SqlError::new("42704", message)
+                })?;
+                StatementResult {
+                    rows: vec![vec![Some(value.to_string())]],
+                    columns: vec![name],
+                    tag: "SHOW".to_string(),
+                }
+            }
+            Statement::Declare { name, query } => {
+                if !connection.in_transaction() {
+                    let message = "DECLARE CURSOR can only be used in transaction blocks";
+                    return Err(SqlError::new("25P01", message));
+                }
+                if self.cursors.contains_key(&name) {
+                    let message = format!("cursor \"{}\" already exists", name);
+                    return Err(SqlError::new("42P03", message));
+                }
+                let cursor = match *query {
+                    Statement::Select {
+                        table,
+                        row: None,
+                        order,
+                    } if in_stored_order(database, TableId(table), order.clone()) => {
+                        Cursor::scan(TableId(table))
+                    }
+                    query => {
+                        let result = self.run_statement(query)?;
+                        self.check_limits(&result, true)?;
+                        Cursor::rows(result)
+                    }
+                };
+                self.cursors.insert(name, cursor);
+                done("DECLARE CURSOR")
+            }
+            Statement::Fetch { name, count } => {
+                let cursor = self
+                    .cursors
+                    .get_mut(&name)
+                    .ok_or_else(|| no_cursor(&name))?;
+                cursor.fetch(connection, count)?
+            }
+            Statement::Close(None) => {
+                self.cursors.clear();
+                done("CLOSE CURSOR ALL")
+            }
+            Statement::Close(Some(name)) => {
+                self.cursors.remove(&name).ok_or_else(|| no_cursor(&name))?;
+                done("CLOSE CURSOR")
+            }
+            Statement::ShowAll => {
+                let rows = self
+                    .variables()
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let description =
+                            variable(&name).map_or("", |variable| variable.description);
+                        vec![Some(name), Some(value), Some(description.to_string())]
+                    })
+                    .collect();
+                StatementResult {
+                    columns: columns(&["name", "setting", "description"]),
+                    rows,
+                    tag: "SHOW".to_string(),
+                }
+            }
+            Statement::Prepare { name, statement } => {
+                if self.prepared.contains_key(&name) {
+                    let message = format!("prepared statement \"{}\" already exists", name);
+                    return Err(SqlError::new("42P05", message));
+                }
+                self.prepared.insert(name, *statement);
+                done("PREPARE")
+            }
+            Statement::Execute { name, params } => {
+                let statement = self
+                    .prepared
+                    .get(&name)
+                    .ok_or_else(|| no_prepared(&name))?
+                    .bind(&params)
+                    .map_err(|e| SqlError::new("42P02", e.to_string()))?;
+                self.run(statement)?
+            }
+            Statement::Deallocate(name) => {
+                if self.prepared.remove(&name).is_none() {
+                    return Err(no_prepared(&name));
+                }
+                done("DEALLOCATE")
+            }
+            Statement::CreateUser {
+                name,
+                password,
+                superuser,
+            } => {
+                outside_transaction(connection, "CREATE USER")?;
+                database.create_user(&name, &password, superuser)?;
+                done("CREATE ROLE")
+            }
+            Statement::DropUser(name) => {
+                outside_transaction(connection, "DROP USER")?;
+                database.drop_user(&name)?;
+                done("DROP ROLE")
+            }
+            Statement::Grant {
+                privileges,
+                table,
+                user,
+            } => {
+                outside_transaction(connection, "GRANT")?;
+                database.grant(&user, &privileges, table.map(TableId))?;
+                done("GRANT")
+            }
+            Statement::Revoke {
+                privileges,
+                table,
+                user,
+            } => {
+                outside_transaction(connection, "REVOKE")?;
+                database.revoke(&user, &privileges, table.map(TableId))?;
+                done("REVOKE")
+            }
+            Statement::CreateTrigger(trigger) => {
+                outside_transaction(connection, "CREATE TRIGGER")?;
+                database.create_trigger(trigger)?;
+                done("CREATE TRIGGER")
+            }
+            Statement::DropTrigger(name) => {
+                outside_transaction(connection, "DROP TRIGGER")?;
+                database.drop_trigger(&name)?;
+                done("DROP TRIGGER")
+            }
+            Statement::CreateFullTextIndex { name, table } => {
+                outside_transaction(connection, "CREATE INDEX")?;
+                database.create_fulltext_index(&name, TableId(table))?;
+                done("CREATE INDEX")
+            }
+            Statement::CreateUniqueIndex {
+                name,
+                table,
+                column,
+                primary,
+            } => {
+                outside_transaction(connection, "CREATE INDEX")?;
+                database.create_unique_index(&name, TableId(table), column, primary)?;
+                done("CREATE INDEX")
+            }
+            Statement::DropIndex(name) => {
+                outside_transaction(connection, "DROP INDEX")?;
+                if database.unique_index_table(&name).is_some() {
+                    database.drop_unique_index(&name)?;
+                } else {
+                    database.drop_fulltext_index(&name)?;
+                }
+                done("DROP INDEX")
+            }
+            Statement::Join {
+                left,
+                right,
+                left_key,
+                right_key,
+            } => {
+                let (left, right) = (TableId(left), TableId(right));
+                let join = Join::new(database, left, left_key, right, right_key, merge);
+                let left = connection.scan(left)?;
+                let right = connection.scan(right)?;
+                let rows: Vec<_> = join
+                    .join(database, left, right)?
+                    .into_iter()
+                    .map(|(left, right)| {
+                        let mut row = text(left);
+                        row.extend(text(right));
+                        row
+                    })
+                    .collect();
+                StatementResult {
+                    columns: columns(&["id", "data", "id", "data"]),
+                    tag: format!("SELECT {}", rows.len()),
+                    rows,
+                }
+            }
+            Statement::SemiJoin {
+                table,
+                key,
+                other,
+                other_key,
+                anti,
+                exists,
+            } => {
+                let (table, other) = (TableId(table), TableId(other));
+                let join = SemiJoin::new(database, table, key, other, other_key, anti, exists);
+                let left = connection.scan(table)?;
+                let right = connection.scan(other)?;
+                let rows: Vec<_> = join.filter(left, right).into_iter().map(text).collect();
+                StatementResult {
+                    columns: columns(&["id", "data"]),
+                    tag: format!("SELECT {}", rows.len()),
+                    rows,
+                }
+            }
+            Statement::Explain { query, format } => StatementResult {
+                columns: columns(&["QUERY PLAN"]),
+                rows: explain(&context, &query, format, merge)?
+                    .into_iter()
+                    .map(|line| vec![Some(line)])
+                    .collect(),
+                tag: "EXPLAIN".to_string(),
+            },
+            Statement::Analyze(table) => {
+                let tables = match table {
+                    Some(table) => vec![TableId(table)],
+                    None => database.tables(),
+                };
+                for table in tables {
+                    database.analyze(table)?;
+                }
+                done("ANALYZE")
+            }
+            Statement::Checkpoint => {
+                database.checkpoint()?;
+                done("CHECKPOINT")
+            }
+            Statement::Vacuum(table) => {
+                outside_transaction(connection, "VACUUM")?;
+                let tables = match table {
+                    Some(table) => vec![TableId(table)],
+                    // Partitions are vacuumed with their table
+                    None => database
+                        .tables()
+                        .into_iter()
+                        .filter(|&table| database.parent_table(table) == table)
+                        .filter(|&table| database.external_table(table).is_none())
+                        .collect(),
+                };
+                for table in tables {
+                    connection.vacuum(table)?;
+                }
+                done("VACUUM")
+            }
+            Statement::Backup { path, base } => {
+                let lsn = match base {
+                    Some(base) => database.incremental_backup(path, base)?,
+                    None => database.backup(path)?,
+                };
+                StatementResult {
+                    columns: columns(&["lsn"]),
+                    rows: vec![vec![Some(lsn.to_string())]],
+                    tag: "BACKUP".to_string(),
+                }
+            }
+            Statement::CopyFrom {
+                table,
+                path,
+                options,
+            } => {
+                let count = copy_from(connection, TableId(table), Path::new(&path), &options)?;
+                done(&format!("COPY {}", count))
+            }
+            Statement::CopyTo {
+                query,
+                path,
+                options,
+            } => {
+                let Statement::Select { table, row, .. } = *query else {
+                    unreachable!("COPY TO parses only a SELECT")
+                };
+                let row = match row {
+                    Some(row) => Some(row_id(database, table, &text_of(&context, &row)?)?),
+                    None => None,
+                };
+                let count = copy_to(connection, TableId(table), row, Path::new(&path), &options)?;
+                done(&format!("COPY {}", count))
+            }
+        })
+    }
+}
+
+impl Connection<'_> {
+    /// Run the statements in `sql` over the connection, with a result for
+    /// each, as `SqlSession::execute_script` does. `BEGIN`, `COMMIT` and
+    /// `ROLLBACK` in it begin and end the connection's transaction, which
+    /// may be left open for the next call; session variables and prepared
+    /// statements last only for the script.
+    pub fn execute_script(&mut self, sql: &str) -> Vec<Result<StatementResult, SqlError>> {
+        let connection = std::mem::replace(self, self.database().connect());
+        let mut session = SqlSession::new(connection);
+        let results = session.execute_script(sql);
+        *self = session.connection;
+        results
+    }
+}
+
+/// Check that `user` may run `statement`. Creating a table is checked as
+/// it runs, and a prepared statement when it is executed.
+fn authorize(database: &Database, user: &str, statement: &Statement) -> Result<(), SqlError> {
+    let (privilege, table) = match statement {
+        Statement::Insert { table, .. } => (Privilege::Insert, *table),
+        Statement::Select { table, .. }
+        | Statement::SelectAsOf { table, .. }
+        | Statement::Aggregate { table, .. }
+        | Statement::Count(table)
+        | Statement::Search { table, .. }
+        | Statement::Find { table, .. } => (Privilege::Select, *table),
+        Statement::Update { table, .. } => (Privilege::Update, *table),
+        Statement::Delete { table, .. } => (Privilege::Delete, *table),
+        Statement::Explain { query, .. } | Statement::Declare { query, .. } => {
+            return authorize(database, user, query)
+        }
+        Statement::Join { left, right, .. }
+        | Statement::SemiJoin {
+            table: left,
+            other: right,
+            ..
+        } => {
+            database.check_privilege(user, Privilege::Select, Some(TableId(*left)))?;
+            (Privilege::Select, *right)
+        }
+        Statement::CreateTrigger(trigger) => (Privilege::Ddl, trigger.table.0),
+        Statement::DropTrigger(name) => match database.trigger(name) {
+            Some(trigger) => (Privilege::Ddl, trigger.table.0),
+            None => return Ok(()),
+        },
+        Statement::CreateFullTextIndex { table, .. }
+        | Statement::CreateUniqueIndex { table, .. } => (Privilege::Ddl, *table),
+        Statement::Analyze(Some(table)) | Statement::Vacuum(Some(table)) => {
+            (Privilege::Ddl, *table)
+        }
+        Statement::DropIndex(name) => match database
+            .fulltext_index_table(name)
+            .or_else(|| database.unique_index_table(name))
+        {
+            Some(table) => (Privilege::Ddl, table.0),
+            None => return Ok(()),
+        },
+        // Reading and writing the server's files is for superusers alone,
+        // as in Postgres
+        Statement::CreateUser { .. }
+        | Statement::DropUser(_)
+        | Statement::Grant { .. }
+        | Statement::Revoke { .. }
+        | Statement::CopyFrom { .. }
+        | Statement::CopyTo { .. }
+        | Statement::CreateExternalTable { .. }
+        | Statement::SetGlobal { .. }
+        | Statement::Analyze(None)
+        | Statement::Checkpoint
+        | Statement::Backup { .. }
+        | Statement::Vacuum(None) => return Ok(database.check_superuser(user)?),
+        // Who prepared a transaction isn't kept, so only a superuser may
+        // finish it
+        Statement::CommitPrepared(_) | Statement::RollbackPrepared(_) => {
+            return Ok(database.check_superuser(user)?)
+        }
+        _ => return Ok(()),
+    };
+    Ok(database.check_privilege(user, privilege, Some(TableId(table)))?)
+}
+
+/// The text `statement` is audited as, if it changes the schema, users or
+/// privileges; passwords are left out.
+fn audited(statement: &Statement) -> Option<String> {
+    let privileges = |privileges: &[Privilege], table: &Option<u32>| {
+        let privileges: Vec<String> = privileges.iter().map(Privilege::to_string).collect();
+        match table {
+            Some(table) => format!("{} ON {}", privileges.join(", "), table),
+            None => format!("{} ON ALL TABLES", privileges.join(", ")),
+        }
+    };
+    let create_table = |options: &TableOptions| {
+        let mut given = Vec::new();
+        if let Some(fill_factor) = options.fill_factor {
+            given.push(format!("FILL_FACTOR = {}", fill_factor));
+        }
+        if let Some(compression) = options.compression {
+            let name = match compression {
+                Compression::None => "none",
+                Compression::Lz => "lz",
+            };
+            given.push(format!("COMPRESSION = '{}'", name));
+        }
+        if options.bloom_filter {
+            given.push("BLOOM_FILTER = TRUE".to_string());
+        }
+        if let Some(column) = &options.ttl_column {
+            given.push(format!("TTL_COLUMN = '{}'", column));
+        }
+        if given.is_empty() {
+            "CREATE TABLE".to_string()
+        } else {
+            format!("CREATE TABLE WITH ({})", given.join(", "))
+        }
+    };
+    Some(match statement {
+        Statement::CreateTable(options) => create_table(options),
+        Statement::CreatePartitionedTable {
+            options,
+            partitioning,
+        } => {
+            let key = partitioning.column.as_deref().unwrap_or("data");
+            let scheme = match &partitioning.scheme {
+                PartitionScheme::Hash(count) => format!("HASH ({}) PARTITIONS {}", key, count),
+                PartitionScheme::Range(bounds) => {
+                    let bounds: Vec<String> =
+                        bounds.iter().map(|bound| format!("'{}'", bound)).collect();
+                    format!("RANGE ({}) SPLIT AT ({})", key, bounds.join(", "))
+                }
+            };
+            format!("{} PARTITION BY {}", create_table(options), scheme)
+        }
+        Statement::CreateExternalTable { location, .. } => {
+            format!("CREATE EXTERNAL TABLE LOCATION '{}'", location)
+        }
+        Statement::CreateUser {
+            name, superuser, ..
+        } => format!(
+            "CREATE USER {}{}",
+            name,
+            if *superuser { " SUPERUSER" } else { "" }
+        ),
+        Statement::DropUser(name) => format!("DROP USER {}", name),
+        Statement::CreateTrigger(trigger) => format!(
+            "CREATE TRIGGER {} {} {} ON {}",
+            trigger.name,
+            match trigger.timing {
+                Timing::Before => "BEFORE",
+                Timing::After => "AFTER",
+            },
+            trigger.event.name(),
+            trigger.table
+        ),
+        Statement::DropTrigger(name) => format!("DROP TRIGGER {}", name),
+        Statement::CreateFullTextIndex { name, table } => {
+            format!("CREATE FULLTEXT INDEX {} ON {} (data)", name, table)
+        }
+        Statement::CreateUniqueIndex {
+            name,
+            table,
+            column,
+            primary,
+        } => format!(
+            "CREATE {} {} ON {} ({})",
+            if *primary {
+                "PRIMARY KEY"
+            } else {
+                "UNIQUE INDEX"
+            },
+            name,
+            table,
+            column.as_deref().unwrap_or("data")
+        ),
+        Statement::DropIndex(name) => format!("DROP INDEX {}", name),
+        Statement::Grant {
+            privileges: granted,
+            table,
+            user,
+        } => format!("GRANT {} TO {}", privileges(granted, table), user),
+        Statement::Revoke {
+            privileges: revoked,
+            table,
+            user,
+        } => format!("REVOKE {} FROM {}", privileges(revoked, table), user),
+        _ => return None,
+    })
+}
+
+/// Users and privileges are changed in transactions of their own, so as
+/// in Postgres, not inside one the session began.
+fn outside_transaction(connection: &Connection, what: &str) -> Result<(), SqlError> {
+    if connection.in_transaction() {
+        let message = format!("{} can't run inside a transaction", what);
+        return Err(SqlError::new("25001", message));
+    }
+    Ok(())
+}
+
+/// Run `write` in a transaction of its own if the session isn't in one,
+/// so that a statement and the triggers it fires change all or nothing.
+fn atomically<T>(
+    connection: &mut Connection,
+    write: impl FnOnce(&mut Connection) -> Result<T, SqlError>,
+) -> Result<T, SqlError> {
+    if connection.in_transaction() {
+        return write(connection);
+    }
+    connection.begin()?;
+    match write(connection) {
+        Ok(result) => {
+            connection.commit()?;
+            Ok(result)
+        }
+        Err(e) => {
+            connection.rollback()?;
+            Err(e)
+        }
+    }
+}
+
+/// Insert a row into `table`, firing its triggers; `depth` is how many
+/// triggers fired the statement inserting it.
+fn insert_row(
+    connection: &mut Connection,
+    context: &Context,
+    table: TableId,
+    data: &[u8],
+    depth: usize,
+) -> Result<RowId, SqlError> {
+    let triggers = connection.database().triggers(table, Event::Insert);
+    fire(
+        connection,
+        context,
+        &triggers,
+        Timing::Before,
+        Some(data),
+        None,
+        depth,
+    )?;
+    let id = connection.insert(table, data)?;
+    fire(
+        connection,
+        context,
+        &triggers,
+        Timing::After,
+        Some(data),
+        None,
+        depth,
+    )?;
+    Ok(id)
+}
+
+/// Update `row`, firing its table's triggers, returning whether it was
+/// there to update.
+fn update_row(
+    connection: &mut Connection,
+    context: &Context,
+    row: RowId,
+    data: &[u8],
+    depth: usize,
+) -> Result<bool, SqlError> {
+    let triggers = connection.database().triggers(row.table, Event::Update);
+    if triggers.is_empty() {
+        return match connection.update(row, data) {
+            Ok(()) => Ok(true),
+            Err(DatabaseError::NoSuchRow(_)) => Ok(false),
+            Err(e) => Err(e.into()),
+        };
+    }
+    let Some(old) = connection.get(row)? else {
+        return Ok(false);
+    };
+    fire(
+        connection,
+        context,
+        &triggers,
+        Timing::Before,
+        Some(data),
+        Some(&old.data),
+        depth,
+    )?;
+    connection.update(row, data)?;
+    fire(
+        connection,
+        context,
+        &triggers,
+        Timing::After,
+        Some(data),
+        Some(&old.data),
+        depth,
+    )?;
+    Ok(true)
+}
+
+/// Delete `row`, firing its table's triggers, returning whether it was
+/// there to delete.
+fn delete_row(
+    connection: &mut Connection,
+    context: &Context,
+    row: RowId,
+    depth: usize,
+) -> Result<bool, SqlError> {
+    let triggers = connection.database().triggers(row.table, Event::Delete);
+    if triggers.is_empty() {
+        return Ok(connection.delete(row)?);
+    }
+    let Some(old) = connection.get(row)? else {
+        return Ok(false);
+    };
+    fire(
+        connection,
+        context,
+        &triggers,
+        Timing::Before,
+        None,
+        Some(&old.data),
+        depth,
+    )?;
+    connection.delete(row)?;
+    fire(
+        connection,
+        context,
+        &triggers,
+        Timing::After,
+        None,
+        Some(&old.data),
+        depth,
+    )?;
+    Ok(true)
+}
+
+/// Run the statements of those of `triggers` that fire at `timing`, with
+/// the row's data as it will be and as it was. They're checked against
+/// the privileges of the user whose statement fired them.
+fn fire(
+    connection: &mut Connection,
+    context: &Context,
+    triggers: &[Trigger],
+    timing: Timing,
+    new: Option<&[u8]>,
+    old: Option<&[u8]>,
+    depth: usize,
+) -> Result<(), SqlError> {
+    let database = connection.database();
+    for trigger in triggers.iter().filter(|trigger| trigger.timing == timing) {
+        if depth >= MAX_DEPTH {
+            let message = format!(
+                "trigger {} nested more than {} deep",
+                trigger.name, MAX_DEPTH
+            );
+            return Err(SqlError::new("54001", message));
+        }
+        if let Some(user) = &context.user {
+            authorize(database, user, &trigger.action)?;
+        }
+        let data = |value: &Value| evaluate(context, value, new, old);
+        let row = |table: u32, value: &Value| -> Result<RowId, SqlError> {
+            Ok(row_to_write(
+                database,
+                table,
+                &String::from_utf8_lossy(&data(value)?),
+            )?)
+        };
+        let depth = depth + 1;
+        match &*trigger.action {
+            Statement::Insert { table, values } => {
+                for value in values {
+                    insert_row(connection, context, TableId(*table), &data(value)?, depth)?;
+                }
+            }
+            Statement::Update {
+                table,
+                row: id,
+                value,
+            } => {
+                update_row(connection, context, row(*table, id)?, &data(value)?, depth)?;
+            }
+            Statement::Delete { table, row: id } => {
+                delete_row(connection, context, row(*table, id)?, depth)?;
+            }
+            _ => unreachable!("a trigger's action is a write"),
+        }
+    }
+    Ok(())
+}
+
+fn columns(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+fn no_query(id: u64) -> SqlError {
+    SqlError::new("42704", format!("no query {} is running", id))
+}
+
+fn no_function(name: &str) -> SqlError {
+    SqlError::new("42883", format!("function {}() does not exist", name))
+}
+
+fn no_cursor(name: &str) -> SqlError {
+    let message = format!("cursor \"{}\" does not exist", name);
+    SqlError::new("34000", message)
+}
+
+/// Whether a scan of `table` reads its rows in `order`, so a cursor can
+/// read them as they're fetched.
+fn in_stored_order(database: &Database, table: TableId, order: Option<Order>) -> bool {
+    let plan = Plan::new(database, table, Read::Scan, None, order);
+    matches!(plan.order, None | Some((_, Sort::Elided)))
+}
+
+fn no_prepared(name: &str) -> SqlError {
+    let message = format!("prepared statement \"{}\" does not exist", name);
+    SqlError::new("26000", message)
+}
+
+/// A value's data, calling the functions in it, those registered before
+/// those built in. Parameters must have been bound by `EXECUTE`, and `NEW`
+/// and `OLD` stand for a trigger's `new` and `old` rows.
+fn evaluate(
+    context: &Context,
+    value: &Value,
+    new: Option<&[u8]>,
+    old: Option<&[u8]>,
+) -> Result<Vec<u8>, SqlError> {
+    nullable(context, value, new, old)?
+        .ok_or_else(|| SqlError::new("22004", "null value not allowed"))
+}
+
+/// A value's data as `evaluate` has it, or `None` for NULL, which only
+/// a value selected without a table may be.
+fn nullable(
+    context: &Context,
+    value: &Value,
+    new: Option<&[u8]>,
+    old: Option<&[u8]>,
+) -> Result<Option<Vec<u8>>, SqlError> {
+    let row = |row: Option<&[u8]>| {
+        row.map(|row| Some(row.to_vec()))
+            .ok_or_else(|| SqlError::new("42P01", "NEW and OLD are only for a trigger's statement"))
+    };
+    match value {
+        Value::Null => Ok(None),
+        Value::String(text) => Ok(Some(text.as_bytes().to_vec())),
+        Value::Param(n) => Err(SqlError::new(
+            "42P02",
+            format!("there is no parameter ${}", n),
+        )),
+        Value::New => row(new),
+        Value::Old => row(old),
+        Value::Call { name, args } => {
+            let args = args
+                .iter()
+                .map(|arg| {
+                    let data = nullable(context, arg, new, old)?;
+                    Ok(data.map(|data| String::from_utf8_lossy(&data).into_owned()))
+                })
+                .collect::<Result<Vec<_>, SqlError>>()?;
+            let result = match context.database.call_scalar_fn(name, &args) {
+                Some(result) => result,
+                None => builtin::call(context, name, &args).ok_or_else(|| no_function(name))??,
+            };
+            Ok(result.map(String::into_bytes))
+        }
+    }
+}
+
+/// A value's text, outside a trigger.
+fn text_of(context: &Context, value: &Value) -> Result<String, SqlError> {
+    let data = evaluate(context, value, None, None)?;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// The rows of `table` a `SELECT` returns: all of them, or the one `row`
+/// names.
+/// The plan `query`, a `Select`, `Find` or `Join`, would be run by, with
+/// joins merged if `merge` allows: its lines, or as `format` asks, the
+/// one row of its JSON.
+fn explain(
+    context: &Context,
+    query: &Statement,
+    format: ExplainFormat,
+    merge: bool,
+) -> Result<Vec<String>, SqlError> {
+    let database = context.database;
+    let shown = |lines: Vec<String>, json: String| match format {
+        ExplainFormat::Text => lines,
+        ExplainFormat::Json => vec![json],
+    };
+    let plan = match query {
+        Statement::Select { table, row, order } => {
+            let read = if row.is_some() { Read::Row } else { Read::Scan };
+            Plan::new(database, TableId(*table), read, None, order.clone())
+        }
+        Statement::Find {
+            table,
+            column,
+            value,
+            access,
+            order,
+        } => {
+            let table = TableId(*table);
+            let (read, filter, value) = match column {
+                Some(column) => {
+                    let value = text_of(context, value)?;
+                    (Read::Scan, Key::Column(column.clone()), value)
+                }
+                None => {
+                    let data = evaluate(context, value, None, None)?;
+                    let read = database.access_with(table, &data, *access).into();
+                    let value = String::from_utf8_lossy(&data).into_owned();
+                    (read, Key::Data, value)
+                }
+            };
+            Plan::new(database, table, read, Some(filter), order.clone())
+                .filtering(database, &value)
+        }
+        Statement::Join {
+            left,
+            right,
+            left_key,
+            right_key,
+        } => {
+            let (left_key, right_key) = (left_key.clone(), right_key.clone());
+            let join = Join::new(
+                database,
+                TableId(*left),
+                left_key,
+                TableId(*right),
+                right_key,
+                merge,
+            );
+            return Ok(shown(join.lines(), join.to_json()));
+        }
+        Statement::SemiJoin {
+            table,
+            key,
+            other,
+            other_key,
+            anti,
+            exists,
+        } => {
+            let (table, other) = (TableId(*table), TableId(*other));
+            let (key, other_key) = (key.clone(), other_key.clone());
+            let join = SemiJoin::new(database, table, key, other, other_key, *anti, *exists);
+            return Ok(shown(join.lines(), join.to_json()));
+        }
+        _ => unreachable!("EXPLAIN parses only a query of a table"),
+    };
+    Ok(shown(plan.lines(), plan.to_json()))
+}
+
+fn select(
+    connection: &mut Connection,
+    context: &Context,
+    table: u32,
+    row: Option<Value>,
+) -> Result<Vec<Row>, SqlError> {
+    Ok(match row {
+        Some(row) => {
+            let row = row_id(context.database, table, &text_of(context, &row)?)?;
+            connection.get(row)?.into_iter().collect()
+        }
+        None => connection.scan(TableId(table))?,
+    })
+}
+
+/// Parse a row id given for `table`, which must name a row in it or in
+/// one of its partitions.
+fn row_id(database: &Database, table: u32, row: &str) -> Result<RowId, DatabaseError> {
+    let id: RowId = row.parse()?;
+    if id.table != TableId(table) && database.parent_table(id.table) != TableId(table) {
+        return Err(DatabaseError::NoSuchRow(id));
+    }
+    Ok(id)
+}
+
+/// Parse a row id given for `table` to change, which can't be a
+/// partition: rows are changed through the table it partitions.
+fn row_to_write(database: &Database, table: u32, row: &str) -> Result<RowId, DatabaseError> {
+    database.check_not_partition(TableId(table))?;
+    row_id(database, table, row)
+}
+
+/// A row as text, converting contents that aren't UTF-8 lossily.
+pub(crate) fn text(row: Row) -> Vec<Option<String>> {
+    vec![
+        Some(row.id.to_string()),
+        Some(String::from_utf8_lossy(&row.data).into_owned()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, UserLimits};
+    use crate::database::tests::{temp_database, test_config};
+
+    #[test]
+    fn test_sql_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        config.storage.page_size = 128;
+        let database = Database::with_config(&config).unwrap();
+
+        let mut session = SqlSession::new(database.connect());
+        let results = session.execute("CREATE TABLE; INSERT INTO 1 VALUES ('a'), ('b')");
+        let tags: Vec<_> = results
+            .iter()
+            .map(|r| r.as_ref().unwrap().tag.as_str())
+            .collect();
+        assert_eq!(tags, vec!["CREATE TABLE", "INSERT 0 2"]);
+
+        // The first failure ends the script
+        let results = session.execute("SELECT * FROM 1; SELECT * FROM 2; SELECT * FROM 1");
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap().rows.len(), 2);
+        assert_eq!(results[1].as_ref().unwrap_err().code(), "42P01");
+        assert_eq!(
+            session.execute("SELEKT")[0].as_ref().unwrap_err().code(),
+            "42601"
+        );
+
+        // Users are held to their privileges, sessions without one aren't
+        session
+            .execute("CREATE USER bob PASSWORD 'pw'")
+            .remove(0)
+            .unwrap();
+        let mut bob = SqlSession::for_user(database.connect(), "bob");
+        let results = bob.execute("SELECT * FROM 1");
+        assert_eq!(results[0].as_ref().unwrap_err().code(), "42501");
+        assert!(session.execute("DELETE FROM 1 WHERE id = '1:0:0'")[0].is_ok());
+    }
+
+    #[test]
+    fn test_execute_batch() {
+        let (_dir, database) = temp_database();
+        let mut session = SqlSession::new(database.connect());
+        session.execute("CREATE TABLE").remove(0).unwrap();
+
+        // Inserts into a table in a row are written together, each
+        // reporting the rows it added
+        let batch = ["INSERT INTO 1 VALUES ('c')"; 50];
+        let results = session.execute_batch(&batch).unwrap();
+        assert_eq!(results.len(), 50);
+        assert_eq!(results[49].tag, "INSERT 0 1");
+        let ids: std::collections::HashSet<_> =
+            results.iter().map(|result| &result.rows[0][0]).collect();
+        assert_eq!(ids.len(), 50);
+        let results = session
+            .execute_batch(&[
+                "INSERT INTO 1 VALUES ('d'), ('e')",
+                "SELECT COUNT(*) FROM 1",
+            ])
+            .unwrap();
+        assert_eq!(results[0].rows.len(), 2);
+        assert_eq!(results[1].rows, vec![vec![Some("52".to_string())]]);
+
+        // A batch is written all or nothing, in one transaction
+        let failed = ["INSERT INTO 1 VALUES ('f')", "SELECT * FROM 2"];
+        assert_eq!(session.execute_batch(&failed).unwrap_err().code(), "42P01");
+        let failed = ["INSERT INTO 1 VALUES ('f')", "COMMIT"];
+        assert_eq!(session.execute_batch(&failed).unwrap_err().code(), "25001");
+        assert_eq!(session.connection_mut().count(TableId(1)).unwrap(), 52);
+
+        // In the session's transaction, which it doesn't end
+        session.execute("BEGIN").remove(0).unwrap();
+        session.execute_batch(&batch[..3]).unwrap();
+        assert!(session.connection_mut().in_transaction());
+        session.execute("ROLLBACK").remove(0).unwrap();
+        assert_eq!(session.connection_mut().count(TableId(1)).unwrap(), 52);
+
+        // Failing there undoes the batch but not the rest of the transaction
+        session.execute("BEGIN").remove(0).unwrap();
+        session
+            .execute("INSERT INTO 1 VALUES ('g')")
+            .remove(0)
+            .unwrap();
+        let failed = ["INSERT INTO 1 VALUES ('a')", "INSERT INTO 99 VALUES ('b')"];
+        assert_eq!(session.execute_batch(&failed).unwrap_err().code(), "42P01");
+        session.execute("COMMIT").remove(0).unwrap();
+        let rows: Vec<_> = session
+            .connection_mut()
+            .scan(TableId(1))
+            .unwrap()
+            .into_iter()
+            .map(|row| row.data)
+            .filter(|data| data.as_slice() != b"c")
+            .collect();
+        assert_eq!(rows, [b"d".to_vec(), b"e".to_vec(), b"g".to_vec()]);
+        assert_eq!(session.connection_mut().count(TableId(1)).unwrap(), 53);
+
+        // Users are held to their privileges
+        session
+            .execute("CREATE USER bob PASSWORD 'pw'")
+            .remove(0)
+            .unwrap();
+        let mut bob = SqlSession::for_user(database.connect(), "bob");
+        assert_eq!(bob.execute_batch(&batch[..1]).unwrap_err().code(), "42501");
+    }
+
+    #[test]
+    fn test_parse_errors_counted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.db_path = dir.path().to_str().unwrap().to_string();
+        let database = Database::with_config(&config).unwrap();
+        let mut session = SqlSession::new(database.connect());
+        assert!(session.execute("SELEKT")[0].is_err());
+        let results = session.execute_script("CREATE TABLE; SELEKT");
+        assert!(results[1].is_err());
+        assert!(session.execute_batch(&["SELEKT"]).is_err());
+        let metrics = database.metrics();
+        assert_eq!((metrics.queries, metrics.failed_queries), (4, 3));
+    }
+
+    #[test]
+    fn test_execute_script() {
+        let (_dir, database) = temp_database();
+        let mut connection = database.connect();
+        let codes = |results: &[Result<StatementResult, SqlError>]| -> Vec<String> {
+            results
+                .iter()
+                .map(|result| match result {
+                    Ok(result) => result.tag.clone(),
+                    Err(e) => e.code().to_string(),
+                })
+                .collect()
+        };
+
+        // Every statement runs, whether those before it failed or not
+        let results = connection.execute_script(
+            "CREATE TABLE; SELEKT; INSERT INTO 1 VALUES ('a');; SELECT * FROM 2;
+             INSERT INTO 1 VALUES (';')",
+        );
+        assert_eq!(
+            codes(&results),
+            ["CREATE TABLE", "42601", "INSERT 0 1", "42P01", "INSERT 0 1"]
+        );
+
+        // A failure ends the transaction it's in, and what follows up to
+        // its end is skipped
+        let results = connection.execute_script(
+            "BEGIN; INSERT INTO 1 VALUES ('b'); SELECT * FROM 2; INSERT INTO 1 VALUES ('c');
+             COMMIT; INSERT INTO 1 VALUES ('d')",
+        );
+        assert_eq!(
+            codes(&results),
+            [
+                "BEGIN",
+                "INSERT 0 1",
+                "42P01",
+                "25P02",
+                "ROLLBACK",
+                "INSERT 0 1"
+            ]
+        );
+        let rows: Vec<_> = connection
+            .scan(TableId(1))
+            .unwrap()
+            .into_iter()
+            .map(|row| row.data)
+            .collect();
+        assert_eq!(rows, [b"a".to_vec(), b";".to_vec(), b"d".to_vec()]);
+
+        // A transaction the script leaves open stays open
+        let results = connection.execute_script("BEGIN; INSERT INTO 1 VALUES ('e')");
+        assert_eq!(codes(&results), ["BEGIN", "INSERT 0 1"]);
+        assert!(connection.in_transaction());
+        let results = connection.execute_script("ROLLBACK; SELECT COUNT(*) FROM 1");
+        assert_eq!(results[1].as_ref().unwrap().rows, [[Some("3".to_string())]]);
+        let unterminated = connection.execute_script("SELECT * FROM 1; INSERT INTO 1 VALUES ('f");
+        assert_eq!(codes(&unterminated), ["42601"]);
+    }
+
+    #[test]
+    fn test_savepoints() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.db_path = dir.path().to_str().unwrap().to_string();
+        let database = Database::with_config(&config).unwrap();
+        let mut session = SqlSession::new(database.connect());
+        let mut run = |sql: &str| {
+            let results = session.execute(sql);
+            results
+                .into_iter()
+                .map(|result| match result {
+                    Ok(result) => result.tag,
+                    Err(e) => e.code().to_string(),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(run("SAVEPOINT a"), ["25P01"]);
+        assert_eq!(
+            run(
+                "CREATE TABLE; BEGIN; INSERT INTO 1 VALUES ('a'); SAVEPOINT s;
+                 INSERT INTO 1 VALUES ('b'); SAVEPOINT t; INSERT INTO 1 VALUES ('c');
+                 ROLLBACK TO s; INSERT INTO 1 VALUES ('d')"
+            ),
+            [
+                "CREATE TABLE",
+                "BEGIN",
+                "INSERT 0 1",
+                "SAVEPOINT",
+                "INSERT 0 1",
+                "SAVEPOINT",
+                "INSERT 0 1",
+                "ROLLBACK",
+                "INSERT 0 1"
+            ]
+        );
+        // Rolling back to a savepoint forgets those after it
+        assert_eq!(run("RELEASE t"), ["3B001"]);
+        assert_eq!(run("RELEASE s; ROLLBACK TO s"), ["RELEASE", "3B001"]);
+        assert_eq!(run("COMMIT"), ["COMMIT"]);
+        let rows: Vec<_> = session
+            .connection_mut()
+            .scan(TableId(1))
+            .unwrap()
+            .into_iter()
+            .map(|row| row.data)
+            .collect();
+        assert_eq!(rows, [b"a".to_vec(), b"d".to_vec()]);
+        assert_eq!(session.connection_mut().count(TableId(1)).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_select_as_of() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.db_path = dir.path().to_str().unwrap().to_string();
+        let database = Database::with_config(&config).unwrap();
+        let mut session = SqlSession::new(database.connect());
+        let mut run = |sql: &str| -> Result<Vec<String>, &'static str> {
+            let result = session.execute(sql).pop().unwrap().map_err(|e| e.code())?;
+            let values = result.rows.into_iter().map(|row| row.last().cloned());
+            Ok(values.map(|value| value.flatten().unwrap()).collect())
+        };
+
+        run("CREATE TABLE; INSERT INTO 1 VALUES ('a'), ('b')").unwrap();
+        thread::sleep(Duration::from_millis(5));
+        let before = Timestamp(SystemTime::now()).to_string();
+        thread::sleep(Duration::from_millis(5));
+        run("INSERT INTO 1 VALUES ('c'); UPDATE 1 SET data = 'd' WHERE id = '1:0:0'").unwrap();
+
+        let sql = format!(
+            "SELECT * FROM 1 AS OF TIMESTAMP '{}' ORDER BY data DESC",
+            before
+        );
+        assert_eq!(run(&sql).unwrap(), ["b", "a"]);
+        assert_eq!(run("SELECT * FROM 1").unwrap(), ["d", "b", "c"]);
+        // Before anything was committed
+        assert_eq!(
+            run("SELECT * FROM 1 AS OF LSN 0").unwrap(),
+            Vec::<String>::new()
+        );
+        assert_eq!(run("SELECT * FROM 1 AS OF TIMESTAMP 'then'"), Err("22007"));
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.db_path = dir.path().to_str().unwrap().to_string();
+        let database = Database::with_config(&config).unwrap();
+        let mut session = SqlSession::new(database.connect());
+        let results = session.execute("CREATE TABLE; INSERT INTO 1 VALUES ('a'); CHECKPOINT");
+        assert_eq!(results[2].as_ref().unwrap().tag, "CHECKPOINT");
+        assert_eq!(database.pages().stats().dirty_pages, 0);
+
+        // Only superusers may checkpoint
+        session
+            .execute("CREATE USER bob PASSWORD 'pw'")
+            .remove(0)
+            .unwrap();
+        let mut bob = SqlSession::for_user(database.connect(), "bob");
+        assert_eq!(
+            bob.execute("CHECKPOINT")[0].as_ref().unwrap_err().code(),
+            "42501"
+        );
+    }
+
+    #[test]
+    fn test_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup = tempfile::tempdir().unwrap();
+        let backup = backup.path().join("copy");
+        let mut config = Config::default();
+        config.storage.db_path = dir.path().to_str().unwrap().to_string();
+        let database = Database::with_config(&config).unwrap();
+        let mut session = SqlSession::new(database.connect());
+        let statement = format!("BACKUP TO '{}'", backup.display());
+        let results = session.execute(&format!(
+            "CREATE TABLE; INSERT INTO 1 VALUES ('a'); {statement}"
+        ));
+        let result = results[2].as_ref().unwrap();
+        assert_eq!(result.tag, "BACKUP");
+        assert_eq!(result.columns, columns(&["lsn"]));
+
+        config.storage.db_path = backup.to_str().unwrap().to_string();
+        let copy = Database::with_config(&config).unwrap();
+        assert_eq!(copy.connect().scan(TableId(1)).unwrap()[0].data, b"a");
+
+        let incremental = backup.with_file_name("changes");
+        let result = session
+            .execute(&format!(
+                "BACKUP TO '{}' INCREMENTAL FROM '{}'",
+                incremental.display(),
+                backup.display()
+            ))
+            .remove(0)
+            .unwrap();
+        assert_eq!(result.tag, "BACKUP");
+        assert!(session
+            .execute(&format!(
+                "BACKUP TO '{}' INCREMENTAL FROM '{}'",
+                incremental.with_file_name("more").display(),
+                incremental.with_file_name("missing").display()
+            ))
+            .remove(0)
+            .is_err());
+
+        // Only superusers may back up, as it writes the server's files
+        session
+            .execute("CREATE USER bob PASSWORD 'pw'")
+            .remove(0)
+            .unwrap();
+        let mut bob = SqlSession::for_user(database.connect(), "bob");
+        assert_eq!(
+            bob.execute(&statement)[0].as_ref().unwrap_err().code(),
+            "42501"
+        );
+    }
+
+    #[test]
+    fn test_prepared_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.db_path = dir.path().to_str().unwrap().to_string();
+        let tags = |results: Vec<Result<StatementResult, SqlError>>| -> Vec<String> {
+            results
+                .into_iter()
+                .map(|result| match result {
+                    Ok(result) => result.tag,
+                    Err(e) => e.code().to_string(),
+                })
+                .collect()
+        };
+        {
+            let database = Database::with_config(&config).unwrap();
+            let mut session = SqlSession::new(database.connect());
+            let results = session.execute(
+                "CREATE TABLE; CREATE TABLE; BEGIN; INSERT INTO 1 VALUES ('a');
+                 PREPARE TRANSACTION 'g'; BEGIN; INSERT INTO 2 VALUES ('b')",
+            );
+            assert_eq!(
+                tags(results),
+                [
+                    "CREATE TABLE",
+                    "CREATE TABLE",
+                    "BEGIN",
+                    "INSERT 0 1",
+                    "PREPARE TRANSACTION",
+                    "BEGIN",
+                    "INSERT 0 1"
+                ]
+            );
+            assert_eq!(tags(session.execute("COMMIT PREPARED 'g'")), ["25001"]);
+            // Both are listed, the prepared one by its global id
+            let listed = session
+                .execute("SELECT * FROM information_schema.transactions")
+                .remove(0)
+                .unwrap();
+            assert_eq!(listed.columns.len(), 6);
+            let listed: Vec<_> = listed
+                .rows
+                .iter()
+                .map(|row| (row[3].as_deref().unwrap(), row[5].as_deref()))
+                .collect();
+            assert_eq!(
+                listed,
+                [
+                    ("table 1 exclusive", Some("g")),
+                    ("table 2 exclusive", None)
+                ]
+            );
+            // Failing to prepare rolls the transaction back
+            assert_eq!(tags(session.execute("PREPARE TRANSACTION 'g'")), ["42710"]);
+            assert!(!session.connection_mut().in_transaction());
+            assert_eq!(database.prepared(), ["g"]);
+        }
+
+        // Prepared transactions outlast a restart, to be finished then
+        let database = Database::with_config(&config).unwrap();
+        let mut session = SqlSession::new(database.connect());
+        assert_eq!(database.prepared(), ["g"]);
+        assert_eq!(
+            tags(session.execute("COMMIT PREPARED 'g'; ROLLBACK PREPARED 'g'")),
+            ["COMMIT PREPARED", "42704"]
+        );
+        assert_eq!(
+            tags(session.execute("SELECT COUNT(*) FROM 1; SELECT COUNT(*) FROM 2")),
+            ["SELECT 1", "SELECT 1"]
+        );
+        let count = |session: &mut SqlSession, table| session.connection_mut().count(table);
+        assert_eq!(count(&mut session, TableId(1)).unwrap(), 1);
+        assert_eq!(count(&mut session, TableId(2)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_session_variables() {
+        let (_dir, database) = temp_database();
+        let table = database.create_table().unwrap();
+        let mut session = SqlSession::new(database.connect());
+        let mut run = |sql: &str| session.execute(sql).remove(0);
+        // None of the values shown here is NULL
+        let show = |result: Result<StatementResult, SqlError>| -> Vec<Vec<String>> {
+            let rows = result.unwrap().rows.into_iter();
+            rows.map(|row| row.into_iter().map(Option::unwrap).collect())
+                .collect()
+        };
+
+        // The session's own variables start at their defaults, are checked
+        // and are shown the one way
+        assert_eq!(show(run("SHOW statement_timeout")), [["0"]]);
+        run("SET statement_timeout = 2000").unwrap();
+        assert_eq!(show(run("SHOW Statement_Timeout")), [["2s"]]);
+        assert_eq!(
+            run("SET statement_timeout TO soon").unwrap_err().code(),
+            "22023"
+        );
+        run("SET transaction_isolation = 'READ COMMITTED'").unwrap();
+        run("SET application_name = psql").unwrap();
+        let all = show(run("SHOW ALL"));
+        let names: Vec<_> = all.iter().map(|row| row[0].as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "application_name",
+                "enable_mergejoin",
+                "output_format",
+                "statement_timeout",
+                "transaction_isolation"
+            ]
+        );
+        assert_eq!(all[0][1..], ["psql", ""]);
+        assert_eq!(all[4][1], "read committed");
+        assert_eq!(run("SHOW nothing").unwrap_err().code(), "42704");
+
+        // The planner merges joins unless told not to
+        let join = format!("EXPLAIN SELECT * FROM {table} JOIN {table} ON id = id");
+        assert!(show(run(&join))[0][0].starts_with("Merge join"));
+        run("SET enable_mergejoin = off").unwrap();
+        assert!(show(run(&join))[0][0].starts_with("Hash join"));
+
+        run("SET output_format = CSV").unwrap();
+        assert_eq!(session.output_format(), ResultFormat::Csv);
+
+        // A statement running past the timeout is cancelled, and the next
+        // one isn't
+        database.register_scalar_fn("slow", |args| {
+            thread::sleep(Duration::from_millis(100));
+            args[0].clone()
+        });
+        session.set("statement_timeout", "10ms").unwrap();
+        let insert = format!("INSERT INTO {table} VALUES (slow('a'))");
+        let error = session.execute(&insert).remove(0).unwrap_err();
+        assert_eq!(error.code(), "57014");
+        assert_eq!(
+            error.message(),
+            "canceling statement due to statement timeout"
+        );
+        let count = format!("SELECT COUNT(*) FROM {table}");
+        assert_eq!(show(session.execute(&count).remove(0)), [["0"]]);
+        session.set("statement_timeout", "0").unwrap();
+        session.execute(&insert).remove(0).unwrap();
+        assert_eq!(show(session.execute(&count).remove(0)), [["1"]]);
+    }
+
+    #[test]
+    fn test_user_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        let limits = UserLimits {
+            max_query_memory: Some(50),
+            max_rows: Some(3),
+            statement_timeout_ms: Some(5000),
+            ..UserLimits::default()
+        };
+        config.limits.users.insert("alice".to_string(), limits);
+        let database = Database::with_config(&config).unwrap();
+        let table = database.create_table().unwrap();
+        let mut session = SqlSession::new(database.connect());
+        for n in 0..5 {
+            let insert = format!(r#"INSERT INTO {table} VALUES ('{{"n": {n}}}')"#);
+            session.execute(&insert).remove(0).unwrap();
+        }
+
+        // A session without a user isn't limited
+        let select = format!("SELECT * FROM {table}");
+        assert_eq!(session.execute(&select).remove(0).unwrap().rows.len(), 5);
+        assert_eq!(session.statement_timeout(), None);
+
+        // One for a user is held to theirs, and the shorter timeout holds
+        let mut session = SqlSession::for_user(database.connect(), "alice");
+        assert_eq!(session.statement_timeout(), Some(Duration::from_secs(5)));
+        session.set("statement_timeout", "1s").unwrap();
+        assert_eq!(session.statement_timeout(), Some(Duration::from_secs(1)));
+        session.set("statement_timeout", "1h").unwrap();
+        assert_eq!(session.statement_timeout(), Some(Duration::from_secs(5)));
+        let mut run = |sql: &str| session.execute(sql).remove(0);
+        assert_eq!(run(&select).unwrap_err().code(), "53400");
+        let first = format!("SELECT * FROM {table} WHERE n = '0'");
+        assert_eq!(run(&first).unwrap().rows.len(), 1);
+
+        // Rows too many to send at once can be paged through, if there's
+        // memory to hold them
+        run("BEGIN").unwrap();
+        run(&format!("DECLARE c CURSOR FOR {select}")).unwrap();
+        assert_eq!(run("FETCH 3 FROM c").unwrap().rows.len(), 3);
+        assert_eq!(run("FETCH ALL FROM c").unwrap().rows.len(), 2);
+        let sorted = format!("{select} ORDER BY id DESC");
+        let declare = format!("DECLARE sorted CURSOR FOR {sorted}");
+        assert_eq!(run(&declare).unwrap_err().code(), "53400");
+    }
+}
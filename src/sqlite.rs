@@ -0,0 +1,827 @@
+//! Importing a SQLite database from the statements `sqlite3 FILE .dump`
+//! prints, which recreate its schema and rows.
+//!
+//! Each SQLite table becomes a new ferrodb table, and each of its rows a
+//! row whose data is a JSON object of the row's columns by name: numbers
+//! as numbers, text as strings, blobs as `\x`-prefixed hex strings, as
+//! Postgres writes `bytea`, and `NULL` as `null`. Indexes, views and
+//! triggers have nothing to become in ferrodb, so they're passed over, as
+//! is SQLite's own bookkeeping. The dump is read a statement at a time
+//! and rows are inserted in batches, so neither is ever held whole.
+
+use crate::database::{Connection, DatabaseError, TableId};
+use crate::encoding::json_string;
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use thiserror::Error;
+
+/// Rows inserted at a time.
+const BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Error)]
+pub enum SqliteImportError {
+    #[error("could not read the dump: {0}")]
+    Read(#[from] io::Error),
+
+    /// A statement that can't be imported, by the line it starts on
+    #[error("line {line}: {message}")]
+    Invalid { line: u64, message: String },
+
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+}
+
+/// A SQLite table and the ferrodb table its rows went into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedTable {
+    pub name: String,
+    pub table: TableId,
+    pub columns: Vec<String>,
+    pub rows: u64,
+}
+
+impl Connection<'_> {
+    /// Recreate the tables and rows of the SQLite dump read from `input`,
+    /// returning the tables in the order the dump creates them.
+    ///
+    /// The rows go in all together or, on failure, not at all, in a
+    /// transaction of their own unless one is open; tables are created as
+    /// the dump reaches them, so a failed import leaves them empty.
+    pub fn import_sqlite_dump(
+        &mut self,
+        input: impl BufRead,
+    ) -> Result<Vec<ImportedTable>, SqliteImportError> {
+        let implicit = !self.in_transaction();
+        if implicit {
+            self.begin()?;
+        }
+        let mut importer = Importer {
+            connection: self,
+            tables: Vec::new(),
+            names: HashMap::new(),
+            batch: Vec::with_capacity(BATCH_SIZE),
+        };
+        let imported = importer.import(Lexer::new(input));
+        match imported {
+            Ok(_) if implicit => self.commit()?,
+            Err(_) if implicit => self.rollback()?,
+            _ => {}
+        }
+        imported
+    }
+}
+
+struct Importer<'c, 'a> {
+    connection: &'c mut Connection<'a>,
+    tables: Vec<ImportedTable>,
+    /// Indexes into `tables` by lowercase name, as SQLite's names are
+    /// case-insensitive
+    names: HashMap<String, usize>,
+    /// Rows not yet inserted, all into the last table inserted into, with
+    /// the line of the statement each came from
+    batch: Vec<(usize, u64, Vec<u8>)>,
+}
+
+impl Importer<'_, '_> {
+    fn import(
+        &mut self,
+        mut lexer: Lexer<impl BufRead>,
+    ) -> Result<Vec<ImportedTable>, SqliteImportError> {
+        while let Some(mut statement) = lexer.statement()? {
+            self.statement(&mut statement)?;
+        }
+        self.flush()?;
+        Ok(std::mem::take(&mut self.tables))
+    }
+
+    fn statement(&mut self, statement: &mut Statement) -> Result<(), SqliteImportError> {
+        let first = match statement.next() {
+            Some(Token::Word(word)) => word.to_uppercase(),
+            _ => return Err(statement.error("expected a statement")),
+        };
+        match first.as_str() {
+            "CREATE" => {
+                // A temporary table is imported like any other
+                let _ = statement.keyword("TEMP") || statement.keyword("TEMPORARY");
+                if statement.keyword("TABLE") {
+                    self.create_table(statement)
+                } else {
+                    // Indexes, views, triggers and virtual tables, whose
+                    // contents are dumped as tables of their own
+                    Ok(())
+                }
+            }
+            "INSERT" => {
+                if statement.keyword("OR") {
+                    statement.next();
+                }
+                statement.expect_keyword("INTO")?;
+                self.insert(statement)
+            }
+            // Settings and transactions, which the import has its own of
+            "PRAGMA" | "BEGIN" | "COMMIT" | "END" | "ANALYZE" => Ok(()),
+            // Only SQLite's own tables are changed other than by inserts,
+            // such as `DELETE FROM sqlite_sequence`
+            "DELETE" | "UPDATE"
+                if statement
+                    .tokens
+                    .iter()
+                    .any(|token| token.name().is_some_and(is_internal)) =>
+            {
+                Ok(())
+            }
+            _ => Err(statement.error(format!("can't import {} statements", first))),
+        }
+    }
+
+    fn create_table(&mut self, statement: &mut Statement) -> Result<(), SqliteImportError> {
+        let if_not_exists = statement.keyword("IF");
+        if if_not_exists {
+            statement.expect_keyword("NOT")?;
+            statement.expect_keyword("EXISTS")?;
+        }
+        let name = statement.table_name()?;
+        if is_internal(&name) {
+            return Ok(());
+        }
+        if self.names.contains_key(&name.to_lowercase()) {
+            return if if_not_exists {
+                Ok(())
+            } else {
+                Err(statement.error(format!("table \"{}\" is created twice", name)))
+            };
+        }
+        if !statement.symbol('(') {
+            return Err(statement.error(format!("table \"{}\" has no columns listed", name)));
+        }
+        let mut columns = Vec::new();
+        loop {
+            // A definition is a column, or a constraint on the table
+            let first = statement.next();
+            let constraint = matches!(&first, Some(Token::Word(word))
+                if ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"]
+                    .iter()
+                    .any(|keyword| word.eq_ignore_ascii_case(keyword)));
+            if !constraint {
+                match first.as_ref().and_then(Token::name) {
+                    Some(column) => columns.push(column.to_string()),
+                    None => return Err(statement.error("expected a column name")),
+                }
+            }
+            match statement.skip_to_separator() {
+                Some(',') => {}
+                Some(_) => break,
+                None => return Err(statement.error("the column list doesn't end")),
+            }
+        }
+        let table = self.connection.database().create_table()?;
+        self.names.insert(name.to_lowercase(), self.tables.len());
+        self.tables.push(ImportedTable {
+            name,
+            table,
+            columns,
+            rows: 0,
+        });
+        Ok(())
+    }
+
+    fn insert(&mut self, statement: &mut Statement) -> Result<(), SqliteImportError> {
+        let name = statement.table_name()?;
+        if is_internal(&name) {
+            return Ok(());
+        }
+        let Some(&index) = self.names.get(&name.to_lowercase()) else {
+            return Err(statement.error(format!("no table named \"{}\" was created", name)));
+        };
+        let columns = if statement.symbol('(') {
+            let mut columns = Vec::new();
+            loop {
+                match statement.next().as_ref().and_then(Token::name) {
+                    Some(column) => columns.push(column.to_string()),
+                    None => return Err(statement.error("expected a column name")),
+                }
+                if !statement.symbol(',') {
+                    break;
+                }
+            }
+            statement.expect_symbol(')')?;
+            columns
+        } else {
+            self.tables[index].columns.clone()
+        };
+        statement.expect_keyword("VALUES")?;
+        loop {
+            statement.expect_symbol('(')?;
+            let mut values = Vec::new();
+            loop {
+                values.push(statement.value()?);
+                if !statement.symbol(',') {
+                    break;
+                }
+            }
+            statement.expect_symbol(')')?;
+            if values.len() != columns.len() {
+                return Err(statement.error(format!(
+                    "{} values for {} columns",
+                    values.len(),
+                    columns.len()
+                )));
+            }
+            let row = json_object(&columns, &values);
+            self.push(index, statement.line, row)?;
+            if !statement.symbol(',') {
+                break;
+            }
+        }
+        match statement.next() {
+            None => Ok(()),
+            Some(_) => Err(statement.error("expected the statement to end after its values")),
+        }
+    }
+
+    fn push(&mut self, table: usize, line: u64, row: Vec<u8>) -> Result<(), SqliteImportError> {
+        if self.batch.first().is_some_and(|(last, ..)| *last != table) {
+            self.flush()?;
+        }
+        self.batch.push((table, line, row));
+        if self.batch.len() == BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SqliteImportError> {
+        let Some(&(index, ..)) = self.batch.first() else {
+            return Ok(());
+        };
+        let rows: Vec<&[u8]> = self.batch.iter().map(|(_, _, row)| &row[..]).collect();
+        match self
+            .connection
+            .insert_batch(self.tables[index].table, &rows)
+        {
+            Ok(ids) => self.tables[index].rows += ids.len() as u64,
+            // Rows are checked in order before any goes in, so the first
+            // of its size failed
+            Err(e @ DatabaseError::RowTooLarge { size, .. }) => {
+                let (_, line, _) = self
+                    .batch
+                    .iter()
+                    .find(|(.., row)| row.len() == size)
+                    .unwrap();
+                return Err(SqliteImportError::Invalid {
+                    line: *line,
+                    message: e.to_string(),
+                });
+            }
+            Err(e) => return Err(e.into()),
+        }
+        self.batch.clear();
+        Ok(())
+    }
+}
+
+/// Whether `name` is one of the tables SQLite keeps for itself, such as
+/// `sqlite_sequence`.
+fn is_internal(name: &str) -> bool {
+    name.len() >= 7 && name[..7].eq_ignore_ascii_case("sqlite_")
+}
+
+/// A value of a row being inserted.
+#[derive(Debug, Clone, PartialEq)]
+enum SqlValue {
+    Null,
+    /// An integer or real, as the dump writes it
+    Number(String),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+fn json_object(columns: &[String], values: &[SqlValue]) -> Vec<u8> {
+    let mut out = String::from("{");
+    for (i, (column, value)) in columns.iter().zip(values).enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str(&json_string(column));
+        out.push(':');
+        match value {
+            SqlValue::Null => out.push_str("null"),
+            SqlValue::Number(number) if is_json_number(number) => out.push_str(number),
+            // Such as `.5`, which JSON has no way to write as it is
+            SqlValue::Number(number) => out.push_str(&json_string(number)),
+            SqlValue::Text(text) => out.push_str(&json_string(text)),
+            SqlValue::Blob(bytes) => {
+                let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                out.push_str(&json_string(&format!("\\x{}", hex)));
+            }
+        }
+    }
+    out.push('}');
+    out.into_bytes()
+}
+
+/// Whether `number` is written as JSON writes numbers: no leading zeros,
+/// and digits on both sides of any point.
+fn is_json_number(number: &str) -> bool {
+    let digits = |s: &str| s.chars().take_while(char::is_ascii_digit).count();
+    let rest = number.strip_prefix('-').unwrap_or(number);
+    let whole = digits(rest);
+    if whole == 0 || (whole > 1 && rest.starts_with('0')) {
+        return false;
+    }
+    let mut rest = &rest[whole..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = digits(fraction);
+        if len == 0 {
+            return false;
+        }
+        rest = &fraction[len..];
+    }
+    if let Some(exponent) = rest.strip_prefix(['e', 'E']) {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        let len = digits(exponent);
+        return len > 0 && len == exponent.len();
+    }
+    rest.is_empty()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A keyword or a name without quotes
+    Word(String),
+    /// A name in `"`, `` ` `` or `[]`
+    Quoted(String),
+    Text(String),
+    Blob(Vec<u8>),
+    Number(String),
+    Symbol(char),
+}
+
+impl Token {
+    /// The name the token is, if it can be one: SQLite takes a string
+    /// where it expects a name, too.
+    fn name(&self) -> Option<&str> {
+        match self {
+            Token::Word(name) | Token::Quoted(name) | Token::Text(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+}
+
+/// A statement's tokens, without its `;`, read from the front.
+struct Statement {
+    tokens: Vec<Token>,
+    at: usize,
+    /// The line it starts on
+    line: u64,
+}
+
+impl Statement {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.at).cloned();
+        self.at += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at)
+    }
+
+    /// Take the next token if it's `keyword`.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek().is_some_and(|token| token.is_keyword(keyword));
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), SqliteImportError> {
+        if self.keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected {}", keyword)))
+        }
+    }
+
+    /// Take the next token if it's `symbol`.
+    fn symbol(&mut self, symbol: char) -> bool {
+        let found = self.peek() == Some(&Token::Symbol(symbol));
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<(), SqliteImportError> {
+        if self.symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected \"{}\"", symbol)))
+        }
+    }
+
+    /// A table's name, without the schema it may be given in.
+    fn table_name(&mut self) -> Result<String, SqliteImportError> {
+        loop {
+            let name = match self.next().as_ref().and_then(Token::name) {
+                Some(name) => name.to_string(),
+                None => return Err(self.error("expected a table name")),
+            };
+            if !self.symbol('.') {
+                return Ok(name);
+            }
+        }
+    }
+
+    /// Pass over tokens up to a `,` or `)` outside any parentheses,
+    /// taking and returning it.
+    fn skip_to_separator(&mut self) -> Option<char> {
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                Token::Symbol('(') => depth += 1,
+                Token::Symbol(')') if depth > 0 => depth -= 1,
+                Token::Symbol(c @ (',' | ')')) if depth == 0 => return Some(c),
+                _ => {}
+            }
+        }
+    }
+
+    /// A value as `.dump` writes them: a literal, or the few functions it
+    /// writes text with characters that don't survive a line of SQL in.
+    fn value(&mut self) -> Result<SqlValue, SqliteImportError> {
+        Ok(match self.next() {
+            Some(Token::Number(number)) => SqlValue::Number(number),
+            Some(Token::Symbol(sign @ ('-' | '+'))) => match self.next() {
+                Some(Token::Number(number)) if sign == '-' => {
+                    SqlValue::Number(format!("-{}", number))
+                }
+                Some(Token::Number(number)) => SqlValue::Number(number),
+                _ => return Err(self.error("expected a number after the sign")),
+            },
+            Some(Token::Text(text)) => SqlValue::Text(text),
+            Some(Token::Blob(bytes)) => SqlValue::Blob(bytes),
+            Some(Token::Symbol('(')) => {
+                let value = self.value()?;
+                self.expect_symbol(')')?;
+                value
+            }
+            Some(Token::Word(word)) => match word.to_lowercase().as_str() {
+                "null" => SqlValue::Null,
+                "true" => SqlValue::Number("1".to_string()),
+                "false" => SqlValue::Number("0".to_string()),
+                function @ ("replace" | "char" | "unistr") => {
+                    self.expect_symbol('(')?;
+                    let mut arguments = Vec::new();
+                    loop {
+                        arguments.push(self.value()?);
+                        if !self.symbol(',') {
+                            break;
+                        }
+                    }
+                    self.expect_symbol(')')?;
+                    self.call(function, arguments)?
+                }
+                _ => return Err(self.error(format!("can't import the value {}", word))),
+            },
+            _ => return Err(self.error("expected a value")),
+        })
+    }
+
+    fn call(
+        &self,
+        function: &str,
+        arguments: Vec<SqlValue>,
+    ) -> Result<SqlValue, SqliteImportError> {
+        let bad = || self.error(format!("bad arguments to {}()", function));
+        Ok(match (function, arguments.as_slice()) {
+            ("replace", [SqlValue::Text(text), SqlValue::Text(from), SqlValue::Text(to)]) => {
+                SqlValue::Text(if from.is_empty() {
+                    text.clone()
+                } else {
+                    text.replace(from.as_str(), to)
+                })
+            }
+            ("char", codes) => SqlValue::Text(
+                codes
+                    .iter()
+                    .map(|code| match code {
+                        SqlValue::Number(code) => code
+                            .parse::<u32>()
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(bad),
+                        _ => Err(bad()),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            ("unistr", [SqlValue::Text(text)]) => SqlValue::Text(unistr(text).ok_or_else(bad)?),
+            _ => return Err(bad()),
+        })
+    }
+
+    fn error(&self, message: impl Into<String>) -> SqliteImportError {
+        SqliteImportError::Invalid {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+}
+
+/// The text of `unistr(text)`, in which `\XXXX`, `\uXXXX`, `\+XXXXXX` and
+/// `\UXXXXXXXX` are characters by their hex code and `\\` is `\`.
+fn unistr(text: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(at) = rest.find('\\') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at + 1..];
+        let (skip, digits) = match rest.chars().next()? {
+            '\\' => {
+                out.push('\\');
+                rest = &rest[1..];
+                continue;
+            }
+            'u' => (1, 4),
+            '+' => (1, 6),
+            'U' => (1, 8),
+            _ => (0, 4),
+        };
+        let code = rest.get(skip..skip + digits)?;
+        out.push(char::from_u32(u32::from_str_radix(code, 16).ok()?)?);
+        rest = &rest[skip + digits..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+/// Splits a dump into statements of tokens, reading it a line at a time.
+struct Lexer<R> {
+    input: R,
+    line: String,
+    /// The byte of `line` next read
+    at: usize,
+    /// The number of `line`, from 1
+    number: u64,
+}
+
+impl<R: BufRead> Lexer<R> {
+    fn new(input: R) -> Self {
+        Self {
+            input,
+            line: String::new(),
+            at: 0,
+            number: 0,
+        }
+    }
+
+    /// The next statement, if any are left.
+    fn statement(&mut self) -> Result<Option<Statement>, SqliteImportError> {
+        let mut tokens = Vec::new();
+        let mut line = 0;
+        loop {
+            let token = self.token()?;
+            if tokens.is_empty() {
+                line = self.number;
+            }
+            match token {
+                None if tokens.is_empty() => return Ok(None),
+                None => {
+                    return Err(SqliteImportError::Invalid {
+                        line,
+                        message: "the dump ends in a statement".to_string(),
+                    })
+                }
+                Some(Token::Symbol(';')) if tokens.is_empty() => {}
+                // A trigger's body has statements of its own, and only
+                // `END` ends it
+                Some(Token::Symbol(';'))
+                    if !is_trigger(&tokens)
+                        || tokens.last().is_some_and(|token| token.is_keyword("END")) =>
+                {
+                    return Ok(Some(Statement {
+                        tokens,
+                        at: 0,
+                        line,
+                    }));
+                }
+                Some(token) => tokens.push(token),
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<char>, SqliteImportError> {
+        if self.at == self.line.len() {
+            self.line.clear();
+            self.at = 0;
+            if self.input.read_line(&mut self.line)? == 0 {
+                return Ok(None);
+            }
+            self.number += 1;
+        }
+        Ok(self.line[self.at..].chars().next())
+    }
+
+    /// The character after the next, on the same line.
+    fn peek_second(&self) -> Option<char> {
+        self.line.get(self.at..)?.chars().nth(1)
+    }
+
+    fn next(&mut self) -> Result<Option<char>, SqliteImportError> {
+        let c = self.peek()?;
+        if let Some(c) = c {
+            self.at += c.len_utf8();
+        }
+        Ok(c)
+    }
+
+    fn token(&mut self) -> Result<Option<Token>, SqliteImportError> {
+        loop {
+            let Some(c) = self.peek()? else {
+                return Ok(None);
+            };
+            let second = self.peek_second();
+            match c {
+                c if c.is_whitespace() => {
+                    self.next()?;
+                }
+                '-' if second == Some('-') => {
+                    self.at = self.line.len();
+                }
+                '/' if second == Some('*') => {
+                    self.next()?;
+                    self.next()?;
+                    let mut last = None;
+                    loop {
+                        match self.next()? {
+                            Some('/') if last == Some('*') => break,
+                            Some(c) => last = Some(c),
+                            None => return Ok(None),
+                        }
+                    }
+                }
+                _ => break,
+            }
+        }
+        let start = self.number;
+        let c = self.next()?.unwrap();
+        let unterminated = || SqliteImportError::Invalid {
+            line: start,
+            message: "a quoted string or name doesn't end".to_string(),
+        };
+        Ok(Some(match c {
+            '\'' => Token::Text(self.quoted('\'').ok_or_else(unterminated)?),
+            '"' | '`' => Token::Quoted(self.quoted(c).ok_or_else(unterminated)?),
+            '[' => Token::Quoted(self.quoted(']').ok_or_else(unterminated)?),
+            'x' | 'X' if self.peek()? == Some('\'') => {
+                self.next()?;
+                let hex = self.quoted('\'').ok_or_else(unterminated)?;
+                let bytes = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| {
+                        hex.get(i..i + 2)
+                            .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    })
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| SqliteImportError::Invalid {
+                        line: start,
+                        message: format!("X'{}' isn't a blob", hex),
+                    })?;
+                Token::Blob(bytes)
+            }
+            c if c.is_ascii_digit()
+                || (c == '.' && self.peek()?.is_some_and(|c| c.is_ascii_digit())) =>
+            {
+                let mut number = c.to_string();
+                while let Some(c) = self.peek()? {
+                    let exponent_sign = matches!(c, '+' | '-') && number.ends_with(['e', 'E']);
+                    if !(c.is_ascii_alphanumeric() || c == '.' || exponent_sign) {
+                        break;
+                    }
+                    number.push(c);
+                    self.next()?;
+                }
+                Token::Number(number)
+            }
+            c if c.is_alphanumeric() || c == '_' || c == '$' => {
+                let mut word = c.to_string();
+                while let Some(c) = self.peek()? {
+                    if !(c.is_alphanumeric() || c == '_' || c == '$') {
+                        break;
+                    }
+                    word.push(c);
+                    self.next()?;
+                }
+                Token::Word(word)
+            }
+            c => Token::Symbol(c),
+        }))
+    }
+
+    /// The rest of a string or name ending in `end`, in which `end` is
+    /// doubled to be taken as itself, or `None` if it doesn't end.
+    fn quoted(&mut self, end: char) -> Option<String> {
+        let mut text = String::new();
+        loop {
+            match self.next().ok()?? {
+                c if c == end => {
+                    if end != ']' && self.peek().ok()? == Some(end) {
+                        self.next().ok()?;
+                        text.push(end);
+                    } else {
+                        return Some(text);
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+    }
+}
+
+fn is_trigger(tokens: &[Token]) -> bool {
+    let mut tokens = tokens.iter();
+    tokens
+        .next()
+        .is_some_and(|token| token.is_keyword("CREATE"))
+        && tokens.take(2).any(|token| token.is_keyword("TRIGGER"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::open_logged;
+
+    #[test]
+    fn test_import_sqlite_dump() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = open_logged(&dir.path().join("db"));
+        let mut connection = database.connect();
+
+        let dump = "\
+PRAGMA foreign_keys=OFF;
+BEGIN TRANSACTION;
+CREATE TABLE users(id INTEGER PRIMARY KEY AUTOINCREMENT, \"full name\" TEXT NOT NULL,
+  score REAL CHECK (score >= 0), avatar BLOB, UNIQUE(\"full name\"));
+INSERT INTO users VALUES(1,'O''Brien',1.5,X'0aff');
+INSERT INTO users VALUES(2,replace('one\\ntwo','\\n',char(10)),-3,NULL);
+INSERT INTO users VALUES(3,unistr('caf\\u00e9'),.5,NULL);
+DELETE FROM sqlite_sequence;
+INSERT INTO sqlite_sequence VALUES('users',3);
+CREATE TABLE [Empty] (x); -- nothing goes in
+CREATE INDEX users_score ON users(score);
+CREATE TRIGGER touch AFTER UPDATE ON users BEGIN
+  UPDATE users SET score = 0 WHERE id = new.id;
+END;
+/* a comment; with a semicolon */
+INSERT INTO \"USERS\"(\"full name\", id) VALUES('four', 4), ('five', 5);
+COMMIT;
+";
+        let tables = connection.import_sqlite_dump(dump.as_bytes()).unwrap();
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables[0].name, "users");
+        assert_eq!(tables[0].columns, ["id", "full name", "score", "avatar"]);
+        assert_eq!(tables[0].rows, 5);
+        assert_eq!(tables[1].name, "Empty");
+        assert_eq!(tables[1].rows, 0);
+
+        let rows = connection.scan(tables[0].table).unwrap();
+        let data: Vec<String> = rows
+            .into_iter()
+            .map(|row| String::from_utf8(row.data).unwrap())
+            .collect();
+        assert_eq!(
+            data,
+            [
+                r#"{"id":1,"full name":"O'Brien","score":1.5,"avatar":"\\x0aff"}"#,
+                r#"{"id":2,"full name":"one\ntwo","score":-3,"avatar":null}"#,
+                r#"{"id":3,"full name":"café","score":".5","avatar":null}"#,
+                r#"{"full name":"four","id":4}"#,
+                r#"{"full name":"five","id":5}"#,
+            ]
+        );
+
+        // A bad statement imports no rows, and says where it is
+        let error = connection
+            .import_sqlite_dump(
+                "CREATE TABLE t(a);\nINSERT INTO t VALUES(1);\nINSERT INTO t VALUES(1, 2);\n"
+                    .as_bytes(),
+            )
+            .unwrap_err();
+        assert!(matches!(error, SqliteImportError::Invalid { line: 3, .. }));
+        let error = connection
+            .import_sqlite_dump("DROP TABLE users;".as_bytes())
+            .unwrap_err();
+        assert!(matches!(error, SqliteImportError::Invalid { line: 1, .. }));
+        assert_eq!(database.tables().len(), 3);
+        assert!(connection.scan(database.tables()[2]).unwrap().is_empty());
+
+        assert!(is_json_number("-0.5e+10"));
+        assert!(!is_json_number("01"));
+        assert!(!is_json_number("5."));
+    }
+}
@@ -0,0 +1,329 @@
+//! Table statistics, as `ANALYZE` gathers them, and the estimates of how
+//! many rows a predicate matches that are drawn from them.
+//!
+//! For a table's data and each column of its rows that are JSON objects,
+//! `ANALYZE` counts the rows with a value and the distinct values, keeps
+//! the most common values with how often each occurs, and divides the
+//! rest into an equi-depth histogram: bounds between which about as many
+//! values fall. Values are ordered as range partitions order keys, so
+//! numbers by value. An equality is estimated from a common value's count
+//! or the other values' average, and a range from the common values in it
+//! and the share of the histogram it covers.
+//!
+//! Statistics are kept in memory until `ANALYZE` runs again or the
+//! database closes. A table without them, or not analyzed since opening,
+//! is estimated with fixed fractions, as Postgres does.
+
+use crate::database::{Database, DatabaseError, TableId};
+use crate::mapping::column_values;
+use crate::partition::compare;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+
+/// The most common values kept for each column.
+const MOST_COMMON: usize = 10;
+
+/// The buckets of each column's histogram.
+const BUCKETS: usize = 16;
+
+/// The share of rows an equality is taken to match without statistics.
+pub(crate) const DEFAULT_EQUALITY_SELECTIVITY: f64 = 0.005;
+
+/// The share of rows a range is taken to match without statistics.
+pub(crate) const DEFAULT_RANGE_SELECTIVITY: f64 = 1.0 / 3.0;
+
+/// What `ANALYZE` found of one column's values.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ColumnStatistics {
+    /// The rows with a value in the column
+    pub(crate) values: u64,
+    pub(crate) distinct: u64,
+    /// The values more common than the average, most common first, with
+    /// the rows having each
+    pub(crate) most_common: Vec<(String, u64)>,
+    /// The bounds of the histogram of the other values, lowest first: the
+    /// least value, the greatest, and those between buckets
+    pub(crate) histogram: Vec<String>,
+}
+
+/// What `ANALYZE` found of a table.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct TableStatistics {
+    pub(crate) rows: u64,
+    /// The pages holding them, across its partitions if it has them
+    pub(crate) pages: u64,
+    /// The statistics of the rows' data as a whole
+    pub(crate) data: ColumnStatistics,
+    pub(crate) columns: HashMap<String, ColumnStatistics>,
+}
+
+/// A condition on a column's value to estimate the rows matching.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Predicate<'a> {
+    Equal(&'a str),
+    // No statement filters on a range yet, so only the tests estimate one
+    #[allow(dead_code)]
+    Range(Bound<&'a str>, Bound<&'a str>),
+}
+
+impl Predicate<'_> {
+    fn matches(&self, value: &str) -> bool {
+        match *self {
+            Predicate::Equal(wanted) => value == wanted,
+            Predicate::Range(low, high) => {
+                let order = |bound: &str| compare(value.as_bytes(), bound.as_bytes());
+                let above = match low {
+                    Bound::Included(low) => order(low) != Ordering::Less,
+                    Bound::Excluded(low) => order(low) == Ordering::Greater,
+                    Bound::Unbounded => true,
+                };
+                let below = match high {
+                    Bound::Included(high) => order(high) != Ordering::Greater,
+                    Bound::Excluded(high) => order(high) == Ordering::Less,
+                    Bound::Unbounded => true,
+                };
+                above && below
+            }
+        }
+    }
+}
+
+impl ColumnStatistics {
+    /// The statistics of `values`, every one a row's.
+    fn of(values: Vec<String>) -> Self {
+        let mut counts = HashMap::<&str, u64>::new();
+        for value in &values {
+            *counts.entry(value).or_default() += 1;
+        }
+        let distinct = counts.len() as u64;
+        let average = values.len() as f64 / distinct.max(1) as f64;
+        let mut common: Vec<(String, u64)> = counts
+            .into_iter()
+            .filter(|&(_, count)| count > 1 && count as f64 > average)
+            .map(|(value, count)| (value.to_string(), count))
+            .collect();
+        common.sort_by(|(a, a_count), (b, b_count)| {
+            b_count
+                .cmp(a_count)
+                .then_with(|| compare(a.as_bytes(), b.as_bytes()))
+        });
+        common.truncate(MOST_COMMON);
+
+        let count = values.len() as u64;
+        let kept: HashSet<&str> = common.iter().map(|(value, _)| value.as_str()).collect();
+        let mut rest: Vec<&String> = values
+            .iter()
+            .filter(|value| !kept.contains(value.as_str()))
+            .collect();
+        rest.sort_by(|a, b| compare(a.as_bytes(), b.as_bytes()));
+        let buckets = BUCKETS.min(rest.len().saturating_sub(1));
+        let histogram = match rest.len() {
+            0 => Vec::new(),
+            1 => vec![rest[0].clone()],
+            n => (0..=buckets)
+                .map(|i| rest[i * (n - 1) / buckets].clone())
+                .collect(),
+        };
+        Self {
+            values: count,
+            distinct,
+            most_common: common,
+            histogram,
+        }
+    }
+
+    /// The share of `rows` rows whose value in the column matches
+    /// `predicate`.
+    fn selectivity(&self, rows: u64, predicate: Predicate) -> f64 {
+        if rows == 0 {
+            return 0.0;
+        }
+        let common_rows: u64 = self.most_common.iter().map(|(_, count)| count).sum();
+        let other_rows = self.values.saturating_sub(common_rows) as f64;
+        let matching_common: u64 = self
+            .most_common
+            .iter()
+            .filter(|(value, _)| predicate.matches(value))
+            .map(|(_, count)| count)
+            .sum();
+        let matching_other = match predicate {
+            // A value that isn't common is taken to be as common as the
+            // other uncommon ones are on average
+            Predicate::Equal(_) if matching_common > 0 => 0.0,
+            Predicate::Equal(_) => {
+                let others = self.distinct.saturating_sub(self.most_common.len() as u64);
+                other_rows / others.max(1) as f64
+            }
+            Predicate::Range(..) => other_rows * self.histogram_share(predicate),
+        };
+        ((matching_common as f64 + matching_other) / rows as f64).min(1.0)
+    }
+
+    /// The share of the histogram's values within the range `predicate`:
+    /// all of each bucket it covers and half of each it only overlaps.
+    fn histogram_share(&self, predicate: Predicate) -> f64 {
+        match self.histogram.as_slice() {
+            [] => 0.0,
+            [value] => f64::from(u8::from(predicate.matches(value))),
+            bounds => {
+                let covered: f64 = bounds
+                    .windows(2)
+                    .map(|bucket| {
+                        match (predicate.matches(&bucket[0]), predicate.matches(&bucket[1])) {
+                            (true, true) => 1.0,
+                            (false, false) if !overlaps(predicate, &bucket[0], &bucket[1]) => 0.0,
+                            _ => 0.5,
+                        }
+                    })
+                    .sum();
+                covered / (bounds.len() - 1) as f64
+            }
+        }
+    }
+}
+
+/// Whether the range `predicate` falls strictly inside the bucket from
+/// `low` to `high`, neither bound of which it matches.
+fn overlaps(predicate: Predicate, low: &str, high: &str) -> bool {
+    let Predicate::Range(from, to) = predicate else {
+        return false;
+    };
+    let inside = |bound: Bound<&str>| match bound {
+        Bound::Included(value) | Bound::Excluded(value) => {
+            compare(value.as_bytes(), low.as_bytes()) == Ordering::Greater
+                && compare(value.as_bytes(), high.as_bytes()) == Ordering::Less
+        }
+        Bound::Unbounded => false,
+    };
+    inside(from) || inside(to)
+}
+
+impl Database {
+    /// Gather the statistics of `table` from every row it has, replacing
+    /// those it had.
+    pub fn analyze(&self, table: TableId) -> Result<(), DatabaseError> {
+        if !self.tables().contains(&table) {
+            return Err(DatabaseError::NoSuchTable(table));
+        }
+        let mut rows = 0;
+        let mut pages = HashSet::new();
+        let mut data = Vec::new();
+        let mut columns = HashMap::<String, Vec<String>>::new();
+        self.connect().scan_each(table, |row| {
+            rows += 1;
+            pages.insert((row.id.table, row.id.page_no));
+            for (column, value) in column_values(&row.data) {
+                columns.entry(column).or_default().push(value);
+            }
+            data.push(String::from_utf8_lossy(&row.data).into_owned());
+            Ok::<_, DatabaseError>(())
+        })?;
+        let statistics = TableStatistics {
+            rows,
+            pages: pages.len() as u64,
+            data: ColumnStatistics::of(data),
+            columns: columns
+                .into_iter()
+                .map(|(column, values)| (column, ColumnStatistics::of(values)))
+                .collect(),
+        };
+        self.statistics.write().unwrap().insert(table, statistics);
+        Ok(())
+    }
+
+    /// The rows and pages `table` had when last analyzed, if it has been.
+    pub(crate) fn analyzed_size(&self, table: TableId) -> Option<(u64, u64)> {
+        let statistics = self.statistics.read().unwrap();
+        let table = statistics.get(&table)?;
+        Some((table.rows, table.pages))
+    }
+
+    /// The share of `table`'s rows estimated to match `predicate` on
+    /// `column`, or on their data for `None`.
+    pub(crate) fn selectivity(
+        &self,
+        table: TableId,
+        column: Option<&str>,
+        predicate: Predicate,
+    ) -> f64 {
+        let statistics = self.statistics.read().unwrap();
+        let Some(table) = statistics.get(&table) else {
+            return match predicate {
+                Predicate::Equal(_) => DEFAULT_EQUALITY_SELECTIVITY,
+                Predicate::Range(..) => DEFAULT_RANGE_SELECTIVITY,
+            };
+        };
+        let column = match column {
+            Some(column) => table.columns.get(column),
+            None => Some(&table.data),
+        };
+        // No row had the column when the table was analyzed
+        column.map_or(0.0, |column| column.selectivity(table.rows, predicate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::temp_database;
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_statistics() {
+        let (_dir, database) = temp_database();
+        let table = database.create_table().unwrap();
+        let mut connection = database.connect();
+        // Six in ten rows are "open", the rest of the states unique
+        let rows: Vec<String> = (0..200)
+            .map(|n| {
+                let state = if n % 10 < 6 {
+                    "open".to_string()
+                } else {
+                    format!("closed {n}")
+                };
+                format!(r#"{{"n": {n}, "state": "{state}"}}"#)
+            })
+            .collect();
+        connection.insert_batch(table, &rows).unwrap();
+        let estimate = |column, predicate| database.selectivity(table, Some(column), predicate);
+        let between = |low, high| Predicate::Range(Bound::Included(low), Bound::Excluded(high));
+
+        assert_eq!(
+            estimate("state", Predicate::Equal("open")),
+            DEFAULT_EQUALITY_SELECTIVITY
+        );
+        assert_eq!(estimate("n", between("0", "50")), DEFAULT_RANGE_SELECTIVITY);
+
+        let mut session = SqlSession::new(database.connect());
+        let result = session.execute(&format!("ANALYZE {}", table.0)).remove(0);
+        assert_eq!(result.unwrap().tag, "ANALYZE");
+        assert_eq!(database.analyzed_size(table), Some((200, 2)));
+        let statistics = database.statistics.read().unwrap()[&table].clone();
+        assert_eq!(statistics.rows, 200);
+        assert_eq!(statistics.data.distinct, 200);
+        assert_eq!(statistics.columns["state"].distinct, 81);
+        assert_eq!(
+            statistics.columns["state"].most_common,
+            vec![("open".to_string(), 120)]
+        );
+
+        assert_eq!(estimate("state", Predicate::Equal("open")), 0.6);
+        assert_eq!(estimate("state", Predicate::Equal("closed 9")), 1.0 / 200.0);
+        assert_eq!(estimate("missing", Predicate::Equal("open")), 0.0);
+        // Numbers are ordered by value, so 0 to 49 are a quarter of them
+        let quarter = estimate("n", between("0", "50"));
+        assert!((0.2..0.3).contains(&quarter), "{}", quarter);
+        assert!(estimate("n", between("300", "400")) < 0.05);
+        assert_eq!(
+            estimate("n", Predicate::Range(Bound::Unbounded, Bound::Unbounded)),
+            1.0
+        );
+        let data = r#"{"n": 7, "state": "open"}"#;
+        assert_eq!(
+            database.selectivity(table, None, Predicate::Equal(data)),
+            1.0 / 200.0
+        );
+        assert!(session.execute("ANALYZE 99").remove(0).is_err());
+    }
+}
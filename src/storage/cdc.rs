@@ -0,0 +1,286 @@
+use super::page::{Page, PageId};
+use super::slotted_page::{RecordKind, SlotId, SlottedPage};
+use super::wal::{Lsn, TxnId, Wal, WalError, WalIter, WalRecord};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// A record as it was on one side of a change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub kind: RecordKind,
+    pub data: Vec<u8>,
+}
+
+/// How a record changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RowChange {
+    Insert { after: Record },
+    Update { before: Record, after: Record },
+    Delete { before: Record },
+}
+
+/// A change to one record of a slotted page, made by a committed
+/// transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent {
+    pub txn: TxnId,
+    /// The LSN of the transaction's commit record
+    pub commit_lsn: Lsn,
+    /// Microseconds since the Unix epoch when the transaction committed
+    pub commit_time: u64,
+    pub page_id: PageId,
+    pub slot: SlotId,
+    pub change: RowChange,
+}
+
+/// A page changed by a transaction that hasn't finished yet: its contents
+/// before the first change and after the latest.
+struct PageChange {
+    page_id: PageId,
+    before: Vec<u8>,
+    after: Vec<u8>,
+}
+
+/// Record-level changes decoded from the log, one transaction at a time as
+/// each commits.
+///
+/// Each transaction's page writes are held back until its commit record,
+/// then every slotted page it changed is compared before and after to find
+/// the records inserted, updated and deleted; pages that aren't slotted
+/// pages are skipped, as are overflow records, which are pieces of the
+/// spilled rows. Aborted transactions produce nothing, and only
+/// durable commits are reported.
+///
+/// The stream follows the log: `next` returns `None` once it has caught up,
+/// and can be called again later to pick up newer commits. Changes made by
+/// a transaction before `from` are missed, so start from a point where
+/// none was running, such as a checkpoint's recovery start.
+pub struct ChangeStream<'a> {
+    wal: &'a Wal,
+    records: Option<WalIter>,
+    /// Where to read from once the current records run out
+    next: Lsn,
+    pending: HashMap<TxnId, Vec<PageChange>>,
+    ready: VecDeque<ChangeEvent>,
+}
+
+impl<'a> ChangeStream<'a> {
+    /// Stream the changes committed from `from` onwards.
+    pub fn new(wal: &'a Wal, from: Lsn) -> Self {
+        Self {
+            wal,
+            records: None,
+            next: from,
+            pending: HashMap::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Read the next durable record, or `None` if there isn't one yet.
+    fn next_record(&mut self) -> Result<Option<(Lsn, WalRecord)>, WalError> {
+        let flushed = self.wal.flushed();
+        if self.next >= flushed {
+            return Ok(None);
+        }
+        let records = match &mut self.records {
+            Some(records) => records,
+            None => self.records.insert(self.wal.read_from(self.next)?),
+        };
+        match records.next().transpose()? {
+            Some((lsn, record)) if lsn < flushed => {
+                self.next = Lsn(lsn.0 + 1);
+                Ok(Some((lsn, record)))
+            }
+            // Come back once more of the log is durable
+            _ => {
+                self.records = None;
+                Ok(None)
+            }
+        }
+    }
+
+    fn apply(&mut self, lsn: Lsn, record: WalRecord) {
+        match record {
+            WalRecord::PageWrite {
+                txn,
+                page_id,
+                before,
+                after,
+            } => {
+                let changes = self.pending.entry(txn).or_default();
+                match changes.iter_mut().find(|change| change.page_id == page_id) {
+                    Some(change) => change.after = after,
+                    None => changes.push(PageChange {
+                        page_id,
+                        before,
+                        after,
+                    }),
+                }
+            }
+            WalRecord::Commit { txn, time } => {
+                for change in self.pending.remove(&txn).unwrap_or_default() {
+                    for (slot, row) in diff(&change.before, &change.after) {
+                        self.ready.push_back(ChangeEvent {
+                            txn,
+                            commit_lsn: lsn,
+                            commit_time: time,
+                            page_id: change.page_id,
+                            slot,
+                            change: row,
+                        });
+                    }
+                }
+            }
+            WalRecord::Abort { txn } => {
+                self.pending.remove(&txn);
+            }
+            WalRecord::Begin { .. } | WalRecord::Prepare { .. } | WalRecord::Checkpoint { .. } => {}
+        }
+    }
+}
+
+impl Iterator for ChangeStream<'_> {
+    type Item = Result<ChangeEvent, WalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.ready.is_empty() {
+            match self.next_record() {
+                Ok(Some((lsn, record))) => self.apply(lsn, record),
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.ready.pop_front().map(Ok)
+    }
+}
+
+/// The records that differ between two images of a slotted page, in slot
+/// order. Nothing if either image isn't a valid slotted page.
+fn diff(before: &[u8], after: &[u8]) -> Vec<(SlotId, RowChange)> {
+    let records = |image: &[u8]| -> Option<BTreeMap<SlotId, Record>> {
+        let page = SlottedPage::from_page(Page::new(image.to_vec())).ok()?;
+        let records = page.entries().ok()?;
+        Some(
+            records
+                .into_iter()
+                .filter(|&(_, kind, _)| kind != RecordKind::Overflow)
+                .map(|(slot, kind, data)| {
+                    let data = data.to_vec();
+                    (slot, Record { kind, data })
+                })
+                .collect(),
+        )
+    };
+    let (mut before, after) = match (records(before), records(after)) {
+        (Some(before), Some(after)) => (before, after),
+        _ => return Vec::new(),
+    };
+
+    let mut changes = Vec::new();
+    for (slot, after) in after {
+        match before.remove(&slot) {
+            None => changes.push((slot, RowChange::Insert { after })),
+            Some(before) if before != after => {
+                changes.push((slot, RowChange::Update { before, after }))
+            }
+            Some(_) => {}
+        }
+    }
+    changes.extend(
+        before
+            .into_iter()
+            .map(|(slot, before)| (slot, RowChange::Delete { before })),
+    );
+    changes.sort_by_key(|&(slot, _)| slot);
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Durability;
+    use crate::storage::file_manager::FileId;
+    use crate::storage::wal::WalOptions;
+
+    const PAGE_SIZE: usize = 128;
+
+    fn row(data: &[u8]) -> Record {
+        Record {
+            kind: RecordKind::Row,
+            data: data.to_vec(),
+        }
+    }
+
+    fn slotted(records: &[&[u8]]) -> SlottedPage {
+        let mut page = SlottedPage::new(PAGE_SIZE);
+        for record in records {
+            page.insert(record).unwrap().unwrap();
+        }
+        page
+    }
+
+    fn write(wal: &Wal, txn: u64, before: &SlottedPage, after: &SlottedPage) {
+        wal.append(&WalRecord::PageWrite {
+            txn: TxnId(txn),
+            page_id: PageId::new(FileId(1), 0),
+            before: before.as_page().as_bytes().to_vec(),
+            after: after.as_page().as_bytes().to_vec(),
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_committed_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal = Wal::open(
+            dir.path(),
+            WalOptions {
+                segment_size: 1 << 20,
+                durability: Durability::None,
+                commit_delay: None,
+                retention: None,
+                archive_dir: None,
+                full_page_writes: true,
+                faults: None,
+            },
+        )
+        .unwrap();
+        let empty = SlottedPage::new(PAGE_SIZE);
+        let one = slotted(&[b"a"]);
+        let two = slotted(&[b"a", b"b"]);
+        write(&wal, 1, &empty, &one);
+        write(&wal, 1, &one, &two);
+        // Aborted, so never seen
+        write(&wal, 2, &two, &empty);
+        wal.append(&WalRecord::Abort { txn: TxnId(2) }).unwrap();
+
+        let mut stream = ChangeStream::new(&wal, Lsn::ZERO);
+        let commit = wal.append(&WalRecord::commit(TxnId(1))).unwrap();
+        // Nothing until the commit is durable
+        assert!(stream.next().is_none());
+        wal.flush_all().unwrap();
+        let events: Vec<_> = stream.by_ref().map(|event| event.unwrap()).collect();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event.commit_lsn == commit));
+        assert_eq!(events[1].change, RowChange::Insert { after: row(b"b") });
+
+        // The stream picks up later commits
+        let mut after = slotted(&[b"a", b"b"]);
+        after.delete(SlotId(0)).unwrap();
+        after.delete(SlotId(1)).unwrap();
+        after.insert(b"c").unwrap();
+        write(&wal, 3, &two, &after);
+        wal.append(&WalRecord::commit(TxnId(3))).unwrap();
+        wal.flush_all().unwrap();
+        let changes: Vec<_> = stream.map(|event| event.unwrap().change).collect();
+        assert_eq!(
+            changes,
+            vec![
+                RowChange::Update {
+                    before: row(b"a"),
+                    after: row(b"c")
+                },
+                RowChange::Delete { before: row(b"b") },
+            ]
+        );
+    }
+}
@@ -0,0 +1,443 @@
+use super::compression::lz;
+use super::encryption::{EncryptionError, PageCipher, NONCE_SIZE, TAG_SIZE};
+use super::file_manager::FileId;
+use super::page::{Page, PageDecodeError, PageId};
+use super::wal::Lsn;
+use crate::config::Compression;
+use byteorder::{BigEndian, ByteOrder};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Bytes reserved at the start of every stored page once any of the
+/// features below is enabled: the codec used for the page, then the length
+/// of the stored payload.
+pub const PAGE_HEADER_SIZE: usize = 8;
+
+/// Extra header bytes holding the page's LSN, when the database is logged.
+pub const LSN_SIZE: usize = 8;
+
+/// Extra header bytes holding each page's nonce and tag, when encrypted.
+pub const ENCRYPTION_HEADER_SIZE: usize = NONCE_SIZE + TAG_SIZE;
+
+const CODEC_STORED: u8 = 0;
+const CODEC_LZ: u8 = 1;
+
+/// Translates between the pages the buffer pool hands out and the pages
+/// stored on disk.
+///
+/// With no compression, encryption or logging the two are identical.
+/// Otherwise each stored page starts with a header, leaving that much less
+/// room for callers. A logged database records each page's LSN there. The
+/// payload is compressed whenever that makes it smaller, then encrypted with
+/// a fresh nonce; the page's id is authenticated along with it so pages
+/// can't be swapped around on disk.
+///
+/// A file may be compressed differently from the rest, as a table's
+/// options ask, once the header is there to record how each page was.
+pub struct PageCodec {
+    compression: Compression,
+    files: RwLock<HashMap<FileId, Compression>>,
+    cipher: Option<PageCipher>,
+    logged: bool,
+}
+
+impl PageCodec {
+    pub fn new(compression: Compression, cipher: Option<PageCipher>, logged: bool) -> Self {
+        Self {
+            compression,
+            files: RwLock::default(),
+            cipher,
+            logged,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.compression != Compression::None || self.cipher.is_some() || self.logged
+    }
+
+    /// Compress the pages of `file` with `compression` from now on, rather
+    /// than as the database's are. Pages already written keep theirs until
+    /// they are written again.
+    pub fn set_file_compression(&self, file: FileId, compression: Compression) {
+        self.files.write().unwrap().insert(file, compression);
+    }
+
+    fn header_size(&self) -> usize {
+        if !self.is_enabled() {
+            return 0;
+        }
+        let mut size = self.nonce_offset();
+        if self.cipher.is_some() {
+            size += ENCRYPTION_HEADER_SIZE;
+        }
+        size
+    }
+
+    /// Where the nonce and tag start, after the LSN if there is one.
+    fn nonce_offset(&self) -> usize {
+        if self.logged {
+            PAGE_HEADER_SIZE + LSN_SIZE
+        } else {
+            PAGE_HEADER_SIZE
+        }
+    }
+
+    /// Bytes available to callers in each page of `page_size` on disk.
+    pub fn usable_size(&self, page_size: usize) -> usize {
+        page_size - self.header_size()
+    }
+
+    /// The LSN a stored page records, read without decoding the page:
+    /// `Lsn::ZERO` for one never written, or if the database isn't logged.
+    pub fn stored_lsn(&self, page: &Page) -> Lsn {
+        if !self.logged {
+            return Lsn::ZERO;
+        }
+        Lsn(BigEndian::read_u64(&page.as_bytes()[PAGE_HEADER_SIZE..]))
+    }
+
+    /// Encode `page`, last changed by the log record at `lsn`, for disk.
+    /// Returns the stored page and how many of its leading bytes are in use;
+    /// the rest is zero padding.
+    pub fn encode(
+        &self,
+        page: &Page,
+        page_size: usize,
+        page_id: PageId,
+        lsn: Lsn,
+    ) -> Result<(Page, usize), EncryptionError> {
+        if !self.is_enabled() {
+            return Ok((Page::new(page.as_bytes().to_vec()), page_size));
+        }
+        let files = self.files.read().unwrap();
+        let compression = files.get(&page_id.file).unwrap_or(&self.compression);
+        let compressed = match compression {
+            Compression::Lz => lz::compress(page.as_bytes()),
+            Compression::None => None,
+        };
+        let (codec, payload) = match &compressed {
+            Some(compressed) if compressed.len() < page.as_bytes().len() => {
+                (CODEC_LZ, compressed.as_slice())
+            }
+            _ => (CODEC_STORED, page.as_bytes()),
+        };
+
+        let header_size = self.header_size();
+        let used = header_size + payload.len();
+        let mut data = vec![0; page_size];
+        data[0] = codec;
+        BigEndian::write_u32(&mut data[4..PAGE_HEADER_SIZE], payload.len() as u32);
+        if self.logged {
+            BigEndian::write_u64(&mut data[PAGE_HEADER_SIZE..], lsn.0);
+        }
+        data[header_size..used].copy_from_slice(payload);
+
+        if let Some(cipher) = &self.cipher {
+            let nonce = PageCipher::nonce()?;
+            let aad = self.aad(&data, page_id);
+            let tag = cipher.seal(&nonce, &aad, &mut data[header_size..used]);
+            let nonce_offset = self.nonce_offset();
+            data[nonce_offset..nonce_offset + NONCE_SIZE].copy_from_slice(&nonce);
+            data[nonce_offset + NONCE_SIZE..header_size].copy_from_slice(&tag);
+        }
+        Ok((Page::new(data), used))
+    }
+
+    /// Decode a stored page, returning it along with its LSN, which is
+    /// `Lsn::ZERO` for a database that isn't logged.
+    pub fn decode(
+        &self,
+        page: Page,
+        page_size: usize,
+        page_id: PageId,
+    ) -> Result<(Page, Lsn), PageDecodeError> {
+        if !self.is_enabled() {
+            return Ok((page, Lsn::ZERO));
+        }
+        let header_size = self.header_size();
+        let usable = self.usable_size(page_size);
+        let mut bytes = page.as_bytes().to_vec();
+        // Pages never written have an all-zero header and read as zeros
+        if bytes[..header_size].iter().all(|&b| b == 0) {
+            return Ok((Page::zeros(usable), Lsn::ZERO));
+        }
+        let lsn = if self.logged {
+            Lsn(BigEndian::read_u64(&bytes[PAGE_HEADER_SIZE..]))
+        } else {
+            Lsn::ZERO
+        };
+
+        let len = BigEndian::read_u32(&bytes[4..PAGE_HEADER_SIZE]) as usize;
+        if len > usable {
+            return Err(PageDecodeError::Corrupted(format!(
+                "stored length {} exceeds the page",
+                len
+            )));
+        }
+        if let Some(cipher) = &self.cipher {
+            let nonce_offset = self.nonce_offset();
+            let nonce: [u8; NONCE_SIZE] = bytes[nonce_offset..nonce_offset + NONCE_SIZE]
+                .try_into()
+                .unwrap();
+            let tag: [u8; TAG_SIZE] = bytes[nonce_offset + NONCE_SIZE..header_size]
+                .try_into()
+                .unwrap();
+            let aad = self.aad(&bytes, page_id);
+            cipher
+                .open(
+                    &nonce,
+                    &aad,
+                    &mut bytes[header_size..header_size + len],
+                    &tag,
+                )
+                .map_err(|e| PageDecodeError::Corrupted(format!("page {}: {}", page_id, e)))?;
+        }
+
+        let payload = &bytes[header_size..header_size + len];
+        let page = match bytes[0] {
+            CODEC_STORED => {
+                let mut data = payload.to_vec();
+                data.resize(usable, 0);
+                Page::new(data)
+            }
+            CODEC_LZ => {
+                let data = lz::decompress(payload, usable)?;
+                if data.len() != usable {
+                    return Err(PageDecodeError::Corrupted(
+                        "decompressed page has the wrong size".into(),
+                    ));
+                }
+                Page::new(data)
+            }
+            codec => {
+                return Err(PageDecodeError::Corrupted(format!(
+                    "unknown page codec {}",
+                    codec
+                )))
+            }
+        };
+        Ok((page, lsn))
+    }
+
+    /// The data authenticated alongside an encrypted payload: the plaintext
+    /// header fields and where the page lives.
+    fn aad(&self, data: &[u8], page_id: PageId) -> Vec<u8> {
+        let mut aad = data[..self.nonce_offset()].to_vec();
+        aad.extend_from_slice(&page_id.file.0.to_be_bytes());
+        aad.extend_from_slice(&page_id.page_no.to_be_bytes());
+        aad
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_ID: PageId = PageId {
+        file: FileId(1),
+        page_no: 3,
+    };
+
+    fn sample_page(len: usize) -> Page {
+        // Repetitive rows with a little variation, like a mostly-cold table
+        let data = (0..len)
+            .map(|i| if i % 64 < 48 { b'a' + (i % 7) as u8 } else { 0 })
+            .collect();
+        Page::new(data)
+    }
+
+    fn random_page(len: usize) -> Page {
+        let mut state = 1u32;
+        let data = (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect();
+        Page::new(data)
+    }
+
+    #[cfg(feature = "encryption")]
+    fn encrypted(compression: Compression) -> PageCodec {
+        PageCodec::new(compression, Some(PageCipher::new(&[5; 32])), false)
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_compressed_round_trip() {
+        let codec = PageCodec::new(Compression::Lz, None, false);
+        let page = sample_page(codec.usable_size(4096));
+
+        let (stored, used) = codec.encode(&page, 4096, PAGE_ID, Lsn::ZERO).unwrap();
+        assert_eq!(stored.as_bytes().len(), 4096);
+        assert!(used < 4096 / 2);
+        assert_eq!(
+            codec.decode(stored, 4096, PAGE_ID).unwrap(),
+            (page, Lsn::ZERO)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_file_compression() {
+        let codec = PageCodec::new(Compression::None, None, true);
+        codec.set_file_compression(PAGE_ID.file, Compression::Lz);
+        let page = sample_page(codec.usable_size(4096));
+
+        // Other files are stored as the database's are
+        let other = PageId::new(FileId(PAGE_ID.file.0 + 1), 0);
+        let (stored, _) = codec.encode(&page, 4096, other, Lsn(7)).unwrap();
+        assert_eq!(stored.as_bytes()[0], CODEC_STORED);
+
+        let (stored, used) = codec.encode(&page, 4096, PAGE_ID, Lsn(7)).unwrap();
+        assert_eq!(stored.as_bytes()[0], CODEC_LZ);
+        assert!(used < 4096 / 2);
+        assert_eq!(codec.decode(stored, 4096, PAGE_ID).unwrap(), (page, Lsn(7)));
+    }
+
+    #[test]
+    fn test_incompressible_page_is_stored() {
+        let codec = PageCodec::new(Compression::Lz, None, false);
+        let page = random_page(codec.usable_size(256));
+
+        let (stored, used) = codec.encode(&page, 256, PAGE_ID, Lsn::ZERO).unwrap();
+        assert_eq!(stored.as_bytes()[0], CODEC_STORED);
+        assert_eq!(used, 256);
+        assert_eq!(
+            codec.decode(stored, 256, PAGE_ID).unwrap(),
+            (page, Lsn::ZERO)
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "compression"))]
+    fn test_compression_unavailable() {
+        let codec = PageCodec::new(Compression::Lz, None, false);
+        let page = sample_page(codec.usable_size(256));
+
+        let (stored, used) = codec.encode(&page, 256, PAGE_ID, Lsn::ZERO).unwrap();
+        assert_eq!(stored.as_bytes()[0], CODEC_STORED);
+        assert_eq!(used, 256);
+
+        // A page compressed elsewhere can't be read
+        let mut data = stored.as_bytes().to_vec();
+        data[0] = CODEC_LZ;
+        assert!(matches!(
+            codec.decode(Page::new(data), 256, PAGE_ID),
+            Err(PageDecodeError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn test_unwritten_page_reads_as_zeros() {
+        for codec in [
+            PageCodec::new(Compression::Lz, None, false),
+            PageCodec::new(Compression::None, None, true),
+            #[cfg(feature = "encryption")]
+            encrypted(Compression::None),
+        ] {
+            let usable = codec.usable_size(128);
+            let (page, _) = codec.decode(Page::zeros(128), 128, PAGE_ID).unwrap();
+            assert_eq!(page, Page::zeros(usable));
+        }
+    }
+
+    #[test]
+    fn test_corrupted_page() {
+        let codec = PageCodec::new(Compression::Lz, None, false);
+        let (stored, _) = codec
+            .encode(&sample_page(120), 128, PAGE_ID, Lsn::ZERO)
+            .unwrap();
+        let mut data = stored.as_bytes().to_vec();
+        data[4..PAGE_HEADER_SIZE].copy_from_slice(&500u32.to_be_bytes());
+        assert!(matches!(
+            codec.decode(Page::new(data), 128, PAGE_ID),
+            Err(PageDecodeError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn test_disabled_codec_is_identity() {
+        let codec = PageCodec::new(Compression::None, None, false);
+        let page = sample_page(128);
+        let (stored, used) = codec.encode(&page, 128, PAGE_ID, Lsn::ZERO).unwrap();
+        assert_eq!(used, 128);
+        assert_eq!(
+            codec.decode(stored, 128, PAGE_ID).unwrap(),
+            (page, Lsn::ZERO)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encrypted_round_trip() {
+        for compression in [Compression::None, Compression::Lz] {
+            let codec = encrypted(compression);
+            let page = sample_page(codec.usable_size(256));
+            let (stored, _) = codec.encode(&page, 256, PAGE_ID, Lsn::ZERO).unwrap();
+
+            // No run of the plaintext survives on disk
+            let plaintext = &page.as_bytes()[..16];
+            assert!(!stored.as_bytes().windows(16).any(|w| w == plaintext));
+            assert_eq!(
+                codec.decode(stored, 256, PAGE_ID).unwrap(),
+                (page, Lsn::ZERO)
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encrypted_page_is_bound_to_its_location() {
+        let codec = encrypted(Compression::None);
+        let page = sample_page(codec.usable_size(256));
+        let (stored, _) = codec.encode(&page, 256, PAGE_ID, Lsn::ZERO).unwrap();
+
+        let elsewhere = PageId::new(FileId(1), 4);
+        assert!(matches!(
+            codec.decode(stored, 256, elsewhere),
+            Err(PageDecodeError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_each_write_uses_a_new_nonce() {
+        let codec = encrypted(Compression::None);
+        let page = sample_page(codec.usable_size(256));
+        let (first, _) = codec.encode(&page, 256, PAGE_ID, Lsn::ZERO).unwrap();
+        let (second, _) = codec.encode(&page, 256, PAGE_ID, Lsn::ZERO).unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_logged_pages_keep_their_lsn() {
+        let ciphers = vec![
+            None,
+            #[cfg(feature = "encryption")]
+            Some(PageCipher::new(&[5; 32])),
+        ];
+        for cipher in ciphers {
+            let encrypted = cipher.is_some();
+            let codec = PageCodec::new(Compression::None, cipher, true);
+            let page = sample_page(codec.usable_size(256));
+            let (stored, _) = codec.encode(&page, 256, PAGE_ID, Lsn(42)).unwrap();
+            assert_eq!(
+                codec
+                    .decode(Page::new(stored.as_bytes().to_vec()), 256, PAGE_ID)
+                    .unwrap(),
+                (page, Lsn(42))
+            );
+
+            // An encrypted page's LSN is authenticated along with it
+            if encrypted {
+                let mut data = stored.as_bytes().to_vec();
+                data[PAGE_HEADER_SIZE + LSN_SIZE - 1] ^= 1;
+                assert!(matches!(
+                    codec.decode(Page::new(data), 256, PAGE_ID),
+                    Err(PageDecodeError::Corrupted(_))
+                ));
+            }
+        }
+    }
+}
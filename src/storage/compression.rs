@@ -0,0 +1,151 @@
+use super::page::PageDecodeError;
+use crate::config::Compression;
+
+impl Compression {
+    /// The value recorded in the superblock.
+    pub fn code(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz => 1,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz),
+            _ => None,
+        }
+    }
+}
+
+/// Page compression with LZ4's block format.
+///
+/// Without the compression feature nothing is compressed, so databases
+/// asking for it store their pages as they are, and pages that were
+/// compressed can't be read.
+pub mod lz {
+    use super::PageDecodeError;
+
+    /// `input` compressed, or `None` if ferrodb was built without the
+    /// compression feature.
+    #[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+    pub fn compress(input: &[u8]) -> Option<Vec<u8>> {
+        #[cfg(feature = "compression")]
+        return Some(lz4_flex::block::compress(input));
+        #[cfg(not(feature = "compression"))]
+        None
+    }
+
+    /// Decompress `input`, which must expand to no more than `capacity`
+    /// bytes.
+    #[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+    pub fn decompress(input: &[u8], capacity: usize) -> Result<Vec<u8>, PageDecodeError> {
+        #[cfg(feature = "compression")]
+        {
+            let mut out = vec![0; capacity];
+            let len = lz4_flex::block::decompress_into(input, &mut out)
+                .map_err(|e| PageDecodeError::Corrupted(format!("compressed page: {}", e)))?;
+            out.truncate(len);
+            Ok(out)
+        }
+        #[cfg(not(feature = "compression"))]
+        Err(PageDecodeError::Corrupted(
+            "compressed page needs ferrodb built with the compression feature".into(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| if i % 64 < 48 { b'a' + (i % 7) as u8 } else { 0 })
+            .collect()
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_lz_round_trip() {
+        let input = sample(4000);
+        let compressed = lz::compress(&input).unwrap();
+        assert!(compressed.len() < input.len() / 2);
+        assert_eq!(lz::decompress(&compressed, input.len()).unwrap(), input);
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_lz_rejects_truncated_input() {
+        let input = sample(4000);
+        let compressed = lz::compress(&input).unwrap();
+        for len in [1, compressed.len() / 2, compressed.len() - 1] {
+            assert!(matches!(
+                lz::decompress(&compressed[..len], input.len()),
+                Err(PageDecodeError::Corrupted(_))
+            ));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_lz_rejects_corrupted_input() {
+        // A match reaching back before the start of the output
+        assert!(matches!(
+            lz::decompress(&[0x10, b'a', 0x05, 0x00], 128),
+            Err(PageDecodeError::Corrupted(_))
+        ));
+        // A literal run longer than the input
+        assert!(matches!(
+            lz::decompress(&[0xf0, 0x20, b'a'], 128),
+            Err(PageDecodeError::Corrupted(_))
+        ));
+
+        // Any byte changed either fails or decodes to something of no more
+        // than the page's size
+        let input = sample(512);
+        let compressed = lz::compress(&input).unwrap();
+        for at in 0..compressed.len() {
+            for flip in [0x01, 0x80, 0xff] {
+                let mut corrupted = compressed.clone();
+                corrupted[at] ^= flip;
+                if let Ok(output) = lz::decompress(&corrupted, input.len()) {
+                    assert!(output.len() <= input.len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_lz_rejects_expansion_past_capacity() {
+        let input = sample(4000);
+        let compressed = lz::compress(&input).unwrap();
+        assert!(matches!(
+            lz::decompress(&compressed, input.len() - 1),
+            Err(PageDecodeError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(not(feature = "compression"))]
+    fn test_lz_unavailable() {
+        assert_eq!(lz::compress(&sample(4000)), None);
+        assert!(matches!(
+            lz::decompress(&[0x00, b'a'], 128),
+            Err(PageDecodeError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn test_compression_codes() {
+        for compression in [Compression::None, Compression::Lz] {
+            assert_eq!(
+                Compression::from_code(compression.code()),
+                Some(compression)
+            );
+        }
+        assert_eq!(Compression::from_code(9), None);
+    }
+}
@@ -0,0 +1,87 @@
+use crate::config::IoMode;
+use std::alloc::{self, Layout};
+use std::fs::OpenOptions;
+use std::io;
+use std::ptr::NonNull;
+
+/// Alignment required of buffers, offsets, and lengths for direct I/O. 4KiB
+/// covers the logical block size of every common device.
+pub const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// Set the platform open flags needed for `mode` on `options`.
+#[cfg(target_os = "linux")]
+pub fn apply_open_flags(options: &mut OpenOptions, mode: IoMode) -> io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    match mode {
+        IoMode::Buffered | IoMode::Mmap => {}
+        IoMode::Direct => {
+            options.custom_flags(libc::O_DIRECT);
+        }
+        IoMode::Dsync => {
+            options.custom_flags(libc::O_DSYNC);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply_open_flags(_options: &mut OpenOptions, mode: IoMode) -> io::Result<()> {
+    match mode {
+        IoMode::Buffered | IoMode::Mmap => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("I/O mode {:?} is only supported on Linux", mode),
+        )),
+    }
+}
+
+/// A zeroed heap buffer aligned for direct I/O.
+pub struct AlignedBuffer {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+// The buffer uniquely owns its allocation
+unsafe impl Send for AlignedBuffer {}
+
+impl AlignedBuffer {
+    /// Allocate `len` bytes; `len` must be a non-zero multiple of
+    /// `DIRECT_IO_ALIGNMENT`.
+    pub fn new(len: usize) -> Self {
+        assert!(len > 0 && len.is_multiple_of(DIRECT_IO_ALIGNMENT));
+        let layout = Layout::from_size_align(len, DIRECT_IO_ALIGNMENT).unwrap();
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        Self { ptr, layout }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aligned_buffer() {
+        let mut buffer = AlignedBuffer::new(2 * DIRECT_IO_ALIGNMENT);
+        assert_eq!(buffer.as_slice().as_ptr() as usize % DIRECT_IO_ALIGNMENT, 0);
+        assert!(buffer.as_slice().iter().all(|&b| b == 0));
+
+        buffer.as_mut_slice()[10] = 7;
+        assert_eq!(buffer.as_slice()[10], 7);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::config::EncryptionConfig;
+#[cfg(feature = "encryption")]
+use aes_gcm::{AeadInPlace, Aes256Gcm, KeyInit};
+use thiserror::Error;
+
+/// Environment variable the key is read from when the config doesn't set one.
+pub const KEY_ENV_VAR: &str = "FERRODB_ENCRYPTION_KEY";
+
+pub const KEY_SIZE: usize = 32;
+pub const NONCE_SIZE: usize = 12;
+pub const TAG_SIZE: usize = 16;
+
+#[derive(Debug, Error)]
+pub enum EncryptionError {
+    #[error("No encryption key: set storage.encryption.key or {KEY_ENV_VAR}")]
+    MissingKey,
+
+    #[error("Encryption key must be {} hex digits", KEY_SIZE * 2)]
+    InvalidKey,
+
+    #[error("Unable to generate a nonce: {0}")]
+    Random(getrandom::Error),
+
+    #[error("Authentication failed: wrong key or corrupted data")]
+    AuthenticationFailed,
+
+    #[error("Encryption needs ferrodb built with the encryption feature")]
+    Unsupported,
+}
+
+/// Encrypts pages with AES-256-GCM under one database key. Without the
+/// `encryption` feature there's no cipher to be had, and a database that
+/// asks for one is refused.
+pub struct PageCipher {
+    #[cfg(feature = "encryption")]
+    aead: Aes256Gcm,
+    #[cfg(not(feature = "encryption"))]
+    never: std::convert::Infallible,
+}
+
+impl PageCipher {
+    #[cfg(feature = "encryption")]
+    pub fn new(key: &[u8; KEY_SIZE]) -> Self {
+        Self {
+            aead: Aes256Gcm::new(key.into()),
+        }
+    }
+
+    /// Build a cipher from the configured key, falling back to
+    /// `KEY_ENV_VAR` looked up through `env`.
+    pub fn from_config(
+        config: &EncryptionConfig,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, EncryptionError> {
+        let hex = config
+            .key
+            .clone()
+            .or_else(|| env(KEY_ENV_VAR))
+            .ok_or(EncryptionError::MissingKey)?;
+        #[cfg(feature = "encryption")]
+        return Ok(Self::new(&parse_key(hex.trim())?));
+        #[cfg(not(feature = "encryption"))]
+        return parse_key(hex.trim()).and(Err(EncryptionError::Unsupported));
+    }
+
+    /// A fresh random nonce. Every encryption under a key must use a
+    /// different one.
+    pub fn nonce() -> Result<[u8; NONCE_SIZE], EncryptionError> {
+        let mut nonce = [0; NONCE_SIZE];
+        getrandom::getrandom(&mut nonce).map_err(EncryptionError::Random)?;
+        Ok(nonce)
+    }
+
+    /// Encrypt `data` in place, returning the tag authenticating it and
+    /// `aad`.
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    pub fn seal(&self, nonce: &[u8; NONCE_SIZE], aad: &[u8], data: &mut [u8]) -> [u8; TAG_SIZE] {
+        #[cfg(feature = "encryption")]
+        return self
+            .aead
+            .encrypt_in_place_detached(nonce.into(), aad, data)
+            .expect("a page is far shorter than GCM's limit")
+            .into();
+        #[cfg(not(feature = "encryption"))]
+        match self.never {}
+    }
+
+    /// Check `tag` and decrypt `data` in place. `data` is left untouched if
+    /// authentication fails.
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    pub fn open(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        aad: &[u8],
+        data: &mut [u8],
+        tag: &[u8; TAG_SIZE],
+    ) -> Result<(), EncryptionError> {
+        #[cfg(feature = "encryption")]
+        return self
+            .aead
+            .decrypt_in_place_detached(nonce.into(), aad, data, tag.into())
+            .map_err(|_| EncryptionError::AuthenticationFailed);
+        #[cfg(not(feature = "encryption"))]
+        match self.never {}
+    }
+
+    /// A value derived from the key that identifies it without revealing it,
+    /// so a database opened with the wrong key is refused up front.
+    pub fn key_check(&self) -> [u8; TAG_SIZE] {
+        self.seal(&[0; NONCE_SIZE], b"ferrodb key check", &mut [])
+    }
+}
+
+fn parse_key(hex: &str) -> Result<[u8; KEY_SIZE], EncryptionError> {
+    if hex.len() != KEY_SIZE * 2 || !hex.is_ascii() {
+        return Err(EncryptionError::InvalidKey);
+    }
+    let mut key = [0; KEY_SIZE];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| EncryptionError::InvalidKey)?;
+    }
+    Ok(key)
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_gcm_spec_vectors() {
+        // Test cases 13 and 14 of the GCM specification
+        let cipher = PageCipher::new(&[0; 32]);
+        let tag = cipher.seal(&[0; 12], &[], &mut []);
+        assert_eq!(tag.to_vec(), hex("530f8afbc74536b9a963b4f1c4cb738b"));
+
+        let mut data = [0u8; 16];
+        let tag = cipher.seal(&[0; 12], &[], &mut data);
+        assert_eq!(data.to_vec(), hex("cea7403d4d606b6e074ec5d3baf39d18"));
+        assert_eq!(tag.to_vec(), hex("d0d1c8a799996bf0265b98b5d48ab919"));
+
+        // Test case 15
+        let key: [u8; 32] = hex("feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308")
+            .try_into()
+            .unwrap();
+        let nonce: [u8; 12] = hex("cafebabefacedbaddecaf888").try_into().unwrap();
+        let mut data = hex(
+            "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+             1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b391aafd255",
+        );
+        let tag = PageCipher::new(&key).seal(&nonce, &[], &mut data);
+        assert_eq!(
+            data,
+            hex(
+                "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa\
+                 8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662898015ad"
+            )
+        );
+        assert_eq!(tag.to_vec(), hex("b094dac5d93471bdec1a502270e3cc6c"));
+    }
+
+    #[test]
+    fn test_open_rejects_tampering() {
+        let cipher = PageCipher::new(&[7; 32]);
+        let nonce = PageCipher::nonce().unwrap();
+        let mut data = b"secret page contents".to_vec();
+        let tag = cipher.seal(&nonce, b"page 1", &mut data);
+        assert_ne!(data, b"secret page contents");
+
+        let mut tampered = data.clone();
+        tampered[0] ^= 1;
+        assert!(cipher.open(&nonce, b"page 1", &mut tampered, &tag).is_err());
+        assert!(cipher
+            .open(&nonce, b"page 2", &mut data.clone(), &tag)
+            .is_err());
+
+        cipher.open(&nonce, b"page 1", &mut data, &tag).unwrap();
+        assert_eq!(data, b"secret page contents");
+    }
+
+    #[test]
+    fn test_key_from_config_or_env() {
+        let key = "00".repeat(KEY_SIZE);
+        let from_config = EncryptionConfig {
+            key: Some(key.clone()),
+        };
+        let cipher = PageCipher::from_config(&from_config, |_| None).unwrap();
+        assert_eq!(cipher.key_check(), PageCipher::new(&[0; 32]).key_check());
+
+        let from_env = EncryptionConfig { key: None };
+        let cipher =
+            PageCipher::from_config(&from_env, |name| (name == KEY_ENV_VAR).then(|| key.clone()))
+                .unwrap();
+        assert_eq!(cipher.key_check(), PageCipher::new(&[0; 32]).key_check());
+
+        assert!(matches!(
+            PageCipher::from_config(&from_env, |_| None),
+            Err(EncryptionError::MissingKey)
+        ));
+        let short = EncryptionConfig {
+            key: Some("abcd".into()),
+        };
+        assert!(matches!(
+            PageCipher::from_config(&short, |_| None),
+            Err(EncryptionError::InvalidKey)
+        ));
+    }
+}
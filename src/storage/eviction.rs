@@ -0,0 +1,261 @@
+use super::page::PageId;
+use crate::config::EvictionPolicyKind;
+use lru::LruCache;
+use std::collections::{HashMap, VecDeque};
+
+/// Decides which cached page a buffer pool shard gives up when it is full.
+///
+/// The shard reports every insert, hit, and removal; `victim` is only asked
+/// for pages it holds, and must skip any page `evictable` rejects.
+pub trait EvictionPolicy: Send {
+    fn insert(&mut self, page_id: PageId);
+
+    /// Insert a page that is unlikely to be reused, such as one loaded by a
+    /// sequential scan, so that it is among the first to be evicted.
+    fn insert_cold(&mut self, page_id: PageId);
+
+    fn record_access(&mut self, page_id: PageId);
+
+    fn remove(&mut self, page_id: PageId);
+
+    fn victim(&mut self, evictable: &dyn Fn(PageId) -> bool) -> Option<PageId>;
+}
+
+pub fn new_policy(kind: EvictionPolicyKind, lru_k: usize) -> Box<dyn EvictionPolicy> {
+    match kind {
+        EvictionPolicyKind::Lru => Box::new(Lru::new()),
+        EvictionPolicyKind::Clock => Box::new(Clock::new()),
+        EvictionPolicyKind::LruK => Box::new(LruK::new(lru_k)),
+    }
+}
+
+/// Evicts the least recently used page.
+pub struct Lru {
+    order: LruCache<PageId, ()>,
+}
+
+impl Lru {
+    pub fn new() -> Self {
+        Self {
+            order: LruCache::unbounded(),
+        }
+    }
+}
+
+impl EvictionPolicy for Lru {
+    fn insert(&mut self, page_id: PageId) {
+        self.order.put(page_id, ());
+    }
+
+    fn insert_cold(&mut self, page_id: PageId) {
+        self.order.put(page_id, ());
+        self.order.demote(&page_id);
+    }
+
+    fn record_access(&mut self, page_id: PageId) {
+        self.order.promote(&page_id);
+    }
+
+    fn remove(&mut self, page_id: PageId) {
+        self.order.pop(&page_id);
+    }
+
+    fn victim(&mut self, evictable: &dyn Fn(PageId) -> bool) -> Option<PageId> {
+        self.order
+            .iter()
+            .rev()
+            .map(|(&page_id, _)| page_id)
+            .find(|&page_id| evictable(page_id))
+    }
+}
+
+/// Second-chance eviction: a hand sweeps the pages in insertion order,
+/// clearing reference bits and evicting the first page found unreferenced.
+pub struct Clock {
+    ring: VecDeque<(PageId, bool)>,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self {
+            ring: VecDeque::new(),
+        }
+    }
+}
+
+impl EvictionPolicy for Clock {
+    fn insert(&mut self, page_id: PageId) {
+        self.ring.push_back((page_id, false));
+    }
+
+    fn insert_cold(&mut self, page_id: PageId) {
+        // Place the page under the hand so it is the next candidate
+        self.ring.push_front((page_id, false));
+    }
+
+    fn record_access(&mut self, page_id: PageId) {
+        if let Some(entry) = self.ring.iter_mut().find(|(id, _)| *id == page_id) {
+            entry.1 = true;
+        }
+    }
+
+    fn remove(&mut self, page_id: PageId) {
+        self.ring.retain(|(id, _)| *id != page_id);
+    }
+
+    fn victim(&mut self, evictable: &dyn Fn(PageId) -> bool) -> Option<PageId> {
+        // The front of the ring is under the hand. Two sweeps clear every
+        // reference bit, so if nothing turns up by then everything is pinned.
+        for _ in 0..self.ring.len() * 2 {
+            let (page_id, referenced) = self.ring.pop_front()?;
+            if !referenced && evictable(page_id) {
+                self.ring.push_front((page_id, false));
+                return Some(page_id);
+            }
+            self.ring.push_back((page_id, false));
+        }
+        None
+    }
+}
+
+/// LRU-K: evicts the page whose K-th most recent access is oldest. Pages seen
+/// fewer than K times go first, so a single large scan can't flush pages
+/// that are accessed repeatedly.
+pub struct LruK {
+    k: usize,
+    clock: u64,
+    history: HashMap<PageId, VecDeque<u64>>,
+}
+
+impl LruK {
+    pub fn new(k: usize) -> Self {
+        Self {
+            k: k.max(1),
+            clock: 0,
+            history: HashMap::new(),
+        }
+    }
+
+    fn touch(&mut self, page_id: PageId) {
+        self.clock += 1;
+        let accesses = self.history.entry(page_id).or_default();
+        if accesses.len() == self.k {
+            accesses.pop_front();
+        }
+        accesses.push_back(self.clock);
+    }
+}
+
+impl EvictionPolicy for LruK {
+    fn insert(&mut self, page_id: PageId) {
+        self.touch(page_id);
+    }
+
+    fn insert_cold(&mut self, page_id: PageId) {
+        // A single access older than any real one ranks the page first
+        self.history.insert(page_id, VecDeque::from([0]));
+    }
+
+    fn record_access(&mut self, page_id: PageId) {
+        self.touch(page_id);
+    }
+
+    fn remove(&mut self, page_id: PageId) {
+        self.history.remove(&page_id);
+    }
+
+    fn victim(&mut self, evictable: &dyn Fn(PageId) -> bool) -> Option<PageId> {
+        // Rank by (has K accesses, oldest retained access); pages with fewer
+        // than K accesses fall back to plain LRU among themselves
+        self.history
+            .iter()
+            .filter(|(&page_id, _)| evictable(page_id))
+            .min_by_key(|(_, accesses)| (accesses.len() == self.k, accesses[0]))
+            .map(|(&page_id, _)| page_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::file_manager::FileId;
+
+    fn page(page_no: u64) -> PageId {
+        PageId::new(FileId(1), page_no)
+    }
+
+    fn fill(policy: &mut dyn EvictionPolicy, pages: &[u64]) {
+        for &page_no in pages {
+            policy.insert(page(page_no));
+        }
+    }
+
+    #[test]
+    fn test_lru_evicts_least_recent() {
+        let mut policy = Lru::new();
+        fill(&mut policy, &[1, 2, 3]);
+        policy.record_access(page(1));
+        assert_eq!(policy.victim(&|_| true), Some(page(2)));
+        assert_eq!(policy.victim(&|id| id != page(2)), Some(page(3)));
+    }
+
+    #[test]
+    fn test_clock_gives_second_chance() {
+        let mut policy = Clock::new();
+        fill(&mut policy, &[1, 2, 3]);
+        policy.record_access(page(1));
+        assert_eq!(policy.victim(&|_| true), Some(page(2)));
+
+        policy.remove(page(2));
+        // Page 1's reference bit was cleared by the previous sweep
+        assert_eq!(policy.victim(&|_| true), Some(page(3)));
+    }
+
+    #[test]
+    fn test_clock_all_pinned() {
+        let mut policy = Clock::new();
+        fill(&mut policy, &[1, 2]);
+        policy.record_access(page(1));
+        assert_eq!(policy.victim(&|_| false), None);
+    }
+
+    #[test]
+    fn test_lru_k_is_scan_resistant() {
+        let mut policy = LruK::new(2);
+        fill(&mut policy, &[1, 2]);
+        policy.record_access(page(1));
+        policy.record_access(page(2));
+
+        // A scan touches pages 3 and 4 once each, after the hot pages
+        fill(&mut policy, &[3, 4]);
+        assert_eq!(policy.victim(&|_| true), Some(page(3)));
+        policy.remove(page(3));
+        assert_eq!(policy.victim(&|_| true), Some(page(4)));
+        policy.remove(page(4));
+
+        // Among pages with K accesses, the oldest K-th access goes first
+        assert_eq!(policy.victim(&|_| true), Some(page(1)));
+        assert_eq!(policy.victim(&|id| id != page(1)), Some(page(2)));
+    }
+
+    #[test]
+    fn test_cold_pages_evicted_first() {
+        let policies: Vec<Box<dyn EvictionPolicy>> = vec![
+            Box::new(Lru::new()),
+            Box::new(Clock::new()),
+            Box::new(LruK::new(2)),
+        ];
+        for mut policy in policies {
+            fill(policy.as_mut(), &[1, 2]);
+            policy.insert_cold(page(3));
+            assert_eq!(policy.victim(&|_| true), Some(page(3)));
+        }
+    }
+
+    #[test]
+    fn test_new_policy() {
+        let mut policy = new_policy(EvictionPolicyKind::Clock, 2);
+        fill(policy.as_mut(), &[7]);
+        assert_eq!(policy.victim(&|_| true), Some(page(7)));
+    }
+}
@@ -0,0 +1,394 @@
+//! Faults injected into the I/O of pages and the log, so tests can
+//! reproduce the failures recovery and concurrency have to survive: a
+//! read, write or sync that fails, a write torn halfway, and a crash that
+//! stops every write after it.
+//!
+//! Which operation a fault hits is decided from how many times its point
+//! has been reached, or by a generator seeded once, so a single-threaded
+//! test hits the same faults every run. A crash leaves on disk what had
+//! been handed to the files before it; the test then drops the database
+//! and opens it again, which recovers it as it would after a real one.
+
+use super::page::Page;
+use super::page_io::PageIOError;
+use super::page_store::PageStore;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// A place in the storage layer's I/O a fault can be injected at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultPoint {
+    PageRead,
+    PageWrite,
+    /// Pushing a file's written pages to disk
+    PageFlush,
+    WalWrite,
+    /// Syncing the log, as a commit does
+    WalSync,
+}
+
+/// What happens to an operation a fault hits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// It fails with an I/O error; later ones go ahead
+    IoError,
+    /// Only the first half of what it writes reaches the file, and then
+    /// the process crashes
+    TornWrite,
+    /// The process crashes before it: it and every operation after it fail
+    Crash,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Trigger {
+    /// The `n`th time the point is reached, counting from 1
+    Nth(u64),
+    /// Each time, with this probability
+    Probability(f64),
+}
+
+#[derive(Debug)]
+struct Rule {
+    point: FaultPoint,
+    trigger: Trigger,
+    fault: Fault,
+}
+
+#[derive(Debug)]
+struct State {
+    rules: Vec<Rule>,
+    /// How many times each point has been reached
+    counts: HashMap<FaultPoint, u64>,
+    /// A xorshift generator, for faults hit by chance
+    random: u64,
+}
+
+impl State {
+    fn next_random(&mut self) -> f64 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        (self.random >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// What the I/O at a point is to do.
+pub(crate) enum Injected {
+    Proceed,
+    Fail(io::Error),
+    /// Write half of what it would, then fail as the crash it is
+    Tear,
+}
+
+type Hook = Box<dyn Fn(FaultPoint, u64) + Send + Sync>;
+
+/// Decides which I/O operations fail, and how. Shared by every file and
+/// the log of a database opened with `Database::with_faults` or
+/// `PageManagerBuilder::faults`.
+pub struct FaultInjector {
+    state: Mutex<State>,
+    crashed: AtomicBool,
+    /// Run as each point is reached, before its I/O
+    hook: RwLock<Option<Hook>>,
+}
+
+impl fmt::Debug for FaultInjector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FaultInjector")
+            .field("state", &self.state)
+            .field("crashed", &self.crashed)
+            .finish_non_exhaustive()
+    }
+}
+
+fn crash() -> io::Error {
+    io::Error::other("simulated crash")
+}
+
+impl FaultInjector {
+    /// An injector with no faults yet, whose chance faults come from
+    /// `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: Mutex::new(State {
+                rules: Vec::new(),
+                counts: HashMap::new(),
+                // Xorshift never leaves zero
+                random: seed.max(1),
+            }),
+            crashed: AtomicBool::new(false),
+            hook: RwLock::new(None),
+        }
+    }
+
+    /// Hit the `n`th operation at `point`, counting from 1 since the
+    /// injector was made, with `fault`.
+    pub fn fail_nth(&self, point: FaultPoint, n: u64, fault: Fault) -> &Self {
+        self.add(point, Trigger::Nth(n), fault)
+    }
+
+    /// Hit each operation at `point` with `fault` with probability
+    /// `probability`.
+    pub fn fail_randomly(&self, point: FaultPoint, probability: f64, fault: Fault) -> &Self {
+        self.add(point, Trigger::Probability(probability), fault)
+    }
+
+    fn add(&self, point: FaultPoint, trigger: Trigger, fault: Fault) -> &Self {
+        let rule = Rule {
+            point,
+            trigger,
+            fault,
+        };
+        self.state.lock().unwrap().rules.push(rule);
+        self
+    }
+
+    /// Inject no more faults, though a crash stays crashed.
+    pub fn clear(&self) {
+        self.state.lock().unwrap().rules.clear();
+    }
+
+    /// Run `hook` each time a point is reached, with the point and how many
+    /// times it has been, before its I/O goes ahead: such as to hold a
+    /// thread there until another has got somewhere, for an interleaving
+    /// that would otherwise be left to chance.
+    pub fn on_point(&self, hook: impl Fn(FaultPoint, u64) + Send + Sync + 'static) {
+        *self.hook.write().unwrap() = Some(Box::new(hook));
+    }
+
+    /// How many times `point` has been reached.
+    pub fn count(&self, point: FaultPoint) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.counts.get(&point).copied().unwrap_or(0)
+    }
+
+    /// Whether a fault has crashed the process, failing all I/O since.
+    pub fn crashed(&self) -> bool {
+        self.crashed.load(Ordering::Acquire)
+    }
+
+    /// Reach `point`, returning what its I/O is to do.
+    pub(crate) fn inject(&self, point: FaultPoint) -> Injected {
+        let (n, fault) = {
+            let mut state = self.state.lock().unwrap();
+            let count = state.counts.entry(point).or_default();
+            *count += 1;
+            let n = *count;
+            let mut hit = None;
+            for i in 0..state.rules.len() {
+                let Rule {
+                    point: at,
+                    trigger,
+                    fault,
+                } = state.rules[i];
+                let fires = at == point
+                    && match trigger {
+                        Trigger::Nth(nth) => nth == n,
+                        Trigger::Probability(probability) => state.next_random() < probability,
+                    };
+                if fires && hit.is_none() {
+                    hit = Some(fault);
+                }
+            }
+            (n, hit)
+        };
+        if let Some(hook) = self.hook.read().unwrap().as_ref() {
+            hook(point, n);
+        }
+        if self.crashed() {
+            return Injected::Fail(crash());
+        }
+        match fault {
+            None => Injected::Proceed,
+            Some(Fault::IoError) => {
+                Injected::Fail(io::Error::other(format!("injected I/O error at {point:?}")))
+            }
+            Some(Fault::TornWrite) => {
+                self.crashed.store(true, Ordering::Release);
+                Injected::Tear
+            }
+            Some(Fault::Crash) => {
+                self.crashed.store(true, Ordering::Release);
+                Injected::Fail(crash())
+            }
+        }
+    }
+}
+
+/// A file's pages, with faults injected into their I/O.
+pub(crate) struct FaultyPageStore<S> {
+    pub(crate) inner: S,
+    pub(crate) faults: Arc<FaultInjector>,
+}
+
+impl<S: PageStore> PageStore for FaultyPageStore<S> {
+    fn validate_length(&self, page_size: usize) -> Result<u64, PageIOError> {
+        self.inner.validate_length(page_size)
+    }
+
+    fn read_page(&mut self, page_id: u64, page_size: usize) -> Result<Page, PageIOError> {
+        match self.faults.inject(FaultPoint::PageRead) {
+            Injected::Fail(e) => Err(e.into()),
+            Injected::Proceed | Injected::Tear => self.inner.read_page(page_id, page_size),
+        }
+    }
+
+    fn write_page(
+        &mut self,
+        page_id: u64,
+        page_size: usize,
+        page: &Page,
+    ) -> Result<(), PageIOError> {
+        match self.faults.inject(FaultPoint::PageWrite) {
+            Injected::Proceed => self.inner.write_page(page_id, page_size, page),
+            Injected::Fail(e) => Err(e.into()),
+            Injected::Tear => {
+                let mut torn = match self.inner.read_page(page_id, page_size) {
+                    Ok(old) => old.as_bytes().to_vec(),
+                    Err(_) => vec![0; page_size],
+                };
+                let half = page_size / 2;
+                torn[..half].copy_from_slice(&page.as_bytes()[..half]);
+                self.inner
+                    .write_page(page_id, page_size, &Page::new(torn))?;
+                Err(crash().into())
+            }
+        }
+    }
+
+    fn punch_hole(
+        &mut self,
+        page_id: u64,
+        page_size: usize,
+        used: usize,
+    ) -> Result<(), PageIOError> {
+        if self.faults.crashed() {
+            return Err(crash().into());
+        }
+        self.inner.punch_hole(page_id, page_size, used)
+    }
+
+    fn flush(&mut self) -> Result<(), PageIOError> {
+        match self.faults.inject(FaultPoint::PageFlush) {
+            Injected::Fail(e) => Err(e.into()),
+            Injected::Proceed | Injected::Tear => self.inner.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::database::tests::test_config;
+    use crate::database::{Database, DatabaseError};
+    use crate::storage::page_store::MemoryPageStore;
+    use std::path::Path;
+
+    fn config(dir: &Path) -> Config {
+        let mut config = test_config(dir);
+        config.storage.page_size = 128;
+        config
+    }
+
+    #[test]
+    fn test_faulty_page_store() {
+        let faults = Arc::new(FaultInjector::new(1));
+        let mut store = FaultyPageStore {
+            inner: MemoryPageStore::default(),
+            faults: faults.clone(),
+        };
+        faults
+            .fail_nth(FaultPoint::PageWrite, 2, Fault::IoError)
+            .fail_nth(FaultPoint::PageWrite, 4, Fault::TornWrite);
+        store.write_page(0, 8, &Page::new(vec![1; 8])).unwrap();
+        assert!(store.write_page(0, 8, &Page::new(vec![2; 8])).is_err());
+        store.write_page(0, 8, &Page::new(vec![3; 8])).unwrap();
+
+        // Only half the page is written before the crash
+        assert!(store.write_page(0, 8, &Page::new(vec![4; 8])).is_err());
+        assert!(faults.crashed());
+        assert!(store.read_page(0, 8).is_err());
+        assert_eq!(
+            store.inner.read_page(0, 8).unwrap().as_bytes(),
+            [4, 4, 4, 4, 3, 3, 3, 3]
+        );
+        assert_eq!(faults.count(FaultPoint::PageWrite), 4);
+        assert_eq!(faults.count(FaultPoint::PageRead), 1);
+    }
+
+    #[test]
+    fn test_crash_recovery() {
+        for fault in [Fault::Crash, Fault::TornWrite] {
+            let dir = tempfile::tempdir().unwrap();
+            let faults = Arc::new(FaultInjector::new(1));
+            let database = Database::with_faults(&config(dir.path()), faults.clone()).unwrap();
+            let table = database.create_table().unwrap();
+            let mut connection = database.connect();
+            let kept = connection.insert(table, b"kept").unwrap();
+
+            // The next record logged never fully reaches the log
+            let next = faults.count(FaultPoint::WalWrite) + 1;
+            faults.fail_nth(FaultPoint::WalWrite, next, fault);
+            assert!(connection.insert(table, b"lost").is_err());
+            assert!(faults.crashed());
+            assert!(connection.insert(table, b"after").is_err());
+            drop(connection);
+            drop(database);
+
+            let database = Database::with_config(&config(dir.path())).unwrap();
+            let rows = database.connect().scan(table).unwrap();
+            assert_eq!(rows.len(), 1, "{fault:?}");
+            assert_eq!((rows[0].id, rows[0].data.as_slice()), (kept, &b"kept"[..]));
+        }
+    }
+
+    #[test]
+    fn test_deterministic_faults() {
+        // The same seed fails the same operations
+        let run = |seed| {
+            let dir = tempfile::tempdir().unwrap();
+            let faults = Arc::new(FaultInjector::new(seed));
+            let database = Database::with_faults(&config(dir.path()), faults.clone()).unwrap();
+            let table = database.create_table().unwrap();
+            faults.fail_randomly(FaultPoint::WalSync, 0.3, Fault::IoError);
+            let mut connection = database.connect();
+            let outcomes: Vec<_> = (0..20u8)
+                .map(|i| match connection.insert(table, &[i; 8]) {
+                    Ok(_) => true,
+                    Err(DatabaseError::TransactionError(_)) => false,
+                    Err(e) => panic!("{e}"),
+                })
+                .collect();
+            assert!(outcomes.contains(&true) && outcomes.contains(&false));
+            outcomes
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn test_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        let faults = Arc::new(FaultInjector::new(1));
+        let database = Database::with_faults(&config(dir.path()), faults.clone()).unwrap();
+        let table = database.create_table().unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook_seen = seen.clone();
+        faults.on_point(move |point, n| hook_seen.lock().unwrap().push((point, n)));
+        database.connect().insert(table, b"row").unwrap();
+
+        // A commit logs its changes, then syncs the log
+        let seen = seen.lock().unwrap();
+        let syncs = faults.count(FaultPoint::WalSync);
+        assert!(seen.contains(&(FaultPoint::WalSync, syncs)));
+        let writes = seen
+            .iter()
+            .filter(|(point, _)| *point == FaultPoint::WalWrite)
+            .count();
+        assert!(writes >= 2);
+    }
+}
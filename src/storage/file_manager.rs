@@ -0,0 +1,485 @@
+use super::encryption::TAG_SIZE;
+use super::faults::{FaultInjector, FaultyPageStore};
+use super::incremental::DeltaWriter;
+use super::page::Page;
+use super::page_io::{PageIO, PageIOError};
+use super::page_store::{MemoryPageStore, PageStore};
+use super::poison::{LockRecover, RwLockRecover};
+use super::superblock::{Superblock, SuperblockError, SUPERBLOCK_SIZE};
+use super::wal::Lsn;
+use crate::config::{Compression, Durability, IoMode, IN_MEMORY};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use thiserror::Error;
+
+/// Identifies one file in the database directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(pub u32);
+
+impl FileId {
+    /// The catalog file, which always exists and can't be removed.
+    pub const CATALOG: FileId = FileId(0);
+}
+
+impl Display for FileId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+const CATALOG_FILE_NAME: &str = "catalog.fdb";
+const DATA_FILE_EXTENSION: &str = "fdb";
+
+#[derive(Debug, Error)]
+pub enum FileManagerError {
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Page IO error: {0}")]
+    PageIOError(#[from] PageIOError),
+
+    #[error("Superblock error: {0}")]
+    SuperblockError(#[from] SuperblockError),
+
+    #[error("File {0} not found")]
+    FileNotFound(FileId),
+
+    #[error("File {0} is reserved and cannot be removed")]
+    ReservedFile(FileId),
+}
+
+/// How each file in the directory is opened.
+#[derive(Debug, Clone, Copy)]
+pub struct FileOptions {
+    pub page_size: usize,
+    pub durability: Durability,
+    pub io_mode: IoMode,
+    pub growth_chunk_pages: u64,
+    /// Recorded in the superblock of a new database, and checked against it
+    /// for an existing one
+    pub compression: Compression,
+    /// `PageCipher::key_check` of the encryption key, if pages are encrypted;
+    /// recorded and checked like `compression`
+    pub key_check: Option<[u8; TAG_SIZE]>,
+    /// Whether the database keeps a write-ahead log, and so stores page LSNs
+    pub logged: bool,
+}
+
+/// Manages the database directory: a catalog file plus one file per table or
+/// index, named `<id>.fdb`. Dropping a table deletes its file, returning the
+/// space to the filesystem.
+///
+/// Page 0 of the catalog file holds the `Superblock`.
+///
+/// Opened at `IN_MEMORY` rather than a directory, each file is a
+/// `MemoryPageStore` instead, and nothing is read from or written to disk
+/// but for copies asked for with `copy_to` and `copy_changed_to`.
+pub struct FileManager {
+    root: PathBuf,
+    options: FileOptions,
+    superblock: RwLock<Superblock>,
+    files: RwLock<HashMap<FileId, Arc<Mutex<dyn PageStore>>>>,
+    /// Faults injected into every file's I/O, for tests
+    faults: Option<Arc<FaultInjector>>,
+}
+
+impl FileManager {
+    /// Open the database directory at `root`, creating it and the catalog
+    /// file if needed, and opening every data file already present, with
+    /// any `faults` injected into the I/O of every file.
+    ///
+    /// An existing database is checked against the superblock before any file
+    /// is read, so one written with another page size or format version is
+    /// refused rather than misread.
+    pub fn open(
+        root: impl AsRef<Path>,
+        options: FileOptions,
+        faults: Option<Arc<FaultInjector>>,
+    ) -> Result<Self, FileManagerError> {
+        let root = root.as_ref().to_path_buf();
+        let in_memory = root == Path::new(IN_MEMORY);
+        if !in_memory {
+            fs::create_dir_all(&root)?;
+        }
+
+        let catalog_path = Self::catalog_path(&root);
+        let existing = if !in_memory && catalog_path.exists() {
+            Superblock::read_from(&catalog_path)?
+        } else {
+            None
+        };
+        if let Some(superblock) = &existing {
+            superblock.validate(
+                options.page_size,
+                options.compression,
+                options.key_check,
+                options.logged,
+            )?;
+        }
+
+        let superblock = existing.unwrap_or_else(|| {
+            Superblock::new(
+                options.page_size,
+                options.compression,
+                options.key_check,
+                options.logged,
+            )
+        });
+        let manager = Self {
+            root,
+            options,
+            superblock: RwLock::new(superblock),
+            files: RwLock::new(HashMap::new()),
+            faults,
+        };
+        manager.open_file(FileId::CATALOG)?;
+        if existing.is_none() {
+            let catalog = manager.file(FileId::CATALOG)?;
+            let mut catalog = catalog.lock_recover();
+            let page = superblock.encode(options.page_size);
+            catalog.write_page(0, options.page_size, &page)?;
+            catalog.flush()?;
+        }
+        if !in_memory {
+            for entry in fs::read_dir(&manager.root)? {
+                if let Some(file_id) = Self::parse_file_name(&entry?.path()) {
+                    manager.open_file(file_id)?;
+                }
+            }
+        }
+        Ok(manager)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Whether the files are kept in memory rather than on disk.
+    pub fn in_memory(&self) -> bool {
+        self.root == Path::new(IN_MEMORY)
+    }
+
+    pub fn superblock(&self) -> Superblock {
+        *self.superblock.read_recover()
+    }
+
+    /// Record the latest checkpoint in the superblock.
+    pub fn set_checkpoint(&self, lsn: Lsn) -> Result<(), FileManagerError> {
+        let mut superblock = self.superblock.write_recover();
+        let mut updated = *superblock;
+        updated.checkpoint = lsn;
+        if self.in_memory() {
+            let catalog = self.file(FileId::CATALOG)?;
+            let mut catalog = catalog.lock_recover();
+            let mut page = catalog.read_page(0, self.options.page_size)?;
+            let encoded = updated.encode(SUPERBLOCK_SIZE);
+            page.as_bytes_mut()[..SUPERBLOCK_SIZE].copy_from_slice(encoded.as_bytes());
+            catalog.write_page(0, self.options.page_size, &page)?;
+        } else {
+            updated.write_to(Self::catalog_path(&self.root))?;
+        }
+        *superblock = updated;
+        Ok(())
+    }
+
+    /// The catalog file of the database directory at `root`.
+    pub fn catalog_path(root: &Path) -> PathBuf {
+        root.join(CATALOG_FILE_NAME)
+    }
+
+    pub fn path(&self, file_id: FileId) -> PathBuf {
+        Self::file_path(&self.root, file_id)
+    }
+
+    /// Where file `file_id` of the database directory at `root` lives.
+    pub fn file_path(root: &Path, file_id: FileId) -> PathBuf {
+        if file_id == FileId::CATALOG {
+            Self::catalog_path(root)
+        } else {
+            root.join(format!("{}.{}", file_id.0, DATA_FILE_EXTENSION))
+        }
+    }
+
+    /// Create a new, empty data file.
+    pub fn create_file(&self) -> Result<FileId, FileManagerError> {
+        let next = self
+            .files
+            .read_recover()
+            .keys()
+            .map(|file_id| file_id.0)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let file_id = FileId(next);
+        self.open_file(file_id)?;
+        Ok(file_id)
+    }
+
+    /// Open a data file, creating it if it doesn't exist yet, as when a
+    /// replica first receives a page of a file created on its primary.
+    pub fn ensure_file(&self, file_id: FileId) -> Result<(), FileManagerError> {
+        if self.files.read_recover().contains_key(&file_id) {
+            return Ok(());
+        }
+        self.open_file(file_id)
+    }
+
+    /// Close and delete a data file.
+    pub fn remove_file(&self, file_id: FileId) -> Result<(), FileManagerError> {
+        if file_id == FileId::CATALOG {
+            return Err(FileManagerError::ReservedFile(file_id));
+        }
+        self.files
+            .write_recover()
+            .remove(&file_id)
+            .ok_or(FileManagerError::FileNotFound(file_id))?;
+        if !self.in_memory() {
+            fs::remove_file(self.path(file_id))?;
+        }
+        Ok(())
+    }
+
+    pub fn file(&self, file_id: FileId) -> Result<Arc<Mutex<dyn PageStore>>, FileManagerError> {
+        self.files
+            .read_recover()
+            .get(&file_id)
+            .cloned()
+            .ok_or(FileManagerError::FileNotFound(file_id))
+    }
+
+    /// The number of pages a file holds on disk, including any allocated
+    /// ahead of use.
+    pub fn page_count(&self, file_id: FileId) -> Result<u64, FileManagerError> {
+        let file = self.file(file_id)?;
+        let pages = file
+            .lock_recover()
+            .validate_length(self.options.page_size)?;
+        Ok(pages)
+    }
+
+    /// The ids of every open file, in ascending order.
+    pub fn file_ids(&self) -> Vec<FileId> {
+        let mut file_ids: Vec<_> = self.files.read_recover().keys().copied().collect();
+        file_ids.sort();
+        file_ids
+    }
+
+    /// Copy the catalog and every data file into `dest`, which is created
+    /// if needed. Each file is held while it is copied, so no page write
+    /// lands in the middle of it.
+    pub fn copy_to(&self, dest: &Path) -> Result<(), FileManagerError> {
+        fs::create_dir_all(dest)?;
+        let _superblock = self.superblock.read_recover();
+        for file_id in self.file_ids() {
+            let file = self.file(file_id)?;
+            let mut file = file.lock_recover();
+            file.flush()?;
+            let path = self.path(file_id);
+            let copy = dest.join(path.file_name().unwrap());
+            if self.in_memory() {
+                fs::write(copy, self.contents(&mut *file)?)?;
+            } else {
+                fs::copy(&path, copy)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Every page of `file`, back to back.
+    fn contents(&self, file: &mut dyn PageStore) -> Result<Vec<u8>, FileManagerError> {
+        let page_size = self.options.page_size;
+        let mut data = Vec::new();
+        for page_no in 0..file.validate_length(page_size)? {
+            data.extend_from_slice(file.read_page(page_no, page_size)?.as_bytes());
+        }
+        Ok(data)
+    }
+
+    /// Like `copy_to`, but only the pages of each data file that `changed`
+    /// picks out are copied, with the file's length, into a delta file.
+    /// The catalog is copied whole.
+    pub fn copy_changed_to(
+        &self,
+        dest: &Path,
+        changed: impl Fn(&Page) -> bool,
+    ) -> Result<(), FileManagerError> {
+        fs::create_dir_all(dest)?;
+        let _superblock = self.superblock.read_recover();
+        for file_id in self.file_ids() {
+            let file = self.file(file_id)?;
+            let mut file = file.lock_recover();
+            file.flush()?;
+            if file_id == FileId::CATALOG && self.in_memory() {
+                fs::write(Self::catalog_path(dest), self.contents(&mut *file)?)?;
+                continue;
+            }
+            if file_id == FileId::CATALOG {
+                fs::copy(Self::catalog_path(&self.root), Self::catalog_path(dest))?;
+                continue;
+            }
+            let page_count = file.validate_length(self.options.page_size)?;
+            let mut delta = DeltaWriter::create(dest, file_id, page_count)?;
+            for page_no in 0..page_count {
+                let page = file.read_page(page_no, self.options.page_size)?;
+                if changed(&page) {
+                    delta.write(page_no, &page)?;
+                }
+            }
+            delta.finish()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<(), FileManagerError> {
+        for file_id in self.file_ids() {
+            self.file(file_id)?.lock_recover().flush()?;
+        }
+        Ok(())
+    }
+
+    fn open_file(&self, file_id: FileId) -> Result<(), FileManagerError> {
+        let store: Arc<Mutex<dyn PageStore>> = match (self.in_memory(), &self.faults) {
+            (true, None) => Arc::new(Mutex::new(MemoryPageStore::default())),
+            (true, Some(faults)) => Arc::new(Mutex::new(FaultyPageStore {
+                inner: MemoryPageStore::default(),
+                faults: faults.clone(),
+            })),
+            (false, faults) => {
+                let mut page_io = PageIO::open(
+                    self.path(file_id),
+                    self.options.durability,
+                    self.options.io_mode,
+                )?;
+                page_io.validate_length(self.options.page_size)?;
+                page_io.set_growth_chunk(self.options.growth_chunk_pages);
+                match faults {
+                    None => Arc::new(Mutex::new(page_io)),
+                    Some(faults) => Arc::new(Mutex::new(FaultyPageStore {
+                        inner: page_io,
+                        faults: faults.clone(),
+                    })),
+                }
+            }
+        };
+        self.files.write_recover().insert(file_id, store);
+        Ok(())
+    }
+
+    pub(super) fn parse_file_name(path: &Path) -> Option<FileId> {
+        if path.extension()? != DATA_FILE_EXTENSION {
+            return None;
+        }
+        path.file_stem()?.to_str()?.parse().ok().map(FileId)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> FileOptions {
+        FileOptions {
+            page_size: 128,
+            durability: Durability::Full,
+            io_mode: IoMode::Buffered,
+            growth_chunk_pages: 1,
+            compression: Compression::None,
+            key_check: None,
+            logged: false,
+        }
+    }
+
+    #[test]
+    fn test_creates_directory_and_catalog() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("db");
+        let manager = FileManager::open(&root, options(), None).unwrap();
+
+        assert!(root.join("catalog.fdb").exists());
+        assert_eq!(manager.file_ids(), vec![FileId::CATALOG]);
+        assert_eq!(manager.superblock().page_size, 128);
+    }
+
+    #[test]
+    fn test_superblock_is_checked_on_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let created = FileManager::open(dir.path(), options(), None)
+            .unwrap()
+            .superblock();
+        let reopened = FileManager::open(dir.path(), options(), None).unwrap();
+        assert_eq!(reopened.superblock(), created);
+        drop(reopened);
+
+        let wrong_page_size = FileOptions {
+            page_size: 256,
+            ..options()
+        };
+        assert!(matches!(
+            FileManager::open(dir.path(), wrong_page_size, None),
+            Err(FileManagerError::SuperblockError(
+                SuperblockError::PageSizeMismatch { .. }
+            ))
+        ));
+
+        fs::write(dir.path().join("catalog.fdb"), vec![0u8; 128]).unwrap();
+        assert!(matches!(
+            FileManager::open(dir.path(), options(), None),
+            Err(FileManagerError::SuperblockError(
+                SuperblockError::InvalidMagic
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_set_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FileManager::open(dir.path(), options(), None).unwrap();
+        manager.set_checkpoint(Lsn(99)).unwrap();
+        assert_eq!(manager.superblock().checkpoint, Lsn(99));
+        drop(manager);
+
+        let reopened = FileManager::open(dir.path(), options(), None).unwrap();
+        assert_eq!(reopened.superblock().checkpoint, Lsn(99));
+    }
+
+    #[test]
+    fn test_create_and_reopen_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FileManager::open(dir.path(), options(), None).unwrap();
+        assert_eq!(manager.create_file().unwrap(), FileId(1));
+        assert_eq!(manager.create_file().unwrap(), FileId(2));
+        assert!(dir.path().join("2.fdb").exists());
+        drop(manager);
+
+        // Unrelated files in the directory are ignored
+        fs::write(dir.path().join("notes.txt"), "hello").unwrap();
+        let manager = FileManager::open(dir.path(), options(), None).unwrap();
+        assert_eq!(
+            manager.file_ids(),
+            vec![FileId::CATALOG, FileId(1), FileId(2)]
+        );
+        assert_eq!(manager.create_file().unwrap(), FileId(3));
+    }
+
+    #[test]
+    fn test_remove_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = FileManager::open(dir.path(), options(), None).unwrap();
+        let file_id = manager.create_file().unwrap();
+
+        manager.remove_file(file_id).unwrap();
+        assert!(!dir.path().join("1.fdb").exists());
+        assert!(matches!(
+            manager.file(file_id),
+            Err(FileManagerError::FileNotFound(_))
+        ));
+        assert!(matches!(
+            manager.remove_file(FileId::CATALOG),
+            Err(FileManagerError::ReservedFile(_))
+        ));
+    }
+}
@@ -0,0 +1,278 @@
+use super::file_manager::{FileId, FileManager, FileManagerError};
+use super::page::{Page, PageId};
+use super::page_manager::{PageManager, PageManagerError};
+use super::slotted_page::SlottedPage;
+use super::wal::{Lsn, Wal, WalRecord};
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::PathBuf;
+
+/// Something wrong found by `check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The page can't be decoded: its header or compressed contents are
+    /// damaged, or it fails authentication
+    UnreadablePage { page_id: PageId, error: String },
+    /// The page decodes, but its slot directory is damaged
+    DamagedRecords { page_id: PageId, error: String },
+    /// The page records a change later than anything in the log
+    PageAheadOfLog { page_id: PageId, lsn: Lsn },
+    /// The superblock's checkpoint isn't a checkpoint record in the log
+    MissingCheckpoint(Lsn),
+    /// The log can't be read past this point
+    DamagedLog { after: Lsn, error: String },
+    /// A file in the database directory that doesn't belong there
+    UnknownFile(PathBuf),
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::UnreadablePage { page_id, error } => {
+                write!(f, "page {} can't be read: {}", page_id, error)
+            }
+            Problem::DamagedRecords { page_id, error } => {
+                write!(f, "page {} has damaged records: {}", page_id, error)
+            }
+            Problem::PageAheadOfLog { page_id, lsn } => {
+                write!(
+                    f,
+                    "page {} records LSN {}, past the end of the log",
+                    page_id, lsn
+                )
+            }
+            Problem::MissingCheckpoint(lsn) => {
+                write!(f, "the checkpoint at LSN {} isn't in the log", lsn)
+            }
+            Problem::DamagedLog { after, error } => {
+                write!(f, "the log can't be read after LSN {}: {}", after, error)
+            }
+            Problem::UnknownFile(path) => write!(f, "{} isn't a database file", path.display()),
+        }
+    }
+}
+
+/// What `check` found, and in salvage mode, repaired.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FsckReport {
+    /// Data pages checked
+    pub pages: u64,
+    pub problems: Vec<Problem>,
+    /// Pages rewritten by salvage
+    pub repaired: usize,
+    /// Records salvage couldn't recover from damaged pages; a page that
+    /// couldn't be decoded at all is emptied and counted only in `repaired`
+    pub lost_records: usize,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Check a database for damage, reading every page from disk; best opened
+/// without recovery and with nothing else using it, so what is checked is
+/// what was on disk.
+///
+/// Every data page must decode, which authenticates encrypted pages, and
+/// hold a valid slotted page. Pages can't claim changes the log doesn't
+/// have, the log must read cleanly, the superblock's checkpoint must be
+/// in it, and the directory may only hold the database's own files.
+///
+/// With `salvage`, damaged pages are rebuilt from the records still
+/// readable in them, and pages that can't be decoded are emptied, so the
+/// rest of the database can be used. Nothing else is repaired.
+pub fn check(pages: &PageManager, salvage: bool) -> Result<FsckReport, PageManagerError> {
+    let mut report = FsckReport::default();
+    check_directory(pages, &mut report)?;
+    let log_end = match pages.wal() {
+        Some(wal) => Some(check_log(wal, pages, &mut report)?),
+        None => None,
+    };
+
+    pages.flush()?;
+    for file_id in pages.files().file_ids() {
+        if file_id == FileId::CATALOG {
+            continue;
+        }
+        for page_no in 0..pages.files().page_count(file_id)? {
+            let page_id = PageId::new(file_id, page_no);
+            report.pages += 1;
+            let (page, lsn) = match pages.read_stored(page_id) {
+                Ok(stored) => stored,
+                Err(PageManagerError::PageDecodeError(e)) => {
+                    report.problems.push(Problem::UnreadablePage {
+                        page_id,
+                        error: e.to_string(),
+                    });
+                    if salvage {
+                        pages.write_page(page_id, Page::zeros(pages.page_size()))?;
+                        report.repaired += 1;
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if log_end.is_some_and(|end| lsn >= end) {
+                report
+                    .problems
+                    .push(Problem::PageAheadOfLog { page_id, lsn });
+            }
+            let bytes = page.as_bytes().to_vec();
+            if let Err(e) = SlottedPage::from_page(page) {
+                report.problems.push(Problem::DamagedRecords {
+                    page_id,
+                    error: e.to_string(),
+                });
+                if salvage {
+                    let (salvaged, lost) = SlottedPage::salvage(Page::new(bytes));
+                    pages.write_page(page_id, salvaged.into_page())?;
+                    report.repaired += 1;
+                    report.lost_records += lost;
+                }
+            }
+        }
+    }
+    if salvage {
+        pages.flush()?;
+    }
+    Ok(report)
+}
+
+fn check_directory(pages: &PageManager, report: &mut FsckReport) -> Result<(), PageManagerError> {
+    let root = pages.files().root();
+    let catalog = FileManager::catalog_path(root);
+    let entries = fs::read_dir(root).map_err(FileManagerError::from)?;
+    for entry in entries {
+        let path = entry.map_err(FileManagerError::from)?.path();
+        let known = path == catalog
+            || path == Wal::dir(root)
+            || FileManager::parse_file_name(&path).is_some();
+        if !known {
+            report.problems.push(Problem::UnknownFile(path));
+        }
+    }
+    Ok(())
+}
+
+/// Read the whole log, returning where it ends.
+fn check_log(
+    wal: &Wal,
+    pages: &PageManager,
+    report: &mut FsckReport,
+) -> Result<Lsn, PageManagerError> {
+    let checkpoint = pages.files().superblock().checkpoint;
+    let mut found_checkpoint = checkpoint == Lsn::ZERO;
+    let mut after = wal.history_start()?;
+    for entry in wal.read_from(after)? {
+        match entry {
+            Ok((lsn, record)) => {
+                if lsn == checkpoint && matches!(record, WalRecord::Checkpoint { .. }) {
+                    found_checkpoint = true;
+                }
+                after = lsn;
+            }
+            Err(e) => {
+                report.problems.push(Problem::DamagedLog {
+                    after,
+                    error: e.to_string(),
+                });
+                break;
+            }
+        }
+    }
+    if !found_checkpoint {
+        report.problems.push(Problem::MissingCheckpoint(checkpoint));
+    }
+    Ok(wal.end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WalConfig;
+    use crate::storage::page_manager::PageManagerBuilder;
+    use crate::storage::slotted_page::SlotId;
+    use crate::storage::wal::TxnId;
+    use std::os::unix::fs::FileExt;
+    use std::path::Path;
+
+    const PAGE_SIZE: usize = 128;
+    /// The codec header of a logged page
+    const HEADER: u64 = 16;
+
+    fn open(dir: &Path) -> PageManager {
+        PageManagerBuilder::new(dir)
+            .page_size(PAGE_SIZE)
+            .wal(Some(WalConfig {
+                segment_size: 4096,
+                checkpoint_interval_ms: None,
+                commit_delay_us: None,
+                retention_ms: None,
+                archive_dir: None,
+                full_page_writes: true,
+            }))
+            .recover(false)
+            .build()
+            .unwrap()
+    }
+
+    fn corrupt(dir: &Path, page_id: PageId, offset: u64, bytes: &[u8]) {
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .open(FileManager::file_path(dir, page_id.file))
+            .unwrap();
+        file.write_all_at(bytes, page_id.page_no * PAGE_SIZE as u64 + offset)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_check_and_salvage() {
+        let dir = tempfile::tempdir().unwrap();
+        let pages = open(dir.path());
+        let file_id = pages.create_file().unwrap();
+        for page_no in 0..3 {
+            let mut page = SlottedPage::new(pages.page_size());
+            for i in 0..3 {
+                page.insert(&[i; 8]).unwrap().unwrap();
+            }
+            pages
+                .log_write(TxnId(1), PageId::new(file_id, page_no), page.into_page())
+                .unwrap();
+        }
+        pages.checkpoint().unwrap();
+        assert!(check(&pages, false).unwrap().is_clean());
+        drop(pages);
+
+        let (unreadable, damaged) = (PageId::new(file_id, 0), PageId::new(file_id, 2));
+        // A stored length longer than the page
+        corrupt(dir.path(), unreadable, 4, &[0xff; 4]);
+        // The second slot points past the end of the page
+        corrupt(dir.path(), damaged, HEADER + 12 + 8, &[0, 0, 0xff, 0]);
+        fs::write(dir.path().join("stray.tmp"), b"").unwrap();
+
+        let pages = open(dir.path());
+        let report = check(&pages, false).unwrap();
+        assert_eq!(report.pages, 3);
+        assert!(matches!(
+            &report.problems[..],
+            [
+                Problem::UnknownFile(_),
+                Problem::UnreadablePage { page_id: a, .. },
+                Problem::DamagedRecords { page_id: b, .. },
+            ] if *a == unreadable && *b == damaged
+        ));
+
+        let report = check(&pages, true).unwrap();
+        assert_eq!((report.repaired, report.lost_records), (2, 1));
+        drop(pages);
+        let pages = open(dir.path());
+        let report = check(&pages, false).unwrap();
+        assert_eq!(report.problems.len(), 1);
+        let salvaged = pages.get_page(damaged).unwrap().page().as_bytes().to_vec();
+        let salvaged = SlottedPage::from_page(Page::new(salvaged)).unwrap();
+        assert_eq!(salvaged.get(SlotId(2)).unwrap(), Some(&[2; 8][..]));
+        assert_eq!(salvaged.records().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,241 @@
+use super::page::{Page, PageId};
+use super::page_manager::{PageManager, PageManagerError};
+use super::wal::{micros_since_epoch, Lsn, TxnId, WalRecord};
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+
+/// A point in the past to read pages as of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// Just before the record at this LSN: transactions that committed
+    /// earlier in the log are visible, and no others
+    Lsn(Lsn),
+    /// Transactions that committed at or before this time are visible
+    Time(SystemTime),
+}
+
+/// A change to the page being rebuilt.
+struct Change {
+    txn: TxnId,
+    before: Vec<u8>,
+}
+
+/// Rebuild a page as it was at `as_of`, from its current contents and the
+/// log.
+///
+/// Every change to the page by a transaction that wasn't committed at that
+/// point is undone, newest first, by restoring its before image, as
+/// recovery does. History only reaches back as far as the log, which the
+/// WAL's retention period extends; asking for anything earlier, or for a
+/// point when a transaction whose start has been removed was still running,
+/// fails with `HistoryUnavailable`. Changes made with `write_page` bypass
+/// the log and have no history.
+pub fn page_as_of(
+    pages: &PageManager,
+    page_id: PageId,
+    as_of: AsOf,
+) -> Result<Page, PageManagerError> {
+    let wal = pages.wal().ok_or(PageManagerError::WalDisabled)?;
+    // Changes logged after this are undone from the log like the rest
+    let mut page = Page::new(pages.get_page(page_id)?.page().as_bytes().to_vec());
+
+    // Without older segments, transactions seen without their begin record
+    // started before the log does
+    let history_start = wal.history_start()?;
+    let trimmed = history_start != Lsn::ZERO;
+    let mut seen = HashSet::new();
+    let mut partial: HashMap<TxnId, Lsn> = HashMap::new();
+    let mut commits: HashMap<TxnId, Lsn> = HashMap::new();
+    // Commit and checkpoint times, in log order
+    let mut times = Vec::new();
+    let mut changes = Vec::new();
+    for entry in wal.read_from(history_start)? {
+        let (lsn, record) = entry?;
+        if let Some(txn) = record.txn() {
+            if seen.insert(txn) && trimmed && !matches!(record, WalRecord::Begin { .. }) {
+                partial.insert(txn, Lsn(u64::MAX));
+            }
+        }
+        match record {
+            WalRecord::Commit { txn, time } => {
+                commits.insert(txn, lsn);
+                times.push((lsn, time));
+                partial.entry(txn).and_modify(|end| *end = lsn);
+            }
+            WalRecord::Abort { txn } => {
+                partial.entry(txn).and_modify(|end| *end = lsn);
+            }
+            WalRecord::PageWrite {
+                txn,
+                page_id: changed,
+                before,
+                ..
+            } if changed == page_id => changes.push(Change { txn, before }),
+            WalRecord::Checkpoint { time, .. } => times.push((lsn, time)),
+            _ => {}
+        }
+    }
+
+    let target = match as_of {
+        AsOf::Lsn(lsn) => {
+            if trimmed && lsn < history_start {
+                return Err(PageManagerError::HistoryUnavailable);
+            }
+            lsn
+        }
+        AsOf::Time(time) => {
+            let time = micros_since_epoch(time);
+            // Commits after `time` may have been removed along with the
+            // older log, unless something from before it remains
+            if trimmed && !times.iter().any(|&(_, at)| at <= time) {
+                return Err(PageManagerError::HistoryUnavailable);
+            }
+            times
+                .iter()
+                .find(|&&(_, at)| at > time)
+                .map_or(Lsn(u64::MAX), |&(lsn, _)| lsn)
+        }
+    };
+    // A transaction that started before the log does must have finished
+    // by then, or its older changes would be needed
+    if partial.values().any(|&end| end >= target) {
+        return Err(PageManagerError::HistoryUnavailable);
+    }
+
+    for change in changes.into_iter().rev() {
+        if commits.get(&change.txn).is_none_or(|&lsn| lsn >= target) {
+            page = Page::new(change.before);
+        }
+    }
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WalConfig;
+    use crate::storage::file_manager::FileId;
+    use crate::storage::page_manager::PageManagerBuilder;
+    use std::thread;
+    use std::time::Duration;
+
+    const PAGE: PageId = PageId {
+        file: FileId(1),
+        page_no: 0,
+    };
+
+    fn open(dir: &std::path::Path, retention_ms: Option<u64>) -> PageManager {
+        let pages = PageManagerBuilder::new(dir)
+            .page_size(128)
+            .wal(Some(WalConfig {
+                segment_size: 512,
+                checkpoint_interval_ms: None,
+                commit_delay_us: None,
+                retention_ms,
+                archive_dir: None,
+                full_page_writes: true,
+            }))
+            .build()
+            .unwrap();
+        if pages.files().file(PAGE.file).is_err() {
+            pages.create_file().unwrap();
+        }
+        pages
+    }
+
+    fn page(value: u8) -> Page {
+        Page::full(value, 112)
+    }
+
+    fn write(pages: &PageManager, txn: u64, value: u8, commit: bool) -> Lsn {
+        let wal = pages.wal().unwrap();
+        wal.append(&WalRecord::Begin { txn: TxnId(txn) }).unwrap();
+        pages.log_write(TxnId(txn), PAGE, page(value)).unwrap();
+        match commit {
+            true => wal.append(&WalRecord::commit(TxnId(txn))).unwrap(),
+            false => wal.end(),
+        }
+    }
+
+    fn bytes_as_of(pages: &PageManager, as_of: AsOf) -> Result<Vec<u8>, PageManagerError> {
+        page_as_of(pages, PAGE, as_of).map(|page| page.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_page_as_of_lsn() {
+        let dir = tempfile::tempdir().unwrap();
+        let pages = open(dir.path(), None);
+        let first = write(&pages, 1, 1, true);
+        let second = write(&pages, 2, 2, true);
+        // Still running, so never visible
+        write(&pages, 3, 3, false);
+
+        assert_eq!(bytes_as_of(&pages, AsOf::Lsn(first)).unwrap(), vec![0; 112]);
+        assert_eq!(
+            bytes_as_of(&pages, AsOf::Lsn(Lsn(first.0 + 1))).unwrap(),
+            vec![1; 112]
+        );
+        assert_eq!(
+            bytes_as_of(&pages, AsOf::Lsn(Lsn(second.0 + 1))).unwrap(),
+            vec![2; 112]
+        );
+        assert_eq!(
+            bytes_as_of(&pages, AsOf::Time(SystemTime::now())).unwrap(),
+            vec![2; 112]
+        );
+        // The page itself is left as it is
+        let current = pages.get_page(PAGE).unwrap();
+        assert_eq!(*current.page(), page(3));
+    }
+
+    #[test]
+    fn test_page_as_of_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let pages = open(dir.path(), None);
+        write(&pages, 1, 1, true);
+        thread::sleep(Duration::from_millis(2));
+        let between = SystemTime::now();
+        thread::sleep(Duration::from_millis(2));
+        write(&pages, 2, 2, true);
+
+        assert_eq!(
+            bytes_as_of(&pages, AsOf::Time(between)).unwrap(),
+            vec![1; 112]
+        );
+        assert_eq!(
+            bytes_as_of(&pages, AsOf::Time(SystemTime::UNIX_EPOCH)).unwrap(),
+            vec![0; 112]
+        );
+    }
+
+    #[test]
+    fn test_history_is_limited_to_retained_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let pages = open(dir.path(), Some(60_000));
+        let first = write(&pages, 1, 1, true);
+        for txn in 2..10 {
+            write(&pages, txn, txn as u8, true);
+        }
+        pages.checkpoint().unwrap();
+        // Segments within the retention period are kept
+        assert_eq!(pages.wal().unwrap().history_start().unwrap(), Lsn::ZERO);
+        assert_eq!(bytes_as_of(&pages, AsOf::Lsn(first)).unwrap(), vec![0; 112]);
+        drop(pages);
+
+        let pages = open(dir.path(), None);
+        pages.checkpoint().unwrap();
+        assert!(pages.wal().unwrap().history_start().unwrap() > first);
+        assert!(matches!(
+            bytes_as_of(&pages, AsOf::Lsn(first)),
+            Err(PageManagerError::HistoryUnavailable)
+        ));
+        assert!(matches!(
+            bytes_as_of(&pages, AsOf::Time(SystemTime::UNIX_EPOCH)),
+            Err(PageManagerError::HistoryUnavailable)
+        ));
+        assert_eq!(
+            bytes_as_of(&pages, AsOf::Time(SystemTime::now())).unwrap(),
+            vec![9; 112]
+        );
+    }
+}
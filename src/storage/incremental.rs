@@ -0,0 +1,278 @@
+use super::file_manager::{FileId, FileManager, FileManagerError};
+use super::page::Page;
+use super::page_manager::PageManagerError;
+use super::superblock::Superblock;
+use super::wal::{Lsn, Wal, WalError};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Present in an incremental backup, holding the LSN its pages were
+/// copied from.
+const MARKER_FILE_NAME: &str = "incremental";
+const DELTA_FILE_EXTENSION: &str = "delta";
+
+/// Writes the pages of one data file changed since an earlier backup: the
+/// file's length in pages, then each page number and stored page.
+pub(super) struct DeltaWriter {
+    out: BufWriter<File>,
+}
+
+impl DeltaWriter {
+    pub(super) fn create(dest: &Path, file_id: FileId, page_count: u64) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(delta_path(dest, file_id))?);
+        out.write_u64::<BigEndian>(page_count)?;
+        Ok(Self { out })
+    }
+
+    pub(super) fn write(&mut self, page_no: u64, page: &Page) -> io::Result<()> {
+        self.out.write_u64::<BigEndian>(page_no)?;
+        self.out.write_all(page.as_bytes())
+    }
+
+    pub(super) fn finish(mut self) -> io::Result<()> {
+        self.out.flush()?;
+        self.out.get_ref().sync_all()
+    }
+}
+
+fn delta_path(dir: &Path, file_id: FileId) -> PathBuf {
+    dir.join(format!("{}.{}", file_id.0, DELTA_FILE_EXTENSION))
+}
+
+/// Mark `dest` as an incremental backup of the pages changed from `since`.
+pub(super) fn write_marker(dest: &Path, since: Lsn) -> io::Result<()> {
+    fs::write(dest.join(MARKER_FILE_NAME), since.0.to_be_bytes())
+}
+
+/// Where an incremental backup's pages were copied from, or `None` for a
+/// full backup.
+fn read_marker(dir: &Path) -> io::Result<Option<Lsn>> {
+    match File::open(dir.join(MARKER_FILE_NAME)) {
+        Ok(mut file) => Ok(Some(Lsn(file.read_u64::<BigEndian>()?))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Where recovery of the backup in `dir` starts reading its log. Every page
+/// changed since has a later LSN, so an incremental backup taken on top of
+/// this one copies the pages from here on.
+pub(super) fn recovery_start(dir: &Path) -> Result<Lsn, PageManagerError> {
+    let superblock = Superblock::read_from(FileManager::catalog_path(dir))
+        .map_err(FileManagerError::from)?
+        .ok_or(PageManagerError::InvalidBackup(dir.to_path_buf()))?;
+    if superblock.checkpoint == Lsn::ZERO {
+        return Ok(Lsn::ZERO);
+    }
+    let (lsn, record) = Wal::read_dir(&Wal::dir(dir), superblock.checkpoint)?
+        .next()
+        .ok_or(WalError::Corrupted(superblock.checkpoint))??;
+    Ok(record
+        .recovery_start()
+        .filter(|_| lsn == superblock.checkpoint)
+        .ok_or(WalError::Corrupted(superblock.checkpoint))?)
+}
+
+/// Combine a full backup and the incremental backups taken after it, in
+/// order, each on top of the one before, into a full backup at `dest` as
+/// of the last of them. Fails with `InvalidBackup` if the chain is out of
+/// order or broken.
+///
+/// The full backup is copied, then each incremental backup's changed pages
+/// are written over it, files it lacks are removed, and its catalog and log
+/// replace the old ones. Opening `dest` then recovers from the last
+/// backup's checkpoint, as with any other backup.
+pub fn merge(chain: &[impl AsRef<Path>], dest: impl AsRef<Path>) -> Result<(), PageManagerError> {
+    let dest = dest.as_ref();
+    let (full, incrementals) = chain
+        .split_first()
+        .ok_or(PageManagerError::InvalidBackup(dest.to_path_buf()))?;
+    let full = full.as_ref();
+    if read_marker(full).map_err(FileManagerError::from)?.is_some() {
+        return Err(PageManagerError::InvalidBackup(full.to_path_buf()));
+    }
+    copy_full(full, dest).map_err(FileManagerError::from)?;
+
+    for dir in incrementals {
+        let dir = dir.as_ref();
+        let since = read_marker(dir).map_err(FileManagerError::from)?;
+        if since != Some(recovery_start(dest)?) {
+            return Err(PageManagerError::InvalidBackup(dir.to_path_buf()));
+        }
+        apply(dir, dest).map_err(FileManagerError::from)?;
+    }
+    Ok(())
+}
+
+/// Copy a full backup's catalog, data files and log.
+fn copy_full(full: &Path, dest: &Path) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(full)? {
+        let path = entry?.path();
+        if path.is_file() {
+            fs::copy(&path, dest.join(path.file_name().unwrap()))?;
+        }
+    }
+    copy_dir(&Wal::dir(full), &Wal::dir(dest))
+}
+
+/// Apply one incremental backup to the full backup in `dest`.
+fn apply(dir: &Path, dest: &Path) -> io::Result<()> {
+    let superblock = Superblock::read_from(FileManager::catalog_path(dir))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        .ok_or(io::ErrorKind::InvalidData)?;
+    let page_size = superblock.page_size as usize;
+
+    let mut kept = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_none_or(|ext| ext != DELTA_FILE_EXTENSION)
+        {
+            continue;
+        }
+        let Some(file_id) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str()?.parse().ok())
+        else {
+            continue;
+        };
+        let file_id = FileId(file_id);
+        kept.push(file_id);
+
+        let mut delta = BufReader::new(File::open(&path)?);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(FileManager::file_path(dest, file_id))?;
+        file.set_len(delta.read_u64::<BigEndian>()? * page_size as u64)?;
+        let mut page = vec![0; page_size];
+        loop {
+            let page_no = match delta.read_u64::<BigEndian>() {
+                Ok(page_no) => page_no,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            };
+            delta.read_exact(&mut page)?;
+            file.seek(SeekFrom::Start(page_no * page_size as u64))?;
+            file.write_all(&page)?;
+        }
+        file.sync_all()?;
+    }
+    // Files dropped since the last backup
+    for entry in fs::read_dir(dest)? {
+        let path = entry?.path();
+        if FileManager::parse_file_name(&path).is_some_and(|file_id| !kept.contains(&file_id)) {
+            fs::remove_file(path)?;
+        }
+    }
+
+    fs::copy(
+        FileManager::catalog_path(dir),
+        FileManager::catalog_path(dest),
+    )?;
+    fs::remove_dir_all(Wal::dir(dest))?;
+    copy_dir(&Wal::dir(dir), &Wal::dir(dest))
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let path = entry?.path();
+        fs::copy(&path, to.join(path.file_name().unwrap()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WalConfig;
+    use crate::storage::page::PageId;
+    use crate::storage::page_manager::{PageManager, PageManagerBuilder};
+    use crate::storage::wal::{TxnId, WalRecord};
+
+    fn open(dir: &Path) -> PageManager {
+        PageManagerBuilder::new(dir)
+            .page_size(128)
+            .wal(Some(WalConfig {
+                segment_size: 4096,
+                checkpoint_interval_ms: None,
+                commit_delay_us: None,
+                retention_ms: None,
+                archive_dir: None,
+                full_page_writes: true,
+            }))
+            .build()
+            .unwrap()
+    }
+
+    fn write(pages: &PageManager, txn: u64, page_id: PageId, value: u8) {
+        let wal = pages.wal().unwrap();
+        wal.append(&WalRecord::Begin { txn: TxnId(txn) }).unwrap();
+        pages
+            .log_write(TxnId(txn), page_id, Page::full(value, 112))
+            .unwrap();
+        wal.append(&WalRecord::commit(TxnId(txn))).unwrap();
+    }
+
+    fn value(pages: &PageManager, page_id: PageId) -> u8 {
+        pages.get_page(page_id).unwrap().page().as_bytes()[0]
+    }
+
+    fn delta_pages(dir: &Path, file_id: FileId) -> u64 {
+        let len = fs::metadata(delta_path(dir, file_id)).unwrap().len();
+        (len - 8) / (8 + 128)
+    }
+
+    #[test]
+    fn test_incremental_backups_merge() {
+        let (live, backups) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let backup = |name: &str| backups.path().join(name);
+        let pages = open(live.path());
+        let (first, dropped) = (pages.create_file().unwrap(), pages.create_file().unwrap());
+        for page_no in 0..8 {
+            write(&pages, page_no + 1, PageId::new(first, page_no), 1);
+        }
+        write(&pages, 9, PageId::new(dropped, 0), 1);
+        pages.backup(backup("full")).unwrap();
+
+        write(&pages, 10, PageId::new(first, 2), 2);
+        pages
+            .incremental_backup(backup("monday"), backup("full"))
+            .unwrap();
+        // Only the page written since is copied
+        assert_eq!(delta_pages(&backup("monday"), first), 1);
+
+        write(&pages, 11, PageId::new(first, 5), 3);
+        write(&pages, 12, PageId::new(first, 8), 3);
+        pages.drop_file(dropped).unwrap();
+        let third = pages.create_file().unwrap();
+        write(&pages, 13, PageId::new(third, 0), 4);
+        pages
+            .incremental_backup(backup("tuesday"), backup("monday"))
+            .unwrap();
+        assert_eq!(delta_pages(&backup("tuesday"), first), 2);
+
+        merge(
+            &[backup("full"), backup("monday"), backup("tuesday")],
+            backup("merged"),
+        )
+        .unwrap();
+        let merged = open(&backup("merged"));
+        assert_eq!(merged.files().file_ids(), pages.files().file_ids());
+        for page_no in 0..9 {
+            let page_id = PageId::new(first, page_no);
+            assert_eq!(value(&merged, page_id), value(&pages, page_id));
+        }
+        assert_eq!(value(&merged, PageId::new(third, 0)), 4);
+
+        // Each incremental backup must follow on from the one before
+        let result = merge(&[backup("full"), backup("tuesday")], backup("broken"));
+        assert!(matches!(result, Err(PageManagerError::InvalidBackup(_))));
+    }
+}
@@ -0,0 +1,299 @@
+use super::file_manager::FileId;
+use super::poison::LockRecover;
+use super::wal::TxnId;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Something a transaction can lock. Writes replace whole pages, and undo
+/// puts back each page as it was, so two transactions can't write to one
+/// table at once: the table is the smallest unit locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LockTarget {
+    Table(FileId),
+}
+
+impl Display for LockTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LockTarget::Table(table) => write!(f, "table {}", table),
+        }
+    }
+}
+
+/// How a target is locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LockMode {
+    /// Readers only
+    Shared,
+    /// A single writer
+    Exclusive,
+}
+
+impl LockMode {
+    fn compatible(self, other: LockMode) -> bool {
+        self == LockMode::Shared && other == LockMode::Shared
+    }
+
+    /// Whether holding `self` already grants `other`.
+    fn covers(self, other: LockMode) -> bool {
+        self == LockMode::Exclusive || other == LockMode::Shared
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum LockError {
+    #[error("Deadlock detected: transaction {0} waiting for a lock on {1}")]
+    Deadlock(TxnId, LockTarget),
+
+    #[error("Timed out waiting for a lock on {0}")]
+    Timeout(LockTarget),
+}
+
+#[derive(Default)]
+struct LockTable {
+    /// The transactions holding each target, and how
+    holders: HashMap<LockTarget, HashMap<TxnId, LockMode>>,
+    /// The targets each transaction holds
+    held: HashMap<TxnId, HashSet<LockTarget>>,
+    /// The lock each blocked transaction is waiting for
+    waiting: HashMap<TxnId, (LockTarget, LockMode)>,
+}
+
+impl LockTable {
+    /// The transactions `txn` would have to wait for to lock `target`.
+    fn blockers(&self, txn: TxnId, target: LockTarget, mode: LockMode) -> Vec<TxnId> {
+        self.holders
+            .get(&target)
+            .into_iter()
+            .flatten()
+            .filter(|&(&holder, &held)| holder != txn && !held.compatible(mode))
+            .map(|(&holder, _)| holder)
+            .collect()
+    }
+
+    /// Whether waiting for `blockers` would leave `txn` waiting, through the
+    /// waits-for graph, on itself.
+    fn deadlocks(&self, txn: TxnId, blockers: Vec<TxnId>) -> bool {
+        let mut seen = HashSet::new();
+        let mut stack = blockers;
+        while let Some(next) = stack.pop() {
+            if next == txn {
+                return true;
+            }
+            if !seen.insert(next) {
+                continue;
+            }
+            if let Some(&(target, mode)) = self.waiting.get(&next) {
+                stack.extend(self.blockers(next, target, mode));
+            }
+        }
+        false
+    }
+
+    fn grant(&mut self, txn: TxnId, target: LockTarget, mode: LockMode) {
+        self.holders.entry(target).or_default().insert(txn, mode);
+        self.held.entry(txn).or_default().insert(target);
+    }
+}
+
+/// Shared and exclusive locks on tables, held by transactions
+/// until they finish.
+///
+/// A lock that conflicts with one held by another transaction waits for it
+/// to be released. If waiting would close a cycle in the waits-for graph,
+/// the transaction asking is refused with `LockError::Deadlock` instead, so
+/// the outcome doesn't depend on timing; with a timeout set, waits that run
+/// over it fail with `LockError::Timeout`.
+pub struct LockManager {
+    table: Mutex<LockTable>,
+    released: Condvar,
+    timeout: Option<Duration>,
+}
+
+impl LockManager {
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            table: Mutex::new(LockTable::default()),
+            released: Condvar::new(),
+            timeout,
+        }
+    }
+
+    /// Lock `target` for `txn`, waiting for conflicting locks to be released.
+    /// Asking again for a lock already held, or a weaker one, returns
+    /// straight away; asking for a stronger one upgrades it.
+    pub fn lock(&self, txn: TxnId, target: LockTarget, mode: LockMode) -> Result<(), LockError> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut table = self.table.lock_recover();
+        let held = table
+            .holders
+            .get(&target)
+            .and_then(|holders| holders.get(&txn))
+            .copied();
+        if held.is_some_and(|held| held.covers(mode)) {
+            return Ok(());
+        }
+
+        loop {
+            let blockers = table.blockers(txn, target, mode);
+            if blockers.is_empty() {
+                table.waiting.remove(&txn);
+                table.grant(txn, target, mode);
+                return Ok(());
+            }
+            if table.deadlocks(txn, blockers) {
+                table.waiting.remove(&txn);
+                return Err(LockError::Deadlock(txn, target));
+            }
+            table.waiting.insert(txn, (target, mode));
+
+            table = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        table.waiting.remove(&txn);
+                        return Err(LockError::Timeout(target));
+                    }
+                    let wait = self.released.wait_timeout(table, deadline - now);
+                    wait.unwrap_or_else(PoisonError::into_inner).0
+                }
+                None => {
+                    let wait = self.released.wait(table);
+                    wait.unwrap_or_else(PoisonError::into_inner)
+                }
+            };
+        }
+    }
+
+    /// Release every lock `txn` holds, waking transactions waiting on them.
+    pub fn release_all(&self, txn: TxnId) {
+        let mut table = self.table.lock_recover();
+        table.waiting.remove(&txn);
+        for target in table.held.remove(&txn).unwrap_or_default() {
+            if let Some(holders) = table.holders.get_mut(&target) {
+                holders.remove(&txn);
+                if holders.is_empty() {
+                    table.holders.remove(&target);
+                }
+            }
+        }
+        drop(table);
+        self.released.notify_all();
+    }
+
+    /// The locks `txn` holds.
+    pub fn held(&self, txn: TxnId) -> Vec<(LockTarget, LockMode)> {
+        let table = self.table.lock_recover();
+        let mut held: Vec<_> = table
+            .held
+            .get(&txn)
+            .into_iter()
+            .flatten()
+            .map(|&target| (target, table.holders[&target][&txn]))
+            .collect();
+        held.sort();
+        held
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn table(table: u32) -> LockTarget {
+        LockTarget::Table(FileId(table))
+    }
+
+    /// Run `f` on another thread, returning once it is blocked on a lock.
+    fn spawn_blocked(
+        locks: &Arc<LockManager>,
+        txn: TxnId,
+        f: impl FnOnce(&LockManager) -> Result<(), LockError> + Send + 'static,
+    ) -> thread::JoinHandle<Result<(), LockError>> {
+        let handle = {
+            let locks = locks.clone();
+            thread::spawn(move || f(&locks))
+        };
+        while !locks.table.lock().unwrap().waiting.contains_key(&txn) {
+            thread::yield_now();
+        }
+        handle
+    }
+
+    #[test]
+    fn test_compatible_locks() {
+        let locks = LockManager::new(None);
+        for txn in 1..=3 {
+            locks.lock(TxnId(txn), table(1), LockMode::Shared).unwrap();
+        }
+        locks.lock(TxnId(4), table(2), LockMode::Exclusive).unwrap();
+        assert_eq!(locks.held(TxnId(4)), vec![(table(2), LockMode::Exclusive)]);
+
+        // Already held, so granted straight away
+        locks.lock(TxnId(4), table(2), LockMode::Shared).unwrap();
+        assert_eq!(locks.held(TxnId(4)), vec![(table(2), LockMode::Exclusive)]);
+    }
+
+    #[test]
+    fn test_conflicting_lock_waits_for_release() {
+        let locks = Arc::new(LockManager::new(None));
+        locks.lock(TxnId(1), table(1), LockMode::Exclusive).unwrap();
+        let waiter = spawn_blocked(&locks, TxnId(2), |locks| {
+            locks.lock(TxnId(2), table(1), LockMode::Shared)
+        });
+        locks.release_all(TxnId(1));
+        waiter.join().unwrap().unwrap();
+        assert_eq!(locks.held(TxnId(2)), vec![(table(1), LockMode::Shared)]);
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let locks = Arc::new(LockManager::new(None));
+        locks.lock(TxnId(1), table(1), LockMode::Exclusive).unwrap();
+        locks.lock(TxnId(2), table(2), LockMode::Exclusive).unwrap();
+        let waiter = spawn_blocked(&locks, TxnId(1), |locks| {
+            locks.lock(TxnId(1), table(2), LockMode::Exclusive)
+        });
+
+        // Transaction 2 would close the cycle, so it is the one refused
+        assert_eq!(
+            locks.lock(TxnId(2), table(1), LockMode::Exclusive),
+            Err(LockError::Deadlock(TxnId(2), table(1)))
+        );
+        locks.release_all(TxnId(2));
+        waiter.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_deadlock() {
+        let locks = Arc::new(LockManager::new(None));
+        locks.lock(TxnId(1), table(1), LockMode::Shared).unwrap();
+        locks.lock(TxnId(2), table(1), LockMode::Shared).unwrap();
+        let waiter = spawn_blocked(&locks, TxnId(1), |locks| {
+            locks.lock(TxnId(1), table(1), LockMode::Exclusive)
+        });
+        assert_eq!(
+            locks.lock(TxnId(2), table(1), LockMode::Exclusive),
+            Err(LockError::Deadlock(TxnId(2), table(1)))
+        );
+        locks.release_all(TxnId(2));
+        waiter.join().unwrap().unwrap();
+        assert_eq!(locks.held(TxnId(1)), vec![(table(1), LockMode::Exclusive)]);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let locks = LockManager::new(Some(Duration::from_millis(10)));
+        locks.lock(TxnId(1), table(1), LockMode::Shared).unwrap();
+        assert_eq!(
+            locks.lock(TxnId(2), table(1), LockMode::Exclusive),
+            Err(LockError::Timeout(table(1)))
+        );
+        assert!(locks.held(TxnId(2)).is_empty());
+    }
+}
@@ -0,0 +1,267 @@
+use super::file_manager::FileManager;
+use super::superblock::{Superblock, SuperblockError, FORMAT_VERSION};
+use crate::config::MigrationMode;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Superblock error: {0}")]
+    SuperblockError(#[from] SuperblockError),
+
+    #[error("No database found at {0}")]
+    DatabaseNotFound(PathBuf),
+
+    #[error("No migration from format version {0}")]
+    NoMigration(u32),
+
+    #[error("Migration from format version {version} failed: {reason}")]
+    Failed { version: u32, reason: String },
+}
+
+/// Upgrades a database directory from one on-disk format version to the next.
+pub trait Migration {
+    /// The version this migration upgrades from. It leaves the files in
+    /// version `source_version() + 1`.
+    fn source_version(&self) -> u32;
+
+    /// Rewrite the files under `root`. The superblock's version is bumped by
+    /// the `Migrator` once this returns successfully.
+    fn migrate(&self, root: &Path) -> Result<(), MigrationError>;
+}
+
+/// Brings an older database up to `FORMAT_VERSION` by running its registered
+/// migrations in order.
+pub struct Migrator {
+    migrations: Vec<Box<dyn Migration>>,
+}
+
+impl Migrator {
+    /// A migrator holding every migration shipped with this build.
+    pub fn new() -> Self {
+        // Version 1 is the first on-disk format, so there is nothing to
+        // upgrade from yet
+        Self::with_migrations(Vec::new())
+    }
+
+    pub fn with_migrations(migrations: Vec<Box<dyn Migration>>) -> Self {
+        Self { migrations }
+    }
+
+    /// The format version of the database at `root`, or `None` if there is
+    /// no database there yet.
+    pub fn version(root: &Path) -> Result<Option<u32>, MigrationError> {
+        let catalog_path = FileManager::catalog_path(root);
+        if !catalog_path.exists() {
+            return Ok(None);
+        }
+        let superblock = Superblock::read_from(catalog_path)?;
+        Ok(superblock.map(|superblock| superblock.format_version))
+    }
+
+    /// Upgrade the database at `root` to `FORMAT_VERSION`, returning the
+    /// version it started at. Databases that are already current, and
+    /// directories with no database yet, are left alone.
+    pub fn migrate(&self, root: &Path, mode: MigrationMode) -> Result<u32, MigrationError> {
+        let version = match Self::version(root)? {
+            Some(version) => version,
+            None => return Ok(FORMAT_VERSION),
+        };
+        if version > FORMAT_VERSION {
+            return Err(SuperblockError::UnsupportedVersion(version).into());
+        }
+        if version == FORMAT_VERSION {
+            return Ok(version);
+        }
+        // Check every step exists before touching anything
+        for step in version..FORMAT_VERSION {
+            self.step(step)?;
+        }
+
+        match mode {
+            MigrationMode::InPlace => self.run(root, version)?,
+            MigrationMode::Copy => {
+                let staging = Self::sibling(root, "migrating");
+                if staging.exists() {
+                    fs::remove_dir_all(&staging)?;
+                }
+                copy_dir(root, &staging)?;
+                if let Err(e) = self.run(&staging, version) {
+                    let _ = fs::remove_dir_all(&staging);
+                    return Err(e);
+                }
+                fs::rename(root, Self::sibling(root, &format!("v{}", version)))?;
+                fs::rename(&staging, root)?;
+            }
+        }
+        Ok(version)
+    }
+
+    fn run(&self, root: &Path, from: u32) -> Result<(), MigrationError> {
+        let catalog_path = FileManager::catalog_path(root);
+        for version in from..FORMAT_VERSION {
+            self.step(version)?.migrate(root)?;
+
+            // Record each step as it completes, so an interrupted in-place
+            // migration resumes from where it stopped
+            let mut superblock = Superblock::read_from(&catalog_path)?
+                .ok_or_else(|| MigrationError::DatabaseNotFound(root.to_path_buf()))?;
+            superblock.format_version = version + 1;
+            superblock.write_to(&catalog_path)?;
+        }
+        Ok(())
+    }
+
+    fn step(&self, version: u32) -> Result<&dyn Migration, MigrationError> {
+        self.migrations
+            .iter()
+            .find(|migration| migration.source_version() == version)
+            .map(|migration| migration.as_ref())
+            .ok_or(MigrationError::NoMigration(version))
+    }
+
+    fn sibling(root: &Path, suffix: &str) -> PathBuf {
+        let mut name = root.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{}", suffix));
+        root.with_file_name(name)
+    }
+}
+
+/// Copy a directory and everything under it, such as the log segments.
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()))?;
+        } else if file_type.is_file() {
+            fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Compression;
+
+    /// Stands in for a real upgrade by adding a marker file.
+    struct AddMarker {
+        from: u32,
+    }
+
+    impl Migration for AddMarker {
+        fn source_version(&self) -> u32 {
+            self.from
+        }
+
+        fn migrate(&self, root: &Path) -> Result<(), MigrationError> {
+            fs::write(root.join(format!("migrated-{}", self.from)), "")?;
+            Ok(())
+        }
+    }
+
+    /// Create a database at `root` claiming to be in format `version`.
+    fn create_database(root: &Path, version: u32) {
+        fs::create_dir_all(root).unwrap();
+        let mut superblock = Superblock::new(128, Compression::None, None, false);
+        superblock.format_version = version;
+        fs::write(
+            FileManager::catalog_path(root),
+            superblock.encode(128).as_bytes(),
+        )
+        .unwrap();
+    }
+
+    fn migrator() -> Migrator {
+        Migrator::with_migrations(vec![Box::new(AddMarker {
+            from: FORMAT_VERSION - 1,
+        })])
+    }
+
+    #[test]
+    fn test_migrate_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        create_database(dir.path(), FORMAT_VERSION - 1);
+
+        let from = migrator()
+            .migrate(dir.path(), MigrationMode::InPlace)
+            .unwrap();
+        assert_eq!(from, FORMAT_VERSION - 1);
+        assert_eq!(Migrator::version(dir.path()).unwrap(), Some(FORMAT_VERSION));
+        assert!(dir
+            .path()
+            .join(format!("migrated-{}", FORMAT_VERSION - 1))
+            .exists());
+    }
+
+    #[test]
+    fn test_migrate_via_copy_keeps_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("db");
+        create_database(&root, FORMAT_VERSION - 1);
+
+        migrator().migrate(&root, MigrationMode::Copy).unwrap();
+        assert_eq!(Migrator::version(&root).unwrap(), Some(FORMAT_VERSION));
+
+        let original = dir.path().join(format!("db.v{}", FORMAT_VERSION - 1));
+        assert_eq!(
+            Migrator::version(&original).unwrap(),
+            Some(FORMAT_VERSION - 1)
+        );
+        assert!(!dir.path().join("db.migrating").exists());
+    }
+
+    #[test]
+    fn test_current_and_missing_databases_are_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            Migrator::new()
+                .migrate(dir.path(), MigrationMode::InPlace)
+                .unwrap(),
+            FORMAT_VERSION
+        );
+
+        create_database(dir.path(), FORMAT_VERSION);
+        assert_eq!(
+            Migrator::new()
+                .migrate(dir.path(), MigrationMode::InPlace)
+                .unwrap(),
+            FORMAT_VERSION
+        );
+    }
+
+    #[test]
+    fn test_missing_migration() {
+        let dir = tempfile::tempdir().unwrap();
+        create_database(dir.path(), FORMAT_VERSION - 1);
+        assert!(matches!(
+            Migrator::new().migrate(dir.path(), MigrationMode::InPlace),
+            Err(MigrationError::NoMigration(_))
+        ));
+        // Nothing was changed
+        assert_eq!(
+            Migrator::version(dir.path()).unwrap(),
+            Some(FORMAT_VERSION - 1)
+        );
+    }
+
+    #[test]
+    fn test_newer_version_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        create_database(dir.path(), FORMAT_VERSION + 1);
+        assert!(matches!(
+            Migrator::new().migrate(dir.path(), MigrationMode::InPlace),
+            Err(MigrationError::SuperblockError(
+                SuperblockError::UnsupportedVersion(_)
+            ))
+        ));
+    }
+}
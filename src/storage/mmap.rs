@@ -0,0 +1,65 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::ptr::NonNull;
+
+/// A shared, writable mapping of the first `len` bytes of a file. Writes
+/// through it land in the OS page cache as the file's own would, and
+/// `sync` waits for them to reach the device.
+///
+/// The file must not be shrunk below `len` while it's mapped, or touching
+/// the lost pages raises `SIGBUS`.
+pub struct Mmap {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// The mapping is only reached through `&self` and `&mut self`
+unsafe impl Send for Mmap {}
+
+impl Mmap {
+    /// Map the first `len` bytes of `file`, which must be open for both
+    /// reading and writing; `len` must be non-zero.
+    pub fn new(file: &File, len: usize) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let ptr = NonNull::new(ptr.cast()).ok_or_else(io::Error::last_os_error)?;
+        Ok(Self { ptr, len })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+
+    /// Wait for every write through the mapping to reach the device.
+    pub fn sync(&self) -> io::Result<()> {
+        let ret = unsafe { libc::msync(self.ptr.as_ptr().cast(), self.len, libc::MS_SYNC) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr.as_ptr().cast(), self.len);
+        }
+    }
+}
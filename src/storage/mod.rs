@@ -1,3 +1,46 @@
+mod cdc;
+mod checksum;
+mod codec;
+mod compression;
+mod direct_io;
+mod encryption;
+mod eviction;
+mod faults;
+mod file_manager;
+mod fsck;
+mod history;
+mod incremental;
+mod lock_manager;
+mod migration;
+mod mmap;
 mod page;
 mod page_io;
 mod page_manager;
+mod page_store;
+mod poison;
+mod prefetch;
+mod recovery;
+mod replication;
+mod restore;
+mod slotted_page;
+mod stats;
+mod superblock;
+mod transaction;
+mod wal;
+
+pub use cdc::{ChangeEvent, ChangeStream, Record, RowChange};
+pub(crate) use checksum::crc32;
+pub use faults::{Fault, FaultInjector, FaultPoint};
+pub use file_manager::FileId;
+pub use fsck::{check as fsck, FsckReport, Problem};
+pub use history::AsOf;
+pub use incremental::merge;
+pub use lock_manager::{LockMode, LockTarget};
+pub use page::{Page, PageDecodeError, PageId};
+pub use page_io::PageIOError;
+pub use page_manager::{PageManager, PageManagerBuilder, PageManagerError};
+pub(crate) use poison::{panic_message, LockRecover};
+pub use slotted_page::{RecordKind, SlotId, SlottedPage};
+pub use stats::BufferStats;
+pub use transaction::{Transaction, TransactionError, TransactionInfo, TransactionManager};
+pub use wal::Lsn;
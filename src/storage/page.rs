@@ -1,7 +1,28 @@
+use super::file_manager::FileId;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::fmt::{self, Display};
 use std::io::{self, Cursor};
 use thiserror::Error;
 
+/// Identifies a page by the file it lives in and its position in that file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PageId {
+    pub file: FileId,
+    pub page_no: u64,
+}
+
+impl PageId {
+    pub fn new(file: FileId, page_no: u64) -> Self {
+        Self { file, page_no }
+    }
+}
+
+impl Display for PageId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.page_no)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Page {
     data: Vec<u8>,
@@ -17,6 +38,9 @@ pub enum PageDecodeError {
 
     #[error("Unable to parse bytes into expected type")]
     InvalidBytes(#[from] io::Error),
+
+    #[error("Corrupted page: {0}")]
+    Corrupted(String),
 }
 
 impl Page {
@@ -28,6 +52,7 @@ impl Page {
         Self::new(vec![0; page_size])
     }
 
+    #[cfg(test)]
     pub fn full(value: u8, page_size: usize) -> Self {
         Self::new(vec![value; page_size])
     }
@@ -36,6 +61,10 @@ impl Page {
         &self.data
     }
 
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
     pub fn read_u32(&self, offset: usize) -> Result<u32, PageDecodeError> {
         if offset + 4 > self.data.len() {
             return Err(PageDecodeError::UnexpectedEof);
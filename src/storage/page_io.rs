@@ -1,6 +1,10 @@
+use super::direct_io::{self, AlignedBuffer, DIRECT_IO_ALIGNMENT};
+use super::mmap::Mmap;
 use super::page::{Page, PageDecodeError};
+use crate::config::{Durability, IoMode};
 use std::fs::{File, OpenOptions};
 use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
 use std::path::Path;
 use thiserror::Error;
 
@@ -17,34 +21,148 @@ pub enum PageIOError {
 
     #[error("Permission denied: {0}")]
     PermissionDenied(String),
+
+    #[error("File length {len} is not a multiple of the page size {page_size}")]
+    MisalignedFile { len: u64, page_size: usize },
+
+    #[error("Page size {0} is not a multiple of the direct I/O alignment")]
+    UnalignedPageSize(usize),
+}
+
+/// The open file handles, which depend on the I/O mode.
+enum Handles {
+    /// Separate buffered reader and writer over the page cache
+    Buffered {
+        reader: BufReader<File>,
+        writer: BufWriter<File>,
+    },
+    /// A single unbuffered handle opened with `O_DIRECT`; every transfer goes
+    /// through an aligned buffer
+    Direct { file: File },
+    /// A single handle and a mapping of the allocated length, remade as the
+    /// file grows; `None` while the file is empty, as it can't be mapped
+    Mapped { file: File, map: Option<Mmap> },
 }
 
 pub struct PageIO {
-    reader: BufReader<File>,
-    writer: BufWriter<File>,
+    handles: Handles,
+    durability: Durability,
+    // Bytes allocated on disk, including preallocated pages not yet written
+    allocated_len: u64,
+    growth_chunk_pages: u64,
 }
 
 impl PageIO {
-    pub fn new(db_path: impl AsRef<Path>) -> Result<Self, PageIOError> {
-        let reader_file = File::open(&db_path)?;
-        let writer_file = OpenOptions::new().write(true).create(true).open(&db_path)?;
+    #[cfg(test)]
+    pub fn new(db_path: impl AsRef<Path>, durability: Durability) -> Result<Self, PageIOError> {
+        Self::open(db_path, durability, IoMode::Buffered)
+    }
+
+    pub fn open(
+        db_path: impl AsRef<Path>,
+        durability: Durability,
+        io_mode: IoMode,
+    ) -> Result<Self, PageIOError> {
+        // Open the writer first so a missing file is created before reading
+        let mut options = OpenOptions::new();
+        options
+            .read(matches!(io_mode, IoMode::Direct | IoMode::Mmap))
+            .write(true)
+            .create(true)
+            .truncate(false);
+        direct_io::apply_open_flags(&mut options, io_mode)?;
+        let writer_file = options.open(&db_path)?;
+        let allocated_len = writer_file.metadata()?.len();
+
+        let handles = match io_mode {
+            IoMode::Direct => Handles::Direct { file: writer_file },
+            IoMode::Mmap => {
+                let map = match allocated_len {
+                    0 => None,
+                    len => Some(Mmap::new(&writer_file, len as usize)?),
+                };
+                Handles::Mapped {
+                    file: writer_file,
+                    map,
+                }
+            }
+            IoMode::Buffered | IoMode::Dsync => {
+                let reader_file = File::open(&db_path)?;
+                Handles::Buffered {
+                    reader: BufReader::new(reader_file),
+                    writer: BufWriter::new(writer_file),
+                }
+            }
+        };
+
+        Ok(Self {
+            handles,
+            durability,
+            allocated_len,
+            growth_chunk_pages: 1,
+        })
+    }
 
-        let reader = BufReader::new(reader_file.try_clone()?);
-        let writer = BufWriter::new(writer_file.try_clone()?);
+    /// Grow the file this many pages at a time when writing past its end.
+    pub fn set_growth_chunk(&mut self, pages: u64) {
+        self.growth_chunk_pages = pages.max(1);
+    }
 
-        Ok(Self { reader, writer })
+    /// Check that the file holds a whole number of pages, returning how many,
+    /// and that `page_size` suits the I/O mode.
+    pub fn validate_length(&self, page_size: usize) -> Result<u64, PageIOError> {
+        if matches!(self.handles, Handles::Direct { .. })
+            && !page_size.is_multiple_of(DIRECT_IO_ALIGNMENT)
+        {
+            return Err(PageIOError::UnalignedPageSize(page_size));
+        }
+        if !self.allocated_len.is_multiple_of(page_size as u64) {
+            return Err(PageIOError::MisalignedFile {
+                len: self.allocated_len,
+                page_size,
+            });
+        }
+        Ok(self.allocated_len / page_size as u64)
     }
 
     pub fn read_page(&mut self, page_id: u64, page_size: usize) -> Result<Page, PageIOError> {
-        let mut buffer = vec![0; page_size];
-        let offset = page_id as u64 * page_size as u64;
+        let offset = page_id * page_size as u64;
+
+        let result = match &mut self.handles {
+            Handles::Buffered { reader, writer } => {
+                let mut buffer = vec![0; page_size];
+
+                // Make buffered writes visible to the reader's file handle
+                writer.flush()?;
 
-        // Seek to position
-        self.reader.seek(SeekFrom::Start(offset))?;
+                // Seek to position
+                reader.seek(SeekFrom::Start(offset))?;
 
-        // Try to read the exact amount
-        match self.reader.read_exact(&mut buffer) {
-            Ok(_) => Ok(Page::new(buffer)),
+                // Try to read the exact amount
+                reader.read_exact(&mut buffer).map(|_| buffer)
+            }
+            Handles::Direct { file } => {
+                if !page_size.is_multiple_of(DIRECT_IO_ALIGNMENT) {
+                    return Err(PageIOError::UnalignedPageSize(page_size));
+                }
+                let mut buffer = AlignedBuffer::new(page_size);
+                file.read_exact_at(buffer.as_mut_slice(), offset)
+                    .map(|_| buffer.as_slice().to_vec())
+            }
+            Handles::Mapped { map, .. } => {
+                let start = offset as usize;
+                let bytes = map
+                    .as_ref()
+                    .and_then(|map| map.as_slice().get(start..start + page_size));
+                return match bytes {
+                    Some(bytes) => Ok(Page::new(bytes.to_vec())),
+                    None => Err(PageIOError::PageNotFound(page_id)),
+                };
+            }
+        };
+
+        match result {
+            Ok(buffer) => Ok(Page::new(buffer)),
             Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
                 Err(PageIOError::PageNotFound(page_id))
             }
@@ -58,17 +176,163 @@ impl PageIO {
         page_size: usize,
         page: &Page,
     ) -> Result<(), PageIOError> {
-        let offset = page_id as u64 * page_size as u64;
-        self.writer.seek(SeekFrom::Start(offset))?;
-        self.writer.write_all(page.as_bytes())?;
+        let offset = page_id * page_size as u64;
+        self.ensure_allocated(offset + page_size as u64, page_size)?;
+        match &mut self.handles {
+            Handles::Buffered { writer, .. } => {
+                writer.seek(SeekFrom::Start(offset))?;
+                writer.write_all(page.as_bytes())?;
+            }
+            Handles::Direct { file } => {
+                if !page_size.is_multiple_of(DIRECT_IO_ALIGNMENT) {
+                    return Err(PageIOError::UnalignedPageSize(page_size));
+                }
+                let mut buffer = AlignedBuffer::new(page_size);
+                buffer.as_mut_slice().copy_from_slice(page.as_bytes());
+                file.write_all_at(buffer.as_slice(), offset)?;
+            }
+            Handles::Mapped { map, .. } => {
+                // Allocated above, so the page is within the mapping
+                let start = offset as usize;
+                let map = map.as_mut().expect("allocated file is mapped");
+                map.as_mut_slice()[start..start + page_size].copy_from_slice(page.as_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    /// Release the disk blocks backing the unused tail of a page, keeping
+    /// the first `used` bytes. The tail reads back as zeros. Filesystems
+    /// without hole punching keep the blocks allocated.
+    pub fn punch_hole(
+        &mut self,
+        page_id: u64,
+        page_size: usize,
+        used: usize,
+    ) -> Result<(), PageIOError> {
+        let start = used.next_multiple_of(HOLE_ALIGNMENT);
+        if start >= page_size {
+            return Ok(());
+        }
+        // The tail must not be rewritten by a buffered write after the punch
+        if let Handles::Buffered { writer, .. } = &mut self.handles {
+            writer.flush()?;
+        }
+        let offset = page_id * page_size as u64;
+        deallocate(
+            self.file(),
+            offset + start as u64,
+            offset + page_size as u64,
+        )?;
+        Ok(())
+    }
+
+    /// Extend the file to cover `end`, rounding up to a whole growth chunk
+    /// so that appending pages doesn't extend the file on every write.
+    fn ensure_allocated(&mut self, end: u64, page_size: usize) -> Result<(), PageIOError> {
+        if end <= self.allocated_len {
+            return Ok(());
+        }
+        let chunk = self.growth_chunk_pages * page_size as u64;
+        let new_len = end.div_ceil(chunk) * chunk;
+        preallocate(self.file(), self.allocated_len, new_len)?;
+        self.allocated_len = new_len;
+        if let Handles::Mapped { file, map } = &mut self.handles {
+            // Unmap the old length before mapping the new
+            *map = None;
+            *map = Some(Mmap::new(file, new_len as usize)?);
+        }
         Ok(())
     }
 
+    /// Push written pages as far as the configured durability requires.
     pub fn flush(&mut self) -> Result<(), PageIOError> {
-        self.writer.flush()?; // Add this line to flush the buffer
+        if self.durability == Durability::None {
+            return Ok(());
+        }
+        if let Handles::Buffered { writer, .. } = &mut self.handles {
+            writer.flush()?;
+        }
+        if self.durability == Durability::Full {
+            if let Handles::Mapped { map: Some(map), .. } = &self.handles {
+                map.sync()?;
+            }
+            // fdatasync also persists the file length, which is the only
+            // metadata reading pages back depends on
+            self.file().sync_data()?;
+        }
         Ok(())
     }
+
+    /// The handle pages are written through.
+    fn file(&self) -> &File {
+        match &self.handles {
+            Handles::Buffered { writer, .. } => writer.get_ref(),
+            Handles::Direct { file } | Handles::Mapped { file, .. } => file,
+        }
+    }
 }
+
+/// Allocate disk blocks for `from..to`, extending the file to `to`.
+#[cfg(target_os = "linux")]
+fn preallocate(file: &File, from: u64, to: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let ret = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            0,
+            from as libc::off_t,
+            (to - from) as libc::off_t,
+        )
+    };
+    if ret == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    // Not every filesystem supports fallocate; a sparse extension still works
+    if err.raw_os_error() == Some(libc::EOPNOTSUPP) {
+        return file.set_len(to);
+    }
+    Err(err)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn preallocate(file: &File, _from: u64, to: u64) -> io::Result<()> {
+    file.set_len(to)
+}
+
+/// Granularity at which filesystems free blocks when punching holes.
+const HOLE_ALIGNMENT: usize = 4096;
+
+/// Free the disk blocks backing `from..to` without changing the file length.
+#[cfg(target_os = "linux")]
+fn deallocate(file: &File, from: u64, to: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let ret = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            from as libc::off_t,
+            (to - from) as libc::off_t,
+        )
+    };
+    if ret == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EOPNOTSUPP) {
+        return Ok(());
+    }
+    Err(err)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn deallocate(_file: &File, _from: u64, _to: u64) -> io::Result<()> {
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -77,14 +341,14 @@ mod tests {
     fn setup_test_page_io() -> (NamedTempFile, usize, PageIO) {
         let temp_file = NamedTempFile::new().unwrap();
         let page_size: usize = 128; // Smaller page size for testing
-        let page_io = PageIO::new(temp_file.path()).unwrap();
+        let page_io = PageIO::new(temp_file.path(), Durability::Full).unwrap();
         (temp_file, page_size, page_io)
     }
 
     #[test]
     fn test_write_and_read_page() {
         let (_temp, page_size, mut page_io) = setup_test_page_io();
-        let write_data = vec![42u8; page_size as usize];
+        let write_data = vec![42u8; page_size];
         page_io
             .write_page(0, page_size, &Page::new(write_data.clone()))
             .unwrap();
@@ -106,4 +370,179 @@ mod tests {
         let result = page_io.read_page(0, page_size);
         assert!(matches!(result, Err(PageIOError::PageNotFound(0))));
     }
+
+    #[test]
+    fn test_flush_durability() {
+        for durability in [Durability::Full, Durability::Os] {
+            let temp_file = NamedTempFile::new().unwrap();
+            let mut page_io = PageIO::new(temp_file.path(), durability).unwrap();
+            page_io.write_page(0, 128, &Page::full(1, 128)).unwrap();
+            page_io.flush().unwrap();
+            assert_eq!(std::fs::read(temp_file.path()).unwrap(), vec![1u8; 128]);
+        }
+    }
+
+    #[test]
+    fn test_flush_without_durability_keeps_buffer() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let mut page_io = PageIO::new(temp_file.path(), Durability::None).unwrap();
+        page_io.write_page(0, 128, &Page::full(1, 128)).unwrap();
+        page_io.flush().unwrap();
+        // The file has been extended, but the page itself is still buffered
+        assert_eq!(std::fs::read(temp_file.path()).unwrap(), vec![0u8; 128]);
+
+        // Dropping the writer still gets the page out
+        drop(page_io);
+        assert_eq!(std::fs::read(temp_file.path()).unwrap(), vec![1u8; 128]);
+    }
+
+    #[test]
+    fn test_file_grows_in_chunks() {
+        let (temp, page_size, mut page_io) = setup_test_page_io();
+        page_io.set_growth_chunk(4);
+
+        page_io
+            .write_page(0, page_size, &Page::full(1, page_size))
+            .unwrap();
+        assert_eq!(temp.as_file().metadata().unwrap().len(), 4 * 128);
+
+        page_io
+            .write_page(3, page_size, &Page::full(1, page_size))
+            .unwrap();
+        assert_eq!(temp.as_file().metadata().unwrap().len(), 4 * 128);
+
+        page_io
+            .write_page(5, page_size, &Page::full(1, page_size))
+            .unwrap();
+        assert_eq!(temp.as_file().metadata().unwrap().len(), 8 * 128);
+
+        // Preallocated pages read back as zeros
+        let page = page_io.read_page(6, page_size).unwrap();
+        assert_eq!(page, Page::zeros(page_size));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_punch_hole_zeroes_tail() {
+        let (_temp, _, mut page_io) = setup_test_page_io();
+        let page_size = 4 * HOLE_ALIGNMENT;
+        page_io
+            .write_page(0, page_size, &Page::full(1, page_size))
+            .unwrap();
+        page_io.punch_hole(0, page_size, 10).unwrap();
+
+        let page = page_io.read_page(0, page_size).unwrap();
+        assert!(page.as_bytes()[..HOLE_ALIGNMENT].iter().all(|&b| b == 1));
+        assert!(page.as_bytes()[HOLE_ALIGNMENT..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_validate_length() {
+        let temp_file = NamedTempFile::new().unwrap();
+        std::fs::write(temp_file.path(), vec![0u8; 300]).unwrap();
+        let page_io = PageIO::new(temp_file.path(), Durability::Full).unwrap();
+
+        assert_eq!(page_io.validate_length(100).unwrap(), 3);
+        assert!(matches!(
+            page_io.validate_length(128),
+            Err(PageIOError::MisalignedFile {
+                len: 300,
+                page_size: 128
+            })
+        ));
+    }
+
+    #[test]
+    fn test_creates_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("new.fdb");
+        PageIO::new(&path, Durability::Full).unwrap();
+        assert!(path.exists());
+    }
+
+    /// Open with `O_DIRECT`, or `None` if the filesystem doesn't support it.
+    fn open_direct(path: &Path) -> Option<PageIO> {
+        match PageIO::open(path, Durability::Full, IoMode::Direct) {
+            Ok(page_io) => Some(page_io),
+            Err(PageIOError::IoError(e)) if e.raw_os_error() == Some(libc::EINVAL) => None,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    #[test]
+    fn test_direct_io_round_trip() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let Some(mut page_io) = open_direct(temp_file.path()) else {
+            return;
+        };
+        let page_size = DIRECT_IO_ALIGNMENT;
+        page_io.validate_length(page_size).unwrap();
+
+        page_io
+            .write_page(1, page_size, &Page::full(9, page_size))
+            .unwrap();
+        page_io.flush().unwrap();
+        assert_eq!(
+            page_io.read_page(1, page_size).unwrap(),
+            Page::full(9, page_size)
+        );
+        assert_eq!(
+            page_io.read_page(0, page_size).unwrap(),
+            Page::zeros(page_size)
+        );
+        assert!(matches!(
+            page_io.read_page(2, page_size),
+            Err(PageIOError::PageNotFound(2))
+        ));
+    }
+
+    #[test]
+    fn test_direct_io_rejects_unaligned_page_size() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let Some(page_io) = open_direct(temp_file.path()) else {
+            return;
+        };
+        assert!(matches!(
+            page_io.validate_length(128),
+            Err(PageIOError::UnalignedPageSize(128))
+        ));
+    }
+
+    #[test]
+    fn test_mmap_round_trip() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let open = || PageIO::open(temp_file.path(), Durability::Full, IoMode::Mmap).unwrap();
+        let mut page_io = open();
+        assert!(matches!(
+            page_io.read_page(0, 128),
+            Err(PageIOError::PageNotFound(0))
+        ));
+
+        // Growing past the mapping maps the file again
+        page_io.set_growth_chunk(2);
+        page_io.write_page(0, 128, &Page::full(3, 128)).unwrap();
+        page_io.write_page(4, 128, &Page::full(5, 128)).unwrap();
+        page_io.flush().unwrap();
+        assert_eq!(page_io.read_page(4, 128).unwrap(), Page::full(5, 128));
+        assert_eq!(page_io.read_page(3, 128).unwrap(), Page::zeros(128));
+        assert!(matches!(
+            page_io.read_page(6, 128),
+            Err(PageIOError::PageNotFound(6))
+        ));
+        let on_disk = std::fs::read(temp_file.path()).unwrap();
+        assert_eq!(&on_disk[512..640], &[5u8; 128][..]);
+        drop(page_io);
+
+        let mut page_io = open();
+        assert_eq!(page_io.validate_length(128).unwrap(), 6);
+        assert_eq!(page_io.read_page(0, 128).unwrap(), Page::full(3, 128));
+    }
+
+    #[test]
+    fn test_dsync_round_trip() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let mut page_io = PageIO::open(temp_file.path(), Durability::Os, IoMode::Dsync).unwrap();
+        page_io.write_page(0, 128, &Page::full(4, 128)).unwrap();
+        assert_eq!(page_io.read_page(0, 128).unwrap(), Page::full(4, 128));
+    }
 }
@@ -1,8 +1,36 @@
-use super::page::{Page, PageDecodeError};
-use crate::storage::page_io::{PageIO, PageIOError};
-use lru::LruCache;
-use std::num::NonZeroUsize;
+use super::codec::PageCodec;
+use super::encryption::{EncryptionError, PageCipher};
+use super::eviction::{self, EvictionPolicy};
+use super::faults::FaultInjector;
+use super::file_manager::{FileId, FileManager, FileManagerError, FileOptions};
+use super::history::{self, AsOf};
+use super::incremental;
+use super::migration::{MigrationError, Migrator};
+use super::page::{Page, PageDecodeError, PageId};
+use super::poison::{LockRecover, RwLockRecover};
+use super::prefetch::ScanDetector;
+use super::recovery::{self, RecoveryReport};
+use super::replication::{WalReceiver, WalSender};
+use super::restore;
+use super::stats::{BufferCounters, BufferStats};
+use super::superblock::SUPERBLOCK_SIZE;
+use super::wal::{Lsn, TxnId, Wal, WalError, WalOptions, WalRecord};
+use crate::config::{
+    Compression, Durability, EncryptionConfig, EvictionPolicyKind, FlusherConfig, IoMode,
+    MigrationMode, ReplicationConfig, StorageConfig, WalConfig, IN_MEMORY,
+};
+use crate::storage::page_io::PageIOError;
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+#[cfg(test)]
+use std::sync::RwLockWriteGuard;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -10,204 +38,2174 @@ pub enum PageManagerError {
     #[error("Invalid cache size: {0}")]
     InvalidCacheSize(String),
 
+    #[error("Invalid shard count: {0}")]
+    InvalidShardCount(String),
+
     #[error("Page error: {0}")]
     PageDecodeError(#[from] PageDecodeError),
 
     #[error("Page IO error: {0}")]
     PageIOError(#[from] PageIOError),
+
+    #[error("File error: {0}")]
+    FileManagerError(#[from] FileManagerError),
+
+    #[error("Migration error: {0}")]
+    MigrationError(#[from] MigrationError),
+
+    #[error("Encryption error: {0}")]
+    EncryptionError(#[from] EncryptionError),
+
+    #[error("WAL error: {0}")]
+    WalError(#[from] WalError),
+
+    #[error("The write-ahead log is disabled")]
+    WalDisabled,
+
+    #[error("The database is kept in memory, so has nothing on disk to restore")]
+    InMemory,
+
+    #[error("{0} isn't a backup this one can follow on from")]
+    InvalidBackup(PathBuf),
+
+    #[error("The database isn't a replica")]
+    NotReplica,
+
+    #[error("The log no longer reaches back that far")]
+    HistoryUnavailable,
+
+    #[error("Page {0} is pinned")]
+    PagePinned(PageId),
+
+    #[error("All cached pages are pinned")]
+    NoEvictablePage,
 }
 
-pub struct PageManager {
-    page_io: PageIO,
-    cache: LruCache<u64, Page>,
-    page_size: usize,
+/// A cached page along with whether it differs from its on-disk copy and how
+/// many guards currently hold it.
+struct Frame {
+    page: RwLock<Page>,
+    dirty: AtomicBool,
+    pin_count: AtomicUsize,
+    /// LSN of the last log record that changed the page, which must be
+    /// durable before the page is written back
+    lsn: AtomicU64,
 }
 
-impl PageManager {
-    fn new(
-        db_path: impl AsRef<Path>,
-        page_size: usize,
-        cache_size: usize,
-    ) -> Result<Self, PageManagerError> {
-        let cache = NonZeroUsize::new(cache_size).ok_or(PageManagerError::InvalidCacheSize(
-            "Cache size must be greater than 0.".into(),
-        ))?;
-        Ok(Self {
-            page_io: PageIO::new(db_path)?,
-            cache: LruCache::new(NonZeroUsize::new(cache_size).unwrap()),
-            page_size: page_size,
+impl Frame {
+    fn new(page: Page, dirty: bool, lsn: Lsn) -> Arc<Self> {
+        Arc::new(Self {
+            page: RwLock::new(page),
+            dirty: AtomicBool::new(dirty),
+            pin_count: AtomicUsize::new(0),
+            lsn: AtomicU64::new(lsn.0),
         })
     }
 
-    pub fn get_page(&mut self, page_id: u64) -> Result<&Page, PageManagerError> {
-        if !self.cache.contains(&page_id) {
-            let page = self.page_io.read_page(page_id, self.page_size)?;
-            self.cache.put(page_id, page);
+    fn lsn(&self) -> Lsn {
+        Lsn(self.lsn.load(Ordering::Acquire))
+    }
+
+    fn is_pinned(&self) -> bool {
+        self.pin_count.load(Ordering::Acquire) > 0
+    }
+}
+
+/// A pinned page. The page cannot be evicted while any guard for it is alive.
+pub struct PageGuard {
+    frame: Arc<Frame>,
+}
+
+impl PageGuard {
+    /// Pins `frame`. Callers must hold the owning shard's lock so the pin
+    /// can't race with eviction.
+    fn new(frame: Arc<Frame>) -> Self {
+        frame.pin_count.fetch_add(1, Ordering::AcqRel);
+        Self { frame }
+    }
+
+    pub fn page(&self) -> RwLockReadGuard<'_, Page> {
+        self.frame.page.read_recover()
+    }
+
+    /// Lock the page for writing, marking it dirty so it is written back on
+    /// eviction or flush.
+    #[cfg(test)]
+    pub fn page_mut(&self) -> RwLockWriteGuard<'_, Page> {
+        let page = self.frame.page.write_recover();
+        // Only mark dirty once the write lock is held, so a concurrent flush
+        // can't clear the flag before this change lands
+        self.frame.dirty.store(true, Ordering::Release);
+        page
+    }
+}
+
+impl Drop for PageGuard {
+    fn drop(&mut self) {
+        self.frame.pin_count.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// One independently locked slice of the buffer pool.
+struct Shard {
+    frames: HashMap<PageId, Arc<Frame>>,
+    policy: Box<dyn EvictionPolicy>,
+    capacity: usize,
+}
+
+impl Shard {
+    /// Look up a cached page. Accesses made by a sequential scan aren't
+    /// reported to the eviction policy, so they can't make a page look hot.
+    fn get(&mut self, page_id: PageId, access: Access) -> Option<&Arc<Frame>> {
+        let frame = self.frames.get(&page_id)?;
+        if access == Access::Normal {
+            self.policy.record_access(page_id);
+        }
+        Some(frame)
+    }
+
+    fn insert(&mut self, page_id: PageId, frame: Arc<Frame>, access: Access) {
+        self.frames.insert(page_id, frame);
+        match access {
+            Access::Normal => self.policy.insert(page_id),
+            Access::Scan => self.policy.insert_cold(page_id),
+        }
+    }
+
+    fn remove(&mut self, page_id: PageId) -> Option<Arc<Frame>> {
+        self.policy.remove(page_id);
+        self.frames.remove(&page_id)
+    }
+
+    fn victim(&mut self) -> Option<PageId> {
+        let frames = &self.frames;
+        self.policy.victim(&|page_id| !frames[&page_id].is_pinned())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Access {
+    Normal,
+    /// Part of a sequential scan; the page should be evicted early
+    Scan,
+}
+
+/// The shared state of a `PageManager`, split into independently locked
+/// shards by page id so threads touching different pages rarely contend.
+///
+/// Lock order is always shard, then the page's file. When several shards are
+/// needed at once they are locked in index order.
+struct BufferPool {
+    files: FileManager,
+    shards: Vec<Mutex<Shard>>,
+    /// Size of a page on disk; cached pages may be smaller, see `PageCodec`
+    page_size: usize,
+    codec: PageCodec,
+    wal: Option<Arc<Wal>>,
+    /// Held while checkpointing, so only one checkpoint runs at a time
+    checkpointing: Mutex<()>,
+    counters: BufferCounters,
+}
+
+impl BufferPool {
+    fn get_page(&self, page_id: PageId, access: Access) -> Result<PageGuard, PageManagerError> {
+        let mut shard = self.shard(page_id).lock_recover();
+        if let Some(frame) = shard.get(page_id, access) {
+            BufferCounters::increment(&self.counters.hits);
+            return Ok(PageGuard::new(frame.clone()));
+        }
+        BufferCounters::increment(&self.counters.misses);
+        let (page, lsn) = self.read_page(page_id)?;
+        let frame = self.insert(&mut shard, page_id, Frame::new(page, false, lsn), access)?;
+        Ok(PageGuard::new(frame))
+    }
+
+    fn should_flush(&self, dirty_threshold_percent: Option<u8>) -> bool {
+        let stats = self.stats();
+        match dirty_threshold_percent {
+            Some(percent) => stats.dirty_pages * 100 >= stats.capacity * percent as usize,
+            None => stats.dirty_pages > 0,
+        }
+    }
+
+    fn stats(&self) -> BufferStats {
+        let mut stats = BufferStats::from_counters(&self.counters);
+        for shard in &self.shards {
+            let shard = shard.lock_recover();
+            stats.capacity += shard.capacity;
+            stats.cached_pages += shard.frames.len();
+            for frame in shard.frames.values() {
+                if frame.dirty.load(Ordering::Acquire) {
+                    stats.dirty_pages += 1;
+                }
+                if frame.is_pinned() {
+                    stats.pinned_pages += 1;
+                }
+            }
+        }
+        stats
+    }
+
+    fn cached_by_file(&self) -> HashMap<FileId, (usize, usize)> {
+        let mut files: HashMap<FileId, (usize, usize)> = HashMap::new();
+        for shard in &self.shards {
+            for (page_id, frame) in &shard.lock_recover().frames {
+                let (cached, dirty) = files.entry(page_id.file).or_default();
+                *cached += 1;
+                if frame.dirty.load(Ordering::Acquire) {
+                    *dirty += 1;
+                }
+            }
+        }
+        files
+    }
+
+    /// Load a page ahead of a scan. Failures are ignored: the scan will
+    /// simply read the page itself if it gets there.
+    fn prefetch(&self, page_id: PageId) {
+        let mut shard = self.shard(page_id).lock_recover();
+        if shard.frames.contains_key(&page_id) {
+            return;
+        }
+        if let Ok((page, lsn)) = self.read_page(page_id) {
+            let frame = Frame::new(page, false, lsn);
+            if self
+                .insert(&mut shard, page_id, frame, Access::Scan)
+                .is_ok()
+            {
+                BufferCounters::increment(&self.counters.prefetches);
+            }
+        }
+    }
+
+    fn write_page(&self, page_id: PageId, page: Page) -> Result<(), PageManagerError> {
+        let mut shard = self.shard(page_id).lock_recover();
+        if let Some(frame) = shard.get(page_id, Access::Normal) {
+            *frame.page.write_recover() = page;
+            frame.dirty.store(true, Ordering::Release);
+            return Ok(());
+        }
+        self.insert(
+            &mut shard,
+            page_id,
+            Frame::new(page, true, Lsn::ZERO),
+            Access::Normal,
+        )?;
+        Ok(())
+    }
+
+    fn log_write(&self, txn: TxnId, page_id: PageId, page: Page) -> Result<Lsn, PageManagerError> {
+        let wal = self.wal.as_ref().ok_or(PageManagerError::WalDisabled)?;
+        let mut shard = self.shard(page_id).lock_recover();
+        let frame = self.load_for_write(&mut shard, page_id)?;
+        let mut current = frame.page.write_recover();
+        let lsn = wal.append(&WalRecord::PageWrite {
+            txn,
+            page_id,
+            before: current.as_bytes().to_vec(),
+            after: page.as_bytes().to_vec(),
+        })?;
+        *current = page;
+        frame.lsn.store(lsn.0, Ordering::Release);
+        frame.dirty.store(true, Ordering::Release);
+        Ok(lsn)
+    }
+
+    /// Reapply a logged change to a page unless the page already reflects
+    /// it, as shown by its LSN, or replace the page `whole` without reading
+    /// it. Returns whether the page was changed.
+    fn redo(
+        &self,
+        page_id: PageId,
+        page: Page,
+        lsn: Lsn,
+        whole: bool,
+    ) -> Result<bool, PageManagerError> {
+        let mut shard = self.shard(page_id).lock_recover();
+        if whole && !shard.frames.contains_key(&page_id) {
+            // Not read, as it may have been torn
+            self.files.file(page_id.file)?;
+            self.insert(
+                &mut shard,
+                page_id,
+                Frame::new(page, true, lsn),
+                Access::Normal,
+            )?;
+            return Ok(true);
+        }
+        let frame = self.load_for_write(&mut shard, page_id)?;
+        let mut current = frame.page.write_recover();
+        if frame.lsn() >= lsn && !whole {
+            return Ok(false);
+        }
+        *current = page;
+        frame.lsn.store(lsn.0, Ordering::Release);
+        frame.dirty.store(true, Ordering::Release);
+        Ok(true)
+    }
+
+    /// The LSN a page has on disk, read from its header alone.
+    fn stored_lsn(&self, page_id: PageId) -> Result<Lsn, PageManagerError> {
+        let file = self.files.file(page_id.file)?;
+        let stored = file
+            .lock_recover()
+            .read_page(page_id.page_no, self.page_size);
+        match stored {
+            Err(PageIOError::PageNotFound(_)) => Ok(Lsn::ZERO),
+            stored => Ok(self.codec.stored_lsn(&stored?)),
+        }
+    }
+
+    /// The cached frame for a page about to be changed, loading it if
+    /// needed.
+    fn load_for_write(
+        &self,
+        shard: &mut Shard,
+        page_id: PageId,
+    ) -> Result<Arc<Frame>, PageManagerError> {
+        if let Some(frame) = shard.get(page_id, Access::Normal) {
+            return Ok(frame.clone());
+        }
+        BufferCounters::increment(&self.counters.misses);
+        let (page, lsn) = match self.read_page(page_id) {
+            // A page past the end of the file is new, and starts out empty
+            Err(PageManagerError::PageIOError(PageIOError::PageNotFound(_))) => (
+                Page::zeros(self.codec.usable_size(self.page_size)),
+                Lsn::ZERO,
+            ),
+            result => result?,
+        };
+        self.insert(shard, page_id, Frame::new(page, false, lsn), Access::Normal)
+    }
+
+    fn resize_cache(&self, cache_size: usize) -> Result<(), PageManagerError> {
+        if cache_size == 0 {
+            return Err(PageManagerError::InvalidCacheSize(
+                "Cache size must be greater than 0.".into(),
+            ));
+        }
+        let capacity = cache_size.div_ceil(self.shards.len());
+        for shard in &self.shards {
+            let mut shard = shard.lock_recover();
+            shard.capacity = capacity;
+            while shard.frames.len() > capacity {
+                match self.evict(&mut shard) {
+                    Ok(()) => {}
+                    // Pinned pages stay until they're unpinned and evicted
+                    // to make room in turn
+                    Err(PageManagerError::NoEvictablePage) => break,
+                    Err(e) => return Err(e),
+                }
+            }
+            // Give back what the evicted pages' entries took up
+            shard.frames.shrink_to(capacity);
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), PageManagerError> {
+        for shard in &self.shards {
+            let shard = shard.lock_recover();
+            for (&page_id, frame) in &shard.frames {
+                self.write_back(page_id, frame)?;
+            }
         }
-        Ok(self.cache.get(&page_id).unwrap())
+        self.files.flush()?;
+        Ok(())
+    }
+
+    fn checkpoint(&self) -> Result<Lsn, PageManagerError> {
+        let wal = self.wal.as_ref().ok_or(PageManagerError::WalDisabled)?;
+        let _checkpointing = self.checkpointing.lock_recover();
+        let (lsn, start) = self.write_checkpoint(wal)?;
+        // Only remove segments once the superblock no longer points into them
+        wal.remove_segments_before(start)?;
+        Ok(lsn)
+    }
+
+    /// Write every dirty page, log a checkpoint and point the superblock at
+    /// it. Returns its LSN and where recovery from it starts. Only called
+    /// while holding `checkpointing`.
+    fn write_checkpoint(&self, wal: &Wal) -> Result<(Lsn, Lsn), PageManagerError> {
+        // Everything logged before this point is on disk once the flush
+        // returns; later changes may or may not be
+        let redo_from = wal.end();
+        self.flush()?;
+        let (lsn, start) = wal.checkpoint(redo_from)?;
+        self.files.set_checkpoint(lsn)?;
+        Ok((lsn, start))
+    }
+
+    fn backup(&self, dest: &Path) -> Result<Lsn, PageManagerError> {
+        let wal = self.wal.as_ref().ok_or(PageManagerError::WalDisabled)?;
+        // Held until the log is copied, so no checkpoint removes segments
+        // the backup needs
+        let _checkpointing = self.checkpointing.lock_recover();
+        let (_, start) = self.write_checkpoint(wal)?;
+        // Pages keep changing while they are copied. Each was only written
+        // after its log records were flushed, so the log copied afterwards
+        // is enough for recovery to bring every copy up to date
+        self.files.copy_to(dest)?;
+        let end = wal.copy_to(&Wal::dir(dest), start)?;
+        wal.remove_segments_before(start)?;
+        Ok(end)
+    }
+
+    fn incremental_backup(&self, dest: &Path, base: &Path) -> Result<Lsn, PageManagerError> {
+        let wal = self.wal.as_ref().ok_or(PageManagerError::WalDisabled)?;
+        let since = incremental::recovery_start(base)?;
+        let _checkpointing = self.checkpointing.lock_recover();
+        let (_, start) = self.write_checkpoint(wal)?;
+        fs::create_dir_all(dest).map_err(FileManagerError::from)?;
+        // Written first, so a backup that fails partway still isn't taken
+        // for a full one
+        incremental::write_marker(dest, since).map_err(FileManagerError::from)?;
+        // Pages unchanged since `since` already match the base's copies
+        self.files
+            .copy_changed_to(dest, |page| self.codec.stored_lsn(page) >= since)?;
+        let end = wal.copy_to(&Wal::dir(dest), start)?;
+        wal.remove_segments_before(start)?;
+        Ok(end)
     }
 
-    pub fn write_page(&mut self, page_id: u64, page: Page) -> Result<(), PageManagerError> {
-        self.page_io.write_page(page_id, self.page_size, &page)?;
-        self.cache.put(page_id, page);
+    /// Apply a record received from the primary at the same LSN in its log.
+    /// Changes are redone as recovery would; a checkpoint becomes a restart
+    /// point once everything before it is on disk here too.
+    fn replicate(&self, lsn: Lsn, record: WalRecord) -> Result<(), PageManagerError> {
+        let wal = self.wal.as_ref().ok_or(PageManagerError::WalDisabled)?;
+        if !wal.append_at(lsn, &record)? {
+            return Ok(());
+        }
+        match record {
+            WalRecord::PageWrite { page_id, after, .. } => {
+                // Creating a file isn't logged, so its first page creates it
+                self.files.ensure_file(page_id.file)?;
+                self.redo(page_id, Page::new(after), lsn, false)?;
+            }
+            WalRecord::Checkpoint { .. } => {
+                let _checkpointing = self.checkpointing.lock_recover();
+                wal.flush_all()?;
+                self.flush()?;
+                self.files.set_checkpoint(lsn)?;
+                wal.remove_segments_before(record.recovery_start().unwrap())?;
+            }
+            _ => {}
+        }
         Ok(())
     }
 
-    pub fn invalidate(&mut self, page_id: u64) {
-        self.cache.pop(&page_id);
+    /// Delete a file and discard its cached pages without writing them back.
+    fn drop_file(&self, file_id: FileId) -> Result<(), PageManagerError> {
+        // Hold every shard so nothing can load one of the file's pages while
+        // it is being removed
+        let mut shards: Vec<_> = self.shards.iter().map(|s| s.lock_recover()).collect();
+        for shard in &shards {
+            for (&page_id, frame) in &shard.frames {
+                if page_id.file == file_id && frame.is_pinned() {
+                    return Err(PageManagerError::PagePinned(page_id));
+                }
+            }
+        }
+        for shard in &mut shards {
+            let page_ids: Vec<_> = shard
+                .frames
+                .keys()
+                .filter(|page_id| page_id.file == file_id)
+                .copied()
+                .collect();
+            for page_id in page_ids {
+                shard.remove(page_id);
+            }
+        }
+        self.files.remove_file(file_id)?;
+        Ok(())
+    }
+
+    fn shard(&self, page_id: PageId) -> &Mutex<Shard> {
+        // Offset by the file id so page 0 of every file doesn't share a shard
+        let hash = page_id.page_no.wrapping_add(page_id.file.0 as u64);
+        &self.shards[(hash % self.shards.len() as u64) as usize]
+    }
+
+    fn read_page(&self, page_id: PageId) -> Result<(Page, Lsn), PageManagerError> {
+        let file = self.files.file(page_id.file)?;
+        let page = file
+            .lock_recover()
+            .read_page(page_id.page_no, self.page_size)?;
+        Ok(self.codec.decode(page, self.page_size, page_id)?)
+    }
+
+    fn insert(
+        &self,
+        shard: &mut Shard,
+        page_id: PageId,
+        frame: Arc<Frame>,
+        access: Access,
+    ) -> Result<Arc<Frame>, PageManagerError> {
+        if shard.frames.len() >= shard.capacity {
+            self.evict(shard)?;
+        }
+        shard.insert(page_id, frame.clone(), access);
+        Ok(frame)
+    }
+
+    fn write_encoded(
+        &self,
+        page_id: PageId,
+        page: &Page,
+        lsn: Lsn,
+    ) -> Result<(), PageManagerError> {
+        let (stored, used) = self.codec.encode(page, self.page_size, page_id, lsn)?;
+        let file = self.files.file(page_id.file)?;
+        let mut file = file.lock_recover();
+        file.write_page(page_id.page_no, self.page_size, &stored)?;
+        if self.codec.is_enabled() {
+            file.punch_hole(page_id.page_no, self.page_size, used)?;
+        }
+        Ok(())
+    }
+
+    /// Evict the unpinned page chosen by the shard's eviction policy. The
+    /// page stays cached unless it's written back, so a failed write can't
+    /// lose a change.
+    fn evict(&self, shard: &mut Shard) -> Result<(), PageManagerError> {
+        let victim = shard.victim().ok_or(PageManagerError::NoEvictablePage)?;
+        self.write_back(victim, &shard.frames[&victim])?;
+        shard.remove(victim);
+        BufferCounters::increment(&self.counters.evictions);
+        Ok(())
+    }
+
+    fn write_back(&self, page_id: PageId, frame: &Frame) -> Result<(), PageManagerError> {
+        // Hold the page lock across the check so no writer can slip a change
+        // in between clearing the flag and writing the page out
+        let page = frame.page.read_recover();
+        if frame.dirty.swap(false, Ordering::AcqRel) {
+            if let Err(e) = self
+                .flush_log(frame)
+                .and_then(|_| self.write_encoded(page_id, &page, frame.lsn()))
+            {
+                frame.dirty.store(true, Ordering::Release);
+                return Err(e);
+            }
+            BufferCounters::increment(&self.counters.write_backs);
+        }
+        Ok(())
+    }
+
+    /// Make the log records behind a page's changes durable before the page
+    /// itself is written, so a crash never leaves a change on disk that the
+    /// log can't undo.
+    fn flush_log(&self, frame: &Frame) -> Result<(), PageManagerError> {
+        let lsn = frame.lsn();
+        if let Some(wal) = &self.wal {
+            if lsn != Lsn::ZERO {
+                wal.flush(lsn)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Detects sequential scans and hands the pages ahead of them to a
+/// background thread to load.
+struct Prefetcher {
+    detector: Mutex<ScanDetector>,
+    sender: Option<Sender<PageId>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Prefetcher {
+    fn spawn(pool: Arc<BufferPool>, read_ahead: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<PageId>();
+        let worker = thread::spawn(move || {
+            for page_id in receiver {
+                pool.prefetch(page_id);
+            }
+        });
+        Self {
+            detector: Mutex::new(ScanDetector::new(read_ahead)),
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    fn observe(&self, page_id: PageId) -> Access {
+        let scan = self.detector.lock_recover().record(page_id);
+        if let Some(sender) = &self.sender {
+            for page_no in scan.prefetch {
+                // The worker only stops once the sender is dropped
+                let _ = sender.send(PageId::new(page_id.file, page_no));
+            }
+        }
+        if scan.sequential {
+            Access::Scan
+        } else {
+            Access::Normal
+        }
+    }
+}
+
+impl Drop for Prefetcher {
+    fn drop(&mut self) {
+        // Closing the channel ends the worker's loop
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Periodically writes dirty pages in the background so that explicit
+/// flushes find little left to do.
+struct BackgroundFlusher {
+    shutdown: Option<Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl BackgroundFlusher {
+    fn spawn(pool: Arc<BufferPool>, config: FlusherConfig) -> Self {
+        let (shutdown, receiver) = mpsc::channel::<()>();
+        let interval = Duration::from_millis(config.interval_ms);
+        let worker = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                if pool.should_flush(config.dirty_threshold_percent) {
+                    // A failed write leaves its page dirty, so the next round
+                    // (or an explicit flush) retries it
+                    let _ = pool.flush();
+                }
+            }
+        });
+        Self {
+            shutdown: Some(shutdown),
+            worker: Some(worker),
+        }
+    }
+}
+
+impl Drop for BackgroundFlusher {
+    fn drop(&mut self) {
+        self.shutdown.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Periodically checkpoints the log, bounding both how much recovery has to
+/// replay and how much log is kept.
+struct BackgroundCheckpointer {
+    shutdown: Option<Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl BackgroundCheckpointer {
+    fn spawn(pool: Arc<BufferPool>, interval: Duration) -> Self {
+        let (shutdown, receiver) = mpsc::channel::<()>();
+        let worker = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                // A failed checkpoint leaves the previous one in place, and the
+                // next round tries again
+                let _ = pool.checkpoint();
+            }
+        });
+        Self {
+            shutdown: Some(shutdown),
+            worker: Some(worker),
+        }
+    }
+}
+
+impl Drop for BackgroundCheckpointer {
+    fn drop(&mut self) {
+        self.shutdown.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
     }
+}
+
+/// A thread-safe buffer pool over the files of a database directory.
+pub struct PageManager {
+    /// Stopped first, while nothing else has shut down
+    receiver: Option<WalReceiver>,
+    sender: Option<WalSender>,
+    pool: Arc<BufferPool>,
+    prefetcher: Option<Prefetcher>,
+    /// Held for its thread, which stops once it's dropped
+    _flusher: Option<BackgroundFlusher>,
+    checkpointer: Option<BackgroundCheckpointer>,
+    checkpoint_interval: Option<Duration>,
+    recovery: RecoveryReport,
+}
+
+impl PageManager {
+    fn new(builder: PageManagerBuilder) -> Result<Self, PageManagerError> {
+        let PageManagerBuilder {
+            db_path,
+            page_size,
+            cache_size,
+            shard_count,
+            eviction_policy,
+            lru_k,
+            read_ahead,
+            flusher,
+            durability,
+            growth_chunk_pages,
+            io_mode,
+            migration,
+            compression,
+            encryption,
+            wal,
+            restore,
+            replication,
+            recover,
+            faults,
+        } = builder;
+
+        if cache_size == 0 {
+            return Err(PageManagerError::InvalidCacheSize(
+                "Cache size must be greater than 0.".into(),
+            ));
+        }
+        if shard_count == 0 {
+            return Err(PageManagerError::InvalidShardCount(
+                "Shard count must be greater than 0.".into(),
+            ));
+        }
+
+        // Every shard needs room for at least one page
+        let shard_count = shard_count.min(cache_size);
+        let shard_capacity = cache_size.div_ceil(shard_count);
+        let shards = (0..shard_count)
+            .map(|_| {
+                Mutex::new(Shard {
+                    frames: HashMap::with_capacity(shard_capacity),
+                    policy: eviction::new_policy(eviction_policy, lru_k),
+                    capacity: shard_capacity,
+                })
+            })
+            .collect();
 
-    pub fn flush(&mut self) -> Result<(), PageManagerError> {
-        for (&page_id, page) in self.cache.iter() {
-            self.page_io.write_page(page_id, self.page_size, page)?;
+        let cipher = encryption
+            .map(|config| PageCipher::from_config(&config, |name| std::env::var(name).ok()))
+            .transpose()?;
+
+        let in_memory = db_path == Path::new(IN_MEMORY);
+        if let Some(mode) = migration.filter(|_| !in_memory) {
+            Migrator::new().migrate(&db_path, mode)?;
+        }
+        let files = FileManager::open(
+            db_path,
+            FileOptions {
+                page_size,
+                durability,
+                io_mode,
+                growth_chunk_pages,
+                compression,
+                key_check: cipher.as_ref().map(PageCipher::key_check),
+                logged: wal.is_some(),
+            },
+            faults.clone(),
+        )?;
+
+        if let Some((archive_dir, target)) = restore {
+            if wal.is_none() {
+                return Err(PageManagerError::WalDisabled);
+            }
+            if in_memory {
+                return Err(PageManagerError::InMemory);
+            }
+            restore::restore(
+                &Wal::dir(files.root()),
+                &archive_dir,
+                target,
+                files.superblock().checkpoint,
+            )?;
         }
+
+        let checkpoint_interval = wal
+            .as_ref()
+            .and_then(|config| config.checkpoint_interval_ms)
+            .map(Duration::from_millis);
+        let replication = replication.filter(|_| wal.is_some());
+        let standby = replication
+            .as_ref()
+            .is_some_and(|config| config.primary.is_some());
+        let wal = wal
+            .map(|config| {
+                let options = WalOptions {
+                    segment_size: config.segment_size,
+                    durability,
+                    commit_delay: config.commit_delay_us.map(Duration::from_micros),
+                    retention: config.retention_ms.map(Duration::from_millis),
+                    archive_dir: config.archive_dir.map(PathBuf::from),
+                    full_page_writes: config.full_page_writes,
+                    faults: faults.clone(),
+                };
+                if in_memory {
+                    Wal::open_in_memory(options)
+                } else {
+                    Wal::open(Wal::dir(files.root()), options)
+                }
+                .map(Arc::new)
+            })
+            .transpose()?;
+        if let Some(wal) = &wal {
+            wal.set_read_only(standby);
+        }
+
+        let pool = Arc::new(BufferPool {
+            files,
+            shards,
+            page_size,
+            codec: PageCodec::new(compression, cipher, wal.is_some()),
+            wal,
+            checkpointing: Mutex::new(()),
+            counters: BufferCounters::default(),
+        });
+        let prefetcher = (read_ahead > 0).then(|| Prefetcher::spawn(pool.clone(), read_ahead));
+
+        let flusher = flusher.map(|config| BackgroundFlusher::spawn(pool.clone(), config));
+
+        let mut manager = Self {
+            receiver: None,
+            sender: None,
+            pool,
+            prefetcher,
+            _flusher: flusher,
+            checkpointer: None,
+            checkpoint_interval,
+            recovery: RecoveryReport::default(),
+        };
+        // A replica's unfinished transactions may yet commit on the primary
+        if recover {
+            manager.recovery = recovery::recover(&manager, !standby)?;
+        }
+        if let Some(config) = replication {
+            let poll = Duration::from_millis(config.poll_interval_ms);
+            if let Some(addr) = &config.listen {
+                let wal = manager.pool.wal.clone().unwrap();
+                manager.sender = Some(WalSender::spawn(wal, addr, poll).map_err(WalError::from)?);
+            }
+            if let Some(primary) = config.primary {
+                let (next, apply) = (manager.pool.clone(), manager.pool.clone());
+                manager.receiver = Some(WalReceiver::spawn(
+                    primary,
+                    poll,
+                    move || next.wal.as_ref().unwrap().end(),
+                    move |lsn, record| apply.replicate(lsn, record),
+                ));
+            }
+        }
+        // Only start checkpointing once recovery has finished with the log.
+        // A replica's checkpoints arrive from its primary instead
+        if !standby {
+            manager.start_checkpointer();
+        }
+        Ok(manager)
+    }
+
+    fn start_checkpointer(&mut self) {
+        self.checkpointer = self
+            .checkpoint_interval
+            .map(|interval| BackgroundCheckpointer::spawn(self.pool.clone(), interval));
+    }
+
+    /// Bytes available in each page. Smaller than the configured page size
+    /// when compression, encryption or the log is enabled, as each page on
+    /// disk then carries a header.
+    pub fn page_size(&self) -> usize {
+        self.pool.codec.usable_size(self.pool.page_size)
+    }
+
+    /// Compress the pages of `file` with `compression` as they are next
+    /// written. Pages only record how they were compressed in their header,
+    /// so this does nothing when they have none: with no compression,
+    /// encryption or log.
+    pub fn set_file_compression(&self, file: FileId, compression: Compression) {
+        self.pool.codec.set_file_compression(file, compression);
+    }
+
+    /// Snapshot the pool's counters and current occupancy.
+    pub fn stats(&self) -> BufferStats {
+        self.pool.stats()
+    }
+
+    /// How many pages of each file the pool holds, and how many of those
+    /// are dirty. Files with none cached are left out.
+    pub fn cached_by_file(&self) -> HashMap<FileId, (usize, usize)> {
+        self.pool.cached_by_file()
+    }
+
+    /// Change how many pages the pool holds while it's in use, writing
+    /// back and evicting those past a smaller size. The pool keeps at least
+    /// a page per shard.
+    pub fn resize_cache(&self, cache_size: usize) -> Result<(), PageManagerError> {
+        self.pool.resize_cache(cache_size)
+    }
+
+    /// Pin a page, reading it from disk if it isn't cached.
+    pub fn get_page(&self, page_id: PageId) -> Result<PageGuard, PageManagerError> {
+        let access = match &self.prefetcher {
+            Some(prefetcher) => prefetcher.observe(page_id),
+            None => Access::Normal,
+        };
+        self.pool.get_page(page_id, access)
+    }
+
+    /// Replace a page in the cache. The page only reaches disk once it is
+    /// evicted or the manager is flushed.
+    pub fn write_page(&self, page_id: PageId, page: Page) -> Result<(), PageManagerError> {
+        self.pool.write_page(page_id, page)
+    }
+
+    /// Replace a page as part of `txn`, logging its old and new contents
+    /// first. Unlike `write_page`, the change can be recovered after a
+    /// crash. Returns the LSN of the log record.
+    pub fn log_write(
+        &self,
+        txn: TxnId,
+        page_id: PageId,
+        page: Page,
+    ) -> Result<Lsn, PageManagerError> {
+        self.pool.log_write(txn, page_id, page)
+    }
+
+    /// Reapply a logged change during recovery, unless the page's LSN shows
+    /// it already reflects the change, or replace the page `whole` whatever
+    /// its LSN, as a page torn while being written may have the new LSN
+    /// and old contents. Returns whether the page was changed.
+    pub(super) fn redo(
+        &self,
+        page_id: PageId,
+        page: Page,
+        lsn: Lsn,
+        whole: bool,
+    ) -> Result<bool, PageManagerError> {
+        self.pool.redo(page_id, page, lsn, whole)
+    }
+
+    /// The LSN a page has on disk, without reading the rest of it.
+    pub(super) fn stored_lsn(&self, page_id: PageId) -> Result<Lsn, PageManagerError> {
+        self.pool.stored_lsn(page_id)
+    }
+
+    /// Read a page as stored on disk, bypassing the cache, with its LSN.
+    pub(super) fn read_stored(&self, page_id: PageId) -> Result<(Page, Lsn), PageManagerError> {
+        self.pool.read_page(page_id)
+    }
+
+    /// Write every dirty page and log a checkpoint, so recovery only needs
+    /// the log from here on (or from the oldest running transaction's first
+    /// change, if earlier); older log segments are removed. Returns the
+    /// checkpoint's LSN.
+    pub fn checkpoint(&self) -> Result<Lsn, PageManagerError> {
+        self.pool.checkpoint()
+    }
+
+    /// A page as it was at an earlier point, rebuilt from the log. See
+    /// `history::page_as_of`.
+    pub fn page_as_of(&self, page_id: PageId, as_of: AsOf) -> Result<Page, PageManagerError> {
+        history::page_as_of(self, page_id, as_of)
+    }
+
+    /// Copy the database to `dest` while it stays in use: a checkpoint,
+    /// the data files, then the log from the checkpoint on. Opening the
+    /// copy recovers it to a consistent state as of the returned LSN; with
+    /// the archived log, it can also be restored to any later point.
+    /// Writes to a file only wait while that file is being copied.
+    pub fn backup(&self, dest: impl AsRef<Path>) -> Result<Lsn, PageManagerError> {
+        self.pool.backup(dest.as_ref())
+    }
+
+    /// Like `backup`, but only the pages changed since the backup in
+    /// `base` was taken are copied, as shown by their LSNs; `base` may be a
+    /// full backup or another incremental one. `incremental::merge` turns
+    /// the chain back into a full backup. Changes made with `write_page`
+    /// aren't logged and may be missed, as may those to a file dropped and
+    /// created again under the same id in between.
+    pub fn incremental_backup(
+        &self,
+        dest: impl AsRef<Path>,
+        base: impl AsRef<Path>,
+    ) -> Result<Lsn, PageManagerError> {
+        self.pool.incremental_backup(dest.as_ref(), base.as_ref())
+    }
+
+    /// What crash recovery did when the manager was opened.
+    pub fn recovery(&self) -> &RecoveryReport {
+        &self.recovery
+    }
+
+    /// The write-ahead log, if enabled.
+    pub fn wal(&self) -> Option<&Wal> {
+        self.pool.wal.as_deref()
+    }
+
+    /// The address replicas connect to, if accepting them.
+    pub fn replication_addr(&self) -> Option<SocketAddr> {
+        self.sender.as_ref().map(WalSender::local_addr)
+    }
+
+    /// Whether this is a replica still following its primary.
+    pub fn is_replica(&self) -> bool {
+        self.receiver.is_some()
+    }
+
+    /// Stop following the primary and start accepting writes, as when the
+    /// primary has failed. Whatever was received so far is kept; the
+    /// transactions it leaves unfinished are rolled back, as in crash
+    /// recovery, whose report replaces the one from opening.
+    // The database shares its pages, so only the tests promote a replica
+    #[allow(dead_code)]
+    pub fn promote(&mut self) -> Result<(), PageManagerError> {
+        let receiver = self.receiver.take().ok_or(PageManagerError::NotReplica)?;
+        drop(receiver);
+        let wal = self.wal().ok_or(PageManagerError::WalDisabled)?;
+        wal.flush_all()?;
+        wal.set_read_only(false);
+        self.recovery = recovery::recover(self, true)?;
+        self.start_checkpointer();
         Ok(())
     }
+
+    /// Write every dirty page to disk and flush the underlying files.
+    pub fn flush(&self) -> Result<(), PageManagerError> {
+        self.pool.flush()
+    }
+
+    /// The database directory's files.
+    pub fn files(&self) -> &FileManager {
+        &self.pool.files
+    }
+
+    /// Create a new, empty data file, such as for a new table or index.
+    pub fn create_file(&self) -> Result<FileId, PageManagerError> {
+        Ok(self.pool.files.create_file()?)
+    }
+
+    /// Delete a data file, discarding any of its pages still in the cache.
+    /// Fails if one of them is pinned.
+    // For DROP TABLE, which isn't parsed yet
+    #[allow(dead_code)]
+    pub fn drop_file(&self, file_id: FileId) -> Result<(), PageManagerError> {
+        self.pool.drop_file(file_id)
+    }
 }
 
 pub struct PageManagerBuilder {
     db_path: PathBuf,
     page_size: usize,
     cache_size: usize,
+    shard_count: usize,
+    eviction_policy: EvictionPolicyKind,
+    lru_k: usize,
+    read_ahead: usize,
+    flusher: Option<FlusherConfig>,
+    durability: Durability,
+    growth_chunk_pages: u64,
+    io_mode: IoMode,
+    migration: Option<MigrationMode>,
+    compression: Compression,
+    encryption: Option<EncryptionConfig>,
+    wal: Option<WalConfig>,
+    restore: Option<(PathBuf, Option<AsOf>)>,
+    replication: Option<ReplicationConfig>,
+    recover: bool,
+    faults: Option<Arc<FaultInjector>>,
 }
 
 impl PageManagerBuilder {
+    /// Start a manager over the database directory at `db_path`, which is
+    /// created if it doesn't exist, or over pages and a log kept in memory
+    /// if it is `IN_MEMORY`.
     pub fn new(db_path: impl AsRef<Path>) -> Self {
         Self {
             db_path: db_path.as_ref().to_path_buf(),
             page_size: 4096,  // Default page size
             cache_size: 1000, // Default cache size
+            shard_count: 16,  // Default shard count
+            eviction_policy: EvictionPolicyKind::Lru,
+            lru_k: 2,
+            read_ahead: 0,
+            flusher: None,
+            durability: Durability::Full,
+            growth_chunk_pages: 1,
+            io_mode: IoMode::Buffered,
+            migration: None,
+            compression: Compression::None,
+            encryption: None,
+            wal: None,
+            restore: None,
+            replication: None,
+            recover: true,
+            faults: None,
         }
     }
 
-    pub fn page_size(mut self, size: usize) -> Self {
-        self.page_size = size;
+    /// Start from the storage settings in `config`.
+    pub fn from_config(config: &StorageConfig) -> Self {
+        Self::new(&config.db_path)
+            .page_size(config.page_size as usize)
+            .cache_size(config.cache_size)
+            .eviction_policy(config.eviction_policy)
+            .lru_k(config.lru_k)
+            .read_ahead(config.read_ahead)
+            .background_flush(config.flusher)
+            .durability(config.durability)
+            .growth_chunk_pages(config.growth_chunk_pages)
+            .io_mode(config.io_mode)
+            .migration(config.migration)
+            .compression(config.compression)
+            .encryption(config.encryption.clone())
+            .wal(config.wal.clone())
+            .replication(config.replication.clone())
+    }
+
+    pub fn page_size(mut self, size: usize) -> Self {
+        self.page_size = size;
+        self
+    }
+
+    pub fn cache_size(mut self, size: usize) -> Self {
+        self.cache_size = size;
+        self
+    }
+
+    /// Number of independently locked cache shards. Clamped to the cache
+    /// size so every shard holds at least one page.
+    #[cfg(test)]
+    pub fn shards(mut self, count: usize) -> Self {
+        self.shard_count = count;
+        self
+    }
+
+    pub fn eviction_policy(mut self, policy: EvictionPolicyKind) -> Self {
+        self.eviction_policy = policy;
+        self
+    }
+
+    /// Number of accesses tracked per page by `EvictionPolicyKind::LruK`.
+    pub fn lru_k(mut self, k: usize) -> Self {
+        self.lru_k = k;
+        self
+    }
+
+    /// Number of pages to load ahead of a detected sequential scan. Zero
+    /// disables scan detection entirely.
+    pub fn read_ahead(mut self, pages: usize) -> Self {
+        self.read_ahead = pages;
+        self
+    }
+
+    /// Run a background writer for dirty pages, or `None` to disable it.
+    pub fn background_flush(mut self, config: Option<FlusherConfig>) -> Self {
+        self.flusher = config;
+        self
+    }
+
+    /// How far `flush` pushes pages before returning.
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Pages to extend the file by when writing past its end.
+    pub fn growth_chunk_pages(mut self, pages: u64) -> Self {
+        self.growth_chunk_pages = pages;
+        self
+    }
+
+    pub fn io_mode(mut self, mode: IoMode) -> Self {
+        self.io_mode = mode;
+        self
+    }
+
+    /// Upgrade a database in an older on-disk format before opening it, or
+    /// `None` to refuse such databases.
+    pub fn migration(mut self, mode: Option<MigrationMode>) -> Self {
+        self.migration = mode;
+        self
+    }
+
+    /// Compress pages on disk. Only takes effect when creating a database;
+    /// an existing one must be opened with the setting it was created with.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Encrypt pages on disk with the configured key, or `None` for
+    /// plaintext. Like compression, this is fixed when the database is
+    /// created.
+    pub fn encryption(mut self, config: Option<EncryptionConfig>) -> Self {
+        self.encryption = config;
+        self
+    }
+
+    /// Keep a write-ahead log under the database directory, or `None` to
+    /// disable it. Only changes made through `PageManager::log_write` are
+    /// logged. Fixed when the database is created, since logged pages carry
+    /// their LSN; the log is replayed to recover from a crash on open.
+    pub fn wal(mut self, config: Option<WalConfig>) -> Self {
+        self.wal = config;
+        self
+    }
+
+    /// Open a database restored from a backup by replaying the log archived
+    /// in `archive_dir` up to `target`, or all of it. See
+    /// `restore::restore`. Only for the first open after restoring; later
+    /// ones would discard the log written since.
+    pub fn restore(mut self, archive_dir: impl AsRef<Path>, target: Option<AsOf>) -> Self {
+        self.restore = Some((archive_dir.as_ref().to_path_buf(), target));
+        self
+    }
+
+    /// Stream the log to replicas, or follow a primary's log as a replica,
+    /// or `None` for neither. Needs the log enabled; see `replication`.
+    ///
+    /// A replica starts from a backup of its primary and opens read-only:
+    /// logged writes fail, and reads may see changes from transactions that
+    /// haven't committed yet. Only logged changes are replicated, so pages
+    /// written with `write_page`, and dropped files, aren't.
+    pub fn replication(mut self, config: Option<ReplicationConfig>) -> Self {
+        self.replication = config;
         self
     }
 
-    pub fn cache_size(mut self, size: usize) -> Self {
-        self.cache_size = size;
+    /// Whether to recover from a crash on open; it is on by default. Off
+    /// is only for inspecting a damaged database, such as with
+    /// `fsck::check`: pages may then lack changes that only the log holds,
+    /// and the log shouldn't be written to.
+    pub fn recover(mut self, enabled: bool) -> Self {
+        self.recover = enabled;
+        self
+    }
+
+    /// Inject `faults` into the I/O of every file and of the log, so a
+    /// test can make them fail, tear or crash where it chooses.
+    pub fn faults(mut self, faults: Arc<FaultInjector>) -> Self {
+        self.faults = Some(faults);
         self
     }
 
     pub fn build(self) -> Result<PageManager, PageManagerError> {
-        if self.page_size == 0 {
+        if self.page_size < SUPERBLOCK_SIZE {
             return Err(PageManagerError::PageDecodeError(
-                PageDecodeError::InvalidPageSize("Page size cannot be 0".into()),
+                PageDecodeError::InvalidPageSize(format!(
+                    "Page size must be at least {} bytes",
+                    SUPERBLOCK_SIZE
+                )),
             ));
         }
 
-        PageManager::new(self.db_path, self.page_size, self.cache_size)
+        PageManager::new(self)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::NamedTempFile;
+    use crate::storage::faults::{Fault, FaultPoint};
+    use crate::storage::superblock::SuperblockError;
+    use tempfile::TempDir;
 
-    fn setup_test_manager() -> (NamedTempFile, PageManager) {
-        let temp_file = NamedTempFile::new().unwrap();
-        let manager = PageManagerBuilder::new(temp_file.path())
-            .page_size(128) // Smaller page size for testing
-            .cache_size(10) // Small cache for testing
-            .build()
-            .unwrap();
-        (temp_file, manager)
+    fn setup_test_manager() -> (TempDir, PageManager) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128) // Smaller page size for testing
+                .cache_size(10), // Small cache for testing
+        );
+        (temp_dir, manager)
+    }
+
+    /// The data file the tests read and write; catalog page 0 holds the
+    /// superblock.
+    const DATA_FILE: FileId = FileId(1);
+
+    fn data_page(page_no: u64) -> PageId {
+        PageId::new(DATA_FILE, page_no)
+    }
+
+    fn data_path(dir: &TempDir) -> std::path::PathBuf {
+        dir.path().join("1.fdb")
+    }
+
+    /// Build the manager, creating the data file if it doesn't exist yet.
+    fn build(builder: PageManagerBuilder) -> PageManager {
+        let manager = builder.build().unwrap();
+        if manager.files().file(DATA_FILE).is_err() {
+            manager.create_file().unwrap();
+        }
+        manager
+    }
+
+    fn is_cached(manager: &PageManager, page_no: u64) -> bool {
+        let page_id = data_page(page_no);
+        manager
+            .pool
+            .shard(page_id)
+            .lock()
+            .unwrap()
+            .frames
+            .contains_key(&page_id)
     }
 
     #[test]
     fn test_builder_configuration() {
-        let temp_file = NamedTempFile::new().unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
 
         // Test default configuration
-        let default_manager = PageManagerBuilder::new(temp_file.path()).build().unwrap();
-        assert_eq!(default_manager.page_size, 4096);
+        let default_manager = build(PageManagerBuilder::new(temp_dir.path()));
+        assert_eq!(default_manager.page_size(), 4096);
 
         // Test custom configuration
-        let custom_manager = PageManagerBuilder::new(temp_file.path())
-            .page_size(8192)
-            .cache_size(500)
-            .build()
-            .unwrap();
-        assert_eq!(custom_manager.page_size, 8192);
+        let custom_dir = tempfile::tempdir().unwrap();
+        let custom_manager = build(
+            PageManagerBuilder::new(custom_dir.path())
+                .page_size(8192)
+                .cache_size(500),
+        );
+        assert_eq!(custom_manager.page_size(), 8192);
+    }
+
+    #[test]
+    fn test_page_size_must_match_superblock() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        drop(build(
+            PageManagerBuilder::new(temp_dir.path()).page_size(128),
+        ));
+
+        let result = PageManagerBuilder::new(temp_dir.path())
+            .page_size(256)
+            .build();
+        assert!(matches!(
+            result,
+            Err(PageManagerError::FileManagerError(
+                FileManagerError::SuperblockError(SuperblockError::PageSizeMismatch {
+                    expected: 256,
+                    found: 128
+                })
+            ))
+        ));
     }
 
     #[test]
     fn test_invalid_page_size() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let result = PageManagerBuilder::new(temp_file.path())
+        let temp_dir = tempfile::tempdir().unwrap();
+        let result = PageManagerBuilder::new(temp_dir.path())
             .page_size(0)
             .build();
         assert!(matches!(
             result,
             Err(PageManagerError::PageDecodeError(
-                PageDecodeError::InvalidBytes(_)
+                PageDecodeError::InvalidPageSize(_)
             ))
         ));
     }
 
     #[test]
     fn test_cache_hit() {
-        let (_temp, mut manager) = setup_test_manager();
-        let page_size = manager.page_size;
+        let (_temp, manager) = setup_test_manager();
+        let page_size = manager.page_size();
         let page = Page::full(42, page_size);
-        manager.write_page(0, page).unwrap();
+        manager.write_page(data_page(0), page).unwrap();
 
-        let page = manager.get_page(0).unwrap();
-        assert_eq!(page.as_bytes(), &vec![42u8; page_size]);
+        let page = manager.get_page(data_page(0)).unwrap();
+        assert_eq!(page.page().as_bytes(), &vec![42u8; page_size]);
     }
 
     #[test]
     fn test_cache_eviction() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let mut manager = PageManagerBuilder::new(temp_file.path())
-            .page_size(128)
-            .cache_size(1) // Very small cache for testing eviction
-            .build()
-            .unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .cache_size(1), // Very small cache for testing eviction
+        );
 
         // Write two pages with cache size 1
-        let data1 = vec![1u8; manager.page_size];
-        let data2 = vec![2u8; manager.page_size];
+        let data1 = vec![1u8; manager.page_size()];
+        let data2 = vec![2u8; manager.page_size()];
 
-        manager.write_page(0, Page::new(data1.clone())).unwrap();
-        manager.write_page(1, Page::new(data2.clone())).unwrap();
+        manager
+            .write_page(data_page(0), Page::new(data1.clone()))
+            .unwrap();
+        manager
+            .write_page(data_page(1), Page::new(data2.clone()))
+            .unwrap();
 
         // First page should be evicted and require disk read
-        let page1 = manager.get_page(0).unwrap();
-        assert_eq!(page1.as_bytes(), &data1);
+        let page1 = manager.get_page(data_page(0)).unwrap();
+        assert_eq!(page1.page().as_bytes(), &data1);
     }
 
     #[test]
     fn test_flush() {
-        let (_temp, mut manager) = setup_test_manager();
-        let data = vec![42u8; manager.page_size];
+        let (_temp, manager) = setup_test_manager();
+        let data = vec![42u8; manager.page_size()];
 
-        manager.write_page(0, Page::new(data.clone())).unwrap();
+        manager
+            .write_page(data_page(0), Page::new(data.clone()))
+            .unwrap();
         manager.flush().unwrap();
 
         // Create new manager to verify data was written to disk
-        let mut new_manager = PageManager::new(_temp.path(), manager.page_size, 10).unwrap();
-        let page = new_manager.get_page(0).unwrap();
-        assert_eq!(page.as_bytes(), &data);
+        let new_manager =
+            build(PageManagerBuilder::new(_temp.path()).page_size(manager.page_size()));
+        let page = new_manager.get_page(data_page(0)).unwrap();
+        assert_eq!(page.page().as_bytes(), &data);
     }
 
     #[test]
     fn test_buffer_reuse() {
-        let (_temp, mut manager) = setup_test_manager();
+        let (_temp, manager) = setup_test_manager();
 
         // Write different data to multiple pages
         for i in 0..5 {
-            let data = vec![(i as u8); manager.page_size];
-            manager.write_page(i, Page::new(data)).unwrap();
+            let data = vec![i as u8; manager.page_size()];
+            manager.write_page(data_page(i), Page::new(data)).unwrap();
         }
 
         // Read them back, this should cycle through the buffers
         for i in 0..5 {
-            let expected = vec![(i as u8); manager.page_size];
-            let page = manager.get_page(i).unwrap();
-            assert_eq!(*page, Page::new(expected));
+            let expected = vec![i as u8; manager.page_size()];
+            let page = manager.get_page(data_page(i)).unwrap();
+            assert_eq!(*page.page(), Page::new(expected));
+        }
+    }
+
+    #[test]
+    fn test_write_is_deferred_until_flush() {
+        let (temp, manager) = setup_test_manager();
+        manager
+            .write_page(data_page(0), Page::full(7, manager.page_size()))
+            .unwrap();
+        assert!(std::fs::read(data_path(&temp)).unwrap().is_empty());
+
+        manager.flush().unwrap();
+        assert_eq!(
+            std::fs::read(data_path(&temp)).unwrap(),
+            vec![7u8; manager.page_size()]
+        );
+    }
+
+    #[test]
+    fn test_dirty_page_written_back_on_eviction() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .cache_size(1),
+        );
+
+        manager
+            .write_page(data_page(0), Page::full(1, 128))
+            .unwrap();
+        manager
+            .write_page(data_page(1), Page::full(2, 128))
+            .unwrap();
+        manager.flush().unwrap();
+
+        // Page 0 was evicted before the flush, so it must have been written back
+        let contents = std::fs::read(data_path(&temp_dir)).unwrap();
+        assert_eq!(&contents[..128], &[1u8; 128][..]);
+        assert_eq!(&contents[128..], &[2u8; 128][..]);
+    }
+
+    #[test]
+    fn test_failed_write_back_keeps_page() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let faults = Arc::new(FaultInjector::new(1));
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .cache_size(1)
+                .faults(faults.clone()),
+        );
+
+        manager
+            .write_page(data_page(0), Page::full(1, 128))
+            .unwrap();
+        let next = faults.count(FaultPoint::PageWrite) + 1;
+        faults.fail_nth(FaultPoint::PageWrite, next, Fault::IoError);
+        assert!(manager
+            .write_page(data_page(1), Page::full(2, 128))
+            .is_err());
+        assert!(is_cached(&manager, 0));
+        assert_eq!(manager.stats().evictions, 0);
+
+        // The page is still dirty, and written back by the next eviction
+        manager
+            .write_page(data_page(1), Page::full(2, 128))
+            .unwrap();
+        manager.flush().unwrap();
+        let contents = std::fs::read(data_path(&temp_dir)).unwrap();
+        assert_eq!(&contents[..128], &[1u8; 128][..]);
+        assert_eq!(&contents[128..], &[2u8; 128][..]);
+    }
+
+    #[test]
+    fn test_flush_skips_clean_pages() {
+        let (temp, manager) = setup_test_manager();
+        let page_size = manager.page_size();
+        manager
+            .write_page(data_page(0), Page::full(1, page_size))
+            .unwrap();
+        manager.flush().unwrap();
+
+        // Overwrite the file behind the manager's back; a clean cached page
+        // must not clobber it on the next flush
+        std::fs::write(data_path(&temp), vec![9u8; page_size]).unwrap();
+        manager.get_page(data_page(0)).unwrap();
+        manager.flush().unwrap();
+        assert_eq!(
+            std::fs::read(data_path(&temp)).unwrap(),
+            vec![9u8; page_size]
+        );
+    }
+
+    #[test]
+    fn test_page_mut_marks_dirty() {
+        let (temp, manager) = setup_test_manager();
+        manager
+            .write_page(data_page(0), Page::zeros(manager.page_size()))
+            .unwrap();
+        manager.flush().unwrap();
+
+        manager
+            .get_page(data_page(0))
+            .unwrap()
+            .page_mut()
+            .write_u32(0, 42)
+            .unwrap();
+        manager.flush().unwrap();
+
+        let new_manager =
+            build(PageManagerBuilder::new(temp.path()).page_size(manager.page_size()));
+        let page = new_manager.get_page(data_page(0)).unwrap();
+        assert_eq!(page.page().read_u32(0).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_pinned_page_is_not_evicted() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .cache_size(2)
+                .shards(1),
+        );
+        manager
+            .write_page(data_page(0), Page::full(1, 128))
+            .unwrap();
+        manager
+            .write_page(data_page(1), Page::full(2, 128))
+            .unwrap();
+
+        // Page 0 is least recently used but pinned, so page 1 is evicted instead
+        let pinned = manager.get_page(data_page(0)).unwrap();
+        manager
+            .write_page(data_page(1), Page::full(3, 128))
+            .unwrap();
+        manager
+            .write_page(data_page(2), Page::full(4, 128))
+            .unwrap();
+        assert!(is_cached(&manager, 0));
+        assert!(!is_cached(&manager, 1));
+        assert_eq!(pinned.page().as_bytes(), &[1u8; 128][..]);
+    }
+
+    #[test]
+    fn test_all_pages_pinned() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .cache_size(1),
+        );
+        manager
+            .write_page(data_page(0), Page::full(1, 128))
+            .unwrap();
+
+        let guard = manager.get_page(data_page(0)).unwrap();
+        assert!(matches!(
+            manager.write_page(data_page(1), Page::full(2, 128)),
+            Err(PageManagerError::NoEvictablePage)
+        ));
+
+        // Dropping the guard unpins the page
+        drop(guard);
+        manager
+            .write_page(data_page(1), Page::full(2, 128))
+            .unwrap();
+        assert!(!is_cached(&manager, 0));
+    }
+
+    #[test]
+    fn test_shards_clamped_to_cache_size() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .cache_size(3)
+                .shards(16),
+        );
+        assert_eq!(manager.pool.shards.len(), 3);
+
+        let result = PageManagerBuilder::new(temp_dir.path()).shards(0).build();
+        assert!(matches!(
+            result,
+            Err(PageManagerError::InvalidShardCount(_))
+        ));
+    }
+
+    #[test]
+    fn test_concurrent_access() {
+        let (temp, manager) = setup_test_manager();
+        let page_size = manager.page_size();
+        let manager = Arc::new(manager);
+
+        let handles: Vec<_> = (0..4u64)
+            .map(|t| {
+                let manager = Arc::clone(&manager);
+                std::thread::spawn(move || {
+                    for i in 0..25u64 {
+                        let page_id = t * 25 + i;
+                        manager
+                            .write_page(data_page(page_id), Page::full(page_id as u8, page_size))
+                            .unwrap();
+                        let page = manager.get_page(data_page(page_id)).unwrap();
+                        assert_eq!(page.page().as_bytes()[0], page_id as u8);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        manager.flush().unwrap();
+
+        let contents = std::fs::read(data_path(&temp)).unwrap();
+        for page_id in 0..100 {
+            assert_eq!(contents[page_id * page_size], page_id as u8);
+        }
+    }
+
+    #[test]
+    fn test_configured_eviction_policy() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .cache_size(2)
+                .shards(1)
+                .eviction_policy(EvictionPolicyKind::LruK),
+        );
+
+        // Page 0 is accessed twice; a scan over pages 1 and 2 must not evict it
+        manager
+            .write_page(data_page(0), Page::full(0, 128))
+            .unwrap();
+        manager.get_page(data_page(0)).unwrap();
+        manager
+            .write_page(data_page(1), Page::full(1, 128))
+            .unwrap();
+        manager
+            .write_page(data_page(2), Page::full(2, 128))
+            .unwrap();
+        assert!(is_cached(&manager, 0));
+        assert!(!is_cached(&manager, 1));
+    }
+
+    #[test]
+    fn test_from_config() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut config = crate::config::Config::default().storage;
+        config.db_path = temp_dir.path().to_string_lossy().into_owned();
+        config.page_size = 512;
+
+        let manager = PageManagerBuilder::from_config(&config).build().unwrap();
+        assert_eq!(manager.page_size(), 512);
+    }
+
+    fn setup_scan_manager(pages: u64, cache_size: usize) -> (TempDir, PageManager) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..pages).flat_map(|i| vec![i as u8; 128]).collect();
+        std::fs::write(data_path(&temp_dir), data).unwrap();
+
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .cache_size(cache_size)
+                .shards(1)
+                .read_ahead(4),
+        );
+        (temp_dir, manager)
+    }
+
+    #[test]
+    fn test_sequential_scan_prefetches_ahead() {
+        let (_temp, manager) = setup_scan_manager(10, 16);
+        for page_id in 0..3 {
+            manager.get_page(data_page(page_id)).unwrap();
+        }
+
+        // Pages 3..7 are loaded by the background worker
+        wait_for(|| (3..7).all(|page_id| is_cached(&manager, page_id)));
+        assert!(!is_cached(&manager, 7));
+    }
+
+    #[test]
+    fn test_scan_does_not_evict_hot_pages() {
+        let (_temp, manager) = setup_scan_manager(40, 4);
+        manager.get_page(data_page(30)).unwrap();
+
+        for page_id in 0..20 {
+            let page = manager.get_page(data_page(page_id)).unwrap();
+            assert_eq!(page.page().as_bytes()[0], page_id as u8);
+        }
+        assert!(is_cached(&manager, 30));
+    }
+
+    #[test]
+    fn test_stats() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .cache_size(2)
+                .shards(1),
+        );
+
+        manager
+            .write_page(data_page(0), Page::full(0, 128))
+            .unwrap();
+        manager
+            .write_page(data_page(1), Page::full(1, 128))
+            .unwrap();
+        let _pinned = manager.get_page(data_page(1)).unwrap();
+        manager
+            .write_page(data_page(2), Page::full(2, 128))
+            .unwrap(); // evicts page 0
+        manager.get_page(data_page(0)).unwrap(); // miss, evicts page 2
+
+        let stats = manager.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.evictions, 2);
+        assert_eq!(stats.write_backs, 2);
+        assert_eq!(stats.capacity, 2);
+        assert_eq!(stats.cached_pages, 2);
+        assert_eq!(stats.dirty_pages, 1);
+        assert_eq!(stats.pinned_pages, 1);
+        assert_eq!(stats.hit_ratio(), 0.5);
+    }
+
+    #[test]
+    fn test_resize_cache() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .cache_size(4)
+                .shards(1),
+        );
+        for page_no in 0..4 {
+            manager
+                .write_page(data_page(page_no), Page::full(page_no as u8, 128))
+                .unwrap();
+        }
+        let _pinned = manager.get_page(data_page(3)).unwrap();
+
+        manager.resize_cache(1).unwrap();
+        let stats = manager.stats();
+        assert_eq!(stats.capacity, 1);
+        assert_eq!(stats.cached_pages, 1);
+        assert_eq!(stats.write_backs, 3);
+        assert!(is_cached(&manager, 3));
+        let result = manager.get_page(data_page(0));
+        assert!(matches!(result, Err(PageManagerError::NoEvictablePage)));
+
+        manager.resize_cache(8).unwrap();
+        assert_eq!(
+            manager.get_page(data_page(0)).unwrap().page().as_bytes()[0],
+            0
+        );
+        assert_eq!(manager.stats().capacity, 8);
+        assert!(manager.resize_cache(0).is_err());
+    }
+
+    fn wait_for(mut condition: impl FnMut() -> bool) {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(std::time::Instant::now() < deadline, "timed out");
+            std::thread::sleep(Duration::from_millis(1));
         }
     }
+
+    #[test]
+    fn test_background_flush() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .background_flush(Some(FlusherConfig {
+                    interval_ms: 1,
+                    dirty_threshold_percent: None,
+                })),
+        );
+
+        manager
+            .write_page(data_page(0), Page::full(5, 128))
+            .unwrap();
+        wait_for(|| std::fs::read(data_path(&temp_dir)).unwrap() == vec![5u8; 128]);
+        assert_eq!(manager.stats().dirty_pages, 0);
+    }
+
+    #[test]
+    fn test_background_flush_threshold() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .cache_size(4)
+                .background_flush(Some(FlusherConfig {
+                    interval_ms: 1,
+                    dirty_threshold_percent: Some(50),
+                })),
+        );
+
+        assert!(!manager.pool.should_flush(Some(50)));
+        manager
+            .write_page(data_page(0), Page::full(1, 128))
+            .unwrap();
+        assert!(!manager.pool.should_flush(Some(50)));
+
+        // Two of four pages dirty reaches the threshold
+        manager
+            .write_page(data_page(1), Page::full(2, 128))
+            .unwrap();
+        wait_for(|| manager.stats().dirty_pages == 0);
+        assert_eq!(std::fs::read(data_path(&temp_dir)).unwrap().len(), 256);
+    }
+
+    #[test]
+    fn test_misaligned_file_is_rejected() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(data_path(&temp_dir), vec![0u8; 200]).unwrap();
+        let result = PageManagerBuilder::new(temp_dir.path())
+            .page_size(128)
+            .build();
+        assert!(matches!(
+            result,
+            Err(PageManagerError::FileManagerError(
+                FileManagerError::PageIOError(PageIOError::MisalignedFile { .. })
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_outdated_database_is_refused() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut superblock =
+            crate::storage::superblock::Superblock::new(128, Compression::None, None, false);
+        superblock.format_version -= 1;
+        std::fs::write(
+            temp_dir.path().join("catalog.fdb"),
+            superblock.encode(128).as_bytes(),
+        )
+        .unwrap();
+
+        let result = PageManagerBuilder::new(temp_dir.path())
+            .page_size(128)
+            .build();
+        assert!(matches!(
+            result,
+            Err(PageManagerError::FileManagerError(
+                FileManagerError::SuperblockError(SuperblockError::OutdatedVersion(_))
+            ))
+        ));
+
+        // No migration to the current version is registered in this build
+        let result = PageManagerBuilder::new(temp_dir.path())
+            .page_size(128)
+            .migration(Some(MigrationMode::InPlace))
+            .build();
+        assert!(matches!(
+            result,
+            Err(PageManagerError::MigrationError(
+                MigrationError::NoMigration(_)
+            ))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_compressed_pages() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let builder = || {
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .compression(Compression::Lz)
+        };
+        let manager = build(builder());
+        assert_eq!(manager.page_size(), 120);
+
+        manager
+            .write_page(data_page(0), Page::full(7, 120))
+            .unwrap();
+        manager.flush().unwrap();
+        let stored = std::fs::read(data_path(&temp_dir)).unwrap();
+        assert_eq!(stored.len(), 128);
+        assert!(!stored[8..].starts_with(&[7u8; 120]));
+        drop(manager);
+
+        let manager = build(builder());
+        let page = manager.get_page(data_page(0)).unwrap();
+        assert_eq!(*page.page(), Page::full(7, 120));
+
+        // The setting is fixed when the database is created
+        let result = PageManagerBuilder::new(temp_dir.path())
+            .page_size(128)
+            .build();
+        assert!(matches!(
+            result,
+            Err(PageManagerError::FileManagerError(
+                FileManagerError::SuperblockError(SuperblockError::CompressionMismatch { .. })
+            ))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encrypted_pages() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let builder = |key: &str| {
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .encryption(Some(EncryptionConfig {
+                    key: Some(key.to_string()),
+                }))
+        };
+        let key = "00".repeat(32);
+        let manager = build(builder(&key));
+        assert_eq!(manager.page_size(), 128 - 36);
+
+        manager.write_page(data_page(0), Page::full(7, 92)).unwrap();
+        manager.flush().unwrap();
+        let stored = std::fs::read(data_path(&temp_dir)).unwrap();
+        assert!(!stored.windows(16).any(|w| w == [7u8; 16]));
+        drop(manager);
+
+        let manager = build(builder(&key));
+        let page = manager.get_page(data_page(0)).unwrap();
+        assert_eq!(*page.page(), Page::full(7, 92));
+        drop(page);
+        drop(manager);
+
+        let result = builder(&"01".repeat(32)).build();
+        assert!(matches!(
+            result,
+            Err(PageManagerError::FileManagerError(
+                FileManagerError::SuperblockError(SuperblockError::WrongKey)
+            ))
+        ));
+        let result = PageManagerBuilder::new(temp_dir.path())
+            .page_size(128)
+            .build();
+        assert!(matches!(
+            result,
+            Err(PageManagerError::FileManagerError(
+                FileManagerError::SuperblockError(SuperblockError::EncryptedDatabase)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_log_is_flushed_before_page() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .cache_size(1)
+                .wal(Some(WalConfig {
+                    segment_size: 1 << 20,
+                    checkpoint_interval_ms: None,
+                    commit_delay_us: None,
+                    retention_ms: None,
+                    archive_dir: None,
+                    full_page_writes: true,
+                })),
+        );
+        let wal = manager.wal().unwrap();
+        // Each page records its LSN in a header
+        assert_eq!(manager.page_size(), 128 - 16);
+
+        let lsn = manager
+            .log_write(TxnId(1), data_page(0), Page::full(1, 112))
+            .unwrap();
+        assert!(wal.flushed() <= lsn);
+        let records: Vec<_> = wal.read_from(lsn).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(
+            records,
+            vec![(
+                lsn,
+                WalRecord::PageWrite {
+                    txn: TxnId(1),
+                    page_id: data_page(0),
+                    before: vec![0; 112],
+                    after: vec![1; 112],
+                }
+            )]
+        );
+
+        // Evicting the page writes it back, which must flush its record first
+        manager
+            .write_page(data_page(1), Page::full(2, 112))
+            .unwrap();
+        assert!(wal.flushed() > lsn);
+        manager.files().flush().unwrap();
+        let stored = std::fs::read(data_path(&temp_dir)).unwrap();
+        assert_eq!(&stored[8..16], &lsn.0.to_be_bytes());
+        assert_eq!(&stored[16..128], &[1; 112]);
+    }
+
+    #[test]
+    fn test_background_checkpoint() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path()).wal(Some(WalConfig {
+                segment_size: 1 << 20,
+                checkpoint_interval_ms: Some(1),
+                commit_delay_us: None,
+                retention_ms: None,
+                archive_dir: None,
+                full_page_writes: true,
+            })),
+        );
+        manager
+            .log_write(TxnId(1), data_page(0), Page::full(1, manager.page_size()))
+            .unwrap();
+        wait_for(|| manager.files().superblock().checkpoint != Lsn::ZERO);
+        wait_for(|| manager.stats().dirty_pages == 0);
+    }
+
+    #[test]
+    fn test_backup_while_writing() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let wal = WalConfig {
+            segment_size: 4096,
+            checkpoint_interval_ms: None,
+            commit_delay_us: None,
+            retention_ms: None,
+            archive_dir: None,
+            full_page_writes: true,
+        };
+        let manager = build(
+            PageManagerBuilder::new(temp_dir.path())
+                .page_size(128)
+                .cache_size(2)
+                .wal(Some(wal.clone())),
+        );
+        let stop = AtomicBool::new(false);
+        let end = thread::scope(|scope| {
+            scope.spawn(|| {
+                // Each transaction sets both pages to the same value
+                for txn in 1.. {
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let log = manager.wal().unwrap();
+                    let value = (txn % 255 + 1) as u8;
+                    for page_no in 0..2 {
+                        manager
+                            .log_write(TxnId(txn), data_page(page_no), Page::full(value, 112))
+                            .unwrap();
+                    }
+                    log.flush(log.append(&WalRecord::commit(TxnId(txn))).unwrap())
+                        .unwrap();
+                }
+            });
+            thread::sleep(Duration::from_millis(5));
+            let end = manager.backup(backup_dir.path()).unwrap();
+            stop.store(true, Ordering::Relaxed);
+            end
+        });
+        assert!(end > Lsn::ZERO);
+
+        let restored = build(
+            PageManagerBuilder::new(backup_dir.path())
+                .page_size(128)
+                .wal(Some(wal)),
+        );
+        let first = restored.get_page(data_page(0)).unwrap().page().as_bytes()[0];
+        let second = restored.get_page(data_page(1)).unwrap().page().as_bytes()[0];
+        assert_eq!(first, second);
+        assert_ne!(first, 0);
+    }
+
+    #[test]
+    fn test_log_write_requires_wal() {
+        let (_temp, manager) = setup_test_manager();
+        assert!(manager.wal().is_none());
+        assert!(matches!(
+            manager.log_write(TxnId(1), data_page(0), Page::full(1, 128)),
+            Err(PageManagerError::WalDisabled)
+        ));
+        assert!(matches!(
+            manager.checkpoint(),
+            Err(PageManagerError::WalDisabled)
+        ));
+    }
+
+    #[test]
+    fn test_pages_of_different_files() {
+        let (temp, manager) = setup_test_manager();
+        let file_id = manager.create_file().unwrap();
+        manager
+            .write_page(data_page(0), Page::full(1, 128))
+            .unwrap();
+        manager
+            .write_page(PageId::new(file_id, 0), Page::full(2, 128))
+            .unwrap();
+        manager.flush().unwrap();
+
+        assert_eq!(std::fs::read(data_path(&temp)).unwrap(), vec![1u8; 128]);
+        assert_eq!(
+            std::fs::read(manager.files().path(file_id)).unwrap(),
+            vec![2u8; 128]
+        );
+    }
+
+    #[test]
+    fn test_drop_file_discards_cached_pages() {
+        let (_temp, manager) = setup_test_manager();
+        let file_id = manager.create_file().unwrap();
+        let page_id = PageId::new(file_id, 0);
+        manager.write_page(page_id, Page::full(1, 128)).unwrap();
+
+        let guard = manager.get_page(page_id).unwrap();
+        assert!(matches!(
+            manager.drop_file(file_id),
+            Err(PageManagerError::PagePinned(id)) if id == page_id
+        ));
+        drop(guard);
+
+        let path = manager.files().path(file_id);
+        manager.drop_file(file_id).unwrap();
+        assert!(!path.exists());
+        assert_eq!(manager.stats().cached_pages, 0);
+        assert!(matches!(
+            manager.get_page(page_id),
+            Err(PageManagerError::FileManagerError(
+                FileManagerError::FileNotFound(_)
+            ))
+        ));
+
+        // Nothing is left to write back for the dropped file
+        manager.flush().unwrap();
+    }
 }
@@ -0,0 +1,149 @@
+use super::page::Page;
+use super::page_io::{PageIO, PageIOError};
+
+/// Where the pages of one file are kept: on disk through `PageIO`, or in
+/// a `MemoryPageStore` for a database that never touches the disk.
+pub trait PageStore: Send {
+    /// Check that the store holds a whole number of pages, returning how
+    /// many, and that `page_size` suits it.
+    fn validate_length(&self, page_size: usize) -> Result<u64, PageIOError>;
+
+    fn read_page(&mut self, page_id: u64, page_size: usize) -> Result<Page, PageIOError>;
+
+    fn write_page(
+        &mut self,
+        page_id: u64,
+        page_size: usize,
+        page: &Page,
+    ) -> Result<(), PageIOError>;
+
+    /// Give up the space behind the unused tail of a page, keeping the
+    /// first `used` bytes. The tail reads back as zeros.
+    fn punch_hole(
+        &mut self,
+        page_id: u64,
+        page_size: usize,
+        used: usize,
+    ) -> Result<(), PageIOError>;
+
+    /// Push written pages as far as the store's durability requires.
+    fn flush(&mut self) -> Result<(), PageIOError>;
+}
+
+impl PageStore for PageIO {
+    fn validate_length(&self, page_size: usize) -> Result<u64, PageIOError> {
+        PageIO::validate_length(self, page_size)
+    }
+
+    fn read_page(&mut self, page_id: u64, page_size: usize) -> Result<Page, PageIOError> {
+        PageIO::read_page(self, page_id, page_size)
+    }
+
+    fn write_page(
+        &mut self,
+        page_id: u64,
+        page_size: usize,
+        page: &Page,
+    ) -> Result<(), PageIOError> {
+        PageIO::write_page(self, page_id, page_size, page)
+    }
+
+    fn punch_hole(
+        &mut self,
+        page_id: u64,
+        page_size: usize,
+        used: usize,
+    ) -> Result<(), PageIOError> {
+        PageIO::punch_hole(self, page_id, page_size, used)
+    }
+
+    fn flush(&mut self) -> Result<(), PageIOError> {
+        PageIO::flush(self)
+    }
+}
+
+/// A file's pages held in memory, back to back as they would be on disk,
+/// and lost once it is dropped.
+#[derive(Debug, Default)]
+pub struct MemoryPageStore {
+    data: Vec<u8>,
+}
+
+impl PageStore for MemoryPageStore {
+    fn validate_length(&self, page_size: usize) -> Result<u64, PageIOError> {
+        let len = self.data.len() as u64;
+        if !len.is_multiple_of(page_size as u64) {
+            return Err(PageIOError::MisalignedFile { len, page_size });
+        }
+        Ok(len / page_size as u64)
+    }
+
+    fn read_page(&mut self, page_id: u64, page_size: usize) -> Result<Page, PageIOError> {
+        let offset = page_id as usize * page_size;
+        match self.data.get(offset..offset + page_size) {
+            Some(data) => Ok(Page::new(data.to_vec())),
+            None => Err(PageIOError::PageNotFound(page_id)),
+        }
+    }
+
+    fn write_page(
+        &mut self,
+        page_id: u64,
+        page_size: usize,
+        page: &Page,
+    ) -> Result<(), PageIOError> {
+        let offset = page_id as usize * page_size;
+        if self.data.len() < offset + page_size {
+            self.data.resize(offset + page_size, 0);
+        }
+        self.data[offset..offset + page_size].copy_from_slice(page.as_bytes());
+        Ok(())
+    }
+
+    fn punch_hole(
+        &mut self,
+        page_id: u64,
+        page_size: usize,
+        used: usize,
+    ) -> Result<(), PageIOError> {
+        let offset = page_id as usize * page_size;
+        if let Some(tail) = self.data.get_mut(offset + used..offset + page_size) {
+            tail.fill(0);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), PageIOError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_page_store() {
+        let mut store = MemoryPageStore::default();
+        assert_eq!(store.validate_length(64).unwrap(), 0);
+        assert!(matches!(
+            store.read_page(0, 64),
+            Err(PageIOError::PageNotFound(0))
+        ));
+
+        // Writing past the end fills the gap with zeroed pages
+        store.write_page(2, 64, &Page::new(vec![7; 64])).unwrap();
+        assert_eq!(store.validate_length(64).unwrap(), 3);
+        assert_eq!(store.read_page(1, 64).unwrap(), Page::zeros(64));
+        assert_eq!(store.read_page(2, 64).unwrap(), Page::new(vec![7; 64]));
+
+        store.punch_hole(2, 64, 16).unwrap();
+        let mut expected = vec![7; 16];
+        expected.resize(64, 0);
+        assert_eq!(store.read_page(2, 64).unwrap(), Page::new(expected));
+        assert!(matches!(
+            store.validate_length(100),
+            Err(PageIOError::MisalignedFile { len: 192, .. })
+        ));
+    }
+}
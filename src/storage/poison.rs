@@ -0,0 +1,44 @@
+use std::any::Any;
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Take a lock even if a thread panicked while holding it.
+///
+/// The buffer pool, files, locks and transactions are left usable at any
+/// point a panic can unwind through them, and the pages a failed
+/// transaction half changed are put back by rolling it back through the
+/// log. So a query that panics fails on its own, rather than poisoning
+/// its locks and with them every session that comes after.
+pub(crate) trait LockRecover<T: ?Sized> {
+    fn lock_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T: ?Sized> LockRecover<T> for Mutex<T> {
+    fn lock_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// `LockRecover` for both halves of a `RwLock`.
+pub(super) trait RwLockRecover<T: ?Sized> {
+    fn read_recover(&self) -> RwLockReadGuard<'_, T>;
+
+    fn write_recover(&self) -> RwLockWriteGuard<'_, T>;
+}
+
+impl<T: ?Sized> RwLockRecover<T> for RwLock<T> {
+    fn read_recover(&self) -> RwLockReadGuard<'_, T> {
+        self.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_recover(&self) -> RwLockWriteGuard<'_, T> {
+        self.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// What a caught panic said, if it said it with a string.
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<String>() {
+        Some(message) => message.as_str(),
+        None => panic.downcast_ref::<&str>().copied().unwrap_or_default(),
+    }
+}
@@ -0,0 +1,123 @@
+use super::page::PageId;
+use std::ops::Range;
+
+/// Consecutive page ids needed before accesses count as a sequential scan.
+const SEQUENTIAL_RUN: u64 = 2;
+
+/// Watches the stream of page requests for ascending runs within a file, and
+/// tells the caller which pages to read ahead once a run looks like a
+/// sequential scan.
+pub struct ScanDetector {
+    read_ahead: u64,
+    last: Option<PageId>,
+    run: u64,
+    prefetched_to: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ScanAccess {
+    /// The access continues a sequential scan
+    pub sequential: bool,
+    /// Page numbers, in the accessed page's file, that should be loaded ahead
+    /// of the scan
+    pub prefetch: Range<u64>,
+}
+
+impl ScanDetector {
+    pub fn new(read_ahead: usize) -> Self {
+        Self {
+            read_ahead: read_ahead as u64,
+            last: None,
+            run: 0,
+            prefetched_to: 0,
+        }
+    }
+
+    pub fn record(&mut self, page_id: PageId) -> ScanAccess {
+        match self.last {
+            // Re-reading the same page neither extends nor breaks a run
+            Some(last) if last == page_id => {}
+            Some(last) if last.file == page_id.file && last.page_no + 1 == page_id.page_no => {
+                self.run += 1
+            }
+            _ => {
+                self.run = 0;
+                self.prefetched_to = 0;
+            }
+        }
+        self.last = Some(page_id);
+
+        let sequential = self.run >= SEQUENTIAL_RUN;
+        let mut prefetch = 0..0;
+        if sequential {
+            let start = self.prefetched_to.max(page_id.page_no + 1);
+            let end = page_id.page_no + 1 + self.read_ahead;
+            if start < end {
+                prefetch = start..end;
+                self.prefetched_to = end;
+            }
+        }
+        ScanAccess {
+            sequential,
+            prefetch,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::file_manager::FileId;
+
+    fn page(page_no: u64) -> PageId {
+        PageId::new(FileId(1), page_no)
+    }
+
+    #[test]
+    fn test_random_access_is_not_a_scan() {
+        let mut detector = ScanDetector::new(4);
+        for page_no in [5, 1, 9, 2] {
+            let access = detector.record(page(page_no));
+            assert!(!access.sequential);
+            assert!(access.prefetch.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_sequential_run_prefetches_ahead() {
+        let mut detector = ScanDetector::new(4);
+        assert!(!detector.record(page(10)).sequential);
+        assert!(!detector.record(page(11)).sequential);
+        assert_eq!(
+            detector.record(page(12)),
+            ScanAccess {
+                sequential: true,
+                prefetch: 13..17,
+            }
+        );
+
+        // Only pages not already requested are prefetched
+        assert_eq!(detector.record(page(13)).prefetch, 17..18);
+        assert_eq!(detector.record(page(13)).prefetch, 0..0);
+    }
+
+    #[test]
+    fn test_jump_resets_run() {
+        let mut detector = ScanDetector::new(4);
+        for page_no in 0..3 {
+            detector.record(page(page_no));
+        }
+        assert!(!detector.record(page(50)).sequential);
+        assert!(!detector.record(page(51)).sequential);
+        assert_eq!(detector.record(page(52)).prefetch, 53..57);
+    }
+
+    #[test]
+    fn test_switching_files_resets_run() {
+        let mut detector = ScanDetector::new(4);
+        for page_no in 0..3 {
+            detector.record(page(page_no));
+        }
+        assert!(!detector.record(PageId::new(FileId(2), 3)).sequential);
+    }
+}
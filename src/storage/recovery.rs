@@ -0,0 +1,441 @@
+use super::file_manager::FileManagerError;
+use super::lock_manager::{LockMode, LockTarget};
+use super::page::{Page, PageId};
+use super::page_manager::{PageManager, PageManagerError};
+use super::wal::{Lsn, TxnId, WalError, WalRecord};
+use std::collections::HashMap;
+
+/// What recovery found and did when a database was opened.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Logged changes reapplied to pages that were missing them
+    pub redone: usize,
+    /// Changes rolled back from transactions that never finished
+    pub undone: usize,
+    /// The transactions that were rolled back, in id order
+    pub losers: Vec<TxnId>,
+    /// Where in the log recovery started reading
+    pub start: Lsn,
+    /// The highest transaction id found in the log
+    pub last_txn: TxnId,
+    /// Transactions prepared but not yet committed or rolled back, which
+    /// are left as they are, in id order
+    pub prepared: Vec<PreparedTxn>,
+}
+
+/// A prepared transaction found by recovery, still waiting to be committed
+/// or rolled back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreparedTxn {
+    pub txn: TxnId,
+    pub gid: String,
+    /// The LSN of its first record
+    pub first: Lsn,
+    /// The locks it held when prepared, which it still needs
+    pub locks: Vec<(LockTarget, LockMode)>,
+}
+
+/// A change made by a transaction that hasn't finished yet.
+struct Change {
+    lsn: Lsn,
+    page_id: PageId,
+    before: Vec<u8>,
+}
+
+/// Bring the pages of a logged database back to a consistent state after a
+/// crash, in the style of ARIES.
+///
+/// Redo replays the log from the last checkpoint, or from the start if there
+/// is none, reapplying every change a page is missing, as shown by its LSN.
+/// Losers' changes are repeated too, so undo starts from exactly the state
+/// at the crash. With full page writes, the
+/// first change to each page is redone by replacing the page whole, as the
+/// log holds it, whatever LSN the page has: a page torn as it was written
+/// out may have its new LSN and some of its old bytes. Undo then rolls back the
+/// transactions that neither committed nor aborted, newest change first,
+/// except those that were prepared: the decision on those belongs to
+/// whoever prepared them.
+/// Undo logs its own changes and ends each loser with an abort record, so a
+/// crash during recovery is itself recovered from, and an aborted
+/// transaction is never undone twice. Without `undo`, as on a replica whose
+/// unfinished transactions may still commit on its primary, recovery stops
+/// after redo.
+pub fn recover(pages: &PageManager, undo: bool) -> Result<RecoveryReport, PageManagerError> {
+    let mut report = RecoveryReport::default();
+    let wal = match pages.wal() {
+        Some(wal) => wal,
+        None => return Ok(report),
+    };
+
+    let checkpoint = pages.files().superblock().checkpoint;
+    let start = if checkpoint == Lsn::ZERO {
+        Lsn::ZERO
+    } else {
+        let (lsn, record) = wal
+            .read_from(checkpoint)?
+            .next()
+            .ok_or(WalError::Corrupted(checkpoint))??;
+        record
+            .recovery_start()
+            .filter(|_| lsn == checkpoint)
+            .ok_or(WalError::Corrupted(checkpoint))?
+    };
+    report.start = start;
+
+    let mut open: HashMap<TxnId, Vec<Change>> = HashMap::new();
+    let mut first: HashMap<TxnId, Lsn> = HashMap::new();
+    let mut prepared = HashMap::new();
+    // The LSNs pages replaced whole had on disk
+    let mut replaced: HashMap<PageId, Lsn> = HashMap::new();
+    for entry in wal.read_from(start)? {
+        let (lsn, record) = entry?;
+        if let Some(txn) = record.txn() {
+            report.last_txn = report.last_txn.max(txn);
+            first.entry(txn).or_insert(lsn);
+        }
+        match record {
+            WalRecord::Begin { txn } => {
+                open.entry(txn).or_default();
+            }
+            WalRecord::Commit { txn, .. } | WalRecord::Abort { txn } => {
+                open.remove(&txn);
+                prepared.remove(&txn);
+            }
+            WalRecord::Prepare { txn, gid, locks } => {
+                prepared.insert(txn, (gid, locks));
+            }
+            WalRecord::PageWrite {
+                txn,
+                page_id,
+                before,
+                after,
+            } => {
+                let whole = wal.full_page_writes() && !replaced.contains_key(&page_id);
+                if whole {
+                    let stored = skip_dropped(pages.stored_lsn(page_id))?;
+                    replaced.insert(page_id, stored.unwrap_or(Lsn::ZERO));
+                }
+                let redone = skip_dropped(pages.redo(page_id, Page::new(after), lsn, whole))?;
+                // Replacing a page doesn't mean it was missing the change
+                let missing = match replaced.get(&page_id) {
+                    Some(&stored) => redone.is_some() && lsn > stored,
+                    None => redone == Some(true),
+                };
+                if missing {
+                    report.redone += 1;
+                }
+                open.entry(txn).or_default().push(Change {
+                    lsn,
+                    page_id,
+                    before,
+                });
+            }
+            WalRecord::Checkpoint { last_txn, .. } => {
+                report.last_txn = report.last_txn.max(last_txn);
+            }
+        }
+    }
+    wal.reserve_txns(report.last_txn);
+
+    for (txn, (gid, locks)) in prepared {
+        open.remove(&txn);
+        let first = first[&txn];
+        wal.resume_txn(txn, first);
+        report.prepared.push(PreparedTxn {
+            txn,
+            gid,
+            first,
+            locks,
+        });
+    }
+    report.prepared.sort_by_key(|prepared| prepared.txn);
+    if !undo {
+        return Ok(report);
+    }
+
+    let mut undo: Vec<_> = open
+        .iter()
+        .flat_map(|(&txn, changes)| changes.iter().map(move |change| (txn, change)))
+        .collect();
+    undo.sort_by_key(|(_, change)| std::cmp::Reverse(change.lsn));
+    for (txn, change) in undo {
+        let page = Page::new(change.before.clone());
+        if skip_dropped(pages.log_write(txn, change.page_id, page))?.is_some() {
+            report.undone += 1;
+        }
+    }
+
+    let mut losers: Vec<_> = open.into_keys().collect();
+    losers.sort();
+    for &txn in &losers {
+        wal.append(&WalRecord::Abort { txn })?;
+    }
+    wal.flush_all()?;
+    report.losers = losers;
+    Ok(report)
+}
+
+/// Changes to a file that was dropped later in the log have nothing left to
+/// apply to.
+fn skip_dropped<T>(result: Result<T, PageManagerError>) -> Result<Option<T>, PageManagerError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(PageManagerError::FileManagerError(FileManagerError::FileNotFound(_))) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WalConfig;
+    use crate::storage::file_manager::{FileId, FileManager};
+    use crate::storage::page_manager::PageManagerBuilder;
+    use std::os::unix::fs::FileExt;
+    use std::path::Path;
+
+    const PAGE_SIZE: usize = 128;
+
+    fn open(dir: &Path) -> PageManager {
+        open_with_segments(dir, 1 << 20)
+    }
+
+    fn open_with_segments(dir: &Path, segment_size: u64) -> PageManager {
+        let manager = PageManagerBuilder::new(dir)
+            .page_size(PAGE_SIZE)
+            .wal(Some(WalConfig {
+                segment_size,
+                checkpoint_interval_ms: None,
+                commit_delay_us: None,
+                retention_ms: None,
+                archive_dir: None,
+                full_page_writes: true,
+            }))
+            .build()
+            .unwrap();
+        if manager.files().file(FileId(1)).is_err() {
+            manager.create_file().unwrap();
+        }
+        manager
+    }
+
+    fn page(page_no: u64) -> PageId {
+        PageId::new(FileId(1), page_no)
+    }
+
+    fn full(manager: &PageManager, value: u8) -> Page {
+        Page::full(value, manager.page_size())
+    }
+
+    fn read(manager: &PageManager, page_no: u64) -> Page {
+        let guard = manager.get_page(page(page_no)).unwrap();
+        let bytes = guard.page().as_bytes().to_vec();
+        Page::new(bytes)
+    }
+
+    fn commit(manager: &PageManager, txn: TxnId) {
+        let wal = manager.wal().unwrap();
+        let lsn = wal.append(&WalRecord::commit(txn)).unwrap();
+        wal.flush(lsn).unwrap();
+    }
+
+    /// Dropping a manager without flushing loses its dirty pages, just as a
+    /// crash would.
+    fn crash(manager: PageManager) {
+        drop(manager);
+    }
+
+    #[test]
+    fn test_committed_changes_are_redone() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        manager
+            .log_write(TxnId(1), page(0), full(&manager, 1))
+            .unwrap();
+        manager
+            .log_write(TxnId(1), page(1), full(&manager, 2))
+            .unwrap();
+        commit(&manager, TxnId(1));
+        crash(manager);
+
+        let manager = open(dir.path());
+        assert_eq!(manager.recovery().redone, 2);
+        assert!(manager.recovery().losers.is_empty());
+        assert_eq!(read(&manager, 0), full(&manager, 1));
+        assert_eq!(read(&manager, 1), full(&manager, 2));
+    }
+
+    #[test]
+    fn test_unfinished_transactions_are_undone() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        manager
+            .log_write(TxnId(1), page(0), full(&manager, 1))
+            .unwrap();
+        commit(&manager, TxnId(1));
+        manager
+            .log_write(TxnId(2), page(0), full(&manager, 2))
+            .unwrap();
+        manager
+            .log_write(TxnId(2), page(1), full(&manager, 3))
+            .unwrap();
+        // The loser's pages reach disk before the crash
+        manager.flush().unwrap();
+        crash(manager);
+
+        let manager = open(dir.path());
+        assert_eq!(manager.recovery().losers, vec![TxnId(2)]);
+        assert_eq!(manager.recovery().undone, 2);
+        assert_eq!(read(&manager, 0), full(&manager, 1));
+        assert_eq!(read(&manager, 1), full(&manager, 0));
+        crash(manager);
+
+        // The rollback was logged, so it isn't repeated
+        let manager = open(dir.path());
+        assert!(manager.recovery().losers.is_empty());
+        assert_eq!(manager.recovery().undone, 0);
+        assert_eq!(read(&manager, 0), full(&manager, 1));
+        assert_eq!(read(&manager, 1), full(&manager, 0));
+    }
+
+    #[test]
+    fn test_pages_already_on_disk_are_not_redone() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        manager
+            .log_write(TxnId(1), page(0), full(&manager, 1))
+            .unwrap();
+        manager
+            .log_write(TxnId(1), page(0), full(&manager, 2))
+            .unwrap();
+        commit(&manager, TxnId(1));
+        manager.flush().unwrap();
+        manager
+            .log_write(TxnId(2), page(1), full(&manager, 3))
+            .unwrap();
+        commit(&manager, TxnId(2));
+        crash(manager);
+
+        // Only the change made after the flush is missing from disk
+        let manager = open(dir.path());
+        assert_eq!(manager.recovery().redone, 1);
+        assert_eq!(read(&manager, 0), full(&manager, 2));
+        assert_eq!(read(&manager, 1), full(&manager, 3));
+    }
+
+    #[test]
+    fn test_torn_page_is_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        manager
+            .log_write(TxnId(1), page(0), full(&manager, 1))
+            .unwrap();
+        commit(&manager, TxnId(1));
+        manager.checkpoint().unwrap();
+        manager
+            .log_write(TxnId(2), page(0), full(&manager, 2))
+            .unwrap();
+        commit(&manager, TxnId(2));
+        manager.flush().unwrap();
+        crash(manager);
+
+        // The page's new header made it to disk, but not its second half
+        let path = FileManager::file_path(dir.path(), FileId(1));
+        let file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+        let half = PAGE_SIZE / 2;
+        file.write_all_at(&vec![1; half], half as u64).unwrap();
+
+        let manager = open(dir.path());
+        assert_eq!(read(&manager, 0), full(&manager, 2));
+    }
+
+    #[test]
+    fn test_changes_to_dropped_files_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        let file_id = manager.create_file().unwrap();
+        let dropped = PageId::new(file_id, 0);
+        manager
+            .log_write(TxnId(1), dropped, full(&manager, 1))
+            .unwrap();
+        manager
+            .log_write(TxnId(2), dropped, full(&manager, 2))
+            .unwrap();
+        commit(&manager, TxnId(1));
+        manager.drop_file(file_id).unwrap();
+        crash(manager);
+
+        let manager = open(dir.path());
+        assert_eq!(manager.recovery().redone, 0);
+        assert_eq!(manager.recovery().undone, 0);
+        assert_eq!(manager.recovery().losers, vec![TxnId(2)]);
+    }
+
+    #[test]
+    fn test_recovery_starts_at_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        manager
+            .log_write(TxnId(1), page(0), full(&manager, 1))
+            .unwrap();
+        commit(&manager, TxnId(1));
+        let redo_from = manager.wal().unwrap().end();
+        manager.checkpoint().unwrap();
+        manager
+            .log_write(TxnId(2), page(1), full(&manager, 2))
+            .unwrap();
+        commit(&manager, TxnId(2));
+        crash(manager);
+
+        let manager = open(dir.path());
+        assert_eq!(manager.recovery().start, redo_from);
+        assert_eq!(manager.recovery().redone, 1);
+        assert_eq!(read(&manager, 0), full(&manager, 1));
+        assert_eq!(read(&manager, 1), full(&manager, 2));
+    }
+
+    #[test]
+    fn test_checkpoint_keeps_running_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        let first = manager
+            .log_write(TxnId(2), page(0), full(&manager, 2))
+            .unwrap();
+        manager
+            .log_write(TxnId(1), page(1), full(&manager, 1))
+            .unwrap();
+        commit(&manager, TxnId(1));
+        manager.checkpoint().unwrap();
+        crash(manager);
+
+        // Transaction 2's change precedes the checkpoint, but is still undone
+        let manager = open(dir.path());
+        assert_eq!(manager.recovery().start, first);
+        assert_eq!(manager.recovery().losers, vec![TxnId(2)]);
+        assert_eq!(read(&manager, 0), full(&manager, 0));
+        assert_eq!(read(&manager, 1), full(&manager, 1));
+    }
+
+    #[test]
+    fn test_checkpoint_removes_old_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal_dir = crate::storage::wal::Wal::dir(dir.path());
+        let segments = || std::fs::read_dir(&wal_dir).unwrap().count();
+
+        let manager = open_with_segments(dir.path(), 1024);
+        for i in 0..20 {
+            manager
+                .log_write(TxnId(i), page(i), full(&manager, i as u8))
+                .unwrap();
+            commit(&manager, TxnId(i));
+        }
+        assert!(segments() > 5);
+        manager.checkpoint().unwrap();
+        assert!(segments() <= 2);
+        crash(manager);
+
+        let manager = open_with_segments(dir.path(), 1024);
+        assert_eq!(manager.recovery().redone, 0);
+        assert_eq!(read(&manager, 19), full(&manager, 19));
+    }
+}
@@ -0,0 +1,336 @@
+use super::page_manager::PageManagerError;
+use super::wal::{Lsn, Wal, WalError, WalRecord};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use thiserror::Error;
+
+/// LSN and payload length ahead of each streamed record.
+const FRAME_HEADER_SIZE: usize = 12;
+
+#[derive(Debug, Error)]
+pub enum ReplicationError {
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("WAL error: {0}")]
+    WalError(#[from] WalError),
+
+    #[error("Page manager error: {0}")]
+    PageManagerError(#[from] PageManagerError),
+
+    #[error("The log from LSN {0} has already been removed")]
+    LogRemoved(Lsn),
+}
+
+/// Streams the log to replicas.
+///
+/// A replica connects and sends the LSN to start from, the end of its own
+/// log; from then on it is sent each record as it becomes durable, as its
+/// LSN, length and encoding. Replication is asynchronous: the primary never
+/// waits for a replica, so one that falls behind catches up on its own, as
+/// long as the log it needs hasn't been removed. Retaining log for longer
+/// gives it more time.
+pub struct WalSender {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl WalSender {
+    /// Accept replicas on `addr`, checking for newly flushed log every
+    /// `poll`.
+    pub fn spawn(wal: Arc<Wal>, addr: impl ToSocketAddrs, poll: Duration) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut replicas: Vec<JoinHandle<()>> = Vec::new();
+                while !stop.load(Ordering::Acquire) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let (wal, stop) = (wal.clone(), stop.clone());
+                            replicas.push(thread::spawn(move || {
+                                // A replica that is lost reconnects when it can
+                                let _ = send(&wal, stream, &stop, poll);
+                            }));
+                        }
+                        Err(_) => thread::sleep(poll),
+                    }
+                    replicas.retain(|replica| !replica.is_finished());
+                }
+                for replica in replicas {
+                    let _ = replica.join();
+                }
+            })
+        };
+        Ok(Self {
+            addr,
+            stop,
+            worker: Some(worker),
+        })
+    }
+
+    /// The address replicas connect to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for WalSender {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Send one replica the log from the LSN it asks for until it goes away or
+/// the sender stops.
+fn send(
+    wal: &Wal,
+    stream: TcpStream,
+    stop: &AtomicBool,
+    poll: Duration,
+) -> Result<(), ReplicationError> {
+    stream.set_nonblocking(false)?;
+    let mut next = Lsn((&stream).read_u64::<BigEndian>()?);
+    if next < wal.history_start()? {
+        return Err(ReplicationError::LogRemoved(next));
+    }
+    let mut out = BufWriter::new(&stream);
+    while !stop.load(Ordering::Acquire) {
+        let flushed = wal.flushed();
+        if next < flushed {
+            for entry in wal.read_from(next)? {
+                let (lsn, record) = entry?;
+                if lsn >= flushed {
+                    break;
+                }
+                let payload = record.encode();
+                out.write_u64::<BigEndian>(lsn.0)?;
+                out.write_u32::<BigEndian>(payload.len() as u32)?;
+                out.write_all(&payload)?;
+                next = Lsn(lsn.0 + 1);
+            }
+            out.flush()?;
+        }
+        thread::sleep(poll);
+    }
+    Ok(())
+}
+
+/// Follows a primary's log, handing each record received to `apply`.
+///
+/// It connects to the primary, asks for the log from `next`, and applies
+/// records as they arrive. A lost connection is retried every poll
+/// interval, from wherever `next` then says.
+pub struct WalReceiver {
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl WalReceiver {
+    pub fn spawn<N, A>(primary: String, poll: Duration, next: N, mut apply: A) -> Self
+    where
+        N: Fn() -> Lsn + Send + 'static,
+        A: FnMut(Lsn, WalRecord) -> Result<(), PageManagerError> + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                while !stop.load(Ordering::Acquire) {
+                    // Whatever went wrong, start over from the log so far
+                    if receive(&primary, next(), &mut apply, &stop, poll).is_err() {
+                        thread::sleep(poll);
+                    }
+                }
+            })
+        };
+        Self {
+            stop,
+            worker: Some(worker),
+        }
+    }
+}
+
+impl Drop for WalReceiver {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Receive and apply the log from `from` until the connection is lost or
+/// the receiver stops.
+fn receive(
+    primary: &str,
+    from: Lsn,
+    apply: &mut impl FnMut(Lsn, WalRecord) -> Result<(), PageManagerError>,
+    stop: &AtomicBool,
+    poll: Duration,
+) -> Result<(), ReplicationError> {
+    let addr = primary
+        .to_socket_addrs()?
+        .next()
+        .ok_or(io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+    let mut stream = TcpStream::connect_timeout(&addr, poll.max(Duration::from_millis(10)))?;
+    // Wake up now and then to check whether to stop
+    stream.set_read_timeout(Some(poll))?;
+    stream.write_u64::<BigEndian>(from.0)?;
+
+    let mut buffer = Vec::new();
+    let mut chunk = vec![0; 64 * 1024];
+    while !stop.load(Ordering::Acquire) {
+        match stream.read(&mut chunk) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(len) => buffer.extend_from_slice(&chunk[..len]),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e.into()),
+        }
+        // Frames can arrive split across reads
+        let mut used = 0;
+        while let Some(header) = buffer.get(used..used + FRAME_HEADER_SIZE) {
+            let lsn = Lsn(BigEndian::read_u64(&header[..8]));
+            let len = BigEndian::read_u32(&header[8..]) as usize;
+            let Some(payload) =
+                buffer.get(used + FRAME_HEADER_SIZE..used + FRAME_HEADER_SIZE + len)
+            else {
+                break;
+            };
+            let record = WalRecord::decode(payload).map_err(|_| WalError::Corrupted(lsn))?;
+            apply(lsn, record)?;
+            used += FRAME_HEADER_SIZE + len;
+        }
+        buffer.drain(..used);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ReplicationConfig, WalConfig};
+    use crate::storage::file_manager::FileId;
+    use crate::storage::page::{Page, PageId};
+    use crate::storage::page_manager::{PageManager, PageManagerBuilder};
+    use crate::storage::wal::TxnId;
+    use std::path::Path;
+    use std::time::Instant;
+
+    fn open(dir: &Path, listen: Option<&str>, primary: Option<String>) -> PageManager {
+        PageManagerBuilder::new(dir)
+            .page_size(128)
+            .wal(Some(WalConfig {
+                segment_size: 512,
+                checkpoint_interval_ms: None,
+                commit_delay_us: None,
+                // Long enough for the replica to catch up
+                retention_ms: Some(60_000),
+                archive_dir: None,
+                full_page_writes: true,
+            }))
+            .replication(Some(ReplicationConfig {
+                listen: listen.map(str::to_string),
+                primary,
+                poll_interval_ms: 1,
+            }))
+            .build()
+            .unwrap()
+    }
+
+    fn page(file: u32, page_no: u64) -> PageId {
+        PageId {
+            file: FileId(file),
+            page_no,
+        }
+    }
+
+    fn write(pages: &PageManager, txn: u64, page_id: PageId, value: u8, commit: bool) {
+        let wal = pages.wal().unwrap();
+        wal.append(&WalRecord::Begin { txn: TxnId(txn) }).unwrap();
+        pages
+            .log_write(TxnId(txn), page_id, Page::full(value, 112))
+            .unwrap();
+        if commit {
+            wal.append(&WalRecord::commit(TxnId(txn))).unwrap();
+        }
+        wal.flush_all().unwrap();
+    }
+
+    fn value(pages: &PageManager, page_id: PageId) -> u8 {
+        pages.get_page(page_id).unwrap().page().as_bytes()[0]
+    }
+
+    fn wait_for(mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_replica_follows_primary() {
+        let (primary_dir, replica_dir) =
+            (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let primary = open(primary_dir.path(), Some("127.0.0.1:0"), None);
+        primary.create_file().unwrap();
+        write(&primary, 1, page(1, 0), 1, true);
+        primary.backup(replica_dir.path()).unwrap();
+
+        let addr = primary.replication_addr().unwrap().to_string();
+        let mut replica = open(replica_dir.path(), None, Some(addr));
+        assert!(replica.is_replica());
+        write(&primary, 2, page(1, 0), 2, true);
+        // A file created since the backup arrives with its first page
+        primary.create_file().unwrap();
+        write(&primary, 3, page(2, 0), 3, true);
+        write(&primary, 4, page(1, 1), 4, false);
+        let checkpoint = primary.checkpoint().unwrap();
+        let caught_up = primary.wal().unwrap().flushed();
+        wait_for(|| {
+            replica.wal().unwrap().end() == caught_up
+                && replica.files().superblock().checkpoint == checkpoint
+        });
+
+        assert_eq!(value(&replica, page(1, 0)), 2);
+        assert_eq!(value(&replica, page(2, 0)), 3);
+        // Changes that haven't committed are visible too
+        assert_eq!(value(&replica, page(1, 1)), 4);
+        assert!(matches!(
+            replica.log_write(TxnId(5), page(1, 0), Page::full(5, 112)),
+            Err(PageManagerError::WalError(WalError::ReadOnly))
+        ));
+
+        drop(primary);
+        replica.promote().unwrap();
+        assert!(!replica.is_replica());
+        assert_eq!(replica.recovery().losers, vec![TxnId(4)]);
+        assert_eq!(value(&replica, page(1, 1)), 0);
+        write(&replica, 5, page(1, 0), 5, true);
+        assert_eq!(value(&replica, page(1, 0)), 5);
+        assert!(matches!(
+            replica.promote(),
+            Err(PageManagerError::NotReplica)
+        ));
+    }
+}
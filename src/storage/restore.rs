@@ -0,0 +1,168 @@
+use super::history::AsOf;
+use super::page_manager::PageManagerError;
+use super::wal::{micros_since_epoch, Lsn, Wal, WalError, WalRecord};
+use std::fs;
+use std::path::Path;
+
+/// Prepare the log of a database restored from a backup for recovery to a
+/// point in time.
+///
+/// Archived segments the log directory lacks, or holds less of, are copied
+/// in, then every record from `target` on is discarded: with a time, from
+/// the first commit after it. Recovery then redoes the rest from the
+/// backup's checkpoint and rolls back whatever hadn't committed by the
+/// target. Without a target the whole archive is replayed. The backup's
+/// pages may already hold changes up to the end of its own log, so a
+/// target before that can't be reached and fails with
+/// `HistoryUnavailable`.
+///
+/// The restored database starts a new history from the target, so it
+/// should archive to a fresh directory: the old one still holds the log
+/// that was discarded.
+pub fn restore(
+    wal_dir: &Path,
+    archive_dir: &Path,
+    target: Option<AsOf>,
+    checkpoint: Lsn,
+) -> Result<(), PageManagerError> {
+    // Recovery starts from the backup's checkpoint record, which must stay,
+    // and must replay at least the log taken with the backup
+    let mut floor = checkpoint;
+    if wal_dir.exists() {
+        for entry in Wal::read_dir(wal_dir, Lsn::ZERO)? {
+            floor = floor.max(entry?.0);
+        }
+    }
+    copy_archive(wal_dir, archive_dir)?;
+
+    let cut = match target {
+        None => return Ok(()),
+        Some(AsOf::Lsn(lsn)) => lsn,
+        Some(AsOf::Time(time)) => {
+            let time = micros_since_epoch(time);
+            let mut cut = None;
+            for entry in Wal::read_dir(wal_dir, Lsn::ZERO)? {
+                match entry? {
+                    (lsn, WalRecord::Commit { time: at, .. }) if at > time => {
+                        cut = Some(lsn);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            match cut {
+                Some(cut) => cut,
+                None => return Ok(()),
+            }
+        }
+    };
+    if cut <= floor {
+        return Err(PageManagerError::HistoryUnavailable);
+    }
+    Wal::truncate(wal_dir, cut)?;
+    Ok(())
+}
+
+/// Copy archived segments into `wal_dir` where its own copy is missing or
+/// shorter, as the last segment of a backup usually is.
+fn copy_archive(wal_dir: &Path, archive_dir: &Path) -> Result<(), WalError> {
+    fs::create_dir_all(wal_dir)?;
+    for (_, path) in Wal::segments(archive_dir)? {
+        let local = wal_dir.join(path.file_name().unwrap());
+        if !local.exists() || fs::metadata(&path)?.len() > fs::metadata(&local)?.len() {
+            fs::copy(&path, local)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WalConfig;
+    use crate::storage::file_manager::FileId;
+    use crate::storage::page::{Page, PageId};
+    use crate::storage::page_manager::{PageManager, PageManagerBuilder};
+    use crate::storage::wal::TxnId;
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    const PAGE: PageId = PageId {
+        file: FileId(1),
+        page_no: 0,
+    };
+
+    fn builder(dir: &Path, archive_dir: Option<&Path>) -> PageManagerBuilder {
+        PageManagerBuilder::new(dir)
+            .page_size(128)
+            .wal(Some(WalConfig {
+                segment_size: 512,
+                checkpoint_interval_ms: None,
+                commit_delay_us: None,
+                retention_ms: None,
+                archive_dir: archive_dir.map(|dir| dir.to_str().unwrap().to_string()),
+                full_page_writes: true,
+            }))
+    }
+
+    fn write(pages: &PageManager, txn: u64, value: u8, commit: bool) {
+        let wal = pages.wal().unwrap();
+        wal.append(&WalRecord::Begin { txn: TxnId(txn) }).unwrap();
+        pages
+            .log_write(TxnId(txn), PAGE, Page::full(value, 112))
+            .unwrap();
+        if commit {
+            let lsn = wal.append(&WalRecord::commit(TxnId(txn))).unwrap();
+            wal.flush(lsn).unwrap();
+        }
+    }
+
+    fn value(pages: &PageManager) -> u8 {
+        pages.get_page(PAGE).unwrap().page().as_bytes()[0]
+    }
+
+    #[test]
+    fn test_point_in_time_recovery() {
+        let live = tempfile::tempdir().unwrap();
+        let archive = tempfile::tempdir().unwrap();
+        let backups = tempfile::tempdir().unwrap();
+        let backup = |name: &str| backups.path().join(name);
+
+        let pages = builder(live.path(), Some(archive.path())).build().unwrap();
+        pages.create_file().unwrap();
+        write(&pages, 1, 1, true);
+        let earlier = pages.backup(backup("to_time")).unwrap();
+        pages.backup(backup("to_end")).unwrap();
+        pages.backup(backup("too_early")).unwrap();
+
+        write(&pages, 2, 2, true);
+        thread::sleep(Duration::from_millis(2));
+        let between = SystemTime::now();
+        thread::sleep(Duration::from_millis(2));
+        write(&pages, 3, 3, true);
+        write(&pages, 4, 4, false);
+        pages.wal().unwrap().switch_segment().unwrap();
+
+        let restored = builder(&backup("to_time"), None)
+            .restore(archive.path(), Some(AsOf::Time(between)))
+            .build()
+            .unwrap();
+        assert_eq!(value(&restored), 2);
+        // Transaction 4 only began after the target
+        assert_eq!(restored.recovery().losers, vec![TxnId(3)]);
+
+        // Without a target, only the transaction still running is lost
+        let restored = builder(&backup("to_end"), None)
+            .restore(archive.path(), None)
+            .build()
+            .unwrap();
+        assert_eq!(value(&restored), 3);
+        assert_eq!(restored.recovery().losers, vec![TxnId(4)]);
+
+        // The last backup already holds everything logged before it ended
+        let result = builder(&backup("too_early"), None)
+            .restore(archive.path(), Some(AsOf::Lsn(earlier)))
+            .build();
+        assert!(matches!(result, Err(PageManagerError::HistoryUnavailable)));
+    }
+}
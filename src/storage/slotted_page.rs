@@ -0,0 +1,599 @@
+use super::page::{Page, PageDecodeError};
+
+/// Slot count plus the start and end of the free space.
+const HEADER_SIZE: usize = 12;
+/// Offset and length of one record.
+const SLOT_SIZE: usize = 8;
+/// The offset of a slot whose record was deleted.
+const DEAD: u32 = 0;
+/// A slot's length keeps its record's kind in the bits above this.
+const KIND_SHIFT: u32 = 30;
+const LENGTH_MASK: u32 = (1 << KIND_SHIFT) - 1;
+
+/// Identifies a record within its page. Slots keep their number when the
+/// page is compacted, so a record's id stays valid until it is deleted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SlotId(pub u32);
+
+/// What a record holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    /// A row
+    Row,
+    /// A row too large for a page, with some of its values moved to
+    /// overflow records it points to
+    Spilled,
+    /// A piece of a value moved out of a spilled row
+    Overflow,
+}
+
+impl RecordKind {
+    fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            0 => Some(Self::Row),
+            1 => Some(Self::Spilled),
+            2 => Some(Self::Overflow),
+            _ => None,
+        }
+    }
+
+    fn bits(self) -> u32 {
+        match self {
+            Self::Row => 0,
+            Self::Spilled => 1,
+            Self::Overflow => 2,
+        }
+    }
+}
+
+/// A live record with its slot and kind.
+pub type Entry<'a> = (SlotId, RecordKind, &'a [u8]);
+
+/// A page holding variable-length records.
+///
+/// The slot directory grows up from the header and record data grows down
+/// from the end of the page, with the free space between them. Deleting a
+/// record only marks its slot dead; `compact` then moves the remaining
+/// records together to reclaim the space, and reuses dead slots.
+///
+/// Layout, big-endian:
+///
+/// | Offset     | Size | Field                                    |
+/// |------------|------|------------------------------------------|
+/// | 0          | 4    | Number of slots                          |
+/// | 4          | 4    | Start of the free space                  |
+/// | 8          | 4    | End of the free space                    |
+/// | 12 + 8 * n | 4    | Record offset, or 0 if the slot is dead  |
+/// | 16 + 8 * n | 4    | Record kind (top 2 bits) and length      |
+#[derive(Debug, PartialEq)]
+pub struct SlottedPage {
+    page: Page,
+}
+
+impl SlottedPage {
+    /// An empty page of `page_size` bytes.
+    pub fn new(page_size: usize) -> Self {
+        let mut page = Page::zeros(page_size);
+        page.write_u32(4, HEADER_SIZE as u32).unwrap();
+        page.write_u32(8, page_size as u32).unwrap();
+        Self { page }
+    }
+
+    /// Interpret `page` as a slotted page, checking its header and slots
+    /// lie within it. An all-zero page, as read from past the end of a
+    /// file, is taken as empty.
+    pub fn from_page(page: Page) -> Result<Self, PageDecodeError> {
+        let size = page.as_bytes().len();
+        if page.as_bytes().iter().all(|&byte| byte == 0) {
+            return Ok(Self::new(size));
+        }
+        let this = Self { page };
+        let corrupted = |reason: &str| PageDecodeError::Corrupted(reason.to_string());
+        let (count, start, end) = (this.slot_count()?, this.free_start()?, this.free_end()?);
+        if start != HEADER_SIZE + count as usize * SLOT_SIZE || start > end || end > size {
+            return Err(corrupted("slotted page header out of bounds"));
+        }
+        for slot in (0..count).map(SlotId) {
+            let (offset, len) = this.slot(slot)?;
+            if offset != DEAD && ((offset as usize) < end || offset as usize + len as usize > size)
+            {
+                return Err(corrupted("slotted page record out of bounds"));
+            }
+            if offset != DEAD && this.slot_kind(slot).is_none() {
+                return Err(corrupted("slotted page record of unknown kind"));
+            }
+        }
+        Ok(this)
+    }
+
+    /// Rebuild a damaged page from whatever records can still be found in
+    /// it: those whose slot lies within the page and points at data within
+    /// it. Records keep their slots. Returns the page and how many records
+    /// had to be dropped.
+    pub fn salvage(page: Page) -> (Self, usize) {
+        let size = page.as_bytes().len();
+        let damaged = Self { page };
+        let max_slots = size.saturating_sub(HEADER_SIZE) / SLOT_SIZE;
+        let count = (damaged.slot_count().unwrap_or(0) as usize).min(max_slots);
+        let directory_end = HEADER_SIZE + count * SLOT_SIZE;
+
+        let mut lost = 0;
+        let mut records = Vec::new();
+        for slot in (0..count as u32).map(SlotId) {
+            let (offset, len) = damaged.slot(slot).unwrap_or((DEAD, 0));
+            let (offset, len) = (offset as usize, len as usize);
+            if offset == DEAD as usize {
+                continue;
+            }
+            let record = damaged.page.as_bytes().get(offset..offset + len);
+            match (record, damaged.slot_kind(slot)) {
+                (Some(record), Some(kind)) if offset >= directory_end => {
+                    records.push((slot, kind, record))
+                }
+                _ => lost += 1,
+            }
+        }
+
+        let mut page = Self::new(size);
+        let (mut count, mut end) = (0, size);
+        for (slot, kind, record) in records {
+            // Overlapping records may not all fit once laid out apart
+            let start = HEADER_SIZE + (slot.0 as usize + 1) * SLOT_SIZE;
+            if end < start + record.len() {
+                lost += 1;
+                continue;
+            }
+            end -= record.len();
+            page.page.as_bytes_mut()[end..end + record.len()].copy_from_slice(record);
+            page.set_slot(slot, end as u32, record.len() as u32, kind)
+                .unwrap();
+            count = slot.0 + 1;
+        }
+        page.page.write_u32(0, count).unwrap();
+        page.page
+            .write_u32(4, (HEADER_SIZE + count as usize * SLOT_SIZE) as u32)
+            .unwrap();
+        page.page.write_u32(8, end as u32).unwrap();
+        (page, lost)
+    }
+
+    pub fn into_page(self) -> Page {
+        self.page
+    }
+
+    #[cfg(any(test, feature = "testing"))]
+    pub fn as_page(&self) -> &Page {
+        &self.page
+    }
+
+    /// Store `record`, returning its slot, or `None` if the page lacks room
+    /// even after compaction.
+    #[cfg(test)]
+    pub fn insert(&mut self, record: &[u8]) -> Result<Option<SlotId>, PageDecodeError> {
+        self.insert_reserving(record, 0)
+    }
+
+    /// Store `record` as `insert` does, but only if `reserved` bytes are
+    /// still free afterwards, kept for records on the page to grow into.
+    /// A page with no live records takes any record that fits.
+    #[cfg(test)]
+    pub fn insert_reserving(
+        &mut self,
+        record: &[u8],
+        reserved: usize,
+    ) -> Result<Option<SlotId>, PageDecodeError> {
+        self.insert_as(record, reserved, RecordKind::Row)
+    }
+
+    /// Store `record` as `insert_reserving` does, as a record of `kind`.
+    pub fn insert_as(
+        &mut self,
+        record: &[u8],
+        reserved: usize,
+        kind: RecordKind,
+    ) -> Result<Option<SlotId>, PageDecodeError> {
+        let reserved = if self.is_empty()? { 0 } else { reserved };
+        if self.needed_space(record)? + reserved > self.free_space()? {
+            self.compact()?;
+            if self.needed_space(record)? + reserved > self.free_space()? {
+                return Ok(None);
+            }
+        }
+        let reused = self.dead_slot()?;
+
+        let end = self.free_end()? - record.len();
+        self.page.as_bytes_mut()[end..end + record.len()].copy_from_slice(record);
+        self.page.write_u32(8, end as u32)?;
+        let slot = match reused {
+            Some(slot) => slot,
+            None => {
+                let count = self.slot_count()?;
+                self.page.write_u32(0, count + 1)?;
+                self.page
+                    .write_u32(4, (self.free_start()? + SLOT_SIZE) as u32)?;
+                SlotId(count)
+            }
+        };
+        self.set_slot(slot, end as u32, record.len() as u32, kind)?;
+        Ok(Some(slot))
+    }
+
+    /// The largest record a page of `page_size` bytes holds.
+    pub fn max_record_size(page_size: usize) -> usize {
+        page_size - HEADER_SIZE - SLOT_SIZE
+    }
+
+    /// The record in `slot`, of whatever kind, or `None` if it was deleted
+    /// or never existed.
+    pub fn get(&self, slot: SlotId) -> Result<Option<&[u8]>, PageDecodeError> {
+        if slot.0 >= self.slot_count()? {
+            return Ok(None);
+        }
+        let (offset, len) = self.slot(slot)?;
+        if offset == DEAD {
+            return Ok(None);
+        }
+        let offset = offset as usize;
+        Ok(Some(&self.page.as_bytes()[offset..offset + len as usize]))
+    }
+
+    /// The kind of the record in `slot`, or `None` if there isn't one.
+    pub fn kind(&self, slot: SlotId) -> Result<Option<RecordKind>, PageDecodeError> {
+        if self.get(slot)?.is_none() {
+            return Ok(None);
+        }
+        Ok(self.slot_kind(slot))
+    }
+
+    /// Delete the record in `slot`. Returns whether there was one. Its space
+    /// is only reclaimed by `compact`.
+    pub fn delete(&mut self, slot: SlotId) -> Result<bool, PageDecodeError> {
+        if self.get(slot)?.is_none() {
+            return Ok(false);
+        }
+        self.set_slot(slot, DEAD, 0, RecordKind::Row)?;
+        Ok(true)
+    }
+
+    /// Replace the record in `slot`, keeping its slot. Returns false,
+    /// changing nothing, if there is no record there or no room for the new
+    /// one even once the old one's space is reclaimed.
+    #[cfg(test)]
+    pub fn update(&mut self, slot: SlotId, record: &[u8]) -> Result<bool, PageDecodeError> {
+        self.update_as(slot, record, RecordKind::Row)
+    }
+
+    /// Replace the record in `slot` as `update` does, with a record of
+    /// `kind`.
+    pub fn update_as(
+        &mut self,
+        slot: SlotId,
+        record: &[u8],
+        kind: RecordKind,
+    ) -> Result<bool, PageDecodeError> {
+        let Some(old) = self.get(slot)?.map(<[u8]>::len) else {
+            return Ok(false);
+        };
+        if record.len() <= old {
+            let offset = self.slot(slot)?.0;
+            let at = offset as usize;
+            self.page.as_bytes_mut()[at..at + record.len()].copy_from_slice(record);
+            self.set_slot(slot, offset, record.len() as u32, kind)?;
+            return Ok(true);
+        }
+
+        let saved = Page::new(self.page.as_bytes().to_vec());
+        self.delete(slot)?;
+        self.compact()?;
+        // Compaction drops trailing dead slots, which may include this one
+        let count = self.slot_count()?;
+        let grown = (slot.0 + 1).saturating_sub(count) as usize * SLOT_SIZE;
+        if record.len() + grown > self.free_space()? {
+            self.page = saved;
+            return Ok(false);
+        }
+        if grown > 0 {
+            // The free space is zeroed, so the slots regained are dead
+            self.page.write_u32(0, slot.0 + 1)?;
+            self.page
+                .write_u32(4, (self.free_start()? + grown) as u32)?;
+        }
+        let end = self.free_end()? - record.len();
+        self.page.as_bytes_mut()[end..end + record.len()].copy_from_slice(record);
+        self.page.write_u32(8, end as u32)?;
+        self.set_slot(slot, end as u32, record.len() as u32, kind)?;
+        Ok(true)
+    }
+
+    /// The live rows and their slots, in slot order: the records of every
+    /// kind but `Overflow`, which are pieces of the spilled ones.
+    pub fn records(&self) -> Result<Vec<(SlotId, &[u8])>, PageDecodeError> {
+        let records = self.entries()?.into_iter();
+        Ok(records
+            .filter(|&(_, kind, _)| kind != RecordKind::Overflow)
+            .map(|(slot, _, record)| (slot, record))
+            .collect())
+    }
+
+    /// The live records of every kind, with their slots, in slot order.
+    pub fn entries(&self) -> Result<Vec<Entry<'_>>, PageDecodeError> {
+        let mut records = Vec::new();
+        for slot in (0..self.slot_count()?).map(SlotId) {
+            if let Some(record) = self.get(slot)? {
+                let kind = self.slot_kind(slot).unwrap_or(RecordKind::Row);
+                records.push((slot, kind, record));
+            }
+        }
+        Ok(records)
+    }
+
+    /// Whether the page holds no live records.
+    pub fn is_empty(&self) -> Result<bool, PageDecodeError> {
+        Ok(self.entries()?.is_empty())
+    }
+
+    /// Bytes free between the slot directory and the records.
+    pub fn free_space(&self) -> Result<usize, PageDecodeError> {
+        Ok(self.free_end()? - self.free_start()?)
+    }
+
+    /// Move the live records together at the end of the page, reclaiming
+    /// the space of deleted ones, and drop dead slots from the end of the
+    /// directory. Returns how many bytes were reclaimed.
+    pub fn compact(&mut self) -> Result<usize, PageDecodeError> {
+        let before = self.free_space()?;
+        let records: Vec<_> = self
+            .entries()?
+            .into_iter()
+            .map(|(slot, kind, record)| (slot, kind, record.to_vec()))
+            .collect();
+        let count = records.last().map_or(0, |(slot, ..)| slot.0 + 1);
+
+        let size = self.page.as_bytes().len();
+        let mut end = size;
+        for (slot, kind, record) in &records {
+            end -= record.len();
+            self.page.as_bytes_mut()[end..end + record.len()].copy_from_slice(record);
+            self.set_slot(*slot, end as u32, record.len() as u32, *kind)?;
+        }
+        let start = HEADER_SIZE + count as usize * SLOT_SIZE;
+        self.page.as_bytes_mut()[start..end].fill(0);
+        self.page.write_u32(0, count)?;
+        self.page.write_u32(4, start as u32)?;
+        self.page.write_u32(8, end as u32)?;
+        Ok(self.free_space()? - before)
+    }
+
+    /// Check the invariants every change to the page keeps, failing with
+    /// the first one broken: the header agrees with the slot directory,
+    /// and the free space lies between it and the records and is zeroed;
+    /// each live record is of a known kind, within the page past the free
+    /// space, and overlaps no other; and a dead slot has no length.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn check_invariants(&self) -> Result<(), PageDecodeError> {
+        let broken = |reason: String| Err(PageDecodeError::Corrupted(reason));
+        let size = self.page.as_bytes().len();
+        let (count, start, end) = (self.slot_count()?, self.free_start()?, self.free_end()?);
+        if start != HEADER_SIZE + count as usize * SLOT_SIZE {
+            return broken(format!("free space starts at {start} after {count} slots"));
+        }
+        if start > end || end > size {
+            return broken(format!("free space {start}..{end} of a {size} byte page"));
+        }
+        if let Some(at) = self.page.as_bytes()[start..end]
+            .iter()
+            .position(|&b| b != 0)
+        {
+            return broken(format!("free space byte {} isn't zero", start + at));
+        }
+        let mut records = Vec::new();
+        for slot in (0..count).map(SlotId) {
+            let (offset, len) = self.slot(slot)?;
+            let (offset, len) = (offset as usize, len as usize);
+            if offset == DEAD as usize {
+                if len != 0 {
+                    return broken(format!("dead slot {} has length {len}", slot.0));
+                }
+                continue;
+            }
+            if offset < end || offset + len > size {
+                return broken(format!("slot {} holds {offset}..{}", slot.0, offset + len));
+            }
+            if self.slot_kind(slot).is_none() {
+                return broken(format!("slot {} has a record of unknown kind", slot.0));
+            }
+            records.push((offset, offset + len, slot));
+        }
+        records.sort();
+        for pair in records.windows(2) {
+            let ((_, first_end, first), (second_start, _, second)) = (pair[0], pair[1]);
+            if first_end > second_start {
+                return broken(format!("slots {} and {} overlap", first.0, second.0));
+            }
+        }
+        Ok(())
+    }
+
+    /// Space inserting `record` takes, including a new slot unless a dead
+    /// one can be reused.
+    fn needed_space(&self, record: &[u8]) -> Result<usize, PageDecodeError> {
+        let slot = match self.dead_slot()? {
+            Some(_) => 0,
+            None => SLOT_SIZE,
+        };
+        Ok(record.len() + slot)
+    }
+
+    fn dead_slot(&self) -> Result<Option<SlotId>, PageDecodeError> {
+        for slot in (0..self.slot_count()?).map(SlotId) {
+            if self.slot(slot)?.0 == DEAD {
+                return Ok(Some(slot));
+            }
+        }
+        Ok(None)
+    }
+
+    fn slot_count(&self) -> Result<u32, PageDecodeError> {
+        self.page.read_u32(0)
+    }
+
+    fn free_start(&self) -> Result<usize, PageDecodeError> {
+        Ok(self.page.read_u32(4)? as usize)
+    }
+
+    fn free_end(&self) -> Result<usize, PageDecodeError> {
+        Ok(self.page.read_u32(8)? as usize)
+    }
+
+    fn slot(&self, slot: SlotId) -> Result<(u32, u32), PageDecodeError> {
+        let at = HEADER_SIZE + slot.0 as usize * SLOT_SIZE;
+        let len = self.page.read_u32(at + 4)? & LENGTH_MASK;
+        Ok((self.page.read_u32(at)?, len))
+    }
+
+    fn slot_kind(&self, slot: SlotId) -> Option<RecordKind> {
+        let at = HEADER_SIZE + slot.0 as usize * SLOT_SIZE;
+        RecordKind::from_bits(self.page.read_u32(at + 4).ok()? >> KIND_SHIFT)
+    }
+
+    fn set_slot(
+        &mut self,
+        slot: SlotId,
+        offset: u32,
+        len: u32,
+        kind: RecordKind,
+    ) -> Result<(), PageDecodeError> {
+        let at = HEADER_SIZE + slot.0 as usize * SLOT_SIZE;
+        self.page.write_u32(at, offset)?;
+        self.page.write_u32(at + 4, kind.bits() << KIND_SHIFT | len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_get() {
+        let mut page = SlottedPage::new(128);
+        let a = page.insert(b"hello").unwrap().unwrap();
+        let b = page.insert(b"world!").unwrap().unwrap();
+        assert_eq!(page.get(a).unwrap(), Some(&b"hello"[..]));
+        assert_eq!(page.get(b).unwrap(), Some(&b"world!"[..]));
+        assert_eq!(page.get(SlotId(2)).unwrap(), None);
+        assert_eq!(page.free_space().unwrap(), 128 - 12 - 16 - 11);
+
+        let page = SlottedPage::from_page(page.into_page()).unwrap();
+        assert_eq!(page.records().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_compact_reclaims_deleted_records() {
+        let mut page = SlottedPage::new(128);
+        let slots: Vec<_> = (0..4)
+            .map(|i| page.insert(&[i; 20]).unwrap().unwrap())
+            .collect();
+        assert!(page.delete(slots[1]).unwrap());
+        assert!(!page.delete(slots[1]).unwrap());
+        assert!(page.delete(slots[3]).unwrap());
+
+        // The trailing dead slot is dropped; the one in the middle is kept
+        // so slot 2 keeps its number
+        assert_eq!(page.compact().unwrap(), 40 + SLOT_SIZE);
+        assert_eq!(page.get(slots[0]).unwrap(), Some(&[0; 20][..]));
+        assert_eq!(page.get(slots[2]).unwrap(), Some(&[2; 20][..]));
+        assert_eq!(page.insert(&[9; 4]).unwrap(), Some(slots[1]));
+
+        for slot in [slots[0], slots[1], slots[2]] {
+            page.delete(slot).unwrap();
+        }
+        assert!(page.is_empty().unwrap());
+        page.compact().unwrap();
+        assert_eq!(page, SlottedPage::new(128));
+    }
+
+    #[test]
+    fn test_full_page() {
+        let mut page = SlottedPage::new(64);
+        let first = page.insert(&[1; 30]).unwrap().unwrap();
+        assert_eq!(page.insert(&[2; 30]).unwrap(), None);
+        // Deleting makes room, compacting on the way
+        page.delete(first).unwrap();
+        assert_eq!(page.insert(&[2; 30]).unwrap(), Some(first));
+
+        // Space held back is only given up on an empty page
+        let mut page = SlottedPage::new(64);
+        assert!(page.insert_reserving(&[1; 10], 40).unwrap().is_some());
+        assert_eq!(page.insert_reserving(&[2; 5], 30).unwrap(), None);
+        assert!(page.insert(&[2; 5]).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_update() {
+        let mut page = SlottedPage::new(64);
+        let a = page.insert(&[1; 10]).unwrap().unwrap();
+        let b = page.insert(&[2; 10]).unwrap().unwrap();
+        assert!(page.update(a, &[3; 4]).unwrap());
+        assert_eq!(page.get(a).unwrap(), Some(&[3; 4][..]));
+        // Growing the last record moves it, its slot regained after
+        // compaction drops it
+        assert!(page.update(b, &[4; 30]).unwrap());
+        assert_eq!(page.get(b).unwrap(), Some(&[4; 30][..]));
+        assert_eq!(page.get(a).unwrap(), Some(&[3; 4][..]));
+        assert!(!page.update(a, &[5; 40]).unwrap());
+        assert_eq!(page.get(a).unwrap(), Some(&[3; 4][..]));
+        page.delete(a).unwrap();
+        assert!(!page.update(a, &[5; 1]).unwrap());
+    }
+
+    #[test]
+    fn test_record_kinds() {
+        let mut page = SlottedPage::new(128);
+        let row = page.insert(&[1; 10]).unwrap().unwrap();
+        let spilled = page
+            .insert_as(&[2; 10], 0, RecordKind::Spilled)
+            .unwrap()
+            .unwrap();
+        let piece = page
+            .insert_as(&[3; 10], 0, RecordKind::Overflow)
+            .unwrap()
+            .unwrap();
+        assert_eq!(page.kind(row).unwrap(), Some(RecordKind::Row));
+        assert_eq!(page.kind(spilled).unwrap(), Some(RecordKind::Spilled));
+        assert_eq!(page.get(piece).unwrap(), Some(&[3; 10][..]));
+        // Overflow records are parts of rows, not rows
+        assert_eq!(page.records().unwrap().len(), 2);
+        assert_eq!(page.entries().unwrap().len(), 3);
+
+        // Kinds survive compaction and reading the page back, and an
+        // update gives the record its new kind
+        page.delete(row).unwrap();
+        page.compact().unwrap();
+        let mut page = SlottedPage::from_page(page.into_page()).unwrap();
+        assert_eq!(page.kind(piece).unwrap(), Some(RecordKind::Overflow));
+        assert!(page.update(spilled, &[4; 20]).unwrap());
+        assert_eq!(page.kind(spilled).unwrap(), Some(RecordKind::Row));
+        assert!(page
+            .update_as(spilled, &[5; 5], RecordKind::Spilled)
+            .unwrap());
+        assert_eq!(page.kind(spilled).unwrap(), Some(RecordKind::Spilled));
+        assert_eq!(page.kind(row).unwrap(), None);
+
+        let (page, lost) = SlottedPage::salvage(page.into_page());
+        assert_eq!(lost, 0);
+        assert_eq!(page.kind(piece).unwrap(), Some(RecordKind::Overflow));
+        assert_eq!(SlottedPage::max_record_size(128), 108);
+        assert!(page.is_empty().is_ok_and(|empty| !empty));
+    }
+
+    #[test]
+    fn test_rejects_corrupted_page() {
+        let mut page = SlottedPage::new(64).into_page();
+        page.write_u32(8, 100).unwrap();
+        assert!(matches!(
+            SlottedPage::from_page(page),
+            Err(PageDecodeError::Corrupted(_))
+        ));
+        assert!(SlottedPage::from_page(Page::zeros(64)).is_ok());
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Running totals kept by the buffer pool as pages move through it.
+#[derive(Default)]
+pub struct BufferCounters {
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+    pub evictions: AtomicU64,
+    pub write_backs: AtomicU64,
+    pub prefetches: AtomicU64,
+}
+
+impl BufferCounters {
+    pub fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Running totals kept by the write-ahead log.
+#[derive(Default)]
+pub struct WalCounters {
+    pub flushes: AtomicU64,
+    pub syncs: AtomicU64,
+}
+
+/// A point-in-time snapshot of log activity, from `Wal::stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalStats {
+    /// Calls to `Wal::flush`, such as one per commit
+    pub flushes: u64,
+    /// Writes of the log to the OS and, with full durability, to stable
+    /// storage. Fewer than `flushes` when commits are grouped.
+    pub syncs: u64,
+}
+
+impl WalStats {
+    pub(crate) fn from_counters(counters: &WalCounters) -> Self {
+        Self {
+            flushes: counters.flushes.load(Ordering::Relaxed),
+            syncs: counters.syncs.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A point-in-time snapshot of buffer pool activity, from `PageManager::stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BufferStats {
+    /// Requests served from the cache
+    pub hits: u64,
+    /// Requests that had to read the page from disk
+    pub misses: u64,
+    /// Pages dropped from the cache to make room for others
+    pub evictions: u64,
+    /// Dirty pages written to disk by eviction, invalidation, or flush
+    pub write_backs: u64,
+    /// Pages loaded ahead of a sequential scan
+    pub prefetches: u64,
+    /// Maximum number of pages the pool can hold
+    pub capacity: usize,
+    /// Pages currently cached
+    pub cached_pages: usize,
+    /// Cached pages that differ from their on-disk copy
+    pub dirty_pages: usize,
+    /// Cached pages held by at least one guard
+    pub pinned_pages: usize,
+}
+
+impl BufferStats {
+    pub(crate) fn from_counters(counters: &BufferCounters) -> Self {
+        Self {
+            hits: counters.hits.load(Ordering::Relaxed),
+            misses: counters.misses.load(Ordering::Relaxed),
+            evictions: counters.evictions.load(Ordering::Relaxed),
+            write_backs: counters.write_backs.load(Ordering::Relaxed),
+            prefetches: counters.prefetches.load(Ordering::Relaxed),
+            ..Default::default()
+        }
+    }
+
+    /// Fraction of page requests served from the cache, or 0 before any
+    /// requests have been made.
+    pub fn hit_ratio(&self) -> f64 {
+        let requests = self.hits + self.misses;
+        if requests == 0 {
+            0.0
+        } else {
+            self.hits as f64 / requests as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hit_ratio() {
+        let mut stats = BufferStats::default();
+        assert_eq!(stats.hit_ratio(), 0.0);
+
+        stats.hits = 3;
+        stats.misses = 1;
+        assert_eq!(stats.hit_ratio(), 0.75);
+    }
+}
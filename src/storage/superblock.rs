@@ -0,0 +1,308 @@
+use super::encryption::TAG_SIZE;
+use super::page::Page;
+use super::wal::Lsn;
+use crate::config::Compression;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Identifies a ferrodb catalog file.
+pub const MAGIC: [u8; 8] = *b"FERRODB\0";
+
+/// The on-disk format version written by this build.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Bytes at the start of page 0 taken up by the superblock, including
+/// reserved space for future fields. Pages must be at least this large.
+pub const SUPERBLOCK_SIZE: usize = 64;
+
+#[derive(Debug, Error)]
+pub enum SuperblockError {
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Not a ferrodb database: bad magic number")]
+    InvalidMagic,
+
+    #[error("Unsupported format version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("Format version {0} is out of date and must be migrated")]
+    OutdatedVersion(u32),
+
+    #[error("Database has page size {found}, but {expected} was configured")]
+    PageSizeMismatch { expected: usize, found: usize },
+
+    #[error("Database uses compression {found:?}, but {expected:?} was configured")]
+    CompressionMismatch {
+        expected: Compression,
+        found: Compression,
+    },
+
+    #[error("Unknown compression code {0}")]
+    UnknownCompression(u8),
+
+    #[error("Database is encrypted, but no key was configured")]
+    EncryptedDatabase,
+
+    #[error("Database is not encrypted, but a key was configured")]
+    UnencryptedDatabase,
+
+    #[error("Wrong encryption key")]
+    WrongKey,
+
+    #[error("Database was created with a write-ahead log, but none was configured")]
+    LoggedDatabase,
+
+    #[error("Database was created without a write-ahead log, but one was configured")]
+    UnloggedDatabase,
+}
+
+/// The header stored in page 0 of the catalog file, describing how the rest
+/// of the database is laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Superblock {
+    pub format_version: u32,
+    pub page_size: u32,
+    /// Seconds since the Unix epoch when the database was created
+    pub created_at: u64,
+    pub compression: Compression,
+    /// Identifies the key pages are encrypted with, or `None` for plaintext
+    pub key_check: Option<[u8; TAG_SIZE]>,
+    /// Whether pages carry the LSN of their last logged change
+    pub logged: bool,
+    /// The latest checkpoint record in the log, where recovery starts
+    pub checkpoint: Lsn,
+}
+
+impl Superblock {
+    pub fn new(
+        page_size: usize,
+        compression: Compression,
+        key_check: Option<[u8; TAG_SIZE]>,
+        logged: bool,
+    ) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        Self {
+            format_version: FORMAT_VERSION,
+            page_size: page_size as u32,
+            created_at,
+            compression,
+            key_check,
+            logged,
+            checkpoint: Lsn::ZERO,
+        }
+    }
+
+    /// Read the superblock from the start of the file at `path`, or `None`
+    /// if the file is empty.
+    pub fn read_from(path: impl AsRef<Path>) -> Result<Option<Self>, SuperblockError> {
+        let file = File::open(path)?;
+        let mut bytes = Vec::with_capacity(SUPERBLOCK_SIZE);
+        file.take(SUPERBLOCK_SIZE as u64).read_to_end(&mut bytes)?;
+        match bytes.len() {
+            0 => Ok(None),
+            SUPERBLOCK_SIZE => Self::decode(&bytes).map(Some),
+            _ => Err(SuperblockError::InvalidMagic),
+        }
+    }
+
+    /// Overwrite the superblock at the start of the file at `path`, leaving
+    /// the rest of page 0 untouched.
+    pub fn write_to(&self, path: impl AsRef<Path>) -> Result<(), SuperblockError> {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&self.encode(SUPERBLOCK_SIZE).as_bytes()[..SUPERBLOCK_SIZE])?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, SuperblockError> {
+        if bytes.len() < SUPERBLOCK_SIZE || bytes[..MAGIC.len()] != MAGIC {
+            return Err(SuperblockError::InvalidMagic);
+        }
+        let mut cursor = Cursor::new(&bytes[MAGIC.len()..]);
+        let format_version = cursor.read_u32::<BigEndian>()?;
+        let page_size = cursor.read_u32::<BigEndian>()?;
+        let created_at = cursor.read_u64::<BigEndian>()?;
+        let code = cursor.read_u8()?;
+        let encrypted = cursor.read_u8()? != 0;
+        let mut key_check = [0; TAG_SIZE];
+        cursor.read_exact(&mut key_check)?;
+        let logged = cursor.read_u8()? != 0;
+        let checkpoint = Lsn(cursor.read_u64::<BigEndian>()?);
+        Ok(Self {
+            format_version,
+            page_size,
+            created_at,
+            compression: Compression::from_code(code)
+                .ok_or(SuperblockError::UnknownCompression(code))?,
+            key_check: encrypted.then_some(key_check),
+            logged,
+            checkpoint,
+        })
+    }
+
+    /// Encode the superblock into an otherwise zeroed page.
+    pub fn encode(&self, page_size: usize) -> Page {
+        let mut data = Vec::with_capacity(page_size);
+        data.extend_from_slice(&MAGIC);
+        data.write_u32::<BigEndian>(self.format_version).unwrap();
+        data.write_u32::<BigEndian>(self.page_size).unwrap();
+        data.write_u64::<BigEndian>(self.created_at).unwrap();
+        data.write_u8(self.compression.code()).unwrap();
+        data.write_u8(self.key_check.is_some() as u8).unwrap();
+        data.extend_from_slice(&self.key_check.unwrap_or_default());
+        data.write_u8(self.logged as u8).unwrap();
+        data.write_u64::<BigEndian>(self.checkpoint.0).unwrap();
+        data.resize(page_size, 0);
+        Page::new(data)
+    }
+
+    /// Check that the database can be opened with `page_size`,
+    /// `compression`, the key identified by `key_check`, and with or without
+    /// a write-ahead log by this build.
+    pub fn validate(
+        &self,
+        page_size: usize,
+        compression: Compression,
+        key_check: Option<[u8; TAG_SIZE]>,
+        logged: bool,
+    ) -> Result<(), SuperblockError> {
+        if self.format_version < FORMAT_VERSION {
+            return Err(SuperblockError::OutdatedVersion(self.format_version));
+        }
+        if self.format_version > FORMAT_VERSION {
+            return Err(SuperblockError::UnsupportedVersion(self.format_version));
+        }
+        if self.page_size as usize != page_size {
+            return Err(SuperblockError::PageSizeMismatch {
+                expected: page_size,
+                found: self.page_size as usize,
+            });
+        }
+        if self.compression != compression {
+            return Err(SuperblockError::CompressionMismatch {
+                expected: compression,
+                found: self.compression,
+            });
+        }
+        match (self.key_check, key_check) {
+            (Some(_), None) => return Err(SuperblockError::EncryptedDatabase),
+            (None, Some(_)) => return Err(SuperblockError::UnencryptedDatabase),
+            (Some(stored), Some(given)) if stored != given => {
+                return Err(SuperblockError::WrongKey)
+            }
+            _ => {}
+        }
+        match (self.logged, logged) {
+            (true, false) => Err(SuperblockError::LoggedDatabase),
+            (false, true) => Err(SuperblockError::UnloggedDatabase),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut superblock = Superblock::new(128, Compression::None, None, false);
+        superblock.checkpoint = Lsn(1234);
+        let page = superblock.encode(128);
+        assert_eq!(page.as_bytes().len(), 128);
+        assert_eq!(Superblock::decode(page.as_bytes()).unwrap(), superblock);
+        assert!(superblock
+            .validate(128, Compression::None, None, false)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut superblock = Superblock::new(128, Compression::None, None, false);
+        assert!(matches!(
+            superblock.validate(256, Compression::None, None, false),
+            Err(SuperblockError::PageSizeMismatch {
+                expected: 256,
+                found: 128
+            })
+        ));
+
+        superblock.format_version = FORMAT_VERSION + 1;
+        assert!(matches!(
+            superblock.validate(128, Compression::None, None, false),
+            Err(SuperblockError::UnsupportedVersion(_))
+        ));
+
+        superblock.format_version = FORMAT_VERSION - 1;
+        assert!(matches!(
+            superblock.validate(128, Compression::None, None, false),
+            Err(SuperblockError::OutdatedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn test_compression_must_match() {
+        let superblock = Superblock::new(128, Compression::Lz, None, false);
+        let decoded = Superblock::decode(superblock.encode(128).as_bytes()).unwrap();
+        assert_eq!(decoded.compression, Compression::Lz);
+        assert!(matches!(
+            decoded.validate(128, Compression::None, None, false),
+            Err(SuperblockError::CompressionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_key_must_match() {
+        let superblock = Superblock::new(128, Compression::None, Some([1; TAG_SIZE]), false);
+        let decoded = Superblock::decode(superblock.encode(128).as_bytes()).unwrap();
+        assert_eq!(decoded, superblock);
+        assert!(decoded
+            .validate(128, Compression::None, Some([1; TAG_SIZE]), false)
+            .is_ok());
+        assert!(matches!(
+            decoded.validate(128, Compression::None, Some([2; TAG_SIZE]), false),
+            Err(SuperblockError::WrongKey)
+        ));
+        assert!(matches!(
+            decoded.validate(128, Compression::None, None, false),
+            Err(SuperblockError::EncryptedDatabase)
+        ));
+    }
+
+    #[test]
+    fn test_logging_must_match() {
+        let superblock = Superblock::new(128, Compression::None, None, true);
+        let decoded = Superblock::decode(superblock.encode(128).as_bytes()).unwrap();
+        assert_eq!(decoded, superblock);
+        assert!(matches!(
+            decoded.validate(128, Compression::None, None, false),
+            Err(SuperblockError::LoggedDatabase)
+        ));
+        assert!(matches!(
+            Superblock::new(128, Compression::None, None, false).validate(
+                128,
+                Compression::None,
+                None,
+                true
+            ),
+            Err(SuperblockError::UnloggedDatabase)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_magic() {
+        assert!(matches!(
+            Superblock::decode(&[0u8; SUPERBLOCK_SIZE]),
+            Err(SuperblockError::InvalidMagic)
+        ));
+    }
+}
@@ -0,0 +1,838 @@
+use super::lock_manager::{LockError, LockManager, LockMode, LockTarget};
+use super::page::{Page, PageId};
+use super::page_manager::{PageGuard, PageManager, PageManagerError};
+use super::poison::LockRecover;
+use super::wal::{Lsn, TxnId, Wal, WalError, WalRecord};
+use crate::config::TransactionConfig;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TransactionError {
+    #[error("Page manager error: {0}")]
+    PageManagerError(#[from] PageManagerError),
+
+    #[error("WAL error: {0}")]
+    WalError(#[from] WalError),
+
+    #[error("Lock error: {0}")]
+    LockError(#[from] LockError),
+
+    #[error("Transactions need the write-ahead log")]
+    WalDisabled,
+
+    #[error("No such savepoint: {0}")]
+    NoSuchSavepoint(String),
+
+    #[error("Transaction {0} was rolled back after being idle too long")]
+    IdleTimeout(TxnId),
+
+    #[error("A transaction is already prepared as {0}")]
+    GidInUse(String),
+
+    #[error("No prepared transaction {0}")]
+    NoSuchPrepared(String),
+}
+
+/// A snapshot of a running transaction, from `TransactionManager::transactions`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionInfo {
+    pub id: TxnId,
+    pub started: SystemTime,
+    /// How long since the transaction was last used; zero while in use
+    pub idle: Duration,
+    /// The locks it holds
+    pub locks: Vec<(LockTarget, LockMode)>,
+    /// Bytes of log its records take up so far
+    pub wal_bytes: u64,
+    /// The global id it was prepared under, if any
+    pub gid: Option<String>,
+}
+
+/// Starts transactions over the pages of a logged database.
+///
+/// Transaction ids carry on from the highest one in the log, so they stay
+/// unique across restarts. With an idle timeout set, a background thread
+/// rolls back transactions left unused for longer, releasing their locks;
+/// their next use fails with `TransactionError::IdleTimeout`.
+///
+/// For two-phase commit, a transaction can be prepared under a global id,
+/// after which it survives restarts undecided, holding its locks, until
+/// `commit_prepared` or `rollback_prepared` is called with that id.
+pub struct TransactionManager {
+    shared: Arc<Shared>,
+    /// Stops its thread when dropped
+    _reaper: Option<IdleReaper>,
+}
+
+impl TransactionManager {
+    pub fn new(
+        pages: Arc<PageManager>,
+        config: TransactionConfig,
+    ) -> Result<Self, TransactionError> {
+        let wal = pages.wal().ok_or(TransactionError::WalDisabled)?;
+        let last_txn = AtomicU64::new(wal.last_txn().0);
+        let lock_timeout = config.lock_timeout_ms.map(Duration::from_millis);
+        let shared = Arc::new(Shared {
+            locks: LockManager::new(lock_timeout),
+            last_txn,
+            running: Mutex::new(HashMap::new()),
+            prepared: Mutex::new(HashMap::new()),
+            pages,
+        });
+        for recovered in &shared.pages.recovery().prepared {
+            let state = TxnState::new(recovered.txn, recovered.first);
+            state.gid.set(recovered.gid.clone()).unwrap();
+            // Nothing else is running yet, so these can't conflict
+            for &(target, mode) in &recovered.locks {
+                shared.locks.lock(recovered.txn, target, mode)?;
+            }
+            let state = Arc::new(state);
+            shared
+                .running
+                .lock_recover()
+                .insert(state.id, state.clone());
+            shared
+                .prepared
+                .lock_recover()
+                .insert(recovered.gid.clone(), state);
+        }
+        let reaper = config
+            .idle_timeout_ms
+            .map(|timeout| IdleReaper::spawn(shared.clone(), Duration::from_millis(timeout)));
+        Ok(Self {
+            shared,
+            _reaper: reaper,
+        })
+    }
+
+    /// Start a new transaction.
+    pub fn begin(&self) -> Result<Transaction, TransactionError> {
+        let shared = &self.shared;
+        let id = TxnId(shared.last_txn.fetch_add(1, Ordering::Relaxed) + 1);
+        let begin = shared.wal().append(&WalRecord::Begin { txn: id })?;
+        let state = Arc::new(TxnState::new(id, begin));
+        shared.running.lock_recover().insert(id, state.clone());
+        Ok(Transaction {
+            shared: shared.clone(),
+            state,
+            savepoints: Vec::new(),
+            finished: false,
+        })
+    }
+
+    /// The transactions still running, in id order.
+    pub fn transactions(&self) -> Vec<TransactionInfo> {
+        let running: Vec<_> = self
+            .shared
+            .running
+            .lock_recover()
+            .values()
+            .cloned()
+            .collect();
+        let mut transactions: Vec<_> = running
+            .iter()
+            .map(|state| TransactionInfo {
+                id: state.id,
+                started: state.started,
+                idle: match state.activity.try_lock() {
+                    Ok(activity) => activity.last_used.elapsed(),
+                    Err(_) => Duration::ZERO,
+                },
+                locks: self.shared.locks.held(state.id),
+                wal_bytes: self.shared.wal().txn_bytes(state.id).unwrap_or(0),
+                gid: state.gid.get().cloned(),
+            })
+            .collect();
+        transactions.sort_by_key(|info| info.id);
+        transactions
+    }
+
+    /// The global ids of the prepared transactions, sorted.
+    pub fn prepared(&self) -> Vec<String> {
+        let mut gids: Vec<_> = self
+            .shared
+            .prepared
+            .lock_recover()
+            .keys()
+            .cloned()
+            .collect();
+        gids.sort();
+        gids
+    }
+
+    /// Commit the transaction prepared as `gid`, waiting for the commit to
+    /// be durable.
+    pub fn commit_prepared(&self, gid: &str) -> Result<Lsn, TransactionError> {
+        let state = self.shared.take_prepared(gid)?;
+        let _busy = state.enter()?;
+        let wal = self.shared.wal();
+        let lsn = wal.append(&WalRecord::commit(state.id));
+        let result = lsn.and_then(|lsn| wal.flush(lsn).map(|()| lsn));
+        self.shared.finish(state.id);
+        Ok(result?)
+    }
+
+    /// Roll back the transaction prepared as `gid`.
+    pub fn rollback_prepared(&self, gid: &str) -> Result<(), TransactionError> {
+        let state = self.shared.take_prepared(gid)?;
+        let _busy = state.enter()?;
+        self.shared.abort(&state)
+    }
+
+    pub fn pages(&self) -> &Arc<PageManager> {
+        &self.shared.pages
+    }
+
+    #[cfg(test)]
+    pub fn locks(&self) -> &LockManager {
+        &self.shared.locks
+    }
+}
+
+/// State shared by the manager, its transactions and the idle reaper.
+struct Shared {
+    pages: Arc<PageManager>,
+    locks: LockManager,
+    last_txn: AtomicU64,
+    running: Mutex<HashMap<TxnId, Arc<TxnState>>>,
+    /// The prepared transactions, by global id; also in `running`
+    prepared: Mutex<HashMap<String, Arc<TxnState>>>,
+}
+
+impl Shared {
+    fn wal(&self) -> &Wal {
+        self.pages
+            .wal()
+            .expect("the transaction manager checks the log is enabled")
+    }
+
+    /// Restore the before image of each of a transaction's changes from
+    /// `from` on, read back from the log. The restores are logged like any
+    /// other change, so an abort interrupted by a crash is finished by
+    /// recovery, and a later rollback undoes them in turn.
+    fn undo_from(&self, txn: TxnId, from: Lsn) -> Result<(), TransactionError> {
+        let mut changes = Vec::new();
+        for entry in self.wal().read_from(from)? {
+            let (_, record) = entry?;
+            match record {
+                WalRecord::PageWrite {
+                    txn: owner,
+                    page_id,
+                    before,
+                    ..
+                } if owner == txn => changes.push((page_id, before)),
+                _ => {}
+            }
+        }
+        for (page_id, before) in changes.into_iter().rev() {
+            self.pages.log_write(txn, page_id, Page::new(before))?;
+        }
+        Ok(())
+    }
+
+    /// Undo all of a transaction's changes, log the abort and forget it.
+    fn abort(&self, state: &TxnState) -> Result<(), TransactionError> {
+        let result = self.undo_from(state.id, state.begin).and_then(|()| {
+            self.wal().append(&WalRecord::Abort { txn: state.id })?;
+            Ok(())
+        });
+        // Recovery undoes whatever couldn't be rolled back here
+        self.finish(state.id);
+        result
+    }
+
+    /// Log that a transaction is prepared as `gid`, holding its current
+    /// locks, and wait for that to be durable.
+    fn prepare(&self, state: &Arc<TxnState>, gid: &str) -> Result<(), TransactionError> {
+        let mut prepared = self.prepared.lock_recover();
+        if prepared.contains_key(gid) {
+            return Err(TransactionError::GidInUse(gid.to_string()));
+        }
+        let wal = self.wal();
+        let lsn = wal.append(&WalRecord::Prepare {
+            txn: state.id,
+            gid: gid.to_string(),
+            locks: self.locks.held(state.id),
+        })?;
+        wal.flush(lsn)?;
+        state.gid.set(gid.to_string()).unwrap();
+        prepared.insert(gid.to_string(), state.clone());
+        Ok(())
+    }
+
+    fn take_prepared(&self, gid: &str) -> Result<Arc<TxnState>, TransactionError> {
+        self.prepared
+            .lock_recover()
+            .remove(gid)
+            .ok_or_else(|| TransactionError::NoSuchPrepared(gid.to_string()))
+    }
+
+    /// Release a finished transaction's locks and stop tracking it.
+    fn finish(&self, txn: TxnId) {
+        self.locks.release_all(txn);
+        self.running.lock_recover().remove(&txn);
+    }
+
+    /// Roll back the transactions unused for at least `timeout`. Those in
+    /// use are skipped, even if blocked on a lock, as are prepared ones,
+    /// which wait for a decision however long it takes.
+    fn abort_idle(&self, timeout: Duration) {
+        let running: Vec<_> = self.running.lock_recover().values().cloned().collect();
+        for state in running.iter().filter(|state| state.gid.get().is_none()) {
+            let Ok(mut activity) = state.activity.try_lock() else {
+                continue;
+            };
+            if !activity.timed_out && activity.last_used.elapsed() >= timeout {
+                // Holding `activity` keeps the owner out until it's done
+                activity.timed_out = true;
+                let _ = self.abort(state);
+            }
+        }
+    }
+}
+
+/// What the manager tracks about a running transaction.
+struct TxnState {
+    id: TxnId,
+    /// The LSN of the transaction's begin record, which all its changes
+    /// follow
+    begin: Lsn,
+    started: SystemTime,
+    /// Locked while the transaction is in use
+    activity: Mutex<Activity>,
+    /// The global id, once prepared
+    gid: OnceLock<String>,
+}
+
+struct Activity {
+    last_used: Instant,
+    /// Whether the transaction was rolled back for being idle
+    timed_out: bool,
+}
+
+impl TxnState {
+    fn new(id: TxnId, begin: Lsn) -> Self {
+        Self {
+            id,
+            begin,
+            started: SystemTime::now(),
+            activity: Mutex::new(Activity {
+                last_used: Instant::now(),
+                timed_out: false,
+            }),
+            gid: OnceLock::new(),
+        }
+    }
+
+    /// Mark the transaction in use until the guard is dropped. Fails if it
+    /// was rolled back for being idle.
+    fn enter(&self) -> Result<Busy<'_>, TransactionError> {
+        let activity = self.activity.lock_recover();
+        if activity.timed_out {
+            return Err(TransactionError::IdleTimeout(self.id));
+        }
+        Ok(Busy(activity))
+    }
+}
+
+/// Keeps a transaction marked in use, and records when it stopped.
+struct Busy<'a>(MutexGuard<'a, Activity>);
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        self.0.last_used = Instant::now();
+    }
+}
+
+/// Periodically rolls back transactions that have been idle too long.
+struct IdleReaper {
+    shutdown: Option<Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl IdleReaper {
+    fn spawn(shared: Arc<Shared>, timeout: Duration) -> Self {
+        let (shutdown, receiver) = mpsc::channel::<()>();
+        // Check often enough that transactions don't overstay by much
+        let interval = (timeout / 4).max(Duration::from_millis(1));
+        let worker = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                shared.abort_idle(timeout);
+            }
+        });
+        Self {
+            shutdown: Some(shutdown),
+            worker: Some(worker),
+        }
+    }
+}
+
+impl Drop for IdleReaper {
+    fn drop(&mut self) {
+        self.shutdown.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// A unit of work whose page changes either all survive or are all undone.
+///
+/// Changes are logged as they are made and are visible to other readers
+/// straight away. Committing makes them durable with a single log flush;
+/// rolling back, or dropping the transaction unfinished, restores every
+/// page it changed.
+///
+/// Callers lock the tables they touch with `lock`; locks are held until
+/// the transaction finishes.
+pub struct Transaction {
+    shared: Arc<Shared>,
+    state: Arc<TxnState>,
+    /// Named points in the log the transaction can roll back to, oldest
+    /// first
+    savepoints: Vec<(String, Lsn)>,
+    finished: bool,
+}
+
+impl Transaction {
+    pub fn id(&self) -> TxnId {
+        self.state.id
+    }
+
+    /// Lock a row or table until the transaction finishes. Fails, leaving
+    /// the transaction to be rolled back, on a deadlock or timeout.
+    pub fn lock(&self, target: LockTarget, mode: LockMode) -> Result<(), TransactionError> {
+        let _busy = self.state.enter()?;
+        Ok(self.shared.locks.lock(self.id(), target, mode)?)
+    }
+
+    /// Pin a page to read it.
+    pub fn read(&self, page_id: PageId) -> Result<PageGuard, TransactionError> {
+        let _busy = self.state.enter()?;
+        Ok(self.shared.pages.get_page(page_id)?)
+    }
+
+    /// Replace a page, logging the change. Returns the LSN of its record.
+    pub fn write(&mut self, page_id: PageId, page: Page) -> Result<Lsn, TransactionError> {
+        let _busy = self.state.enter()?;
+        Ok(self.shared.pages.log_write(self.id(), page_id, page)?)
+    }
+
+    /// Mark the current point, so the changes after it can be undone on
+    /// their own with `rollback_to`. A savepoint with the same name as an
+    /// earlier one hides it until released.
+    pub fn savepoint(&mut self, name: &str) {
+        let lsn = self.shared.wal().end();
+        self.savepoints.push((name.to_string(), lsn));
+    }
+
+    /// Undo the changes made since the savepoint `name`, newest first. The
+    /// savepoint itself is kept and later ones are forgotten. Locks taken
+    /// since are still held.
+    pub fn rollback_to(&mut self, name: &str) -> Result<(), TransactionError> {
+        let _busy = self.state.enter()?;
+        let index = self.find_savepoint(name)?;
+        self.savepoints.truncate(index + 1);
+        self.shared.undo_from(self.id(), self.savepoints[index].1)
+    }
+
+    /// Forget the savepoint `name` and every one after it, keeping their
+    /// changes.
+    pub fn release(&mut self, name: &str) -> Result<(), TransactionError> {
+        let index = self.find_savepoint(name)?;
+        self.savepoints.truncate(index);
+        Ok(())
+    }
+
+    fn find_savepoint(&self, name: &str) -> Result<usize, TransactionError> {
+        self.savepoints
+            .iter()
+            .rposition(|(savepoint, _)| savepoint == name)
+            .ok_or_else(|| TransactionError::NoSuchSavepoint(name.to_string()))
+    }
+
+    /// Log the commit and wait for it to be durable. If that fails, whether
+    /// the transaction committed is only known after recovery.
+    pub fn commit(mut self) -> Result<Lsn, TransactionError> {
+        self.finished = true;
+        let _busy = self.state.enter()?;
+        let wal = self.shared.wal();
+        let lsn = wal.append(&WalRecord::commit(self.id()));
+        let result = lsn.and_then(|lsn| wal.flush(lsn).map(|()| lsn));
+        self.shared.finish(self.id());
+        Ok(result?)
+    }
+
+    /// Prepare to commit, as the first phase of two-phase commit: log that
+    /// the transaction is prepared as `gid`, with the locks it holds, and
+    /// wait for that to be durable. From then on it is finished with
+    /// `TransactionManager::commit_prepared` or `rollback_prepared`, even
+    /// after a restart. If preparing fails, the transaction is rolled back.
+    pub fn prepare(mut self, gid: &str) -> Result<(), TransactionError> {
+        self.finished = true;
+        let _busy = self.state.enter()?;
+        let result = self.shared.prepare(&self.state, gid);
+        if result.is_err() {
+            let _ = self.shared.abort(&self.state);
+        }
+        result
+    }
+
+    /// Undo every change, newest first, and log the abort.
+    pub fn rollback(mut self) -> Result<(), TransactionError> {
+        self.finished = true;
+        let _busy = match self.state.enter() {
+            // Already rolled back
+            Err(TransactionError::IdleTimeout(_)) => return Ok(()),
+            busy => busy?,
+        };
+        self.shared.abort(&self.state)
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if let Ok(_busy) = self.state.enter() {
+            let _ = self.shared.abort(&self.state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WalConfig;
+    use crate::storage::file_manager::FileId;
+    use crate::storage::page_manager::PageManagerBuilder;
+    use std::path::Path;
+    use std::thread;
+
+    fn open(dir: &Path) -> TransactionManager {
+        open_with(
+            dir,
+            TransactionConfig {
+                lock_timeout_ms: Some(10),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn open_with(dir: &Path, config: TransactionConfig) -> TransactionManager {
+        let pages = PageManagerBuilder::new(dir)
+            .page_size(128)
+            .wal(Some(WalConfig {
+                segment_size: 1 << 20,
+                checkpoint_interval_ms: None,
+                commit_delay_us: None,
+                retention_ms: None,
+                archive_dir: None,
+                full_page_writes: true,
+            }))
+            .build()
+            .unwrap();
+        if pages.files().file(FileId(1)).is_err() {
+            pages.create_file().unwrap();
+        }
+        TransactionManager::new(Arc::new(pages), config).unwrap()
+    }
+
+    fn page(page_no: u64) -> PageId {
+        PageId::new(FileId(1), page_no)
+    }
+
+    fn full(manager: &TransactionManager, value: u8) -> Page {
+        Page::full(value, manager.pages().page_size())
+    }
+
+    fn read(manager: &TransactionManager, page_no: u64) -> Page {
+        let guard = manager.pages().get_page(page(page_no)).unwrap();
+        let bytes = guard.page().as_bytes().to_vec();
+        Page::new(bytes)
+    }
+
+    #[test]
+    fn test_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        let mut txn = manager.begin().unwrap();
+        txn.write(page(0), full(&manager, 1)).unwrap();
+        assert_eq!(*txn.read(page(0)).unwrap().page(), full(&manager, 1));
+        let lsn = txn.commit().unwrap();
+        assert!(manager.pages().wal().unwrap().flushed() > lsn);
+        drop(manager);
+
+        // The commit survives a crash that loses the dirty page
+        let manager = open(dir.path());
+        assert!(manager.pages().recovery().losers.is_empty());
+        assert_eq!(read(&manager, 0), full(&manager, 1));
+    }
+
+    #[test]
+    fn test_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        let mut txn = manager.begin().unwrap();
+        txn.write(page(0), full(&manager, 1)).unwrap();
+        txn.commit().unwrap();
+
+        let mut txn = manager.begin().unwrap();
+        txn.write(page(0), full(&manager, 2)).unwrap();
+        txn.write(page(0), full(&manager, 3)).unwrap();
+        txn.write(page(1), full(&manager, 4)).unwrap();
+        txn.rollback().unwrap();
+        assert_eq!(read(&manager, 0), full(&manager, 1));
+        assert_eq!(read(&manager, 1), full(&manager, 0));
+
+        // Dropping an unfinished transaction rolls it back too
+        let mut txn = manager.begin().unwrap();
+        txn.write(page(1), full(&manager, 5)).unwrap();
+        drop(txn);
+        assert_eq!(read(&manager, 1), full(&manager, 0));
+        drop(manager);
+
+        // Nothing is left for recovery to undo
+        let manager = open(dir.path());
+        assert!(manager.pages().recovery().losers.is_empty());
+        assert_eq!(read(&manager, 0), full(&manager, 1));
+        assert_eq!(read(&manager, 1), full(&manager, 0));
+    }
+
+    #[test]
+    fn test_ids_are_unique_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        let first = manager.begin().unwrap();
+        let second = manager.begin().unwrap();
+        assert!(second.id() > first.id());
+        let last = second.id();
+        first.commit().unwrap();
+        second.commit().unwrap();
+        manager.pages().checkpoint().unwrap();
+        drop(manager);
+
+        // The checkpoint carries the last id past the log it replaced
+        let manager = open(dir.path());
+        assert!(manager.begin().unwrap().id() > last);
+        drop(manager);
+        let manager = open(dir.path());
+        assert!(manager.begin().unwrap().id() > TxnId(last.0 + 1));
+    }
+
+    #[test]
+    fn test_requires_wal() {
+        let dir = tempfile::tempdir().unwrap();
+        let pages = PageManagerBuilder::new(dir.path()).build().unwrap();
+        assert!(matches!(
+            TransactionManager::new(Arc::new(pages), TransactionConfig::default()),
+            Err(TransactionError::WalDisabled)
+        ));
+    }
+
+    #[test]
+    fn test_locks_are_held_until_finished() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        let table = LockTarget::Table(FileId(1));
+        let first = manager.begin().unwrap();
+        first.lock(table, LockMode::Exclusive).unwrap();
+
+        let second = manager.begin().unwrap();
+        assert!(matches!(
+            second.lock(table, LockMode::Shared),
+            Err(TransactionError::LockError(LockError::Timeout(_)))
+        ));
+        let id = first.id();
+        first.commit().unwrap();
+        assert!(manager.locks().held(id).is_empty());
+        second.lock(table, LockMode::Shared).unwrap();
+
+        // Rolling back, even by dropping, releases them too
+        let id = second.id();
+        drop(second);
+        assert!(manager.locks().held(id).is_empty());
+    }
+
+    #[test]
+    fn test_savepoints() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        let mut txn = manager.begin().unwrap();
+        txn.write(page(0), full(&manager, 1)).unwrap();
+        txn.savepoint("a");
+        txn.write(page(0), full(&manager, 2)).unwrap();
+        txn.savepoint("b");
+        txn.write(page(1), full(&manager, 3)).unwrap();
+
+        txn.rollback_to("a").unwrap();
+        assert_eq!(read(&manager, 0), full(&manager, 1));
+        assert_eq!(read(&manager, 1), full(&manager, 0));
+        // Rolling back to "a" forgot "b" but kept "a"
+        assert!(matches!(
+            txn.rollback_to("b"),
+            Err(TransactionError::NoSuchSavepoint(name)) if name == "b"
+        ));
+        txn.write(page(1), full(&manager, 4)).unwrap();
+        txn.rollback_to("a").unwrap();
+        assert_eq!(read(&manager, 1), full(&manager, 0));
+
+        txn.write(page(1), full(&manager, 5)).unwrap();
+        txn.release("a").unwrap();
+        assert!(txn.release("a").is_err());
+        txn.commit().unwrap();
+        drop(manager);
+
+        let manager = open(dir.path());
+        assert_eq!(read(&manager, 0), full(&manager, 1));
+        assert_eq!(read(&manager, 1), full(&manager, 5));
+    }
+
+    #[test]
+    fn test_rollback_after_rollback_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        let mut txn = manager.begin().unwrap();
+        txn.write(page(0), full(&manager, 1)).unwrap();
+        txn.savepoint("a");
+        txn.write(page(0), full(&manager, 2)).unwrap();
+        txn.rollback_to("a").unwrap();
+        txn.rollback().unwrap();
+        assert_eq!(read(&manager, 0), full(&manager, 0));
+    }
+
+    #[test]
+    fn test_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        let idle = manager.begin().unwrap();
+        let mut txn = manager.begin().unwrap();
+        let table = LockTarget::Table(FileId(1));
+        txn.lock(table, LockMode::Exclusive).unwrap();
+        txn.write(page(0), full(&manager, 1)).unwrap();
+
+        let transactions = manager.transactions();
+        assert_eq!(transactions.len(), 2);
+        let (first, second) = (&transactions[0], &transactions[1]);
+        assert_eq!(first.id, idle.id());
+        assert!(first.locks.is_empty());
+        assert!(first.started <= second.started);
+        assert_eq!(second.id, txn.id());
+        assert_eq!(second.locks, manager.locks().held(txn.id()));
+        // The log holds both page images
+        assert!(second.wal_bytes > 2 * manager.pages().page_size() as u64);
+        assert!(second.wal_bytes > first.wal_bytes);
+
+        txn.commit().unwrap();
+        drop(idle);
+        assert!(manager.transactions().is_empty());
+    }
+
+    #[test]
+    fn test_idle_transactions_are_rolled_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open_with(
+            dir.path(),
+            TransactionConfig {
+                idle_timeout_ms: Some(10),
+                ..Default::default()
+            },
+        );
+        let mut txn = manager.begin().unwrap();
+        let table = LockTarget::Table(FileId(1));
+        txn.lock(table, LockMode::Exclusive).unwrap();
+        txn.write(page(0), full(&manager, 1)).unwrap();
+        while !manager.transactions().is_empty() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(read(&manager, 0), full(&manager, 0));
+        assert!(manager.locks().held(txn.id()).is_empty());
+        assert!(matches!(
+            txn.write(page(0), full(&manager, 2)),
+            Err(TransactionError::IdleTimeout(id)) if id == txn.id()
+        ));
+        assert!(matches!(
+            txn.commit(),
+            Err(TransactionError::IdleTimeout(_))
+        ));
+
+        // Rolling back what was already rolled back succeeds
+        let txn = manager.begin().unwrap();
+        while !manager.transactions().is_empty() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        txn.rollback().unwrap();
+    }
+
+    #[test]
+    fn test_prepared_transactions_survive_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        let table = LockTarget::Table(FileId(1));
+        for (gid, value) in [("commit-me", 1), ("roll-me-back", 2)] {
+            let mut txn = manager.begin().unwrap();
+            txn.lock(table, LockMode::Shared).unwrap();
+            txn.write(page(value), full(&manager, value as u8)).unwrap();
+            txn.prepare(gid).unwrap();
+        }
+        assert_eq!(manager.prepared(), vec!["commit-me", "roll-me-back"]);
+        // A checkpoint mustn't drop the log the prepared transactions need
+        manager.pages().checkpoint().unwrap();
+        drop(manager);
+
+        let manager = open(dir.path());
+        assert!(manager.pages().recovery().losers.is_empty());
+        assert_eq!(manager.prepared(), vec!["commit-me", "roll-me-back"]);
+        let transactions = manager.transactions();
+        assert_eq!(transactions[0].gid.as_deref(), Some("commit-me"));
+        // Their locks were taken again
+        let other = manager.begin().unwrap();
+        assert!(other.lock(table, LockMode::Exclusive).is_err());
+        drop(other);
+        manager.pages().checkpoint().unwrap();
+        drop(manager);
+
+        let manager = open(dir.path());
+        manager.commit_prepared("commit-me").unwrap();
+        manager.rollback_prepared("roll-me-back").unwrap();
+        assert!(matches!(
+            manager.commit_prepared("roll-me-back"),
+            Err(TransactionError::NoSuchPrepared(_))
+        ));
+        assert!(manager.prepared().is_empty());
+        assert!(manager.transactions().is_empty());
+        let other = manager.begin().unwrap();
+        other.lock(table, LockMode::Exclusive).unwrap();
+        drop(other);
+        drop(manager);
+
+        let manager = open(dir.path());
+        assert!(manager.prepared().is_empty());
+        assert_eq!(read(&manager, 1), full(&manager, 1));
+        assert_eq!(read(&manager, 2), full(&manager, 0));
+    }
+
+    #[test]
+    fn test_gids_are_unique() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = open(dir.path());
+        manager.begin().unwrap().prepare("gid").unwrap();
+        let mut txn = manager.begin().unwrap();
+        txn.write(page(0), full(&manager, 1)).unwrap();
+        assert!(matches!(
+            txn.prepare("gid"),
+            Err(TransactionError::GidInUse(gid)) if gid == "gid"
+        ));
+        // The failed transaction was rolled back
+        assert_eq!(read(&manager, 0), full(&manager, 0));
+        assert_eq!(manager.transactions().len(), 1);
+    }
+}
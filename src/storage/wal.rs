@@ -0,0 +1,1645 @@
+use super::checksum::crc32;
+use super::faults::{FaultInjector, FaultPoint, Injected};
+use super::file_manager::FileId;
+use super::lock_manager::{LockMode, LockTarget};
+use super::page::PageId;
+use super::stats::{WalCounters, WalStats};
+use crate::config::{Durability, IN_MEMORY};
+use crate::logging::{log, span};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Directory under the database root holding the log segments.
+pub const WAL_DIR_NAME: &str = "wal";
+
+const SEGMENT_EXTENSION: &str = "wal";
+const SEGMENT_MAGIC: [u8; 8] = *b"FDBWAL\0\x01";
+/// Magic, the LSN the segment starts at and its sequence number.
+const SEGMENT_HEADER_SIZE: u64 = 24;
+/// Payload length, segment sequence number and CRC.
+const FRAME_HEADER_SIZE: usize = 12;
+
+/// A position in the log. Each record is identified by the LSN it starts at,
+/// and LSNs only ever grow, so they also order records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Lsn(pub u64);
+
+impl Lsn {
+    /// Before every record. A page with this LSN hasn't been changed
+    /// through the log.
+    pub const ZERO: Lsn = Lsn(0);
+}
+
+impl Display for Lsn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Identifies the transaction a log record belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct TxnId(pub u64);
+
+impl Display for TxnId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Microseconds from the Unix epoch to `time`, or 0 for times before it.
+pub fn micros_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as u64)
+}
+
+#[derive(Debug, Error)]
+pub enum WalError {
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Invalid log segment {0}")]
+    InvalidSegment(PathBuf),
+
+    #[error("Corrupted log record at LSN {0}")]
+    Corrupted(Lsn),
+
+    #[error("The log only follows another one and can't be written to")]
+    ReadOnly,
+
+    #[error("Record at LSN {0} doesn't follow on from this log")]
+    Diverged(Lsn),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalRecord {
+    Begin {
+        txn: TxnId,
+    },
+    Commit {
+        txn: TxnId,
+        /// Microseconds since the Unix epoch when it committed
+        time: u64,
+    },
+    Abort {
+        txn: TxnId,
+    },
+    /// A page changed by `txn`, with its full contents before and after so
+    /// the change can be both redone and undone.
+    PageWrite {
+        txn: TxnId,
+        page_id: PageId,
+        before: Vec<u8>,
+        after: Vec<u8>,
+    },
+    /// `txn` is prepared to commit under the global id `gid`, and must
+    /// survive a crash undecided, still holding `locks`, until it is
+    /// committed or rolled back.
+    Prepare {
+        txn: TxnId,
+        gid: String,
+        locks: Vec<(LockTarget, LockMode)>,
+    },
+    /// Every change logged before `redo_from` was on disk when this record
+    /// was written. `active` lists the transactions still running then,
+    /// with the LSN of each one's first record. `last_txn` is the highest
+    /// transaction id logged so far, so ids aren't reused once the older
+    /// log is removed, and `time` when it was taken, in microseconds since
+    /// the Unix epoch.
+    Checkpoint {
+        redo_from: Lsn,
+        active: Vec<(TxnId, Lsn)>,
+        last_txn: TxnId,
+        time: u64,
+    },
+}
+
+const RECORD_BEGIN: u8 = 1;
+const RECORD_COMMIT: u8 = 2;
+const RECORD_ABORT: u8 = 3;
+const RECORD_PAGE_WRITE: u8 = 4;
+const RECORD_CHECKPOINT: u8 = 5;
+const RECORD_PREPARE: u8 = 6;
+/// A page write logged as the bytes changed since the page's last image
+const RECORD_PAGE_DELTA: u8 = 7;
+/// Changes fewer than this many bytes apart are logged as one
+const DELTA_GAP: usize = 8;
+
+const LOCK_MODES: [LockMode; 2] = [LockMode::Shared, LockMode::Exclusive];
+
+impl WalRecord {
+    /// A commit of `txn` stamped with the current time.
+    pub fn commit(txn: TxnId) -> Self {
+        WalRecord::Commit {
+            txn,
+            time: micros_since_epoch(SystemTime::now()),
+        }
+    }
+
+    /// The transaction the record belongs to; checkpoints belong to none.
+    pub fn txn(&self) -> Option<TxnId> {
+        match self {
+            WalRecord::Begin { txn }
+            | WalRecord::Commit { txn, .. }
+            | WalRecord::Abort { txn }
+            | WalRecord::PageWrite { txn, .. }
+            | WalRecord::Prepare { txn, .. } => Some(*txn),
+            WalRecord::Checkpoint { .. } => None,
+        }
+    }
+
+    /// Where recovery must start reading for a checkpoint: early enough to
+    /// redo what was missing from disk and to undo every transaction that
+    /// was running.
+    pub fn recovery_start(&self) -> Option<Lsn> {
+        match self {
+            WalRecord::Checkpoint {
+                redo_from, active, ..
+            } => Some(
+                active
+                    .iter()
+                    .map(|&(_, first)| first)
+                    .fold(*redo_from, Lsn::min),
+            ),
+            _ => None,
+        }
+    }
+
+    pub(super) fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        let kind = match self {
+            WalRecord::Begin { .. } => RECORD_BEGIN,
+            WalRecord::Commit { .. } => RECORD_COMMIT,
+            WalRecord::Abort { .. } => RECORD_ABORT,
+            WalRecord::PageWrite { .. } => RECORD_PAGE_WRITE,
+            WalRecord::Checkpoint { .. } => RECORD_CHECKPOINT,
+            WalRecord::Prepare { .. } => RECORD_PREPARE,
+        };
+        data.write_u8(kind).unwrap();
+        if let Some(txn) = self.txn() {
+            data.write_u64::<BigEndian>(txn.0).unwrap();
+        }
+        match self {
+            WalRecord::PageWrite {
+                page_id,
+                before,
+                after,
+                ..
+            } => {
+                data.write_u32::<BigEndian>(page_id.file.0).unwrap();
+                data.write_u64::<BigEndian>(page_id.page_no).unwrap();
+                for image in [before, after] {
+                    data.write_u32::<BigEndian>(image.len() as u32).unwrap();
+                    data.extend_from_slice(image);
+                }
+            }
+            WalRecord::Checkpoint {
+                redo_from,
+                active,
+                last_txn,
+                time,
+            } => {
+                data.write_u64::<BigEndian>(redo_from.0).unwrap();
+                data.write_u64::<BigEndian>(last_txn.0).unwrap();
+                data.write_u64::<BigEndian>(*time).unwrap();
+                data.write_u32::<BigEndian>(active.len() as u32).unwrap();
+                for (txn, first) in active {
+                    data.write_u64::<BigEndian>(txn.0).unwrap();
+                    data.write_u64::<BigEndian>(first.0).unwrap();
+                }
+            }
+            WalRecord::Commit { time, .. } => {
+                data.write_u64::<BigEndian>(*time).unwrap();
+            }
+            WalRecord::Prepare { gid, locks, .. } => {
+                data.write_u32::<BigEndian>(gid.len() as u32).unwrap();
+                data.extend_from_slice(gid.as_bytes());
+                data.write_u32::<BigEndian>(locks.len() as u32).unwrap();
+                for &(LockTarget::Table(table), mode) in locks {
+                    data.write_u32::<BigEndian>(table.0).unwrap();
+                    let mode = LOCK_MODES.iter().position(|&m| m == mode).unwrap();
+                    data.write_u8(mode as u8).unwrap();
+                }
+            }
+            _ => {}
+        }
+        data
+    }
+
+    /// The page write encoded as the ranges of bytes it changes, if it
+    /// starts from `image`, the page's last logged image.
+    fn encode_delta(&self, image: &[u8]) -> Option<Vec<u8>> {
+        let WalRecord::PageWrite {
+            txn,
+            page_id,
+            before,
+            after,
+        } = self
+        else {
+            return None;
+        };
+        if before.as_slice() != image || before.len() != after.len() {
+            return None;
+        }
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for i in (0..after.len()).filter(|&i| before[i] != after[i]) {
+            match ranges.last_mut() {
+                Some((_, end)) if i - *end < DELTA_GAP => *end = i + 1,
+                _ => ranges.push((i, i + 1)),
+            }
+        }
+        let mut data = vec![RECORD_PAGE_DELTA];
+        data.write_u64::<BigEndian>(txn.0).unwrap();
+        data.write_u32::<BigEndian>(page_id.file.0).unwrap();
+        data.write_u64::<BigEndian>(page_id.page_no).unwrap();
+        data.write_u32::<BigEndian>(ranges.len() as u32).unwrap();
+        for (start, end) in ranges {
+            data.write_u32::<BigEndian>(start as u32).unwrap();
+            data.write_u32::<BigEndian>((end - start) as u32).unwrap();
+            data.extend_from_slice(&after[start..end]);
+        }
+        Some(data)
+    }
+
+    pub(super) fn decode(data: &[u8]) -> io::Result<Self> {
+        Self::decode_with(data, &HashMap::new())
+    }
+
+    /// Decode a record that may be a page write logged as a change to one
+    /// of `images`, the last images of the pages logged before it.
+    fn decode_with(data: &[u8], images: &HashMap<PageId, Vec<u8>>) -> io::Result<Self> {
+        let mut cursor = Cursor::new(data);
+        let kind = cursor.read_u8()?;
+        if kind == RECORD_CHECKPOINT {
+            let redo_from = Lsn(cursor.read_u64::<BigEndian>()?);
+            let last_txn = TxnId(cursor.read_u64::<BigEndian>()?);
+            let time = cursor.read_u64::<BigEndian>()?;
+            let count = cursor.read_u32::<BigEndian>()?;
+            let mut active = Vec::new();
+            for _ in 0..count {
+                let txn = TxnId(cursor.read_u64::<BigEndian>()?);
+                active.push((txn, Lsn(cursor.read_u64::<BigEndian>()?)));
+            }
+            let record = WalRecord::Checkpoint {
+                redo_from,
+                active,
+                last_txn,
+                time,
+            };
+            return Self::finish_decode(cursor, record);
+        }
+        let txn = TxnId(cursor.read_u64::<BigEndian>()?);
+        let record = match kind {
+            RECORD_BEGIN => WalRecord::Begin { txn },
+            RECORD_COMMIT => WalRecord::Commit {
+                txn,
+                time: cursor.read_u64::<BigEndian>()?,
+            },
+            RECORD_ABORT => WalRecord::Abort { txn },
+            RECORD_PAGE_WRITE => {
+                let file = FileId(cursor.read_u32::<BigEndian>()?);
+                let page_no = cursor.read_u64::<BigEndian>()?;
+                let mut images = [Vec::new(), Vec::new()];
+                for image in &mut images {
+                    let len = cursor.read_u32::<BigEndian>()? as usize;
+                    image.resize(len, 0);
+                    cursor.read_exact(image)?;
+                }
+                let [before, after] = images;
+                WalRecord::PageWrite {
+                    txn,
+                    page_id: PageId::new(file, page_no),
+                    before,
+                    after,
+                }
+            }
+            RECORD_PAGE_DELTA => {
+                let file = FileId(cursor.read_u32::<BigEndian>()?);
+                let page_id = PageId::new(file, cursor.read_u64::<BigEndian>()?);
+                let before = images.get(&page_id).ok_or(io::ErrorKind::InvalidData)?;
+                let mut after = before.clone();
+                for _ in 0..cursor.read_u32::<BigEndian>()? {
+                    let start = cursor.read_u32::<BigEndian>()? as usize;
+                    let len = cursor.read_u32::<BigEndian>()? as usize;
+                    let range = after
+                        .get_mut(start..start + len)
+                        .ok_or(io::ErrorKind::InvalidData)?;
+                    cursor.read_exact(range)?;
+                }
+                WalRecord::PageWrite {
+                    txn,
+                    page_id,
+                    before: before.clone(),
+                    after,
+                }
+            }
+            RECORD_PREPARE => {
+                let len = cursor.read_u32::<BigEndian>()? as usize;
+                let mut gid = vec![0; len];
+                cursor.read_exact(&mut gid)?;
+                let gid = String::from_utf8(gid).map_err(|_| io::ErrorKind::InvalidData)?;
+                let count = cursor.read_u32::<BigEndian>()?;
+                let mut locks = Vec::new();
+                for _ in 0..count {
+                    let target = LockTarget::Table(FileId(cursor.read_u32::<BigEndian>()?));
+                    let mode = *LOCK_MODES
+                        .get(cursor.read_u8()? as usize)
+                        .ok_or(io::ErrorKind::InvalidData)?;
+                    locks.push((target, mode));
+                }
+                WalRecord::Prepare { txn, gid, locks }
+            }
+            _ => return Err(io::ErrorKind::InvalidData.into()),
+        };
+        Self::finish_decode(cursor, record)
+    }
+
+    /// Reject trailing bytes after a decoded record.
+    fn finish_decode(cursor: Cursor<&[u8]>, record: Self) -> io::Result<Self> {
+        if cursor.position() as usize != cursor.get_ref().len() {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        Ok(record)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WalOptions {
+    /// Start a new segment file once the current one reaches this many bytes
+    pub segment_size: u64,
+    /// With `Durability::Full`, `flush` syncs the log to stable storage;
+    /// otherwise it only hands it to the OS
+    pub durability: Durability,
+    /// How long a flush waits before syncing while other transactions are
+    /// running, so their commits can join the same sync
+    pub commit_delay: Option<Duration>,
+    /// Keep segments last written to within this long even once recovery
+    /// no longer needs them, so pages can still be read as they were
+    pub retention: Option<Duration>,
+    /// Copy each segment here once it is finished, and before it is
+    /// removed, so the log outlives the database's own copy
+    pub archive_dir: Option<PathBuf>,
+    /// Log a page whole the first time it changes after each checkpoint,
+    /// not just the first time in each segment
+    pub full_page_writes: bool,
+    /// Faults injected into writing and syncing the log, for tests
+    pub faults: Option<Arc<FaultInjector>>,
+}
+
+/// A transaction with records in the log but no commit or abort yet.
+#[derive(Debug, Clone, Copy)]
+struct ActiveTxn {
+    /// The LSN of its first record
+    first: Lsn,
+    /// Bytes of log its records take up
+    bytes: u64,
+}
+
+/// A segment of a log kept in memory.
+struct MemorySegment {
+    data: Vec<u8>,
+    modified: SystemTime,
+}
+
+impl MemorySegment {
+    fn new(data: Vec<u8>) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            data,
+            modified: SystemTime::now(),
+        }))
+    }
+}
+
+/// Where a log's segments are kept.
+enum Store {
+    /// Files in a directory
+    Dir(PathBuf),
+    /// In memory, by the LSN each starts at, for a log never written to disk
+    Memory(Mutex<BTreeMap<Lsn, Arc<Mutex<MemorySegment>>>>),
+}
+
+/// One of a log's segments, to be read.
+#[derive(Clone)]
+enum Segment {
+    File(PathBuf),
+    Memory(Arc<Mutex<MemorySegment>>),
+}
+
+impl Segment {
+    fn read(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Segment::File(path) => fs::read(path),
+            Segment::Memory(segment) => Ok(segment.lock().unwrap().data.clone()),
+        }
+    }
+
+    fn modified(&self) -> io::Result<SystemTime> {
+        match self {
+            Segment::File(path) => fs::metadata(path)?.modified(),
+            Segment::Memory(segment) => Ok(segment.lock().unwrap().modified),
+        }
+    }
+
+    /// The segment's file, or what stands for one in errors, with
+    /// `start` as its name.
+    fn path(&self, start: Lsn) -> PathBuf {
+        match self {
+            Segment::File(path) => path.clone(),
+            Segment::Memory(_) => Wal::segment_path(Path::new(IN_MEMORY), start),
+        }
+    }
+}
+
+/// Where the records of the segment being appended to go.
+enum SegmentWriter {
+    File(BufWriter<File>),
+    Memory(Arc<Mutex<MemorySegment>>),
+}
+
+impl SegmentWriter {
+    /// Hand what is buffered to the OS, and with `sync` have it write it
+    /// to disk too.
+    fn flush_and_sync(&mut self, sync: bool) -> io::Result<()> {
+        self.flush()?;
+        match self {
+            SegmentWriter::File(file) if sync => file.get_ref().sync_data(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Write for SegmentWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            SegmentWriter::File(file) => file.write(buf),
+            SegmentWriter::Memory(segment) => {
+                let mut segment = segment.lock().unwrap();
+                segment.data.extend_from_slice(buf);
+                segment.modified = SystemTime::now();
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SegmentWriter::File(file) => file.flush(),
+            SegmentWriter::Memory(_) => Ok(()),
+        }
+    }
+}
+
+/// The segment currently being appended to.
+struct Writer {
+    segment: SegmentWriter,
+    segment_start: Lsn,
+    /// One more than the segment before's
+    sequence: u64,
+    /// The image each page was last logged with, since the segment started
+    /// or, with full page writes, the last checkpoint; later writes of
+    /// those pages are logged as changes to them
+    images: HashMap<PageId, Vec<u8>>,
+    /// Where the next record will go
+    end: Lsn,
+    active: HashMap<TxnId, ActiveTxn>,
+    /// The highest transaction id logged so far
+    last_txn: TxnId,
+}
+
+struct SyncState {
+    /// Every record before this LSN is durable
+    flushed: Lsn,
+    /// Whether a thread is currently flushing on behalf of the others
+    syncing: bool,
+}
+
+/// An append-only log of changes, written ahead of the pages they affect.
+///
+/// The log is split into segment files named after the LSN they start at,
+/// so old segments can be removed whole, and optionally archived to another
+/// directory first. Each segment is numbered one on from the last, and each
+/// record is framed with its length, its segment's number and a CRC of
+/// both and the record. A torn record at the end of the log, left by a
+/// crash mid-append, fails its CRC, and a record left over from another
+/// segment has the wrong number, so either ends the log: it and anything
+/// after it are discarded on open rather than replayed. A log opened in
+/// memory keeps its segments there instead, and never archives them.
+///
+/// A page is logged whole, before and after, the first time it changes in
+/// a segment and, with full page writes, after each checkpoint. Until then
+/// each change is logged as the bytes it changed, and read back whole from
+/// the page's last image. So a page torn by a crash as it was written out
+/// is still replaced whole by recovery, which reads from a checkpoint.
+///
+/// Appends only buffer the record. `flush` makes it durable, and concurrent
+/// flushes are grouped: the first thread to flush leads, syncing for
+/// everything appended by the time it starts, while the others wait for it
+/// and return if their records were covered. A burst of commits so costs a
+/// single fsync. With a commit delay the leader waits a little first when
+/// other transactions are running, trading latency for larger groups.
+pub struct Wal {
+    store: Store,
+    options: WalOptions,
+    writer: Mutex<Writer>,
+    sync: Mutex<SyncState>,
+    synced: Condvar,
+    counters: WalCounters,
+    /// Set while the log is a replica's copy of another, only appended to
+    /// with `append_at`
+    read_only: AtomicBool,
+}
+
+impl Wal {
+    /// The log directory of the database directory at `root`.
+    pub fn dir(root: &Path) -> PathBuf {
+        root.join(WAL_DIR_NAME)
+    }
+
+    /// Open the log in `dir`, creating it if needed and continuing after the
+    /// last intact record.
+    pub fn open(dir: impl AsRef<Path>, options: WalOptions) -> Result<Self, WalError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut writer = match Self::segments(&dir)?.last() {
+            Some(&(segment_start, ref path)) => {
+                let data = fs::read(path)?;
+                let sequence = Self::check_header(path, &data, segment_start)?;
+                // Anything after the last intact record is a torn write
+                let mut end = SEGMENT_HEADER_SIZE as usize;
+                let (mut seen, mut images) = (HashMap::new(), HashMap::new());
+                while let Some((record, len)) = Self::read_frame(&data[end..], sequence, &mut seen)
+                {
+                    Self::remember(&mut images, &record, options.full_page_writes);
+                    end += len;
+                }
+                if end < data.len() {
+                    log!(
+                        Warn,
+                        "torn log record discarded",
+                        lsn = segment_start.0 + end as u64,
+                        bytes = data.len() - end,
+                    );
+                }
+                let mut file = OpenOptions::new().write(true).open(path)?;
+                file.set_len(end as u64)?;
+                file.seek(SeekFrom::End(0))?;
+                Writer {
+                    segment: SegmentWriter::File(BufWriter::new(file)),
+                    segment_start,
+                    sequence,
+                    images,
+                    end: Lsn(segment_start.0 + end as u64),
+                    active: HashMap::new(),
+                    last_txn: TxnId(0),
+                }
+            }
+            None => Self::create_segment(
+                &Store::Dir(dir.clone()),
+                Lsn::ZERO,
+                0,
+                HashMap::new(),
+                TxnId(0),
+            )?,
+        };
+        writer.segment.flush_and_sync(true)?;
+        Ok(Self::new(Store::Dir(dir), options, writer))
+    }
+
+    /// Open a new, empty log kept in memory, which is lost once it is
+    /// dropped.
+    pub fn open_in_memory(options: WalOptions) -> Result<Self, WalError> {
+        let store = Store::Memory(Mutex::default());
+        let writer = Self::create_segment(&store, Lsn::ZERO, 0, HashMap::new(), TxnId(0))?;
+        Ok(Self::new(store, options, writer))
+    }
+
+    fn new(store: Store, options: WalOptions, writer: Writer) -> Self {
+        let flushed = writer.end;
+        Self {
+            store,
+            options,
+            writer: Mutex::new(writer),
+            sync: Mutex::new(SyncState {
+                flushed,
+                syncing: false,
+            }),
+            synced: Condvar::new(),
+            counters: WalCounters::default(),
+            read_only: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether pages are logged whole after each checkpoint.
+    pub fn full_page_writes(&self) -> bool {
+        self.options.full_page_writes
+    }
+
+    /// Buffer `record`, returning its LSN. It isn't durable until `flush`ed.
+    pub fn append(&self, record: &WalRecord) -> Result<Lsn, WalError> {
+        let mut writer = self.writer.lock().unwrap();
+        self.append_locked(&mut writer, record)
+    }
+
+    /// Log a checkpoint whose changes before `redo_from` are all on disk,
+    /// and flush it. Returns the checkpoint record's LSN, and the LSN
+    /// recovery from it must start at.
+    pub fn checkpoint(&self, redo_from: Lsn) -> Result<(Lsn, Lsn), WalError> {
+        let (lsn, record) = {
+            let mut writer = self.writer.lock().unwrap();
+            let mut active: Vec<_> = writer
+                .active
+                .iter()
+                .map(|(&txn, active)| (txn, active.first))
+                .collect();
+            active.sort();
+            let record = WalRecord::Checkpoint {
+                redo_from,
+                active,
+                last_txn: writer.last_txn,
+                time: micros_since_epoch(SystemTime::now()),
+            };
+            (self.append_locked(&mut writer, &record)?, record)
+        };
+        self.flush(lsn)?;
+        Ok((lsn, record.recovery_start().unwrap()))
+    }
+
+    /// Delete the segments holding only records before `lsn`, which are no
+    /// longer needed for recovery. The current segment is always kept, as
+    /// are those still within the retention period. Returns how many were
+    /// removed.
+    pub fn remove_segments_before(&self, lsn: Lsn) -> Result<usize, WalError> {
+        let segments = self.store.segments()?;
+        let retained_since = self
+            .options
+            .retention
+            .and_then(|retention| SystemTime::now().checked_sub(retention));
+        let mut removed = 0;
+        // A segment ends where the next begins
+        for pair in segments.windows(2) {
+            let (start, segment) = &pair[0];
+            if pair[1].0 > lsn {
+                break;
+            }
+            if let Some(since) = retained_since {
+                if segment.modified()? >= since {
+                    break;
+                }
+            }
+            match (&self.store, segment) {
+                (Store::Memory(segments), _) => {
+                    segments.lock().unwrap().remove(start);
+                }
+                (Store::Dir(_), Segment::File(path)) => {
+                    // Normally archived when it was finished, unless that
+                    // failed
+                    if let Some(archive_dir) = &self.options.archive_dir {
+                        if !archive_dir.join(path.file_name().unwrap()).exists() {
+                            Self::archive(archive_dir, path)?;
+                        }
+                    }
+                    fs::remove_file(path)?;
+                }
+                (Store::Dir(_), Segment::Memory(_)) => {
+                    unreachable!("a directory store only keeps file segments")
+                }
+            }
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    fn append_locked(&self, writer: &mut Writer, record: &WalRecord) -> Result<Lsn, WalError> {
+        if self.read_only.load(Ordering::Acquire) {
+            return Err(WalError::ReadOnly);
+        }
+        let mut frame = Self::frame(writer, record);
+        let segment_len = writer.end.0 - writer.segment_start.0;
+        // A record larger than a whole segment still gets one to itself
+        if segment_len > SEGMENT_HEADER_SIZE
+            && segment_len + frame.len() as u64 > self.options.segment_size
+        {
+            self.rotate(writer)?;
+            frame = Self::frame(writer, record);
+        }
+        Ok(self.write_frame(writer, record, &frame)?)
+    }
+
+    /// Append `record`, read from another log at `lsn`, at the same LSN
+    /// here, so a replica's log stays a copy of its primary's. A record
+    /// just past a segment header means the other log started a new
+    /// segment, and this one does too. Records already here are skipped.
+    pub(super) fn append_at(&self, lsn: Lsn, record: &WalRecord) -> Result<bool, WalError> {
+        let mut writer = self.writer.lock().unwrap();
+        if lsn < writer.end {
+            return Ok(false);
+        }
+        if lsn.0 == writer.end.0 + SEGMENT_HEADER_SIZE {
+            self.rotate(&mut writer)?;
+        } else if lsn != writer.end {
+            return Err(WalError::Diverged(lsn));
+        }
+        let frame = Self::frame(&writer, record);
+        self.write_frame(&mut writer, record, &frame)?;
+        Ok(true)
+    }
+
+    /// Stop or resume accepting appends other than `append_at`.
+    pub(super) fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Release);
+    }
+
+    /// The record framed with its length, the number of the segment
+    /// `writer` is appending to, and their CRC. A page write is framed as a
+    /// change to the page's last image, if it has one there.
+    fn frame(writer: &Writer, record: &WalRecord) -> Vec<u8> {
+        let payload = match record {
+            WalRecord::PageWrite { page_id, .. } => writer
+                .images
+                .get(page_id)
+                .and_then(|image| record.encode_delta(image)),
+            _ => None,
+        }
+        .unwrap_or_else(|| record.encode());
+        let mut frame = vec![0; FRAME_HEADER_SIZE];
+        BigEndian::write_u32(&mut frame[..4], payload.len() as u32);
+        BigEndian::write_u32(&mut frame[4..8], writer.sequence as u32);
+        frame.extend_from_slice(&payload);
+        let crc = Self::frame_crc(&frame);
+        BigEndian::write_u32(&mut frame[8..FRAME_HEADER_SIZE], crc);
+        frame
+    }
+
+    /// The CRC of `frame`'s length, sequence number and payload.
+    fn frame_crc(frame: &[u8]) -> u32 {
+        let mut covered = frame[..8].to_vec();
+        covered.extend_from_slice(&frame[FRAME_HEADER_SIZE..]);
+        crc32(&covered)
+    }
+
+    /// Keep the image a page write leaves, for the next write of the page
+    /// to be logged as a change to, or with full page writes forget them
+    /// all at a checkpoint.
+    fn remember(images: &mut HashMap<PageId, Vec<u8>>, record: &WalRecord, full_page_writes: bool) {
+        match record {
+            WalRecord::PageWrite { page_id, after, .. } => {
+                images.insert(*page_id, after.clone());
+            }
+            WalRecord::Checkpoint { .. } if full_page_writes => images.clear(),
+            _ => {}
+        }
+    }
+
+    fn write_frame(
+        &self,
+        writer: &mut Writer,
+        record: &WalRecord,
+        frame: &[u8],
+    ) -> io::Result<Lsn> {
+        let lsn = writer.end;
+        match self
+            .options
+            .faults
+            .as_ref()
+            .map(|f| f.inject(FaultPoint::WalWrite))
+        {
+            None | Some(Injected::Proceed) => {}
+            Some(Injected::Fail(e)) => return Err(e),
+            Some(Injected::Tear) => {
+                writer.segment.write_all(&frame[..frame.len() / 2])?;
+                writer.segment.flush()?;
+                return Err(io::Error::other("simulated crash"));
+            }
+        }
+        writer.segment.write_all(frame)?;
+        writer.end.0 += frame.len() as u64;
+        Self::remember(&mut writer.images, record, self.options.full_page_writes);
+        if let Some(txn) = record.txn() {
+            writer.last_txn = writer.last_txn.max(txn);
+        }
+        match record {
+            WalRecord::Begin { txn }
+            | WalRecord::PageWrite { txn, .. }
+            | WalRecord::Prepare { txn, .. } => {
+                let active = writer.active.entry(*txn).or_insert(ActiveTxn {
+                    first: lsn,
+                    bytes: 0,
+                });
+                active.bytes += frame.len() as u64;
+            }
+            WalRecord::Commit { txn, .. } | WalRecord::Abort { txn } => {
+                writer.active.remove(txn);
+            }
+            WalRecord::Checkpoint { .. } => {}
+        }
+        Ok(lsn)
+    }
+
+    /// Make the record at `lsn`, and every one before it, durable.
+    pub fn flush(&self, lsn: Lsn) -> Result<(), WalError> {
+        self.counters.flushes.fetch_add(1, Ordering::Relaxed);
+        let mut state = self.sync.lock().unwrap();
+        loop {
+            if state.flushed > lsn {
+                return Ok(());
+            }
+            if !state.syncing {
+                break;
+            }
+            state = self.synced.wait(state).unwrap();
+        }
+        state.syncing = true;
+        drop(state);
+
+        if let Some(delay) = self.options.commit_delay {
+            // Only worth waiting if someone else might commit meanwhile
+            if !self.writer.lock().unwrap().active.is_empty() {
+                thread::sleep(delay);
+            }
+        }
+        // Flush everything appended so far, which covers `lsn` and whatever
+        // other threads appended while the last sync ran
+        let span = span!(Debug, "wal_sync", lsn = lsn);
+        let result = self.sync_writer();
+        drop(span);
+
+        let mut state = self.sync.lock().unwrap();
+        state.syncing = false;
+        if let Ok(end) = result {
+            state.flushed = state.flushed.max(end);
+        }
+        self.synced.notify_all();
+        result.map(|_| ())
+    }
+
+    /// Make every record appended so far durable.
+    pub fn flush_all(&self) -> Result<(), WalError> {
+        let end = self.end();
+        if end == Lsn::ZERO {
+            return Ok(());
+        }
+        self.flush(Lsn(end.0 - 1))
+    }
+
+    /// Where the next record will be appended.
+    pub fn end(&self) -> Lsn {
+        self.writer.lock().unwrap().end
+    }
+
+    /// Every record before this LSN is durable.
+    pub fn flushed(&self) -> Lsn {
+        self.sync.lock().unwrap().flushed
+    }
+
+    /// Count `txn` as running from `first`, as for a prepared transaction
+    /// found by recovery, so checkpoints keep the log it still needs.
+    pub(super) fn resume_txn(&self, txn: TxnId, first: Lsn) {
+        let mut writer = self.writer.lock().unwrap();
+        writer
+            .active
+            .entry(txn)
+            .or_insert(ActiveTxn { first, bytes: 0 });
+    }
+
+    /// Bytes of log written so far by `txn`, or `None` once it has committed
+    /// or aborted.
+    pub fn txn_bytes(&self, txn: TxnId) -> Option<u64> {
+        let writer = self.writer.lock().unwrap();
+        writer.active.get(&txn).map(|active| active.bytes)
+    }
+
+    /// Snapshot the log's counters.
+    pub fn stats(&self) -> WalStats {
+        WalStats::from_counters(&self.counters)
+    }
+
+    /// The highest transaction id in the log, as far as this `Wal` has seen.
+    pub fn last_txn(&self) -> TxnId {
+        self.writer.lock().unwrap().last_txn
+    }
+
+    /// Record that ids up to `txn` are taken, such as by transactions found
+    /// during recovery, so later checkpoints carry them forward.
+    pub(super) fn reserve_txns(&self, txn: TxnId) {
+        let mut writer = self.writer.lock().unwrap();
+        writer.last_txn = writer.last_txn.max(txn);
+    }
+
+    /// The LSN the oldest remaining segment starts at. Records from here on
+    /// can still be read.
+    pub fn history_start(&self) -> Result<Lsn, WalError> {
+        let segments = self.store.segments()?;
+        Ok(segments.first().map_or(Lsn::ZERO, |&(start, _)| start))
+    }
+
+    /// Read the records from `from` onwards, oldest first, including ones
+    /// not yet flushed.
+    pub fn read_from(&self, from: Lsn) -> Result<WalIter, WalError> {
+        // Hand buffered records to the OS so the reader sees them
+        self.writer.lock().unwrap().segment.flush()?;
+        Ok(WalIter::new(self.store.segments()?, from))
+    }
+
+    /// Read the records in the log directory `dir` from `from` onwards,
+    /// without opening it.
+    pub(super) fn read_dir(dir: &Path, from: Lsn) -> Result<WalIter, WalError> {
+        Ok(WalIter::new(
+            Store::Dir(dir.to_path_buf()).segments()?,
+            from,
+        ))
+    }
+
+    /// Flush and sync the current segment, returning the LSN it is durable
+    /// up to.
+    fn sync_writer(&self) -> Result<Lsn, WalError> {
+        self.counters.syncs.fetch_add(1, Ordering::Relaxed);
+        if let Some(faults) = &self.options.faults {
+            if let Injected::Fail(e) = faults.inject(FaultPoint::WalSync) {
+                return Err(e.into());
+            }
+        }
+        let (file, end) = {
+            let mut writer = self.writer.lock().unwrap();
+            writer.segment.flush()?;
+            let file = match (&writer.segment, self.options.durability) {
+                (SegmentWriter::File(file), Durability::Full) => Some(file.get_ref().try_clone()?),
+                _ => None,
+            };
+            (file, writer.end)
+        };
+        // Sync without holding the writer, so appends carry on meanwhile
+        if let Some(file) = file {
+            file.sync_data()?;
+        }
+        Ok(end)
+    }
+
+    /// Finish the current segment, archiving it, and start a new one at
+    /// its end.
+    fn rotate(&self, writer: &mut Writer) -> Result<(), WalError> {
+        writer.segment.flush_and_sync(true)?;
+        let finished = writer.segment_start;
+        let active = std::mem::take(&mut writer.active);
+        *writer = Self::create_segment(
+            &self.store,
+            writer.end,
+            writer.sequence + 1,
+            active,
+            writer.last_txn,
+        )?;
+        if let (Store::Dir(dir), Some(archive_dir)) = (&self.store, &self.options.archive_dir) {
+            Self::archive(archive_dir, &Self::segment_path(dir, finished))?;
+        }
+        Ok(())
+    }
+
+    /// Copy the log from `from` up to its current end into the directory
+    /// `dest`, flushing it first. Returns the LSN the copy ends at.
+    pub fn copy_to(&self, dest: &Path, from: Lsn) -> Result<Lsn, WalError> {
+        let end = self.end();
+        self.flush_all()?;
+        fs::create_dir_all(dest)?;
+        let segments = self.store.segments()?;
+        for (i, (start, segment)) in segments.iter().enumerate() {
+            let next = segments.get(i + 1).map(|&(next, _)| next);
+            if *start < end && next.is_none_or(|next| next > from) {
+                let copy = Self::segment_path(dest, *start);
+                match segment {
+                    Segment::File(path) => fs::copy(path, copy).map(|_| ())?,
+                    Segment::Memory(_) => fs::write(copy, segment.read()?)?,
+                }
+            }
+        }
+        // The current segment may have grown while it was copied
+        Self::truncate(dest, end)?;
+        File::open(dest)?.sync_all()?;
+        Ok(end)
+    }
+
+    /// Finish the current segment now if it holds any records, so it is
+    /// archived without waiting for it to fill up.
+    #[cfg(test)]
+    pub fn switch_segment(&self) -> Result<(), WalError> {
+        let mut writer = self.writer.lock().unwrap();
+        if writer.end.0 - writer.segment_start.0 > SEGMENT_HEADER_SIZE {
+            self.rotate(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    /// Copy the segment at `path` into `archive_dir`, replacing any older
+    /// copy. The copy only takes the segment's name once it is complete.
+    fn archive(archive_dir: &Path, path: &Path) -> Result<(), WalError> {
+        fs::create_dir_all(archive_dir)?;
+        let name = path.file_name().unwrap();
+        let partial = archive_dir.join(name).with_extension("partial");
+        fs::copy(path, &partial)?;
+        File::open(&partial)?.sync_all()?;
+        fs::rename(&partial, archive_dir.join(name))?;
+        File::open(archive_dir)?.sync_all()?;
+        Ok(())
+    }
+
+    /// Discard the records in `dir` from `lsn` on, keeping the segment that
+    /// holds `lsn` up to just before it.
+    pub(super) fn truncate(dir: &Path, lsn: Lsn) -> Result<(), WalError> {
+        let segments = Self::segments(dir)?;
+        for (i, (start, path)) in segments.iter().enumerate() {
+            let next = segments.get(i + 1).map(|&(next, _)| next);
+            if *start >= lsn && i > 0 {
+                fs::remove_file(path)?;
+            } else if next.is_none_or(|next| next > lsn) {
+                let len = lsn.0.saturating_sub(start.0).max(SEGMENT_HEADER_SIZE);
+                let file = OpenOptions::new().write(true).open(path)?;
+                if file.metadata()?.len() > len {
+                    file.set_len(len)?;
+                    file.sync_all()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn create_segment(
+        store: &Store,
+        start: Lsn,
+        sequence: u64,
+        active: HashMap<TxnId, ActiveTxn>,
+        last_txn: TxnId,
+    ) -> Result<Writer, WalError> {
+        let mut header = SEGMENT_MAGIC.to_vec();
+        header.write_u64::<BigEndian>(start.0)?;
+        header.write_u64::<BigEndian>(sequence)?;
+        let segment = match store {
+            Store::Dir(dir) => {
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(Self::segment_path(dir, start))?;
+                file.write_all(&header)?;
+                file.sync_data()?;
+                // Make the new file's name durable too
+                File::open(dir)?.sync_all()?;
+                SegmentWriter::File(BufWriter::new(file))
+            }
+            Store::Memory(segments) => {
+                let segment = MemorySegment::new(header);
+                segments.lock().unwrap().insert(start, segment.clone());
+                SegmentWriter::Memory(segment)
+            }
+        };
+        Ok(Writer {
+            segment,
+            segment_start: start,
+            sequence,
+            images: HashMap::new(),
+            end: Lsn(start.0 + SEGMENT_HEADER_SIZE),
+            active,
+            last_txn,
+        })
+    }
+
+    fn segment_path(dir: &Path, start: Lsn) -> PathBuf {
+        dir.join(format!("{:016x}.{}", start.0, SEGMENT_EXTENSION))
+    }
+
+    /// The segments in `dir` and the LSNs they start at, in order.
+    pub(super) fn segments(dir: &Path) -> Result<Vec<(Lsn, PathBuf)>, WalError> {
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == SEGMENT_EXTENSION) {
+                let start = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| u64::from_str_radix(stem, 16).ok())
+                    .ok_or_else(|| WalError::InvalidSegment(path.clone()))?;
+                segments.push((Lsn(start), path));
+            }
+        }
+        segments.sort();
+        Ok(segments)
+    }
+
+    /// Check the header of the segment at `path`, holding `data`, and
+    /// return its sequence number.
+    fn check_header(path: &Path, data: &[u8], start: Lsn) -> Result<u64, WalError> {
+        let header_size = SEGMENT_HEADER_SIZE as usize;
+        let magic = SEGMENT_MAGIC.len();
+        if data.len() < header_size
+            || data[..magic] != SEGMENT_MAGIC
+            || BigEndian::read_u64(&data[magic..magic + 8]) != start.0
+        {
+            return Err(WalError::InvalidSegment(path.to_path_buf()));
+        }
+        Ok(BigEndian::read_u64(&data[magic + 8..header_size]))
+    }
+
+    /// Decode the frame at the start of `data`, in the segment `sequence`
+    /// after the pages' `images`, and keep the image it leaves. Returns the
+    /// record and the frame's length, or `None` if it is incomplete, fails
+    /// its CRC or belongs to another segment.
+    fn read_frame(
+        data: &[u8],
+        sequence: u64,
+        images: &mut HashMap<PageId, Vec<u8>>,
+    ) -> Option<(WalRecord, usize)> {
+        let header = data.get(..FRAME_HEADER_SIZE)?;
+        let len = BigEndian::read_u32(&header[..4]) as usize;
+        let frame = data.get(..FRAME_HEADER_SIZE + len)?;
+        if Self::frame_crc(frame) != BigEndian::read_u32(&header[8..])
+            || BigEndian::read_u32(&header[4..8]) != sequence as u32
+        {
+            return None;
+        }
+        let record = WalRecord::decode_with(&frame[FRAME_HEADER_SIZE..], images).ok()?;
+        Self::remember(images, &record, false);
+        Some((record, frame.len()))
+    }
+}
+
+impl Store {
+    /// The segments and the LSNs they start at, in order.
+    fn segments(&self) -> Result<Vec<(Lsn, Segment)>, WalError> {
+        Ok(match self {
+            Store::Dir(dir) => Wal::segments(dir)?
+                .into_iter()
+                .map(|(start, path)| (start, Segment::File(path)))
+                .collect(),
+            Store::Memory(segments) => segments
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(&start, segment)| (start, Segment::Memory(segment.clone())))
+                .collect(),
+        })
+    }
+}
+
+/// Iterates over log records in LSN order. See `Wal::read_from`.
+pub struct WalIter {
+    segments: std::vec::IntoIter<(Lsn, Segment)>,
+    /// The segment being read, its start and sequence number, and the
+    /// offset of the next record
+    current: Option<(Vec<u8>, Lsn, u64, usize)>,
+    /// The pages' last images in the segment, to read changes to them
+    images: HashMap<PageId, Vec<u8>>,
+    from: Lsn,
+}
+
+impl WalIter {
+    fn new(mut segments: Vec<(Lsn, Segment)>, from: Lsn) -> Self {
+        // Skip segments that end before `from`
+        let first = segments
+            .iter()
+            .rposition(|&(start, _)| start <= from)
+            .unwrap_or(0);
+        segments.drain(..first);
+        Self {
+            segments: segments.into_iter(),
+            current: None,
+            images: HashMap::new(),
+            from,
+        }
+    }
+
+    fn next_record(&mut self) -> Result<Option<(Lsn, WalRecord)>, WalError> {
+        loop {
+            let (data, start, sequence, pos) = match &mut self.current {
+                Some(current) => current,
+                None => match self.segments.next() {
+                    Some((start, segment)) => {
+                        let data = segment.read()?;
+                        let sequence = Wal::check_header(&segment.path(start), &data, start)?;
+                        let pos = SEGMENT_HEADER_SIZE as usize;
+                        self.current = Some((data, start, sequence, pos));
+                        self.images.clear();
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+            };
+            if *pos == data.len() {
+                self.current = None;
+                continue;
+            }
+            let lsn = Lsn(start.0 + *pos as u64);
+            let (record, len) = Wal::read_frame(&data[*pos..], *sequence, &mut self.images)
+                .ok_or(WalError::Corrupted(lsn))?;
+            *pos += len;
+            if lsn >= self.from {
+                return Ok(Some((lsn, record)));
+            }
+        }
+    }
+}
+
+impl Iterator for WalIter {
+    type Item = Result<(Lsn, WalRecord), WalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.next_record();
+        if result.is_err() {
+            // Stop after the first error rather than returning it forever
+            self.segments = Vec::new().into_iter();
+            self.current = None;
+        }
+        result.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn options(segment_size: u64) -> WalOptions {
+        WalOptions {
+            segment_size,
+            durability: Durability::Full,
+            commit_delay: None,
+            retention: None,
+            archive_dir: None,
+            full_page_writes: true,
+            faults: None,
+        }
+    }
+
+    fn page_write(txn: u64, page_no: u64, value: u8) -> WalRecord {
+        WalRecord::PageWrite {
+            txn: TxnId(txn),
+            page_id: PageId::new(FileId(1), page_no),
+            before: vec![0; 32],
+            after: vec![value; 32],
+        }
+    }
+
+    fn read_all(wal: &Wal) -> Vec<(Lsn, WalRecord)> {
+        wal.read_from(Lsn::ZERO)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn segment_files(dir: &Path) -> Vec<PathBuf> {
+        Wal::segments(dir)
+            .unwrap()
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect()
+    }
+
+    #[test]
+    fn test_append_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal = Wal::open(dir.path(), options(1 << 20)).unwrap();
+        let records = vec![
+            WalRecord::Begin { txn: TxnId(1) },
+            page_write(1, 3, 7),
+            WalRecord::Commit {
+                txn: TxnId(1),
+                time: 1_000_000,
+            },
+            WalRecord::Abort { txn: TxnId(2) },
+            WalRecord::Prepare {
+                txn: TxnId(3),
+                gid: "global-1".to_string(),
+                locks: vec![
+                    (LockTarget::Table(FileId(1)), LockMode::Shared),
+                    (LockTarget::Table(FileId(2)), LockMode::Exclusive),
+                ],
+            },
+            WalRecord::Checkpoint {
+                redo_from: Lsn(16),
+                active: vec![(TxnId(3), Lsn(20)), (TxnId(4), Lsn(30))],
+                last_txn: TxnId(4),
+                time: 2_000_000,
+            },
+        ];
+        let lsns: Vec<_> = records.iter().map(|r| wal.append(r).unwrap()).collect();
+        assert!(lsns.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let read = read_all(&wal);
+        assert_eq!(read, lsns.iter().copied().zip(records).collect::<Vec<_>>());
+
+        // Reading from an LSN skips the records before it
+        let tail: Vec<_> = wal
+            .read_from(lsns[2])
+            .unwrap()
+            .map(|r| r.unwrap().0)
+            .collect();
+        assert_eq!(tail, lsns[2..]);
+    }
+
+    #[test]
+    fn test_flush_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal = Wal::open(dir.path(), options(1 << 20)).unwrap();
+        let lsn = wal.append(&page_write(1, 0, 1)).unwrap();
+        assert!(wal.flushed() <= lsn);
+        wal.flush(lsn).unwrap();
+        assert!(wal.flushed() > lsn);
+        drop(wal);
+
+        let wal = Wal::open(dir.path(), options(1 << 20)).unwrap();
+        let next = wal.append(&page_write(1, 1, 2)).unwrap();
+        assert!(next > lsn);
+        assert_eq!(
+            read_all(&wal),
+            vec![(lsn, page_write(1, 0, 1)), (next, page_write(1, 1, 2))]
+        );
+    }
+
+    #[test]
+    fn test_torn_tail_is_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal = Wal::open(dir.path(), options(1 << 20)).unwrap();
+        let first = wal.append(&page_write(1, 0, 1)).unwrap();
+        wal.append(&page_write(1, 1, 2)).unwrap();
+        wal.flush_all().unwrap();
+        drop(wal);
+
+        // Cut the second record short, as a crash mid-write would
+        let path = &segment_files(dir.path())[0];
+        let len = fs::metadata(path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_len(len - 5)
+            .unwrap();
+
+        let wal = Wal::open(dir.path(), options(1 << 20)).unwrap();
+        assert_eq!(read_all(&wal), vec![(first, page_write(1, 0, 1))]);
+        let next = wal.append(&page_write(1, 2, 3)).unwrap();
+        assert_eq!(read_all(&wal).last().unwrap().0, next);
+    }
+
+    #[test]
+    fn test_record_from_another_segment_is_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal = Wal::open(dir.path(), options(1 << 20)).unwrap();
+        let first = wal.append(&page_write(1, 0, 1)).unwrap();
+        let second = wal.append(&page_write(1, 1, 2)).unwrap();
+        wal.switch_segment().unwrap();
+        let third = wal.append(&page_write(1, 2, 3)).unwrap();
+        wal.flush_all().unwrap();
+        drop(wal);
+
+        // A record intact but for its segment, as if left from before the
+        // file was reused
+        let files = segment_files(dir.path());
+        let old = fs::read(&files[0]).unwrap();
+        let stale = &old[first.0 as usize..second.0 as usize];
+        let mut data = fs::read(&files[1]).unwrap();
+        data.extend_from_slice(stale);
+        fs::write(&files[1], &data).unwrap();
+
+        let wal = Wal::open(dir.path(), options(1 << 20)).unwrap();
+        let lsns: Vec<_> = read_all(&wal).into_iter().map(|(lsn, _)| lsn).collect();
+        assert_eq!(lsns, [first, second, third]);
+        assert_eq!(
+            fs::metadata(&files[1]).unwrap().len() as usize,
+            data.len() - stale.len()
+        );
+    }
+
+    #[test]
+    fn test_page_writes_after_checkpoint() {
+        for full_page_writes in [true, false] {
+            let dir = tempfile::tempdir().unwrap();
+            let options = WalOptions {
+                full_page_writes,
+                ..options(1 << 20)
+            };
+            let wal = Wal::open(dir.path(), options.clone()).unwrap();
+            let mut image = vec![0; 32];
+            let mut written = Vec::new();
+            // The bytes each change to one byte of the page takes up
+            let mut change = |wal: &Wal, byte: usize| {
+                let before = image.clone();
+                image[byte] += 1;
+                let record = WalRecord::PageWrite {
+                    txn: TxnId(1),
+                    page_id: PageId::new(FileId(1), 0),
+                    before,
+                    after: image.clone(),
+                };
+                let lsn = wal.append(&record).unwrap();
+                written.push((lsn, record));
+                wal.end().0 - lsn.0
+            };
+            let whole = change(&wal, 0);
+            assert!(change(&wal, 1) < whole / 2);
+            wal.checkpoint(wal.end()).unwrap();
+            assert_eq!(change(&wal, 2) == whole, full_page_writes);
+            // Reopened, and in a new segment
+            wal.flush_all().unwrap();
+            drop(wal);
+            let wal = Wal::open(dir.path(), options).unwrap();
+            assert!(change(&wal, 3) < whole / 2);
+            wal.switch_segment().unwrap();
+            assert_eq!(change(&wal, 4), whole);
+
+            // Read back whole, whichever way they were logged
+            let writes: Vec<_> = read_all(&wal)
+                .into_iter()
+                .filter(|(_, record)| matches!(record, WalRecord::PageWrite { .. }))
+                .collect();
+            assert_eq!(writes, written);
+        }
+    }
+
+    #[test]
+    fn test_corrupted_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal = Wal::open(dir.path(), options(1 << 20)).unwrap();
+        let first = wal.append(&page_write(1, 0, 1)).unwrap();
+        let second = wal.append(&page_write(1, 1, 2)).unwrap();
+        wal.flush_all().unwrap();
+
+        let path = &segment_files(dir.path())[0];
+        let mut data = fs::read(path).unwrap();
+        let offset = (second.0 + FRAME_HEADER_SIZE as u64 + 20) as usize;
+        data[offset] ^= 0xff;
+        fs::write(path, data).unwrap();
+
+        let mut records = wal.read_from(Lsn::ZERO).unwrap();
+        assert_eq!(records.next().unwrap().unwrap().0, first);
+        assert!(matches!(
+            records.next(),
+            Some(Err(WalError::Corrupted(lsn))) if lsn == second
+        ));
+        assert!(records.next().is_none());
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal = Wal::open(dir.path(), options(256)).unwrap();
+        let lsns: Vec<_> = (0..10)
+            .map(|i| wal.append(&page_write(1, i, i as u8)).unwrap())
+            .collect();
+        wal.flush_all().unwrap();
+
+        // Each record is around 100 bytes, so only two fit per segment
+        let segments = segment_files(dir.path());
+        assert_eq!(segments.len(), 5);
+        assert!(segments
+            .iter()
+            .all(|path| fs::metadata(path).unwrap().len() <= 256));
+
+        let read: Vec<_> = read_all(&wal).into_iter().map(|(lsn, _)| lsn).collect();
+        assert_eq!(read, lsns);
+        let tail: Vec<_> = wal
+            .read_from(lsns[7])
+            .unwrap()
+            .map(|r| r.unwrap().0)
+            .collect();
+        assert_eq!(tail, lsns[7..]);
+        drop(wal);
+
+        let wal = Wal::open(dir.path(), options(256)).unwrap();
+        assert_eq!(read_all(&wal).len(), 10);
+    }
+
+    #[test]
+    fn test_checkpoint_tracks_active_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal = Wal::open(dir.path(), options(256)).unwrap();
+        let first = wal.append(&page_write(1, 0, 1)).unwrap();
+        wal.append(&page_write(2, 1, 1)).unwrap();
+        wal.append(&WalRecord::commit(TxnId(2))).unwrap();
+        for i in 0..4 {
+            wal.append(&page_write(1, i, 2)).unwrap();
+        }
+
+        let redo_from = wal.end();
+        let (lsn, start) = wal.checkpoint(redo_from).unwrap();
+        assert!(wal.flushed() > lsn);
+        // Transaction 1 is still running, so recovery must go back to it
+        assert_eq!(start, first);
+        let (_, record) = wal.read_from(lsn).unwrap().next().unwrap().unwrap();
+        let time = match record {
+            WalRecord::Checkpoint { time, .. } => time,
+            _ => panic!("expected a checkpoint, found {:?}", record),
+        };
+        assert_eq!(
+            record,
+            WalRecord::Checkpoint {
+                redo_from,
+                active: vec![(TxnId(1), first)],
+                last_txn: TxnId(2),
+                time,
+            }
+        );
+
+        assert!(wal.txn_bytes(TxnId(1)).unwrap() > 5 * 32);
+        assert_eq!(wal.txn_bytes(TxnId(2)), None);
+
+        // No segment can be removed while transaction 1 needs the first one
+        assert_eq!(wal.remove_segments_before(start).unwrap(), 0);
+        wal.append(&WalRecord::commit(TxnId(1))).unwrap();
+        let (_, start) = wal.checkpoint(wal.end()).unwrap();
+        let segments = segment_files(dir.path()).len();
+        let removed = wal.remove_segments_before(start).unwrap();
+        assert!(removed > 0);
+        assert_eq!(segment_files(dir.path()).len(), segments - removed);
+        assert!(wal.read_from(start).unwrap().all(|r| r.is_ok()));
+    }
+
+    #[test]
+    fn test_archive_and_truncate() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = tempfile::tempdir().unwrap();
+        let archiving = WalOptions {
+            archive_dir: Some(archive.path().to_path_buf()),
+            ..options(256)
+        };
+        let wal = Wal::open(dir.path(), archiving).unwrap();
+        let lsns: Vec<_> = (0..5)
+            .map(|i| wal.append(&page_write(1, i, 0)).unwrap())
+            .collect();
+        // Every finished segment is archived; the current one once switched
+        assert_eq!(segment_files(archive.path()).len(), 2);
+        wal.switch_segment().unwrap();
+        wal.switch_segment().unwrap();
+        assert_eq!(segment_files(archive.path()).len(), 3);
+        drop(wal);
+
+        Wal::truncate(dir.path(), lsns[3]).unwrap();
+        let wal = Wal::open(dir.path(), options(256)).unwrap();
+        let read: Vec<_> = read_all(&wal).into_iter().map(|(lsn, _)| lsn).collect();
+        assert_eq!(read, lsns[..3]);
+        let archived: Vec<_> = Wal::read_dir(archive.path(), Lsn::ZERO)
+            .unwrap()
+            .map(|r| r.unwrap().0)
+            .collect();
+        assert_eq!(archived, lsns);
+    }
+
+    #[test]
+    fn test_concurrent_flushes() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal = Arc::new(Wal::open(dir.path(), options(1 << 20)).unwrap());
+        let handles: Vec<_> = (0..8)
+            .map(|txn| {
+                let wal = wal.clone();
+                thread::spawn(move || {
+                    for i in 0..20 {
+                        let lsn = wal.append(&page_write(txn, i, 0)).unwrap();
+                        wal.flush(lsn).unwrap();
+                        assert!(wal.flushed() > lsn);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(read_all(&wal).len(), 160);
+    }
+
+    #[test]
+    fn test_group_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = WalOptions {
+            commit_delay: Some(Duration::from_millis(5)),
+            ..options(1 << 20)
+        };
+        let wal = Arc::new(Wal::open(dir.path(), options).unwrap());
+        // A long-running transaction keeps the delay in effect throughout
+        wal.append(&WalRecord::Begin { txn: TxnId(100) }).unwrap();
+        let handles: Vec<_> = (0..8)
+            .map(|thread| {
+                let wal = wal.clone();
+                thread::spawn(move || {
+                    for i in 0..5 {
+                        let txn = TxnId(thread * 5 + i);
+                        wal.append(&page_write(txn.0, i, 0)).unwrap();
+                        let lsn = wal.append(&WalRecord::commit(txn)).unwrap();
+                        wal.flush(lsn).unwrap();
+                        assert!(wal.flushed() > lsn);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let stats = wal.stats();
+        assert_eq!(stats.flushes, 40);
+        assert!(stats.syncs < stats.flushes);
+    }
+}
@@ -0,0 +1,417 @@
+//! Completing a statement as it's typed, from what the statement so far
+//! leaves to come next.
+
+use super::statement::{ACTIVE_QUERIES, BUFFER_STATS, TABLE_STATS, TRANSACTIONS};
+use super::tokenizer::tokenize;
+use super::tokens::{Operator, Separator, Token};
+use crate::database::Database;
+
+const STATEMENTS: &[&str] = &[
+    "ANALYZE",
+    "BACKUP",
+    "BEGIN",
+    "CHECKPOINT",
+    "CLOSE",
+    "COMMIT",
+    "COPY",
+    "CREATE",
+    "DEALLOCATE",
+    "DECLARE",
+    "DELETE",
+    "EXPLAIN",
+    "DROP",
+    "EXECUTE",
+    "FETCH",
+    "GRANT",
+    "INSERT",
+    "KILL",
+    "PREPARE",
+    "REVOKE",
+    "ROLLBACK",
+    "SELECT",
+    "SET",
+    "SHOW",
+    "UPDATE",
+    "VACUUM",
+];
+
+const PRIVILEGES: &[&str] = &["DDL", "DELETE", "INSERT", "SELECT", "UPDATE"];
+
+/// What may come next in a statement: keywords or columns, and whether a
+/// table may.
+struct Next {
+    words: &'static [&'static str],
+    tables: bool,
+}
+
+impl Next {
+    const NOTHING: Next = Next::words(&[]);
+    const TABLES: Next = Next {
+        words: &[],
+        tables: true,
+    };
+
+    const fn words(words: &'static [&'static str]) -> Self {
+        Self {
+            words,
+            tables: false,
+        }
+    }
+}
+
+/// Whether `c` can be part of the word being completed.
+/// Whether `c` can be part of a word, such as a schema-qualified name.
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
+
+impl Database {
+    /// The words that could finish the one at the end of `sql`: keywords,
+    /// columns or, where the statement takes a table, the tables there
+    /// are. Keywords are completed in the case the word was begun in.
+    pub fn complete(&self, sql: &str) -> Vec<String> {
+        let start = sql.len()
+            - sql
+                .chars()
+                .rev()
+                .take_while(|&c| is_word(c))
+                .map(char::len_utf8)
+                .sum::<usize>();
+        let (before, prefix) = sql.split_at(start);
+        let Some(words) = words(before) else {
+            return Vec::new();
+        };
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        let next = next(&words);
+
+        let lowercase = prefix.chars().any(char::is_lowercase);
+        let mut candidates: Vec<String> = next
+            .words
+            .iter()
+            .map(|word| match lowercase {
+                true => word.to_lowercase(),
+                false => word.to_string(),
+            })
+            .collect();
+        if next.tables {
+            candidates.extend(self.tables().iter().map(|table| table.to_string()));
+        }
+        candidates.retain(|candidate| candidate.to_lowercase().starts_with(&prefix.to_lowercase()));
+        candidates
+    }
+}
+
+/// The words of the last statement in `sql`, in upper case, with numbers
+/// and strings standing in for any, or `None` if it ends inside a string.
+fn words(sql: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    for item in tokenize(sql) {
+        let word = match item.ok()?.token {
+            Token::Separator(Separator::Semicolon) => {
+                words.clear();
+                continue;
+            }
+            Token::Separator(Separator::Whitespace(_)) | Token::Hint(_) => continue,
+            Token::Keyword(keyword) => format!("{:?}", keyword).to_uppercase(),
+            Token::Identifier(word) => word.to_uppercase(),
+            Token::Number(_) => "<number>".to_string(),
+            Token::String(_) => "<string>".to_string(),
+            Token::Separator(Separator::Comma) => ",".to_string(),
+            Token::Separator(Separator::Operator(operator)) => match operator {
+                Operator::Multiply => "*",
+                Operator::Eq => "=",
+                Operator::ParenOpen => "(",
+                Operator::ParenClose => ")",
+                _ => "<operator>",
+            }
+            .to_string(),
+            _ => "<other>".to_string(),
+        };
+        words.push(word);
+    }
+    Some(words)
+}
+
+/// What may follow `words` in the grammar of `Statement`.
+fn next(words: &[&str]) -> Next {
+    match words {
+        [] => Next::words(STATEMENTS),
+        ["PREPARE", _, "AS", rest @ ..] => next(rest),
+        ["PREPARE", _] => Next::words(&["AS"]),
+        ["BEGIN"] => Next::words(&["TRANSACTION"]),
+        ["EXPLAIN"] => Next::words(&["SELECT"]),
+        ["DECLARE", _] => Next::words(&["CURSOR"]),
+        ["DECLARE", _, "CURSOR"] => Next::words(&["FOR"]),
+        ["DECLARE", _, "CURSOR", "FOR"] => Next::words(&["SELECT"]),
+        ["DECLARE", _, "CURSOR", "FOR", rest @ ..] => next(rest),
+        ["FETCH"] => Next::words(&["ALL", "FROM", "NEXT"]),
+        ["FETCH", "<number>" | "ALL" | "NEXT"] => Next::words(&["FROM"]),
+        ["EXPLAIN", rest @ ..] => next(rest),
+        ["CREATE"] => Next::words(&["EXTERNAL", "FULLTEXT", "TABLE", "TRIGGER", "USER"]),
+        ["CREATE", "FULLTEXT"] => Next::words(&["INDEX"]),
+        ["CREATE", "FULLTEXT", "INDEX", _] => Next::words(&["ON"]),
+        ["CREATE", "FULLTEXT", "INDEX", _, "ON"] => Next::TABLES,
+        ["CREATE", "FULLTEXT", "INDEX", _, "ON", "<number>", "("] => Next::words(&["data"]),
+        ["CREATE", "EXTERNAL"] => Next::words(&["TABLE"]),
+        ["CREATE", "TABLE"] => Next::words(&["PARTITION", "WITH"]),
+        ["CREATE", "TABLE", rest @ ..] if rest.contains(&"PARTITION") => partition(rest),
+        ["CREATE", "TABLE", .., ")"] => Next::words(&["PARTITION"]),
+        ["CREATE", "TABLE", .., "(" | ","] => {
+            Next::words(&["BLOOM_FILTER", "COMPRESSION", "FILL_FACTOR", "TTL_COLUMN"])
+        }
+        ["CREATE", "TABLE", .., "BLOOM_FILTER", "="] => Next::words(&["FALSE", "TRUE"]),
+        ["CREATE", "EXTERNAL", "TABLE", "(", rest @ ..] if !rest.contains(&")") => match rest {
+            // After a column's name, its type
+            [_] | [.., ",", _] => Next::words(&["BIGINT", "BOOLEAN", "INTEGER", "REAL", "TEXT"]),
+            _ => Next::NOTHING,
+        },
+        ["CREATE", "EXTERNAL", rest @ ..] => external(rest),
+        ["CREATE", "USER", _] => Next::words(&["WITH", "PASSWORD"]),
+        ["CREATE", "USER", _, "WITH"] => Next::words(&["PASSWORD"]),
+        ["CREATE", "USER", .., "PASSWORD", "<string>"] => Next::words(&["SUPERUSER"]),
+        ["CREATE", "TRIGGER", rest @ ..] => trigger(rest),
+        ["DROP"] => Next::words(&["INDEX", "TRIGGER", "USER"]),
+        ["INSERT"] => Next::words(&["INTO"]),
+        ["COPY", "("] => Next::words(&["SELECT"]),
+        // A search can't be copied
+        ["COPY", "(", .., "WHERE"] => Next::words(&["id"]),
+        ["COPY", "(", rest @ ..] if !rest.contains(&")") => next(rest),
+        ["COPY", .., ")"] => Next::words(&["TO"]),
+        ["COPY", "<number>"] => Next::words(&["FROM", "TO"]),
+        ["COPY", .., "FROM" | "TO", "<string>"] => Next::words(&["WITH"]),
+        ["COPY", .., "(" | ","] => {
+            Next::words(&["DELIMITER", "ESCAPE", "FORMAT", "HEADER", "QUOTE"])
+        }
+        ["COPY", .., "FORMAT"] => Next::words(&["CSV", "JSON", "PARQUET"]),
+        ["SELECT", "*", "FROM"] => Next {
+            words: &[ACTIVE_QUERIES, BUFFER_STATS, TABLE_STATS, TRANSACTIONS],
+            tables: true,
+        },
+        ["KILL"] => Next::words(&["QUERY"]),
+        ["BACKUP"] => Next::words(&["TO"]),
+        ["BACKUP", "TO", "<string>"] => Next::words(&["INCREMENTAL"]),
+        ["BACKUP", "TO", "<string>", "INCREMENTAL"] => Next::words(&["FROM"]),
+        ["SHOW"] => Next::words(&["ALL"]),
+        ["INSERT", "INTO"]
+        | ["UPDATE"]
+        | ["DELETE", "FROM"]
+        | ["COPY"]
+        | ["ANALYZE"]
+        | ["VACUUM"] => Next::TABLES,
+        ["INSERT", "INTO", "<number>"] => Next::words(&["VALUES"]),
+        ["SELECT"] => Next::words(&[
+            "*",
+            "CURRENT_CATALOG",
+            "CURRENT_TIMESTAMP",
+            "CURRENT_USER",
+            "SESSION_USER",
+        ]),
+        ["SELECT", "*"] | ["DELETE"] => Next::words(&["FROM"]),
+        ["SELECT", "*", "FROM", "<number>"] => Next::words(&["JOIN", "ORDER", "WHERE"]),
+        ["SELECT", .., "JOIN"] => Next::TABLES,
+        ["SELECT", .., "JOIN", "<number>"] => Next::words(&["ON"]),
+        ["SELECT", .., "JOIN", "<number>", "ON"] => Next::words(&["data", "id"]),
+        ["DELETE", "FROM", "<number>"] => Next::words(&["WHERE"]),
+        ["SELECT", .., "ORDER"] => Next::words(&["BY"]),
+        ["SELECT", .., "ORDER", "BY"] => Next::words(&["data", "id"]),
+        ["UPDATE", "<number>"] => Next::words(&["SET"]),
+        ["UPDATE", "<number>", "SET"] => Next::words(&["data"]),
+        ["UPDATE", "<number>", "SET", "DATA", "=", _] => Next::words(&["WHERE"]),
+        ["SELECT", "*", "FROM", "<number>", "WHERE"] => {
+            Next::words(&["data", "EXISTS", "id", "MATCH", "NOT"])
+        }
+        ["SELECT", "*", "FROM", "<number>", "WHERE", "NOT"] => Next::words(&["EXISTS"]),
+        ["SELECT", "*", "FROM", "<number>", "WHERE", _, "NOT"] => Next::words(&["IN"]),
+        ["SELECT", .., "MATCH", "("] => Next::words(&["data"]),
+        ["SELECT", .., "MATCH", "(", "DATA", ")"] => Next::words(&["AGAINST"]),
+        [.., "WHERE"] => Next::words(&["id"]),
+        ["GRANT" | "REVOKE", rest @ ..] => privileges(words[0], rest),
+        _ => Next::NOTHING,
+    }
+}
+
+/// What may follow `CREATE TABLE` and then `words`, once they reach
+/// `PARTITION`.
+fn partition(words: &[&str]) -> Next {
+    match words {
+        [.., "PARTITION"] => Next::words(&["BY"]),
+        [.., "PARTITION", "BY"] => Next::words(&["HASH", "RANGE"]),
+        [.., "BY", "HASH" | "RANGE", "("] => Next::words(&["data"]),
+        [.., "BY", "HASH", "(", _, ")"] => Next::words(&["PARTITIONS"]),
+        [.., "BY", "RANGE", "(", _, ")"] => Next::words(&["SPLIT"]),
+        [.., "SPLIT"] => Next::words(&["AT"]),
+        _ => Next::NOTHING,
+    }
+}
+
+/// What may follow `CREATE EXTERNAL` and then `words`, once its columns
+/// are listed.
+fn external(words: &[&str]) -> Next {
+    let location = words.contains(&"LOCATION");
+    match words {
+        [.., ")"] if !location => Next::words(&["LOCATION"]),
+        [.., "LOCATION", "<string>"] => Next::words(&["FORMAT", "WITH"]),
+        [.., "FORMAT"] => Next::words(&["CSV"]),
+        [.., "<string>", "FORMAT", "CSV"] => Next::words(&["WITH"]),
+        [.., "(" | ","] if location => {
+            Next::words(&["DELIMITER", "ESCAPE", "FORMAT", "HEADER", "QUOTE"])
+        }
+        _ => Next::NOTHING,
+    }
+}
+
+/// What may follow `CREATE TRIGGER` and then `words`.
+fn trigger(words: &[&str]) -> Next {
+    const WRITES: &[&str] = &["DELETE", "INSERT", "UPDATE"];
+    if let Some(at) = words.iter().position(|&word| word == "EXECUTE") {
+        return match &words[at + 1..] {
+            [] => Next::words(WRITES),
+            action => next(action),
+        };
+    }
+    match words {
+        [_] => Next::words(&["AFTER", "BEFORE"]),
+        [_, "AFTER" | "BEFORE"] => Next::words(WRITES),
+        [_, _, _] => Next::words(&["ON"]),
+        [_, _, _, "ON"] => Next::TABLES,
+        [_, _, _, "ON", _] => Next::words(&["EXECUTE", "FOR"]),
+        [.., "FOR"] => Next::words(&["EACH"]),
+        [.., "EACH"] => Next::words(&["ROW"]),
+        [.., "ROW"] => Next::words(&["EXECUTE"]),
+        _ => Next::NOTHING,
+    }
+}
+
+/// What may follow `GRANT` or `REVOKE` and then `words`.
+fn privileges(statement: &str, words: &[&str]) -> Next {
+    let on = words.iter().position(|&word| word == "ON");
+    match (words, on) {
+        ([], _) => Next::words(&["ALL", "DDL", "DELETE", "INSERT", "SELECT", "UPDATE"]),
+        (["ALL"], _) => Next::words(&["PRIVILEGES", "ON"]),
+        ([.., ","], None) => Next::words(PRIVILEGES),
+        ([.., privilege], None) if *privilege == "PRIVILEGES" || PRIVILEGES.contains(privilege) => {
+            Next::words(&["ON"])
+        }
+        ([.., "ON"], _) => Next {
+            words: &["TABLE", "ALL"],
+            tables: true,
+        },
+        ([.., "ON", "TABLE"], _) => Next::TABLES,
+        ([.., "ON", "ALL"], _) => Next::words(&["TABLES"]),
+        ([.., "<number>" | "TABLES"], Some(_)) if statement == "GRANT" => Next::words(&["TO"]),
+        ([.., "<number>" | "TABLES"], Some(_)) => Next::words(&["FROM"]),
+        _ => Next::NOTHING,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::tests::test_config;
+    use crate::database::Database;
+
+    #[test]
+    fn test_complete() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        config.storage.page_size = 128;
+        let database = Database::with_config(&config).unwrap();
+        for _ in 0..11 {
+            database.create_table().unwrap();
+        }
+
+        assert_eq!(database.complete("SEL"), vec!["SELECT"]);
+        assert_eq!(
+            database.complete("de"),
+            vec!["deallocate", "declare", "delete"]
+        );
+        assert_eq!(database.complete("select * "), vec!["FROM"]);
+        assert_eq!(database.complete("SELECT * FROM 1"), vec!["1", "10", "11"]);
+        assert_eq!(
+            database.complete("SELECT * FROM information_schema.a"),
+            vec!["information_schema.active_queries"]
+        );
+        assert_eq!(
+            database.complete("SELECT * FROM information_schema.t"),
+            vec![
+                "information_schema.table_stats",
+                "information_schema.transactions"
+            ]
+        );
+        assert_eq!(database.complete("KILL "), vec!["QUERY"]);
+        assert_eq!(
+            database.complete("EXPLAIN SELECT * FROM 2 "),
+            vec!["JOIN", "ORDER", "WHERE"]
+        );
+        assert_eq!(database.complete("SELECT * FROM 2 JOIN 3 "), vec!["ON"]);
+        assert_eq!(
+            database.complete("SELECT * FROM 2 WHERE author NOT "),
+            vec!["IN"]
+        );
+        assert_eq!(
+            database.complete("SELECT * FROM 2 ORDER BY "),
+            vec!["data", "id"]
+        );
+        assert_eq!(database.complete("ANALYZE 1"), vec!["1", "10", "11"]);
+        assert_eq!(database.complete("DELETE FROM 3 WHERE "), vec!["id"]);
+        assert_eq!(
+            database.complete("SELECT * FROM 3 WHERE MATCH(data) "),
+            vec!["AGAINST"]
+        );
+        assert_eq!(database.complete("UPDATE 2 SET "), vec!["data"]);
+        assert_eq!(database.complete("BEGIN; INSERT INTO 2 v"), vec!["values"]);
+        assert_eq!(database.complete("PREPARE put AS INSERT "), vec!["INTO"]);
+        assert_eq!(
+            database.complete("COPY 1 FROM 'in.csv' (HEADER, "),
+            vec!["DELIMITER", "ESCAPE", "FORMAT", "HEADER", "QUOTE"]
+        );
+        assert_eq!(
+            database.complete("COPY (SELECT * FROM 1 WHERE "),
+            vec!["id"]
+        );
+        assert_eq!(database.complete("COPY (SELECT * FROM 1) "), vec!["TO"]);
+        assert_eq!(
+            database.complete("CREATE TABLE WITH (fill_factor = 70, "),
+            vec!["BLOOM_FILTER", "COMPRESSION", "FILL_FACTOR", "TTL_COLUMN"]
+        );
+        assert_eq!(
+            database.complete("CREATE TABLE (fill_factor = 70) PARTITION BY "),
+            vec!["HASH", "RANGE"]
+        );
+        assert_eq!(
+            database.complete("CREATE TABLE PARTITION BY RANGE (day) "),
+            vec!["SPLIT"]
+        );
+        assert!(database
+            .complete("CREATE TABLE PARTITION BY HASH (")
+            .contains(&"data".to_string()));
+        assert_eq!(
+            database.complete("CREATE EXTERNAL TABLE (name TEXT, age "),
+            vec!["BIGINT", "BOOLEAN", "INTEGER", "REAL", "TEXT"]
+        );
+        assert_eq!(
+            database.complete("CREATE EXTERNAL TABLE (name TEXT) LOCATION 'dir' FORMAT "),
+            vec!["CSV"]
+        );
+        assert_eq!(
+            database.complete("GRANT SELECT, "),
+            vec!["DDL", "DELETE", "INSERT", "SELECT", "UPDATE"]
+        );
+        assert_eq!(database.complete("GRANT ALL ON ALL TABLES "), vec!["TO"]);
+        assert_eq!(database.complete("REVOKE DDL ON 4 "), vec!["FROM"]);
+        assert_eq!(
+            database.complete("CREATE TRIGGER stamp BEFORE "),
+            vec!["DELETE", "INSERT", "UPDATE"]
+        );
+        assert_eq!(
+            database
+                .complete("CREATE TRIGGER stamp AFTER DELETE ON 2 FOR EACH ROW EXECUTE INSERT "),
+            vec!["INTO"]
+        );
+        // Nothing to complete inside a string, or where anything may go
+        assert!(database.complete("INSERT INTO 1 VALUES ('sel").is_empty());
+        assert!(database.complete("SET datestyle = ").is_empty());
+        assert_eq!(database.complete("show a"), vec!["all"]);
+        assert_eq!(database.complete("DECLARE c CURSOR F"), vec!["FOR"]);
+        assert_eq!(database.complete("fetch 10 "), vec!["FROM"]);
+    }
+}
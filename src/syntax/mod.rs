@@ -1,4 +1,11 @@
 //! Simple SQL parser and AST for our toy database.
 
+mod completion;
+mod statement;
 mod tokenizer;
 mod tokens;
+
+pub(crate) use statement::{
+    parse_script, parse_spanned, redact_passwords, AsOfPoint, CopyFormat, CopyOptions,
+    ExplainFormat, Key, Order, Statement, SyntaxError, Value,
+};
@@ -0,0 +1,3012 @@
+use super::tokenizer::{tokenize, CharacterLocation, TokenizerError};
+use super::tokens::{Keyword, Operator, Separator, Token};
+use crate::auth::Privilege;
+use crate::config::Compression;
+use crate::database::TableId;
+use crate::error::{Position, SourceSpan};
+use crate::external::{Column, ColumnType};
+use crate::index::Access;
+use crate::partition::{PartitionScheme, Partitioning, MAX_PARTITIONS};
+use crate::table_options::TableOptions;
+use crate::trigger::{Event, Timing, Trigger};
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::path::Path;
+use thiserror::Error;
+
+/// A statement over tables of records, as stored by the embedded API, or
+/// about the session running it. Tables are named by number and rows by
+/// the text form of their id. A `<value>` is a string, a number taken as
+/// its text, a call `<name>(<value> [, ...])` of a function the database
+/// has registered or has built in, such as `now()` and `substr`, one of
+/// `CURRENT_USER`, `SESSION_USER`, `CURRENT_CATALOG` and
+/// `CURRENT_TIMESTAMP`, `EXTRACT(<field> FROM <value>)` as `date_part`
+/// is called, `INTERVAL <string>` or `TIMESTAMP <string>`, or in a
+/// prepared statement a parameter `$1`, `$2` and so on. It may be `NULL`
+/// only where it's selected without a table, shown empty, or passed to a
+/// function, which then returns NULL unless it says otherwise.
+///
+/// ```text
+/// BEGIN [TRANSACTION]
+/// COMMIT
+/// ROLLBACK
+/// SAVEPOINT <name>
+/// ROLLBACK TO [SAVEPOINT] <name>
+/// RELEASE [SAVEPOINT] <name>
+/// PREPARE TRANSACTION <string>
+/// { COMMIT | ROLLBACK } PREPARED <string>
+/// CREATE TABLE [[WITH] (<table option> [, ...])] [PARTITION BY <partitioning>]
+/// CREATE EXTERNAL TABLE (<name> <type> [, ...]) LOCATION <string>
+///     [FORMAT CSV] [[WITH] (<copy option> [, ...])]
+/// INSERT INTO <table> VALUES (<value>) [, (<value>) ...]
+/// SELECT [<hints>] * FROM <table> [WHERE id = <value>] [<order>]
+/// SELECT * FROM <table> AS OF { TIMESTAMP <string> | LSN <number> } [<order>]
+/// SELECT <name>(data [, <value> ...] [<order>]) FROM <table>
+///     [WHERE id = <value>]
+/// SELECT COUNT(*) FROM <table>
+/// SELECT <value> [, ...]
+/// SELECT * FROM <table> WHERE MATCH(data) AGAINST (<value>)
+/// SELECT [<hints>] * FROM <table> WHERE { data | <name> } = <value> [<order>]
+/// SELECT * FROM <table> JOIN <table> ON <key> = <key>
+/// SELECT * FROM <table> WHERE <key> [NOT] IN (SELECT <key> FROM <table>)
+/// SELECT * FROM <table> WHERE [NOT] EXISTS
+///     (SELECT * FROM <table> WHERE <key> = <key>)
+/// EXPLAIN [(FORMAT { TEXT | JSON })] SELECT ...
+/// DECLARE <name> CURSOR FOR SELECT ...
+/// FETCH [<number> | ALL | NEXT] [{ FROM | IN }] <name>
+/// CLOSE { <name> | ALL }
+/// SELECT * FROM information_schema.active_queries
+/// SELECT * FROM information_schema.table_stats
+/// SELECT * FROM information_schema.transactions
+/// SELECT * FROM information_schema.buffer_stats
+/// UPDATE <table> SET data = <value> WHERE id = <value>
+/// DELETE FROM <table> WHERE id = <value>
+/// SET <name> { = | TO } <string, number or word>
+/// SET GLOBAL <name> { = | TO } <string, number or word>
+/// SHOW { <name> | ALL }
+/// PREPARE <name> AS <statement>
+/// EXECUTE <name> [(<string> [, <string> ...])]
+/// DEALLOCATE <name>
+/// CREATE USER <name> [WITH] PASSWORD <string> [SUPERUSER]
+/// DROP USER <name>
+/// GRANT <privileges> ON <tables> TO <name>
+/// REVOKE <privileges> ON <tables> FROM <name>
+/// COPY <table> FROM <string> [[WITH] (<copy option> [, ...])]
+/// COPY { <table> | (<select>) } TO <string> [[WITH] (<copy option> [, ...])]
+/// KILL QUERY <number>
+/// CREATE TRIGGER <name> { BEFORE | AFTER } { INSERT | UPDATE | DELETE }
+///     ON <table> [FOR EACH ROW] EXECUTE <write>
+/// DROP TRIGGER <name>
+/// CREATE FULLTEXT INDEX <name> ON <table> (data)
+/// CREATE { UNIQUE INDEX | PRIMARY KEY } <name> ON <table> ({ data | <column> })
+/// DROP INDEX <name>
+/// ANALYZE [<table>]
+/// CHECKPOINT
+/// VACUUM [<table>]
+/// BACKUP TO <string> [INCREMENTAL FROM <string>]
+/// ```
+///
+/// where `<privileges>` is `ALL [PRIVILEGES]` or a list of `SELECT`,
+/// `INSERT`, `UPDATE`, `DELETE` and `DDL`, `<tables>` is
+/// `[TABLE] <table>` or `ALL TABLES`, and a `<copy option>` is
+/// `FORMAT { CSV | JSON | PARQUET }`, `HEADER [TRUE | FALSE]`,
+/// `DELIMITER <string>`, `QUOTE <string>` or `ESCAPE <string>`, and a
+/// `<table option>` is `FILL_FACTOR = <number>`, from 10 to 100,
+/// `COMPRESSION = { 'lz' | 'none' }`, `BLOOM_FILTER = { TRUE | FALSE }`
+/// or `TTL_COLUMN = <string>`, naming the column rows expire by, and a
+/// `<partitioning>` is `HASH (<key>) PARTITIONS <number>` or
+/// `RANGE (<key>) SPLIT AT (<string> [, ...])`, its key `data` or a
+/// column's name. Files named `.json`, `.jsonl` or `.ndjson` are JSON by
+/// default, `.parquet` Parquet and others CSV; JSON can't be read. An
+/// external table's `<type>` is `TEXT`, `INTEGER` or `REAL`, `BOOLEAN`,
+/// or a synonym, and its location a CSV file or a directory of them. A
+/// trigger's `<write>` is an `INSERT`, `UPDATE` or `DELETE` whose values
+/// may also be `NEW`, the data a row is inserted or updated with, or
+/// `OLD`, the data of a row updated or deleted.
+///
+/// `<hints>` is a comment `/*+ <hint> [<hint> ...] */` overriding how the
+/// database would read the table: `FULL` to scan it, or `BLOOM_FILTER` or
+/// `NO_BLOOM_FILTER` to read it through its Bloom filters or not, each
+/// optionally naming the table, as in `FULL(3)`. Hints the database
+/// doesn't know, or naming another table, are ignored, as are hints
+/// anywhere but straight after `SELECT`.
+///
+/// `<order>` is `ORDER BY <key> [ASC | DESC]`, and a `<key>` is `id`,
+/// `data` or a column's name; rows without the column come last, or
+/// first if descending, and don't join. A join's first key is the first
+/// table's, and an `EXISTS`'s second the outer table's. As in SQL, `NOT
+/// IN` finds nothing if a row of its subquery has no key, while `NOT
+/// EXISTS` finds the rows without one. An aggregate is one the database
+/// has registered or has built in, such as `sum` and `string_agg`, and
+/// folds the rows' data in `<order>` if it's given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Statement {
+    Begin,
+    Commit,
+    Rollback,
+    /// Mark the current point of the transaction under this name
+    Savepoint(String),
+    /// Undo the transaction's changes since the savepoint with this name
+    RollbackTo(String),
+    /// Forget the savepoint with this name and those after it
+    Release(String),
+    /// End the transaction prepared to commit under this global id, to be
+    /// committed or rolled back later by any session
+    PrepareTransaction(String),
+    /// Commit the transaction prepared under this global id
+    CommitPrepared(String),
+    /// Roll back the transaction prepared under this global id
+    RollbackPrepared(String),
+    /// A table stored as its options say, or as the database's are where
+    /// they say nothing
+    CreateTable(TableOptions),
+    /// A table keeping its rows in partitions, each stored as its options
+    /// say
+    CreatePartitionedTable {
+        options: TableOptions,
+        partitioning: Partitioning,
+    },
+    /// A table whose rows are read from the CSV files at `location`
+    CreateExternalTable {
+        columns: Vec<Column>,
+        location: String,
+        options: CopyOptions,
+    },
+    Insert {
+        table: u32,
+        values: Vec<Value>,
+    },
+    Select {
+        table: u32,
+        row: Option<Value>,
+        order: Option<Order>,
+    },
+    /// The rows of `table` as they were at `as_of`, read back through the
+    /// log
+    SelectAsOf {
+        table: u32,
+        as_of: AsOfPoint,
+        order: Option<Order>,
+    },
+    /// The rows `Select` would return, folded by the aggregate `function`
+    /// given `args` after their data, in `order` if it matters
+    Aggregate {
+        function: String,
+        args: Vec<Value>,
+        order: Option<Order>,
+        table: u32,
+        row: Option<Value>,
+    },
+    /// The number of rows in `table`
+    Count(u32),
+    /// One row of `values`, as `SELECT` without a table
+    Values(Vec<Value>),
+    Update {
+        table: u32,
+        row: Value,
+        value: Value,
+    },
+    Delete {
+        table: u32,
+        row: Value,
+    },
+    Set {
+        name: String,
+        value: String,
+    },
+    /// A setting of the database changed while it's open, named by its
+    /// path or the last part of it
+    SetGlobal {
+        name: String,
+        value: String,
+    },
+    Show(String),
+    /// Every session variable with a value, the session's own with their
+    /// defaults
+    ShowAll,
+    Prepare {
+        name: String,
+        statement: Box<Statement>,
+    },
+    Execute {
+        name: String,
+        params: Vec<String>,
+    },
+    Deallocate(String),
+    CreateUser {
+        name: String,
+        password: String,
+        superuser: bool,
+    },
+    DropUser(String),
+    Grant {
+        privileges: Vec<Privilege>,
+        /// `None` for every table
+        table: Option<u32>,
+        user: String,
+    },
+    Revoke {
+        privileges: Vec<Privilege>,
+        table: Option<u32>,
+        user: String,
+    },
+    /// Load the CSV file at `path` into `table`
+    CopyFrom {
+        table: u32,
+        path: String,
+        options: CopyOptions,
+    },
+    /// Write the rows `query`, a `Select`, returns to the file at `path`
+    CopyTo {
+        query: Box<Statement>,
+        path: String,
+        options: CopyOptions,
+    },
+    /// List the statements running in every session
+    ActiveQueries,
+    /// List each table's reads and writes since the database opened
+    TableStats,
+    /// List the running transactions, prepared ones among them
+    Transactions,
+    /// Show the buffer pool's hit ratio and each table's pages in it
+    BufferStats,
+    /// Cancel the running statement with this id
+    KillQuery(u64),
+    CreateTrigger(Trigger),
+    DropTrigger(String),
+    /// Index the words in `table`'s rows
+    CreateFullTextIndex {
+        name: String,
+        table: u32,
+    },
+    /// Keep `table`'s rows from sharing a key: the value of `column`, or
+    /// their data if `None`. A primary key has every row have one
+    CreateUniqueIndex {
+        name: String,
+        table: u32,
+        column: Option<String>,
+        primary: bool,
+    },
+    DropIndex(String),
+    /// The rows of `table` with any of the words in `terms`, best first,
+    /// through its full-text index
+    Search {
+        table: u32,
+        terms: Value,
+    },
+    /// The rows of `table` whose `column`, or data for `None`, is `value`
+    Find {
+        table: u32,
+        column: Option<String>,
+        value: Value,
+        /// How hints say to read the table, rather than as the database
+        /// would choose
+        access: Option<Access>,
+        order: Option<Order>,
+    },
+    /// The pairs of rows of `left` and `right` whose keys are equal
+    Join {
+        left: u32,
+        right: u32,
+        left_key: Key,
+        right_key: Key,
+    },
+    /// The rows of `table` whose `key` is, or with `anti` isn't, that of
+    /// a row of `other` by `other_key`
+    SemiJoin {
+        table: u32,
+        key: Key,
+        other: u32,
+        other_key: Key,
+        anti: bool,
+        /// Whether written with `EXISTS` rather than `IN`, which differ
+        /// for rows without a key
+        exists: bool,
+    },
+    /// How the database would run `query`, a `Select`, `Find`, `Join` or
+    /// `SemiJoin`
+    Explain {
+        query: Box<Statement>,
+        format: ExplainFormat,
+    },
+    /// Gather the statistics of `table`, or of every table for `None`
+    Analyze(Option<u32>),
+    /// Write every changed page and log a checkpoint
+    Checkpoint,
+    /// Reclaim the space of deleted rows in `table`'s pages, or in every
+    /// table's for `None`
+    Vacuum(Option<u32>),
+    /// Copy the database to the directory at `path` while it stays in use,
+    /// or only the pages changed since the backup in `base`
+    Backup {
+        path: String,
+        base: Option<String>,
+    },
+    /// A cursor named `name` over the rows of `query`, a `Select`, `Find`,
+    /// `Join` or `SemiJoin`
+    Declare {
+        name: String,
+        query: Box<Statement>,
+    },
+    /// The next `count` rows of a cursor, or all it has left for `None`
+    Fetch {
+        name: String,
+        count: Option<u64>,
+    },
+    /// Close a cursor, or every one for `None`
+    Close(Option<String>),
+}
+
+/// How `EXPLAIN` shows a plan: a line to a step, or a tree in JSON with
+/// each step's estimates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExplainFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// An `ORDER BY`, sorting rows by a key, smallest first unless
+/// `descending`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Order {
+    pub(crate) key: Key,
+    pub(crate) descending: bool,
+}
+
+/// The point in the past `AS OF` reads a table as of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum AsOfPoint {
+    /// As the transactions committed by this time, as `TIMESTAMP` takes
+    /// it, left it
+    Timestamp(String),
+    /// As the transactions committed before the log record at this LSN
+    /// left it
+    Lsn(u64),
+}
+
+/// What rows are ordered or joined by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Key {
+    Id,
+    Data,
+    /// A column of rows that are JSON objects, as `column_value` reads it
+    Column(String),
+}
+
+/// How `COPY` reads or writes a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CopyOptions {
+    pub(crate) format: CopyFormat,
+    /// Whether the first line is a header, skipped when reading
+    pub(crate) header: bool,
+    pub(crate) delimiter: u8,
+    /// What quotes a field holding the delimiter, a quote or a line break
+    pub(crate) quote: u8,
+    /// What comes before a quote, or itself, in a quoted field to stand
+    /// for it; by default the quote, which is then doubled
+    pub(crate) escape: u8,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            format: CopyFormat::Csv,
+            header: false,
+            delimiter: b',',
+            quote: b'"',
+            escape: b'"',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CopyFormat {
+    Csv,
+    /// One object per line, keyed by column
+    Json,
+    /// Read and written only with the `parquet` feature
+    Parquet,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Value {
+    String(String),
+    /// A prepared statement's parameter, numbered from 1
+    Param(usize),
+    /// In a trigger's statement, the data its row is written with
+    New,
+    /// In a trigger's statement, the data its row had
+    Old,
+    /// NULL, which only a value selected without a table may end up as
+    Null,
+    /// A registered function called on the values of `args`
+    Call {
+        name: String,
+        args: Vec<Value>,
+    },
+}
+
+impl Statement {
+    /// This statement with its parameters replaced by `params`, the first
+    /// for `$1`.
+    pub(crate) fn bind(&self, params: &[String]) -> Result<Statement, ParseError> {
+        let bind = |value: &Value| value.bind(params);
+        Ok(match self {
+            Self::Insert { table, values } => Self::Insert {
+                table: *table,
+                values: values.iter().map(bind).collect::<Result<_, _>>()?,
+            },
+            Self::Select { table, row, order } => Self::Select {
+                table: *table,
+                row: row.as_ref().map(bind).transpose()?,
+                order: order.clone(),
+            },
+            Self::Aggregate {
+                function,
+                args,
+                order,
+                table,
+                row,
+            } => Self::Aggregate {
+                function: function.clone(),
+                args: args.iter().map(bind).collect::<Result<_, _>>()?,
+                order: order.clone(),
+                table: *table,
+                row: row.as_ref().map(bind).transpose()?,
+            },
+            Self::Search { table, terms } => Self::Search {
+                table: *table,
+                terms: bind(terms)?,
+            },
+            Self::Values(values) => {
+                Self::Values(values.iter().map(bind).collect::<Result<_, _>>()?)
+            }
+            Self::Find {
+                table,
+                column,
+                value,
+                access,
+                order,
+            } => Self::Find {
+                table: *table,
+                column: column.clone(),
+                value: bind(value)?,
+                access: *access,
+                order: order.clone(),
+            },
+            Self::Update { table, row, value } => Self::Update {
+                table: *table,
+                row: bind(row)?,
+                value: bind(value)?,
+            },
+            Self::Delete { table, row } => Self::Delete {
+                table: *table,
+                row: bind(row)?,
+            },
+            statement => statement.clone(),
+        })
+    }
+}
+
+impl Value {
+    /// The value with its parameters, and those of any call's arguments,
+    /// taken from `params`.
+    fn bind(&self, params: &[String]) -> Result<Value, ParseError> {
+        match self {
+            Value::Param(n) => params
+                .get(n - 1)
+                .map(|param| Value::String(param.clone()))
+                .ok_or(ParseError::MissingParameter(*n)),
+            Value::Call { name, args } => Ok(Value::Call {
+                name: name.clone(),
+                args: args
+                    .iter()
+                    .map(|arg| arg.bind(params))
+                    .collect::<Result<_, _>>()?,
+            }),
+            value => Ok(value.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub(crate) enum ParseError {
+    #[error("Unterminated string")]
+    UnterminatedString,
+
+    #[error("Unterminated comment")]
+    UnterminatedComment,
+
+    #[error("Invalid number")]
+    InvalidNumber,
+
+    #[error("Expected {expected}, found {found}")]
+    Unexpected {
+        expected: &'static str,
+        found: String,
+    },
+
+    #[error("No value given for parameter ${0}")]
+    MissingParameter(usize),
+
+    #[error("Only a query can be prepared")]
+    NotPreparable,
+
+    #[error("A {event} trigger has no {row} row")]
+    NoTransitionRow {
+        row: &'static str,
+        event: &'static str,
+    },
+}
+
+/// A hint given in a comment `/*+ ... */`: its name in upper case, and
+/// the words in parentheses after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Hint {
+    pub(crate) name: String,
+    pub(crate) args: Vec<String>,
+}
+
+impl Hint {
+    /// The hints in `text`, up to any it can't make out, which hints being
+    /// advice, is no error.
+    fn parse(text: &str) -> Vec<Hint> {
+        let mut hints = Vec::new();
+        let mut tokens = tokenize(text)
+            .map_while(Result::ok)
+            .map(|item| item.token)
+            .filter(|token| !matches!(token, Token::Separator(Separator::Whitespace(_))))
+            .peekable();
+        let open = Token::Separator(Separator::Operator(Operator::ParenOpen));
+        let close = Token::Separator(Separator::Operator(Operator::ParenClose));
+        loop {
+            // Such as `INDEX`, which is also a keyword
+            let name = match tokens.next() {
+                Some(Token::Identifier(name)) => name,
+                Some(token @ Token::Keyword(_)) => describe(Some(&token)),
+                _ => return hints,
+            };
+            let mut args = Vec::new();
+            if tokens.next_if_eq(&open).is_some() {
+                loop {
+                    match tokens.next() {
+                        Some(Token::Identifier(arg) | Token::Number(arg)) => args.push(arg),
+                        Some(Token::Separator(Separator::Comma)) => {}
+                        Some(token) if token == close => break,
+                        _ => return hints,
+                    }
+                }
+            }
+            hints.push(Hint {
+                name: name.to_uppercase(),
+                args,
+            });
+        }
+    }
+}
+
+/// The view listing running statements.
+pub(crate) const ACTIVE_QUERIES: &str = "information_schema.active_queries";
+
+/// The view listing each table's reads and writes.
+pub(crate) const TABLE_STATS: &str = "information_schema.table_stats";
+
+/// The view listing running transactions.
+pub(crate) const TRANSACTIONS: &str = "information_schema.transactions";
+
+/// The view of the buffer pool's hits and each table's pages in it.
+pub(crate) const BUFFER_STATS: &str = "information_schema.buffer_stats";
+
+/// The built-in functions SQL calls without parentheses.
+const NILADIC: &[&str] = &[
+    "current_catalog",
+    "current_timestamp",
+    "current_user",
+    "session_user",
+];
+
+const COPY_OPTIONS: &[&str] = &["FORMAT", "HEADER", "DELIMITER", "QUOTE", "ESCAPE"];
+
+/// A statement that didn't parse, and where in its text, unless it's
+/// empty.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct SyntaxError {
+    pub(crate) error: ParseError,
+    pub(crate) span: Option<SourceSpan>,
+}
+
+/// Parse the statements in `sql`, separated by semicolons.
+#[cfg(test)]
+pub(crate) fn parse(sql: &str) -> Result<Vec<Statement>, ParseError> {
+    parse_spanned(sql).map_err(|e| e.error)
+}
+
+/// Parse the statements in `sql`, as `parse` does, failing with where in
+/// it the first that doesn't parse went wrong.
+pub(crate) fn parse_spanned(sql: &str) -> Result<Vec<Statement>, SyntaxError> {
+    let (tokens, spans) = tokens(sql)?;
+    let mut parser = Parser::new(tokens, spans);
+    let mut statements = Vec::new();
+    loop {
+        while parser.eat(|token| *token == Token::Separator(Separator::Semicolon)) {}
+        if parser.peek().is_none() {
+            return Ok(statements);
+        }
+        let statement = parser.statement().map_err(|e| parser.failed(e))?;
+        statements.push(statement);
+        if parser.peek().is_some() {
+            parser
+                .expect("end of statement", |token| {
+                    *token == Token::Separator(Separator::Semicolon)
+                })
+                .map_err(|e| parser.failed(e))?;
+        }
+    }
+}
+
+/// Parse each statement in `sql` on its own, so one that doesn't parse
+/// leaves the others: what's between one semicolon and the next, unless
+/// there's nothing there. Fails only if `sql` can't be split, such as for
+/// a string left open.
+pub(crate) fn parse_script(sql: &str) -> Result<Vec<Result<Statement, SyntaxError>>, SyntaxError> {
+    let mut split = vec![(Vec::new(), Vec::new())];
+    let (tokens, spans) = tokens(sql)?;
+    for (token, span) in tokens.into_iter().zip(spans) {
+        match token {
+            Token::Separator(Separator::Semicolon) => split.push((Vec::new(), Vec::new())),
+            token => {
+                let (tokens, spans) = split.last_mut().unwrap();
+                tokens.push(token);
+                spans.push(span);
+            }
+        }
+    }
+    let statements = split
+        .into_iter()
+        .filter(|(tokens, _)| !tokens.is_empty())
+        .map(|(tokens, spans)| {
+            let mut parser = Parser::new(tokens, spans);
+            let statement = parser.statement().map_err(|e| parser.failed(e))?;
+            match parser.peek() {
+                None => Ok(statement),
+                found => Err(parser.failed(ParseError::Unexpected {
+                    expected: "end of statement",
+                    found: describe(found),
+                })),
+            }
+        })
+        .collect();
+    Ok(statements)
+}
+
+/// `sql` with the password of each `PASSWORD '...'` in it written as
+/// `'***'`, for showing it where the statement's privileges don't reach,
+/// such as the log.
+pub(crate) fn redact_passwords(sql: &str) -> Cow<'_, str> {
+    let mentioned = sql
+        .as_bytes()
+        .windows("PASSWORD".len())
+        .any(|window| window.eq_ignore_ascii_case(b"PASSWORD"));
+    if !mentioned {
+        return Cow::Borrowed(sql);
+    }
+    // Strings don't span lines, so each is a run of a line's characters
+    let mut passwords = Vec::new();
+    let mut after_password = false;
+    for item in tokenize(sql) {
+        let Ok(item) = item else {
+            break;
+        };
+        match item.token {
+            Token::Separator(Separator::Whitespace(_)) => continue,
+            Token::String(_) if after_password => passwords.push((item.start, item.end)),
+            _ => {}
+        }
+        after_password =
+            matches!(&item.token, Token::Identifier(word) if word.eq_ignore_ascii_case("PASSWORD"));
+    }
+    if passwords.is_empty() {
+        return Cow::Borrowed(sql);
+    }
+    let mut redacted = String::with_capacity(sql.len());
+    let mut passwords = passwords.into_iter().peekable();
+    for (row, line) in sql.split_inclusive('\n').enumerate() {
+        let mut quoted = None;
+        for (col, character) in line.chars().enumerate() {
+            let at = |location: CharacterLocation| location.row == row && location.col == col;
+            match passwords.peek() {
+                Some(&(start, _)) if at(start) => {
+                    redacted.push_str("'***'");
+                    quoted = passwords.next();
+                }
+                _ if quoted.is_some() => {}
+                _ => redacted.push(character),
+            }
+            if quoted.is_some_and(|(_, end)| at(end)) {
+                quoted = None;
+            }
+        }
+    }
+    Cow::Owned(redacted)
+}
+
+/// The span in a statement's text from `start` up to `end`.
+fn span(start: CharacterLocation, end: CharacterLocation) -> SourceSpan {
+    let position = |at: CharacterLocation| Position {
+        line: at.row + 1,
+        column: at.col + 1,
+    };
+    SourceSpan {
+        start: position(start),
+        end: position(end),
+    }
+}
+
+/// The tokens of `sql` a statement is parsed from, and where each is.
+fn tokens(sql: &str) -> Result<(Vec<Token>, Vec<SourceSpan>), SyntaxError> {
+    let mut tokens = Vec::new();
+    let mut spans = Vec::new();
+    for item in tokenize(sql) {
+        let (error, at) = match item {
+            Ok(item) => {
+                match item.token {
+                    Token::Separator(Separator::Whitespace(_)) => {}
+                    // Hints only count where they can apply
+                    Token::Hint(_) if tokens.last() != Some(&Token::Keyword(Keyword::Select)) => {}
+                    token => {
+                        tokens.push(token);
+                        spans.push(span(item.start, item.end));
+                    }
+                }
+                continue;
+            }
+            Err(TokenizerError::UnterminatedString(at)) => (ParseError::UnterminatedString, at),
+            Err(TokenizerError::UnterminatedComment(at)) => (ParseError::UnterminatedComment, at),
+            Err(TokenizerError::InvalidNumber(at)) => (ParseError::InvalidNumber, at),
+        };
+        return Err(SyntaxError {
+            error,
+            span: Some(span(at, at)),
+        });
+    }
+    Ok((tokens, spans))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    /// Where each token is in the statement's text
+    spans: Vec<SourceSpan>,
+    at: usize,
+    /// The event of the trigger whose statement is being parsed, which
+    /// may use `NEW` and `OLD` as it has them
+    trigger: Option<Event>,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>, spans: Vec<SourceSpan>) -> Self {
+        Self {
+            tokens,
+            spans,
+            at: 0,
+            trigger: None,
+        }
+    }
+
+    /// `error`, at the token it's about: the one the parser stopped at if
+    /// it's the one found, otherwise the one it last took, or the end of
+    /// the last if it ran out.
+    fn failed(&self, error: ParseError) -> SyntaxError {
+        let at = match &error {
+            ParseError::Unexpected { found, .. } if *found == describe(self.peek()) => self.at,
+            _ => self.at.saturating_sub(1),
+        };
+        let end = self.spans.last().map(|last| SourceSpan {
+            start: last.end,
+            end: last.end,
+        });
+        SyntaxError {
+            error,
+            span: self.spans.get(at).copied().or(end),
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, ParseError> {
+        let keyword = self.expect("a statement", |token| {
+            matches!(token, Token::Keyword(_) | Token::Identifier(_))
+        })?;
+        Ok(match keyword {
+            Token::Keyword(Keyword::Begin) => {
+                self.eat(|token| *token == Token::Keyword(Keyword::Transaction));
+                Statement::Begin
+            }
+            Token::Keyword(Keyword::Commit) => {
+                if self.eat(|token| *token == Token::Keyword(Keyword::Prepared)) {
+                    Statement::CommitPrepared(self.string()?)
+                } else {
+                    Statement::Commit
+                }
+            }
+            Token::Keyword(Keyword::Rollback) => {
+                if self.eat(|token| *token == Token::Keyword(Keyword::Prepared)) {
+                    Statement::RollbackPrepared(self.string()?)
+                } else if self.eat(|token| *token == Token::Keyword(Keyword::To)) {
+                    self.eat(|token| *token == Token::Keyword(Keyword::Savepoint));
+                    Statement::RollbackTo(self.name()?)
+                } else {
+                    Statement::Rollback
+                }
+            }
+            Token::Keyword(Keyword::Savepoint) => Statement::Savepoint(self.name()?),
+            Token::Keyword(Keyword::Release) => {
+                self.eat(|token| *token == Token::Keyword(Keyword::Savepoint));
+                Statement::Release(self.name()?)
+            }
+            Token::Keyword(Keyword::Create) => match self.expect("TABLE, EXTERNAL, USER, TRIGGER, FULLTEXT, UNIQUE or PRIMARY", |token| {
+                matches!(token, Token::Keyword(Keyword::Table | Keyword::Unique | Keyword::Primary))
+                    || matches!(token, Token::Identifier(word)
+                        if ["USER", "EXTERNAL", "TRIGGER", "FULLTEXT"].iter().any(|kind| word.eq_ignore_ascii_case(kind)))
+            })? {
+                Token::Keyword(Keyword::Table) => {
+                    let options = self.table_options()?;
+                    match self.partitioning()? {
+                        Some(partitioning) => Statement::CreatePartitionedTable {
+                            options,
+                            partitioning,
+                        },
+                        None => Statement::CreateTable(options),
+                    }
+                }
+                Token::Identifier(word) if word.eq_ignore_ascii_case("EXTERNAL") => {
+                    self.external_table()?
+                }
+                Token::Identifier(word) if word.eq_ignore_ascii_case("TRIGGER") => {
+                    Statement::CreateTrigger(self.trigger()?)
+                }
+                Token::Identifier(word) if word.eq_ignore_ascii_case("FULLTEXT") => {
+                    self.keyword(Keyword::Index)?;
+                    let name = self.name()?;
+                    self.word("ON")?;
+                    let table = self.table()?;
+                    self.operator(Operator::ParenOpen)?;
+                    self.word("DATA")?;
+                    self.operator(Operator::ParenClose)?;
+                    Statement::CreateFullTextIndex { name, table }
+                }
+                Token::Keyword(keyword @ (Keyword::Unique | Keyword::Primary)) => {
+                    let primary = keyword == Keyword::Primary;
+                    self.keyword(if primary { Keyword::Key } else { Keyword::Index })?;
+                    let name = self.name()?;
+                    self.word("ON")?;
+                    let table = self.table()?;
+                    self.operator(Operator::ParenOpen)?;
+                    let key = self.name()?;
+                    self.operator(Operator::ParenClose)?;
+                    Statement::CreateUniqueIndex {
+                        name,
+                        table,
+                        column: (key != "data").then_some(key),
+                        primary,
+                    }
+                }
+                _ => {
+                    let name = self.name()?;
+                    self.eat(|token| {
+                        matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("WITH"))
+                    });
+                    self.word("PASSWORD")?;
+                    let password = self.string()?;
+                    let superuser = self.eat(|token| {
+                        matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("SUPERUSER"))
+                    });
+                    Statement::CreateUser {
+                        name,
+                        password,
+                        superuser,
+                    }
+                }
+            },
+            Token::Keyword(Keyword::Drop) => match self.expect("USER, TRIGGER or INDEX", |token| {
+                *token == Token::Keyword(Keyword::Index)
+                    || matches!(token, Token::Identifier(word)
+                        if word.eq_ignore_ascii_case("USER") || word.eq_ignore_ascii_case("TRIGGER"))
+            })? {
+                Token::Keyword(Keyword::Index) => Statement::DropIndex(self.name()?),
+                Token::Identifier(word) if word.eq_ignore_ascii_case("TRIGGER") => {
+                    Statement::DropTrigger(self.name()?)
+                }
+                _ => Statement::DropUser(self.name()?),
+            },
+            Token::Keyword(Keyword::Insert) => {
+                self.word("INTO")?;
+                let table = self.table()?;
+                self.keyword(Keyword::Values)?;
+                let mut values = Vec::new();
+                loop {
+                    self.operator(Operator::ParenOpen)?;
+                    values.push(self.value()?);
+                    self.operator(Operator::ParenClose)?;
+                    if !self.eat(|token| *token == Token::Separator(Separator::Comma)) {
+                        break;
+                    }
+                }
+                Statement::Insert { table, values }
+            }
+            Token::Keyword(Keyword::Select) => {
+                let hints = self.hints();
+                // An aggregate's argument is `data`, or `*` for COUNT
+                let folded = matches!(
+                    self.tokens.get(self.at + 2),
+                    Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("DATA")
+                ) || self.tokens.get(self.at + 2)
+                    == Some(&Token::Separator(Separator::Operator(Operator::Multiply)));
+                match self.peek() {
+                    Some(Token::Identifier(_)) if folded => self.aggregate()?,
+                    Some(
+                        Token::Identifier(_)
+                        | Token::String(_)
+                        | Token::Number(_)
+                        | Token::Keyword(Keyword::Null | Keyword::Timestamp)
+                        | Token::Separator(Separator::Operator(Operator::Subtract)),
+                    ) => self.values()?,
+                    _ => self.select(&hints)?,
+                }
+            }
+            Token::Keyword(Keyword::Update) => {
+                let table = self.table()?;
+                self.keyword(Keyword::Set)?;
+                self.word("DATA")?;
+                self.operator(Operator::Eq)?;
+                let value = self.value()?;
+                let row = self.where_id()?;
+                Statement::Update { table, row, value }
+            }
+            Token::Keyword(Keyword::Delete) => {
+                self.keyword(Keyword::From)?;
+                let table = self.table()?;
+                let row = self.where_id()?;
+                Statement::Delete { table, row }
+            }
+            Token::Keyword(Keyword::Set) => {
+                // Unless it's the name of the session's setting
+                let global = matches!(
+                    (self.peek(), self.tokens.get(self.at + 1)),
+                    (Some(Token::Identifier(word)), Some(Token::Identifier(_)))
+                        if word.eq_ignore_ascii_case("GLOBAL")
+                );
+                if global {
+                    self.word("GLOBAL")?;
+                }
+                let name = self.name()?;
+                self.expect("= or TO", |token| {
+                    matches!(
+                        token,
+                        Token::Separator(Separator::Operator(Operator::Eq))
+                            | Token::Keyword(Keyword::To)
+                    )
+                })?;
+                let value = match self.expect("a value", |token| {
+                    matches!(
+                        token,
+                        Token::String(_)
+                            | Token::Number(_)
+                            | Token::Identifier(_)
+                            | Token::Keyword(_)
+                    )
+                })? {
+                    Token::String(value) | Token::Number(value) | Token::Identifier(value) => value,
+                    keyword => describe(Some(&keyword)),
+                };
+                if global {
+                    Statement::SetGlobal { name, value }
+                } else {
+                    Statement::Set { name, value }
+                }
+            }
+            // Told apart from a statement named `transaction` by the string
+            Token::Keyword(Keyword::Prepare)
+                if self.peek() == Some(&Token::Keyword(Keyword::Transaction))
+                    && matches!(self.tokens.get(self.at + 1), Some(Token::String(_))) =>
+            {
+                self.at += 1;
+                Statement::PrepareTransaction(self.string()?)
+            }
+            Token::Keyword(Keyword::Prepare) => {
+                let name = self.name()?;
+                self.keyword(Keyword::As)?;
+                let statement = self.statement()?;
+                if matches!(
+                    statement,
+                    Statement::Prepare { .. }
+                        | Statement::Execute { .. }
+                        | Statement::Deallocate(_)
+                        | Statement::CreateUser { .. }
+                        | Statement::DropUser(_)
+                        | Statement::Grant { .. }
+                        | Statement::Revoke { .. }
+                        | Statement::CopyFrom { .. }
+                        | Statement::CopyTo { .. }
+                        | Statement::CreateExternalTable { .. }
+                        | Statement::KillQuery(_)
+                        | Statement::CreateTrigger(_)
+                        | Statement::DropTrigger(_)
+                        | Statement::CreateFullTextIndex { .. }
+                        | Statement::CreateUniqueIndex { .. }
+                        | Statement::DropIndex(_)
+                        | Statement::Analyze(_)
+                        | Statement::Checkpoint
+                        | Statement::Vacuum(_)
+                        | Statement::Backup { .. }
+                        | Statement::Explain { .. }
+                        | Statement::Declare { .. }
+                        | Statement::Fetch { .. }
+                        | Statement::Close(_)
+                        | Statement::PrepareTransaction(_)
+                        | Statement::CommitPrepared(_)
+                        | Statement::RollbackPrepared(_)
+                ) {
+                    return Err(ParseError::NotPreparable);
+                }
+                Statement::Prepare {
+                    name,
+                    statement: Box::new(statement),
+                }
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("SHOW") => {
+                match self.name()? {
+                    name if name == "all" => Statement::ShowAll,
+                    name => Statement::Show(name),
+                }
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("EXECUTE") => {
+                let name = self.name()?;
+                let mut params = Vec::new();
+                if self.eat(|token| {
+                    *token == Token::Separator(Separator::Operator(Operator::ParenOpen))
+                }) {
+                    loop {
+                        params.push(self.string()?);
+                        if !self.eat(|token| *token == Token::Separator(Separator::Comma)) {
+                            break;
+                        }
+                    }
+                    self.operator(Operator::ParenClose)?;
+                }
+                Statement::Execute { name, params }
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("DEALLOCATE") => {
+                Statement::Deallocate(self.name()?)
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("GRANT") => {
+                let (privileges, table) = self.privileges()?;
+                self.keyword(Keyword::To)?;
+                let user = self.name()?;
+                Statement::Grant {
+                    privileges,
+                    table,
+                    user,
+                }
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("REVOKE") => {
+                let (privileges, table) = self.privileges()?;
+                self.keyword(Keyword::From)?;
+                let user = self.name()?;
+                Statement::Revoke {
+                    privileges,
+                    table,
+                    user,
+                }
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("COPY") => {
+                let query = if self.eat(|token| {
+                    *token == Token::Separator(Separator::Operator(Operator::ParenOpen))
+                }) {
+                    self.keyword(Keyword::Select)?;
+                    let hints = self.hints();
+                    let query = self.select(&hints)?;
+                    let (expected, found) = match &query {
+                        Statement::Select { order: None, .. } => ("", String::new()),
+                        Statement::Select { .. } => (")", "ORDER".to_string()),
+                        Statement::SelectAsOf { .. } => (")", "AS".to_string()),
+                        Statement::ActiveQueries => ("a table number", ACTIVE_QUERIES.to_string()),
+                        Statement::TableStats => ("a table number", TABLE_STATS.to_string()),
+                        Statement::Transactions => ("a table number", TRANSACTIONS.to_string()),
+                        Statement::BufferStats => ("a table number", BUFFER_STATS.to_string()),
+                        Statement::Search { .. } => ("ID", "MATCH".to_string()),
+                        Statement::Join { .. } => (")", "JOIN".to_string()),
+                        Statement::SemiJoin { exists: true, .. } => ("ID", "EXISTS".to_string()),
+                        Statement::SemiJoin { .. } => ("=", "IN".to_string()),
+                        Statement::Find { column, .. } => {
+                            ("ID", column.as_deref().unwrap_or("data").to_uppercase())
+                        }
+                        _ => unreachable!("a SELECT parses as one of these"),
+                    };
+                    if !found.is_empty() {
+                        return Err(ParseError::Unexpected { expected, found });
+                    }
+                    self.operator(Operator::ParenClose)?;
+                    self.keyword(Keyword::To)?;
+                    query
+                } else {
+                    let table = self.table()?;
+                    let direction = self.expect("FROM or TO", |token| {
+                        matches!(token, Token::Keyword(Keyword::From | Keyword::To))
+                    })?;
+                    if direction == Token::Keyword(Keyword::From) {
+                        let path = self.string()?;
+                        let options = self.copy_options(&path)?;
+                        if options.format == CopyFormat::Json {
+                            return Err(ParseError::Unexpected {
+                                expected: "a CSV or Parquet file",
+                                found: "JSON".to_string(),
+                            });
+                        }
+                        return Ok(Statement::CopyFrom {
+                            table,
+                            path,
+                            options,
+                        });
+                    }
+                    Statement::Select {
+                        table,
+                        row: None,
+                        order: None,
+                    }
+                };
+                let path = self.string()?;
+                let options = self.copy_options(&path)?;
+                Statement::CopyTo {
+                    query: Box::new(query),
+                    path,
+                    options,
+                }
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("EXPLAIN") => {
+                let format = self.explain_format()?;
+                Statement::Explain {
+                    query: Box::new(self.table_query()?),
+                    format,
+                }
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("DECLARE") => {
+                let name = self.name()?;
+                self.word("CURSOR")?;
+                self.word("FOR")?;
+                Statement::Declare {
+                    name,
+                    query: Box::new(self.table_query()?),
+                }
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("FETCH") => {
+                let count = match self.peek() {
+                    Some(Token::Number(number)) => {
+                        let count = number.parse().map_err(|_| ParseError::Unexpected {
+                            expected: "a row count",
+                            found: number.clone(),
+                        })?;
+                        self.at += 1;
+                        Some(count)
+                    }
+                    Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("ALL") => {
+                        self.at += 1;
+                        None
+                    }
+                    Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("NEXT") => {
+                        self.at += 1;
+                        Some(1)
+                    }
+                    _ => Some(1),
+                };
+                self.eat(|token| {
+                    matches!(token, Token::Keyword(Keyword::From | Keyword::In))
+                });
+                let name = self.name()?;
+                Statement::Fetch { name, count }
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("CLOSE") => {
+                match self.name()? {
+                    name if name == "all" => Statement::Close(None),
+                    name => Statement::Close(Some(name)),
+                }
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("ANALYZE") => {
+                match self.peek() {
+                    Some(Token::Number(_)) => Statement::Analyze(Some(self.table()?)),
+                    _ => Statement::Analyze(None),
+                }
+            }
+            Token::Keyword(Keyword::Checkpoint) => Statement::Checkpoint,
+            Token::Keyword(Keyword::Vacuum) => match self.peek() {
+                Some(Token::Number(_)) => Statement::Vacuum(Some(self.table()?)),
+                _ => Statement::Vacuum(None),
+            },
+            Token::Keyword(Keyword::Backup) => {
+                self.keyword(Keyword::To)?;
+                let path = self.string()?;
+                let base = if self.eat(|token| {
+                    matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("INCREMENTAL"))
+                }) {
+                    self.keyword(Keyword::From)?;
+                    Some(self.string()?)
+                } else {
+                    None
+                };
+                Statement::Backup { path, base }
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("KILL") => {
+                self.word("QUERY")?;
+                Statement::KillQuery(self.number("a query id")?)
+            }
+            found => {
+                return Err(ParseError::Unexpected {
+                    expected: "a statement",
+                    found: describe(Some(&found)),
+                })
+            }
+        })
+    }
+
+    /// `<privileges> ON <tables>` of a grant or revoke.
+    fn privileges(&mut self) -> Result<(Vec<Privilege>, Option<u32>), ParseError> {
+        let is_all = |token: &Token| matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("ALL"));
+        let privileges = if self.eat(is_all) {
+            self.eat(|token| {
+                matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("PRIVILEGES"))
+            });
+            Privilege::ALL.to_vec()
+        } else {
+            let mut privileges = Vec::new();
+            loop {
+                let privilege = self.expect("a privilege", |token| {
+                    matches!(
+                        token,
+                        Token::Keyword(
+                            Keyword::Select | Keyword::Insert | Keyword::Update | Keyword::Delete
+                        )
+                    ) || matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("DDL"))
+                })?;
+                privileges.push(match privilege {
+                    Token::Keyword(Keyword::Select) => Privilege::Select,
+                    Token::Keyword(Keyword::Insert) => Privilege::Insert,
+                    Token::Keyword(Keyword::Update) => Privilege::Update,
+                    Token::Keyword(Keyword::Delete) => Privilege::Delete,
+                    _ => Privilege::Ddl,
+                });
+                if !self.eat(|token| *token == Token::Separator(Separator::Comma)) {
+                    break;
+                }
+            }
+            privileges
+        };
+        self.word("ON")?;
+        if self.eat(is_all) {
+            self.word("TABLES")?;
+            return Ok((privileges, None));
+        }
+        self.eat(|token| *token == Token::Keyword(Keyword::Table));
+        Ok((privileges, Some(self.table()?)))
+    }
+
+    /// The options of `CREATE TABLE`, in parentheses, if any are given.
+    fn table_options(&mut self) -> Result<TableOptions, ParseError> {
+        let mut options = TableOptions::default();
+        let with = self.eat(
+            |token| matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("WITH")),
+        );
+        let open =
+            |token: &Token| *token == Token::Separator(Separator::Operator(Operator::ParenOpen));
+        if !with && !self.peek().is_some_and(open) {
+            return Ok(options);
+        }
+        self.operator(Operator::ParenOpen)?;
+        // Given or not, for telling an option given twice
+        let mut bloom_filter = None;
+        loop {
+            let option = match self.expect("FILL_FACTOR, COMPRESSION, BLOOM_FILTER or TTL_COLUMN", |token| {
+                matches!(token, Token::Identifier(word)
+                    if ["FILL_FACTOR", "COMPRESSION", "BLOOM_FILTER", "TTL_COLUMN"].iter().any(|option| word.eq_ignore_ascii_case(option)))
+            })? {
+                Token::Identifier(word) => word.to_uppercase(),
+                _ => unreachable!("the token was checked to be an option"),
+            };
+            self.operator(Operator::Eq)?;
+            let given = if option == "FILL_FACTOR" {
+                let fill_factor = match self
+                    .expect("a fill factor", |token| matches!(token, Token::Number(_)))?
+                {
+                    Token::Number(number) => number
+                        .parse()
+                        .ok()
+                        .filter(|&percent| TableOptions::FILL_FACTORS.contains(&percent))
+                        .ok_or(ParseError::Unexpected {
+                            expected: "a fill factor from 10 to 100",
+                            found: number,
+                        })?,
+                    _ => unreachable!("the token was checked to be a number"),
+                };
+                options.fill_factor.replace(fill_factor).is_some()
+            } else if option == "BLOOM_FILTER" {
+                let given = self.expect("TRUE or FALSE", |token| {
+                    matches!(token, Token::Keyword(Keyword::True | Keyword::False))
+                })? == Token::Keyword(Keyword::True);
+                bloom_filter.replace(given).is_some()
+            } else if option == "TTL_COLUMN" {
+                let column = self.string()?;
+                if column.is_empty() {
+                    return Err(ParseError::Unexpected {
+                        expected: "a column name",
+                        found: "''".to_string(),
+                    });
+                }
+                options.ttl_column.replace(column).is_some()
+            } else {
+                let name = self.string()?;
+                let compression = match name.to_lowercase().as_str() {
+                    "lz" => Compression::Lz,
+                    "none" => Compression::None,
+                    _ => {
+                        return Err(ParseError::Unexpected {
+                            expected: "compression 'lz' or 'none'",
+                            found: format!("'{}'", name),
+                        })
+                    }
+                };
+                options.compression.replace(compression).is_some()
+            };
+            if given {
+                return Err(ParseError::Unexpected {
+                    expected: "each table option once",
+                    found: option,
+                });
+            }
+            if !self.eat(|token| *token == Token::Separator(Separator::Comma)) {
+                break;
+            }
+        }
+        self.operator(Operator::ParenClose)?;
+        options.bloom_filter = bloom_filter.unwrap_or_default();
+        Ok(options)
+    }
+
+    /// How `CREATE TABLE` partitions its table, if it does.
+    fn partitioning(&mut self) -> Result<Option<Partitioning>, ParseError> {
+        if !self.eat(
+            |token| matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("PARTITION")),
+        ) {
+            return Ok(None);
+        }
+        self.keyword(Keyword::By)?;
+        let range = match self.expect("RANGE or HASH", |token| {
+            matches!(token, Token::Identifier(word)
+                if word.eq_ignore_ascii_case("RANGE") || word.eq_ignore_ascii_case("HASH"))
+        })? {
+            Token::Identifier(word) => word.eq_ignore_ascii_case("RANGE"),
+            _ => unreachable!("the token was checked to be RANGE or HASH"),
+        };
+        self.operator(Operator::ParenOpen)?;
+        let key = self.name()?;
+        self.operator(Operator::ParenClose)?;
+        let scheme = if range {
+            self.word("SPLIT")?;
+            self.word("AT")?;
+            self.operator(Operator::ParenOpen)?;
+            let mut bounds = vec![self.string()?];
+            while self.eat(|token| *token == Token::Separator(Separator::Comma)) {
+                bounds.push(self.string()?);
+            }
+            self.operator(Operator::ParenClose)?;
+            PartitionScheme::Range(bounds)
+        } else {
+            self.word("PARTITIONS")?;
+            let count = match self.expect("a number of partitions", |token| {
+                matches!(token, Token::Number(_))
+            })? {
+                Token::Number(number) => number
+                    .parse()
+                    .ok()
+                    .filter(|count| (1..=MAX_PARTITIONS).contains(count))
+                    .ok_or(ParseError::Unexpected {
+                        expected: "from 1 to 1024 partitions",
+                        found: number,
+                    })?,
+                _ => unreachable!("the token was checked to be a number"),
+            };
+            PartitionScheme::Hash(count)
+        };
+        Ok(Some(Partitioning {
+            column: (key != "data").then_some(key),
+            scheme,
+        }))
+    }
+
+    /// The name, timing, event, table and statement of `CREATE TRIGGER`.
+    fn trigger(&mut self) -> Result<Trigger, ParseError> {
+        let name = self.name()?;
+        let timing = match self.expect("BEFORE or AFTER", |token| {
+            matches!(token, Token::Identifier(word)
+                if word.eq_ignore_ascii_case("BEFORE") || word.eq_ignore_ascii_case("AFTER"))
+        })? {
+            Token::Identifier(word) if word.eq_ignore_ascii_case("BEFORE") => Timing::Before,
+            _ => Timing::After,
+        };
+        let event = match self.expect("INSERT, UPDATE or DELETE", |token| {
+            matches!(
+                token,
+                Token::Keyword(Keyword::Insert | Keyword::Update | Keyword::Delete)
+            )
+        })? {
+            Token::Keyword(Keyword::Insert) => Event::Insert,
+            Token::Keyword(Keyword::Update) => Event::Update,
+            _ => Event::Delete,
+        };
+        self.word("ON")?;
+        let table = self.table()?;
+        if self.eat(
+            |token| matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("FOR")),
+        ) {
+            self.word("EACH")?;
+            self.word("ROW")?;
+        }
+        self.word("EXECUTE")?;
+        match self.peek() {
+            Some(Token::Keyword(Keyword::Insert | Keyword::Update | Keyword::Delete)) => {}
+            found => {
+                return Err(ParseError::Unexpected {
+                    expected: "INSERT, UPDATE or DELETE",
+                    found: describe(found),
+                })
+            }
+        }
+        self.trigger = Some(event);
+        let action = self.statement();
+        self.trigger = None;
+        Ok(Trigger {
+            name,
+            timing,
+            event,
+            table: TableId(table),
+            action: Box::new(action?),
+        })
+    }
+
+    /// The columns, location and options of `CREATE EXTERNAL TABLE`.
+    fn external_table(&mut self) -> Result<Statement, ParseError> {
+        self.keyword(Keyword::Table)?;
+        self.operator(Operator::ParenOpen)?;
+        // Types that are keywords, such as INT, are named as written
+        let kind = |token: &Token| match token {
+            Token::Identifier(word) => ColumnType::parse(word),
+            Token::Keyword(_) => ColumnType::parse(&describe(Some(token))),
+            _ => None,
+        };
+        let mut columns: Vec<Column> = Vec::new();
+        loop {
+            let name = self.name()?;
+            if columns.iter().any(|column| column.name == name) {
+                return Err(ParseError::Unexpected {
+                    expected: "a column name not yet used",
+                    found: name,
+                });
+            }
+            let token = self.expect("a column type", |token| kind(token).is_some())?;
+            columns.push(Column {
+                name,
+                kind: kind(&token).unwrap(),
+            });
+            if !self.eat(|token| *token == Token::Separator(Separator::Comma)) {
+                break;
+            }
+        }
+        self.operator(Operator::ParenClose)?;
+        self.word("LOCATION")?;
+        let location = self.string()?;
+        if self.eat(
+            |token| matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("FORMAT")),
+        ) {
+            self.word("CSV")?;
+        }
+        let options = self.copy_options(&location)?;
+        if options.format != CopyFormat::Csv {
+            return Err(ParseError::Unexpected {
+                expected: "CSV files",
+                found: format!("{:?}", options.format).to_uppercase(),
+            });
+        }
+        Ok(Statement::CreateExternalTable {
+            columns,
+            location,
+            options,
+        })
+    }
+
+    /// `* FROM <table> [WHERE id = <value>]`, after `SELECT`.
+    /// The rest of `SELECT <name>(data [, <value> ...] [ORDER BY <key>])
+    /// FROM <table> [WHERE id = <value>]`.
+    fn aggregate(&mut self) -> Result<Statement, ParseError> {
+        let function = self.name()?;
+        self.operator(Operator::ParenOpen)?;
+        let star =
+            |token: &Token| *token == Token::Separator(Separator::Operator(Operator::Multiply));
+        if function == "count" && self.eat(star) {
+            self.operator(Operator::ParenClose)?;
+            self.keyword(Keyword::From)?;
+            return Ok(Statement::Count(self.table()?));
+        }
+        self.word("DATA")?;
+        let mut args = Vec::new();
+        while self.eat(|token| *token == Token::Separator(Separator::Comma)) {
+            args.push(self.value()?);
+        }
+        let order = self.order_by()?;
+        self.operator(Operator::ParenClose)?;
+        self.keyword(Keyword::From)?;
+        let table = self.table()?;
+        let row = match self.peek() {
+            Some(Token::Keyword(Keyword::Where)) => Some(self.where_id()?),
+            _ => None,
+        };
+        Ok(Statement::Aggregate {
+            function,
+            args,
+            order,
+            table,
+            row,
+        })
+    }
+
+    /// `<value> [, ...]`, selected without a table.
+    fn values(&mut self) -> Result<Statement, ParseError> {
+        let mut values = vec![self.value()?];
+        while self.eat(|token| *token == Token::Separator(Separator::Comma)) {
+            values.push(self.value()?);
+        }
+        Ok(Statement::Values(values))
+    }
+
+    /// The hints of a comment `/*+ ... */`, if one comes next.
+    fn hints(&mut self) -> Vec<Hint> {
+        match self.peek() {
+            Some(Token::Hint(text)) => {
+                let hints = Hint::parse(text);
+                self.at += 1;
+                hints
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn select(&mut self, hints: &[Hint]) -> Result<Statement, ParseError> {
+        self.operator(Operator::Multiply)?;
+        self.keyword(Keyword::From)?;
+        if self.eat(
+            |token| matches!(token, Token::Identifier(name) if name.eq_ignore_ascii_case(ACTIVE_QUERIES)),
+        ) {
+            return Ok(Statement::ActiveQueries);
+        }
+        if self.eat(
+            |token| matches!(token, Token::Identifier(name) if name.eq_ignore_ascii_case(TABLE_STATS)),
+        ) {
+            return Ok(Statement::TableStats);
+        }
+        if self.eat(
+            |token| matches!(token, Token::Identifier(name) if name.eq_ignore_ascii_case(TRANSACTIONS)),
+        ) {
+            return Ok(Statement::Transactions);
+        }
+        if self.eat(
+            |token| matches!(token, Token::Identifier(name) if name.eq_ignore_ascii_case(BUFFER_STATS)),
+        ) {
+            return Ok(Statement::BufferStats);
+        }
+        let table = self.table()?;
+        if self.eat(|token| *token == Token::Keyword(Keyword::As)) {
+            self.keyword(Keyword::Of)?;
+            let as_of = match self.expect("TIMESTAMP or LSN", |token| {
+                matches!(token, Token::Keyword(Keyword::Timestamp | Keyword::Lsn))
+            })? {
+                Token::Keyword(Keyword::Timestamp) => AsOfPoint::Timestamp(self.string()?),
+                _ => AsOfPoint::Lsn(self.number("an LSN")?),
+            };
+            let order = self.order_by()?;
+            return Ok(Statement::SelectAsOf {
+                table,
+                as_of,
+                order,
+            });
+        }
+        let row = match self.peek() {
+            Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("JOIN") => {
+                return self.join(table);
+            }
+            Some(Token::Keyword(Keyword::Where)) => {
+                match (self.tokens.get(self.at + 1), self.tokens.get(self.at + 2)) {
+                    (Some(Token::Identifier(word)), _) if word.eq_ignore_ascii_case("MATCH") => {
+                        return self.search(table);
+                    }
+                    (Some(Token::Identifier(word)), _) if word.eq_ignore_ascii_case("EXISTS") => {
+                        return self.semi_join(table);
+                    }
+                    (Some(Token::Keyword(Keyword::Not)), _)
+                    | (_, Some(Token::Keyword(Keyword::In | Keyword::Not))) => {
+                        return self.semi_join(table);
+                    }
+                    (Some(Token::Identifier(word)), _) if !word.eq_ignore_ascii_case("ID") => {
+                        return self.find(table, hints);
+                    }
+                    _ => Some(self.where_id()?),
+                }
+            }
+            _ => None,
+        };
+        let order = self.order_by()?;
+        Ok(Statement::Select { table, row, order })
+    }
+
+    /// `WHERE MATCH(data) AGAINST (<value>)`, searching `table`.
+    fn search(&mut self, table: u32) -> Result<Statement, ParseError> {
+        self.keyword(Keyword::Where)?;
+        self.word("MATCH")?;
+        self.operator(Operator::ParenOpen)?;
+        self.word("DATA")?;
+        self.operator(Operator::ParenClose)?;
+        self.word("AGAINST")?;
+        self.operator(Operator::ParenOpen)?;
+        let terms = self.value()?;
+        self.operator(Operator::ParenClose)?;
+        Ok(Statement::Search { table, terms })
+    }
+
+    /// `WHERE { data | <name> } = <value>`, looking up rows of `table` as
+    /// `hints` say, where they say anything of it.
+    fn find(&mut self, table: u32, hints: &[Hint]) -> Result<Statement, ParseError> {
+        self.keyword(Keyword::Where)?;
+        let name = self.name()?;
+        self.operator(Operator::Eq)?;
+        let value = self.value()?;
+        let named = |hint: &Hint| match hint.args.as_slice() {
+            [] => true,
+            [named] => named.parse() == Ok(table),
+            _ => false,
+        };
+        // The last hint given wins
+        let access = hints
+            .iter()
+            .rev()
+            .filter(|hint| named(hint))
+            .filter_map(|hint| match hint.name.as_str() {
+                "FULL" | "NO_BLOOM_FILTER" => Some(Access::Scan),
+                "BLOOM_FILTER" => Some(Access::BloomFilter),
+                _ => None,
+            })
+            .next();
+        Ok(Statement::Find {
+            table,
+            column: (name != "data").then_some(name),
+            value,
+            access,
+            order: self.order_by()?,
+        })
+    }
+
+    /// `ORDER BY { id | data | <name> } [ASC | DESC]`, if it comes next.
+    fn order_by(&mut self) -> Result<Option<Order>, ParseError> {
+        if !self.eat(|token| *token == Token::Keyword(Keyword::Order)) {
+            return Ok(None);
+        }
+        self.keyword(Keyword::By)?;
+        let key = self.key()?;
+        let descending = self.eat(
+            |token| matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("DESC")),
+        );
+        if !descending {
+            self.eat(
+                |token| matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("ASC")),
+            );
+        }
+        Ok(Some(Order { key, descending }))
+    }
+
+    /// `JOIN <table> ON <key> = <key>`, the first key `left`'s and the
+    /// second the table's.
+    fn join(&mut self, left: u32) -> Result<Statement, ParseError> {
+        self.word("JOIN")?;
+        let right = self.table()?;
+        self.word("ON")?;
+        let left_key = self.key()?;
+        self.operator(Operator::Eq)?;
+        let right_key = self.key()?;
+        Ok(Statement::Join {
+            left,
+            right,
+            left_key,
+            right_key,
+        })
+    }
+
+    /// `WHERE <key> [NOT] IN (SELECT <key> FROM <table>)` or
+    /// `WHERE [NOT] EXISTS (SELECT * FROM <table> WHERE <key> = <key>)`,
+    /// keeping rows of `table`, whose key is the last.
+    fn semi_join(&mut self, table: u32) -> Result<Statement, ParseError> {
+        self.keyword(Keyword::Where)?;
+        let not = |token: &Token| *token == Token::Keyword(Keyword::Not);
+        let anti = self.eat(not);
+        if anti || self.peek().is_some_and(
+            |token| matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("EXISTS")),
+        ) {
+            self.word("EXISTS")?;
+            self.operator(Operator::ParenOpen)?;
+            self.keyword(Keyword::Select)?;
+            self.operator(Operator::Multiply)?;
+            self.keyword(Keyword::From)?;
+            let other = self.table()?;
+            self.keyword(Keyword::Where)?;
+            let other_key = self.key()?;
+            self.operator(Operator::Eq)?;
+            let key = self.key()?;
+            self.operator(Operator::ParenClose)?;
+            return Ok(Statement::SemiJoin {
+                table,
+                key,
+                other,
+                other_key,
+                anti,
+                exists: true,
+            });
+        }
+        let key = self.key()?;
+        let anti = self.eat(not);
+        self.keyword(Keyword::In)?;
+        self.operator(Operator::ParenOpen)?;
+        self.keyword(Keyword::Select)?;
+        let other_key = self.key()?;
+        self.keyword(Keyword::From)?;
+        let other = self.table()?;
+        self.operator(Operator::ParenClose)?;
+        Ok(Statement::SemiJoin {
+            table,
+            key,
+            other,
+            other_key,
+            anti,
+            exists: false,
+        })
+    }
+
+    /// `id`, `data` or a column's name.
+    fn key(&mut self) -> Result<Key, ParseError> {
+        let name = self.name()?;
+        Ok(match name.as_str() {
+            "id" => Key::Id,
+            "data" => Key::Data,
+            _ => Key::Column(name),
+        })
+    }
+
+    /// A `SELECT` of rows of a table, as `EXPLAIN` and `DECLARE` take.
+    fn table_query(&mut self) -> Result<Statement, ParseError> {
+        self.keyword(Keyword::Select)?;
+        let hints = self.hints();
+        let query = self.select(&hints)?;
+        let found = match &query {
+            Statement::Select { .. }
+            | Statement::Find { .. }
+            | Statement::Join { .. }
+            | Statement::SemiJoin { .. } => return Ok(query),
+            Statement::ActiveQueries => ACTIVE_QUERIES.to_string(),
+            Statement::TableStats => TABLE_STATS.to_string(),
+            Statement::Transactions => TRANSACTIONS.to_string(),
+            Statement::BufferStats => BUFFER_STATS.to_string(),
+            _ => "MATCH".to_string(),
+        };
+        Err(ParseError::Unexpected {
+            expected: "a query of a table",
+            found,
+        })
+    }
+
+    /// The `(FORMAT { TEXT | JSON })` after `EXPLAIN`, if it's there.
+    fn explain_format(&mut self) -> Result<ExplainFormat, ParseError> {
+        if !self.eat(|token| *token == Token::Separator(Separator::Operator(Operator::ParenOpen))) {
+            return Ok(ExplainFormat::Text);
+        }
+        self.expect(
+            "FORMAT",
+            |token| matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("FORMAT")),
+        )?;
+        let format = |token: &Token| match token {
+            Token::Identifier(word) if word.eq_ignore_ascii_case("TEXT") => {
+                Some(ExplainFormat::Text)
+            }
+            Token::Identifier(word) if word.eq_ignore_ascii_case("JSON") => {
+                Some(ExplainFormat::Json)
+            }
+            _ => None,
+        };
+        let token = self.expect("TEXT or JSON", |token| format(token).is_some())?;
+        self.operator(Operator::ParenClose)?;
+        Ok(format(&token).unwrap())
+    }
+
+    /// The options of a `COPY` of the file at `path`, in parentheses, if
+    /// any are given.
+    fn copy_options(&mut self, path: &str) -> Result<CopyOptions, ParseError> {
+        let extension = Path::new(path).extension().and_then(OsStr::to_str);
+        let extension = extension.unwrap_or_default();
+        let format = match extension.to_lowercase().as_str() {
+            "json" | "jsonl" | "ndjson" => CopyFormat::Json,
+            "parquet" => CopyFormat::Parquet,
+            _ => CopyFormat::Csv,
+        };
+        let mut options = CopyOptions {
+            format,
+            ..CopyOptions::default()
+        };
+        self.eat(
+            |token| matches!(token, Token::Identifier(word) if word.eq_ignore_ascii_case("WITH")),
+        );
+        if !self.eat(|token| *token == Token::Separator(Separator::Operator(Operator::ParenOpen))) {
+            return Ok(options);
+        }
+        let mut escape = None;
+        // The first option given only for CSV, which JSON can't take
+        let mut csv_option = None;
+        loop {
+            let option = match self.expect("a COPY option", |token| {
+                matches!(token, Token::Identifier(word)
+                    if COPY_OPTIONS.iter().any(|option| word.eq_ignore_ascii_case(option)))
+            })? {
+                Token::Identifier(word) => word.to_uppercase(),
+                _ => unreachable!("the token was checked to be an option"),
+            };
+            match option.as_str() {
+                "FORMAT" => {
+                    let format = |token: &Token| match token {
+                        Token::Identifier(word) => match word.to_uppercase().as_str() {
+                            "CSV" => Some(CopyFormat::Csv),
+                            "JSON" => Some(CopyFormat::Json),
+                            "PARQUET" => Some(CopyFormat::Parquet),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    let token =
+                        self.expect("CSV, JSON or PARQUET", |token| format(token).is_some())?;
+                    options.format = format(&token).unwrap();
+                }
+                "HEADER" => {
+                    options.header = !self.eat(|token| *token == Token::Keyword(Keyword::False));
+                    self.eat(|token| *token == Token::Keyword(Keyword::True));
+                }
+                "DELIMITER" => options.delimiter = self.copy_byte("a one-byte delimiter")?,
+                "QUOTE" => options.quote = self.copy_byte("a one-byte quote")?,
+                _ => escape = Some(self.copy_byte("a one-byte escape")?),
+            }
+            if option != "FORMAT" {
+                csv_option.get_or_insert(option);
+            }
+            if !self.eat(|token| *token == Token::Separator(Separator::Comma)) {
+                break;
+            }
+        }
+        self.operator(Operator::ParenClose)?;
+        options.escape = escape.unwrap_or(options.quote);
+
+        if let Some(option) = csv_option {
+            let expected = match options.format {
+                CopyFormat::Csv => None,
+                CopyFormat::Json => Some("no CSV options with FORMAT JSON"),
+                CopyFormat::Parquet => Some("no CSV options with FORMAT PARQUET"),
+            };
+            if let Some(expected) = expected {
+                return Err(ParseError::Unexpected {
+                    expected,
+                    found: option,
+                });
+            }
+        }
+        if options.delimiter == options.quote {
+            return Err(ParseError::Unexpected {
+                expected: "a delimiter other than the quote",
+                found: format!("{:?}", options.delimiter as char),
+            });
+        }
+        Ok(options)
+    }
+
+    /// A string of one byte other than a line break, as `COPY` options take.
+    fn copy_byte(&mut self, expected: &'static str) -> Result<u8, ParseError> {
+        let string = self.string()?;
+        match string.as_bytes() {
+            [byte] if !matches!(byte, b'\r' | b'\n') => Ok(*byte),
+            _ => Err(ParseError::Unexpected {
+                expected,
+                found: format!("{:?}", string),
+            }),
+        }
+    }
+
+    /// `WHERE id = <value>`
+    fn where_id(&mut self) -> Result<Value, ParseError> {
+        self.keyword(Keyword::Where)?;
+        self.word("ID")?;
+        self.operator(Operator::Eq)?;
+        self.value()
+    }
+
+    /// A function's name and the values it's called on.
+    fn call(&mut self) -> Result<Value, ParseError> {
+        let name = self.name()?;
+        self.operator(Operator::ParenOpen)?;
+        // `EXTRACT(<field> FROM <value>)`, as SQL writes `date_part`
+        if name == "extract" {
+            let field = Value::String(self.name()?);
+            self.keyword(Keyword::From)?;
+            let value = self.value()?;
+            self.operator(Operator::ParenClose)?;
+            return Ok(Value::Call {
+                name,
+                args: vec![field, value],
+            });
+        }
+        let close = Token::Separator(Separator::Operator(Operator::ParenClose));
+        let mut args = Vec::new();
+        if !self.eat(|token| *token == close) {
+            loop {
+                args.push(self.value()?);
+                if !self.eat(|token| *token == Token::Separator(Separator::Comma)) {
+                    break;
+                }
+            }
+            self.operator(Operator::ParenClose)?;
+        }
+        Ok(Value::Call { name, args })
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        let open = Token::Separator(Separator::Operator(Operator::ParenOpen));
+        if matches!(self.peek(), Some(Token::Identifier(_)))
+            && self.tokens.get(self.at + 1) == Some(&open)
+        {
+            return self.call();
+        }
+        // `INTERVAL '<text>'` and `TIMESTAMP '<text>'` call the function
+        // reading one
+        let literal = match (self.peek(), self.tokens.get(self.at + 1)) {
+            (Some(Token::Identifier(word)), Some(Token::String(_)))
+                if word.eq_ignore_ascii_case("INTERVAL") =>
+            {
+                Some("interval")
+            }
+            (Some(Token::Keyword(Keyword::Timestamp)), Some(Token::String(_))) => Some("timestamp"),
+            _ => None,
+        };
+        if let Some(name) = literal {
+            self.at += 1;
+            return Ok(Value::Call {
+                name: name.to_string(),
+                args: vec![Value::String(self.string()?)],
+            });
+        }
+        // A number is taken as its text, as a string would be
+        let minus = Token::Separator(Separator::Operator(Operator::Subtract));
+        if self.eat(|token| *token == minus) {
+            return match self.expect("a number", |token| matches!(token, Token::Number(_)))? {
+                Token::Number(number) => Ok(Value::String(format!("-{}", number))),
+                _ => unreachable!("the token was checked to be a number"),
+            };
+        }
+        let param = |ident: &str| {
+            ident
+                .strip_prefix('$')?
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+        };
+        let row = |ident: &str| match ident.to_uppercase().as_str() {
+            "NEW" => Some(Value::New),
+            "OLD" => Some(Value::Old),
+            _ => None,
+        };
+        let niladic = |ident: &str| NILADIC.iter().any(|name| ident.eq_ignore_ascii_case(name));
+        // A trigger's statement is run with its row, rather than bound
+        let trigger = self.trigger;
+        match self.expect("a value", |token| match token {
+            Token::String(_) | Token::Number(_) | Token::Keyword(Keyword::Null) => true,
+            Token::Identifier(ident) if niladic(ident) => true,
+            Token::Identifier(ident) if trigger.is_some() => row(ident).is_some(),
+            Token::Identifier(ident) => param(ident).is_some(),
+            _ => false,
+        })? {
+            Token::String(string) | Token::Number(string) => Ok(Value::String(string)),
+            Token::Keyword(Keyword::Null) => Ok(Value::Null),
+            Token::Identifier(ident) if niladic(&ident) => Ok(Value::Call {
+                name: ident.to_lowercase(),
+                args: Vec::new(),
+            }),
+            Token::Identifier(ident) => match (row(&ident), trigger) {
+                (Some(value), Some(event)) => {
+                    let has_row = match value {
+                        Value::New => event.has_new(),
+                        _ => event.has_old(),
+                    };
+                    if !has_row {
+                        return Err(ParseError::NoTransitionRow {
+                            row: if value == Value::New { "NEW" } else { "OLD" },
+                            event: event.name(),
+                        });
+                    }
+                    Ok(value)
+                }
+                _ => Ok(Value::Param(param(&ident).unwrap())),
+            },
+            _ => unreachable!("the token was checked to be a value"),
+        }
+    }
+
+    /// A variable or prepared statement name, folded to lower case.
+    fn name(&mut self) -> Result<String, ParseError> {
+        match self.expect("a name", |token| {
+            matches!(token, Token::Identifier(_) | Token::Keyword(_))
+        })? {
+            Token::Identifier(name) => Ok(name.to_lowercase()),
+            keyword => Ok(describe(Some(&keyword)).to_lowercase()),
+        }
+    }
+
+    fn table(&mut self) -> Result<u32, ParseError> {
+        match self.expect("a table number", |token| matches!(token, Token::Number(_)))? {
+            Token::Number(number) => number.parse().map_err(|_| ParseError::Unexpected {
+                expected: "a table number",
+                found: number,
+            }),
+            _ => unreachable!("the token was checked to be a number"),
+        }
+    }
+
+    /// A whole number, described as `expected` if it's missing.
+    fn number(&mut self, expected: &'static str) -> Result<u64, ParseError> {
+        match self.expect(expected, |token| matches!(token, Token::Number(_)))? {
+            Token::Number(number) => number.parse().map_err(|_| ParseError::Unexpected {
+                expected,
+                found: number,
+            }),
+            _ => unreachable!("the token was checked to be a number"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        match self.expect("a string", |token| matches!(token, Token::String(_)))? {
+            Token::String(string) => Ok(string),
+            _ => unreachable!("the token was checked to be a string"),
+        }
+    }
+
+    fn keyword(&mut self, keyword: Keyword) -> Result<(), ParseError> {
+        let expected = keyword_name(&keyword);
+        self.expect(expected, |token| *token == Token::Keyword(keyword))
+            .map(|_| ())
+    }
+
+    /// An identifier with special meaning in one place, matched without
+    /// regard to case.
+    fn word(&mut self, word: &'static str) -> Result<(), ParseError> {
+        self.expect(
+            word,
+            |token| matches!(token, Token::Identifier(ident) if ident.eq_ignore_ascii_case(word)),
+        )
+        .map(|_| ())
+    }
+
+    fn operator(&mut self, operator: Operator) -> Result<(), ParseError> {
+        let expected = match operator {
+            Operator::ParenOpen => "(",
+            Operator::ParenClose => ")",
+            Operator::Multiply => "*",
+            Operator::Eq => "=",
+            _ => "an operator",
+        };
+        self.expect(expected, |token| {
+            *token == Token::Separator(Separator::Operator(operator))
+        })
+        .map(|_| ())
+    }
+
+    fn expect(
+        &mut self,
+        expected: &'static str,
+        matches: impl FnOnce(&Token) -> bool,
+    ) -> Result<Token, ParseError> {
+        match self.tokens.get(self.at) {
+            Some(token) if matches(token) => {
+                self.at += 1;
+                Ok(std::mem::replace(
+                    &mut self.tokens[self.at - 1],
+                    Token::Invalid(String::new()),
+                ))
+            }
+            found => Err(ParseError::Unexpected {
+                expected,
+                found: describe(found),
+            }),
+        }
+    }
+
+    /// Consume the next token if it matches.
+    fn eat(&mut self, matches: impl FnOnce(&Token) -> bool) -> bool {
+        let found = self.tokens.get(self.at).is_some_and(matches);
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at)
+    }
+}
+
+fn keyword_name(keyword: &Keyword) -> &'static str {
+    match keyword {
+        Keyword::Table => "TABLE",
+        Keyword::Values => "VALUES",
+        Keyword::From => "FROM",
+        Keyword::Set => "SET",
+        Keyword::Where => "WHERE",
+        Keyword::As => "AS",
+        Keyword::By => "BY",
+        Keyword::In => "IN",
+        Keyword::Select => "SELECT",
+        Keyword::Of => "OF",
+        _ => "a keyword",
+    }
+}
+
+fn describe(token: Option<&Token>) -> String {
+    match token {
+        None => "the end".to_string(),
+        Some(Token::Keyword(keyword)) => format!("{:?}", keyword).to_uppercase(),
+        Some(Token::Identifier(ident)) => ident.clone(),
+        Some(Token::String(string)) => format!("{:?}", string),
+        Some(Token::Number(number)) => number.clone(),
+        Some(Token::Separator(separator)) => format!("{:?}", separator),
+        Some(Token::Hint(hints)) => format!("/*+ {} */", hints),
+        Some(Token::Invalid(text)) => text.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(value: &str) -> Value {
+        Value::String(value.to_string())
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("begin transaction; INSERT INTO 3 VALUES ('a'), (\"b\");;commit").unwrap(),
+            vec![
+                Statement::Begin,
+                Statement::Insert {
+                    table: 3,
+                    values: vec![string("a"), string("b")]
+                },
+                Statement::Commit,
+            ]
+        );
+        assert_eq!(
+            parse("SAVEPOINT a; ROLLBACK TO a; rollback to savepoint B; RELEASE SAVEPOINT a")
+                .unwrap(),
+            vec![
+                Statement::Savepoint("a".to_string()),
+                Statement::RollbackTo("a".to_string()),
+                Statement::RollbackTo("b".to_string()),
+                Statement::Release("a".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse("PREPARE TRANSACTION 'g'; COMMIT PREPARED 'g'; ROLLBACK PREPARED 'h'").unwrap(),
+            vec![
+                Statement::PrepareTransaction("g".to_string()),
+                Statement::CommitPrepared("g".to_string()),
+                Statement::RollbackPrepared("h".to_string()),
+            ]
+        );
+        assert!(matches!(
+            &parse("PREPARE transaction AS COMMIT").unwrap()[..],
+            [Statement::Prepare { name, .. }] if name == "transaction"
+        ));
+        assert_eq!(
+            parse("SELECT * FROM 3 WHERE id = '3:0:1'").unwrap(),
+            vec![Statement::Select {
+                table: 3,
+                row: Some(string("3:0:1")),
+                order: None,
+            }]
+        );
+        assert_eq!(
+            parse("UPDATE 3 SET data = 'x' WHERE id = '3:0:1'; DELETE FROM 3 WHERE id = '3:0:2'")
+                .unwrap(),
+            vec![
+                Statement::Update {
+                    table: 3,
+                    row: string("3:0:1"),
+                    value: string("x")
+                },
+                Statement::Delete {
+                    table: 3,
+                    row: string("3:0:2")
+                },
+            ]
+        );
+        assert_eq!(parse(" ; ").unwrap(), vec![]);
+        assert_eq!(
+            parse("CREATE TABLE; create table with (Fill_Factor = 70, compression = 'LZ', bloom_filter = true)")
+                .unwrap(),
+            vec![
+                Statement::CreateTable(TableOptions::default()),
+                Statement::CreateTable(TableOptions {
+                    fill_factor: Some(70),
+                    compression: Some(Compression::Lz),
+                    bloom_filter: true,
+                    ttl_column: None,
+                }),
+            ]
+        );
+        assert!(parse("CREATE TABLE (BLOOM_FILTER = FALSE, BLOOM_FILTER = TRUE)").is_err());
+        assert_eq!(
+            parse("CREATE TABLE WITH (TTL_COLUMN = 'expires_at')").unwrap(),
+            vec![Statement::CreateTable(TableOptions {
+                ttl_column: Some("expires_at".to_string()),
+                ..TableOptions::default()
+            })]
+        );
+        assert!(parse("CREATE TABLE WITH (TTL_COLUMN = '')").is_err());
+        assert_eq!(
+            parse(
+                "CREATE TABLE (FILL_FACTOR = 90) PARTITION BY HASH (data) PARTITIONS 4;
+                 CREATE TABLE partition by range (Day) split at ('2024-01-01', '2025-01-01')"
+            )
+            .unwrap(),
+            vec![
+                Statement::CreatePartitionedTable {
+                    options: TableOptions {
+                        fill_factor: Some(90),
+                        ..TableOptions::default()
+                    },
+                    partitioning: Partitioning {
+                        column: None,
+                        scheme: PartitionScheme::Hash(4),
+                    },
+                },
+                Statement::CreatePartitionedTable {
+                    options: TableOptions::default(),
+                    partitioning: Partitioning {
+                        column: Some("day".to_string()),
+                        scheme: PartitionScheme::Range(vec![
+                            "2024-01-01".to_string(),
+                            "2025-01-01".to_string()
+                        ]),
+                    },
+                },
+            ]
+        );
+        assert_eq!(
+            parse("CREATE TABLE PARTITION BY HASH (data) PARTITIONS 0"),
+            Err(ParseError::Unexpected {
+                expected: "from 1 to 1024 partitions",
+                found: "0".to_string()
+            })
+        );
+        assert!(parse("CREATE TABLE PARTITION BY RANGE (data) SPLIT AT ()").is_err());
+        assert_eq!(
+            parse("CREATE TABLE WITH (compression = 'lz4')"),
+            Err(ParseError::Unexpected {
+                expected: "compression 'lz' or 'none'",
+                found: "'lz4'".to_string()
+            })
+        );
+        assert!(parse("CREATE TABLE (fill_factor = 5)").is_err());
+        assert!(parse("CREATE TABLE (fill_factor = 50, fill_factor = 60)").is_err());
+        assert!(parse("CREATE TABLE WITH").is_err());
+
+        assert_eq!(
+            parse("CREATE TRIGGER history AFTER UPDATE ON 1 FOR EACH ROW EXECUTE INSERT INTO 2 VALUES (OLD), ('x'); DROP TRIGGER history").unwrap(),
+            vec![
+                Statement::CreateTrigger(Trigger {
+                    name: "history".to_string(),
+                    timing: Timing::After,
+                    event: Event::Update,
+                    table: TableId(1),
+                    action: Box::new(Statement::Insert {
+                        table: 2,
+                        values: vec![Value::Old, string("x")],
+                    }),
+                }),
+                Statement::DropTrigger("history".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse("CREATE TRIGGER t BEFORE INSERT ON 1 EXECUTE DELETE FROM 2 WHERE id = OLD"),
+            Err(ParseError::NoTransitionRow {
+                row: "OLD",
+                event: "INSERT"
+            })
+        );
+        // NEW and OLD are the trigger's alone
+        assert!(parse("CREATE TRIGGER t BEFORE DELETE ON 1 EXECUTE SELECT * FROM 2").is_err());
+        assert!(parse("INSERT INTO 1 VALUES (NEW)").is_err());
+
+        assert_eq!(
+            parse(
+                "INSERT INTO 1 VALUES (Concat('a', upper($1), now())); SELECT total(data) FROM 2"
+            )
+            .unwrap(),
+            vec![
+                Statement::Insert {
+                    table: 1,
+                    values: vec![Value::Call {
+                        name: "concat".to_string(),
+                        args: vec![
+                            string("a"),
+                            Value::Call {
+                                name: "upper".to_string(),
+                                args: vec![Value::Param(1)],
+                            },
+                            Value::Call {
+                                name: "now".to_string(),
+                                args: vec![],
+                            },
+                        ],
+                    }],
+                },
+                Statement::Aggregate {
+                    function: "total".to_string(),
+                    args: vec![],
+                    order: None,
+                    table: 2,
+                    row: None,
+                },
+            ]
+        );
+        assert!(parse("SELECT total(id) FROM 2").is_err());
+        assert_eq!(
+            parse("SELECT string_agg(data, ', ' ORDER BY n DESC) FROM 2").unwrap(),
+            vec![Statement::Aggregate {
+                function: "string_agg".to_string(),
+                args: vec![Value::String(", ".to_string())],
+                order: Some(Order {
+                    key: Key::Column("n".to_string()),
+                    descending: true,
+                }),
+                table: 2,
+                row: None,
+            }]
+        );
+        assert_eq!(
+            parse("SELECT Count(*) FROM 2").unwrap(),
+            vec![Statement::Count(2)]
+        );
+        assert!(parse("SELECT total(*) FROM 2").is_err());
+        let call = |name: &str| Value::Call {
+            name: name.to_string(),
+            args: vec![],
+        };
+        assert_eq!(
+            parse("SELECT Current_User, now(), 'a'").unwrap(),
+            vec![Statement::Values(vec![
+                call("current_user"),
+                call("now"),
+                string("a")
+            ])]
+        );
+        assert_eq!(
+            parse("UPDATE 1 SET data = CURRENT_TIMESTAMP WHERE id = $1").unwrap(),
+            vec![Statement::Update {
+                table: 1,
+                row: Value::Param(1),
+                value: call("current_timestamp"),
+            }]
+        );
+        assert!(parse("SELECT current_user FROM 2").is_err());
+        assert_eq!(
+            parse("SELECT 1, -2.5, NULL").unwrap(),
+            vec![Statement::Values(vec![
+                string("1"),
+                string("-2.5"),
+                Value::Null
+            ])]
+        );
+        assert!(parse("SELECT -'a'").is_err());
+        // Typed literals and EXTRACT are calls
+        let called = |name: &str, args| Value::Call {
+            name: name.to_string(),
+            args,
+        };
+        assert_eq!(
+            parse("SELECT EXTRACT(Year FROM TIMESTAMP '2024-03-15'), INTERVAL '1 day'").unwrap(),
+            vec![Statement::Values(vec![
+                called(
+                    "extract",
+                    vec![
+                        string("year"),
+                        called("timestamp", vec![string("2024-03-15")])
+                    ]
+                ),
+                called("interval", vec![string("1 day")]),
+            ])]
+        );
+        assert!(parse("SELECT extract('year', now())").is_err());
+
+        assert_eq!(
+            parse("CREATE FULLTEXT INDEX posts ON 3 (data); SELECT * FROM 3 WHERE match(data) against ($1); DROP INDEX posts")
+                .unwrap(),
+            vec![
+                Statement::CreateFullTextIndex {
+                    name: "posts".to_string(),
+                    table: 3,
+                },
+                Statement::Search {
+                    table: 3,
+                    terms: Value::Param(1),
+                },
+                Statement::DropIndex("posts".to_string()),
+            ]
+        );
+        assert!(parse("COPY (SELECT * FROM 3 WHERE MATCH(data) AGAINST ('a')) TO 'out'").is_err());
+        assert_eq!(
+            parse("CREATE UNIQUE INDEX emails ON 3 (Email); create primary key pk on 3 (data)")
+                .unwrap(),
+            vec![
+                Statement::CreateUniqueIndex {
+                    name: "emails".to_string(),
+                    table: 3,
+                    column: Some("email".to_string()),
+                    primary: false,
+                },
+                Statement::CreateUniqueIndex {
+                    name: "pk".to_string(),
+                    table: 3,
+                    column: None,
+                    primary: true,
+                },
+            ]
+        );
+        assert!(parse("CREATE PRIMARY INDEX pk ON 3 (id)").is_err());
+        assert!(parse("PREPARE p AS CREATE UNIQUE INDEX u ON 3 (id)").is_err());
+
+        let script =
+            parse_script("BEGIN; SELEKT 1;; INSERT INTO 1 VALUES (';') x; COMMIT").unwrap();
+        assert_eq!(script.len(), 4);
+        assert_eq!(script[0], Ok(Statement::Begin));
+        assert!(script[1].is_err());
+        assert!(script[2].is_err());
+        assert_eq!(script[3], Ok(Statement::Commit));
+        assert!(parse_script("SELECT * FROM 1; SELECT 'x").is_err());
+        let at = |line, column| Position { line, column };
+        let error = parse_spanned("SELECT *\n  FROM 1 WHERE id == 2").unwrap_err();
+        let span = error.span.unwrap();
+        assert_eq!((span.start, span.end), (at(2, 20), at(2, 21)));
+        let error = parse_spanned("SELECT * FROM").unwrap_err();
+        assert_eq!(error.span.unwrap().start, at(1, 14));
+        assert_eq!(
+            script[1].as_ref().unwrap_err().span.unwrap().start,
+            at(1, 8)
+        );
+        assert_eq!(
+            parse("ANALYZE 3; analyze").unwrap(),
+            vec![Statement::Analyze(Some(3)), Statement::Analyze(None)]
+        );
+        assert_eq!(parse("checkpoint").unwrap(), vec![Statement::Checkpoint]);
+        assert_eq!(
+            parse("VACUUM 3; vacuum").unwrap(),
+            vec![Statement::Vacuum(Some(3)), Statement::Vacuum(None)]
+        );
+        assert_eq!(
+            parse("BACKUP TO '/backups/monday'").unwrap(),
+            vec![Statement::Backup {
+                path: "/backups/monday".into(),
+                base: None
+            }]
+        );
+        assert_eq!(
+            parse("BACKUP TO 'tuesday' incremental from 'monday'").unwrap(),
+            vec![Statement::Backup {
+                path: "tuesday".into(),
+                base: Some("monday".into())
+            }]
+        );
+        assert!(parse("BACKUP TO 'tuesday' INCREMENTAL 'monday'").is_err());
+        assert!(parse("BACKUP '/backups/monday'").is_err());
+        assert!(parse("ANALYZE x").is_err());
+        assert_eq!(
+            parse("SELECT * FROM 3 WHERE Data = 'x'; SELECT * FROM 3 WHERE Day = 'y'").unwrap(),
+            vec![
+                Statement::Find {
+                    table: 3,
+                    column: None,
+                    value: string("x"),
+                    access: None,
+                    order: None,
+                },
+                Statement::Find {
+                    table: 3,
+                    column: Some("day".to_string()),
+                    value: string("y"),
+                    access: None,
+                    order: None,
+                },
+            ]
+        );
+        let find = |access| Statement::Find {
+            table: 3,
+            column: None,
+            value: string("x"),
+            access,
+            order: None,
+        };
+        assert_eq!(
+            parse(
+                "SELECT /*+ full */ * FROM 3 WHERE data = 'x';
+                 SELECT /*+ FULL(4) INDEX(3 posts) BLOOM_FILTER(3) */ * FROM 3 WHERE data = 'x';
+                 SELECT /*+ NO_BLOOM_FILTER BLOOM_FILTER( */ * FROM 3 WHERE data = 'x';
+                 SELECT * /*+ FULL */ FROM 3 WHERE data = 'x'"
+            )
+            .unwrap(),
+            vec![
+                find(Some(Access::Scan)),
+                find(Some(Access::BloomFilter)),
+                find(Some(Access::Scan)),
+                find(None),
+            ]
+        );
+        let order = |key, descending| Some(Order { key, descending });
+        assert_eq!(
+            parse(
+                "SELECT * FROM 3 ORDER BY id DESC;
+                 EXPLAIN SELECT * FROM 3 WHERE day = 'y' ORDER BY Name ASC"
+            )
+            .unwrap(),
+            vec![
+                Statement::Select {
+                    table: 3,
+                    row: None,
+                    order: order(Key::Id, true),
+                },
+                Statement::Explain {
+                    query: Box::new(Statement::Find {
+                        table: 3,
+                        column: Some("day".to_string()),
+                        value: string("y"),
+                        access: None,
+                        order: order(Key::Column("name".to_string()), false),
+                    }),
+                    format: ExplainFormat::Text,
+                },
+            ]
+        );
+        assert!(parse("SELECT * FROM 3 ORDER id").is_err());
+        assert!(parse("SELECT * FROM 3 WHERE MATCH(data) AGAINST ('a') ORDER BY id").is_err());
+        assert!(parse("EXPLAIN SELECT * FROM information_schema.active_queries").is_err());
+        assert_eq!(
+            parse("EXPLAIN (format json) SELECT * FROM 3").unwrap(),
+            vec![Statement::Explain {
+                query: Box::new(Statement::Select {
+                    table: 3,
+                    row: None,
+                    order: None,
+                }),
+                format: ExplainFormat::Json,
+            }]
+        );
+        assert!(parse("EXPLAIN (FORMAT XML) SELECT * FROM 3").is_err());
+        assert_eq!(
+            parse(
+                "DECLARE c CURSOR FOR SELECT * FROM 3 ORDER BY id; FETCH 10 FROM C; FETCH c;
+                 FETCH ALL IN c; FETCH NEXT c; CLOSE c; CLOSE ALL"
+            )
+            .unwrap(),
+            vec![
+                Statement::Declare {
+                    name: "c".to_string(),
+                    query: Box::new(Statement::Select {
+                        table: 3,
+                        row: None,
+                        order: order(Key::Id, false),
+                    }),
+                },
+                Statement::Fetch {
+                    name: "c".to_string(),
+                    count: Some(10),
+                },
+                Statement::Fetch {
+                    name: "c".to_string(),
+                    count: Some(1),
+                },
+                Statement::Fetch {
+                    name: "c".to_string(),
+                    count: None,
+                },
+                Statement::Fetch {
+                    name: "c".to_string(),
+                    count: Some(1),
+                },
+                Statement::Close(Some("c".to_string())),
+                Statement::Close(None),
+            ]
+        );
+        assert!(parse("DECLARE c CURSOR FOR SELECT COUNT(*) FROM 3").is_err());
+        assert!(parse("DECLARE c FOR SELECT * FROM 3").is_err());
+        assert!(parse("FETCH -1 FROM c").is_err());
+        assert!(parse("EXPLAIN (FORMAT JSON SELECT * FROM 3").is_err());
+        assert!(parse("COPY (SELECT * FROM 3 ORDER BY data) TO 'out'").is_err());
+        assert_eq!(
+            parse("COPY (SELECT * FROM 3 WHERE day = 'y') TO 'out'"),
+            Err(ParseError::Unexpected {
+                expected: "ID",
+                found: "DAY".to_string()
+            })
+        );
+        assert!(parse("INSERT INTO 1 VALUES (upper('a',))").is_err());
+
+        assert_eq!(
+            parse("SELECT * FROM users"),
+            Err(ParseError::Unexpected {
+                expected: "a table number",
+                found: "users".to_string()
+            })
+        );
+        assert_eq!(
+            parse("COMMIT ROLLBACK"),
+            Err(ParseError::Unexpected {
+                expected: "end of statement",
+                found: "ROLLBACK".to_string()
+            })
+        );
+        assert_eq!(
+            parse("INSERT INTO 1 VALUES ('a"),
+            Err(ParseError::UnterminatedString)
+        );
+    }
+
+    #[test]
+    fn test_parse_join() {
+        assert_eq!(
+            parse("SELECT * FROM 3 JOIN 4 ON author = ID").unwrap(),
+            vec![Statement::Join {
+                left: 3,
+                right: 4,
+                left_key: Key::Column("author".to_string()),
+                right_key: Key::Id,
+            }]
+        );
+        assert!(parse("SELECT * FROM 3 JOIN 4 ON author").is_err());
+        assert!(parse("SELECT * FROM 3 JOIN 4 USING (author)").is_err());
+    }
+
+    #[test]
+    fn test_parse_semi_join() {
+        assert_eq!(
+            parse(
+                "SELECT * FROM 3 WHERE author NOT IN (SELECT id FROM 4);
+                 SELECT * FROM 4 WHERE EXISTS (SELECT * FROM 3 WHERE author = id)"
+            )
+            .unwrap(),
+            vec![
+                Statement::SemiJoin {
+                    table: 3,
+                    key: Key::Column("author".to_string()),
+                    other: 4,
+                    other_key: Key::Id,
+                    anti: true,
+                    exists: false,
+                },
+                Statement::SemiJoin {
+                    table: 4,
+                    key: Key::Id,
+                    other: 3,
+                    other_key: Key::Column("author".to_string()),
+                    anti: false,
+                    exists: true,
+                },
+            ]
+        );
+        assert!(parse("SELECT * FROM 3 WHERE NOT author IN (SELECT id FROM 4)").is_err());
+    }
+
+    #[test]
+    fn test_session_statements() {
+        assert_eq!(
+            parse("SET search_path TO public; set DateStyle = 'ISO'; SHOW datestyle").unwrap(),
+            vec![
+                Statement::Set {
+                    name: "search_path".to_string(),
+                    value: "public".to_string()
+                },
+                Statement::Set {
+                    name: "datestyle".to_string(),
+                    value: "ISO".to_string()
+                },
+                Statement::Show("datestyle".to_string()),
+            ]
+        );
+        assert_eq!(parse("SHOW ALL").unwrap(), vec![Statement::ShowAll]);
+
+        let statements = parse("PREPARE put AS INSERT INTO 1 VALUES ($1), ($2)").unwrap();
+        let Statement::Prepare { name, statement } = &statements[0] else {
+            panic!("expected PREPARE");
+        };
+        assert_eq!(name, "put");
+        assert_eq!(
+            statement.bind(&["a".to_string(), "b".to_string()]),
+            Ok(Statement::Insert {
+                table: 1,
+                values: vec![string("a"), string("b")]
+            })
+        );
+        assert_eq!(
+            statement.bind(&["a".to_string()]),
+            Err(ParseError::MissingParameter(2))
+        );
+        assert_eq!(
+            parse("EXECUTE put ('a', 'b'); DEALLOCATE put").unwrap(),
+            vec![
+                Statement::Execute {
+                    name: "put".to_string(),
+                    params: vec!["a".to_string(), "b".to_string()]
+                },
+                Statement::Deallocate("put".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse("PREPARE a AS EXECUTE b"),
+            Err(ParseError::NotPreparable)
+        );
+
+        assert_eq!(
+            parse("SELECT * FROM information_schema.active_queries; KILL QUERY 12").unwrap(),
+            vec![Statement::ActiveQueries, Statement::KillQuery(12)]
+        );
+        assert_eq!(
+            parse("select * from INFORMATION_SCHEMA.TABLE_STATS").unwrap(),
+            vec![Statement::TableStats]
+        );
+        assert_eq!(
+            parse("SELECT * FROM information_schema.transactions").unwrap(),
+            vec![Statement::Transactions]
+        );
+        assert_eq!(
+            parse("SELECT * FROM information_schema.buffer_stats").unwrap(),
+            vec![Statement::BufferStats]
+        );
+        assert_eq!(
+            parse("SELECT * FROM 3 AS OF TIMESTAMP '2024-03-15T00:00:00Z'; select * from 3 as of lsn 42")
+                .unwrap(),
+            vec![
+                Statement::SelectAsOf {
+                    table: 3,
+                    as_of: AsOfPoint::Timestamp("2024-03-15T00:00:00Z".to_string()),
+                    order: None,
+                },
+                Statement::SelectAsOf {
+                    table: 3,
+                    as_of: AsOfPoint::Lsn(42),
+                    order: None,
+                },
+            ]
+        );
+        assert!(parse("SELECT * FROM 3 AS OF LSN 'x'").is_err());
+        assert!(
+            parse("COPY (SELECT * FROM information_schema.active_queries) TO 'q.csv'").is_err()
+        );
+
+        assert_eq!(
+            parse("CREATE USER Alice WITH PASSWORD 'secret'; drop user alice").unwrap(),
+            vec![
+                Statement::CreateUser {
+                    name: "alice".to_string(),
+                    password: "secret".to_string(),
+                    superuser: false
+                },
+                Statement::DropUser("alice".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse("GRANT select, DDL ON TABLE 3 TO alice; REVOKE ALL PRIVILEGES ON ALL TABLES FROM bob")
+                .unwrap(),
+            vec![
+                Statement::Grant {
+                    privileges: vec![Privilege::Select, Privilege::Ddl],
+                    table: Some(3),
+                    user: "alice".to_string()
+                },
+                Statement::Revoke {
+                    privileges: Privilege::ALL.to_vec(),
+                    table: None,
+                    user: "bob".to_string()
+                },
+            ]
+        );
+        assert_eq!(
+            parse("CREATE USER bob PASSWORD secret"),
+            Err(ParseError::Unexpected {
+                expected: "a string",
+                found: "secret".to_string()
+            })
+        );
+
+        assert_eq!(
+            parse("COPY 2 FROM 'in.csv'; copy 2 from 'in.tsv' WITH (HEADER, DELIMITER '\t')")
+                .unwrap(),
+            vec![
+                Statement::CopyFrom {
+                    table: 2,
+                    path: "in.csv".to_string(),
+                    options: CopyOptions::default()
+                },
+                Statement::CopyFrom {
+                    table: 2,
+                    path: "in.tsv".to_string(),
+                    options: CopyOptions {
+                        header: true,
+                        delimiter: b'\t',
+                        ..CopyOptions::default()
+                    }
+                },
+            ]
+        );
+        assert!(matches!(
+            parse("COPY 2 FROM 'in.csv' (HEADER false)").unwrap()[0],
+            Statement::CopyFrom {
+                options: CopyOptions { header: false, .. },
+                ..
+            }
+        ));
+        assert_eq!(
+            parse("COPY 2 FROM 'in.csv' (DELIMITER ';;')"),
+            Err(ParseError::Unexpected {
+                expected: "a one-byte delimiter",
+                found: "\";;\"".to_string()
+            })
+        );
+        assert_eq!(
+            parse("COPY (SELECT * FROM 2 WHERE id = '2:0:0') TO 'out.jsonl'; COPY 2 TO 'out.csv' (QUOTE '|')")
+                .unwrap(),
+            vec![
+                Statement::CopyTo {
+                    query: Box::new(Statement::Select {
+                        table: 2,
+                        row: Some(Value::String("2:0:0".to_string())),
+                        order: None,
+                    }),
+                    path: "out.jsonl".to_string(),
+                    options: CopyOptions {
+                        format: CopyFormat::Json,
+                        ..CopyOptions::default()
+                    }
+                },
+                Statement::CopyTo {
+                    query: Box::new(Statement::Select {
+                        table: 2,
+                        row: None,
+                        order: None,
+                    }),
+                    path: "out.csv".to_string(),
+                    options: CopyOptions {
+                        quote: b'|',
+                        escape: b'|',
+                        ..CopyOptions::default()
+                    }
+                },
+            ]
+        );
+        assert!(matches!(
+            parse("COPY 2 TO 'out.json' (FORMAT csv, ESCAPE '\\')").unwrap()[0],
+            Statement::CopyTo {
+                options: CopyOptions {
+                    format: CopyFormat::Csv,
+                    quote: b'"',
+                    escape: b'\\',
+                    ..
+                },
+                ..
+            }
+        ));
+        assert_eq!(
+            parse("COPY 2 TO 'out.jsonl' (HEADER)"),
+            Err(ParseError::Unexpected {
+                expected: "no CSV options with FORMAT JSON",
+                found: "HEADER".to_string()
+            })
+        );
+        assert!(parse("COPY 2 FROM 'in.jsonl'").is_err());
+        assert!(matches!(
+            parse("COPY 2 FROM 'in.PARQUET'; COPY 2 TO 'out' (FORMAT parquet)").unwrap()[..],
+            [
+                Statement::CopyFrom {
+                    options: CopyOptions {
+                        format: CopyFormat::Parquet,
+                        ..
+                    },
+                    ..
+                },
+                Statement::CopyTo {
+                    options: CopyOptions {
+                        format: CopyFormat::Parquet,
+                        ..
+                    },
+                    ..
+                }
+            ]
+        ));
+        assert!(parse("COPY 2 FROM 'in.parquet' (DELIMITER ';')").is_err());
+        assert!(parse("COPY (DELETE FROM 2 WHERE id = '2:0:0') TO 'out.csv'").is_err());
+
+        assert_eq!(
+            parse("CREATE EXTERNAL TABLE (Name text, age INT) LOCATION 'people/' FORMAT csv WITH (HEADER)")
+                .unwrap(),
+            vec![Statement::CreateExternalTable {
+                columns: vec![
+                    Column {
+                        name: "name".to_string(),
+                        kind: ColumnType::Text
+                    },
+                    Column {
+                        name: "age".to_string(),
+                        kind: ColumnType::Integer
+                    },
+                ],
+                location: "people/".to_string(),
+                options: CopyOptions {
+                    header: true,
+                    ..CopyOptions::default()
+                }
+            }]
+        );
+        assert_eq!(
+            parse("CREATE EXTERNAL TABLE (a date) LOCATION 'x'"),
+            Err(ParseError::Unexpected {
+                expected: "a column type",
+                found: "date".to_string()
+            })
+        );
+        assert!(parse("CREATE EXTERNAL TABLE (a text, A real) LOCATION 'x'").is_err());
+        assert!(parse("CREATE EXTERNAL TABLE (a text) LOCATION 'x.parquet'").is_err());
+    }
+    #[test]
+    fn test_parse_set_global() {
+        assert_eq!(
+            parse("SET GLOBAL cache_size = 100; SET global TO 1").unwrap(),
+            vec![
+                Statement::SetGlobal {
+                    name: "cache_size".to_string(),
+                    value: "100".to_string()
+                },
+                Statement::Set {
+                    name: "global".to_string(),
+                    value: "1".to_string()
+                },
+            ]
+        );
+        assert!(parse("SET GLOBAL cache_size 100").is_err());
+    }
+
+    #[test]
+    fn test_redact_passwords() {
+        assert_eq!(
+            redact_passwords("CREATE USER bob WITH PASSWORD 'hunter2' SUPERUSER"),
+            "CREATE USER bob WITH PASSWORD '***' SUPERUSER"
+        );
+        assert_eq!(
+            redact_passwords(
+                "SELECT 'é';\ncreate user ann password\n\t\"pässwörd\"; SELECT 'password'"
+            ),
+            "SELECT 'é';\ncreate user ann password\n\t'***'; SELECT 'password'"
+        );
+        assert!(matches!(
+            redact_passwords("SELECT * FROM 1"),
+            Cow::Borrowed("SELECT * FROM 1")
+        ));
+    }
+}
@@ -1,3 +1,6 @@
+// State transitions consume the tokenizer, so `to_*_state` intentionally takes `self`.
+#![allow(clippy::wrong_self_convention)]
+
 use super::tokens::{Operator, Separator, Token, Whitespace};
 use std::collections::VecDeque;
 
@@ -7,18 +10,12 @@ use std::str::Chars;
 // ///////////////// //
 // Character Parsing //
 // ///////////////// //
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
 pub(crate) struct CharacterLocation {
     pub(crate) row: usize,
     pub(crate) col: usize,
 }
 
-impl Default for CharacterLocation {
-    fn default() -> Self {
-        return Self { row: 0, col: 0 };
-    }
-}
-
 impl Display for CharacterLocation {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write_str("CharacterLocation({self.row}:{self.col})")
@@ -97,10 +94,17 @@ pub(crate) struct TokenItem {
 #[derive(Debug)]
 struct BaseState;
 #[derive(Debug)]
-struct StringState;
+struct StringState {
+    // The quote that opened the string, and so closes it
+    quote: char,
+    // Set on the first of two quotes in a row, which stand for one
+    escaped: bool,
+}
 #[derive(Debug)]
 struct CommentState;
 #[derive(Debug)]
+struct BlockCommentState;
+#[derive(Debug)]
 struct OperatorState;
 #[derive(Debug)]
 struct NumberState {
@@ -112,6 +116,7 @@ struct InvalidState;
 #[derive(Debug)]
 pub(crate) enum TokenizerError {
     UnterminatedString(CharacterLocation),
+    UnterminatedComment(CharacterLocation),
     InvalidNumber(CharacterLocation),
 }
 
@@ -144,9 +149,9 @@ impl<S> Tokenizer<S> {
     ) where
         F: FnOnce(String, CharacterLocation, CharacterLocation) -> Option<TokenItem>,
     {
-        tokenize_fn(string, start, end).map(|token_item| {
+        if let Some(token_item) = tokenize_fn(string, start, end) {
             self.tokens.push_back(token_item);
-        });
+        }
     }
 }
 
@@ -155,6 +160,7 @@ enum TokenizerStateMachine {
     Base(Tokenizer<BaseState>),
     String(Tokenizer<StringState>),
     Comment(Tokenizer<CommentState>),
+    BlockComment(Tokenizer<BlockCommentState>),
     Operator(Tokenizer<OperatorState>),
     Number(Tokenizer<NumberState>),
     Invalid(Tokenizer<InvalidState>),
@@ -180,6 +186,9 @@ impl TokenizerStateMachine {
             TokenizerStateMachine::Base(state) => state.process_character(character_item)?,
             TokenizerStateMachine::String(state) => state.process_character(character_item)?,
             TokenizerStateMachine::Comment(state) => state.process_character(character_item)?,
+            TokenizerStateMachine::BlockComment(state) => {
+                state.process_character(character_item)?
+            }
             TokenizerStateMachine::Operator(state) => state.process_character(character_item)?,
             TokenizerStateMachine::Number(state) => state.process_character(character_item)?,
             TokenizerStateMachine::Invalid(_) => {
@@ -194,6 +203,7 @@ impl TokenizerStateMachine {
             TokenizerStateMachine::Base(state) => std::mem::take(&mut state.tokens),
             TokenizerStateMachine::String(state) => std::mem::take(&mut state.tokens),
             TokenizerStateMachine::Comment(state) => std::mem::take(&mut state.tokens),
+            TokenizerStateMachine::BlockComment(state) => std::mem::take(&mut state.tokens),
             TokenizerStateMachine::Operator(state) => std::mem::take(&mut state.tokens),
             TokenizerStateMachine::Number(state) => std::mem::take(&mut state.tokens),
             TokenizerStateMachine::Invalid(state) => std::mem::take(&mut state.tokens),
@@ -227,7 +237,10 @@ impl Tokenizer<BaseState> {
         );
 
         Tokenizer {
-            state: StringState,
+            state: StringState {
+                quote: character_item.character,
+                escaped: false,
+            },
             char_buffer: String::from(""),
             token_start: character_item.location,
             tokens: self.tokens,
@@ -249,6 +262,25 @@ impl Tokenizer<BaseState> {
         }
     }
 
+    fn to_block_comment_state(
+        mut self,
+        character_item: CharacterItem,
+    ) -> Tokenizer<BlockCommentState> {
+        self.push_token(
+            self.char_buffer.clone(),
+            self.token_start,
+            character_item.location,
+            Tokenizer::<BaseState>::tokenize,
+        );
+
+        Tokenizer {
+            state: BlockCommentState,
+            char_buffer: character_item.character.to_string(),
+            token_start: character_item.location,
+            tokens: self.tokens,
+        }
+    }
+
     fn to_number_state(self, character_item: CharacterItem) -> Tokenizer<NumberState> {
         // character_item will always be "" in this instance
 
@@ -266,7 +298,12 @@ impl Tokenizer<BaseState> {
         Tokenizer {
             state: BaseState,
             char_buffer: format!("{}{}", self.char_buffer, character_item.character),
-            token_start: self.token_start,
+            // A word starts with its first character
+            token_start: if self.char_buffer.is_empty() {
+                character_item.location
+            } else {
+                self.token_start
+            },
             tokens: self.tokens,
         }
     }
@@ -282,7 +319,7 @@ impl Tokenizer<BaseState> {
         Tokenizer {
             state: OperatorState,
             char_buffer: character_item.character.to_string(),
-            token_start: self.token_start,
+            token_start: character_item.location,
             tokens: self.tokens,
         }
     }
@@ -296,7 +333,7 @@ impl Tokenizer<BaseState> {
             character_item.next_character,
             self.char_buffer.as_str(),
         ) {
-            ('\0', _, ..) => {
+            ('\0', ..) => {
                 self.push_token(
                     self.char_buffer.clone(),
                     self.token_start,
@@ -305,19 +342,22 @@ impl Tokenizer<BaseState> {
                 );
                 Ok(TokenizerStateMachine::Base(self))
             }
-            ('"', ..) => Ok(TokenizerStateMachine::String(
+            ('"' | '\'', ..) => Ok(TokenizerStateMachine::String(
                 self.to_string_state(character_item),
             )),
             ('-', Some('-'), _) => Ok(TokenizerStateMachine::Comment(
                 self.to_comment_state(character_item),
             )),
+            ('/', Some('*'), _) => Ok(TokenizerStateMachine::BlockComment(
+                self.to_block_comment_state(character_item),
+            )),
             ('0'..='9' | '.', _, "") => Ok(TokenizerStateMachine::Number(
                 self.to_number_state(character_item),
             )),
             _ => {
                 let separator = Separator::from(character_item.character.to_string().as_str());
                 match separator {
-                    Separator::Invalid {} => Ok(TokenizerStateMachine::Base(
+                    Separator::Invalid => Ok(TokenizerStateMachine::Base(
                         self.to_base_state(character_item),
                     )),
                     _ => {
@@ -327,16 +367,16 @@ impl Tokenizer<BaseState> {
                             character_item.location,
                             Tokenizer::<BaseState>::tokenize,
                         );
+                        self.char_buffer = String::new();
 
                         match separator {
                             Separator::Operator { .. } => Ok(TokenizerStateMachine::Operator(
                                 self.to_operator_state(character_item),
                             )),
                             _ => {
-                                self.char_buffer = String::new();
                                 self.push_token(
                                     character_item.character.to_string(),
-                                    self.token_start,
+                                    character_item.location,
                                     character_item.location,
                                     Tokenizer::<BaseState>::tokenize,
                                 );
@@ -357,7 +397,7 @@ impl Tokenizer<StringState> {
         end: CharacterLocation,
     ) -> Option<TokenItem> {
         Some(TokenItem {
-            token: Token::String(string.into()),
+            token: Token::String(string),
             start,
             end,
         })
@@ -381,7 +421,7 @@ impl Tokenizer<StringState> {
 
     fn to_string_state(self, character_item: CharacterItem) -> Tokenizer<StringState> {
         Tokenizer {
-            state: StringState,
+            state: self.state,
             char_buffer: format!("{}{}", self.char_buffer, character_item.character),
             token_start: self.token_start,
             tokens: self.tokens,
@@ -394,7 +434,20 @@ impl Tokenizer<StringState> {
         match (character_item.character, self.char_buffer.as_str()) {
             ('\0', _) => Err(TokenizerError::UnterminatedString(self.token_start)),
             ('\n', _) => Err(TokenizerError::UnterminatedString(self.token_start)),
-            ('"', _) => Ok(TokenizerStateMachine::Base(
+            // The second of a doubled quote, already in the string
+            (quote, _) if quote == self.state.quote && self.state.escaped => {
+                let mut string = self;
+                string.state.escaped = false;
+                Ok(TokenizerStateMachine::String(string))
+            }
+            (quote, _)
+                if quote == self.state.quote && character_item.next_character == Some(quote) =>
+            {
+                let mut string = self.to_string_state(character_item);
+                string.state.escaped = true;
+                Ok(TokenizerStateMachine::String(string))
+            }
+            (quote, _) if quote == self.state.quote => Ok(TokenizerStateMachine::Base(
                 self.to_base_state(character_item),
             )),
             _ => Ok(TokenizerStateMachine::String(
@@ -459,6 +512,63 @@ impl Tokenizer<CommentState> {
     }
 }
 
+impl Tokenizer<BlockCommentState> {
+    /// A comment `/*+ ... */` is the hints between its markers, and any
+    /// other is a space.
+    fn tokenize(
+        string: String,
+        start: CharacterLocation,
+        end: CharacterLocation,
+    ) -> Option<TokenItem> {
+        let token = match string.strip_prefix("/*+") {
+            Some(hints) => Token::Hint(hints[..hints.len() - 2].trim().to_string()),
+            None => Token::Separator(Separator::Whitespace(Whitespace::Space)),
+        };
+        Some(TokenItem { token, start, end })
+    }
+
+    fn to_base_state(mut self, character_item: CharacterItem) -> Tokenizer<BaseState> {
+        self.char_buffer.push(character_item.character);
+        self.push_token(
+            self.char_buffer.clone(),
+            self.token_start,
+            character_item.location,
+            Tokenizer::<BlockCommentState>::tokenize,
+        );
+
+        Tokenizer {
+            state: BaseState,
+            char_buffer: String::new(),
+            token_start: character_item.location,
+            tokens: self.tokens,
+        }
+    }
+
+    fn to_block_comment_state(
+        mut self,
+        character_item: CharacterItem,
+    ) -> Tokenizer<BlockCommentState> {
+        self.char_buffer.push(character_item.character);
+        self
+    }
+
+    fn process_character(
+        self,
+        character_item: CharacterItem,
+    ) -> Result<TokenizerStateMachine, TokenizerError> {
+        match (character_item.character, self.char_buffer.as_str()) {
+            ('\0', _) => Err(TokenizerError::UnterminatedComment(self.token_start)),
+            // The `*` of the opening `/*` doesn't also close it
+            ('/', buffer) if buffer.len() > 2 && buffer.ends_with('*') => Ok(
+                TokenizerStateMachine::Base(self.to_base_state(character_item)),
+            ),
+            _ => Ok(TokenizerStateMachine::BlockComment(
+                self.to_block_comment_state(character_item),
+            )),
+        }
+    }
+}
+
 impl Tokenizer<OperatorState> {
     fn tokenize(
         string: String,
@@ -515,12 +625,12 @@ impl Tokenizer<NumberState> {
         start: CharacterLocation,
         end: CharacterLocation,
     ) -> Option<TokenItem> {
-        if string == "" || string == "\0" {
+        if string.is_empty() || string == "\0" {
             return None;
         }
 
         Some(TokenItem {
-            token: Token::Number(string.into()),
+            token: Token::Number(string),
             start,
             end,
         })
@@ -549,6 +659,26 @@ impl Tokenizer<NumberState> {
         }
     }
 
+    /// The number ends where a comment starts.
+    fn to_block_comment_state(
+        mut self,
+        character_item: CharacterItem,
+    ) -> Tokenizer<BlockCommentState> {
+        self.push_token(
+            self.char_buffer.clone(),
+            self.token_start,
+            character_item.location,
+            Tokenizer::<NumberState>::tokenize,
+        );
+
+        Tokenizer {
+            state: BlockCommentState,
+            char_buffer: character_item.character.to_string(),
+            token_start: character_item.location,
+            tokens: self.tokens,
+        }
+    }
+
     fn to_number_state(
         self,
         character_item: CharacterItem,
@@ -571,6 +701,9 @@ impl Tokenizer<NumberState> {
             self.state.parsing_decimals,
         ) {
             ('.', _, true) => Err(TokenizerError::InvalidNumber(self.token_start)),
+            ('/', ..) if character_item.next_character == Some('*') => Ok(
+                TokenizerStateMachine::BlockComment(self.to_block_comment_state(character_item)),
+            ),
             ('.', _, false) => Ok(TokenizerStateMachine::Number(
                 self.to_number_state(character_item, true),
             )),
@@ -580,6 +713,18 @@ impl Tokenizer<NumberState> {
                     self.to_number_state(character_item, current_state),
                 ))
             }
+            // An exponent, after which there's no point
+            ('e' | 'E', buffer, _)
+                if !buffer.contains(['e', 'E'])
+                    && matches!(character_item.next_character, Some('0'..='9' | '+' | '-')) =>
+            {
+                Ok(TokenizerStateMachine::Number(
+                    self.to_number_state(character_item, true),
+                ))
+            }
+            ('+' | '-', buffer, _) if buffer.ends_with(['e', 'E']) => Ok(
+                TokenizerStateMachine::Number(self.to_number_state(character_item, true)),
+            ),
             _ => Ok(TokenizerStateMachine::Base(
                 self.to_base_state(character_item),
             )),
@@ -612,7 +757,7 @@ impl<'a> Iterator for TokenIterator<'a> {
             return Some(Ok(token_item));
         }
 
-        while let Some(character) = self.char_iter.next() {
+        for character in self.char_iter.by_ref() {
             match self.state_machine.process_character(character) {
                 Ok(()) => {
                     let mut tokens = self.state_machine.collect_tokens();
@@ -631,7 +776,7 @@ impl<'a> Iterator for TokenIterator<'a> {
 }
 
 // Make the tokenize function return type explicit
-pub(crate) fn tokenize(sql: &str) -> TokenIterator {
+pub(crate) fn tokenize(sql: &str) -> TokenIterator<'_> {
     TokenIterator::new(sql)
 }
 
@@ -676,6 +821,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_single_quoted_string() {
+        let tokens = collect_tokens(r#"'it "works"'"#).unwrap();
+        assert_eq!(tokens, vec![Token::String(r#"it "works""#.to_string())]);
+    }
+
+    #[test]
+    fn test_doubled_quote() {
+        let tokens = collect_tokens("'o''k','''',''").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::String("o'k".to_string()),
+                Token::Separator(Separator::Comma),
+                Token::String("'".to_string()),
+                Token::Separator(Separator::Comma),
+                Token::String(String::new()),
+            ]
+        );
+        let tokens = collect_tokens(r#""say ""hi""""#).unwrap();
+        assert_eq!(tokens, vec![Token::String(r#"say "hi""#.to_string())]);
+        assert!(matches!(
+            collect_tokens("'o''"),
+            Err(TokenizerError::UnterminatedString(_))
+        ));
+    }
+
     #[test]
     fn test_numbers() {
         let tokens = collect_tokens("SELECT 42, 3.14").unwrap();
@@ -690,6 +862,17 @@ mod tests {
                 Token::Number("3.14".to_string()),
             ]
         );
+        let tokens = collect_tokens("1e10 2.5E-3").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Number("1e10".to_string()),
+                Token::Separator(Separator::Whitespace(Whitespace::Space)),
+                Token::Number("2.5E-3".to_string()),
+            ]
+        );
+        let result = collect_tokens("SELECT 1e5.5");
+        assert!(matches!(result, Err(TokenizerError::InvalidNumber(_))));
     }
 
     #[test]
@@ -711,6 +894,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_operator_after_identifier() {
+        let tokens = collect_tokens("a=(b)").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Identifier("a".to_string()),
+                Token::Separator(Separator::Operator(Operator::Eq)),
+                Token::Separator(Separator::Operator(Operator::ParenOpen)),
+                Token::Identifier("b".to_string()),
+                Token::Separator(Separator::Operator(Operator::ParenClose)),
+            ]
+        );
+    }
+
     #[test]
     fn test_comments() {
         let tokens = collect_tokens("SELECT -- this is a comment\n42").unwrap();
@@ -725,6 +923,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_block_comments() {
+        let tokens = collect_tokens("SELECT /*+ FULL(3) */ * /* a * / comment */1/**/").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Keyword(Keyword::Select),
+                Token::Separator(Separator::Whitespace(Whitespace::Space)),
+                Token::Hint("FULL(3)".to_string()),
+                Token::Separator(Separator::Whitespace(Whitespace::Space)),
+                Token::Separator(Separator::Operator(Operator::Multiply)),
+                Token::Separator(Separator::Whitespace(Whitespace::Space)),
+                Token::Separator(Separator::Whitespace(Whitespace::Space)),
+                Token::Number("1".to_string()),
+                Token::Separator(Separator::Whitespace(Whitespace::Space)),
+            ]
+        );
+        let result = collect_tokens("SELECT /*/ 1");
+        assert!(matches!(
+            result,
+            Err(TokenizerError::UnterminatedComment(_))
+        ));
+    }
+
     #[test]
     fn test_unterminated_string() {
         let result = collect_tokens(r#"SELECT "unterminated"#);
@@ -5,6 +5,8 @@ pub(crate) enum Token {
     Separator(Separator),
     String(String),
     Number(String),
+    /// The hints of a comment `/*+ ... */`
+    Hint(String),
     Invalid(String),
 }
 
@@ -29,11 +31,13 @@ pub(crate) enum Whitespace {
 pub(crate) enum Keyword {
     And,
     As,
+    Backup,
     Begin,
     Between,
     BigInt,
     Bool,
     By,
+    Checkpoint,
     Commit,
     Create,
     Database,
@@ -50,20 +54,30 @@ pub(crate) enum Keyword {
     Key,
     Like,
     Limit,
+    Lsn,
     Not,
     Null,
+    Of,
     Or,
     Order,
+    Prepare,
+    Prepared,
     Primary,
+    Release,
+    Restore,
     Rollback,
+    Savepoint,
     Select,
     Set,
     Table,
+    Timestamp,
+    To,
     Transaction,
     True,
     Unique,
     Unsigned,
     Update,
+    Vacuum,
     Values,
     Varchar,
     Where,
@@ -135,11 +149,13 @@ impl From<&str> for Keyword {
         match val.to_uppercase().as_str() {
             "AND" => Keyword::And,
             "AS" => Keyword::As,
+            "BACKUP" => Keyword::Backup,
             "BEGIN" => Keyword::Begin,
             "BETWEEN" => Keyword::Between,
             "BIGINT" => Keyword::BigInt,
             "BOOL" => Keyword::Bool,
             "BY" => Keyword::By,
+            "CHECKPOINT" => Keyword::Checkpoint,
             "COMMIT" => Keyword::Commit,
             "CREATE" => Keyword::Create,
             "DATABASE" => Keyword::Database,
@@ -155,20 +171,30 @@ impl From<&str> for Keyword {
             "KEY" => Keyword::Key,
             "LIKE" => Keyword::Like,
             "LIMIT" => Keyword::Limit,
+            "LSN" => Keyword::Lsn,
             "NOT" => Keyword::Not,
             "NULL" => Keyword::Null,
+            "OF" => Keyword::Of,
             "OR" => Keyword::Or,
             "ORDER" => Keyword::Order,
+            "PREPARE" => Keyword::Prepare,
+            "PREPARED" => Keyword::Prepared,
             "PRIMARY" => Keyword::Primary,
+            "RELEASE" => Keyword::Release,
+            "RESTORE" => Keyword::Restore,
             "ROLLBACK" => Keyword::Rollback,
+            "SAVEPOINT" => Keyword::Savepoint,
             "SELECT" => Keyword::Select,
             "SET" => Keyword::Set,
             "TABLE" => Keyword::Table,
+            "TIMESTAMP" => Keyword::Timestamp,
+            "TO" => Keyword::To,
             "TRANSACTION" => Keyword::Transaction,
             "TRUE" => Keyword::True,
             "UNIQUE" => Keyword::Unique,
             "UNSIGNED" => Keyword::Unsigned,
             "UPDATE" => Keyword::Update,
+            "VACUUM" => Keyword::Vacuum,
             "VALUES" => Keyword::Values,
             "VARCHAR" => Keyword::Varchar,
             "WHERE" => Keyword::Where,
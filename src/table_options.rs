@@ -0,0 +1,238 @@
+//! How a table's pages are stored, as `CREATE TABLE ... WITH (...)` asks,
+//! kept in a catalog record of its own.
+//!
+//! A table with no record, or an option it doesn't give, is stored as
+//! the database's settings say: `storage.fill_factor` and
+//! `storage.compression`. The options apply from when the table is
+//! created, and are read back from the catalog each time the database
+//! opens.
+//!
+//! A table with a `ttl_column` has its rows deleted once the time in that
+//! column has passed, by `Database::sweep_expired` or a `TtlSweeper`.
+
+use crate::config::{Compression, MIN_FILL_FACTOR};
+use crate::database::{Database, DatabaseError, TableId};
+use crate::storage::FileId;
+use std::ops::RangeInclusive;
+
+/// Marks a catalog record as a table's options.
+const TABLE_OPTIONS: u8 = 4;
+
+/// Stands in the catalog for an option that isn't given.
+const UNSET: u8 = 0xFF;
+
+/// How a table is stored, where it differs from the database's settings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableOptions {
+    /// Percent of each page to fill with new rows, leaving the rest for
+    /// those on it to grow into when updated
+    pub fill_factor: Option<u8>,
+    /// How to compress the table's pages on disk
+    pub compression: Option<Compression>,
+    /// Whether to keep a Bloom filter of each page's rows, so that
+    /// `Connection::find` skips the pages that can't hold what it looks for
+    pub bloom_filter: bool,
+    /// The column of the table's JSON rows holding when each expires: a
+    /// time as in RFC 3339, or seconds since the Unix epoch. Rows without
+    /// one don't expire.
+    pub ttl_column: Option<String>,
+}
+
+impl TableOptions {
+    /// The fill factors a table may have.
+    pub const FILL_FACTORS: RangeInclusive<u8> = MIN_FILL_FACTOR..=100;
+
+    /// Bytes of each page of `page_size` to keep free, given the
+    /// database's own fill factor when the table has none.
+    pub(crate) fn reserved(&self, page_size: usize, default: u8) -> usize {
+        let fill_factor = self.fill_factor.unwrap_or(default);
+        page_size * (100 - usize::from(fill_factor)) / 100
+    }
+
+    fn encode(&self, table: TableId) -> Vec<u8> {
+        let mut bytes = vec![TABLE_OPTIONS];
+        bytes.extend(table.0.to_be_bytes());
+        bytes.push(self.fill_factor.unwrap_or(UNSET));
+        bytes.push(self.compression.map_or(UNSET, Compression::code));
+        bytes.push(self.bloom_filter as u8);
+        bytes.extend_from_slice(self.ttl_column.as_deref().unwrap_or_default().as_bytes());
+        bytes
+    }
+
+    /// The table and options a catalog record holds, or `None` if it holds
+    /// something else. Records from before Bloom filters end before their
+    /// byte, and the TTL column's name follows it.
+    fn decode(bytes: &[u8]) -> Result<Option<(TableId, Self)>, DatabaseError> {
+        if bytes.first() != Some(&TABLE_OPTIONS) {
+            return Ok(None);
+        }
+        let (fields, rest) = bytes.split_at(bytes.len().min(7));
+        let &[_, a, b, c, d, fill_factor, compression] = fields else {
+            return Err(DatabaseError::CorruptedCatalog);
+        };
+        let (bloom_filter, ttl_column) = match rest {
+            [] => (false, ""),
+            [flag @ (0 | 1), column @ ..] => (
+                *flag == 1,
+                std::str::from_utf8(column).map_err(|_| DatabaseError::CorruptedCatalog)?,
+            ),
+            _ => return Err(DatabaseError::CorruptedCatalog),
+        };
+        let table = TableId(u32::from_be_bytes([a, b, c, d]));
+        let fill_factor = match fill_factor {
+            UNSET => None,
+            percent if Self::FILL_FACTORS.contains(&percent) => Some(percent),
+            _ => return Err(DatabaseError::CorruptedCatalog),
+        };
+        let compression = match compression {
+            UNSET => None,
+            code => Some(Compression::from_code(code).ok_or(DatabaseError::CorruptedCatalog)?),
+        };
+        Ok(Some((
+            table,
+            Self {
+                fill_factor,
+                compression,
+                bloom_filter,
+                ttl_column: (!ttl_column.is_empty()).then(|| ttl_column.to_string()),
+            },
+        )))
+    }
+}
+
+impl Database {
+    /// Create a new, empty table stored as `options` say.
+    pub fn create_table_with(&self, options: TableOptions) -> Result<TableId, DatabaseError> {
+        if let Some(fill_factor) = options.fill_factor {
+            if !TableOptions::FILL_FACTORS.contains(&fill_factor) {
+                return Err(DatabaseError::InvalidFillFactor(fill_factor));
+            }
+        }
+        let table = self.create_table()?;
+        if options != TableOptions::default() {
+            self.connect_system()
+                .insert(TableId::CATALOG, &options.encode(table))?;
+            self.set_table_options(table, options);
+        }
+        Ok(table)
+    }
+
+    /// Store `table` as `options` say from now on.
+    pub(crate) fn set_table_options(&self, table: TableId, options: TableOptions) {
+        if let Some(compression) = options.compression {
+            self.pages()
+                .set_file_compression(FileId(table.0), compression);
+        }
+        if options.bloom_filter {
+            let mut tables = self.bloom_filters.write().unwrap();
+            tables.entry(table).or_default();
+        }
+        let mut tables = self.table_options.write().unwrap();
+        tables.insert(table, options);
+    }
+
+    /// How `table` is stored, where it differs from the database's
+    /// settings.
+    pub fn table_options(&self, table: TableId) -> TableOptions {
+        let tables = self.table_options.read().unwrap();
+        tables.get(&table).cloned().unwrap_or_default()
+    }
+
+    /// Bytes of each of `table`'s pages that new rows leave free. The
+    /// catalog's are filled whole.
+    pub(crate) fn reserved_space(&self, table: TableId) -> usize {
+        if table == TableId::CATALOG {
+            return 0;
+        }
+        let page_size = self.pages().page_size();
+        self.table_options(table)
+            .reserved(page_size, self.fill_factor)
+    }
+
+    /// Every table's options in the catalog.
+    pub(crate) fn read_table_options(&self) -> Result<Vec<(TableId, TableOptions)>, DatabaseError> {
+        let mut tables = Vec::new();
+        for row in self.connect_system().scan(TableId::CATALOG)? {
+            if let Some(table) = TableOptions::decode(&row.data)? {
+                tables.push(table);
+            }
+        }
+        Ok(tables)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_config;
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_table_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config(dir.path());
+        config.storage.page_size = 512;
+        let row = [b'a'; 40];
+        let rows_on_first_page = |database: &Database, table| {
+            let rows = database.connect().scan(table).unwrap();
+            rows.iter().filter(|row| row.id.page_no == 0).count()
+        };
+
+        let (full, sparse, compressed) = {
+            let database = Database::with_config(&config).unwrap();
+            let mut session = SqlSession::new(database.connect());
+            let mut tables = Vec::new();
+            for sql in [
+                "CREATE TABLE",
+                "CREATE TABLE WITH (fill_factor = 50)",
+                "CREATE TABLE (COMPRESSION = 'lz', FILL_FACTOR = 100, TTL_COLUMN = 'expires')",
+            ] {
+                let result = session.execute(sql).remove(0).unwrap();
+                tables.push(TableId(
+                    result.rows[0][0].as_ref().unwrap().parse().unwrap(),
+                ));
+            }
+            let invalid = TableOptions {
+                fill_factor: Some(5),
+                ..TableOptions::default()
+            };
+            assert!(matches!(
+                database.create_table_with(invalid),
+                Err(DatabaseError::InvalidFillFactor(5))
+            ));
+            let mut connection = database.connect();
+            for &table in &tables {
+                connection.insert_batch(table, &[row; 20]).unwrap();
+                for _ in 0..10 {
+                    connection.insert(table, &row).unwrap();
+                }
+            }
+            database.pages().checkpoint().unwrap();
+            (tables[0], tables[1], tables[2])
+        };
+        // Each stored page's header starts with how it was compressed
+        let codec = |table: TableId| {
+            let path = dir.path().join(format!("{}.fdb", table.0));
+            std::fs::read(path).unwrap()[0]
+        };
+        assert_eq!(codec(full), 0);
+        #[cfg(feature = "compression")]
+        assert_eq!(codec(compressed), 1);
+
+        // The options stand once the database is opened again
+        let database = Database::with_config(&config).unwrap();
+        assert_eq!(database.table_options(full), TableOptions::default());
+        assert_eq!(
+            database.table_options(compressed),
+            TableOptions {
+                fill_factor: Some(100),
+                compression: Some(Compression::Lz),
+                bloom_filter: false,
+                ttl_column: Some("expires".to_string()),
+            }
+        );
+        let packed = rows_on_first_page(&database, full);
+        assert!(rows_on_first_page(&database, sparse) < packed * 2 / 3);
+        assert_eq!(rows_on_first_page(&database, compressed), packed);
+    }
+}
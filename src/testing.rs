@@ -0,0 +1,194 @@
+//! What property tests outside the crate need to hammer its storage layer,
+//! with the `testing` feature: slotted pages, `SlottedPage::check_invariants`
+//! to run after each change to one, and generators of arbitrary changes and
+//! pages. A generator draws everything from one seed, so a proptest or
+//! quickcheck strategy only has to produce the seed, and a failure found
+//! reproduces from it.
+
+pub use crate::storage::{Page, PageDecodeError, RecordKind, SlotId, SlottedPage};
+
+/// A xorshift generator of arbitrary values, the same for the same seed.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    state: u64,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        // Xorshift never leaves zero
+        Self { state: seed.max(1) }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A value less than `n`, which must be positive.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Up to `max_len` arbitrary bytes.
+    pub fn bytes(&mut self, max_len: usize) -> Vec<u8> {
+        let len = self.below(max_len as u64 + 1) as usize;
+        (0..len).map(|_| self.next_u64() as u8).collect()
+    }
+
+    pub fn kind(&mut self) -> RecordKind {
+        match self.below(3) {
+            0 => RecordKind::Row,
+            1 => RecordKind::Spilled,
+            _ => RecordKind::Overflow,
+        }
+    }
+
+    /// A change to make to `page`, to a slot it has or just past them.
+    pub fn page_op(&mut self, page: &SlottedPage) -> PageOp {
+        let page_size = page.as_page().as_bytes().len();
+        let slots = page.entries().map_or(0, |entries| {
+            entries.last().map_or(0, |&(slot, ..)| slot.0 as u64 + 1)
+        });
+        let slot = SlotId(self.below(slots + 1) as u32);
+        match self.below(10) {
+            0..=4 => PageOp::Insert {
+                record: self.bytes(page_size / 4),
+                reserved: self.below(page_size as u64 / 8) as usize,
+                kind: self.kind(),
+            },
+            5 | 6 => PageOp::Delete(slot),
+            7 | 8 => PageOp::Update {
+                slot,
+                record: self.bytes(page_size / 4),
+                kind: self.kind(),
+            },
+            _ => PageOp::Compact,
+        }
+    }
+
+    /// A page of `page_size` bytes after up to `changes` arbitrary
+    /// changes.
+    pub fn page(&mut self, page_size: usize, changes: usize) -> SlottedPage {
+        let mut page = SlottedPage::new(page_size);
+        for _ in 0..self.below(changes as u64 + 1) {
+            self.page_op(&page)
+                .apply(&mut page)
+                .expect("a page made by its own changes reads back");
+        }
+        page
+    }
+}
+
+/// A change to a slotted page, as a property test makes them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PageOp {
+    Insert {
+        record: Vec<u8>,
+        reserved: usize,
+        kind: RecordKind,
+    },
+    Delete(SlotId),
+    Update {
+        slot: SlotId,
+        record: Vec<u8>,
+        kind: RecordKind,
+    },
+    Compact,
+}
+
+impl PageOp {
+    /// Make the change, returning the slot an insert took, or any other
+    /// change's slot if it changed a record there.
+    pub fn apply(&self, page: &mut SlottedPage) -> Result<Option<SlotId>, PageDecodeError> {
+        Ok(match self {
+            Self::Insert {
+                record,
+                reserved,
+                kind,
+            } => page.insert_as(record, *reserved, *kind)?,
+            Self::Delete(slot) => page.delete(*slot)?.then_some(*slot),
+            Self::Update { slot, record, kind } => {
+                page.update_as(*slot, record, *kind)?.then_some(*slot)
+            }
+            Self::Compact => {
+                page.compact()?;
+                None
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_slotted_page_invariants() {
+        for seed in 1..200 {
+            let mut generator = Generator::new(seed);
+            let page_size = [64, 128, 512][seed as usize % 3];
+            let mut page = SlottedPage::new(page_size);
+            // What the page should hold, by slot
+            let mut model = BTreeMap::new();
+            for _ in 0..100 {
+                let op = generator.page_op(&page);
+                let changed = op.apply(&mut page).unwrap();
+                match (&op, changed) {
+                    (PageOp::Insert { record, kind, .. }, Some(slot)) => {
+                        assert!(model.insert(slot, (*kind, record.clone())).is_none());
+                    }
+                    (PageOp::Update { record, kind, .. }, Some(slot)) => {
+                        assert!(model.insert(slot, (*kind, record.clone())).is_some());
+                    }
+                    (PageOp::Delete(slot), deleted) => {
+                        assert_eq!(model.remove(slot).is_some(), deleted.is_some());
+                    }
+                    (PageOp::Update { slot, .. }, None) => {
+                        // Only a missing record or one with no room fails
+                        let record = page.get(*slot).unwrap();
+                        assert!(record.is_none_or(|record| record == model[slot].1));
+                    }
+                    _ => {}
+                }
+                if let Err(e) = page.check_invariants() {
+                    panic!("seed {seed}, after {op:?}: {e}");
+                }
+                let held: BTreeMap<_, _> = page
+                    .entries()
+                    .unwrap()
+                    .into_iter()
+                    .map(|(slot, kind, record)| (slot, (kind, record.to_vec())))
+                    .collect();
+                assert_eq!(held, model, "seed {seed}, after {op:?}");
+            }
+            let page = SlottedPage::from_page(page.into_page()).unwrap();
+            page.check_invariants().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_generated_pages() {
+        // The same seed makes the same page
+        let page = Generator::new(3).page(256, 50);
+        assert_eq!(page, Generator::new(3).page(256, 50));
+        page.check_invariants().unwrap();
+
+        // Damage is caught
+        let mut bytes = page.into_page().as_bytes().to_vec();
+        bytes[4] ^= 8;
+        let damaged = SlottedPage::from_page(Page::new(bytes));
+        assert!(damaged.is_err() || damaged.unwrap().check_invariants().is_err());
+        let mut page = SlottedPage::new(64);
+        page.insert(b"abc").unwrap();
+        let mut bytes = page.into_page().as_bytes().to_vec();
+        bytes[30] = 1;
+        let page = SlottedPage::from_page(Page::new(bytes)).unwrap();
+        assert!(matches!(
+            page.check_invariants(),
+            Err(PageDecodeError::Corrupted(reason)) if reason == "free space byte 30 isn't zero"
+        ));
+    }
+}
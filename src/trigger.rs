@@ -0,0 +1,370 @@
+//! Row-level triggers: a write statement run for each row an `INSERT`,
+//! `UPDATE` or `DELETE` statement changes in a table, before or after the
+//! change, as `CREATE TRIGGER` defines it.
+//!
+//! Triggers are kept in catalog records of their own and read back each
+//! time the database opens. They are fired by the SQL executors, in the
+//! transaction of the statement firing them, so a trigger that fails
+//! fails the statement; the embedded API's writes and `COPY` don't fire
+//! them.
+
+use crate::database::{Connection, Database, DatabaseError, Row, TableId};
+use crate::syntax::{Statement, Value};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Cursor, Read};
+
+/// Marks a catalog record as a trigger.
+const TRIGGER: u8 = 5;
+
+/// How deep triggers may fire others through the writes they make,
+/// before a loop is assumed.
+pub(crate) const MAX_DEPTH: usize = 16;
+
+/// Whether a trigger fires before or after the change to its row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Timing {
+    Before,
+    After,
+}
+
+/// The change to a row a trigger fires on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Event {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl Event {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Event::Insert => "INSERT",
+            Event::Update => "UPDATE",
+            Event::Delete => "DELETE",
+        }
+    }
+
+    /// Whether the row has data as it will be, for `NEW`.
+    pub(crate) fn has_new(self) -> bool {
+        self != Event::Delete
+    }
+
+    /// Whether the row has data as it was, for `OLD`.
+    pub(crate) fn has_old(self) -> bool {
+        self != Event::Insert
+    }
+}
+
+/// A trigger record: `TRIGGER`, the table, timing and event, the name's
+/// length and bytes, then the action as `encode_action` writes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Trigger {
+    pub(crate) name: String,
+    pub(crate) timing: Timing,
+    pub(crate) event: Event,
+    pub(crate) table: TableId,
+    /// An `Insert`, `Update` or `Delete`, whose values may be `Value::New`
+    /// and `Value::Old` as `event` allows
+    pub(crate) action: Box<Statement>,
+}
+
+impl Trigger {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![TRIGGER];
+        bytes.write_u32::<BigEndian>(self.table.0).unwrap();
+        bytes.push(self.timing as u8);
+        bytes.push(self.event as u8);
+        bytes
+            .write_u16::<BigEndian>(self.name.len() as u16)
+            .unwrap();
+        bytes.extend_from_slice(self.name.as_bytes());
+        encode_action(&self.action, &mut bytes);
+        bytes
+    }
+
+    /// The trigger a catalog record holds, or `None` if it holds something
+    /// else.
+    fn decode(bytes: &[u8]) -> Result<Option<Self>, DatabaseError> {
+        if bytes.first() != Some(&TRIGGER) {
+            return Ok(None);
+        }
+        let decode = || -> io::Result<Self> {
+            let mut cursor = Cursor::new(&bytes[1..]);
+            let table = TableId(cursor.read_u32::<BigEndian>()?);
+            let timing = match cursor.read_u8()? {
+                0 => Timing::Before,
+                1 => Timing::After,
+                _ => return Err(io::ErrorKind::InvalidData.into()),
+            };
+            let event = match cursor.read_u8()? {
+                0 => Event::Insert,
+                1 => Event::Update,
+                2 => Event::Delete,
+                _ => return Err(io::ErrorKind::InvalidData.into()),
+            };
+            let len = cursor.read_u16::<BigEndian>()?;
+            let name = read_string(&mut cursor, len)?;
+            let action = Box::new(decode_action(&mut cursor)?);
+            Ok(Self {
+                name,
+                timing,
+                event,
+                table,
+                action,
+            })
+        };
+        decode()
+            .map(Some)
+            .map_err(|_| DatabaseError::CorruptedCatalog)
+    }
+}
+
+/// Write an action: its kind, the table it writes and its values as
+/// `encode_value` writes them.
+fn encode_action(action: &Statement, bytes: &mut Vec<u8>) {
+    let (kind, table, values) = match action {
+        Statement::Insert { table, values } => (0, table, values.iter().collect()),
+        Statement::Update { table, row, value } => (1, table, vec![row, value]),
+        Statement::Delete { table, row } => (2, table, vec![row]),
+        _ => unreachable!("a trigger's action is a write"),
+    };
+    bytes.push(kind);
+    bytes.write_u32::<BigEndian>(*table).unwrap();
+    bytes.write_u16::<BigEndian>(values.len() as u16).unwrap();
+    for value in values {
+        encode_value(value, bytes);
+    }
+}
+
+/// Write a value's kind and, for a string, its length and bytes, or for
+/// a call, its name's and its arguments.
+fn encode_value(value: &Value, bytes: &mut Vec<u8>) {
+    match value {
+        Value::String(string) => {
+            bytes.push(0);
+            bytes.write_u32::<BigEndian>(string.len() as u32).unwrap();
+            bytes.extend_from_slice(string.as_bytes());
+        }
+        Value::New => bytes.push(1),
+        Value::Old => bytes.push(2),
+        Value::Call { name, args } => {
+            bytes.push(3);
+            bytes.write_u16::<BigEndian>(name.len() as u16).unwrap();
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.write_u16::<BigEndian>(args.len() as u16).unwrap();
+            for arg in args {
+                encode_value(arg, bytes);
+            }
+        }
+        Value::Null => bytes.push(4),
+        Value::Param(_) => unreachable!("a trigger's action takes no parameters"),
+    }
+}
+
+fn decode_action(cursor: &mut Cursor<&[u8]>) -> io::Result<Statement> {
+    let kind = cursor.read_u8()?;
+    let table = cursor.read_u32::<BigEndian>()?;
+    let values = decode_values(cursor)?;
+    let mut values = values.into_iter();
+    Ok(match (kind, values.len()) {
+        (0, _) => Statement::Insert {
+            table,
+            values: values.collect(),
+        },
+        (1, 2) => Statement::Update {
+            table,
+            row: values.next().unwrap(),
+            value: values.next().unwrap(),
+        },
+        (2, 1) => Statement::Delete {
+            table,
+            row: values.next().unwrap(),
+        },
+        _ => return Err(io::ErrorKind::InvalidData.into()),
+    })
+}
+
+/// Read a count of values, then each as `encode_value` wrote it.
+fn decode_values(cursor: &mut Cursor<&[u8]>) -> io::Result<Vec<Value>> {
+    let count = cursor.read_u16::<BigEndian>()?;
+    let mut values = Vec::new();
+    for _ in 0..count {
+        values.push(match cursor.read_u8()? {
+            0 => {
+                let len = cursor.read_u32::<BigEndian>()? as usize;
+                Value::String(read_string(cursor, len)?)
+            }
+            1 => Value::New,
+            2 => Value::Old,
+            3 => {
+                let len = cursor.read_u16::<BigEndian>()?;
+                let name = read_string(cursor, len)?;
+                let args = decode_values(cursor)?;
+                Value::Call { name, args }
+            }
+            4 => Value::Null,
+            _ => return Err(io::ErrorKind::InvalidData.into()),
+        });
+    }
+    Ok(values)
+}
+
+fn read_string(cursor: &mut Cursor<&[u8]>, len: impl Into<usize>) -> io::Result<String> {
+    let mut bytes = vec![0; len.into()];
+    cursor.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| io::ErrorKind::InvalidData.into())
+}
+
+/// Every trigger in the catalog, with the record holding it.
+fn triggers(connection: &mut Connection) -> Result<Vec<(Row, Trigger)>, DatabaseError> {
+    let mut triggers = Vec::new();
+    for row in connection.scan(TableId::CATALOG)? {
+        if let Some(trigger) = Trigger::decode(&row.data)? {
+            triggers.push((row, trigger));
+        }
+    }
+    Ok(triggers)
+}
+
+impl Database {
+    /// Add a trigger, to fire from the next statement on.
+    pub(crate) fn create_trigger(&self, trigger: Trigger) -> Result<(), DatabaseError> {
+        if !self.tables().contains(&trigger.table) {
+            return Err(DatabaseError::NoSuchTable(trigger.table));
+        }
+        if self.external_table(trigger.table).is_some() {
+            return Err(DatabaseError::ReadOnlyTable(trigger.table));
+        }
+        let mut connection = self.connect_system();
+        connection.begin()?;
+        connection.lock_exclusive(TableId::CATALOG)?;
+        if triggers(&mut connection)?
+            .iter()
+            .any(|(_, other)| other.name == trigger.name)
+        {
+            return Err(DatabaseError::TriggerExists(trigger.name));
+        }
+        connection.insert(TableId::CATALOG, &trigger.encode())?;
+        connection.commit()?;
+        self.add_trigger(trigger);
+        Ok(())
+    }
+
+    /// Remove the trigger `name`.
+    pub(crate) fn drop_trigger(&self, name: &str) -> Result<(), DatabaseError> {
+        let mut connection = self.connect_system();
+        connection.begin()?;
+        connection.lock_exclusive(TableId::CATALOG)?;
+        let (row, _) = triggers(&mut connection)?
+            .into_iter()
+            .find(|(_, trigger)| trigger.name == name)
+            .ok_or_else(|| DatabaseError::NoSuchTrigger(name.to_string()))?;
+        connection.delete(row.id)?;
+        connection.commit()?;
+        let mut triggers = self.triggers.write().unwrap();
+        triggers.retain(|trigger| trigger.name != name);
+        Ok(())
+    }
+
+    pub(crate) fn add_trigger(&self, trigger: Trigger) {
+        let mut triggers = self.triggers.write().unwrap();
+        let at = triggers.partition_point(|other| other.name < trigger.name);
+        triggers.insert(at, trigger);
+    }
+
+    /// The trigger `name`, if there is one.
+    pub(crate) fn trigger(&self, name: &str) -> Option<Trigger> {
+        let triggers = self.triggers.read().unwrap();
+        triggers
+            .iter()
+            .find(|trigger| trigger.name == name)
+            .cloned()
+    }
+
+    /// The triggers on `table` that fire on `event`, in name order, which
+    /// is the order they fire in. A partition's are its table's.
+    pub(crate) fn triggers(&self, table: TableId, event: Event) -> Vec<Trigger> {
+        let table = self.parent_table(table);
+        let triggers = self.triggers.read().unwrap();
+        triggers
+            .iter()
+            .filter(|trigger| trigger.table == table && trigger.event == event)
+            .cloned()
+            .collect()
+    }
+
+    /// Every trigger in the catalog.
+    pub(crate) fn read_triggers(&self) -> Result<Vec<Trigger>, DatabaseError> {
+        let triggers = triggers(&mut self.connect_system())?;
+        Ok(triggers.into_iter().map(|(_, trigger)| trigger).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::test_config;
+    use crate::sql::SqlSession;
+
+    #[test]
+    fn test_triggers() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(dir.path());
+        let data = |database: &Database, table| -> Vec<String> {
+            let rows = database.connect().scan(TableId(table)).unwrap();
+            rows.into_iter()
+                .map(|row| String::from_utf8(row.data).unwrap())
+                .collect()
+        };
+
+        {
+            let database = Database::with_config(&config).unwrap();
+            let mut session = SqlSession::new(database.connect());
+            for result in session.execute(
+                "CREATE TABLE; CREATE TABLE; CREATE TABLE;
+                 CREATE TRIGGER added AFTER INSERT ON 1 EXECUTE INSERT INTO 2 VALUES (NEW);
+                 CREATE TRIGGER changed BEFORE UPDATE ON 1 FOR EACH ROW
+                     EXECUTE INSERT INTO 2 VALUES (OLD), (NEW);
+                 CREATE TRIGGER removed BEFORE DELETE ON 1 EXECUTE INSERT INTO 2 VALUES ('gone');
+                 CREATE TRIGGER echo AFTER INSERT ON 3 EXECUTE INSERT INTO 3 VALUES (NEW)",
+            ) {
+                result.unwrap();
+            }
+            let again = session.execute(
+                "CREATE TRIGGER added BEFORE DELETE ON 2 EXECUTE DELETE FROM 2 WHERE id = OLD",
+            );
+            assert_eq!(again[0].as_ref().unwrap_err().code(), "42710");
+
+            let result = session.execute("INSERT INTO 1 VALUES ('a')").remove(0);
+            let row = result.unwrap().rows[0][0].clone().unwrap();
+            let sql = format!(
+                "UPDATE 1 SET data = 'b' WHERE id = '{row}'; DELETE FROM 1 WHERE id = '{row}'"
+            );
+            for result in session.execute(&sql) {
+                result.unwrap();
+            }
+            assert_eq!(data(&database, 2), vec!["a", "a", "b", "gone"]);
+
+            // A trigger firing itself is stopped, and its statement undone
+            let looped = session.execute("INSERT INTO 3 VALUES ('x')").remove(0);
+            assert_eq!(looped.unwrap_err().code(), "54001");
+            assert!(data(&database, 3).is_empty());
+        }
+
+        // The triggers stand once the database is opened again
+        let database = Database::with_config(&config).unwrap();
+        assert_eq!(database.read_triggers().unwrap().len(), 4);
+        assert_eq!(
+            database.triggers(TableId(1), Event::Update)[0].name,
+            "changed"
+        );
+        let mut session = SqlSession::new(database.connect());
+        for result in session.execute("DROP TRIGGER added; INSERT INTO 1 VALUES ('c')") {
+            result.unwrap();
+        }
+        assert_eq!(data(&database, 2).len(), 4);
+        assert!(database.trigger("added").is_none());
+        let missing = session.execute("DROP TRIGGER added").remove(0);
+        assert_eq!(missing.unwrap_err().code(), "42704");
+    }
+}
@@ -0,0 +1,176 @@
+//! Row expiry, for tables created `WITH (TTL_COLUMN = '<column>')`: a
+//! row whose column holds a time that has passed is deleted by the next
+//! sweep.
+//!
+//! The column's value is an RFC 3339 time, such as `2024-01-31T12:00:00Z`,
+//! or a number of seconds since the Unix epoch. Rows without it, or that
+//! aren't JSON objects, never expire. Expired rows are deleted a batch at a
+//! time, each batch in a transaction of its own, so a sweep never holds a
+//! table's lock for long. A partitioned table's rows are swept in its
+//! partitions, which share its options.
+
+use crate::config::TtlSweepConfig;
+use crate::database::{Database, DatabaseError, RowId};
+use crate::logging::{log, Timestamp};
+use crate::mapping::column_value;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// When the row `data` expires, by its `column`.
+fn expires_at(data: &[u8], column: &str) -> Option<SystemTime> {
+    let value = column_value(data, column)?;
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => {
+            Some(UNIX_EPOCH + Duration::from_secs_f64(seconds))
+        }
+        Ok(_) => None,
+        Err(_) => Timestamp::parse(&value),
+    }
+}
+
+fn expired(data: &[u8], column: &str, now: SystemTime) -> bool {
+    expires_at(data, column).is_some_and(|at| at <= now)
+}
+
+impl Database {
+    /// Delete every row past the time in its table's TTL column,
+    /// `batch_size` rows to a transaction. Returns how many were deleted.
+    pub fn sweep_expired(&self, batch_size: usize) -> Result<usize, DatabaseError> {
+        let now = SystemTime::now();
+        let mut connection = self.connect();
+        let mut deleted = 0;
+        for table in self.tables() {
+            let Some(column) = self.table_options(table).ttl_column else {
+                continue;
+            };
+            // Swept through its partitions, which are tables too
+            if self.partitions(table).is_some() {
+                continue;
+            }
+            let mut rows: Vec<RowId> = Vec::new();
+            connection.scan_each(table, |row| {
+                if expired(&row.data, &column, now) {
+                    rows.push(row.id);
+                }
+                Ok::<_, DatabaseError>(())
+            })?;
+            for batch in rows.chunks(batch_size.max(1)) {
+                connection.begin()?;
+                for &row in batch {
+                    // It may have been changed or deleted since the scan
+                    let still_expired = connection
+                        .get(row)?
+                        .is_some_and(|row| expired(&row.data, &column, now));
+                    if still_expired && connection.delete(row)? {
+                        deleted += 1;
+                    }
+                }
+                connection.commit()?;
+            }
+        }
+        if deleted > 0 {
+            log!(Info, "expired rows deleted", rows = deleted);
+        }
+        Ok(deleted)
+    }
+}
+
+/// Periodically deletes expired rows, as `storage.ttl_sweep` asks, until
+/// dropped.
+pub struct TtlSweeper {
+    shutdown: Option<Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl TtlSweeper {
+    pub fn spawn(database: Arc<Database>, config: TtlSweepConfig) -> Self {
+        let (shutdown, receiver) = mpsc::channel::<()>();
+        let interval = Duration::from_millis(config.interval_ms);
+        let worker = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                // Whatever is left is tried again next time
+                if let Err(e) = database.sweep_expired(config.batch_size) {
+                    log!(Warn, "could not delete expired rows", error = e);
+                }
+            }
+        });
+        Self {
+            shutdown: Some(shutdown),
+            worker: Some(worker),
+        }
+    }
+}
+
+impl Drop for TtlSweeper {
+    fn drop(&mut self) {
+        self.shutdown.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::open_logged;
+    use crate::table_options::TableOptions;
+
+    #[test]
+    fn test_sweep_expired() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = Arc::new(open_logged(dir.path()));
+        let table = database
+            .create_table_with(TableOptions {
+                ttl_column: Some("expires_at".to_string()),
+                ..TableOptions::default()
+            })
+            .unwrap();
+        let plain = database.create_table().unwrap();
+        let mut connection = database.connect();
+        let rows = [
+            r#"{"n": 1, "expires_at": "2000-01-01T00:00:00Z"}"#,
+            r#"{"n": 2, "expires_at": 946684800}"#,
+            r#"{"n": 3, "expires_at": "2999-01-01T00:00:00+02:00"}"#,
+            r#"{"n": 4}"#,
+            "not json",
+        ];
+        for row in rows {
+            connection.insert(table, row.as_bytes()).unwrap();
+        }
+        connection
+            .insert(plain, br#"{"expires_at": 0}"#.as_slice())
+            .unwrap();
+
+        assert_eq!(database.sweep_expired(1).unwrap(), 2);
+        let left: Vec<_> = connection
+            .scan(table)
+            .unwrap()
+            .into_iter()
+            .map(|row| String::from_utf8(row.data).unwrap())
+            .collect();
+        assert_eq!(left, rows[2..]);
+        assert_eq!(connection.scan(plain).unwrap().len(), 1);
+        assert_eq!(database.sweep_expired(100).unwrap(), 0);
+
+        // The sweeper runs the same sweep in the background
+        connection
+            .insert(table, br#"{"expires_at": 1}"#.as_slice())
+            .unwrap();
+        drop(connection);
+        let sweeper = TtlSweeper::spawn(
+            database.clone(),
+            TtlSweepConfig {
+                interval_ms: 1,
+                batch_size: 10,
+            },
+        );
+        let mut connection = database.connect();
+        while connection.scan(table).unwrap().len() > 3 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(sweeper);
+    }
+}
@@ -0,0 +1,152 @@
+//! The session variables a session consults itself, as `SET` changes and
+//! `SHOW` reads them. Each has a default a new session starts with, and
+//! a value set is checked and written the one way it is shown. Any other
+//! name may be set too, as clients set their own, and is kept as given.
+
+use crate::encoding::ResultFormat;
+use std::time::Duration;
+
+/// A session variable the session itself consults.
+#[derive(Debug)]
+pub(crate) struct Variable {
+    pub(crate) name: &'static str,
+    pub(crate) default: &'static str,
+    pub(crate) description: &'static str,
+    /// The value as it's kept, or `None` if it isn't one
+    normalize: fn(&str) -> Option<String>,
+}
+
+impl Variable {
+    /// `value` as it's kept, or `None` if it isn't one of the variable's.
+    pub(crate) fn normalize(&self, value: &str) -> Option<String> {
+        (self.normalize)(value)
+    }
+}
+
+/// The session's own variables, in name order.
+pub(crate) const VARIABLES: &[Variable] = &[
+    Variable {
+        name: "enable_mergejoin",
+        default: "on",
+        description: "Lets the planner merge the rows of tables read in the order of their keys",
+        normalize: |value| parse_bool(value).map(|on| if on { "on" } else { "off" }.to_string()),
+    },
+    Variable {
+        name: "output_format",
+        default: "aligned",
+        description: "How the shell prints rows: aligned, unaligned, csv, json or msgpack",
+        normalize: |value| ResultFormat::parse(value).map(|format| format.name().to_string()),
+    },
+    Variable {
+        name: "statement_timeout",
+        default: "0",
+        description: "Cancels a statement running longer than this, in milliseconds unless \
+                      a unit is given; 0 for no limit",
+        normalize: |value| parse_duration(value).map(show_duration),
+    },
+    Variable {
+        name: "transaction_isolation",
+        default: "serializable",
+        description: "The isolation of the session's transactions, which run serializable \
+                      at every level since they lock what they read until they end",
+        normalize: |value| {
+            let level = value.to_lowercase();
+            let levels = [
+                "read uncommitted",
+                "read committed",
+                "repeatable read",
+                "serializable",
+            ];
+            levels.contains(&level.as_str()).then_some(level)
+        },
+    },
+];
+
+/// The session's own variable `name`, which is case-insensitive.
+pub(crate) fn variable(name: &str) -> Option<&'static Variable> {
+    VARIABLES
+        .iter()
+        .find(|variable| variable.name.eq_ignore_ascii_case(name))
+}
+
+/// A boolean as Postgres takes them: on, off, true, false, yes, no, 1 or
+/// 0.
+pub(crate) fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "on" | "true" | "yes" | "1" => Some(true),
+        "off" | "false" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// A duration in milliseconds, or in the unit after it: `ms`, `s`, `min`
+/// or `h`.
+pub(crate) fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let amount: u64 = value[..digits].parse().ok()?;
+    let millis = match value[digits..].trim() {
+        "" | "ms" => 1,
+        "s" => 1000,
+        "min" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        _ => return None,
+    };
+    Some(Duration::from_millis(amount.checked_mul(millis)?))
+}
+
+/// A duration in the largest unit that shows it whole, as Postgres shows
+/// them.
+fn show_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if millis == 0 {
+        return "0".to_string();
+    }
+    let units = [(60 * 60 * 1000, "h"), (60 * 1000, "min"), (1000, "s")];
+    for (size, unit) in units {
+        if millis.is_multiple_of(size) {
+            return format!("{}{}", millis / size, unit);
+        }
+    }
+    format!("{}ms", millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variables() {
+        let timeout = variable("Statement_Timeout").unwrap();
+        assert_eq!(timeout.normalize("1500").as_deref(), Some("1500ms"));
+        assert_eq!(timeout.normalize("2000").as_deref(), Some("2s"));
+        assert_eq!(timeout.normalize("90 s").as_deref(), Some("90s"));
+        assert_eq!(timeout.normalize("120s").as_deref(), Some("2min"));
+        assert_eq!(timeout.normalize("0").as_deref(), Some("0"));
+        assert_eq!(timeout.normalize("-1"), None);
+        assert_eq!(timeout.normalize("1d"), None);
+        assert_eq!(parse_duration("2min"), Some(Duration::from_secs(120)));
+
+        let merge = variable("enable_mergejoin").unwrap();
+        assert_eq!(merge.normalize("FALSE").as_deref(), Some("off"));
+        assert_eq!(merge.normalize("maybe"), None);
+        let isolation = variable("transaction_isolation").unwrap();
+        assert_eq!(
+            isolation.normalize("READ COMMITTED").as_deref(),
+            Some("read committed")
+        );
+        assert_eq!(isolation.normalize("snapshot"), None);
+        assert!(variable("search_path").is_none());
+
+        // Listed in name order, each with a default it accepts
+        assert!(VARIABLES.windows(2).all(|pair| pair[0].name < pair[1].name));
+        for variable in VARIABLES {
+            assert_eq!(
+                variable.normalize(variable.default).as_deref(),
+                Some(variable.default)
+            );
+        }
+    }
+}